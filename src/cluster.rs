@@ -0,0 +1,62 @@
+/// The number of hash slots that keys, and sharded channels, are distributed over
+pub(crate) const SLOTS: u16 = 16384;
+
+/// The CRC16 (XMODEM) checksum used for hash slots
+fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for &byte in bytes {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// The hash slot of a key or sharded channel
+///
+/// If the key contains a non-empty hash tag, the part between the first `{` and the `}` after it,
+/// only that part is hashed, so that related keys can be put in the same slot.
+pub(crate) fn key_slot(key: &[u8]) -> u16 {
+    let hashed = key
+        .iter()
+        .position(|&b| b == b'{')
+        .and_then(|start| {
+            let tag = &key[start + 1..];
+            tag.iter()
+                .position(|&b| b == b'}')
+                .filter(|&end| end > 0)
+                .map(|end| &tag[..end])
+        })
+        .unwrap_or(key);
+    crc16(hashed) & (SLOTS - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc16() {
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+    }
+
+    #[test]
+    fn test_key_slot() {
+        assert_eq!(key_slot(b"foo"), 12182);
+        assert_eq!(key_slot(b"bar"), 5061);
+        assert_eq!(key_slot(b""), 0);
+        assert_eq!(
+            key_slot(b"{user1000}.following"),
+            key_slot(b"{user1000}.followers")
+        );
+        assert_eq!(key_slot(b"{user1000}.following"), key_slot(b"user1000"));
+        // Empty hash tags hash the whole key
+        assert_eq!(key_slot(b"foo{}{bar}"), crc16(b"foo{}{bar}") & (SLOTS - 1));
+        assert_eq!(key_slot(b"foo{{bar}}zap"), key_slot(b"{bar"));
+    }
+}
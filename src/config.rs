@@ -1,3 +1,6 @@
+use crate::notify;
+use std::path::Path;
+
 #[derive(Debug)]
 pub struct Config {
     pub dir: String,
@@ -5,6 +8,19 @@ pub struct Config {
     pub host: String,
     pub port: u16,
     pub snapshot_interval: u64,
+    pub hash_max_listpack_entries: usize,
+    pub hash_max_listpack_value: usize,
+    pub set_max_intset_entries: usize,
+    pub zset_max_listpack_entries: usize,
+    pub zset_max_listpack_value: usize,
+    pub stream_node_max_entries: usize,
+    pub stream_node_max_bytes: usize,
+    pub hll_sparse_max_bytes: usize,
+    pub notify_keyspace_events: String,
+    pub busy_reply_threshold: u64,
+    pub requirepass: String,
+    pub aclfile: String,
+    pub protected_mode: bool,
 }
 
 impl Config {
@@ -16,7 +32,137 @@ impl Config {
         &self.dbfilename
     }
 
+    /// The full path to the RDB file
+    pub fn rdb_path(&self) -> String {
+        format!("{}/{}", self.dir, self.dbfilename)
+    }
+
     pub fn listen_addr(&self) -> String {
         format!("{}:{}", self.host, self.port)
     }
+
+    /// The classes of keyspace events to publish, where unknown flags disable all of them
+    pub(crate) fn notify_keyspace_events(&self) -> u32 {
+        notify::parse_flags(&self.notify_keyspace_events).unwrap_or(0)
+    }
+
+    /// Get the value of a config parameter by name, as returned by CONFIG GET
+    pub fn get(&self, name: &str) -> Option<String> {
+        let value = match name {
+            "dir" => self.dir.clone(),
+            "dbfilename" => self.dbfilename.clone(),
+            "hash-max-listpack-entries" | "hash-max-ziplist-entries" => {
+                self.hash_max_listpack_entries.to_string()
+            }
+            "hash-max-listpack-value" | "hash-max-ziplist-value" => {
+                self.hash_max_listpack_value.to_string()
+            }
+            "set-max-intset-entries" => self.set_max_intset_entries.to_string(),
+            "zset-max-listpack-entries" | "zset-max-ziplist-entries" => {
+                self.zset_max_listpack_entries.to_string()
+            }
+            "zset-max-listpack-value" | "zset-max-ziplist-value" => {
+                self.zset_max_listpack_value.to_string()
+            }
+            "stream-node-max-entries" => self.stream_node_max_entries.to_string(),
+            "stream-node-max-bytes" => self.stream_node_max_bytes.to_string(),
+            "hll-sparse-max-bytes" => self.hll_sparse_max_bytes.to_string(),
+            "notify-keyspace-events" => notify::format_flags(self.notify_keyspace_events()),
+            "busy-reply-threshold" | "lua-time-limit" => self.busy_reply_threshold.to_string(),
+            "requirepass" => self.requirepass.clone(),
+            "aclfile" => self.aclfile.clone(),
+            "protected-mode" => match self.protected_mode {
+                true => "yes".to_string(),
+                false => "no".to_string(),
+            },
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// Set a config parameter by name, as done by CONFIG SET
+    ///
+    /// Only parameters that can be changed at runtime can be set, an error message is returned for
+    /// unknown parameters or invalid values
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let result = match name {
+            "dir" => {
+                if Path::new(value).is_dir() {
+                    self.dir = value.to_string();
+                    Ok(())
+                } else {
+                    Err("No such file or directory".to_string())
+                }
+            }
+            "dbfilename" => {
+                self.dbfilename = value.to_string();
+                Ok(())
+            }
+            "hash-max-listpack-entries" | "hash-max-ziplist-entries" => {
+                parse_config_int(value).map(|v| self.hash_max_listpack_entries = v)
+            }
+            "hash-max-listpack-value" | "hash-max-ziplist-value" => {
+                parse_config_int(value).map(|v| self.hash_max_listpack_value = v)
+            }
+            "set-max-intset-entries" => {
+                parse_config_int(value).map(|v| self.set_max_intset_entries = v)
+            }
+            "zset-max-listpack-entries" | "zset-max-ziplist-entries" => {
+                parse_config_int(value).map(|v| self.zset_max_listpack_entries = v)
+            }
+            "zset-max-listpack-value" | "zset-max-ziplist-value" => {
+                parse_config_int(value).map(|v| self.zset_max_listpack_value = v)
+            }
+            "stream-node-max-entries" => {
+                parse_config_int(value).map(|v| self.stream_node_max_entries = v)
+            }
+            "stream-node-max-bytes" => {
+                parse_config_int(value).map(|v| self.stream_node_max_bytes = v)
+            }
+            "hll-sparse-max-bytes" => {
+                parse_config_int(value).map(|v| self.hll_sparse_max_bytes = v)
+            }
+            "notify-keyspace-events" => match notify::parse_flags(value) {
+                Some(flags) => {
+                    self.notify_keyspace_events = notify::format_flags(flags);
+                    Ok(())
+                }
+                None => Err("Invalid event class character. Use 'Ag$lshzxeKEtmn'.".to_string()),
+            },
+            "busy-reply-threshold" | "lua-time-limit" => {
+                parse_config_int(value).map(|v| self.busy_reply_threshold = v as u64)
+            }
+            "requirepass" => {
+                self.requirepass = value.to_string();
+                Ok(())
+            }
+            "protected-mode" => parse_config_bool(value).map(|v| self.protected_mode = v),
+            _ => {
+                return Err(format!(
+                    "Unknown option or number of arguments for CONFIG SET - '{}'",
+                    name
+                ))
+            }
+        };
+        result.map_err(|e| {
+            format!(
+                "CONFIG SET failed (possibly related to argument '{}') - {}",
+                name, e
+            )
+        })
+    }
+}
+
+fn parse_config_int(value: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .map_err(|_| "argument couldn't be parsed into an integer".to_string())
+}
+
+fn parse_config_bool(value: &str) -> Result<bool, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err("argument must be 'yes' or 'no'".to_string()),
+    }
 }
@@ -0,0 +1,1054 @@
+use super::{bulk_strings, commands, Connection, OK};
+use crate::{database::now, error::RustisError, glob, resp::RESPData, sha256::sha256_hex, Result};
+use std::{collections::BTreeMap, collections::VecDeque, fs, io::Write};
+
+/// The user clients are authenticated as when they connect, whose password is `requirepass`
+pub(super) const DEFAULT_USER: &str = "default";
+
+/// How many entries the ACL LOG keeps, like the default `acllog-max-len`
+const LOG_MAX_LEN: usize = 128;
+
+/// How many milliseconds apart denials can be to be grouped in the same entry of the ACL LOG
+const LOG_GROUPING_MAX_TIME_DELTA: u128 = 60000;
+
+/// The commands that publish to or subscribe to channels, whose arguments after the name are the
+/// channels, and whether they are patterns
+const CHANNEL_COMMANDS: &[(&[u8], bool)] = &[
+    (b"PUBLISH", false),
+    (b"SPUBLISH", false),
+    (b"SUBSCRIBE", false),
+    (b"SSUBSCRIBE", false),
+    (b"PSUBSCRIBE", true),
+];
+
+/// The commands whose first argument is a subcommand, which rules like "+config|get" allow or deny
+const CONTAINER_COMMANDS: &[&[u8]] = &[
+    b"ACL",
+    b"CLIENT",
+    b"COMMAND",
+    b"CONFIG",
+    b"FUNCTION",
+    b"OBJECT",
+    b"PUBSUB",
+    b"SCRIPT",
+    b"XGROUP",
+    b"XINFO",
+];
+
+const NO_ACL_FILE: &str = "This Redis instance is not configured to use an ACL file. You may want \
+                           to specify users via the ACL SETUSER command and then issue a CONFIG \
+                           REWRITE (assuming you have a Redis configuration file set) in order to \
+                           store users in the Redis configuration.";
+
+/// Compare passwords in a time that doesn't depend on how much of them matches
+fn passwords_match(given: &[u8], expected: &[u8]) -> bool {
+    let mut difference = given.len() ^ expected.len();
+    for (i, &c) in given.iter().enumerate() {
+        difference |= (c ^ expected.get(i).copied().unwrap_or(!c)) as usize;
+    }
+    difference == 0
+}
+
+/// Check whether a password hash given with "#" or "!" is a SHA256 digest in lowercase hex
+fn valid_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.bytes().all(|c| matches!(c, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// A pattern of keys a user can access, for reading, writing or both
+#[derive(Debug, Clone, PartialEq)]
+struct KeyPattern {
+    pattern: String,
+    read: bool,
+    write: bool,
+}
+
+impl KeyPattern {
+    fn describe(&self) -> String {
+        match (self.read, self.write) {
+            (true, false) => format!("%R~{}", self.pattern),
+            (false, true) => format!("%W~{}", self.pattern),
+            _ => format!("~{}", self.pattern),
+        }
+    }
+}
+
+/// A user, with the passwords it authenticates with and what it's allowed to do
+#[derive(Debug, Clone, PartialEq)]
+struct User {
+    enabled: bool,
+    nopass: bool,
+    /// The SHA256 digests of the passwords, in lowercase hex
+    passwords: Vec<String>,
+    all_keys: bool,
+    key_patterns: Vec<KeyPattern>,
+    all_channels: bool,
+    channel_patterns: Vec<String>,
+    /// The rules allowing and denying commands, such as "+get" or "-@write", since the last one
+    /// that allowed or denied all of them, which are applied in order
+    command_rules: Vec<String>,
+}
+
+impl Default for User {
+    /// A new user, which is disabled and can't do anything until it's given rules
+    fn default() -> Self {
+        User {
+            enabled: false,
+            nopass: false,
+            passwords: Vec::new(),
+            all_keys: false,
+            key_patterns: Vec::new(),
+            all_channels: false,
+            channel_patterns: Vec::new(),
+            command_rules: Vec::new(),
+        }
+    }
+}
+
+impl User {
+    /// The default user, which can do anything without a password unless `requirepass` is set
+    fn default_user(requirepass: &str) -> Self {
+        let mut user = User {
+            enabled: true,
+            all_keys: true,
+            all_channels: true,
+            command_rules: vec!["+@all".to_string()],
+            ..User::default()
+        };
+        user.set_requirepass(requirepass);
+        user
+    }
+
+    fn set_requirepass(&mut self, requirepass: &str) {
+        self.passwords.clear();
+        self.nopass = requirepass.is_empty();
+        if !requirepass.is_empty() {
+            self.passwords.push(sha256_hex(requirepass.as_bytes()));
+        }
+    }
+
+    /// Apply a rule given to ACL SETUSER, returning why it's invalid if it is
+    fn apply(&mut self, rule: &str) -> std::result::Result<(), &'static str> {
+        const SYNTAX_ERROR: &str = "Syntax error";
+        const NO_SUCH_PASSWORD: &str =
+            "The password you are trying to remove from the user does not exist";
+        const INVALID_HASH: &str = "The password hash must be exactly 64 characters and contain \
+                                    only lowercase hexadecimal characters";
+        const KEYS_AFTER_ALL: &str = "Adding a pattern after the * pattern (or the 'allkeys' \
+                                      flag) is not valid and does not have any effect. Try \
+                                      'resetkeys' to start with an empty list of patterns";
+        const CHANNELS_AFTER_ALL: &str = "Adding a pattern after the * pattern (or the \
+                                          'allchannels' flag) is not valid and does not have any \
+                                          effect. Try 'resetchannels' to start with an empty list \
+                                          of channels";
+        const UNKNOWN_COMMAND: &str = "Unknown command or category name in ACL";
+
+        match rule.to_ascii_lowercase().as_str() {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allkeys" => {
+                self.all_keys = true;
+                self.key_patterns.clear();
+            }
+            "resetkeys" => {
+                self.all_keys = false;
+                self.key_patterns.clear();
+            }
+            "allchannels" => {
+                self.all_channels = true;
+                self.channel_patterns.clear();
+            }
+            "resetchannels" => {
+                self.all_channels = false;
+                self.channel_patterns.clear();
+            }
+            "allcommands" | "+@all" => self.command_rules = vec!["+@all".to_string()],
+            "nocommands" | "-@all" => self.command_rules = vec!["-@all".to_string()],
+            "reset" => *self = User::default(),
+            "sanitize-payload" | "skip-sanitize-payload" => {}
+            _ => {
+                let Some(first) = rule.chars().next() else {
+                    return Err(SYNTAX_ERROR);
+                };
+                let rest = &rule[first.len_utf8()..];
+                match first {
+                    '>' => {
+                        let hash = sha256_hex(rest.as_bytes());
+                        if !self.passwords.contains(&hash) {
+                            self.passwords.push(hash);
+                        }
+                        self.nopass = false;
+                    }
+                    '#' => {
+                        if !valid_hash(rest) {
+                            return Err(INVALID_HASH);
+                        }
+                        if !self.passwords.iter().any(|hash| hash == rest) {
+                            self.passwords.push(rest.to_string());
+                        }
+                        self.nopass = false;
+                    }
+                    '<' | '!' => {
+                        let hash = match first {
+                            '<' => sha256_hex(rest.as_bytes()),
+                            _ if valid_hash(rest) => rest.to_string(),
+                            _ => return Err(INVALID_HASH),
+                        };
+                        let Some(index) = self.passwords.iter().position(|h| *h == hash) else {
+                            return Err(NO_SUCH_PASSWORD);
+                        };
+                        self.passwords.remove(index);
+                        self.nopass = false;
+                    }
+                    '~' | '%' => {
+                        let (flags, pattern) = match first {
+                            '~' => ("RW", rest),
+                            _ => rest.split_once('~').ok_or(SYNTAX_ERROR)?,
+                        };
+                        let read = flags.contains(['R', 'r']);
+                        let write = flags.contains(['W', 'w']);
+                        if flags.is_empty() || flags.chars().any(|c| !"RWrw".contains(c)) {
+                            return Err(SYNTAX_ERROR);
+                        }
+                        if self.all_keys {
+                            return Err(KEYS_AFTER_ALL);
+                        }
+                        if pattern == "*" && read && write {
+                            self.all_keys = true;
+                            self.key_patterns.clear();
+                        } else {
+                            let pattern = KeyPattern {
+                                pattern: pattern.to_string(),
+                                read,
+                                write,
+                            };
+                            if !self.key_patterns.contains(&pattern) {
+                                self.key_patterns.push(pattern);
+                            }
+                        }
+                    }
+                    '&' => {
+                        if self.all_channels {
+                            return Err(CHANNELS_AFTER_ALL);
+                        }
+                        if rest == "*" {
+                            self.all_channels = true;
+                            self.channel_patterns.clear();
+                        } else if !self.channel_patterns.iter().any(|p| p == rest) {
+                            self.channel_patterns.push(rest.to_string());
+                        }
+                    }
+                    '+' | '-' => {
+                        let rest = rest.to_ascii_lowercase();
+                        let valid = match rest.strip_prefix('@') {
+                            Some(category) => commands::CATEGORIES
+                                .iter()
+                                .any(|&(name, _)| name == category),
+                            None => {
+                                let name = rest.split('|').next().unwrap_or_default();
+                                commands::lookup(name.to_ascii_uppercase().as_bytes()).is_some()
+                            }
+                        };
+                        if !valid {
+                            return Err(UNKNOWN_COMMAND);
+                        }
+                        self.command_rules.push(format!("{}{}", first, rest));
+                    }
+                    _ => return Err(SYNTAX_ERROR),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check whether the user can run a command, whose first argument is its subcommand if it has
+    /// any, by applying the rules in order
+    fn can_run(&self, command: &commands::Command, subcommand: Option<&[u8]>) -> bool {
+        let name = command.name();
+        let full_name = subcommand.map(|subcommand| {
+            format!(
+                "{}|{}",
+                name,
+                String::from_utf8_lossy(subcommand).to_lowercase()
+            )
+        });
+        let mut allowed = false;
+        for rule in &self.command_rules {
+            let (sign, target) = rule.split_at(1);
+            let matches = match target.strip_prefix('@') {
+                Some(category) => command.in_category(category),
+                None if target.contains('|') => full_name.as_deref() == Some(target),
+                None => target == name,
+            };
+            if matches {
+                allowed = sign == "+";
+            }
+        }
+        allowed
+    }
+
+    /// Check whether the user can access a key, with a single pattern allowing all of the access
+    fn can_access_key(&self, key: &[u8], read: bool, write: bool) -> bool {
+        self.all_keys
+            || self.key_patterns.iter().any(|pattern| {
+                (pattern.read || !read)
+                    && (pattern.write || !write)
+                    && glob::matches(pattern.pattern.as_bytes(), key)
+            })
+    }
+
+    /// Check whether the user can access a channel, where patterns given to PSUBSCRIBE have to be
+    /// one of the user's patterns exactly
+    fn can_access_channel(&self, channel: &[u8], is_pattern: bool) -> bool {
+        self.all_channels
+            || self
+                .channel_patterns
+                .iter()
+                .any(|pattern| match is_pattern {
+                    true => pattern.as_bytes() == channel,
+                    false => glob::matches(pattern.as_bytes(), channel),
+                })
+    }
+
+    fn describe_keys(&self) -> String {
+        match self.all_keys {
+            true => "~*".to_string(),
+            false => self
+                .key_patterns
+                .iter()
+                .map(KeyPattern::describe)
+                .collect::<Vec<_>>()
+                .join(" "),
+        }
+    }
+
+    fn describe_channels(&self) -> String {
+        match self.all_channels {
+            true => "&*".to_string(),
+            false => self
+                .channel_patterns
+                .iter()
+                .map(|pattern| format!("&{}", pattern))
+                .collect::<Vec<_>>()
+                .join(" "),
+        }
+    }
+
+    fn describe_commands(&self) -> String {
+        let mut rules = Vec::new();
+        if !matches!(
+            self.command_rules.first().map(String::as_str),
+            Some("+@all" | "-@all")
+        ) {
+            rules.push("-@all");
+        }
+        rules.extend(self.command_rules.iter().map(String::as_str));
+        rules.join(" ")
+    }
+
+    /// The rules that recreate the user, as listed by ACL LIST and saved in the ACL file
+    fn describe(&self) -> String {
+        let mut parts = vec![match self.enabled {
+            true => "on".to_string(),
+            false => "off".to_string(),
+        }];
+        if self.nopass {
+            parts.push("nopass".to_string());
+        }
+        parts.extend(self.passwords.iter().map(|hash| format!("#{}", hash)));
+        if self.all_keys || !self.key_patterns.is_empty() {
+            parts.push(self.describe_keys());
+        }
+        if !self.all_channels {
+            parts.push("resetchannels".to_string());
+        }
+        if self.all_channels || !self.channel_patterns.is_empty() {
+            parts.push(self.describe_channels());
+        }
+        parts.push(self.describe_commands());
+        parts.join(" ")
+    }
+}
+
+/// Why a command was denied, as listed in the ACL LOG
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Denial {
+    Command,
+    Key,
+    Channel,
+    Auth,
+}
+
+impl Denial {
+    fn reason(&self) -> &'static str {
+        match self {
+            Denial::Command => "command",
+            Denial::Key => "key",
+            Denial::Channel => "channel",
+            Denial::Auth => "auth",
+        }
+    }
+}
+
+/// A command that was denied, or failed authentication, grouped with similar ones that happened
+/// shortly after
+#[derive(Debug)]
+struct LogEntry {
+    count: u64,
+    denial: Denial,
+    /// Where the command ran, which is "toplevel", "multi" or "lua"
+    context: &'static str,
+    /// The command, key or channel that was denied
+    object: String,
+    username: String,
+    client_info: String,
+    entry_id: u64,
+    created: u128,
+    updated: u128,
+}
+
+/// The users, and the log of the commands they were denied
+pub(crate) struct Acl {
+    users: BTreeMap<String, User>,
+    /// The latest entries first
+    log: VecDeque<LogEntry>,
+    next_entry_id: u64,
+}
+
+impl Acl {
+    pub(crate) fn new(requirepass: &str) -> Self {
+        let mut users = BTreeMap::new();
+        users.insert(DEFAULT_USER.to_string(), User::default_user(requirepass));
+        Acl {
+            users,
+            log: VecDeque::new(),
+            next_entry_id: 0,
+        }
+    }
+
+    /// Set the password of the default user, when `requirepass` is changed with CONFIG SET
+    pub(crate) fn set_requirepass(&mut self, requirepass: &str) {
+        if let Some(user) = self.users.get_mut(DEFAULT_USER) {
+            user.set_requirepass(requirepass);
+        }
+    }
+
+    /// Whether clients are authenticated as the default user when they connect, which they are
+    /// when it doesn't need a password
+    pub(crate) fn default_user_nopass(&self) -> bool {
+        self.users
+            .get(DEFAULT_USER)
+            .is_some_and(|user| user.enabled && user.nopass)
+    }
+
+    pub(super) fn authenticate(&self, username: &str, password: &[u8]) -> bool {
+        let Some(user) = self.users.get(username) else {
+            return false;
+        };
+        let hash = sha256_hex(password);
+        user.enabled
+            && (user.nopass
+                || user
+                    .passwords
+                    .iter()
+                    .any(|expected| passwords_match(hash.as_bytes(), expected.as_bytes())))
+    }
+
+    /// Parse the users in an ACL file, with one user per line like "user alice on >pass ~* +@all"
+    ///
+    /// The default user is kept as it is unless the file has it. Nothing is changed if the file
+    /// has any errors.
+    pub(crate) fn load_file(&mut self, path: &str) -> std::result::Result<(), String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Error loading ACLs, opening file '{}': {}", path, e))?;
+
+        let mut users = BTreeMap::new();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| format!("{}:{}: {}", path, number + 1, message);
+
+            let mut words = line.split_whitespace();
+            if words.next() != Some("user") {
+                return Err(error("line should start with user keyword".to_string()));
+            }
+            let Some(name) = words.next() else {
+                return Err(error("user name is missing".to_string()));
+            };
+            if users.contains_key(name) {
+                return Err(error(format!("Duplicate user '{}' found", name)));
+            }
+            let mut user = User::default();
+            for rule in words {
+                user.apply(rule).map_err(|reason| {
+                    error(format!(
+                        "Error in applying operation '{}': {}",
+                        rule, reason
+                    ))
+                })?;
+            }
+            users.insert(name.to_string(), user);
+        }
+
+        if !users.contains_key(DEFAULT_USER) {
+            users.insert(DEFAULT_USER.to_string(), self.users[DEFAULT_USER].clone());
+        }
+        self.users = users;
+        Ok(())
+    }
+
+    /// Write the users to an ACL file, replacing it
+    fn save_file(&self, path: &str) -> std::io::Result<()> {
+        let mut contents = String::new();
+        for line in self.list() {
+            contents.push_str(&line);
+            contents.push('\n');
+        }
+        let temp_path = format!("{}.tmp", path);
+        fs::write(&temp_path, contents)?;
+        fs::rename(temp_path, path)
+    }
+
+    fn list(&self) -> Vec<String> {
+        self.users
+            .iter()
+            .map(|(name, user)| format!("user {} {}", name, user.describe()))
+            .collect()
+    }
+
+    /// Add a denied command to the log, or count it in the entry of a similar one, if it was
+    /// recent
+    fn add_log(
+        &mut self,
+        denial: Denial,
+        context: &'static str,
+        object: String,
+        username: String,
+        client_info: String,
+    ) {
+        let timestamp = now();
+        let similar = self.log.iter().take(10).position(|entry| {
+            entry.denial == denial
+                && entry.context == context
+                && entry.object == object
+                && entry.username == username
+                && timestamp - entry.updated < LOG_GROUPING_MAX_TIME_DELTA
+        });
+        if let Some(index) = similar {
+            let mut entry = self.log.remove(index).unwrap();
+            entry.count += 1;
+            entry.updated = timestamp;
+            entry.client_info = client_info;
+            self.log.push_front(entry);
+            return;
+        }
+
+        self.log.push_front(LogEntry {
+            count: 1,
+            denial,
+            context,
+            object,
+            username,
+            client_info,
+            entry_id: self.next_entry_id,
+            created: timestamp,
+            updated: timestamp,
+        });
+        self.next_entry_id += 1;
+        self.log.truncate(LOG_MAX_LEN);
+    }
+}
+
+impl Connection {
+    /// Describe the client for the ACL LOG, like CLIENT LIST does
+    fn client_info(&self) -> String {
+        let addr = self
+            .stream
+            .socket
+            .peer_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_default();
+        format!("id={} addr={} user={}", self.id, addr, self.user)
+    }
+
+    /// Where a command runs, for the ACL LOG
+    fn context(&self) -> &'static str {
+        if self.stream.captured.is_some() {
+            "lua"
+        } else if self.in_transaction() {
+            "multi"
+        } else {
+            "toplevel"
+        }
+    }
+
+    /// Log a failed authentication in the ACL LOG
+    pub(super) fn log_auth_failure(&mut self, username: &str) {
+        let client_info = self.client_info();
+        self.acl.borrow_mut().add_log(
+            Denial::Auth,
+            "toplevel",
+            "AUTH".to_string(),
+            username.to_string(),
+            client_info,
+        );
+    }
+
+    /// Check that the client's user can run a command, with the keys and channels it's given,
+    /// logging it in the ACL LOG if it can't
+    ///
+    /// Commands that don't exist or have the wrong number of arguments are left to fail when
+    /// they're run. Clients whose user was deleted are disconnected.
+    pub(super) fn check_permissions(&mut self, array: &[RESPData]) -> Result<()> {
+        let Ok(args) = bulk_strings(array) else {
+            return Ok(());
+        };
+        let Some(command) = commands::lookup(&args[0].to_ascii_uppercase()) else {
+            return Ok(());
+        };
+        if !command.arity_matches(args.len()) {
+            return Ok(());
+        }
+
+        let upper = args[0].to_ascii_uppercase();
+        let subcommand = match CONTAINER_COMMANDS.contains(&upper.as_slice()) {
+            true => args.get(1).copied(),
+            false => None,
+        };
+        let name = match subcommand {
+            Some(subcommand) => format!(
+                "{}|{}",
+                command.name(),
+                String::from_utf8_lossy(subcommand).to_lowercase()
+            ),
+            None => command.name(),
+        };
+
+        let denial = {
+            let acl = self.acl.borrow();
+            let Some(user) = acl.users.get(&self.user) else {
+                log::info!("Disconnecting client of deleted user: {}", self.user);
+                return Err(RustisError::ClientDisconnected);
+            };
+            if !user.can_run(&command, subcommand) {
+                Some((Denial::Command, name.clone()))
+            } else {
+                let key = command
+                    .key_positions(&args)
+                    .into_iter()
+                    .enumerate()
+                    .find(|&(index, position)| {
+                        let (read, write) = command.key_access(index);
+                        !user.can_access_key(args[position], read, write)
+                    })
+                    .map(|(_, position)| (Denial::Key, args[position]));
+                let channel = || {
+                    let &(_, is_pattern) = CHANNEL_COMMANDS
+                        .iter()
+                        .find(|(name, _)| *name == upper.as_slice())?;
+                    let channels = match upper.as_slice() {
+                        b"PUBLISH" | b"SPUBLISH" => &args[1..2],
+                        _ => &args[1..],
+                    };
+                    channels
+                        .iter()
+                        .find(|channel| !user.can_access_channel(channel, is_pattern))
+                        .map(|channel| (Denial::Channel, *channel))
+                };
+                key.or_else(channel)
+                    .map(|(denial, object)| (denial, String::from_utf8_lossy(object).into_owned()))
+            }
+        };
+        let Some((denial, object)) = denial else {
+            return Ok(());
+        };
+
+        let (context, client_info) = (self.context(), self.client_info());
+        self.acl
+            .borrow_mut()
+            .add_log(denial, context, object, self.user.clone(), client_info);
+        self.fail_transaction();
+        let message = match denial {
+            Denial::Command => format!(
+                "User {} has no permissions to run the '{}' command",
+                self.user, name
+            ),
+            Denial::Key => "No permissions to access a key".to_string(),
+            _ => "No permissions to access a channel".to_string(),
+        };
+        Err(RustisError::ClientErrorWithCode("NOPERM", message))
+    }
+
+    pub(super) fn handle_acl(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received ACL");
+
+        let Some((RESPData::BulkString(subcommand), args)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'acl' command");
+        };
+        let args = bulk_strings(args)?;
+
+        match (subcommand.to_ascii_uppercase().as_slice(), args.as_slice()) {
+            (b"SETUSER", [name, rules @ ..]) => self.acl_setuser(name, rules),
+            (b"GETUSER", [name]) => self.acl_getuser(name),
+            (b"DELUSER", names) if !names.is_empty() => {
+                let mut acl = self.acl.borrow_mut();
+                if names.iter().any(|name| *name == DEFAULT_USER.as_bytes()) {
+                    return client_error!("The 'default' user cannot be removed");
+                }
+                let deleted = names
+                    .iter()
+                    .filter(|name| acl.users.remove(&*String::from_utf8_lossy(name)).is_some())
+                    .count();
+                drop(acl);
+                self.write_integer(deleted as i64)
+            }
+            (b"LIST", []) => {
+                let list = self.acl.borrow().list();
+                self.write_array(list.iter().map(|line| line.as_bytes()).collect())
+            }
+            (b"USERS", []) => {
+                let users: Vec<String> = self.acl.borrow().users.keys().cloned().collect();
+                self.write_array(users.iter().map(|user| user.as_bytes()).collect())
+            }
+            (b"WHOAMI", []) => {
+                let user = self.user.clone();
+                self.write_bulk_string(user.as_bytes())
+            }
+            (b"CAT", []) => self.write_array(
+                commands::CATEGORIES
+                    .iter()
+                    .map(|(name, _)| name.as_bytes())
+                    .collect(),
+            ),
+            (b"CAT", [category]) => {
+                let category = String::from_utf8_lossy(category).to_lowercase();
+                if !commands::CATEGORIES
+                    .iter()
+                    .any(|&(name, _)| name == category)
+                {
+                    return client_error!("Unknown category '{}'", category);
+                }
+                let commands = commands::in_category(&category);
+                self.write_array(commands.iter().map(|name| name.as_bytes()).collect())
+            }
+            (b"LOG", []) => self.acl_log(usize::MAX),
+            (b"LOG", [argument]) if argument.eq_ignore_ascii_case(b"RESET") => {
+                self.acl.borrow_mut().log.clear();
+                self.stream.write_all(OK)?;
+                Ok(())
+            }
+            (b"LOG", [count]) => match String::from_utf8_lossy(count).parse::<i64>() {
+                Ok(count) if count >= 0 => self.acl_log(count as usize),
+                Ok(_) => client_error!("value is out of range, must be positive"),
+                Err(_) => client_error!("value is not an integer or out of range"),
+            },
+            (b"LOAD", []) => {
+                let path = self.config.borrow().aclfile.clone();
+                if path.is_empty() {
+                    return client_error!("{}", NO_ACL_FILE);
+                }
+                if let Err(e) = self.acl.borrow_mut().load_file(&path) {
+                    return client_error!("{}", e);
+                }
+                self.stream.write_all(OK)?;
+                Ok(())
+            }
+            (b"SAVE", []) => {
+                let path = self.config.borrow().aclfile.clone();
+                if path.is_empty() {
+                    return client_error!("{}", NO_ACL_FILE);
+                }
+                if let Err(e) = self.acl.borrow().save_file(&path) {
+                    log::error!("Failed to save the ACL file: {}", e);
+                    return client_error!(
+                        "There was an error trying to save the ACLs. Please check the server \
+                         logs for more information"
+                    );
+                }
+                self.stream.write_all(OK)?;
+                Ok(())
+            }
+            (
+                subcommand @ (b"SETUSER" | b"GETUSER" | b"DELUSER" | b"LIST" | b"USERS" | b"WHOAMI"
+                | b"CAT" | b"LOG" | b"LOAD" | b"SAVE"),
+                _,
+            ) => client_error!(
+                "wrong number of arguments for 'acl|{}' command",
+                String::from_utf8_lossy(subcommand).to_lowercase()
+            ),
+            _ => client_error!(
+                "unknown subcommand '{}'. Try ACL HELP.",
+                String::from_utf8_lossy(subcommand)
+            ),
+        }
+    }
+
+    /// Handle ACL SETUSER, which creates or modifies a user, applying either all of the rules or
+    /// none of them
+    fn acl_setuser(&mut self, name: &[u8], rules: &[&[u8]]) -> Result<()> {
+        let name = String::from_utf8_lossy(name).into_owned();
+        if name.contains([' ', '\0']) {
+            return client_error!("Usernames can't contain spaces or null characters");
+        }
+
+        let mut user = self
+            .acl
+            .borrow()
+            .users
+            .get(&name)
+            .cloned()
+            .unwrap_or_default();
+        for rule in rules {
+            let rule = String::from_utf8_lossy(rule);
+            if let Err(reason) = user.apply(&rule) {
+                return client_error!("Error in ACL SETUSER modifier '{}': {}", rule, reason);
+            }
+        }
+        self.acl.borrow_mut().users.insert(name, user);
+        self.stream.write_all(OK)?;
+        Ok(())
+    }
+
+    fn acl_getuser(&mut self, name: &[u8]) -> Result<()> {
+        let user = self
+            .acl
+            .borrow()
+            .users
+            .get(&*String::from_utf8_lossy(name))
+            .cloned();
+        let Some(user) = user else {
+            return self.write_resp(&RESPData::Null);
+        };
+
+        let mut flags = vec![RESPData::BulkString(match user.enabled {
+            true => b"on",
+            false => b"off",
+        })];
+        if user.nopass {
+            flags.push(RESPData::BulkString(b"nopass"));
+        }
+        let (commands, keys, channels) = (
+            user.describe_commands(),
+            user.describe_keys(),
+            user.describe_channels(),
+        );
+        self.write_resp(&RESPData::Array(vec![
+            RESPData::BulkString(b"flags"),
+            RESPData::Array(flags),
+            RESPData::BulkString(b"passwords"),
+            RESPData::Array(
+                user.passwords
+                    .iter()
+                    .map(|hash| RESPData::BulkString(hash.as_bytes()))
+                    .collect(),
+            ),
+            RESPData::BulkString(b"commands"),
+            RESPData::BulkString(commands.as_bytes()),
+            RESPData::BulkString(b"keys"),
+            RESPData::BulkString(keys.as_bytes()),
+            RESPData::BulkString(b"channels"),
+            RESPData::BulkString(channels.as_bytes()),
+            RESPData::BulkString(b"selectors"),
+            RESPData::Array(Vec::new()),
+        ]))
+    }
+
+    /// Handle ACL LOG, which replies with the latest entries of the log, up to a count
+    fn acl_log(&mut self, count: usize) -> Result<()> {
+        let timestamp = now();
+        let acl = self.acl.borrow();
+        let entries: Vec<_> = acl
+            .log
+            .iter()
+            .take(count)
+            .map(|entry| {
+                (
+                    entry,
+                    format!("{:.3}", (timestamp - entry.created) as f64 / 1000.0),
+                )
+            })
+            .collect();
+        let reply = RESPData::Array(
+            entries
+                .iter()
+                .map(|(entry, age)| {
+                    RESPData::Array(vec![
+                        RESPData::BulkString(b"count"),
+                        RESPData::Integer(entry.count as i64),
+                        RESPData::BulkString(b"reason"),
+                        RESPData::BulkString(entry.denial.reason().as_bytes()),
+                        RESPData::BulkString(b"context"),
+                        RESPData::BulkString(entry.context.as_bytes()),
+                        RESPData::BulkString(b"object"),
+                        RESPData::BulkString(entry.object.as_bytes()),
+                        RESPData::BulkString(b"username"),
+                        RESPData::BulkString(entry.username.as_bytes()),
+                        RESPData::BulkString(b"age-seconds"),
+                        RESPData::BulkString(age.as_bytes()),
+                        RESPData::BulkString(b"client-info"),
+                        RESPData::BulkString(entry.client_info.as_bytes()),
+                        RESPData::BulkString(b"entry-id"),
+                        RESPData::Integer(entry.entry_id as i64),
+                        RESPData::BulkString(b"timestamp-created"),
+                        RESPData::Integer(entry.created as i64),
+                        RESPData::BulkString(b"timestamp-last-updated"),
+                        RESPData::Integer(entry.updated as i64),
+                    ])
+                })
+                .collect(),
+        );
+        let mut buf = Vec::new();
+        reply.encode(&mut buf);
+        drop(acl);
+        self.stream.write_all(&buf)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(rules: &[&str]) -> User {
+        let mut user = User::default();
+        for rule in rules {
+            user.apply(rule).unwrap();
+        }
+        user
+    }
+
+    fn can_run(user: &User, name: &[u8], subcommand: Option<&[u8]>) -> bool {
+        user.can_run(&commands::lookup(name).unwrap(), subcommand)
+    }
+
+    #[test]
+    fn test_passwords_match() {
+        assert!(passwords_match(b"secret", b"secret"));
+        assert!(passwords_match(b"", b""));
+        assert!(!passwords_match(b"secret", b"Secret"));
+        assert!(!passwords_match(b"secret", b"secret!"));
+        assert!(!passwords_match(b"secret!", b"secret"));
+        assert!(!passwords_match(b"", b"secret"));
+    }
+
+    #[test]
+    fn test_command_rules() {
+        let alice = user(&["+@read", "-hgetall", "+set", "+config|get"]);
+        assert!(can_run(&alice, b"GET", None));
+        assert!(can_run(&alice, b"SET", None));
+        assert!(!can_run(&alice, b"HGETALL", None));
+        assert!(!can_run(&alice, b"LPUSH", None));
+        assert!(can_run(&alice, b"CONFIG", Some(b"GET")));
+        assert!(!can_run(&alice, b"CONFIG", Some(b"SET")));
+
+        let bob = user(&["+@all", "-@dangerous"]);
+        assert!(can_run(&bob, b"LPUSH", None));
+        assert!(!can_run(&bob, b"KEYS", None));
+        assert!(!can_run(&User::default(), b"GET", None));
+
+        let mut carol = User::default();
+        assert_eq!(
+            carol.apply("+nope"),
+            Err("Unknown command or category name in ACL")
+        );
+        assert_eq!(
+            carol.apply("+@nope"),
+            Err("Unknown command or category name in ACL")
+        );
+        assert_eq!(carol.apply("bogus"), Err("Syntax error"));
+        assert_eq!(carol.apply("éx"), Err("Syntax error"));
+        assert_eq!(
+            carol.apply("+é"),
+            Err("Unknown command or category name in ACL")
+        );
+        assert!(carol.apply(">pässwörd").is_ok());
+    }
+
+    #[test]
+    fn test_key_and_channel_patterns() {
+        let alice = user(&["~app:*", "%R~shared:*", "%W~logs:*", "&news.*"]);
+        assert!(alice.can_access_key(b"app:1", true, true));
+        assert!(alice.can_access_key(b"shared:1", true, false));
+        assert!(!alice.can_access_key(b"shared:1", false, true));
+        assert!(alice.can_access_key(b"logs:1", false, true));
+        assert!(!alice.can_access_key(b"logs:1", true, true));
+        assert!(!alice.can_access_key(b"other", true, false));
+
+        assert!(alice.can_access_channel(b"news.sports", false));
+        assert!(!alice.can_access_channel(b"weather", false));
+        assert!(alice.can_access_channel(b"news.*", true));
+        assert!(!alice.can_access_channel(b"news.s*", true));
+
+        let mut bob = user(&["allkeys"]);
+        assert!(bob.apply("~foo").is_err());
+        assert!(bob.apply("%X~foo").is_err());
+    }
+
+    #[test]
+    fn test_passwords() {
+        let mut alice = user(&["on", ">one", ">two"]);
+        let mut acl = Acl::new("");
+        acl.users.insert("alice".to_string(), alice.clone());
+        assert!(acl.authenticate("alice", b"one"));
+        assert!(acl.authenticate("alice", b"two"));
+        assert!(!acl.authenticate("alice", b"three"));
+        assert!(!acl.authenticate("bob", b"one"));
+        assert!(acl.authenticate(DEFAULT_USER, b"anything"));
+
+        assert!(alice.apply("<one").is_ok());
+        assert_eq!(
+            alice.apply("<one"),
+            Err("The password you are trying to remove from the user does not exist")
+        );
+        assert!(alice.apply("#abc").is_err());
+        alice.apply("off").unwrap();
+        acl.users.insert("alice".to_string(), alice);
+        assert!(!acl.authenticate("alice", b"two"));
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(User::default_user("").describe(), "on nopass ~* &* +@all");
+        assert_eq!(User::default().describe(), "off resetchannels -@all");
+        let alice = user(&["on", "%R~a:*", "~b", "&chan", "+get", "-@all", "+set"]);
+        assert_eq!(
+            alice.describe(),
+            "on %R~a:* ~b resetchannels &chan -@all +set"
+        );
+
+        // Describing a user gives the rules that recreate it
+        let description = alice.describe();
+        let rules: Vec<&str> = description.split(' ').collect();
+        assert_eq!(user(&rules), alice);
+    }
+
+    #[test]
+    fn test_log_grouping() {
+        let mut acl = Acl::new("");
+        let entry = |acl: &mut Acl, object: &str| {
+            acl.add_log(
+                Denial::Command,
+                "toplevel",
+                object.to_string(),
+                "alice".to_string(),
+                String::new(),
+            )
+        };
+        entry(&mut acl, "get");
+        entry(&mut acl, "set");
+        entry(&mut acl, "get");
+        assert_eq!(acl.log.len(), 2);
+        assert_eq!(acl.log[0].object, "get");
+        assert_eq!(acl.log[0].count, 2);
+        assert_eq!(acl.log[0].entry_id, 0);
+    }
+}
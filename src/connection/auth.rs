@@ -0,0 +1,140 @@
+use super::{acl::DEFAULT_USER, bulk_strings, commands, Connection, OK};
+use crate::{error::RustisError, resp::RESPData, Result};
+use std::io::Write;
+
+impl Connection {
+    /// Refuse commands until the client authenticates, other than the ones flagged as allowed
+    /// before it does, like AUTH itself
+    ///
+    /// Clients are only authenticated once, so those already connected when `requirepass` is set
+    /// with CONFIG SET stay authenticated, as in Redis.
+    pub(super) fn check_authenticated(&self, command: &[u8]) -> Result<()> {
+        let allowed = commands::lookup(&command.to_ascii_uppercase())
+            .is_some_and(|command| command.allowed_unauthenticated());
+        if self.authenticated || allowed {
+            return Ok(());
+        }
+        Err(RustisError::ClientErrorWithCode(
+            "NOAUTH",
+            "Authentication required.".to_string(),
+        ))
+    }
+
+    /// Handle AUTH, which authenticates as a user with its password, or as the default user if
+    /// only the password is given
+    pub(super) fn handle_auth(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received AUTH");
+
+        let args = bulk_strings(args)?;
+        let (user, password) = match args.as_slice() {
+            [password] => (DEFAULT_USER.as_bytes(), *password),
+            [user, password] => (*user, *password),
+            [] => return client_error!("wrong number of arguments for 'auth' command"),
+            _ => return client_error!("syntax error"),
+        };
+
+        if args.len() == 1 && self.acl.borrow().default_user_nopass() {
+            return client_error!(
+                "AUTH <password> called without any password configured for the default user. \
+                 Are you sure your configuration is correct?"
+            );
+        }
+        self.authenticate(user, password)?;
+        self.stream.write_all(OK)?;
+        Ok(())
+    }
+
+    /// Handle HELLO, which can authenticate the client as it picks the protocol version
+    ///
+    /// Only RESP2 is supported, so any other version is refused, and the client name is accepted
+    /// but not kept, like with CLIENT SETNAME.
+    pub(super) fn handle_hello(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received HELLO");
+
+        let args = bulk_strings(args)?;
+        let mut credentials = None;
+        if let [version, options @ ..] = args.as_slice() {
+            let Ok(version) = String::from_utf8_lossy(version).parse::<i64>() else {
+                return client_error!("Protocol version is not an integer or out of range");
+            };
+            if version != 2 {
+                return Err(RustisError::ClientErrorWithCode(
+                    "NOPROTO",
+                    "unsupported protocol version".to_string(),
+                ));
+            }
+
+            let mut options = options.iter();
+            while let Some(option) = options.next() {
+                match option.to_ascii_uppercase().as_slice() {
+                    b"AUTH" if options.len() >= 2 => {
+                        credentials = options.next().zip(options.next());
+                    }
+                    b"SETNAME" if options.len() >= 1 => {
+                        options.next();
+                    }
+                    _ => {
+                        return client_error!(
+                            "syntax error in HELLO option '{}'",
+                            String::from_utf8_lossy(option)
+                        )
+                    }
+                }
+            }
+        }
+
+        match credentials {
+            Some((user, password)) => self.authenticate(user, password)?,
+            None if !self.authenticated => {
+                return Err(RustisError::ClientErrorWithCode(
+                    "NOAUTH",
+                    "HELLO must be called with the client already authenticated, otherwise the \
+                     HELLO <proto> AUTH <user> <pass> option can be used to authenticate the \
+                     client and select the RESP protocol version at the same time"
+                        .to_string(),
+                ));
+            }
+            None => {}
+        }
+
+        self.write_resp(&RESPData::Array(vec![
+            RESPData::BulkString(b"server"),
+            RESPData::BulkString(b"redis"),
+            RESPData::BulkString(b"version"),
+            RESPData::BulkString(env!("CARGO_PKG_VERSION").as_bytes()),
+            RESPData::BulkString(b"proto"),
+            RESPData::Integer(2),
+            RESPData::BulkString(b"id"),
+            RESPData::Integer(self.id as i64),
+            RESPData::BulkString(b"mode"),
+            RESPData::BulkString(b"standalone"),
+            RESPData::BulkString(b"role"),
+            RESPData::BulkString(b"master"),
+            RESPData::BulkString(b"modules"),
+            RESPData::Array(vec![]),
+        ]))
+    }
+
+    /// Authenticate as a user with its password, logging the failure if it doesn't match
+    fn authenticate(&mut self, user: &[u8], password: &[u8]) -> Result<()> {
+        let user = String::from_utf8_lossy(user).into_owned();
+        if !self.acl.borrow().authenticate(&user, password) {
+            self.log_auth_failure(&user);
+            return Err(RustisError::ClientErrorWithCode(
+                "WRONGPASS",
+                "invalid username-password pair or user is disabled.".to_string(),
+            ));
+        }
+
+        self.authenticated = true;
+        self.user = user;
+        Ok(())
+    }
+
+    /// Go back to the default user, which the client is only authenticated as if it has no
+    /// password, as on RESET
+    pub(super) fn reset_authentication(&mut self) {
+        self.authenticated = self.acl.borrow().default_user_nopass();
+        self.user = DEFAULT_USER.to_string();
+    }
+}
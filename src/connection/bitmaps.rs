@@ -0,0 +1,633 @@
+use super::{parse_int, resolve_range, Connection, PROTO_MAX_BULK_LEN};
+use crate::{
+    database::{self, StringValue, Value},
+    error::RustisError,
+    notify,
+    resp::RESPData,
+    Result,
+};
+use std::ops::Range;
+
+/// What to do when a BITFIELD SET or INCRBY goes out of range of the integer type
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Overflow {
+    Wrap,
+    Sat,
+    Fail,
+}
+
+/// A BITFIELD integer type, such as `i16` or `u8`
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct BitfieldType {
+    signed: bool,
+    bits: u32,
+}
+
+impl BitfieldType {
+    fn parse(raw: &[u8]) -> Result<Self> {
+        let signed = match raw.first() {
+            Some(b'i') | Some(b'I') => true,
+            Some(b'u') | Some(b'U') => false,
+            _ => return invalid_bitfield_type(),
+        };
+        let Ok(bits) = parse_int::<u32>(&raw[1..]) else {
+            return invalid_bitfield_type();
+        };
+        // Unsigned 64 bit integers can't be returned to the client, so they're not supported
+        let max_bits = if signed { 64 } else { 63 };
+        if bits == 0 || bits > max_bits {
+            return invalid_bitfield_type();
+        }
+        Ok(BitfieldType { signed, bits })
+    }
+
+    /// Interpret the raw bits of a field as an integer of this type
+    fn decode(&self, value: u64) -> i64 {
+        if self.signed && self.bits < 64 && value & (1 << (self.bits - 1)) != 0 {
+            // Sign extend
+            (value | (u64::MAX << self.bits)) as i64
+        } else {
+            value as i64
+        }
+    }
+
+    /// Fit the result of a SET or INCRBY into this type according to the overflow behaviour
+    ///
+    /// Returns None if the value is out of range and the overflow behaviour is FAIL
+    fn fit(&self, value: i128, overflow: Overflow) -> Option<i64> {
+        let (min, max) = if self.signed {
+            (-(1i128 << (self.bits - 1)), (1i128 << (self.bits - 1)) - 1)
+        } else {
+            (0, (1i128 << self.bits) - 1)
+        };
+
+        if (min..=max).contains(&value) {
+            return Some(value as i64);
+        }
+
+        match overflow {
+            Overflow::Wrap => {
+                let mask = if self.bits == 64 {
+                    u64::MAX
+                } else {
+                    (1 << self.bits) - 1
+                };
+                Some(self.decode(value as u64 & mask))
+            }
+            Overflow::Sat => Some(if value > max { max } else { min } as i64),
+            Overflow::Fail => None,
+        }
+    }
+}
+
+fn invalid_bitfield_type<T>() -> Result<T> {
+    client_error!(
+        "Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is."
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BitfieldAction {
+    Get,
+    Set(i64),
+    IncrBy(i64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct BitfieldOp {
+    action: BitfieldAction,
+    field_type: BitfieldType,
+    offset: u64,
+    overflow: Overflow,
+}
+
+impl BitfieldOp {
+    /// Run a SET or INCRBY on the bytes, which need to be large enough to hold the field
+    ///
+    /// Returns the old value for SET and the new value for INCRBY, or None if the operation
+    /// failed due to overflow
+    fn apply(&self, bytes: &mut [u8]) -> Option<i64> {
+        let field_type = self.field_type;
+        let old = field_type.decode(get_bits(bytes, self.offset, field_type.bits));
+
+        // Unsigned values are treated as unsigned 64 bit integers before fitting them, so that
+        // negative values saturate to the maximum, which is what Redis does
+        let (new, reply) = match self.action {
+            BitfieldAction::Get => return Some(old),
+            BitfieldAction::Set(value) if field_type.signed => {
+                (field_type.fit(value as i128, self.overflow)?, old)
+            }
+            BitfieldAction::Set(value) => {
+                (field_type.fit(value as u64 as i128, self.overflow)?, old)
+            }
+            BitfieldAction::IncrBy(increment) => {
+                let new = field_type.fit(old as i128 + increment as i128, self.overflow)?;
+                (new, new)
+            }
+        };
+
+        set_bits(bytes, self.offset, field_type.bits, new as u64);
+
+        Some(reply)
+    }
+}
+
+/// Parse a bit offset, which can't point past the maximum size of a string
+fn parse_bit_offset(raw: &[u8]) -> Result<u64> {
+    match parse_int::<u64>(raw) {
+        Ok(offset) if offset >> 3 < PROTO_MAX_BULK_LEN as u64 => Ok(offset),
+        _ => client_error!("bit offset is not an integer or out of range"),
+    }
+}
+
+/// Parse a BITFIELD offset, which can be prefixed with # to be multiplied by the type width
+fn parse_bitfield_offset(raw: &[u8], field_type: BitfieldType) -> Result<u64> {
+    let offset = match raw.strip_prefix(b"#") {
+        Some(index) => parse_bit_offset(index)?.saturating_mul(field_type.bits as u64),
+        None => parse_bit_offset(raw)?,
+    };
+    if offset.saturating_add(field_type.bits as u64 - 1) >> 3 >= PROTO_MAX_BULK_LEN as u64 {
+        return client_error!("bit offset is not an integer or out of range");
+    }
+    Ok(offset)
+}
+
+/// Parse the BYTE or BIT unit of a BITCOUNT or BITPOS range, returning true for BIT
+fn parse_bit_unit(raw: &[u8]) -> Result<bool> {
+    match raw.to_ascii_uppercase().as_slice() {
+        b"BYTE" => Ok(false),
+        b"BIT" => Ok(true),
+        _ => client_error!("syntax error"),
+    }
+}
+
+/// Resolve a BITCOUNT or BITPOS range into a range of bits
+fn resolve_bit_range(len: usize, start: i64, end: i64, bit_unit: bool) -> Range<u64> {
+    if bit_unit {
+        let range = resolve_range(len * 8, start, end);
+        range.start as u64..range.end as u64
+    } else {
+        let range = resolve_range(len, start, end);
+        range.start as u64 * 8..range.end as u64 * 8
+    }
+}
+
+/// Get a single bit, where bit 0 is the most significant bit of the first byte
+fn get_bit(bytes: &[u8], offset: u64) -> u8 {
+    match bytes.get((offset >> 3) as usize) {
+        Some(byte) => (byte >> (7 - (offset & 7))) & 1,
+        None => 0,
+    }
+}
+
+/// Get a number of bits starting at the offset, with the first bit being the most significant
+fn get_bits(bytes: &[u8], offset: u64, bits: u32) -> u64 {
+    (0..bits as u64).fold(0, |value, i| {
+        (value << 1) | get_bit(bytes, offset + i) as u64
+    })
+}
+
+/// Set the lowest bits of the value starting at the offset, the bytes have to be large enough
+fn set_bits(bytes: &mut [u8], offset: u64, bits: u32, value: u64) {
+    for i in 0..bits as u64 {
+        let bit = (value >> (bits as u64 - 1 - i)) & 1;
+        let offset = offset + i;
+        let byte = &mut bytes[(offset >> 3) as usize];
+        let mask = 1 << (7 - (offset & 7));
+        if bit == 1 {
+            *byte |= mask;
+        } else {
+            *byte &= !mask;
+        }
+    }
+}
+
+/// Count the set bits within the range of bits
+fn count_bits(bytes: &[u8], range: Range<u64>) -> u64 {
+    if range.is_empty() {
+        return 0;
+    }
+    let first = (range.start >> 3) as usize;
+    let last = ((range.end - 1) >> 3) as usize;
+
+    bytes[first..=last]
+        .iter()
+        .enumerate()
+        .map(|(i, &byte)| {
+            let mut byte = byte;
+            // Mask out the bits outside of the range in the first and last bytes
+            if i == 0 {
+                byte &= 0xFF >> (range.start & 7);
+            }
+            if first + i == last {
+                byte &= 0xFF << (7 - ((range.end - 1) & 7));
+            }
+            byte.count_ones() as u64
+        })
+        .sum()
+}
+
+/// Find the first bit within the range that is set to the given value
+fn find_bit(bytes: &[u8], bit: u8, range: Range<u64>) -> Option<u64> {
+    let skippable = if bit == 1 { 0x00 } else { 0xFF };
+
+    let mut offset = range.start;
+    while offset < range.end {
+        // Skip over whole bytes that can't contain the bit
+        let byte = bytes[(offset >> 3) as usize];
+        if offset & 7 == 0 && offset + 8 <= range.end && byte == skippable {
+            offset += 8;
+            continue;
+        }
+        if get_bit(bytes, offset) == bit {
+            return Some(offset);
+        }
+        offset += 1;
+    }
+
+    None
+}
+
+impl Connection {
+    pub(super) fn handle_setbit(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received SETBIT");
+
+        let [RESPData::BulkString(key), RESPData::BulkString(offset), RESPData::BulkString(bit)] =
+            args
+        else {
+            return client_error!("wrong number of arguments for 'setbit' command");
+        };
+
+        let offset = parse_bit_offset(offset)?;
+        let bit = match *bit {
+            b"0" => 0,
+            b"1" => 1,
+            _ => return client_error!("bit is not an integer or out of range"),
+        };
+
+        let mut db = database::lock(0);
+        if db.get_string_mut(key)?.is_none() {
+            db.insert(key, Value::String(StringValue::Raw(Vec::new())));
+        }
+        let bytes = db.get_string_mut(key)?.unwrap().raw_mut();
+
+        let needed = (offset >> 3) as usize + 1;
+        if bytes.len() < needed {
+            bytes.resize(needed, 0);
+        }
+        let previous = get_bit(bytes, offset);
+        set_bits(bytes, offset, 1, bit);
+        db.touch(key);
+        db.notify(notify::STRING, "setbit", key);
+        drop(db);
+
+        self.write_integer(previous as i64)
+    }
+
+    pub(super) fn handle_getbit(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received GETBIT");
+
+        let [RESPData::BulkString(key), RESPData::BulkString(offset)] = args else {
+            return client_error!("wrong number of arguments for 'getbit' command");
+        };
+
+        let offset = parse_bit_offset(offset)?;
+
+        let bit = database::lock(0)
+            .get_string(key)?
+            .map_or(0, |value| get_bit(&value.as_bytes(), offset));
+
+        self.write_integer(bit as i64)
+    }
+
+    pub(super) fn handle_bitcount(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received BITCOUNT");
+
+        let (key, range) = match args {
+            [RESPData::BulkString(key)] => (key, None),
+            [RESPData::BulkString(key), RESPData::BulkString(start), RESPData::BulkString(end), unit @ ..]
+                if unit.len() <= 1 =>
+            {
+                let bit_unit = match unit {
+                    [RESPData::BulkString(unit)] => parse_bit_unit(unit)?,
+                    _ => false,
+                };
+                let start: i64 = parse_int(start)?;
+                let end: i64 = parse_int(end)?;
+                (key, Some((start, end, bit_unit)))
+            }
+            [] => return client_error!("wrong number of arguments for 'bitcount' command"),
+            _ => return client_error!("syntax error"),
+        };
+
+        let mut db = database::lock(0);
+        let Some(value) = db.get_string(key)? else {
+            return self.write_integer(0);
+        };
+        let bytes = value.as_bytes();
+
+        let range = match range {
+            Some((start, end, bit_unit)) => resolve_bit_range(bytes.len(), start, end, bit_unit),
+            None => 0..bytes.len() as u64 * 8,
+        };
+        let count = count_bits(&bytes, range);
+
+        self.write_integer(count as i64)
+    }
+
+    pub(super) fn handle_bitpos(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received BITPOS");
+
+        let Some((RESPData::BulkString(key), args)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'bitpos' command");
+        };
+        let Some((RESPData::BulkString(bit), args)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'bitpos' command");
+        };
+
+        let bit = match *bit {
+            b"0" => 0,
+            b"1" => 1,
+            _ => return client_error!("The bit argument must be 1 or 0."),
+        };
+
+        let (start, end, bit_unit) = match args {
+            [] => (0, None, false),
+            [RESPData::BulkString(start)] => (parse_int(start)?, None, false),
+            [RESPData::BulkString(start), RESPData::BulkString(end)] => {
+                (parse_int(start)?, Some(parse_int(end)?), false)
+            }
+            [RESPData::BulkString(start), RESPData::BulkString(end), RESPData::BulkString(unit)] => {
+                let bit_unit = parse_bit_unit(unit)?;
+                (parse_int(start)?, Some(parse_int(end)?), bit_unit)
+            }
+            _ => return client_error!("syntax error"),
+        };
+
+        let mut db = database::lock(0);
+        let Some(value) = db.get_string(key)? else {
+            // A missing key is treated as an empty string, which is all clear bits
+            return self.write_integer(if bit == 1 { -1 } else { 0 });
+        };
+        let bytes = value.as_bytes();
+
+        let range = resolve_bit_range(bytes.len(), start, end.unwrap_or(-1), bit_unit);
+        if range.is_empty() {
+            return self.write_integer(-1);
+        }
+
+        let position = match find_bit(&bytes, bit, range.clone()) {
+            Some(position) => position as i64,
+            // When looking for a clear bit without an explicit end, the string is considered to
+            // be padded with clear bits to the right
+            None if bit == 0 && end.is_none() => range.end as i64,
+            None => -1,
+        };
+
+        self.write_integer(position)
+    }
+
+    pub(super) fn handle_bitop(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received BITOP");
+
+        let [RESPData::BulkString(operation), RESPData::BulkString(dest), keys @ ..] = args else {
+            return client_error!("wrong number of arguments for 'bitop' command");
+        };
+        if keys.is_empty() {
+            return client_error!("wrong number of arguments for 'bitop' command");
+        }
+
+        let operation = operation.to_ascii_uppercase();
+        match operation.as_slice() {
+            b"NOT" if keys.len() != 1 => {
+                return client_error!("BITOP NOT must be called with a single source key.");
+            }
+            b"DIFF" if keys.len() < 2 => {
+                return client_error!("BITOP DIFF must be called with at least two source keys.");
+            }
+            b"AND" | b"OR" | b"XOR" | b"NOT" | b"DIFF" => {}
+            _ => return client_error!("syntax error"),
+        }
+
+        let mut db = database::lock(0);
+
+        // Missing keys are treated as empty strings
+        let sources = keys
+            .iter()
+            .map(|key| match key {
+                RESPData::BulkString(key) => Ok(db
+                    .get_string(key)?
+                    .map(|value| value.as_bytes().into_owned())
+                    .unwrap_or_default()),
+                _ => client_error!("syntax error"),
+            })
+            .collect::<Result<Vec<Vec<u8>>>>()?;
+
+        // Shorter strings are padded with zero bytes up to the length of the longest one
+        let length = sources.iter().map(|s| s.len()).max().unwrap_or(0);
+        let byte = |source: &Vec<u8>, i: usize| source.get(i).copied().unwrap_or(0);
+
+        let result: Vec<u8> = (0..length)
+            .map(|i| match operation.as_slice() {
+                b"AND" => sources.iter().fold(0xFF, |acc, s| acc & byte(s, i)),
+                b"OR" => sources.iter().fold(0x00, |acc, s| acc | byte(s, i)),
+                b"XOR" => sources.iter().fold(0x00, |acc, s| acc ^ byte(s, i)),
+                b"NOT" => !byte(&sources[0], i),
+                b"DIFF" => {
+                    let others = sources[1..].iter().fold(0x00, |acc, s| acc | byte(s, i));
+                    byte(&sources[0], i) & !others
+                }
+                _ => unreachable!(),
+            })
+            .collect();
+
+        // An empty result deletes the destination
+        if result.is_empty() {
+            if db.remove(dest).is_some() {
+                db.notify(notify::GENERIC, "del", dest);
+            }
+        } else {
+            db.insert(dest, Value::String(StringValue::Raw(result)));
+            db.set_expiry(dest, None);
+            db.notify(notify::STRING, "set", dest);
+        }
+
+        self.write_integer(length as i64)
+    }
+
+    /// Handle BITFIELD and BITFIELD_RO, where the latter only allows GET
+    pub(super) fn handle_bitfield(&mut self, args: &[RESPData], read_only: bool) -> Result<()> {
+        log::debug!("Received BITFIELD");
+
+        let Some((RESPData::BulkString(key), args)) = args.split_first() else {
+            return client_error!(
+                "wrong number of arguments for '{}' command",
+                if read_only { "bitfield_ro" } else { "bitfield" }
+            );
+        };
+
+        let mut ops = Vec::new();
+        let mut overflow = Overflow::Wrap;
+
+        let mut iter = args.iter();
+        while let Some(RESPData::BulkString(subcommand)) = iter.next() {
+            let subcommand = subcommand.to_ascii_uppercase();
+
+            if subcommand == b"OVERFLOW" {
+                let Some(RESPData::BulkString(behaviour)) = iter.next() else {
+                    return client_error!("syntax error");
+                };
+                overflow = match behaviour.to_ascii_uppercase().as_slice() {
+                    b"WRAP" => Overflow::Wrap,
+                    b"SAT" => Overflow::Sat,
+                    b"FAIL" => Overflow::Fail,
+                    _ => return client_error!("Invalid OVERFLOW type specified"),
+                };
+                continue;
+            }
+
+            if !matches!(subcommand.as_slice(), b"GET" | b"SET" | b"INCRBY") {
+                return client_error!("syntax error");
+            }
+            if read_only && subcommand != b"GET" {
+                return client_error!("BITFIELD_RO only supports the GET subcommand");
+            }
+
+            let (Some(RESPData::BulkString(field_type)), Some(RESPData::BulkString(offset))) =
+                (iter.next(), iter.next())
+            else {
+                return client_error!("syntax error");
+            };
+            let field_type = BitfieldType::parse(field_type)?;
+            let offset = parse_bitfield_offset(offset, field_type)?;
+
+            let action = match subcommand.as_slice() {
+                b"GET" => BitfieldAction::Get,
+                _ => {
+                    let Some(RESPData::BulkString(value)) = iter.next() else {
+                        return client_error!("syntax error");
+                    };
+                    let value = parse_int(value)?;
+                    if subcommand == b"SET" {
+                        BitfieldAction::Set(value)
+                    } else {
+                        BitfieldAction::IncrBy(value)
+                    }
+                }
+            };
+
+            ops.push(BitfieldOp {
+                action,
+                field_type,
+                offset,
+                overflow,
+            });
+        }
+
+        // The string only needs to be created or grown if there are any writes
+        let write_end = ops
+            .iter()
+            .filter(|op| op.action != BitfieldAction::Get)
+            .map(|op| op.offset + op.field_type.bits as u64)
+            .max();
+
+        let mut db = database::lock(0);
+
+        let results: Vec<Option<i64>> = match write_end {
+            Some(write_end) => {
+                if db.get_string_mut(key)?.is_none() {
+                    db.insert(key, Value::String(StringValue::Raw(Vec::new())));
+                }
+                let bytes = db.get_string_mut(key)?.unwrap().raw_mut();
+                let needed = write_end.div_ceil(8) as usize;
+                if bytes.len() < needed {
+                    bytes.resize(needed, 0);
+                }
+                let results: Vec<Option<i64>> = ops.iter().map(|op| op.apply(bytes)).collect();
+                // Writes that failed because of an overflow don't change anything
+                let changed = ops
+                    .iter()
+                    .zip(&results)
+                    .any(|(op, result)| op.action != BitfieldAction::Get && result.is_some());
+                if changed {
+                    db.touch(key);
+                    db.notify(notify::STRING, "setbit", key);
+                }
+                results
+            }
+            None => {
+                let bytes = db
+                    .get_string(key)?
+                    .map(|v| v.as_bytes())
+                    .unwrap_or_default();
+                ops.iter()
+                    .map(|op| {
+                        let bits = get_bits(&bytes, op.offset, op.field_type.bits);
+                        Some(op.field_type.decode(bits))
+                    })
+                    .collect()
+            }
+        };
+
+        self.write_resp(&RESPData::Array(
+            results
+                .into_iter()
+                .map(|result| match result {
+                    Some(value) => RESPData::Integer(value),
+                    None => RESPData::Null,
+                })
+                .collect(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_and_set_bits() {
+        let mut bytes = vec![0u8; 2];
+        set_bits(&mut bytes, 4, 8, 0xAB);
+        assert_eq!(bytes, vec![0x0A, 0xB0]);
+        assert_eq!(get_bits(&bytes, 4, 8), 0xAB);
+        assert_eq!(get_bit(&bytes, 4), 1);
+        assert_eq!(get_bit(&bytes, 5), 0);
+        // Reading past the end is all zeroes
+        assert_eq!(get_bits(&bytes, 12, 8), 0);
+    }
+
+    #[test]
+    fn test_count_bits() {
+        let bytes = b"foobar";
+        assert_eq!(count_bits(bytes, 0..48), 26);
+        assert_eq!(count_bits(bytes, resolve_bit_range(6, 1, 1, false)), 6);
+        assert_eq!(count_bits(bytes, resolve_bit_range(6, 5, 30, true)), 17);
+    }
+
+    #[test]
+    fn test_find_bit() {
+        let bytes = [0xFF, 0xF0, 0x00];
+        assert_eq!(find_bit(&bytes, 0, 0..24), Some(12));
+        assert_eq!(find_bit(&bytes, 1, 8..24), Some(8));
+        assert_eq!(find_bit(&bytes, 1, 12..24), None);
+    }
+
+    #[test]
+    fn test_bitfield_type_fit() {
+        let u8_type = BitfieldType::parse(b"u8").unwrap();
+        assert_eq!(u8_type.fit(256, Overflow::Wrap), Some(0));
+        assert_eq!(u8_type.fit(300, Overflow::Sat), Some(255));
+        assert_eq!(u8_type.fit(-1, Overflow::Sat), Some(0));
+        assert_eq!(u8_type.fit(256, Overflow::Fail), None);
+
+        let i8_type = BitfieldType::parse(b"i8").unwrap();
+        assert_eq!(i8_type.fit(128, Overflow::Wrap), Some(-128));
+        assert_eq!(i8_type.fit(-129, Overflow::Sat), Some(-128));
+        assert_eq!(i8_type.decode(0xFF), -1);
+
+        assert!(BitfieldType::parse(b"u64").is_err());
+        assert!(BitfieldType::parse(b"i65").is_err());
+        assert!(BitfieldType::parse(b"x8").is_err());
+    }
+}
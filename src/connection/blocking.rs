@@ -0,0 +1,331 @@
+use super::{lists, sorted_sets, streams, Connection, NULL, NULL_ARRAY};
+use crate::{
+    database::{self, StreamId},
+    Result,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    io::Write,
+    time::Instant,
+};
+
+/// The registry of clients that are blocked waiting for keys, shared between all connections
+///
+/// Connections signal keys as ready when they push to them, and the server then tries to serve
+/// the clients blocked on those keys, in the order that they blocked.
+#[derive(Debug, Default)]
+pub(crate) struct BlockedClients {
+    /// The clients blocked on each key, in the order that they blocked
+    queues: HashMap<Vec<u8>, VecDeque<u64>>,
+    /// The keys that each blocked client is waiting on
+    clients: HashMap<u64, Vec<Vec<u8>>>,
+    /// Keys that clients are blocked on and have been pushed to since they were last checked
+    ready_keys: Vec<Vec<u8>>,
+    /// Clients unblocked with CLIENT UNBLOCK, along with whether they should get an error
+    unblock_requests: Vec<(u64, bool)>,
+}
+
+impl BlockedClients {
+    fn block(&mut self, id: u64, keys: &[Vec<u8>]) {
+        for key in keys {
+            let queue = self.queues.entry(key.clone()).or_default();
+            // The same key can be passed more than once, but the client only queues once
+            if !queue.contains(&id) {
+                queue.push_back(id);
+            }
+        }
+        self.clients.insert(id, keys.to_vec());
+    }
+
+    /// Remove a client from the queues of all the keys it was waiting on
+    pub(crate) fn unblock(&mut self, id: u64) {
+        let Some(keys) = self.clients.remove(&id) else {
+            return;
+        };
+        for key in keys {
+            if let Some(queue) = self.queues.get_mut(&key) {
+                queue.retain(|&waiting| waiting != id);
+                if queue.is_empty() {
+                    self.queues.remove(&key);
+                }
+            }
+        }
+    }
+
+    /// Mark a key as ready, if any clients are waiting on it
+    pub(crate) fn signal_key_ready(&mut self, key: &[u8]) {
+        if self.queues.contains_key(key) && !self.ready_keys.iter().any(|k| k == key) {
+            self.ready_keys.push(key.to_vec());
+        }
+    }
+
+    pub(crate) fn take_ready_keys(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.ready_keys)
+    }
+
+    /// The clients blocked on a key, in the order that they should be served
+    pub(crate) fn waiting_on(&self, key: &[u8]) -> Vec<u64> {
+        self.queues
+            .get(key)
+            .map(|queue| queue.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Request a blocked client to be unblocked, returning false if it isn't blocked
+    fn request_unblock(&mut self, id: u64, error: bool) -> bool {
+        if !self.clients.contains_key(&id) {
+            return false;
+        }
+        self.unblock_requests.push((id, error));
+        true
+    }
+
+    pub(crate) fn take_unblock_requests(&mut self) -> Vec<(u64, bool)> {
+        std::mem::take(&mut self.unblock_requests)
+    }
+}
+
+/// A command that a client is blocked on, to be retried when one of its keys is ready
+#[derive(Debug)]
+pub(super) enum BlockedCommand {
+    /// BLPOP and BRPOP, or BLMPOP when there is a count
+    Pop { left: bool, count: Option<usize> },
+    /// BLMOVE and BRPOPLPUSH
+    Move {
+        destination: Vec<u8>,
+        from_left: bool,
+        to_left: bool,
+    },
+    /// BZPOPMIN and BZPOPMAX
+    ZPop { max: bool },
+    /// XREAD with BLOCK, which reads the entries after an ID of each stream, up to a count (or
+    /// all of them if it's zero)
+    XRead {
+        after: Vec<(Vec<u8>, StreamId)>,
+        count: usize,
+    },
+    /// XREADGROUP with BLOCK, which delivers the new entries of a stream to a consumer of a group
+    XReadGroup {
+        group: Vec<u8>,
+        consumer: Vec<u8>,
+        count: usize,
+        no_ack: bool,
+    },
+}
+
+#[derive(Debug)]
+pub(super) struct Blocked {
+    command: BlockedCommand,
+    /// When the client should stop waiting, or `None` to wait forever
+    deadline: Option<Instant>,
+}
+
+impl Connection {
+    /// Block the client on the keys, until one of them is ready or the deadline is reached
+    ///
+    /// No more input is processed for the client until it's unblocked
+    pub(super) fn block(
+        &mut self,
+        keys: &[Vec<u8>],
+        deadline: Option<Instant>,
+        command: BlockedCommand,
+    ) {
+        log::debug!("Blocking client {} on {} keys", self.id, keys.len());
+        self.blocked_clients.borrow_mut().block(self.id, keys);
+        self.blocked = Some(Blocked { command, deadline });
+    }
+
+    pub(crate) fn is_blocked(&self) -> bool {
+        self.blocked.is_some()
+    }
+
+    pub(crate) fn blocked_deadline(&self) -> Option<Instant> {
+        self.blocked.as_ref().and_then(|blocked| blocked.deadline)
+    }
+
+    fn unblock(&mut self) -> Option<Blocked> {
+        self.blocked_clients.borrow_mut().unblock(self.id);
+        self.blocked.take()
+    }
+
+    /// Try to serve the blocked command from a key that is ready
+    ///
+    /// Returns true if the client was served and has been unblocked
+    pub(crate) fn serve_blocked(&mut self, key: &[u8]) -> Result<bool> {
+        let Some(blocked) = &self.blocked else {
+            return Ok(false);
+        };
+
+        let mut db = database::lock(0);
+        match &blocked.command {
+            BlockedCommand::Pop { left, count } => {
+                // The key might have been emptied by a client served before this one, or been
+                // replaced with another type
+                let Ok(Some(elements)) =
+                    lists::pop_elements(&mut db, key, *left, count.unwrap_or(1))
+                else {
+                    return Ok(false);
+                };
+                let count = *count;
+                drop(db);
+                self.unblock();
+                self.write_popped(key, &elements, count.is_some())?;
+            }
+            BlockedCommand::Move {
+                destination,
+                from_left,
+                to_left,
+            } => {
+                if !matches!(db.get_list_mut(key), Ok(Some(_))) {
+                    return Ok(false);
+                }
+                let destination = destination.clone();
+                let moved = lists::move_element(&mut db, key, &destination, *from_left, *to_left);
+                drop(db);
+                self.unblock();
+                match moved {
+                    Ok(element) => {
+                        self.signal_key_ready(&destination);
+                        self.write_optional_bulk_string(element.as_deref())?;
+                    }
+                    Err(e) => self.write_command_error(e)?,
+                }
+            }
+            BlockedCommand::ZPop { max } => {
+                let Ok(Some(popped)) = sorted_sets::pop_entries(&mut db, key, *max, 1) else {
+                    return Ok(false);
+                };
+                drop(db);
+                self.unblock();
+                self.write_zpopped(key, &popped[0])?;
+            }
+            BlockedCommand::XRead { after, count } => {
+                // Only the stream that was added to is replied with, even if others have entries
+                let Some((_, id)) = after.iter().find(|(k, _)| k == key) else {
+                    return Ok(false);
+                };
+                let entries = match streams::read_entries(&mut db, key, *id, *count) {
+                    Ok(entries) if !entries.is_empty() => entries,
+                    _ => return Ok(false),
+                };
+                drop(db);
+                self.unblock();
+                self.write_streams(&[(key, entries)])?;
+            }
+            BlockedCommand::XReadGroup {
+                group,
+                consumer,
+                count,
+                no_ack,
+            } => {
+                let entries = match db.get_stream_mut(key) {
+                    Ok(Some(stream)) => {
+                        stream.read_group(group, consumer, *count, *no_ack, database::now())
+                    }
+                    _ => None,
+                };
+                if entries.as_ref().is_some_and(|entries| !entries.is_empty()) {
+                    db.touch(key);
+                }
+                drop(db);
+                match entries {
+                    Some(entries) if entries.is_empty() => return Ok(false),
+                    Some(entries) => {
+                        self.unblock();
+                        let entries = entries.into_iter().map(|(id, f)| (id, Some(f))).collect();
+                        self.write_group_streams(&[(key, entries)])?;
+                    }
+                    // The group was destroyed, or the stream was replaced, while blocked
+                    None => {
+                        self.unblock();
+                        self.write_error_with_code(
+                            b"NOGROUP",
+                            b"the consumer group this client was blocked on no longer exists",
+                        )?;
+                    }
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Unblock the client with the reply for when the timeout is reached
+    pub(crate) fn time_out(&mut self) -> Result<()> {
+        let Some(blocked) = self.unblock() else {
+            return Ok(());
+        };
+        log::debug!("Client {} timed out while blocked", self.id);
+        match blocked.command {
+            BlockedCommand::Pop { .. }
+            | BlockedCommand::ZPop { .. }
+            | BlockedCommand::XRead { .. }
+            | BlockedCommand::XReadGroup { .. } => self.stream.write_all(NULL_ARRAY)?,
+            BlockedCommand::Move { .. } => self.stream.write_all(NULL)?,
+        }
+        Ok(())
+    }
+
+    /// Unblock the client because of CLIENT UNBLOCK, either as if it timed out or with an error
+    pub(crate) fn force_unblock(&mut self, error: bool) -> Result<()> {
+        if !error {
+            return self.time_out();
+        }
+        if self.unblock().is_some() {
+            self.write_error_with_code(b"UNBLOCKED", b"client unblocked via CLIENT UNBLOCK")?;
+        }
+        Ok(())
+    }
+
+    /// Signal that a key has been pushed to, so clients blocked on it can be served
+    pub(super) fn signal_key_ready(&self, key: &[u8]) {
+        self.blocked_clients.borrow_mut().signal_key_ready(key);
+    }
+
+    /// Request another client to be unblocked, returning false if it isn't blocked
+    pub(super) fn request_unblock(&self, id: u64, error: bool) -> bool {
+        self.blocked_clients.borrow_mut().request_unblock(id, error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocked_clients_queue_in_order() {
+        let mut blocked = BlockedClients::default();
+        blocked.block(1, &[b"a".to_vec(), b"b".to_vec()]);
+        blocked.block(2, &[b"a".to_vec(), b"a".to_vec()]);
+
+        assert_eq!(blocked.waiting_on(b"a"), vec![1, 2]);
+        assert_eq!(blocked.waiting_on(b"b"), vec![1]);
+
+        blocked.unblock(1);
+        assert_eq!(blocked.waiting_on(b"a"), vec![2]);
+        assert_eq!(blocked.waiting_on(b"b"), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn test_blocked_clients_ready_keys() {
+        let mut blocked = BlockedClients::default();
+        blocked.block(1, &[b"a".to_vec()]);
+
+        // Keys nobody is waiting on are never ready
+        blocked.signal_key_ready(b"b");
+        blocked.signal_key_ready(b"a");
+        blocked.signal_key_ready(b"a");
+        assert_eq!(blocked.take_ready_keys(), vec![b"a".to_vec()]);
+        assert!(blocked.take_ready_keys().is_empty());
+    }
+
+    #[test]
+    fn test_blocked_clients_unblock_requests() {
+        let mut blocked = BlockedClients::default();
+        blocked.block(1, &[b"a".to_vec()]);
+
+        assert!(blocked.request_unblock(1, true));
+        assert!(!blocked.request_unblock(2, false));
+        assert_eq!(blocked.take_unblock_requests(), vec![(1, true)]);
+    }
+}
@@ -0,0 +1,538 @@
+/// The command modifies the dataset
+const WRITE: u32 = 1 << 0;
+/// The command only reads the dataset
+const READ: u32 = 1 << 1;
+/// The command can't be run from scripts
+const NOSCRIPT: u32 = 1 << 2;
+/// The command runs in constant or logarithmic time, other commands are slow
+const FAST: u32 = 1 << 3;
+/// The command administers the server
+const ADMIN: u32 = 1 << 4;
+/// The command can harm the server, such as by being slow on large datasets or changing its
+/// configuration
+const DANGEROUS: u32 = 1 << 5;
+/// The command can block the client
+const BLOCKING: u32 = 1 << 6;
+/// The command also reads the keys it writes, such as to reply with their old value
+const ACCESS: u32 = 1 << 7;
+/// The command writes its first key, and only reads the others, like SINTERSTORE
+const STORE: u32 = 1 << 8;
+/// The command can run before the client authenticates
+const NO_AUTH: u32 = 1 << 9;
+
+// The categories of commands, as used by ACL rules such as "+@string"
+const KEYSPACE: u32 = 1 << 10;
+const STRING: u32 = 1 << 11;
+const BITMAP: u32 = 1 << 12;
+const LIST: u32 = 1 << 13;
+const HASH: u32 = 1 << 14;
+const SET: u32 = 1 << 15;
+const SORTEDSET: u32 = 1 << 16;
+const STREAM: u32 = 1 << 17;
+const GEO: u32 = 1 << 18;
+const HYPERLOGLOG: u32 = 1 << 19;
+const PUBSUB: u32 = 1 << 20;
+const TRANSACTION: u32 = 1 << 21;
+const SCRIPTING: u32 = 1 << 22;
+const CONNECTION: u32 = 1 << 23;
+
+/// The ACL categories by name, with the flag of the commands in them
+///
+/// The "slow" category is the commands that aren't fast, so it has no flag of its own.
+pub(super) const CATEGORIES: &[(&str, u32)] = &[
+    ("keyspace", KEYSPACE),
+    ("read", READ),
+    ("write", WRITE),
+    ("set", SET),
+    ("sortedset", SORTEDSET),
+    ("list", LIST),
+    ("hash", HASH),
+    ("string", STRING),
+    ("bitmap", BITMAP),
+    ("hyperloglog", HYPERLOGLOG),
+    ("geo", GEO),
+    ("stream", STREAM),
+    ("pubsub", PUBSUB),
+    ("admin", ADMIN),
+    ("fast", FAST),
+    ("slow", 0),
+    ("blocking", BLOCKING),
+    ("dangerous", DANGEROUS),
+    ("connection", CONNECTION),
+    ("transaction", TRANSACTION),
+    ("scripting", SCRIPTING),
+];
+
+/// A command's name, arity, flags, first key, last key and step between keys
+type CommandSpec = (&'static [u8], i32, u32, i32, i32, i32);
+
+/// The table of supported commands, with their arity, flags and keys as in the Redis command table
+///
+/// A positive arity is the exact number of arguments, including the command name, while a negative
+/// arity is the minimum number of arguments. The keys are the arguments from the first key to the
+/// last key, where a negative last key counts from the end, every step. A first key of 0 means
+/// that the command has no keys, or that they're found as described in `Command::key_positions`.
+const COMMANDS: &[CommandSpec] = &[
+    (b"PING", -1, FAST | CONNECTION, 0, 0, 0),
+    (b"COMMAND", -1, CONNECTION, 0, 0, 0),
+    (b"ECHO", 2, FAST | CONNECTION, 0, 0, 0),
+    (b"SET", -3, WRITE | STRING, 1, 1, 1),
+    (b"GET", 2, READ | FAST | STRING, 1, 1, 1),
+    (b"SETNX", 3, WRITE | FAST | STRING, 1, 1, 1),
+    (b"SETEX", 4, WRITE | STRING, 1, 1, 1),
+    (b"PSETEX", 4, WRITE | STRING, 1, 1, 1),
+    (b"GETSET", 3, WRITE | ACCESS | FAST | STRING, 1, 1, 1),
+    (b"GETDEL", 2, WRITE | ACCESS | FAST | STRING, 1, 1, 1),
+    (b"GETEX", -2, WRITE | ACCESS | FAST | STRING, 1, 1, 1),
+    (b"APPEND", 3, WRITE | FAST | STRING, 1, 1, 1),
+    (b"STRLEN", 2, READ | FAST | STRING, 1, 1, 1),
+    (b"GETRANGE", 4, READ | STRING, 1, 1, 1),
+    (b"SETRANGE", 4, WRITE | STRING, 1, 1, 1),
+    (b"INCR", 2, WRITE | ACCESS | FAST | STRING, 1, 1, 1),
+    (b"DECR", 2, WRITE | ACCESS | FAST | STRING, 1, 1, 1),
+    (b"INCRBY", 3, WRITE | ACCESS | FAST | STRING, 1, 1, 1),
+    (b"DECRBY", 3, WRITE | ACCESS | FAST | STRING, 1, 1, 1),
+    (b"INCRBYFLOAT", 3, WRITE | ACCESS | FAST | STRING, 1, 1, 1),
+    (b"MGET", -2, READ | FAST | STRING, 1, -1, 1),
+    (b"MSET", -3, WRITE | STRING, 1, -1, 2),
+    (b"MSETNX", -3, WRITE | STRING, 1, -1, 2),
+    (b"SETBIT", 4, WRITE | ACCESS | BITMAP, 1, 1, 1),
+    (b"GETBIT", 3, READ | FAST | BITMAP, 1, 1, 1),
+    (b"BITCOUNT", -2, READ | BITMAP, 1, 1, 1),
+    (b"BITPOS", -3, READ | BITMAP, 1, 1, 1),
+    (b"BITOP", -4, WRITE | STORE | BITMAP, 2, -1, 1),
+    (b"BITFIELD", -2, WRITE | ACCESS | BITMAP, 1, 1, 1),
+    (b"BITFIELD_RO", -2, READ | FAST | BITMAP, 1, 1, 1),
+    (b"LPUSH", -3, WRITE | FAST | LIST, 1, 1, 1),
+    (b"RPUSH", -3, WRITE | FAST | LIST, 1, 1, 1),
+    (b"LPUSHX", -3, WRITE | FAST | LIST, 1, 1, 1),
+    (b"RPUSHX", -3, WRITE | FAST | LIST, 1, 1, 1),
+    (b"LPOP", -2, WRITE | ACCESS | FAST | LIST, 1, 1, 1),
+    (b"RPOP", -2, WRITE | ACCESS | FAST | LIST, 1, 1, 1),
+    (b"LLEN", 2, READ | FAST | LIST, 1, 1, 1),
+    (b"LRANGE", 4, READ | LIST, 1, 1, 1),
+    (b"LINDEX", 3, READ | LIST, 1, 1, 1),
+    (b"LSET", 4, WRITE | LIST, 1, 1, 1),
+    (b"LINSERT", 5, WRITE | LIST, 1, 1, 1),
+    (b"LREM", 4, WRITE | LIST, 1, 1, 1),
+    (b"LTRIM", 4, WRITE | LIST, 1, 1, 1),
+    (b"LMOVE", 5, WRITE | ACCESS | LIST, 1, 2, 1),
+    (b"RPOPLPUSH", 3, WRITE | ACCESS | LIST, 1, 2, 1),
+    (b"LPOS", -3, READ | LIST, 1, 1, 1),
+    (b"LMPOP", -4, WRITE | ACCESS | LIST, 0, 0, 0),
+    (b"BLPOP", -3, WRITE | ACCESS | BLOCKING | LIST, 1, -2, 1),
+    (b"BRPOP", -3, WRITE | ACCESS | BLOCKING | LIST, 1, -2, 1),
+    (b"BLMPOP", -5, WRITE | ACCESS | BLOCKING | LIST, 0, 0, 0),
+    (b"BLMOVE", 6, WRITE | ACCESS | BLOCKING | LIST, 1, 2, 1),
+    (b"BRPOPLPUSH", 4, WRITE | ACCESS | BLOCKING | LIST, 1, 2, 1),
+    (b"HSET", -4, WRITE | FAST | HASH, 1, 1, 1),
+    (b"HMSET", -4, WRITE | FAST | HASH, 1, 1, 1),
+    (b"HSETNX", 4, WRITE | FAST | HASH, 1, 1, 1),
+    (b"HGET", 3, READ | FAST | HASH, 1, 1, 1),
+    (b"HMGET", -3, READ | FAST | HASH, 1, 1, 1),
+    (b"HDEL", -3, WRITE | FAST | HASH, 1, 1, 1),
+    (b"HLEN", 2, READ | FAST | HASH, 1, 1, 1),
+    (b"HSTRLEN", 3, READ | FAST | HASH, 1, 1, 1),
+    (b"HEXISTS", 3, READ | FAST | HASH, 1, 1, 1),
+    (b"HGETALL", 2, READ | HASH, 1, 1, 1),
+    (b"HKEYS", 2, READ | HASH, 1, 1, 1),
+    (b"HVALS", 2, READ | HASH, 1, 1, 1),
+    (b"HINCRBY", 4, WRITE | ACCESS | FAST | HASH, 1, 1, 1),
+    (b"HINCRBYFLOAT", 4, WRITE | ACCESS | FAST | HASH, 1, 1, 1),
+    (b"HRANDFIELD", -2, READ | HASH, 1, 1, 1),
+    (b"HEXPIRE", -6, WRITE | FAST | HASH, 1, 1, 1),
+    (b"HPEXPIRE", -6, WRITE | FAST | HASH, 1, 1, 1),
+    (b"HEXPIREAT", -6, WRITE | FAST | HASH, 1, 1, 1),
+    (b"HPEXPIREAT", -6, WRITE | FAST | HASH, 1, 1, 1),
+    (b"HTTL", -5, READ | FAST | HASH, 1, 1, 1),
+    (b"HPTTL", -5, READ | FAST | HASH, 1, 1, 1),
+    (b"HEXPIRETIME", -5, READ | FAST | HASH, 1, 1, 1),
+    (b"HPEXPIRETIME", -5, READ | FAST | HASH, 1, 1, 1),
+    (b"HPERSIST", -5, WRITE | FAST | HASH, 1, 1, 1),
+    (b"HGETEX", -5, WRITE | ACCESS | FAST | HASH, 1, 1, 1),
+    (b"HSETEX", -6, WRITE | FAST | HASH, 1, 1, 1),
+    (b"SADD", -3, WRITE | FAST | SET, 1, 1, 1),
+    (b"SREM", -3, WRITE | FAST | SET, 1, 1, 1),
+    (b"SMEMBERS", 2, READ | SET, 1, 1, 1),
+    (b"SISMEMBER", 3, READ | FAST | SET, 1, 1, 1),
+    (b"SMISMEMBER", -3, READ | FAST | SET, 1, 1, 1),
+    (b"SCARD", 2, READ | FAST | SET, 1, 1, 1),
+    (b"SPOP", -2, WRITE | ACCESS | FAST | SET, 1, 1, 1),
+    (b"SRANDMEMBER", -2, READ | SET, 1, 1, 1),
+    (b"SINTER", -2, READ | SET, 1, -1, 1),
+    (b"SUNION", -2, READ | SET, 1, -1, 1),
+    (b"SDIFF", -2, READ | SET, 1, -1, 1),
+    (b"SINTERSTORE", -3, WRITE | STORE | SET, 1, -1, 1),
+    (b"SUNIONSTORE", -3, WRITE | STORE | SET, 1, -1, 1),
+    (b"SDIFFSTORE", -3, WRITE | STORE | SET, 1, -1, 1),
+    (b"SINTERCARD", -3, READ | SET, 0, 0, 0),
+    (b"SMOVE", 4, WRITE | ACCESS | FAST | SET, 1, 2, 1),
+    (b"ZADD", -4, WRITE | FAST | SORTEDSET, 1, 1, 1),
+    (b"ZINCRBY", 4, WRITE | ACCESS | FAST | SORTEDSET, 1, 1, 1),
+    (b"ZCARD", 2, READ | FAST | SORTEDSET, 1, 1, 1),
+    (b"ZSCORE", 3, READ | FAST | SORTEDSET, 1, 1, 1),
+    (b"ZMSCORE", -3, READ | FAST | SORTEDSET, 1, 1, 1),
+    (b"ZRANK", -3, READ | FAST | SORTEDSET, 1, 1, 1),
+    (b"ZREVRANK", -3, READ | FAST | SORTEDSET, 1, 1, 1),
+    (b"ZREM", -3, WRITE | FAST | SORTEDSET, 1, 1, 1),
+    (b"ZRANGE", -4, READ | SORTEDSET, 1, 1, 1),
+    (b"ZREVRANGE", -4, READ | SORTEDSET, 1, 1, 1),
+    (b"ZRANGEBYSCORE", -4, READ | SORTEDSET, 1, 1, 1),
+    (b"ZREVRANGEBYSCORE", -4, READ | SORTEDSET, 1, 1, 1),
+    (b"ZRANGEBYLEX", -4, READ | SORTEDSET, 1, 1, 1),
+    (b"ZREVRANGEBYLEX", -4, READ | SORTEDSET, 1, 1, 1),
+    (b"ZCOUNT", 4, READ | FAST | SORTEDSET, 1, 1, 1),
+    (b"ZLEXCOUNT", 4, READ | FAST | SORTEDSET, 1, 1, 1),
+    (b"ZREMRANGEBYRANK", 4, WRITE | SORTEDSET, 1, 1, 1),
+    (b"ZREMRANGEBYSCORE", 4, WRITE | SORTEDSET, 1, 1, 1),
+    (b"ZREMRANGEBYLEX", 4, WRITE | SORTEDSET, 1, 1, 1),
+    (b"ZPOPMIN", -2, WRITE | ACCESS | FAST | SORTEDSET, 1, 1, 1),
+    (b"ZPOPMAX", -2, WRITE | ACCESS | FAST | SORTEDSET, 1, 1, 1),
+    (
+        b"BZPOPMIN",
+        -3,
+        WRITE | ACCESS | FAST | BLOCKING | SORTEDSET,
+        1,
+        -2,
+        1,
+    ),
+    (
+        b"BZPOPMAX",
+        -3,
+        WRITE | ACCESS | FAST | BLOCKING | SORTEDSET,
+        1,
+        -2,
+        1,
+    ),
+    (b"ZUNION", -3, READ | SORTEDSET, 0, 0, 0),
+    (b"ZINTER", -3, READ | SORTEDSET, 0, 0, 0),
+    (b"ZDIFF", -3, READ | SORTEDSET, 0, 0, 0),
+    (b"ZUNIONSTORE", -4, WRITE | STORE | SORTEDSET, 0, 0, 0),
+    (b"ZINTERSTORE", -4, WRITE | STORE | SORTEDSET, 0, 0, 0),
+    (b"ZDIFFSTORE", -4, WRITE | STORE | SORTEDSET, 0, 0, 0),
+    (b"XADD", -5, WRITE | FAST | STREAM, 1, 1, 1),
+    (b"XLEN", 2, READ | FAST | STREAM, 1, 1, 1),
+    (b"XRANGE", -4, READ | STREAM, 1, 1, 1),
+    (b"XREVRANGE", -4, READ | STREAM, 1, 1, 1),
+    (b"XDEL", -3, WRITE | FAST | STREAM, 1, 1, 1),
+    (b"XTRIM", -4, WRITE | STREAM, 1, 1, 1),
+    (b"XREAD", -4, READ | BLOCKING | STREAM, 0, 0, 0),
+    (b"XGROUP", -2, WRITE | STREAM, 2, 2, 1),
+    (
+        b"XREADGROUP",
+        -7,
+        WRITE | ACCESS | BLOCKING | STREAM,
+        0,
+        0,
+        0,
+    ),
+    (b"XACK", -4, WRITE | FAST | STREAM, 1, 1, 1),
+    (b"XPENDING", -3, READ | STREAM, 1, 1, 1),
+    (b"XCLAIM", -6, WRITE | ACCESS | FAST | STREAM, 1, 1, 1),
+    (b"XAUTOCLAIM", -6, WRITE | ACCESS | FAST | STREAM, 1, 1, 1),
+    (b"XINFO", -2, READ | STREAM, 2, 2, 1),
+    (b"GEOADD", -5, WRITE | GEO, 1, 1, 1),
+    (b"GEODIST", -4, READ | GEO, 1, 1, 1),
+    (b"GEOPOS", -2, READ | GEO, 1, 1, 1),
+    (b"GEOHASH", -2, READ | GEO, 1, 1, 1),
+    (b"GEOSEARCH", -7, READ | GEO, 1, 1, 1),
+    (b"GEOSEARCHSTORE", -8, WRITE | STORE | GEO, 1, 2, 1),
+    (b"PFADD", -2, WRITE | FAST | HYPERLOGLOG, 1, 1, 1),
+    (b"PFCOUNT", -2, READ | HYPERLOGLOG, 1, -1, 1),
+    (
+        b"PFMERGE",
+        -2,
+        WRITE | STORE | ACCESS | HYPERLOGLOG,
+        1,
+        -1,
+        1,
+    ),
+    (
+        b"PFDEBUG",
+        3,
+        WRITE | ACCESS | ADMIN | DANGEROUS | HYPERLOGLOG,
+        2,
+        2,
+        1,
+    ),
+    (b"PFSELFTEST", 1, ADMIN | DANGEROUS | HYPERLOGLOG, 0, 0, 0),
+    (b"SUBSCRIBE", -2, NOSCRIPT | PUBSUB, 0, 0, 0),
+    (b"UNSUBSCRIBE", -1, NOSCRIPT | PUBSUB, 0, 0, 0),
+    (b"PSUBSCRIBE", -2, NOSCRIPT | PUBSUB, 0, 0, 0),
+    (b"PUNSUBSCRIBE", -1, NOSCRIPT | PUBSUB, 0, 0, 0),
+    (b"PUBLISH", 3, FAST | PUBSUB, 0, 0, 0),
+    (b"SSUBSCRIBE", -2, NOSCRIPT | PUBSUB, 0, 0, 0),
+    (b"SUNSUBSCRIBE", -1, NOSCRIPT | PUBSUB, 0, 0, 0),
+    (b"SPUBLISH", 3, FAST | PUBSUB, 0, 0, 0),
+    (b"PUBSUB", -2, PUBSUB, 0, 0, 0),
+    (b"MULTI", 1, NOSCRIPT | FAST | TRANSACTION, 0, 0, 0),
+    (b"EXEC", 1, NOSCRIPT | TRANSACTION, 0, 0, 0),
+    (b"DISCARD", 1, NOSCRIPT | FAST | TRANSACTION, 0, 0, 0),
+    (b"WATCH", -2, NOSCRIPT | FAST | TRANSACTION, 1, -1, 1),
+    (b"UNWATCH", 1, NOSCRIPT | FAST | TRANSACTION, 0, 0, 0),
+    (b"TYPE", 2, READ | FAST | KEYSPACE, 1, 1, 1),
+    (b"OBJECT", -2, READ | KEYSPACE, 2, 2, 1),
+    (b"SAVE", 1, NOSCRIPT | ADMIN | DANGEROUS, 0, 0, 0),
+    (b"CONFIG", -2, NOSCRIPT | ADMIN | DANGEROUS, 0, 0, 0),
+    (b"CLIENT", -2, NOSCRIPT | CONNECTION, 0, 0, 0),
+    (b"AUTH", -2, NOSCRIPT | FAST | CONNECTION | NO_AUTH, 0, 0, 0),
+    (
+        b"HELLO",
+        -1,
+        NOSCRIPT | FAST | CONNECTION | NO_AUTH,
+        0,
+        0,
+        0,
+    ),
+    (b"QUIT", -1, NOSCRIPT | FAST | CONNECTION | NO_AUTH, 0, 0, 0),
+    (b"RESET", 1, NOSCRIPT | FAST | CONNECTION | NO_AUTH, 0, 0, 0),
+    (b"ACL", -2, NOSCRIPT | ADMIN | DANGEROUS, 0, 0, 0),
+    (b"KEYS", 2, READ | DANGEROUS | KEYSPACE, 0, 0, 0),
+    (b"DEL", -2, WRITE | KEYSPACE, 1, -1, 1),
+    (b"UNLINK", -2, WRITE | FAST | KEYSPACE, 1, -1, 1),
+    (b"EXPIRE", -3, WRITE | FAST | KEYSPACE, 1, 1, 1),
+    (b"PEXPIRE", -3, WRITE | FAST | KEYSPACE, 1, 1, 1),
+    (b"EXPIREAT", -3, WRITE | FAST | KEYSPACE, 1, 1, 1),
+    (b"PEXPIREAT", -3, WRITE | FAST | KEYSPACE, 1, 1, 1),
+    (b"TTL", 2, READ | FAST | KEYSPACE, 1, 1, 1),
+    (b"PTTL", 2, READ | FAST | KEYSPACE, 1, 1, 1),
+    (b"PERSIST", 2, WRITE | FAST | KEYSPACE, 1, 1, 1),
+    (b"FLUSHDB", -1, WRITE | KEYSPACE | DANGEROUS, 0, 0, 0),
+    (b"FLUSHALL", -1, WRITE | KEYSPACE | DANGEROUS, 0, 0, 0),
+    (b"SWAPDB", 3, WRITE | FAST | KEYSPACE | DANGEROUS, 0, 0, 0),
+    (b"EVAL", -3, NOSCRIPT | SCRIPTING, 0, 0, 0),
+    (b"EVALSHA", -3, NOSCRIPT | SCRIPTING, 0, 0, 0),
+    (b"EVAL_RO", -3, NOSCRIPT | SCRIPTING, 0, 0, 0),
+    (b"EVALSHA_RO", -3, NOSCRIPT | SCRIPTING, 0, 0, 0),
+    (b"SCRIPT", -2, NOSCRIPT | SCRIPTING, 0, 0, 0),
+    (b"FUNCTION", -2, NOSCRIPT | SCRIPTING, 0, 0, 0),
+    (b"FCALL", -3, NOSCRIPT | SCRIPTING, 0, 0, 0),
+    (b"FCALL_RO", -3, NOSCRIPT | SCRIPTING, 0, 0, 0),
+];
+
+/// A supported command, as found in the command table
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct Command {
+    name: &'static [u8],
+    arity: i32,
+    flags: u32,
+    first_key: i32,
+    last_key: i32,
+    step: i32,
+}
+
+impl Command {
+    /// The name of the command, in lowercase as it's given in ACL rules and errors
+    pub(super) fn name(&self) -> String {
+        String::from_utf8_lossy(self.name).to_lowercase()
+    }
+
+    /// Check whether a number of arguments, including the command name, is valid for the command
+    pub(super) fn arity_matches(&self, args: usize) -> bool {
+        if self.arity < 0 {
+            args >= self.arity.unsigned_abs() as usize
+        } else {
+            args == self.arity as usize
+        }
+    }
+
+    pub(super) fn is_write(&self) -> bool {
+        self.flags & WRITE != 0
+    }
+
+    pub(super) fn allowed_in_scripts(&self) -> bool {
+        self.flags & NOSCRIPT == 0
+    }
+
+    pub(super) fn allowed_unauthenticated(&self) -> bool {
+        self.flags & NO_AUTH != 0
+    }
+
+    /// Check whether the command is in an ACL category, given by its name without the "@"
+    pub(super) fn in_category(&self, category: &str) -> bool {
+        match category {
+            "all" => true,
+            "slow" => self.flags & FAST == 0,
+            _ => CATEGORIES
+                .iter()
+                .any(|&(name, flag)| name == category && self.flags & flag != 0),
+        }
+    }
+
+    /// The positions of the keys in the arguments of the command, including its name
+    ///
+    /// Most commands have their keys between a first and a last position, while commands like
+    /// EVAL give the number of keys before them, and XREAD has them after STREAMS.
+    pub(super) fn key_positions(&self, args: &[&[u8]]) -> Vec<usize> {
+        let positions: Vec<usize> = match self.name {
+            b"EVAL" | b"EVALSHA" | b"EVAL_RO" | b"EVALSHA_RO" | b"FCALL" | b"FCALL_RO"
+            | b"BLMPOP" => numkeys_positions(args, 2),
+            b"LMPOP" | b"SINTERCARD" | b"ZUNION" | b"ZINTER" | b"ZDIFF" => {
+                numkeys_positions(args, 1)
+            }
+            b"ZUNIONSTORE" | b"ZINTERSTORE" | b"ZDIFFSTORE" => {
+                let mut positions = vec![1];
+                positions.extend(numkeys_positions(args, 2));
+                positions
+            }
+            b"XREAD" | b"XREADGROUP" => {
+                let Some(streams) = args
+                    .iter()
+                    .position(|arg| arg.eq_ignore_ascii_case(b"STREAMS"))
+                else {
+                    return Vec::new();
+                };
+                let count = (args.len() - streams - 1) / 2;
+                (streams + 1..streams + 1 + count).collect()
+            }
+            _ if self.first_key == 0 => Vec::new(),
+            _ => {
+                let last = match self.last_key {
+                    last if last < 0 => args.len() as i32 + last,
+                    last => last,
+                };
+                (self.first_key..=last)
+                    .step_by(self.step as usize)
+                    .map(|i| i as usize)
+                    .collect()
+            }
+        };
+        positions.into_iter().filter(|&i| i < args.len()).collect()
+    }
+
+    /// Whether the command reads and whether it writes one of its keys, given by its index among
+    /// the keys
+    pub(super) fn key_access(&self, index: usize) -> (bool, bool) {
+        if !self.is_write() || (self.flags & STORE != 0 && index > 0) {
+            return (true, false);
+        }
+        (self.flags & ACCESS != 0, true)
+    }
+}
+
+/// The positions of the keys of a command that gives the number of keys at a position, followed by
+/// the keys
+fn numkeys_positions(args: &[&[u8]], position: usize) -> Vec<usize> {
+    let numkeys = args
+        .get(position)
+        .and_then(|numkeys| std::str::from_utf8(numkeys).ok())
+        .and_then(|numkeys| numkeys.parse::<usize>().ok())
+        .unwrap_or(0);
+    (position + 1..=position + numkeys).collect()
+}
+
+/// Look up a command, given in uppercase, or `None` if it's not supported
+pub(super) fn lookup(name: &[u8]) -> Option<Command> {
+    COMMANDS.iter().find(|(command, ..)| *command == name).map(
+        |&(name, arity, flags, first_key, last_key, step)| Command {
+            name,
+            arity,
+            flags,
+            first_key,
+            last_key,
+            step,
+        },
+    )
+}
+
+/// The commands in an ACL category, in lowercase
+pub(super) fn in_category(category: &str) -> Vec<String> {
+    COMMANDS
+        .iter()
+        .filter_map(|(name, ..)| lookup(name))
+        .filter(|command| command.in_category(category))
+        .map(|command| command.name())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(lookup(b"GET").map(|c| c.arity), Some(2));
+        assert_eq!(lookup(b"SET").map(|c| c.arity), Some(-3));
+        assert_eq!(lookup(b"get"), None);
+        assert_eq!(lookup(b"NOPE"), None);
+
+        let get = lookup(b"GET").unwrap();
+        assert!(get.arity_matches(2));
+        assert!(!get.arity_matches(3));
+        let set = lookup(b"SET").unwrap();
+        assert!(set.arity_matches(3));
+        assert!(set.arity_matches(5));
+        assert!(!set.arity_matches(2));
+
+        assert!(set.is_write());
+        assert!(!get.is_write());
+        assert!(get.allowed_in_scripts());
+        assert!(!lookup(b"EVAL").unwrap().allowed_in_scripts());
+        assert!(!lookup(b"MULTI").unwrap().allowed_in_scripts());
+        assert!(lookup(b"AUTH").unwrap().allowed_unauthenticated());
+        assert!(lookup(b"HELLO").unwrap().allowed_unauthenticated());
+        assert!(!get.allowed_unauthenticated());
+    }
+
+    #[test]
+    fn test_in_category() {
+        let get = lookup(b"GET").unwrap();
+        assert!(get.in_category("all"));
+        assert!(get.in_category("string"));
+        assert!(get.in_category("read"));
+        assert!(get.in_category("fast"));
+        assert!(!get.in_category("slow"));
+        assert!(!get.in_category("write"));
+        assert!(!get.in_category("nope"));
+        assert!(lookup(b"KEYS").unwrap().in_category("slow"));
+
+        let strings = in_category("string");
+        assert!(strings.contains(&"set".to_string()));
+        assert!(!strings.contains(&"lpush".to_string()));
+    }
+
+    #[test]
+    fn test_key_positions() {
+        let positions = |args: &[&[u8]]| lookup(args[0]).unwrap().key_positions(args);
+        assert_eq!(positions(&[b"GET", b"a"]), vec![1]);
+        assert_eq!(positions(&[b"MSET", b"a", b"1", b"b", b"2"]), vec![1, 3]);
+        assert_eq!(positions(&[b"BLPOP", b"a", b"b", b"0"]), vec![1, 2]);
+        assert_eq!(
+            positions(&[b"BITOP", b"AND", b"d", b"a", b"b"]),
+            vec![2, 3, 4]
+        );
+        assert_eq!(
+            positions(&[b"EVAL", b"return 1", b"2", b"a", b"b", b"c"]),
+            vec![3, 4]
+        );
+        assert_eq!(
+            positions(&[
+                b"ZUNIONSTORE",
+                b"d",
+                b"2",
+                b"a",
+                b"b",
+                b"WEIGHTS",
+                b"1",
+                b"2"
+            ]),
+            vec![1, 3, 4]
+        );
+        assert_eq!(
+            positions(&[b"XREAD", b"COUNT", b"1", b"STREAMS", b"a", b"b", b"0", b"0"]),
+            vec![4, 5]
+        );
+        assert_eq!(positions(&[b"OBJECT", b"HELP"]), Vec::<usize>::new());
+        assert_eq!(positions(&[b"PING"]), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_key_access() {
+        assert_eq!(lookup(b"GET").unwrap().key_access(0), (true, false));
+        assert_eq!(lookup(b"SET").unwrap().key_access(0), (false, true));
+        assert_eq!(lookup(b"INCR").unwrap().key_access(0), (true, true));
+        let store = lookup(b"SINTERSTORE").unwrap();
+        assert_eq!(store.key_access(0), (false, true));
+        assert_eq!(store.key_access(1), (true, false));
+    }
+}
@@ -0,0 +1,944 @@
+use super::{
+    blocking::BlockedCommand,
+    bulk_strings, parse_int,
+    streams::{
+        encode_entries, encode_entry, parse_id, parse_range_end, parse_range_start, ReadArgs,
+    },
+    Connection, NULL_ARRAY, OK,
+};
+use crate::{
+    database::{self, now, ConsumerGroup, Stream, StreamFields, StreamId, Value},
+    error::RustisError,
+    notify,
+    resp::RESPData,
+    Result,
+};
+use std::io::Write;
+
+/// Entries delivered to a consumer, where entries redelivered from its pending entries have no
+/// fields if they have been deleted since
+pub(super) type GroupEntries = Vec<(StreamId, Option<StreamFields>)>;
+
+/// How many entries XAUTOCLAIM claims when there is no COUNT
+const DEFAULT_AUTOCLAIM_COUNT: usize = 100;
+/// How many pending entries XAUTOCLAIM looks at for each entry that it can claim
+const AUTOCLAIM_ATTEMPTS_FACTOR: usize = 10;
+/// How many entries and pending entries XINFO STREAM FULL replies with when there is no COUNT
+const DEFAULT_INFO_FULL_COUNT: usize = 10;
+
+fn no_such_group<T>(key: &[u8], group: &[u8]) -> Result<T> {
+    Err(RustisError::ClientErrorWithCode(
+        "NOGROUP",
+        format!(
+            "No such consumer group '{}' for key name '{}'",
+            String::from_utf8_lossy(group),
+            String::from_utf8_lossy(key)
+        ),
+    ))
+}
+
+fn no_such_key_or_group<T>(key: &[u8], group: &[u8], command: Option<&str>) -> Result<T> {
+    let mut message = format!(
+        "No such key '{}' or consumer group '{}'",
+        String::from_utf8_lossy(key),
+        String::from_utf8_lossy(group)
+    );
+    if let Some(command) = command {
+        message.push_str(&format!(" in {} with GROUP option", command));
+    }
+    Err(RustisError::ClientErrorWithCode("NOGROUP", message))
+}
+
+/// Parse the ENTRIESREAD option of XGROUP, where -1 means the count isn't known
+fn parse_entries_read(raw: &[u8]) -> Result<Option<u64>> {
+    match parse_int::<i64>(raw)? {
+        -1 => Ok(None),
+        read if read < 0 => client_error!("value for ENTRIESREAD must be positive or -1"),
+        read => Ok(Some(read as u64)),
+    }
+}
+
+/// Parse the options of XGROUP CREATE and SETID, returning whether the stream should be created
+/// and the count of entries read
+fn parse_xgroup_options(options: &[&[u8]], create: bool) -> Result<(bool, Option<u64>)> {
+    let mut mkstream = false;
+    let mut entries_read = None;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.to_ascii_uppercase().as_slice() {
+            b"MKSTREAM" if create => mkstream = true,
+            b"ENTRIESREAD" => match options.next() {
+                Some(read) => entries_read = parse_entries_read(read)?,
+                None => return client_error!("syntax error"),
+            },
+            _ => return client_error!("syntax error"),
+        }
+    }
+    Ok((mkstream, entries_read))
+}
+
+fn encode_bulk(buf: &mut Vec<u8>, data: &[u8]) {
+    RESPData::BulkString(data).encode(buf);
+}
+
+fn encode_integer(buf: &mut Vec<u8>, value: i64) {
+    RESPData::Integer(value).encode(buf);
+}
+
+fn encode_id(buf: &mut Vec<u8>, id: StreamId) {
+    encode_bulk(buf, id.to_string().as_bytes());
+}
+
+/// Encode an integer that might not be known, which is null if it isn't
+fn encode_optional_integer(buf: &mut Vec<u8>, value: Option<u64>) {
+    match value {
+        Some(value) => encode_integer(buf, value as i64),
+        None => RESPData::Null.encode(buf),
+    }
+}
+
+/// Encode the groups of a stream for XINFO STREAM FULL, with up to count of the pending entries
+/// of each group and consumer, or all of them if count is zero
+fn encode_full_groups(buf: &mut Vec<u8>, stream: &Stream, count: usize) {
+    write!(buf, "*{}\r\n", stream.groups().len()).unwrap();
+    for (name, group) in stream.groups() {
+        buf.extend_from_slice(b"*14\r\n");
+        encode_bulk(buf, b"name");
+        encode_bulk(buf, name);
+        encode_bulk(buf, b"last-delivered-id");
+        encode_id(buf, group.last_id);
+        encode_bulk(buf, b"entries-read");
+        encode_optional_integer(buf, group.entries_read);
+        encode_bulk(buf, b"lag");
+        encode_optional_integer(buf, stream.lag(group));
+        encode_bulk(buf, b"pel-count");
+        encode_integer(buf, group.pending.len() as i64);
+        encode_bulk(buf, b"pending");
+        let pending: Vec<_> = group.pending.iter().take(count).collect();
+        write!(buf, "*{}\r\n", pending.len()).unwrap();
+        for (id, entry) in pending {
+            buf.extend_from_slice(b"*4\r\n");
+            encode_id(buf, *id);
+            encode_bulk(buf, &entry.consumer);
+            encode_integer(buf, entry.delivery_time as i64);
+            encode_integer(buf, entry.delivery_count as i64);
+        }
+
+        encode_bulk(buf, b"consumers");
+        write!(buf, "*{}\r\n", group.consumers.len()).unwrap();
+        for (name, consumer) in &group.consumers {
+            buf.extend_from_slice(b"*10\r\n");
+            encode_bulk(buf, b"name");
+            encode_bulk(buf, name);
+            encode_bulk(buf, b"seen-time");
+            encode_integer(buf, consumer.seen_time as i64);
+            encode_bulk(buf, b"active-time");
+            encode_integer(buf, consumer.active_time.map_or(-1, |time| time as i64));
+            encode_bulk(buf, b"pel-count");
+            encode_integer(buf, consumer.pending.len() as i64);
+            encode_bulk(buf, b"pending");
+            let pending: Vec<_> = consumer.pending.iter().take(count).collect();
+            write!(buf, "*{}\r\n", pending.len()).unwrap();
+            for id in pending {
+                let entry = &group.pending[id];
+                buf.extend_from_slice(b"*3\r\n");
+                encode_id(buf, *id);
+                encode_integer(buf, entry.delivery_time as i64);
+                encode_integer(buf, entry.delivery_count as i64);
+            }
+        }
+    }
+}
+
+impl Connection {
+    /// Reply to XREADGROUP with the entries read from each stream, along with the key of the
+    /// stream
+    pub(super) fn write_group_streams(&mut self, streams: &[(&[u8], GroupEntries)]) -> Result<()> {
+        let mut buf = Vec::new();
+        write!(buf, "*{}\r\n", streams.len())?;
+        for (key, entries) in streams {
+            buf.extend_from_slice(b"*2\r\n");
+            encode_bulk(&mut buf, key);
+            write!(buf, "*{}\r\n", entries.len())?;
+            for (id, fields) in entries {
+                encode_entry(&mut buf, *id, fields.as_ref());
+            }
+        }
+        self.stream.write_all(&buf)?;
+        Ok(())
+    }
+
+    /// XGROUP CREATE | SETID | DESTROY | CREATECONSUMER | DELCONSUMER key group ...
+    pub(super) fn handle_xgroup(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received XGROUP");
+
+        let args = bulk_strings(args)?;
+        let Some((subcommand, args)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'xgroup' command");
+        };
+        let subcommand = subcommand.to_ascii_uppercase();
+        let (name, min_args, max_args) = match subcommand.as_slice() {
+            b"CREATE" => ("create", 3, 6),
+            b"SETID" => ("setid", 3, 5),
+            b"DESTROY" => ("destroy", 2, 2),
+            b"CREATECONSUMER" => ("createconsumer", 3, 3),
+            b"DELCONSUMER" => ("delconsumer", 3, 3),
+            _ => {
+                return client_error!(
+                    "unknown subcommand '{}'. Try XGROUP HELP.",
+                    String::from_utf8_lossy(&subcommand)
+                )
+            }
+        };
+        if args.len() < min_args || args.len() > max_args {
+            return client_error!("wrong number of arguments for 'xgroup|{}' command", name);
+        }
+
+        match name {
+            "create" | "setid" => self.handle_xgroup_set(args, name == "create"),
+            "destroy" => self.handle_xgroup_destroy(args[0], args[1]),
+            _ => self.handle_xgroup_consumer(args[0], args[1], args[2], name == "createconsumer"),
+        }
+    }
+
+    /// XGROUP CREATE key group id | $ [MKSTREAM] [ENTRIESREAD entries-read] and
+    /// XGROUP SETID key group id | $ [ENTRIESREAD entries-read]
+    ///
+    /// The ID "$" is the last ID of the stream, so that only entries added later are delivered
+    fn handle_xgroup_set(&mut self, args: &[&[u8]], create: bool) -> Result<()> {
+        let (key, group) = (args[0], args[1]);
+        let id = match args[2] {
+            b"$" => None,
+            id => Some(parse_id(id, 0)?),
+        };
+        let (mkstream, entries_read) = parse_xgroup_options(&args[3..], create)?;
+
+        let mut db = database::lock(0);
+        if mkstream && db.get_stream_mut(key)?.is_none() {
+            db.insert(key, Value::Stream(Stream::new()));
+        }
+        let Some(stream) = db.get_stream_mut(key)? else {
+            return client_error!(
+                "The XGROUP subcommand requires the key to exist. Note that for CREATE you may \
+                 want to use the MKSTREAM option to create an empty stream automatically."
+            );
+        };
+        let last_id = id.unwrap_or_else(|| stream.last_id());
+        if create {
+            if !stream.create_group(group, ConsumerGroup::new(last_id, entries_read)) {
+                return Err(RustisError::ClientErrorWithCode(
+                    "BUSYGROUP",
+                    "Consumer Group name already exists".to_string(),
+                ));
+            }
+        } else {
+            let Some(consumer_group) = stream.group_mut(group) else {
+                return no_such_group(key, group);
+            };
+            consumer_group.last_id = last_id;
+            consumer_group.entries_read = entries_read;
+        }
+        db.touch(key);
+        let event = if create {
+            "xgroup-create"
+        } else {
+            "xgroup-setid"
+        };
+        db.notify(notify::STREAM, event, key);
+        drop(db);
+
+        self.stream.write_all(OK)?;
+        Ok(())
+    }
+
+    /// XGROUP DESTROY key group
+    fn handle_xgroup_destroy(&mut self, key: &[u8], group: &[u8]) -> Result<()> {
+        let mut db = database::lock(0);
+        let destroyed = match db.get_stream_mut(key)? {
+            Some(stream) => stream.remove_group(group),
+            None => {
+                return client_error!("The XGROUP subcommand requires the key to exist.");
+            }
+        };
+        if destroyed {
+            db.touch(key);
+            db.notify(notify::STREAM, "xgroup-destroy", key);
+        }
+        drop(db);
+        // Clients blocked reading from the group are told that it no longer exists
+        if destroyed {
+            self.signal_key_ready(key);
+        }
+
+        self.write_integer(destroyed as i64)
+    }
+
+    /// XGROUP CREATECONSUMER key group consumer and XGROUP DELCONSUMER key group consumer
+    ///
+    /// Creating replies with whether the consumer was created, and deleting replies with how many
+    /// entries were pending for the consumer, which are no longer pending for the group.
+    fn handle_xgroup_consumer(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        consumer: &[u8],
+        create: bool,
+    ) -> Result<()> {
+        let mut db = database::lock(0);
+        let Some(stream) = db.get_stream_mut(key)? else {
+            return client_error!("The XGROUP subcommand requires the key to exist.");
+        };
+        let Some(group) = stream.group_mut(group) else {
+            return no_such_group(key, group);
+        };
+        let (changed, reply) = if create {
+            let created = group.create_consumer(consumer, now());
+            (created, created as usize)
+        } else {
+            let pending = group.remove_consumer(consumer);
+            (pending.is_some(), pending.unwrap_or(0))
+        };
+        if changed {
+            db.touch(key);
+            let event = if create {
+                "xgroup-createconsumer"
+            } else {
+                "xgroup-delconsumer"
+            };
+            db.notify(notify::STREAM, event, key);
+        }
+        drop(db);
+
+        self.write_integer(reply as i64)
+    }
+
+    /// XREADGROUP GROUP group consumer [COUNT count] [BLOCK milliseconds] [NOACK] STREAMS key
+    /// [key ...] id [id ...]
+    ///
+    /// The ID ">" reads the entries that haven't been delivered to the group yet, which become
+    /// pending for the consumer. Any other ID reads the entries that are already pending for the
+    /// consumer after that ID. With BLOCK, the client blocks until one of the streams is added
+    /// to if there are no new entries to read.
+    pub(super) fn handle_xreadgroup(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received XREADGROUP");
+
+        let args = bulk_strings(args)?;
+        let [option, group, consumer, rest @ ..] = args.as_slice() else {
+            return client_error!("wrong number of arguments for 'xreadgroup' command");
+        };
+        if !option.eq_ignore_ascii_case(b"GROUP") {
+            return client_error!("Missing GROUP option for XREADGROUP");
+        }
+        let options = ReadArgs::parse(rest, "xreadgroup")?;
+        let ids = options
+            .ids
+            .iter()
+            .map(|id| match *id {
+                b">" => Ok(None),
+                b"$" => client_error!(
+                    "The $ ID is meaningless in the context of XREADGROUP: you want to read the \
+                     history of this consumer by specifying a proper ID, or use the > ID to get \
+                     new messages. The $ ID would just return an empty result set."
+                ),
+                id => parse_id(id, 0).map(Some),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let now = now();
+        let mut db = database::lock(0);
+        // Every group is checked before reading any, so nothing is delivered on an error
+        for key in options.keys {
+            if db.get_stream(key)?.and_then(|s| s.group(group)).is_none() {
+                return no_such_key_or_group(key, group, Some("XREADGROUP"));
+            }
+        }
+        let mut read: Vec<(&[u8], GroupEntries)> = Vec::new();
+        for (key, id) in options.keys.iter().zip(ids) {
+            let stream = db.get_stream_mut(key)?.unwrap();
+            match id {
+                None => {
+                    let entries = stream
+                        .read_group(group, consumer, options.count, options.no_ack, now)
+                        .unwrap();
+                    if !entries.is_empty() {
+                        let entries = entries.into_iter().map(|(id, f)| (id, Some(f))).collect();
+                        read.push((key, entries));
+                        db.touch(key);
+                    }
+                }
+                // Pending entries are always replied with, even if there are none
+                Some(after) => {
+                    let entries = stream
+                        .read_group_history(group, consumer, after, options.count, now)
+                        .unwrap();
+                    read.push((key, entries));
+                }
+            }
+        }
+        drop(db);
+
+        if !read.is_empty() {
+            return self.write_group_streams(&read);
+        }
+        if options.block.is_none() {
+            self.stream.write_all(NULL_ARRAY)?;
+            return Ok(());
+        }
+        let keys: Vec<Vec<u8>> = options.keys.iter().map(|key| key.to_vec()).collect();
+        self.block(
+            &keys,
+            options.deadline(),
+            BlockedCommand::XReadGroup {
+                group: group.to_vec(),
+                consumer: consumer.to_vec(),
+                count: options.count,
+                no_ack: options.no_ack,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// XACK key group id [id ...]
+    pub(super) fn handle_xack(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received XACK");
+
+        let args = bulk_strings(args)?;
+        let [key, group, ids @ ..] = args.as_slice() else {
+            return client_error!("wrong number of arguments for 'xack' command");
+        };
+        if ids.is_empty() {
+            return client_error!("wrong number of arguments for 'xack' command");
+        }
+        let ids = ids
+            .iter()
+            .map(|id| parse_id(id, 0))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut db = database::lock(0);
+        let acked = match db.get_stream_mut(key)?.and_then(|s| s.group_mut(group)) {
+            Some(group) => ids.into_iter().filter(|id| group.ack(*id)).count(),
+            None => 0,
+        };
+        if acked > 0 {
+            db.touch(key);
+        }
+        drop(db);
+
+        self.write_integer(acked as i64)
+    }
+
+    /// XPENDING key group [[IDLE min-idle-time] start end count [consumer]]
+    ///
+    /// Without a range, replies with a summary of the pending entries: how many there are, the
+    /// lowest and highest IDs, and how many each consumer has. With a range, replies with the
+    /// pending entries in it, along with their consumer, idle time and delivery count.
+    pub(super) fn handle_xpending(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received XPENDING");
+
+        let args = bulk_strings(args)?;
+        let [key, group, rest @ ..] = args.as_slice() else {
+            return client_error!("wrong number of arguments for 'xpending' command");
+        };
+        let (min_idle, rest) = match rest {
+            [option, idle, rest @ ..] if option.eq_ignore_ascii_case(b"IDLE") => {
+                (Some(parse_int::<i64>(idle)?.max(0) as u128), rest)
+            }
+            _ => (None, rest),
+        };
+        let range = match rest {
+            [] if min_idle.is_none() => None,
+            [start, end, count, consumer @ ..] if consumer.len() <= 1 => Some((
+                parse_range_start(start)?,
+                parse_range_end(end)?,
+                parse_int::<i64>(count)?.max(0) as usize,
+                consumer.first(),
+            )),
+            _ => return client_error!("syntax error"),
+        };
+
+        let now = now();
+        let mut db = database::lock(0);
+        let Some(group) = db.get_stream(key)?.and_then(|s| s.group(group)) else {
+            return no_such_key_or_group(key, group, None);
+        };
+        let mut buf = Vec::new();
+        match range {
+            None => {
+                buf.extend_from_slice(b"*4\r\n");
+                encode_integer(&mut buf, group.pending.len() as i64);
+                match (
+                    group.pending.first_key_value(),
+                    group.pending.last_key_value(),
+                ) {
+                    (Some((first, _)), Some((last, _))) => {
+                        encode_id(&mut buf, *first);
+                        encode_id(&mut buf, *last);
+                        let consumers: Vec<_> = group
+                            .consumers
+                            .iter()
+                            .filter(|(_, consumer)| !consumer.pending.is_empty())
+                            .collect();
+                        write!(buf, "*{}\r\n", consumers.len())?;
+                        for (name, consumer) in consumers {
+                            buf.extend_from_slice(b"*2\r\n");
+                            encode_bulk(&mut buf, name);
+                            encode_bulk(&mut buf, consumer.pending.len().to_string().as_bytes());
+                        }
+                    }
+                    _ => {
+                        RESPData::Null.encode(&mut buf);
+                        RESPData::Null.encode(&mut buf);
+                        buf.extend_from_slice(NULL_ARRAY);
+                    }
+                }
+            }
+            Some((start, end, count, consumer)) => {
+                let ids: Box<dyn Iterator<Item = &StreamId>> = match consumer {
+                    _ if start > end => Box::new(std::iter::empty()),
+                    Some(consumer) => match group.consumers.get(*consumer) {
+                        Some(consumer) => Box::new(consumer.pending.range(start..=end)),
+                        None => Box::new(std::iter::empty()),
+                    },
+                    None => Box::new(group.pending.range(start..=end).map(|(id, _)| id)),
+                };
+                let pending: Vec<_> = ids
+                    .map(|id| (id, &group.pending[id]))
+                    .filter(|(_, entry)| {
+                        min_idle.is_none_or(|idle| now.saturating_sub(entry.delivery_time) >= idle)
+                    })
+                    .take(count)
+                    .collect();
+                write!(buf, "*{}\r\n", pending.len())?;
+                for (id, entry) in pending {
+                    buf.extend_from_slice(b"*4\r\n");
+                    encode_id(&mut buf, *id);
+                    encode_bulk(&mut buf, &entry.consumer);
+                    encode_integer(&mut buf, now.saturating_sub(entry.delivery_time) as i64);
+                    encode_integer(&mut buf, entry.delivery_count as i64);
+                }
+            }
+        }
+        drop(db);
+
+        self.stream.write_all(&buf)?;
+        Ok(())
+    }
+
+    /// XCLAIM key group consumer min-idle-time id [id ...] [IDLE ms] [TIME unix-time-milliseconds]
+    /// [RETRYCOUNT count] [FORCE] [JUSTID] [LASTID lastid]
+    ///
+    /// Takes over the pending entries that have been idle for at least the minimum idle time,
+    /// replying with the entries claimed. Pending entries that have been deleted from the stream
+    /// are no longer pending. FORCE also claims entries that aren't pending for any consumer, and
+    /// JUSTID replies with only the IDs, without counting the claim as a delivery.
+    pub(super) fn handle_xclaim(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received XCLAIM");
+
+        let args = bulk_strings(args)?;
+        let [key, group, consumer, min_idle, rest @ ..] = args.as_slice() else {
+            return client_error!("wrong number of arguments for 'xclaim' command");
+        };
+        if rest.is_empty() {
+            return client_error!("wrong number of arguments for 'xclaim' command");
+        }
+        let Ok(min_idle) = parse_int::<i64>(min_idle) else {
+            return client_error!("Invalid min-idle-time argument for XCLAIM");
+        };
+        let min_idle = min_idle.max(0) as u128;
+        // The IDs go up to the first argument that isn't one
+        let id_count = rest.iter().take_while(|id| parse_id(id, 0).is_ok()).count();
+        if id_count == 0 {
+            parse_id(rest[0], 0)?;
+        }
+        let ids: Vec<StreamId> = rest[..id_count]
+            .iter()
+            .map(|id| parse_id(id, 0).unwrap())
+            .collect();
+
+        let now = now();
+        let mut delivery_time = now;
+        let mut retry_count = None;
+        let mut force = false;
+        let mut just_id = false;
+        let mut last_id = None;
+        let mut options = rest[id_count..].iter();
+        while let Some(option) = options.next() {
+            match option.to_ascii_uppercase().as_slice() {
+                b"FORCE" => force = true,
+                b"JUSTID" => just_id = true,
+                name @ (b"IDLE" | b"TIME" | b"RETRYCOUNT" | b"LASTID") => {
+                    let name = String::from_utf8_lossy(name);
+                    let Some(value) = options.next() else {
+                        return client_error!("syntax error");
+                    };
+                    if name == "LASTID" {
+                        last_id = Some(parse_id(value, 0)?);
+                        continue;
+                    }
+                    let Ok(value) = parse_int::<i64>(value) else {
+                        return client_error!("Invalid {} option argument for XCLAIM", name);
+                    };
+                    match name.as_ref() {
+                        "IDLE" => delivery_time = now.saturating_sub(value.max(0) as u128),
+                        "TIME" => delivery_time = value.max(0) as u128,
+                        _ => retry_count = Some(value.max(0) as u64),
+                    }
+                }
+                _ => {
+                    return client_error!(
+                        "Unrecognized XCLAIM option '{}'",
+                        String::from_utf8_lossy(option)
+                    )
+                }
+            }
+        }
+        // Entries can't be delivered in the future
+        delivery_time = delivery_time.min(now);
+
+        let mut db = database::lock(0);
+        let Some(stream) = db.get_stream_mut(key)?.filter(|s| s.group(group).is_some()) else {
+            return no_such_key_or_group(key, group, None);
+        };
+        let exists: Vec<bool> = ids.iter().map(|id| stream.get(*id).is_some()).collect();
+        let consumer_group = stream.group_mut(group).unwrap();
+        if let Some(last_id) = last_id {
+            consumer_group.last_id = consumer_group.last_id.max(last_id);
+        }
+        consumer_group.consumer_mut(consumer, now);
+        let mut claimed = Vec::new();
+        for (id, exists) in ids.into_iter().zip(exists) {
+            if force && exists && !consumer_group.pending.contains_key(&id) {
+                consumer_group.assign(id, consumer, now).delivery_count = 1;
+            }
+            let Some(entry) = consumer_group.pending.get(&id) else {
+                continue;
+            };
+            if !exists {
+                consumer_group.ack(id);
+                continue;
+            }
+            if min_idle > 0 && now.saturating_sub(entry.delivery_time) < min_idle {
+                continue;
+            }
+            let entry = consumer_group.assign(id, consumer, now);
+            entry.delivery_time = delivery_time;
+            match retry_count {
+                Some(count) => entry.delivery_count = count,
+                None if !just_id => entry.delivery_count += 1,
+                None => {}
+            }
+            claimed.push(id);
+        }
+        if !claimed.is_empty() {
+            consumer_group.consumer_mut(consumer, now).active_time = Some(now);
+        }
+        let changed = !claimed.is_empty();
+
+        let mut buf = Vec::new();
+        if just_id {
+            write!(buf, "*{}\r\n", claimed.len())?;
+            for id in claimed {
+                encode_id(&mut buf, id);
+            }
+        } else {
+            let entries = claimed
+                .into_iter()
+                .map(|id| (id, stream.get(id).unwrap().clone()))
+                .collect();
+            encode_entries(&mut buf, &entries);
+        }
+        if changed {
+            db.touch(key);
+        }
+        drop(db);
+
+        self.stream.write_all(&buf)?;
+        Ok(())
+    }
+
+    /// XAUTOCLAIM key group consumer min-idle-time start [COUNT count] [JUSTID]
+    ///
+    /// Claims up to count of the pending entries from the start ID that have been idle for at
+    /// least the minimum idle time, looking at no more than ten times that many. Replies with the
+    /// ID to continue from (0-0 once the end is reached), the entries claimed, and the IDs of
+    /// pending entries that were deleted from the stream, which are no longer pending.
+    pub(super) fn handle_xautoclaim(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received XAUTOCLAIM");
+
+        let args = bulk_strings(args)?;
+        let [key, group, consumer, min_idle, start, options @ ..] = args.as_slice() else {
+            return client_error!("wrong number of arguments for 'xautoclaim' command");
+        };
+        let Ok(min_idle) = parse_int::<i64>(min_idle) else {
+            return client_error!("Invalid min-idle-time argument for XAUTOCLAIM");
+        };
+        let min_idle = min_idle.max(0) as u128;
+        let start = parse_range_start(start)?;
+        let mut count = DEFAULT_AUTOCLAIM_COUNT;
+        let mut just_id = false;
+        let mut options = options.iter();
+        while let Some(option) = options.next() {
+            match option.to_ascii_uppercase().as_slice() {
+                b"JUSTID" => just_id = true,
+                b"COUNT" => {
+                    let Some(value) = options.next() else {
+                        return client_error!("syntax error");
+                    };
+                    count = match parse_int::<i64>(value)? {
+                        value
+                            if value < 1
+                                || value as u64
+                                    > (usize::MAX / AUTOCLAIM_ATTEMPTS_FACTOR) as u64 =>
+                        {
+                            return client_error!("COUNT must be > 0")
+                        }
+                        value => value as usize,
+                    };
+                }
+                _ => return client_error!("syntax error"),
+            }
+        }
+
+        let now = now();
+        let mut db = database::lock(0);
+        let Some(stream) = db.get_stream_mut(key)?.filter(|s| s.group(group).is_some()) else {
+            return no_such_key_or_group(key, group, None);
+        };
+        let candidates: Vec<(StreamId, u128, bool)> = stream
+            .group(group)
+            .unwrap()
+            .pending
+            .range(start..)
+            .take(count * AUTOCLAIM_ATTEMPTS_FACTOR)
+            .map(|(id, entry)| (*id, entry.delivery_time, stream.get(*id).is_some()))
+            .collect();
+        let consumer_group = stream.group_mut(group).unwrap();
+        consumer_group.consumer_mut(consumer, now);
+        let mut claimed = Vec::new();
+        let mut deleted = Vec::new();
+        let mut last_seen = None;
+        for (id, delivery_time, exists) in candidates {
+            if claimed.len() == count {
+                break;
+            }
+            last_seen = Some(id);
+            if !exists {
+                consumer_group.ack(id);
+                deleted.push(id);
+                continue;
+            }
+            if min_idle > 0 && now.saturating_sub(delivery_time) < min_idle {
+                continue;
+            }
+            let entry = consumer_group.assign(id, consumer, now);
+            entry.delivery_time = now;
+            if !just_id {
+                entry.delivery_count += 1;
+            }
+            claimed.push(id);
+        }
+        if !claimed.is_empty() {
+            consumer_group.consumer_mut(consumer, now).active_time = Some(now);
+        }
+        let changed = !claimed.is_empty() || !deleted.is_empty();
+        // The scan continues from the pending entry after the last one looked at
+        let next = last_seen
+            .and_then(|last| last.next())
+            .and_then(|after| consumer_group.pending.range(after..).next())
+            .map_or(StreamId::MIN, |(id, _)| *id);
+
+        let mut buf = Vec::new();
+        buf.extend_from_slice(b"*3\r\n");
+        encode_id(&mut buf, next);
+        if just_id {
+            write!(buf, "*{}\r\n", claimed.len())?;
+            for id in claimed {
+                encode_id(&mut buf, id);
+            }
+        } else {
+            let entries = claimed
+                .into_iter()
+                .map(|id| (id, stream.get(id).unwrap().clone()))
+                .collect();
+            encode_entries(&mut buf, &entries);
+        }
+        write!(buf, "*{}\r\n", deleted.len())?;
+        for id in deleted {
+            encode_id(&mut buf, id);
+        }
+        if changed {
+            db.touch(key);
+        }
+        drop(db);
+
+        self.stream.write_all(&buf)?;
+        Ok(())
+    }
+
+    /// XINFO STREAM | GROUPS | CONSUMERS key ...
+    pub(super) fn handle_xinfo(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received XINFO");
+
+        let args = bulk_strings(args)?;
+        let Some((subcommand, args)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'xinfo' command");
+        };
+
+        match (subcommand.to_ascii_uppercase().as_slice(), args) {
+            (b"STREAM", [key, options @ ..]) => self.handle_xinfo_stream(key, options),
+            (b"GROUPS", [key]) => self.handle_xinfo_groups(key),
+            (b"CONSUMERS", [key, group]) => self.handle_xinfo_consumers(key, group),
+            (name @ (b"STREAM" | b"GROUPS" | b"CONSUMERS"), _) => client_error!(
+                "wrong number of arguments for 'xinfo|{}' command",
+                String::from_utf8_lossy(name).to_lowercase()
+            ),
+            _ => client_error!(
+                "unknown subcommand '{}'. Try XINFO HELP.",
+                String::from_utf8_lossy(subcommand)
+            ),
+        }
+    }
+
+    /// XINFO STREAM key [FULL [COUNT count]]
+    ///
+    /// Replies with the state of the stream, along with its first and last entries. FULL replies
+    /// with up to count of its entries instead, and the full state of its groups and consumers.
+    /// The stream's nodes stand in for the keys and nodes of the radix tree that Redis uses.
+    fn handle_xinfo_stream(&mut self, key: &[u8], options: &[&[u8]]) -> Result<()> {
+        let full = match options {
+            [] => None,
+            [full] if full.eq_ignore_ascii_case(b"FULL") => Some(DEFAULT_INFO_FULL_COUNT),
+            [full, option, count]
+                if full.eq_ignore_ascii_case(b"FULL") && option.eq_ignore_ascii_case(b"COUNT") =>
+            {
+                Some(parse_int::<i64>(count)?.max(0) as usize)
+            }
+            _ => return client_error!("syntax error"),
+        };
+
+        let mut db = database::lock(0);
+        let Some(stream) = db.get_stream(key)? else {
+            return client_error!("no such key");
+        };
+        let mut buf = Vec::new();
+        buf.extend_from_slice(if full.is_some() {
+            b"*18\r\n"
+        } else {
+            b"*20\r\n"
+        });
+        encode_bulk(&mut buf, b"length");
+        encode_integer(&mut buf, stream.len() as i64);
+        let nodes = stream.nodes().count() as i64;
+        encode_bulk(&mut buf, b"radix-tree-keys");
+        encode_integer(&mut buf, nodes);
+        encode_bulk(&mut buf, b"radix-tree-nodes");
+        encode_integer(&mut buf, nodes);
+        encode_bulk(&mut buf, b"last-generated-id");
+        encode_id(&mut buf, stream.last_id());
+        encode_bulk(&mut buf, b"max-deleted-entry-id");
+        encode_id(&mut buf, stream.max_deleted_id());
+        encode_bulk(&mut buf, b"entries-added");
+        encode_integer(&mut buf, stream.entries_added() as i64);
+        encode_bulk(&mut buf, b"recorded-first-entry-id");
+        encode_id(&mut buf, stream.first_id());
+        match full {
+            None => {
+                encode_bulk(&mut buf, b"groups");
+                encode_integer(&mut buf, stream.groups().len() as i64);
+                encode_bulk(&mut buf, b"first-entry");
+                match stream.iter().next() {
+                    Some((id, fields)) => encode_entry(&mut buf, id, Some(fields)),
+                    None => RESPData::Null.encode(&mut buf),
+                }
+                encode_bulk(&mut buf, b"last-entry");
+                match stream.iter().next_back() {
+                    Some((id, fields)) => encode_entry(&mut buf, id, Some(fields)),
+                    None => RESPData::Null.encode(&mut buf),
+                }
+            }
+            Some(count) => {
+                let count = if count == 0 { usize::MAX } else { count };
+                encode_bulk(&mut buf, b"entries");
+                let entries = stream
+                    .iter()
+                    .take(count)
+                    .map(|(id, fields)| (id, fields.clone()))
+                    .collect();
+                encode_entries(&mut buf, &entries);
+                encode_bulk(&mut buf, b"groups");
+                encode_full_groups(&mut buf, stream, count);
+            }
+        }
+        drop(db);
+
+        self.stream.write_all(&buf)?;
+        Ok(())
+    }
+
+    /// XINFO GROUPS key
+    fn handle_xinfo_groups(&mut self, key: &[u8]) -> Result<()> {
+        let mut db = database::lock(0);
+        let Some(stream) = db.get_stream(key)? else {
+            return client_error!("no such key");
+        };
+        let mut buf = Vec::new();
+        write!(buf, "*{}\r\n", stream.groups().len())?;
+        for (name, group) in stream.groups() {
+            buf.extend_from_slice(b"*12\r\n");
+            encode_bulk(&mut buf, b"name");
+            encode_bulk(&mut buf, name);
+            encode_bulk(&mut buf, b"consumers");
+            encode_integer(&mut buf, group.consumers.len() as i64);
+            encode_bulk(&mut buf, b"pending");
+            encode_integer(&mut buf, group.pending.len() as i64);
+            encode_bulk(&mut buf, b"last-delivered-id");
+            encode_id(&mut buf, group.last_id);
+            encode_bulk(&mut buf, b"entries-read");
+            encode_optional_integer(&mut buf, group.entries_read);
+            encode_bulk(&mut buf, b"lag");
+            encode_optional_integer(&mut buf, stream.lag(group));
+        }
+        drop(db);
+
+        self.stream.write_all(&buf)?;
+        Ok(())
+    }
+
+    /// XINFO CONSUMERS key group
+    ///
+    /// The idle time is since the consumer last tried to read or claim entries, and the inactive
+    /// time is since it last did, which is -1 if it never has.
+    fn handle_xinfo_consumers(&mut self, key: &[u8], group: &[u8]) -> Result<()> {
+        let now = now();
+        let mut db = database::lock(0);
+        let Some(stream) = db.get_stream(key)? else {
+            return client_error!("no such key");
+        };
+        let Some(consumer_group) = stream.group(group) else {
+            return no_such_group(key, group);
+        };
+        let mut buf = Vec::new();
+        write!(buf, "*{}\r\n", consumer_group.consumers.len())?;
+        for (name, consumer) in &consumer_group.consumers {
+            buf.extend_from_slice(b"*8\r\n");
+            encode_bulk(&mut buf, b"name");
+            encode_bulk(&mut buf, name);
+            encode_bulk(&mut buf, b"pending");
+            encode_integer(&mut buf, consumer.pending.len() as i64);
+            encode_bulk(&mut buf, b"idle");
+            encode_integer(&mut buf, now.saturating_sub(consumer.seen_time) as i64);
+            encode_bulk(&mut buf, b"inactive");
+            encode_integer(
+                &mut buf,
+                consumer
+                    .active_time
+                    .map_or(-1, |time| now.saturating_sub(time) as i64),
+            );
+        }
+        drop(db);
+
+        self.stream.write_all(&buf)?;
+        Ok(())
+    }
+}
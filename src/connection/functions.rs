@@ -0,0 +1,649 @@
+use super::{
+    bulk_strings,
+    scripting::{self, parse_numkeys, raise_error, Entry, Scripts, LOG_LEVELS},
+    Connection, OK,
+};
+use crate::{
+    database::{dump_functions, restore_functions},
+    error::RustisError,
+    glob,
+    lua::{self, Host, Lua, LuaError, LuaResult, Table, Value},
+    resp::RESPData,
+    Result,
+};
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    io::Write,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+/// How long the code of a library can run while it's loaded, which only registers functions
+const LOAD_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The flags functions can be registered with
+const FUNCTION_FLAGS: &[&str] = &[
+    "no-writes",
+    "allow-oom",
+    "allow-stale",
+    "no-cluster",
+    "allow-cross-slot-keys",
+];
+
+/// A function registered by a library with `redis.register_function`
+struct Function {
+    callback: Value,
+    description: Option<Vec<u8>>,
+    flags: Vec<&'static str>,
+}
+
+impl Function {
+    fn no_writes(&self) -> bool {
+        self.flags.contains(&"no-writes")
+    }
+}
+
+/// A library of functions, loaded from its code with FUNCTION LOAD
+struct Library {
+    name: String,
+    /// The code, including the "#!lua name=..." header, which is what gets persisted
+    code: Vec<u8>,
+    functions: BTreeMap<String, Rc<Function>>,
+}
+
+/// The libraries of functions, by their name
+#[derive(Default, Clone)]
+pub(super) struct Libraries {
+    libraries: BTreeMap<String, Rc<Library>>,
+}
+
+/// What FUNCTION RESTORE does with the libraries that already exist
+#[derive(PartialEq)]
+enum RestorePolicy {
+    /// Delete them first
+    Flush,
+    /// Keep them, failing if a restored library has the same name as one
+    Append,
+    /// Keep them, except for those with the same name as a restored library
+    Replace,
+}
+
+impl Libraries {
+    fn function(&self, name: &str) -> Option<Rc<Function>> {
+        self.libraries
+            .values()
+            .find_map(|library| library.functions.get(name).cloned())
+    }
+
+    /// The code of the libraries, in the order of their names
+    fn codes(&self) -> Vec<Vec<u8>> {
+        self.libraries
+            .values()
+            .map(|library| library.code.clone())
+            .collect()
+    }
+
+    /// Load a library from its code, returning its name
+    ///
+    /// Unless `replace` is given, a library with the same name can't already exist. Either way,
+    /// the functions can't have the same name as those of other libraries.
+    fn create(&mut self, code: &[u8], replace: bool) -> Result<String> {
+        let library = load_library(code)?;
+        if !replace && self.libraries.contains_key(&library.name) {
+            return client_error!("Library '{}' already exists", library.name);
+        }
+        for (name, other) in &self.libraries {
+            if *name == library.name {
+                continue;
+            }
+            if let Some(function) = library
+                .functions
+                .keys()
+                .find(|function| other.functions.contains_key(*function))
+            {
+                return client_error!("Function {} already exists", function);
+            }
+        }
+
+        let name = library.name.clone();
+        self.libraries.insert(name.clone(), Rc::new(library));
+        Ok(name)
+    }
+}
+
+impl Scripts {
+    /// Load libraries of functions, such as those in a RDB file, logging the ones that fail
+    pub(crate) fn load_libraries(&mut self, codes: Vec<Vec<u8>>) {
+        for code in codes {
+            if let Err(e) = self.libraries.create(&code, false) {
+                log::error!("Failed to load library of functions: {}", e);
+            }
+        }
+    }
+
+    /// The code of the libraries of functions, to be persisted
+    pub(crate) fn library_codes(&self) -> Vec<Vec<u8>> {
+        self.libraries.codes()
+    }
+}
+
+/// Check whether a name of a library or a function is made up of letters, numbers and underscores
+fn valid_name(name: &[u8]) -> bool {
+    !name.is_empty() && name.iter().all(|&c| c.is_ascii_alphanumeric() || c == b'_')
+}
+
+/// Parse the header of a library, such as "#!lua name=mylib", returning its length and the name
+/// of the library
+///
+/// The newline ending the header is left for the code, so that line numbers stay the same.
+fn parse_header(code: &[u8]) -> Result<(usize, String)> {
+    if !code.starts_with(b"#!") {
+        return client_error!("Missing library metadata");
+    }
+    let end = code.iter().position(|&c| c == b'\n').unwrap_or(code.len());
+    let header = String::from_utf8_lossy(&code[2..end]);
+    let mut parts = header.split(' ').filter(|part| !part.is_empty());
+    let engine = parts.next().unwrap_or_default();
+
+    let mut name = None;
+    for part in parts {
+        match part.strip_prefix("name=") {
+            Some(_) if name.is_some() => {
+                return client_error!(
+                    "Invalid metadata value, name argument was given multiple times"
+                )
+            }
+            Some(value) => name = Some(value.to_string()),
+            None => return client_error!("Invalid metadata value given: {}", part),
+        }
+    }
+    let Some(name) = name else {
+        return client_error!("Library name was not given");
+    };
+    if !engine.eq_ignore_ascii_case("lua") {
+        return client_error!("Engine '{}' not found", engine);
+    }
+    if !valid_name(name.as_bytes()) {
+        return client_error!(
+            "Library names can only contain letters, numbers, or underscores(_) and must be at \
+             least one character long"
+        );
+    }
+    Ok((end, name))
+}
+
+/// Runs the code of a library while it's loaded, which can't run commands
+struct LoadHost {
+    started: Instant,
+}
+
+impl Host for LoadHost {
+    fn call(&mut self, _args: Vec<Vec<u8>>) -> LuaResult<Value> {
+        unreachable!("the redis library has no call while loading functions")
+    }
+
+    fn hook(&mut self) -> LuaResult<()> {
+        if self.started.elapsed() < LOAD_TIMEOUT {
+            return Ok(());
+        }
+        Err(LuaError {
+            value: Value::string("FUNCTION LOAD timeout"),
+            line: 0,
+            fatal: true,
+        })
+    }
+}
+
+/// Compile and run the code of a library, collecting the functions it registers
+fn load_library(code: &[u8]) -> Result<Library> {
+    let (header_length, name) = parse_header(code)?;
+    let chunk = match lua::compile(&code[header_length..], "user_function") {
+        Ok(chunk) => chunk,
+        Err(e) => return client_error!("Error compiling function: {}", e),
+    };
+
+    let registered = Rc::new(RefCell::new(BTreeMap::new()));
+    let result = {
+        let mut host = LoadHost {
+            started: Instant::now(),
+        };
+        let mut lua = Lua::new(&mut host, "user_function");
+        let mut redis = Table::new();
+        let functions = Rc::clone(&registered);
+        redis.set_str(
+            "register_function",
+            Value::native("register_function", move |lua, args| {
+                register_function(lua, args, &mut functions.borrow_mut())
+            }),
+        );
+        redis.set_str("log", Value::native("log", scripting::log));
+        for &(name, value) in LOG_LEVELS {
+            redis.set_str(name, Value::Number(value));
+        }
+        lua.set_global("redis", Value::table(redis));
+        lua.protect_globals();
+        lua.execute(&chunk)
+    };
+    if let Err(e) = result {
+        let message = scripting::error_message(&e);
+        let message = message.strip_prefix("ERR ").unwrap_or(&message);
+        return client_error!("Error registering functions: {}", message);
+    }
+
+    let functions = registered.take();
+    if functions.is_empty() {
+        return client_error!("No functions registered");
+    }
+    Ok(Library {
+        name,
+        code: code.to_vec(),
+        functions,
+    })
+}
+
+/// `redis.register_function`, given either a name and a callback, or a table with the name, the
+/// callback, a description and flags
+fn register_function(
+    lua: &mut Lua,
+    args: Vec<Value>,
+    functions: &mut BTreeMap<String, Rc<Function>>,
+) -> LuaResult<Vec<Value>> {
+    let (name, callback, description, flags) = match args.as_slice() {
+        [Value::Table(table)] => {
+            let table = table.borrow();
+            let mut key = Value::Nil;
+            while let Ok(Some((k, _))) = table.next(&key) {
+                let known = matches!(&k, Value::String(s)
+                    if [&b"function_name"[..], b"callback", b"description", b"flags"]
+                        .contains(&&s[..]));
+                if !known {
+                    return Err(raise_error(
+                        lua,
+                        "unknown argument given to redis.register_function",
+                    ));
+                }
+                key = k;
+            }
+            (
+                table.get_str("function_name"),
+                table.get_str("callback"),
+                table.get_str("description"),
+                table.get_str("flags"),
+            )
+        }
+        [name, callback] => (name.clone(), callback.clone(), Value::Nil, Value::Nil),
+        _ => {
+            return Err(raise_error(
+                lua,
+                "wrong number of arguments to redis.register_function",
+            ))
+        }
+    };
+
+    let name = match name {
+        Value::String(name) => name,
+        Value::Nil => {
+            return Err(raise_error(
+                lua,
+                "redis.register_function must get a function name argument",
+            ))
+        }
+        _ => {
+            return Err(raise_error(
+                lua,
+                "function_name argument given to redis.register_function must be a string",
+            ))
+        }
+    };
+    let callback = match callback {
+        Value::Function(_) => callback,
+        Value::Nil => {
+            return Err(raise_error(
+                lua,
+                "redis.register_function must get a callback argument",
+            ))
+        }
+        _ => {
+            return Err(raise_error(
+                lua,
+                "callback argument given to redis.register_function must be a function",
+            ))
+        }
+    };
+    let description = match description {
+        Value::String(description) => Some(description.to_vec()),
+        Value::Nil => None,
+        _ => {
+            return Err(raise_error(
+                lua,
+                "description argument given to redis.register_function must be a string",
+            ))
+        }
+    };
+    let flags = match flags {
+        Value::Table(table) => {
+            let table = table.borrow();
+            let mut parsed = Vec::new();
+            for i in 1..=table.len() {
+                let flag = table.get(&Value::Number(i as f64));
+                let Some(&flag) = FUNCTION_FLAGS
+                    .iter()
+                    .find(|known| matches!(&flag, Value::String(s) if &s[..] == known.as_bytes()))
+                else {
+                    return Err(raise_error(lua, "unknown flag given"));
+                };
+                if !parsed.contains(&flag) {
+                    parsed.push(flag);
+                }
+            }
+            parsed
+        }
+        Value::Nil => Vec::new(),
+        _ => {
+            return Err(raise_error(
+                lua,
+                "flags argument to redis.register_function must be a table representing \
+                 function flags",
+            ))
+        }
+    };
+
+    if !valid_name(&name) {
+        return Err(raise_error(
+            lua,
+            "Function names can only contain letters, numbers, or underscores(_) and must be at \
+             least one character long",
+        ));
+    }
+    let name = String::from_utf8_lossy(&name).into_owned();
+    if functions.contains_key(&name) {
+        return Err(raise_error(lua, "Function already exists in the library"));
+    }
+    functions.insert(
+        name,
+        Rc::new(Function {
+            callback,
+            description,
+            flags,
+        }),
+    );
+    Ok(Vec::new())
+}
+
+impl Connection {
+    /// Handle FCALL and FCALL_RO, which run a function of a library by its name
+    pub(super) fn handle_fcall(
+        &mut self,
+        args: &[RESPData],
+        name: &[u8],
+        read_only: bool,
+    ) -> Result<()> {
+        log::debug!("Received {}", String::from_utf8_lossy(name).to_uppercase());
+
+        let [RESPData::BulkString(function_name), RESPData::BulkString(numkeys), rest @ ..] = args
+        else {
+            return client_error!(
+                "wrong number of arguments for '{}' command",
+                String::from_utf8_lossy(name)
+            );
+        };
+        let rest = bulk_strings(rest)?;
+        let function_name = String::from_utf8_lossy(function_name);
+        let Some(function) = self.scripts.borrow().libraries.function(&function_name) else {
+            return client_error!("Function not found");
+        };
+        let (keys, argv) = rest.split_at(parse_numkeys(numkeys, rest.len())?);
+        if read_only && !function.no_writes() {
+            return client_error!("Can not execute a script with write flag using *_ro command.");
+        }
+
+        let entry = Entry::Function {
+            name: &function_name,
+            callback: &function.callback,
+        };
+        self.run_script(entry, keys, argv, read_only || function.no_writes())
+    }
+
+    pub(super) fn handle_function(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received FUNCTION");
+
+        let Some((RESPData::BulkString(subcommand), args)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'function' command");
+        };
+        let args = bulk_strings(args)?;
+
+        match (subcommand.to_ascii_uppercase().as_slice(), args.as_slice()) {
+            (b"LOAD", [options @ .., code]) => {
+                let mut replace = false;
+                for option in options {
+                    if !option.eq_ignore_ascii_case(b"REPLACE") {
+                        return client_error!(
+                            "Unknown option given: {}",
+                            String::from_utf8_lossy(option)
+                        );
+                    }
+                    replace = true;
+                }
+                let name = self.scripts.borrow_mut().libraries.create(code, replace)?;
+                self.write_bulk_string(name.as_bytes())
+            }
+            (b"DELETE", [name]) => {
+                let name = String::from_utf8_lossy(name);
+                if self
+                    .scripts
+                    .borrow_mut()
+                    .libraries
+                    .libraries
+                    .remove(&*name)
+                    .is_none()
+                {
+                    return client_error!("Library not found");
+                }
+                self.stream.write_all(OK)?;
+                Ok(())
+            }
+            (b"FLUSH", []) => self.flush_functions(),
+            (b"FLUSH", [mode])
+                if mode.eq_ignore_ascii_case(b"ASYNC") || mode.eq_ignore_ascii_case(b"SYNC") =>
+            {
+                self.flush_functions()
+            }
+            (b"FLUSH", [_]) => client_error!("FUNCTION FLUSH only supports SYNC|ASYNC option"),
+            (b"KILL", []) => self.kill_script(true),
+            (b"LIST", options) => self.list_functions(options),
+            (b"DUMP", []) => {
+                let payload = dump_functions(&self.scripts.borrow().library_codes());
+                self.write_bulk_string(&payload)
+            }
+            (b"RESTORE", [payload, policy @ ..]) if policy.len() <= 1 => {
+                let policy = match policy.first().map(|p| p.to_ascii_uppercase()).as_deref() {
+                    None | Some(b"APPEND") => RestorePolicy::Append,
+                    Some(b"FLUSH") => RestorePolicy::Flush,
+                    Some(b"REPLACE") => RestorePolicy::Replace,
+                    Some(_) => {
+                        return client_error!(
+                            "Wrong restore policy given, value should be either FLUSH, APPEND or \
+                             REPLACE."
+                        )
+                    }
+                };
+                self.restore_functions(payload, policy)
+            }
+            (subcommand @ (b"LOAD" | b"DELETE" | b"FLUSH" | b"KILL" | b"DUMP" | b"RESTORE"), _) => {
+                client_error!(
+                    "wrong number of arguments for 'function|{}' command",
+                    String::from_utf8_lossy(subcommand).to_lowercase()
+                )
+            }
+            _ => client_error!(
+                "unknown subcommand '{}'. Try FUNCTION HELP.",
+                String::from_utf8_lossy(subcommand)
+            ),
+        }
+    }
+
+    fn flush_functions(&mut self) -> Result<()> {
+        self.scripts.borrow_mut().libraries = Libraries::default();
+        self.stream.write_all(OK)?;
+        Ok(())
+    }
+
+    /// Handle FUNCTION LIST, which replies with the libraries, their functions and optionally
+    /// their code
+    fn list_functions(&mut self, options: &[&[u8]]) -> Result<()> {
+        let mut with_code = false;
+        let mut pattern = None;
+        let mut options = options.iter();
+        while let Some(option) = options.next() {
+            match option.to_ascii_uppercase().as_slice() {
+                b"WITHCODE" if !with_code => with_code = true,
+                b"LIBRARYNAME" if pattern.is_none() => match options.next() {
+                    Some(&p) => pattern = Some(p),
+                    None => return client_error!("library name argument was not given"),
+                },
+                _ => return client_error!("Unknown argument {}", String::from_utf8_lossy(option)),
+            }
+        }
+
+        let libraries: Vec<Rc<Library>> = self
+            .scripts
+            .borrow()
+            .libraries
+            .libraries
+            .values()
+            .filter(|library| pattern.is_none_or(|p| glob::matches(p, library.name.as_bytes())))
+            .cloned()
+            .collect();
+        let reply = libraries
+            .iter()
+            .map(|library| {
+                let functions = library
+                    .functions
+                    .iter()
+                    .map(|(name, function)| {
+                        RESPData::Array(vec![
+                            RESPData::BulkString(b"name"),
+                            RESPData::BulkString(name.as_bytes()),
+                            RESPData::BulkString(b"description"),
+                            match &function.description {
+                                Some(description) => RESPData::BulkString(description),
+                                None => RESPData::Null,
+                            },
+                            RESPData::BulkString(b"flags"),
+                            RESPData::Array(
+                                function
+                                    .flags
+                                    .iter()
+                                    .map(|flag| RESPData::BulkString(flag.as_bytes()))
+                                    .collect(),
+                            ),
+                        ])
+                    })
+                    .collect();
+                let mut fields = vec![
+                    RESPData::BulkString(b"library_name"),
+                    RESPData::BulkString(library.name.as_bytes()),
+                    RESPData::BulkString(b"engine"),
+                    RESPData::BulkString(b"LUA"),
+                    RESPData::BulkString(b"functions"),
+                    RESPData::Array(functions),
+                ];
+                if with_code {
+                    fields.push(RESPData::BulkString(b"library_code"));
+                    fields.push(RESPData::BulkString(&library.code));
+                }
+                RESPData::Array(fields)
+            })
+            .collect();
+        self.write_resp(&RESPData::Array(reply))
+    }
+
+    /// Handle FUNCTION RESTORE, which loads the libraries in a payload of FUNCTION DUMP
+    ///
+    /// Either all of the libraries are restored or, if any of them fails, none are.
+    fn restore_functions(&mut self, payload: &[u8], policy: RestorePolicy) -> Result<()> {
+        let codes = match restore_functions(payload) {
+            Ok(codes) => codes,
+            Err(e) => return client_error!("{}", e),
+        };
+        let mut libraries = match policy {
+            RestorePolicy::Flush => Libraries::default(),
+            _ => self.scripts.borrow().libraries.clone(),
+        };
+        for code in codes {
+            libraries.create(&code, policy == RestorePolicy::Replace)?;
+        }
+        self.scripts.borrow_mut().libraries = libraries;
+        self.stream.write_all(OK)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_header() {
+        assert_eq!(
+            parse_header(b"#!lua name=mylib\nreturn 1").unwrap(),
+            (16, "mylib".to_string())
+        );
+        assert_eq!(
+            parse_header(b"#!LUA  name=lib_2").unwrap(),
+            (17, "lib_2".to_string())
+        );
+        assert!(parse_header(b"return 1").is_err());
+        assert!(parse_header(b"#!lua\nreturn 1").is_err());
+        assert!(parse_header(b"#!lua name=a name=b").is_err());
+        assert!(parse_header(b"#!lua name=my-lib").is_err());
+        assert!(parse_header(b"#!lua version=1 name=lib").is_err());
+        assert!(parse_header(b"#!python name=lib").is_err());
+    }
+
+    #[test]
+    fn test_load_library() {
+        let library = load_library(
+            b"#!lua name=lib\n\
+              redis.register_function('f', function(keys, args) return 1 end)\n\
+              redis.register_function{function_name='g', callback=function() end, \
+                                      description='desc', flags={'no-writes'}}",
+        )
+        .unwrap();
+        assert_eq!(library.name, "lib");
+        assert_eq!(library.functions.keys().collect::<Vec<_>>(), vec!["f", "g"]);
+        assert!(!library.functions["f"].no_writes());
+        assert!(library.functions["g"].no_writes());
+        assert_eq!(
+            library.functions["g"].description.as_deref(),
+            Some(&b"desc"[..])
+        );
+
+        assert!(load_library(b"#!lua name=lib\nlocal x = 1").is_err());
+        assert!(load_library(b"#!lua name=lib\nredis.call('PING')").is_err());
+        assert!(load_library(
+            b"#!lua name=lib\n\
+              redis.register_function('f', function() end)\n\
+              redis.register_function('f', function() end)"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_create_conflicts() {
+        let mut libraries = Libraries::default();
+        let code = b"#!lua name=lib\nredis.register_function('f', function() end)";
+        assert_eq!(libraries.create(code, false).unwrap(), "lib");
+        assert!(libraries.create(code, false).is_err());
+        assert!(libraries.create(code, true).is_ok());
+        assert!(libraries
+            .create(
+                b"#!lua name=other\nredis.register_function('f', function() end)",
+                false
+            )
+            .is_err());
+        assert!(libraries.function("f").is_some());
+        assert!(libraries.function("g").is_none());
+    }
+}
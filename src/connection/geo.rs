@@ -0,0 +1,493 @@
+use super::{
+    bulk_strings, parse_int,
+    sorted_sets::{add_members, AddFlags},
+    strings::parse_float,
+    Connection, EMPTY_ARRAY, NULL, NULL_ARRAY,
+};
+use crate::{
+    database::{
+        self,
+        geohash::{self, Area, Shape, LATITUDE_MAX, LATITUDE_MIN, LONGITUDE_MAX, LONGITUDE_MIN},
+        SortedSet, Value,
+    },
+    error::RustisError,
+    notify,
+    resp::RESPData,
+    Result,
+};
+use std::{cmp::Ordering, io::Write};
+
+/// How many meters there are in a unit of distance
+fn parse_unit(raw: &[u8]) -> Result<f64> {
+    match raw.to_ascii_lowercase().as_slice() {
+        b"m" => Ok(1.0),
+        b"km" => Ok(1000.0),
+        b"ft" => Ok(0.3048),
+        b"mi" => Ok(1609.34),
+        _ => client_error!("unsupported unit provided. please use M, KM, FT, MI"),
+    }
+}
+
+/// Parse a longitude and latitude, which have to be within the range that can be encoded
+fn parse_position(longitude: &[u8], latitude: &[u8]) -> Result<(f64, f64)> {
+    let (Some(longitude), Some(latitude)) = (parse_float(longitude), parse_float(latitude)) else {
+        return client_error!("value is not a valid float");
+    };
+    if !(LONGITUDE_MIN..=LONGITUDE_MAX).contains(&longitude)
+        || !(LATITUDE_MIN..=LATITUDE_MAX).contains(&latitude)
+    {
+        return client_error!(
+            "invalid longitude,latitude pair {:.6},{:.6}",
+            longitude,
+            latitude
+        );
+    }
+    Ok((longitude, latitude))
+}
+
+/// Parse a distance, with the error message to reply with when it's not a number
+fn parse_distance(raw: &[u8], not_a_number: &str) -> Result<f64> {
+    match parse_float(raw) {
+        Some(distance) => Ok(distance),
+        None => Err(RustisError::ClientError(not_a_number.to_string())),
+    }
+}
+
+/// Format a coordinate with 17 decimal places, without trailing zeros
+fn format_coordinate(coordinate: f64) -> Vec<u8> {
+    let formatted = format!("{:.17}", coordinate);
+    let formatted = formatted.trim_end_matches('0').trim_end_matches('.');
+    match formatted {
+        "-0" => b"0".to_vec(),
+        formatted => formatted.as_bytes().to_vec(),
+    }
+}
+
+/// Format a distance with 4 decimal places, which is precise enough even in kilometers
+fn format_distance(distance: f64) -> Vec<u8> {
+    format!("{:.4}", distance).into_bytes()
+}
+
+fn encode_position(buf: &mut Vec<u8>, longitude: f64, latitude: f64) {
+    buf.extend_from_slice(b"*2\r\n");
+    RESPData::BulkString(&format_coordinate(longitude)).encode(buf);
+    RESPData::BulkString(&format_coordinate(latitude)).encode(buf);
+}
+
+/// The order to return the results of a search in, by distance from the centre
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Sort {
+    Asc,
+    Desc,
+}
+
+/// Where GEOSEARCH searches from, which is looked up once all the options are parsed
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Origin<'a> {
+    Member(&'a [u8]),
+    Position(f64, f64),
+}
+
+/// The options of GEOSEARCH and GEOSEARCHSTORE
+#[derive(Debug, Default)]
+struct SearchOptions<'a> {
+    origin: Option<Origin<'a>>,
+    shape: Option<(Shape, f64)>,
+    sort: Option<Sort>,
+    count: Option<usize>,
+    any: bool,
+    with_coord: bool,
+    with_dist: bool,
+    with_hash: bool,
+    store_dist: bool,
+}
+
+impl<'a> SearchOptions<'a> {
+    fn parse(args: &[&'a [u8]], command: &str, store: bool) -> Result<Self> {
+        let mut options = SearchOptions::default();
+        let mut i = 0;
+        while i < args.len() {
+            let remaining = args.len() - i - 1;
+            match args[i].to_ascii_uppercase().as_slice() {
+                b"WITHDIST" => options.with_dist = true,
+                b"WITHHASH" => options.with_hash = true,
+                b"WITHCOORD" => options.with_coord = true,
+                b"ANY" => options.any = true,
+                b"ASC" => options.sort = Some(Sort::Asc),
+                b"DESC" => options.sort = Some(Sort::Desc),
+                b"COUNT" if remaining >= 1 => {
+                    let count: i64 = parse_int(args[i + 1])?;
+                    if count <= 0 {
+                        return client_error!("COUNT must be > 0");
+                    }
+                    options.count = Some(count as usize);
+                    i += 1;
+                }
+                b"FROMMEMBER" if remaining >= 1 && options.origin.is_none() => {
+                    options.origin = Some(Origin::Member(args[i + 1]));
+                    i += 1;
+                }
+                b"FROMLONLAT" if remaining >= 2 && options.origin.is_none() => {
+                    let (longitude, latitude) = parse_position(args[i + 1], args[i + 2])?;
+                    options.origin = Some(Origin::Position(longitude, latitude));
+                    i += 2;
+                }
+                b"BYRADIUS" if remaining >= 2 && options.shape.is_none() => {
+                    let radius = parse_distance(args[i + 1], "need numeric radius")?;
+                    if radius < 0.0 {
+                        return client_error!("radius cannot be negative");
+                    }
+                    options.shape = Some((Shape::Radius(radius), parse_unit(args[i + 2])?));
+                    i += 2;
+                }
+                b"BYBOX" if remaining >= 3 && options.shape.is_none() => {
+                    let width = parse_distance(args[i + 1], "need numeric width")?;
+                    let height = parse_distance(args[i + 2], "need numeric height")?;
+                    if width < 0.0 || height < 0.0 {
+                        return client_error!("height or width cannot be negative");
+                    }
+                    options.shape = Some((Shape::Box { width, height }, parse_unit(args[i + 3])?));
+                    i += 3;
+                }
+                b"STOREDIST" if store => options.store_dist = true,
+                _ => return client_error!("syntax error"),
+            }
+            i += 1;
+        }
+
+        if store && (options.with_dist || options.with_hash || options.with_coord) {
+            return client_error!(
+                "{} is not compatible with WITHDIST, WITHHASH and WITHCOORD options",
+                command.to_uppercase()
+            );
+        }
+        if options.origin.is_none() {
+            return client_error!(
+                "exactly one of FROMMEMBER or FROMLONLAT can be specified for {}",
+                command
+            );
+        }
+        if options.shape.is_none() {
+            return client_error!(
+                "exactly one of BYRADIUS and BYBOX can be specified for {}",
+                command
+            );
+        }
+        if options.any && options.count.is_none() {
+            return client_error!("the ANY argument requires COUNT argument");
+        }
+        Ok(options)
+    }
+}
+
+/// A member found by a search
+#[derive(Debug, Clone, PartialEq)]
+struct Found {
+    member: Vec<u8>,
+    score: f64,
+    longitude: f64,
+    latitude: f64,
+    /// The distance from the centre, in the unit of the search
+    distance: f64,
+}
+
+/// Find the members within an area, looking at the geohash boxes covering the area in order, and
+/// the members within each box in order of their scores
+///
+/// With a limit, the search stops as soon as enough members have been found.
+fn search(sorted_set: &SortedSet, area: &Area, limit: Option<usize>) -> Vec<Found> {
+    let mut found = Vec::new();
+    let is_full = |found: &Vec<Found>| limit.is_some_and(|limit| found.len() >= limit);
+    for range in area.score_ranges() {
+        if is_full(&found) {
+            break;
+        }
+        let (min, max) = (range.start as f64, range.end as f64);
+        let start = sorted_set.count_while(|_, score| score < min);
+        for (member, score) in sorted_set.iter_from(start) {
+            if score >= max {
+                break;
+            }
+            let (longitude, latitude) = geohash::decode(score);
+            if let Some(distance) = area.distance_to(longitude, latitude) {
+                found.push(Found {
+                    member: member.to_vec(),
+                    score,
+                    longitude,
+                    latitude,
+                    distance: distance / area.to_meters,
+                });
+            }
+            if is_full(&found) {
+                break;
+            }
+        }
+    }
+    found
+}
+
+impl Connection {
+    /// GEOADD key [NX | XX] [CH] longitude latitude member [longitude latitude member ...]
+    pub(super) fn handle_geoadd(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received GEOADD");
+
+        let args = bulk_strings(args)?;
+        let Some((key, mut rest)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'geoadd' command");
+        };
+        if rest.len() < 3 {
+            return client_error!("wrong number of arguments for 'geoadd' command");
+        }
+        let mut flags = AddFlags::default();
+        while let Some((option, remaining)) = rest.split_first() {
+            match option.to_ascii_uppercase().as_slice() {
+                b"NX" => flags.nx = true,
+                b"XX" => flags.xx = true,
+                b"CH" => flags.ch = true,
+                _ => break,
+            }
+            rest = remaining;
+        }
+        if rest.is_empty() || rest.len() % 3 != 0 || (flags.nx && flags.xx) {
+            return client_error!("syntax error");
+        }
+
+        let pairs = rest
+            .chunks_exact(3)
+            .map(|triple| {
+                let (longitude, latitude) = parse_position(triple[0], triple[1])?;
+                // The position has been checked to be within the valid range
+                Ok((geohash::encode(longitude, latitude).unwrap(), triple[2]))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let limits = self.zset_limits();
+        let (changed, _) = add_members(&mut database::lock(0), key, &pairs, flags, &limits)?;
+        self.signal_key_ready(key);
+
+        self.write_integer(changed as i64)
+    }
+
+    /// GEODIST key member1 member2 [M | KM | FT | MI]
+    pub(super) fn handle_geodist(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received GEODIST");
+
+        let args = bulk_strings(args)?;
+        let [key, member1, member2, unit @ ..] = args.as_slice() else {
+            return client_error!("wrong number of arguments for 'geodist' command");
+        };
+        let to_meters = match unit {
+            [] => 1.0,
+            [unit] => parse_unit(unit)?,
+            _ => return client_error!("syntax error"),
+        };
+
+        let mut db = database::lock(0);
+        let scores = db
+            .get_zset(key)?
+            .and_then(|sorted_set| Some((sorted_set.score(member1)?, sorted_set.score(member2)?)));
+        drop(db);
+
+        let Some((score1, score2)) = scores else {
+            self.stream.write_all(NULL)?;
+            return Ok(());
+        };
+        let (longitude1, latitude1) = geohash::decode(score1);
+        let (longitude2, latitude2) = geohash::decode(score2);
+        let distance = geohash::distance(longitude1, latitude1, longitude2, latitude2);
+        self.write_bulk_string(&format_distance(distance / to_meters))
+    }
+
+    /// GEOPOS key [member [member ...]]
+    pub(super) fn handle_geopos(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received GEOPOS");
+
+        let args = bulk_strings(args)?;
+        let Some((key, members)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'geopos' command");
+        };
+
+        let mut db = database::lock(0);
+        let sorted_set = db.get_zset(key)?;
+        let mut buf = Vec::new();
+        write!(buf, "*{}\r\n", members.len())?;
+        for member in members {
+            match sorted_set.and_then(|sorted_set| sorted_set.score(member)) {
+                Some(score) => {
+                    let (longitude, latitude) = geohash::decode(score);
+                    encode_position(&mut buf, longitude, latitude);
+                }
+                None => buf.extend_from_slice(NULL_ARRAY),
+            }
+        }
+        drop(db);
+
+        self.stream.write_all(&buf)?;
+        Ok(())
+    }
+
+    /// GEOHASH key [member [member ...]]
+    pub(super) fn handle_geohash(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received GEOHASH");
+
+        let args = bulk_strings(args)?;
+        let Some((key, members)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'geohash' command");
+        };
+
+        let mut db = database::lock(0);
+        let sorted_set = db.get_zset(key)?;
+        let mut buf = Vec::new();
+        write!(buf, "*{}\r\n", members.len())?;
+        for member in members {
+            match sorted_set.and_then(|sorted_set| sorted_set.score(member)) {
+                Some(score) => {
+                    RESPData::BulkString(geohash::to_base32(score).as_bytes()).encode(&mut buf)
+                }
+                None => buf.extend_from_slice(NULL),
+            }
+        }
+        drop(db);
+
+        self.stream.write_all(&buf)?;
+        Ok(())
+    }
+
+    /// Handle GEOSEARCH and GEOSEARCHSTORE, which takes a destination key before the source key
+    ///
+    /// Without ASC or DESC, the members are returned in the order they're found in, unless there's
+    /// a COUNT without ANY, in which case the nearest members are returned.
+    pub(super) fn handle_geosearch(&mut self, args: &[RESPData], store: bool) -> Result<()> {
+        let command = if store { "geosearchstore" } else { "geosearch" };
+        log::debug!("Received {}", command.to_uppercase());
+
+        let args = bulk_strings(args)?;
+        let (destination, key, options) = match (store, args.as_slice()) {
+            (true, [destination, key, options @ ..]) if options.len() >= 5 => {
+                (Some(*destination), *key, options)
+            }
+            (false, [key, options @ ..]) if options.len() >= 5 => (None, *key, options),
+            _ => return client_error!("wrong number of arguments for '{}' command", command),
+        };
+
+        let limits = self.zset_limits();
+        let mut db = database::lock(0);
+        let sorted_set = db.get_zset(key)?;
+        let options = SearchOptions::parse(options, command, store)?;
+
+        let Some(sorted_set) = sorted_set else {
+            if let Some(destination) = destination {
+                if db.remove(destination).is_some() {
+                    db.notify(notify::GENERIC, "del", destination);
+                }
+                drop(db);
+                return self.write_integer(0);
+            }
+            drop(db);
+            self.stream.write_all(EMPTY_ARRAY)?;
+            return Ok(());
+        };
+
+        let (longitude, latitude) = match options.origin.unwrap() {
+            Origin::Position(longitude, latitude) => (longitude, latitude),
+            Origin::Member(member) => match sorted_set.score(member) {
+                Some(score) => geohash::decode(score),
+                None => return client_error!("could not decode requested zset member"),
+            },
+        };
+        let (shape, to_meters) = options.shape.unwrap();
+        let area = Area {
+            longitude,
+            latitude,
+            shape,
+            to_meters,
+        };
+
+        let mut found = search(sorted_set, &area, options.count.filter(|_| options.any));
+        let sort = match options.sort {
+            // Without ANY, COUNT returns the nearest members
+            None if options.count.is_some() && !options.any => Some(Sort::Asc),
+            sort => sort,
+        };
+        let by_distance = |a: &Found, b: &Found| {
+            a.distance
+                .partial_cmp(&b.distance)
+                .unwrap_or(Ordering::Equal)
+        };
+        match sort {
+            Some(Sort::Asc) => found.sort_by(by_distance),
+            Some(Sort::Desc) => found.sort_by(|a, b| by_distance(b, a)),
+            None => {}
+        }
+        if let Some(count) = options.count {
+            found.truncate(count);
+        }
+
+        if let Some(destination) = destination {
+            let stored = found.len();
+            let result = SortedSet::from_entries(
+                found.into_iter().map(|found| {
+                    let score = if options.store_dist {
+                        found.distance
+                    } else {
+                        found.score
+                    };
+                    (found.member, score)
+                }),
+                &limits,
+            );
+            let removed = db.remove(destination).is_some();
+            if stored > 0 {
+                db.insert(destination, Value::SortedSet(result));
+                db.notify(notify::ZSET, "geosearchstore", destination);
+            } else if removed {
+                db.notify(notify::GENERIC, "del", destination);
+            }
+            drop(db);
+            self.signal_key_ready(destination);
+            return self.write_integer(stored as i64);
+        }
+        drop(db);
+
+        let with_options =
+            options.with_dist as usize + options.with_hash as usize + options.with_coord as usize;
+        let mut buf = Vec::new();
+        write!(buf, "*{}\r\n", found.len())?;
+        for found in &found {
+            if with_options == 0 {
+                RESPData::BulkString(&found.member).encode(&mut buf);
+                continue;
+            }
+            write!(buf, "*{}\r\n", with_options + 1)?;
+            RESPData::BulkString(&found.member).encode(&mut buf);
+            if options.with_dist {
+                RESPData::BulkString(&format_distance(found.distance)).encode(&mut buf);
+            }
+            if options.with_hash {
+                RESPData::Integer(found.score as i64).encode(&mut buf);
+            }
+            if options.with_coord {
+                encode_position(&mut buf, found.longitude, found.latitude);
+            }
+        }
+        self.stream.write_all(&buf)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formatting() {
+        assert_eq!(
+            format_coordinate(13.361389338970184),
+            b"13.36138933897018433"
+        );
+        assert_eq!(format_coordinate(-0.0), b"0");
+        assert_eq!(format_coordinate(2.0), b"2");
+        assert_eq!(format_distance(166274.15156960033), b"166274.1516");
+        assert_eq!(format_distance(0.0), b"0.0000");
+    }
+}
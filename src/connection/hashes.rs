@@ -0,0 +1,822 @@
+use super::{
+    bulk_strings, parse_int,
+    strings::{format_human_float, parse_float},
+    Connection, EMPTY_ARRAY, NULL, OK,
+};
+use crate::{
+    database::{self, now, DbHandle, Hash, ListpackLimits, Value},
+    error::RustisError,
+    notify, random,
+    resp::RESPData,
+    Result,
+};
+use std::io::Write;
+
+/// The latest expiry a field can have, in unix time milliseconds
+const MAX_FIELD_EXPIRY: u128 = (1 << 48) - 1;
+
+/// Replies for each field of the field expiry commands
+const FIELD_MISSING: i64 = -2;
+const FIELD_HAS_NO_EXPIRY: i64 = -1;
+const CONDITION_NOT_MET: i64 = 0;
+const FIELD_UPDATED: i64 = 1;
+const FIELD_DELETED: i64 = 2;
+
+/// The condition for HEXPIRE and friends to set the expiry of a field
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ExpiryCondition {
+    /// Only if the field has no expiry
+    Nx,
+    /// Only if the field has an expiry
+    Xx,
+    /// Only if the new expiry is later than the current one, where no expiry is the latest
+    Gt,
+    /// Only if the new expiry is earlier than the current one
+    Lt,
+}
+
+impl ExpiryCondition {
+    fn parse(arg: &[u8]) -> Option<Self> {
+        match arg.to_ascii_uppercase().as_slice() {
+            b"NX" => Some(ExpiryCondition::Nx),
+            b"XX" => Some(ExpiryCondition::Xx),
+            b"GT" => Some(ExpiryCondition::Gt),
+            b"LT" => Some(ExpiryCondition::Lt),
+            _ => None,
+        }
+    }
+
+    fn is_met(&self, current: Option<u128>, new: u128) -> bool {
+        match self {
+            ExpiryCondition::Nx => current.is_none(),
+            ExpiryCondition::Xx => current.is_some(),
+            ExpiryCondition::Gt => current.is_some_and(|current| new > current),
+            ExpiryCondition::Lt => current.is_none_or(|current| new < current),
+        }
+    }
+}
+
+/// How HGETEX and HSETEX should change the expiry of the fields
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ExpiryChange {
+    /// Leave the expiry as it is
+    Keep,
+    /// Remove the expiry
+    Persist,
+    /// Expire at the given unix time in milliseconds
+    At(u128),
+}
+
+/// Parse the `FIELDS numfields field [field ...]` arguments that the field expiry commands end
+/// with, where each field can be followed by more arguments, like its value
+fn parse_fields<'a, 'b>(args: &'b [&'a [u8]], per_field: usize) -> Result<&'b [&'a [u8]]> {
+    let [keyword, numfields, rest @ ..] = args else {
+        return client_error!("Mandatory argument FIELDS is missing or not at the right position");
+    };
+    if !keyword.eq_ignore_ascii_case(b"FIELDS") {
+        return client_error!("Mandatory argument FIELDS is missing or not at the right position");
+    }
+    let numfields = match parse_int::<i64>(numfields) {
+        Ok(numfields) if numfields > 0 => numfields as usize,
+        _ => return client_error!("Parameter `numFields` should be greater than 0"),
+    };
+    if numfields.checked_mul(per_field) != Some(rest.len()) {
+        return client_error!("The `numfields` parameter must match the number of arguments");
+    }
+    Ok(rest)
+}
+
+/// Parse an expire time into unix time milliseconds, where `unit` is the length of the unit in
+/// milliseconds and relative times are from now
+fn parse_field_expiry(raw: &[u8], unit: u128, absolute: bool, command: &str) -> Result<u128> {
+    let time: i64 = parse_int(raw)?;
+    if time < 0 {
+        return client_error!("invalid expire time in '{}' command", command);
+    }
+    let mut expiry = time as u128 * unit;
+    if !absolute {
+        expiry += now();
+    }
+    if expiry > MAX_FIELD_EXPIRY {
+        return client_error!("invalid expire time in '{}' command", command);
+    }
+    Ok(expiry)
+}
+
+/// Parse the EX, PX, EXAT and PXAT options of HGETEX and HSETEX, returning `None` if the option
+/// is something else
+fn parse_expiry_option<'a, 'b, I>(
+    option: &[u8],
+    args: &mut I,
+    command: &str,
+) -> Result<Option<u128>>
+where
+    'a: 'b,
+    I: Iterator<Item = &'b &'a [u8]>,
+{
+    let (unit, absolute) = match option.to_ascii_uppercase().as_slice() {
+        b"EX" => (1000, false),
+        b"PX" => (1, false),
+        b"EXAT" => (1000, true),
+        b"PXAT" => (1, true),
+        _ => return Ok(None),
+    };
+    let Some(raw) = args.next() else {
+        return client_error!("syntax error");
+    };
+    // Unlike HEXPIRE, a time of zero is not allowed
+    if parse_int::<i64>(raw)? == 0 {
+        return client_error!("invalid expire time in '{}' command", command);
+    }
+    parse_field_expiry(raw, unit, absolute, command).map(Some)
+}
+
+/// Apply a change of expiry to a field, deleting it if the new expiry is in the past
+///
+/// Returns whether the field was changed
+fn change_field_expiry(hash: &mut Hash, field: &[u8], change: ExpiryChange, now: u128) -> bool {
+    match change {
+        ExpiryChange::Keep => false,
+        ExpiryChange::Persist => hash.expiry(field).is_some() && hash.set_expiry(field, None),
+        ExpiryChange::At(expiry) if expiry <= now => hash.remove(field),
+        ExpiryChange::At(expiry) => hash.set_expiry(field, Some(expiry)),
+    }
+}
+
+/// The keyspace event for a change of expiry applied to fields, if it changes anything
+fn expiry_change_event(change: ExpiryChange, now: u128) -> Option<&'static str> {
+    match change {
+        ExpiryChange::Keep => None,
+        ExpiryChange::Persist => Some("hpersist"),
+        ExpiryChange::At(expiry) if expiry <= now => Some("hdel"),
+        ExpiryChange::At(_) => Some("hexpire"),
+    }
+}
+
+/// Remove a key if it holds a hash that has become empty, as empty hashes are never kept around
+fn remove_if_empty(db: &mut DbHandle, key: &[u8]) {
+    if matches!(db.get_mut(key), Some(Value::Hash(hash)) if hash.is_empty()) {
+        db.remove(key);
+        db.notify(notify::GENERIC, "del", key);
+    }
+}
+
+/// Look up a hash for modification, creating an empty one if the key doesn't exist
+fn get_or_create_hash<'a>(db: &'a mut DbHandle, key: &[u8]) -> Result<&'a mut Hash> {
+    if db.get_hash_mut(key)?.is_none() {
+        db.insert(key, Value::Hash(Hash::new()));
+    }
+    Ok(db.get_hash_mut(key)?.unwrap())
+}
+
+impl Connection {
+    /// The limits for hashes to stay listpack encoded, from the config
+    fn hash_limits(&self) -> ListpackLimits {
+        let config = self.config.borrow();
+        ListpackLimits {
+            max_entries: config.hash_max_listpack_entries,
+            max_value: config.hash_max_listpack_value,
+        }
+    }
+
+    /// Handle HSET and HMSET, which only differ in their reply
+    pub(super) fn handle_hset(&mut self, args: &[RESPData], command: &[u8]) -> Result<()> {
+        let command = String::from_utf8_lossy(command);
+        log::debug!("Received {}", command.to_uppercase());
+
+        let args = bulk_strings(args)?;
+        let Some((key, pairs)) = args.split_first() else {
+            return client_error!("wrong number of arguments for '{}' command", command);
+        };
+        if pairs.is_empty() || pairs.len() % 2 != 0 {
+            return client_error!("wrong number of arguments for '{}' command", command);
+        }
+
+        let limits = self.hash_limits();
+        let mut db = database::lock(0);
+        let hash = get_or_create_hash(&mut db, key)?;
+        let mut added = 0;
+        for pair in pairs.chunks_exact(2) {
+            if hash.insert(pair[0].to_vec(), pair[1].to_vec(), &limits) {
+                added += 1;
+            }
+        }
+        db.touch(key);
+        db.notify(notify::HASH, "hset", key);
+        drop(db);
+
+        if command == "hmset" {
+            self.stream.write_all(OK)?;
+            Ok(())
+        } else {
+            self.write_integer(added)
+        }
+    }
+
+    pub(super) fn handle_hsetnx(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received HSETNX");
+
+        let [RESPData::BulkString(key), RESPData::BulkString(field), RESPData::BulkString(value)] =
+            args
+        else {
+            return client_error!("wrong number of arguments for 'hsetnx' command");
+        };
+
+        let limits = self.hash_limits();
+        let mut db = database::lock(0);
+        let hash = get_or_create_hash(&mut db, key)?;
+        let was_set = !hash.contains(field) && hash.insert(field.to_vec(), value.to_vec(), &limits);
+        if was_set {
+            db.touch(key);
+            db.notify(notify::HASH, "hset", key);
+        }
+        drop(db);
+
+        self.write_integer(was_set as i64)
+    }
+
+    pub(super) fn handle_hget(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received HGET");
+
+        let [RESPData::BulkString(key), RESPData::BulkString(field)] = args else {
+            return client_error!("wrong number of arguments for 'hget' command");
+        };
+
+        let mut db = database::lock(0);
+        let value = db.get_hash(key)?.and_then(|hash| hash.get(field));
+
+        self.write_optional_bulk_string(value.map(|v| v.as_slice()))
+    }
+
+    pub(super) fn handle_hmget(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received HMGET");
+
+        let args = bulk_strings(args)?;
+        let Some((key, fields)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'hmget' command");
+        };
+        if fields.is_empty() {
+            return client_error!("wrong number of arguments for 'hmget' command");
+        }
+
+        let mut db = database::lock(0);
+        let hash = db.get_hash(key)?;
+
+        self.write_resp(&RESPData::Array(
+            fields
+                .iter()
+                .map(|field| match hash.and_then(|hash| hash.get(field)) {
+                    Some(value) => RESPData::BulkString(value),
+                    None => RESPData::Null,
+                })
+                .collect(),
+        ))
+    }
+
+    pub(super) fn handle_hdel(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received HDEL");
+
+        let args = bulk_strings(args)?;
+        let Some((key, fields)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'hdel' command");
+        };
+        if fields.is_empty() {
+            return client_error!("wrong number of arguments for 'hdel' command");
+        }
+
+        let mut db = database::lock(0);
+        let Some(hash) = db.get_hash_mut(key)? else {
+            return self.write_integer(0);
+        };
+        let removed = fields.iter().filter(|field| hash.remove(field)).count();
+        if removed > 0 {
+            db.touch(key);
+            db.notify(notify::HASH, "hdel", key);
+        }
+        remove_if_empty(&mut db, key);
+
+        self.write_integer(removed as i64)
+    }
+
+    pub(super) fn handle_hlen(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received HLEN");
+
+        let [RESPData::BulkString(key)] = args else {
+            return client_error!("wrong number of arguments for 'hlen' command");
+        };
+
+        let length = database::lock(0)
+            .get_hash(key)?
+            .map_or(0, |hash| hash.len());
+
+        self.write_integer(length as i64)
+    }
+
+    pub(super) fn handle_hstrlen(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received HSTRLEN");
+
+        let [RESPData::BulkString(key), RESPData::BulkString(field)] = args else {
+            return client_error!("wrong number of arguments for 'hstrlen' command");
+        };
+
+        let length = database::lock(0)
+            .get_hash(key)?
+            .and_then(|hash| hash.get(field))
+            .map_or(0, |value| value.len());
+
+        self.write_integer(length as i64)
+    }
+
+    pub(super) fn handle_hexists(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received HEXISTS");
+
+        let [RESPData::BulkString(key), RESPData::BulkString(field)] = args else {
+            return client_error!("wrong number of arguments for 'hexists' command");
+        };
+
+        let exists = database::lock(0)
+            .get_hash(key)?
+            .is_some_and(|hash| hash.contains(field));
+
+        self.write_integer(exists as i64)
+    }
+
+    /// Handle HGETALL, HKEYS and HVALS, which reply with the fields and/or values of the hash
+    pub(super) fn handle_hgetall(
+        &mut self,
+        args: &[RESPData],
+        command: &[u8],
+        fields: bool,
+        values: bool,
+    ) -> Result<()> {
+        let command = String::from_utf8_lossy(command);
+        log::debug!("Received {}", command.to_uppercase());
+
+        let [RESPData::BulkString(key)] = args else {
+            return client_error!("wrong number of arguments for '{}' command", command);
+        };
+
+        let mut db = database::lock(0);
+        let Some(hash) = db.get_hash(key)? else {
+            self.stream.write_all(EMPTY_ARRAY)?;
+            return Ok(());
+        };
+
+        let mut reply = Vec::with_capacity(hash.len() * 2);
+        for (field, value) in hash.iter() {
+            if fields {
+                reply.push(field.as_slice());
+            }
+            if values {
+                reply.push(value.as_slice());
+            }
+        }
+
+        self.write_array(reply)
+    }
+
+    pub(super) fn handle_hincrby(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received HINCRBY");
+
+        let [RESPData::BulkString(key), RESPData::BulkString(field), RESPData::BulkString(increment)] =
+            args
+        else {
+            return client_error!("wrong number of arguments for 'hincrby' command");
+        };
+
+        let increment: i64 = parse_int(increment)?;
+
+        let limits = self.hash_limits();
+        let mut db = database::lock(0);
+        let hash = get_or_create_hash(&mut db, key)?;
+
+        let current = match hash.get(field) {
+            Some(value) => match std::str::from_utf8(value)
+                .ok()
+                .and_then(|v| v.parse::<i64>().ok())
+            {
+                Some(current) => current,
+                None => return client_error!("hash value is not an integer"),
+            },
+            None => 0,
+        };
+        let Some(new) = current.checked_add(increment) else {
+            return client_error!("increment or decrement would overflow");
+        };
+        hash.insert_keep_expiry(field.to_vec(), new.to_string().into_bytes(), &limits);
+        db.touch(key);
+        db.notify(notify::HASH, "hincrby", key);
+        drop(db);
+
+        self.write_integer(new)
+    }
+
+    pub(super) fn handle_hincrbyfloat(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received HINCRBYFLOAT");
+
+        let [RESPData::BulkString(key), RESPData::BulkString(field), RESPData::BulkString(increment)] =
+            args
+        else {
+            return client_error!("wrong number of arguments for 'hincrbyfloat' command");
+        };
+
+        let Some(increment) = parse_float(increment) else {
+            return client_error!("value is not a valid float");
+        };
+
+        let limits = self.hash_limits();
+        let mut db = database::lock(0);
+        let hash = get_or_create_hash(&mut db, key)?;
+
+        let current = match hash.get(field) {
+            Some(value) => match parse_float(value) {
+                Some(current) => current,
+                None => return client_error!("hash value is not a float"),
+            },
+            None => 0.0,
+        };
+        let new = current + increment;
+        if !new.is_finite() {
+            // The hash might have just been created for this
+            remove_if_empty(&mut db, key);
+            return client_error!("increment would produce NaN or Infinity");
+        }
+        let new = format_human_float(new);
+        hash.insert_keep_expiry(field.to_vec(), new.clone(), &limits);
+        db.touch(key);
+        db.notify(notify::HASH, "hincrbyfloat", key);
+        drop(db);
+
+        self.write_bulk_string(&new)
+    }
+
+    pub(super) fn handle_hrandfield(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received HRANDFIELD");
+
+        let (key, count, with_values) = match args {
+            [RESPData::BulkString(key)] => (key, None, false),
+            [RESPData::BulkString(key), RESPData::BulkString(count)] => {
+                (key, Some(parse_int::<i64>(count)?), false)
+            }
+            [RESPData::BulkString(key), RESPData::BulkString(count), RESPData::BulkString(option)]
+                if option.eq_ignore_ascii_case(b"WITHVALUES") =>
+            {
+                (key, Some(parse_int::<i64>(count)?), true)
+            }
+            [_, _, _] => return client_error!("syntax error"),
+            _ => return client_error!("wrong number of arguments for 'hrandfield' command"),
+        };
+
+        // Keep the reply from growing unreasonably large, like Redis does
+        if count.is_some_and(|count| count.unsigned_abs() > i64::MAX as u64 / 2) {
+            return client_error!("value is out of range");
+        }
+
+        let mut db = database::lock(0);
+        let pairs: Vec<(&Vec<u8>, &Vec<u8>)> = match db.get_hash(key)? {
+            Some(hash) => hash.iter().collect(),
+            None => Vec::new(),
+        };
+
+        let Some(count) = count else {
+            if pairs.is_empty() {
+                self.stream.write_all(NULL)?;
+                return Ok(());
+            }
+            let (field, _) = pairs[random::index(pairs.len())];
+            return self.write_bulk_string(field);
+        };
+
+        // A positive count returns distinct fields, while a negative count can repeat them
+        let picked: Vec<usize> = if pairs.is_empty() {
+            Vec::new()
+        } else if count >= 0 {
+            random::distinct_indexes(pairs.len(), count as usize)
+        } else {
+            (0..count.unsigned_abs())
+                .map(|_| random::index(pairs.len()))
+                .collect()
+        };
+
+        let mut reply = Vec::with_capacity(picked.len() * 2);
+        for index in picked {
+            let (field, value) = pairs[index];
+            reply.push(field.as_slice());
+            if with_values {
+                reply.push(value.as_slice());
+            }
+        }
+
+        self.write_array(reply)
+    }
+
+    /// Handle HEXPIRE, HPEXPIRE, HEXPIREAT and HPEXPIREAT, which only differ in how the expire
+    /// time is given, where `unit` is the length of the unit in milliseconds
+    pub(super) fn handle_hexpire(
+        &mut self,
+        args: &[RESPData],
+        command: &[u8],
+        unit: u128,
+        absolute: bool,
+    ) -> Result<()> {
+        let command = String::from_utf8_lossy(command);
+        log::debug!("Received {}", command.to_uppercase());
+
+        let args = bulk_strings(args)?;
+        let [key, time, rest @ ..] = args.as_slice() else {
+            return client_error!("wrong number of arguments for '{}' command", command);
+        };
+        if rest.len() < 3 {
+            return client_error!("wrong number of arguments for '{}' command", command);
+        }
+        let expiry = parse_field_expiry(time, unit, absolute, &command)?;
+        let condition = rest.first().and_then(|arg| ExpiryCondition::parse(arg));
+        let rest = if condition.is_some() {
+            &rest[1..]
+        } else {
+            rest
+        };
+        let fields = parse_fields(rest, 1)?;
+
+        let mut db = database::lock(0);
+        let Some(hash) = db.get_hash_mut(key)? else {
+            return self.write_field_replies(fields.iter().map(|_| FIELD_MISSING));
+        };
+
+        let now = now();
+        let replies: Vec<i64> = fields
+            .iter()
+            .map(|field| {
+                if !hash.contains(field) {
+                    FIELD_MISSING
+                } else if !condition.is_none_or(|c| c.is_met(hash.expiry(field), expiry)) {
+                    CONDITION_NOT_MET
+                } else if expiry <= now {
+                    hash.remove(field);
+                    FIELD_DELETED
+                } else {
+                    hash.set_expiry(field, Some(expiry));
+                    FIELD_UPDATED
+                }
+            })
+            .collect();
+        if replies.contains(&FIELD_UPDATED) {
+            db.touch(key);
+            db.notify(notify::HASH, "hexpire", key);
+        }
+        if replies.contains(&FIELD_DELETED) {
+            db.touch(key);
+            db.notify(notify::HASH, "hdel", key);
+        }
+        remove_if_empty(&mut db, key);
+        drop(db);
+
+        self.write_field_replies(replies.into_iter())
+    }
+
+    /// Handle HTTL, HPTTL, HEXPIRETIME and HPEXPIRETIME, which reply with either the remaining
+    /// time to live or the expire time, where `unit` is the length of the unit in milliseconds
+    pub(super) fn handle_httl(
+        &mut self,
+        args: &[RESPData],
+        command: &[u8],
+        unit: u128,
+        absolute: bool,
+    ) -> Result<()> {
+        let command = String::from_utf8_lossy(command);
+        log::debug!("Received {}", command.to_uppercase());
+
+        let args = bulk_strings(args)?;
+        let [key, rest @ ..] = args.as_slice() else {
+            return client_error!("wrong number of arguments for '{}' command", command);
+        };
+        if rest.len() < 3 {
+            return client_error!("wrong number of arguments for '{}' command", command);
+        }
+        let fields = parse_fields(rest, 1)?;
+
+        let mut db = database::lock(0);
+        let Some(hash) = db.get_hash(key)? else {
+            return self.write_field_replies(fields.iter().map(|_| FIELD_MISSING));
+        };
+
+        let now = now();
+        let replies: Vec<i64> = fields
+            .iter()
+            .map(|field| match hash.expiry(field) {
+                _ if !hash.contains(field) => FIELD_MISSING,
+                None => FIELD_HAS_NO_EXPIRY,
+                Some(expiry) => {
+                    let time = if absolute {
+                        expiry
+                    } else {
+                        expiry.saturating_sub(now)
+                    };
+                    // Round to the nearest unit
+                    ((time + unit / 2) / unit) as i64
+                }
+            })
+            .collect();
+        drop(db);
+
+        self.write_field_replies(replies.into_iter())
+    }
+
+    pub(super) fn handle_hpersist(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received HPERSIST");
+
+        let args = bulk_strings(args)?;
+        let [key, rest @ ..] = args.as_slice() else {
+            return client_error!("wrong number of arguments for 'hpersist' command");
+        };
+        if rest.len() < 3 {
+            return client_error!("wrong number of arguments for 'hpersist' command");
+        }
+        let fields = parse_fields(rest, 1)?;
+
+        let mut db = database::lock(0);
+        let Some(hash) = db.get_hash_mut(key)? else {
+            return self.write_field_replies(fields.iter().map(|_| FIELD_MISSING));
+        };
+
+        let replies: Vec<i64> = fields
+            .iter()
+            .map(|field| match hash.expiry(field) {
+                _ if !hash.contains(field) => FIELD_MISSING,
+                None => FIELD_HAS_NO_EXPIRY,
+                Some(_) => {
+                    hash.set_expiry(field, None);
+                    FIELD_UPDATED
+                }
+            })
+            .collect();
+        if replies.contains(&FIELD_UPDATED) {
+            db.touch(key);
+            db.notify(notify::HASH, "hpersist", key);
+        }
+        drop(db);
+
+        self.write_field_replies(replies.into_iter())
+    }
+
+    /// HGETEX key [EX seconds | PX milliseconds | EXAT unix-time-seconds |
+    ///     PXAT unix-time-milliseconds | PERSIST] FIELDS numfields field [field ...]
+    pub(super) fn handle_hgetex(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received HGETEX");
+
+        let args = bulk_strings(args)?;
+        let [key, rest @ ..] = args.as_slice() else {
+            return client_error!("wrong number of arguments for 'hgetex' command");
+        };
+        if rest.len() < 3 {
+            return client_error!("wrong number of arguments for 'hgetex' command");
+        }
+
+        let mut change = None;
+        let mut iter = rest.iter();
+        let mut rest = rest;
+        while let Some(option) = iter.next() {
+            if option.eq_ignore_ascii_case(b"FIELDS") {
+                break;
+            }
+            let new = if option.eq_ignore_ascii_case(b"PERSIST") {
+                ExpiryChange::Persist
+            } else if let Some(expiry) = parse_expiry_option(option, &mut iter, "hgetex")? {
+                ExpiryChange::At(expiry)
+            } else {
+                return client_error!("syntax error");
+            };
+            if change.replace(new).is_some() {
+                return client_error!(
+                    "Only one of EX, PX, EXAT, PXAT or PERSIST arguments can be specified"
+                );
+            }
+            rest = iter.as_slice();
+        }
+        let fields = parse_fields(rest, 1)?;
+        let change = change.unwrap_or(ExpiryChange::Keep);
+
+        let mut db = database::lock(0);
+        let Some(hash) = db.get_hash_mut(key)? else {
+            return self.write_resp(&RESPData::Array(
+                fields.iter().map(|_| RESPData::Null).collect(),
+            ));
+        };
+
+        let values: Vec<Option<Vec<u8>>> = fields
+            .iter()
+            .map(|field| hash.get(field).cloned())
+            .collect();
+        let now = now();
+        let mut changed = false;
+        for field in fields {
+            changed |= change_field_expiry(hash, field, change, now);
+        }
+        if changed {
+            db.touch(key);
+            if let Some(event) = expiry_change_event(change, now) {
+                db.notify(notify::HASH, event, key);
+            }
+        }
+        remove_if_empty(&mut db, key);
+        drop(db);
+
+        self.write_resp(&RESPData::Array(
+            values
+                .iter()
+                .map(|value| match value {
+                    Some(value) => RESPData::BulkString(value),
+                    None => RESPData::Null,
+                })
+                .collect(),
+        ))
+    }
+
+    /// HSETEX key [FNX | FXX] [EX seconds | PX milliseconds | EXAT unix-time-seconds |
+    ///     PXAT unix-time-milliseconds | KEEPTTL] FIELDS numfields field value [field value ...]
+    ///
+    /// Fields that are set lose their expiry, unless a new one is given or KEEPTTL is used
+    pub(super) fn handle_hsetex(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received HSETEX");
+
+        let args = bulk_strings(args)?;
+        let [key, rest @ ..] = args.as_slice() else {
+            return client_error!("wrong number of arguments for 'hsetex' command");
+        };
+        if rest.len() < 4 {
+            return client_error!("wrong number of arguments for 'hsetex' command");
+        }
+
+        // Whether all fields must exist (FXX) or none of them (FNX)
+        let mut must_exist = None;
+        let mut change = None;
+        let mut iter = rest.iter();
+        let mut rest = rest;
+        while let Some(option) = iter.next() {
+            if option.eq_ignore_ascii_case(b"FIELDS") {
+                break;
+            }
+            if option.eq_ignore_ascii_case(b"FNX") || option.eq_ignore_ascii_case(b"FXX") {
+                if must_exist
+                    .replace(option.eq_ignore_ascii_case(b"FXX"))
+                    .is_some()
+                {
+                    return client_error!("Only one of FXX or FNX arguments can be specified");
+                }
+            } else {
+                let new = if option.eq_ignore_ascii_case(b"KEEPTTL") {
+                    ExpiryChange::Keep
+                } else if let Some(expiry) = parse_expiry_option(option, &mut iter, "hsetex")? {
+                    ExpiryChange::At(expiry)
+                } else {
+                    return client_error!("syntax error");
+                };
+                if change.replace(new).is_some() {
+                    return client_error!(
+                        "Only one of EX, PX, EXAT, PXAT or KEEPTTL arguments can be specified"
+                    );
+                }
+            }
+            rest = iter.as_slice();
+        }
+        let pairs = parse_fields(rest, 2)?;
+        let change = change.unwrap_or(ExpiryChange::Persist);
+
+        let limits = self.hash_limits();
+        let mut db = database::lock(0);
+        if let Some(must_exist) = must_exist {
+            let hash = db.get_hash_mut(key)?.map(|hash| &*hash);
+            let all_match = pairs
+                .chunks_exact(2)
+                .all(|pair| hash.is_some_and(|hash| hash.contains(pair[0])) == must_exist);
+            if !all_match {
+                drop(db);
+                return self.write_integer(0);
+            }
+        }
+
+        let hash = get_or_create_hash(&mut db, key)?;
+        let now = now();
+        for pair in pairs.chunks_exact(2) {
+            hash.insert_keep_expiry(pair[0].to_vec(), pair[1].to_vec(), &limits);
+            change_field_expiry(hash, pair[0], change, now);
+        }
+        db.touch(key);
+        db.notify(notify::HASH, "hset", key);
+        // Dropping the expiry of the fields that are set is part of setting them
+        if let Some(event) =
+            expiry_change_event(change, now).filter(|_| change != ExpiryChange::Persist)
+        {
+            db.notify(notify::HASH, event, key);
+        }
+        remove_if_empty(&mut db, key);
+        drop(db);
+
+        self.write_integer(1)
+    }
+
+    /// Write the per field integer replies of the field expiry commands
+    fn write_field_replies(&mut self, replies: impl Iterator<Item = i64>) -> Result<()> {
+        self.write_resp(&RESPData::Array(replies.map(RESPData::Integer).collect()))
+    }
+}
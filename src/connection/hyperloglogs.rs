@@ -0,0 +1,223 @@
+use super::{bulk_strings, Connection, OK};
+use crate::{
+    database::{
+        self,
+        hyperloglog::{self, Corrupted, Encoding},
+        DbHandle, StringValue, Value,
+    },
+    error::RustisError,
+    resp::RESPData,
+    Result,
+};
+use std::io::Write;
+
+fn not_a_hyperloglog<T>() -> Result<T> {
+    Err(RustisError::ClientErrorWithCode(
+        "WRONGTYPE",
+        "Key is not a valid HyperLogLog string value.".to_string(),
+    ))
+}
+
+impl From<Corrupted> for RustisError {
+    fn from(_: Corrupted) -> Self {
+        RustisError::ClientErrorWithCode("INVALIDOBJ", "Corrupted HLL object detected".to_string())
+    }
+}
+
+/// Look up a HyperLogLog, returning an error if the key holds a string that isn't one
+fn get_hyperloglog<'a>(db: &'a mut DbHandle, key: &[u8]) -> Result<Option<&'a [u8]>> {
+    match db.get_string(key)? {
+        Some(StringValue::Raw(bytes)) if hyperloglog::is_valid(bytes) => Ok(Some(bytes)),
+        Some(_) => not_a_hyperloglog(),
+        None => Ok(None),
+    }
+}
+
+/// Look up a HyperLogLog for modification, returning an error if the key holds a string that isn't
+/// one
+fn get_hyperloglog_mut<'a>(db: &'a mut DbHandle, key: &[u8]) -> Result<Option<&'a mut Vec<u8>>> {
+    match db.get_string_mut(key)? {
+        Some(StringValue::Raw(bytes)) if hyperloglog::is_valid(bytes) => Ok(Some(bytes)),
+        Some(_) => not_a_hyperloglog(),
+        None => Ok(None),
+    }
+}
+
+/// Look up a HyperLogLog for modification, creating an empty one if the key doesn't exist
+///
+/// Also returns whether it was created.
+fn get_or_create_hyperloglog<'a>(
+    db: &'a mut DbHandle,
+    key: &[u8],
+) -> Result<(&'a mut Vec<u8>, bool)> {
+    let created = get_hyperloglog_mut(db, key)?.is_none();
+    if created {
+        db.insert(key, Value::String(StringValue::Raw(hyperloglog::new())));
+    }
+    Ok((get_hyperloglog_mut(db, key)?.unwrap(), created))
+}
+
+impl Connection {
+    /// The maximum size of a sparse HyperLogLog, from the config
+    fn hll_sparse_max_bytes(&self) -> usize {
+        self.config.borrow().hll_sparse_max_bytes
+    }
+
+    pub(super) fn handle_pfadd(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received PFADD");
+
+        let args = bulk_strings(args)?;
+        let Some((key, elements)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'pfadd' command");
+        };
+
+        let sparse_max_bytes = self.hll_sparse_max_bytes();
+        let mut db = database::lock(0);
+        let (hll, mut updated) = get_or_create_hyperloglog(&mut db, key)?;
+        for element in elements {
+            updated |= hyperloglog::add(hll, element, sparse_max_bytes)?;
+        }
+        if updated {
+            hyperloglog::invalidate_cache(hll);
+            db.touch(key);
+        }
+        drop(db);
+
+        self.write_integer(updated as i64)
+    }
+
+    pub(super) fn handle_pfcount(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received PFCOUNT");
+
+        let keys = bulk_strings(args)?;
+        if keys.is_empty() {
+            return client_error!("wrong number of arguments for 'pfcount' command");
+        }
+
+        let mut db = database::lock(0);
+        let count = if let [key] = keys[..] {
+            // The cardinality of a single HyperLogLog is cached in its header
+            match get_hyperloglog_mut(&mut db, key)? {
+                Some(hll) => match hyperloglog::cached_count(hll) {
+                    Some(count) => count,
+                    None => {
+                        let count = hyperloglog::count(hll)?;
+                        hyperloglog::set_cached_count(hll, count);
+                        count
+                    }
+                },
+                None => 0,
+            }
+        } else {
+            let mut registers = vec![0; hyperloglog::REGISTERS];
+            for key in keys {
+                if let Some(hll) = get_hyperloglog(&mut db, key)? {
+                    hyperloglog::merge(&mut registers, hll)?;
+                }
+            }
+            hyperloglog::count_registers(&registers)
+        };
+        drop(db);
+
+        self.write_integer(count as i64)
+    }
+
+    pub(super) fn handle_pfmerge(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received PFMERGE");
+
+        let args = bulk_strings(args)?;
+        let Some(destination) = args.first() else {
+            return client_error!("wrong number of arguments for 'pfmerge' command");
+        };
+
+        let sparse_max_bytes = self.hll_sparse_max_bytes();
+        let mut db = database::lock(0);
+        // The destination is merged too, if it exists
+        let mut registers = vec![0; hyperloglog::REGISTERS];
+        let mut dense = false;
+        for key in &args {
+            if let Some(hll) = get_hyperloglog(&mut db, key)? {
+                dense |= hyperloglog::encoding(hll) == Encoding::Dense;
+                hyperloglog::merge(&mut registers, hll)?;
+            }
+        }
+        let (hll, _) = get_or_create_hyperloglog(&mut db, destination)?;
+        hyperloglog::set_registers(hll, &registers, dense, sparse_max_bytes)?;
+        db.touch(destination);
+        drop(db);
+
+        self.stream.write_all(OK)?;
+        Ok(())
+    }
+
+    pub(super) fn handle_pfdebug(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received PFDEBUG");
+
+        let args = bulk_strings(args)?;
+        let [subcommand, key] = args[..] else {
+            return client_error!("wrong number of arguments for 'pfdebug' command");
+        };
+
+        let mut db = database::lock(0);
+        let Some(hll) = get_hyperloglog_mut(&mut db, key)? else {
+            return client_error!("The specified key does not exist");
+        };
+
+        match subcommand.to_ascii_uppercase().as_slice() {
+            b"GETREG" => {
+                hyperloglog::to_dense(hll)?;
+                let registers = hyperloglog::registers(hll)?;
+                drop(db);
+
+                let mut buf = Vec::new();
+                write!(buf, "*{}\r\n", registers.len())?;
+                for register in registers {
+                    RESPData::Integer(register as i64).encode(&mut buf);
+                }
+                self.stream.write_all(&buf)?;
+                Ok(())
+            }
+            b"DECODE" => {
+                if hyperloglog::encoding(hll) != Encoding::Sparse {
+                    return client_error!("HLL encoding is not sparse");
+                }
+                let decoded = hyperloglog::decode_sparse(hll)?;
+                drop(db);
+                self.write_bulk_string(decoded.as_bytes())
+            }
+            b"ENCODING" => {
+                let encoding = match hyperloglog::encoding(hll) {
+                    Encoding::Dense => "dense",
+                    Encoding::Sparse => "sparse",
+                };
+                drop(db);
+                self.write_resp(&RESPData::SimpleString(encoding.as_bytes()))
+            }
+            b"TODENSE" => {
+                let converted = hyperloglog::to_dense(hll)?;
+                drop(db);
+                self.write_integer(converted as i64)
+            }
+            _ => client_error!(
+                "Unknown PFDEBUG subcommand '{}'",
+                String::from_utf8_lossy(subcommand)
+            ),
+        }
+    }
+
+    pub(super) fn handle_pfselftest(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received PFSELFTEST");
+
+        if !args.is_empty() {
+            return client_error!("wrong number of arguments for 'pfselftest' command");
+        }
+
+        match hyperloglog::self_test(self.hll_sparse_max_bytes()) {
+            Ok(()) => {
+                self.stream.write_all(OK)?;
+                Ok(())
+            }
+            Err(message) => Err(RustisError::ClientErrorWithCode("TESTFAILED", message)),
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use super::{bulk_strings, parse_int, Connection, OK};
+use crate::{
+    database::{self, now},
+    error::RustisError,
+    notify,
+    resp::RESPData,
+    Result,
+};
+use std::io::Write;
+
+impl Connection {
+    /// Handle DEL and UNLINK, which remove keys of any type
+    pub(super) fn handle_del(&mut self, args: &[RESPData], command: &str) -> Result<()> {
+        log::debug!("Received {}", command.to_uppercase());
+
+        let keys = bulk_strings(args)?;
+        if keys.is_empty() {
+            return client_error!("wrong number of arguments for '{}' command", command);
+        }
+
+        let mut db = database::lock(0);
+        let mut deleted = 0;
+        for key in keys {
+            if db.remove(key).is_some() {
+                db.notify(notify::GENERIC, "del", key);
+                deleted += 1;
+            }
+        }
+        drop(db);
+
+        self.write_integer(deleted)
+    }
+
+    /// Handle EXPIRE, PEXPIRE, EXPIREAT and PEXPIREAT, which set the expiry of a key
+    ///
+    /// The unit is how many milliseconds the given time is in, and the time is relative to now
+    /// unless it's a unix time. A time in the past deletes the key right away.
+    pub(super) fn handle_expire(
+        &mut self,
+        args: &[RESPData],
+        command: &str,
+        unit: i128,
+        relative: bool,
+    ) -> Result<()> {
+        log::debug!("Received {}", command.to_uppercase());
+
+        let args = bulk_strings(args)?;
+        let [key, time, options @ ..] = args.as_slice() else {
+            return client_error!("wrong number of arguments for '{}' command", command);
+        };
+        let (mut nx, mut xx, mut gt, mut lt) = (false, false, false, false);
+        for option in options {
+            match option.to_ascii_uppercase().as_slice() {
+                b"NX" => nx = true,
+                b"XX" => xx = true,
+                b"GT" => gt = true,
+                b"LT" => lt = true,
+                _ => {
+                    return client_error!("Unsupported option {}", String::from_utf8_lossy(option))
+                }
+            }
+        }
+        if nx && (xx || gt || lt) {
+            return client_error!(
+                "NX and XX, GT or LT options at the same time are not compatible"
+            );
+        }
+        if gt && lt {
+            return client_error!("GT and LT options at the same time are not compatible");
+        }
+
+        let time: i64 = parse_int(time)?;
+        let base = if relative { now() as i128 } else { 0 };
+        let expiry = (time as i128)
+            .checked_mul(unit)
+            .and_then(|time| time.checked_add(base))
+            .filter(|&expiry| expiry <= i64::MAX as i128);
+        let Some(expiry) = expiry else {
+            return client_error!("invalid expire time in '{}' command", command);
+        };
+
+        let mut db = database::lock(0);
+        if !db.contains_key(key) {
+            drop(db);
+            return self.write_integer(0);
+        }
+        // Keys without an expiry never expire, so they're treated as having an infinite TTL
+        let current = db.expiry(key).map(|expiry| expiry as i128);
+        let allowed = (!nx || current.is_none())
+            && (!xx || current.is_some())
+            && (!gt || current.is_some_and(|current| expiry > current))
+            && (!lt || current.is_none_or(|current| expiry < current));
+        if !allowed {
+            drop(db);
+            return self.write_integer(0);
+        }
+
+        if expiry <= now() as i128 {
+            db.remove(key);
+            db.notify(notify::GENERIC, "del", key);
+        } else {
+            db.set_expiry(key, Some(expiry as u128));
+            db.notify(notify::GENERIC, "expire", key);
+        }
+        drop(db);
+
+        self.write_integer(1)
+    }
+
+    /// Handle TTL and PTTL, which reply with how long a key has left to live, in the unit given in
+    /// milliseconds, or -1 if it has no expiry and -2 if it doesn't exist
+    pub(super) fn handle_ttl(
+        &mut self,
+        args: &[RESPData],
+        command: &str,
+        unit: u128,
+    ) -> Result<()> {
+        log::debug!("Received {}", command.to_uppercase());
+
+        let [RESPData::BulkString(key)] = args else {
+            return client_error!("wrong number of arguments for '{}' command", command);
+        };
+
+        let mut db = database::lock(0);
+        let ttl = if !db.contains_key(key) {
+            -2
+        } else {
+            match db.expiry(key) {
+                Some(expiry) => {
+                    let remaining = expiry.saturating_sub(now());
+                    // Round to the nearest unit, like Redis does
+                    ((remaining + unit / 2) / unit) as i64
+                }
+                None => -1,
+            }
+        };
+        drop(db);
+
+        self.write_integer(ttl)
+    }
+
+    /// Handle PERSIST, which removes the expiry of a key
+    pub(super) fn handle_persist(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received PERSIST");
+
+        let [RESPData::BulkString(key)] = args else {
+            return client_error!("wrong number of arguments for 'persist' command");
+        };
+
+        let mut db = database::lock(0);
+        let persisted = db.expiry(key).is_some();
+        if persisted {
+            db.set_expiry(key, None);
+            db.notify(notify::GENERIC, "persist", key);
+        }
+        drop(db);
+
+        self.write_integer(persisted as i64)
+    }
+
+    /// Handle FLUSHDB and FLUSHALL, which remove every key from the database or from all of them
+    ///
+    /// Flushing is always synchronous, so ASYNC is accepted but makes no difference.
+    pub(super) fn handle_flush(&mut self, args: &[RESPData], command: &str) -> Result<()> {
+        log::debug!("Received {}", command.to_uppercase());
+
+        match args {
+            [] => {}
+            [RESPData::BulkString(mode)]
+                if mode.eq_ignore_ascii_case(b"ASYNC") || mode.eq_ignore_ascii_case(b"SYNC") => {}
+            _ => return client_error!("syntax error"),
+        }
+
+        if command == "flushall" {
+            database::flush_all();
+        } else {
+            database::lock(0).flush();
+        }
+
+        self.stream.write_all(OK)?;
+        Ok(())
+    }
+
+    /// Handle SWAPDB, which swaps the keys of two databases
+    pub(super) fn handle_swapdb(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received SWAPDB");
+
+        let [RESPData::BulkString(first), RESPData::BulkString(second)] = args else {
+            return client_error!("wrong number of arguments for 'swapdb' command");
+        };
+        let Ok(first) = parse_int::<i64>(first) else {
+            return client_error!("invalid first DB index");
+        };
+        let Ok(second) = parse_int::<i64>(second) else {
+            return client_error!("invalid second DB index");
+        };
+        let (Ok(first), Ok(second)) = (usize::try_from(first), usize::try_from(second)) else {
+            return client_error!("DB index is out of range");
+        };
+
+        if !database::lock(first).swap(second) {
+            return client_error!("DB index is out of range");
+        }
+
+        self.stream.write_all(OK)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,735 @@
+use super::{
+    blocking::BlockedCommand, bulk_strings, parse_int, resolve_range, Connection, EMPTY_ARRAY,
+    NULL, NULL_ARRAY, OK,
+};
+use crate::{
+    database::{self, DbHandle, List, Value},
+    error::RustisError,
+    notify,
+    resp::RESPData,
+    Result,
+};
+use std::{
+    io::Write,
+    time::{Duration, Instant},
+};
+
+/// Parse a LEFT or RIGHT argument, returning true for LEFT
+fn parse_end(raw: &[u8]) -> Result<bool> {
+    match raw.to_ascii_uppercase().as_slice() {
+        b"LEFT" => Ok(true),
+        b"RIGHT" => Ok(false),
+        _ => client_error!("syntax error"),
+    }
+}
+
+/// Parse a count argument for the pop commands, which can't be negative
+fn parse_count(raw: &[u8]) -> Result<usize> {
+    let count: i64 = parse_int(raw)?;
+    if count < 0 {
+        return client_error!("value is out of range, must be positive");
+    }
+    Ok(count as usize)
+}
+
+/// Parse the timeout of a blocking command in seconds, returning the deadline it results in
+///
+/// A timeout of zero means blocking forever, which has no deadline
+pub(super) fn parse_timeout(raw: &[u8]) -> Result<Option<Instant>> {
+    let Some(timeout) = std::str::from_utf8(raw)
+        .ok()
+        .and_then(|s| s.parse::<f64>().ok())
+        .filter(|t| t.is_finite())
+    else {
+        return client_error!("timeout is not a float or out of range");
+    };
+    if timeout < 0.0 {
+        return client_error!("timeout is negative");
+    }
+    if timeout == 0.0 {
+        return Ok(None);
+    }
+    match Duration::try_from_secs_f64(timeout) {
+        Ok(timeout) => Ok(Instant::now().checked_add(timeout)),
+        Err(_) => client_error!("timeout is out of range"),
+    }
+}
+
+/// Remove a key if it holds a list that has become empty, as empty lists are never kept around
+fn remove_if_empty(db: &mut DbHandle, key: &[u8]) {
+    if matches!(db.get_mut(key), Some(Value::List(list)) if list.is_empty()) {
+        db.remove(key);
+        db.notify(notify::GENERIC, "del", key);
+    }
+}
+
+/// Push elements to the head or tail of a list, creating it if it doesn't exist
+///
+/// Returns the length of the list after the push
+fn push_elements(db: &mut DbHandle, key: &[u8], elements: &[&[u8]], left: bool) -> Result<usize> {
+    if db.get_list_mut(key)?.is_none() {
+        db.insert(key, Value::List(List::new()));
+    }
+    let list = db.get_list_mut(key)?.unwrap();
+    for element in elements {
+        if left {
+            list.push_front(element.to_vec());
+        } else {
+            list.push_back(element.to_vec());
+        }
+    }
+    let len = list.len();
+    db.touch(key);
+    db.notify(notify::LIST, if left { "lpush" } else { "rpush" }, key);
+    Ok(len)
+}
+
+/// Pop up to count elements from the head or tail of a list, removing the key if it ends up empty
+///
+/// Returns `None` if the key does not exist
+pub(super) fn pop_elements(
+    db: &mut DbHandle,
+    key: &[u8],
+    left: bool,
+    count: usize,
+) -> Result<Option<Vec<Vec<u8>>>> {
+    let Some(list) = db.get_list_mut(key)? else {
+        return Ok(None);
+    };
+    let mut elements = Vec::with_capacity(count.min(list.len()));
+    while elements.len() < count {
+        let element = if left {
+            list.pop_front()
+        } else {
+            list.pop_back()
+        };
+        match element {
+            Some(element) => elements.push(element),
+            None => break,
+        }
+    }
+    if !elements.is_empty() {
+        db.touch(key);
+        db.notify(notify::LIST, if left { "lpop" } else { "rpop" }, key);
+    }
+    remove_if_empty(db, key);
+    Ok(Some(elements))
+}
+
+/// Pop an element from the source list and push it to the destination list
+///
+/// The destination is type checked before anything is popped, so a WRONGTYPE error never loses
+/// the element. Returns `None` if the source does not exist.
+pub(super) fn move_element(
+    db: &mut DbHandle,
+    source: &[u8],
+    destination: &[u8],
+    from_left: bool,
+    to_left: bool,
+) -> Result<Option<Vec<u8>>> {
+    if db.get_list_mut(source)?.is_none() {
+        return Ok(None);
+    }
+    db.get_list_mut(destination)?;
+
+    let list = db.get_list_mut(source)?.unwrap();
+    let element = if from_left {
+        list.pop_front()
+    } else {
+        list.pop_back()
+    }
+    .unwrap();
+    db.touch(source);
+    db.notify(
+        notify::LIST,
+        if from_left { "lpop" } else { "rpop" },
+        source,
+    );
+
+    // The source is only cleaned up after the push, so rotating a single element list in place
+    // doesn't delete the key in between
+    push_elements(db, destination, &[&element], to_left)?;
+    remove_if_empty(db, source);
+
+    Ok(Some(element))
+}
+
+impl Connection {
+    /// Handle LPUSH, RPUSH, LPUSHX and RPUSHX, where the X variants only push to existing lists
+    pub(super) fn handle_push(
+        &mut self,
+        args: &[RESPData],
+        command: &[u8],
+        left: bool,
+        only_existing: bool,
+    ) -> Result<()> {
+        let command = String::from_utf8_lossy(command);
+        log::debug!("Received {}", command.to_uppercase());
+
+        let args = bulk_strings(args)?;
+        let Some((key, elements)) = args.split_first() else {
+            return client_error!("wrong number of arguments for '{}' command", command);
+        };
+        if elements.is_empty() {
+            return client_error!("wrong number of arguments for '{}' command", command);
+        }
+
+        let mut db = database::lock(0);
+
+        if only_existing && db.get_list_mut(key)?.is_none() {
+            return self.write_integer(0);
+        }
+        let length = push_elements(&mut db, key, elements, left)?;
+        self.signal_key_ready(key);
+
+        self.write_integer(length as i64)
+    }
+
+    /// Handle LPOP and RPOP
+    ///
+    /// Without a count a single element is returned, with a count an array of elements is
+    /// returned, or a null array if the key doesn't exist
+    pub(super) fn handle_pop(
+        &mut self,
+        args: &[RESPData],
+        command: &[u8],
+        left: bool,
+    ) -> Result<()> {
+        let command = String::from_utf8_lossy(command);
+        log::debug!("Received {}", command.to_uppercase());
+
+        let (key, count) = match args {
+            [RESPData::BulkString(key)] => (key, None),
+            [RESPData::BulkString(key), RESPData::BulkString(count)] => {
+                (key, Some(parse_count(count)?))
+            }
+            _ => return client_error!("wrong number of arguments for '{}' command", command),
+        };
+
+        let popped = pop_elements(&mut database::lock(0), key, left, count.unwrap_or(1))?;
+
+        match (popped, count) {
+            (None, None) => self.stream.write_all(NULL)?,
+            (None, Some(_)) => self.stream.write_all(NULL_ARRAY)?,
+            (Some(elements), None) => self.write_bulk_string(&elements[0])?,
+            (Some(elements), Some(_)) => {
+                self.write_array(elements.iter().map(|e| e.as_slice()).collect())?
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn handle_llen(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received LLEN");
+
+        let [RESPData::BulkString(key)] = args else {
+            return client_error!("wrong number of arguments for 'llen' command");
+        };
+
+        let length = database::lock(0)
+            .get_list(key)?
+            .map_or(0, |list| list.len());
+
+        self.write_integer(length as i64)
+    }
+
+    pub(super) fn handle_lrange(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received LRANGE");
+
+        let [RESPData::BulkString(key), RESPData::BulkString(start), RESPData::BulkString(end)] =
+            args
+        else {
+            return client_error!("wrong number of arguments for 'lrange' command");
+        };
+
+        let start: i64 = parse_int(start)?;
+        let end: i64 = parse_int(end)?;
+
+        let mut db = database::lock(0);
+        let Some(list) = db.get_list(key)? else {
+            self.stream.write_all(EMPTY_ARRAY)?;
+            return Ok(());
+        };
+
+        let range = resolve_range(list.len(), start, end);
+        let elements = list
+            .iter()
+            .skip(range.start)
+            .take(range.len())
+            .map(|e| e.as_slice())
+            .collect();
+
+        self.write_array(elements)
+    }
+
+    pub(super) fn handle_lindex(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received LINDEX");
+
+        let [RESPData::BulkString(key), RESPData::BulkString(index)] = args else {
+            return client_error!("wrong number of arguments for 'lindex' command");
+        };
+
+        let index: i64 = parse_int(index)?;
+
+        let mut db = database::lock(0);
+        let element = db
+            .get_list(key)?
+            .and_then(|list| resolve_index(list.len(), index).and_then(|i| list.get(i)));
+
+        self.write_optional_bulk_string(element.map(|e| e.as_slice()))
+    }
+
+    pub(super) fn handle_lset(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received LSET");
+
+        let [RESPData::BulkString(key), RESPData::BulkString(index), RESPData::BulkString(element)] =
+            args
+        else {
+            return client_error!("wrong number of arguments for 'lset' command");
+        };
+
+        let index: i64 = parse_int(index)?;
+
+        let mut db = database::lock(0);
+        let Some(list) = db.get_list_mut(key)? else {
+            return client_error!("no such key");
+        };
+        let Some(current) = resolve_index(list.len(), index).and_then(|i| list.get_mut(i)) else {
+            return client_error!("index out of range");
+        };
+        *current = element.to_vec();
+        db.touch(key);
+        db.notify(notify::LIST, "lset", key);
+
+        self.stream.write_all(OK)?;
+
+        Ok(())
+    }
+
+    pub(super) fn handle_linsert(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received LINSERT");
+
+        let [RESPData::BulkString(key), RESPData::BulkString(position), RESPData::BulkString(pivot), RESPData::BulkString(element)] =
+            args
+        else {
+            return client_error!("wrong number of arguments for 'linsert' command");
+        };
+
+        let after = match position.to_ascii_uppercase().as_slice() {
+            b"BEFORE" => false,
+            b"AFTER" => true,
+            _ => return client_error!("syntax error"),
+        };
+
+        let mut db = database::lock(0);
+        let Some(list) = db.get_list_mut(key)? else {
+            return self.write_integer(0);
+        };
+        let Some(index) = list.iter().position(|e| e == pivot) else {
+            return self.write_integer(-1);
+        };
+        list.insert(index + after as usize, element.to_vec());
+        let length = list.len();
+        db.touch(key);
+        db.notify(notify::LIST, "linsert", key);
+
+        self.write_integer(length as i64)
+    }
+
+    pub(super) fn handle_lrem(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received LREM");
+
+        let [RESPData::BulkString(key), RESPData::BulkString(count), RESPData::BulkString(element)] =
+            args
+        else {
+            return client_error!("wrong number of arguments for 'lrem' command");
+        };
+
+        let count: i64 = parse_int(count)?;
+
+        let mut db = database::lock(0);
+        let Some(list) = db.get_list_mut(key)? else {
+            return self.write_integer(0);
+        };
+        let removed = list.remove_matching(element, count);
+        if removed > 0 {
+            db.touch(key);
+            db.notify(notify::LIST, "lrem", key);
+        }
+        remove_if_empty(&mut db, key);
+
+        self.write_integer(removed as i64)
+    }
+
+    pub(super) fn handle_ltrim(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received LTRIM");
+
+        let [RESPData::BulkString(key), RESPData::BulkString(start), RESPData::BulkString(end)] =
+            args
+        else {
+            return client_error!("wrong number of arguments for 'ltrim' command");
+        };
+
+        let start: i64 = parse_int(start)?;
+        let end: i64 = parse_int(end)?;
+
+        let mut db = database::lock(0);
+        if let Some(list) = db.get_list_mut(key)? {
+            let range = resolve_range(list.len(), start, end);
+            let len = list.len();
+            list.retain_range(range.start, range.end);
+            if list.len() != len {
+                db.touch(key);
+            }
+            db.notify(notify::LIST, "ltrim", key);
+            remove_if_empty(&mut db, key);
+        }
+
+        self.stream.write_all(OK)?;
+
+        Ok(())
+    }
+
+    pub(super) fn handle_lmove(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received LMOVE");
+
+        let [RESPData::BulkString(source), RESPData::BulkString(destination), RESPData::BulkString(from), RESPData::BulkString(to)] =
+            args
+        else {
+            return client_error!("wrong number of arguments for 'lmove' command");
+        };
+
+        let from_left = parse_end(from)?;
+        let to_left = parse_end(to)?;
+
+        let element = move_element(
+            &mut database::lock(0),
+            source,
+            destination,
+            from_left,
+            to_left,
+        )?;
+        if element.is_some() {
+            self.signal_key_ready(destination);
+        }
+
+        self.write_optional_bulk_string(element.as_deref())
+    }
+
+    pub(super) fn handle_rpoplpush(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received RPOPLPUSH");
+
+        let [RESPData::BulkString(source), RESPData::BulkString(destination)] = args else {
+            return client_error!("wrong number of arguments for 'rpoplpush' command");
+        };
+
+        let element = move_element(&mut database::lock(0), source, destination, false, true)?;
+        if element.is_some() {
+            self.signal_key_ready(destination);
+        }
+
+        self.write_optional_bulk_string(element.as_deref())
+    }
+
+    pub(super) fn handle_lpos(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received LPOS");
+
+        let Some((RESPData::BulkString(key), args)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'lpos' command");
+        };
+        let Some((RESPData::BulkString(element), args)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'lpos' command");
+        };
+
+        let mut rank: i64 = 1;
+        let mut count: Option<usize> = None;
+        let mut max_len: usize = 0;
+
+        let options = bulk_strings(args)?;
+        let mut iter = options.iter();
+        while let Some(option) = iter.next() {
+            let Some(value) = iter.next() else {
+                return client_error!("syntax error");
+            };
+            match option.to_ascii_uppercase().as_slice() {
+                b"RANK" => {
+                    rank = parse_int(value)?;
+                    if rank == 0 {
+                        return client_error!(
+                            "RANK can't be zero: use 1 to start from the first match, 2 from the second ... or use negative to start from the last match"
+                        );
+                    }
+                    if rank == i64::MIN {
+                        return client_error!("value is out of range");
+                    }
+                }
+                b"COUNT" => {
+                    let value: i64 = parse_int(value)?;
+                    if value < 0 {
+                        return client_error!("COUNT can't be negative");
+                    }
+                    count = Some(value as usize);
+                }
+                b"MAXLEN" => {
+                    let value: i64 = parse_int(value)?;
+                    if value < 0 {
+                        return client_error!("MAXLEN can't be negative");
+                    }
+                    max_len = value as usize;
+                }
+                _ => return client_error!("syntax error"),
+            }
+        }
+
+        let matches: Vec<i64> = {
+            let mut db = database::lock(0);
+            match db.get_list(key)? {
+                Some(list) => {
+                    // A count of zero means all matches, and a max length of zero means the whole
+                    // list is scanned
+                    let wanted = match count {
+                        Some(0) => usize::MAX,
+                        Some(count) => count,
+                        None => 1,
+                    };
+                    let scanned = if max_len == 0 { list.len() } else { max_len };
+                    let skip = (rank.unsigned_abs() - 1) as usize;
+                    let len = list.len();
+
+                    // Scanning from the tail is done over the reversed list, mapping the indexes
+                    // back to positions from the head
+                    let scan: Box<dyn Iterator<Item = (usize, &Vec<u8>)>> = if rank > 0 {
+                        Box::new(list.iter().enumerate())
+                    } else {
+                        Box::new(list.iter().rev().enumerate().map(|(i, e)| (len - 1 - i, e)))
+                    };
+                    scan.take(scanned)
+                        .filter(|(_, e)| *e == element)
+                        .skip(skip)
+                        .take(wanted)
+                        .map(|(i, _)| i as i64)
+                        .collect()
+                }
+                None => Vec::new(),
+            }
+        };
+
+        match count {
+            Some(_) => self.write_resp(&RESPData::Array(
+                matches.into_iter().map(RESPData::Integer).collect(),
+            )),
+            None => match matches.first() {
+                Some(&index) => self.write_integer(index),
+                None => {
+                    self.stream.write_all(NULL)?;
+                    Ok(())
+                }
+            },
+        }
+    }
+
+    /// Write the reply for elements popped from a key
+    ///
+    /// LMPOP and BLMPOP reply with the key and an array of the elements, while BLPOP and BRPOP
+    /// reply with the key and the single element
+    pub(super) fn write_popped(
+        &mut self,
+        key: &[u8],
+        elements: &[Vec<u8>],
+        multiple: bool,
+    ) -> Result<()> {
+        let reply = if multiple {
+            RESPData::Array(vec![
+                RESPData::BulkString(key),
+                RESPData::Array(elements.iter().map(|e| RESPData::BulkString(e)).collect()),
+            ])
+        } else {
+            RESPData::Array(vec![
+                RESPData::BulkString(key),
+                RESPData::BulkString(&elements[0]),
+            ])
+        };
+        self.write_resp(&reply)
+    }
+
+    /// Handle BLPOP and BRPOP, which pop from the first non-empty list or block until one of the
+    /// lists is pushed to
+    pub(super) fn handle_blpop(
+        &mut self,
+        args: &[RESPData],
+        command: &[u8],
+        left: bool,
+    ) -> Result<()> {
+        let command = String::from_utf8_lossy(command);
+        log::debug!("Received {}", command.to_uppercase());
+
+        let args = bulk_strings(args)?;
+        let Some((timeout, keys)) = args.split_last() else {
+            return client_error!("wrong number of arguments for '{}' command", command);
+        };
+        if keys.is_empty() {
+            return client_error!("wrong number of arguments for '{}' command", command);
+        }
+        let deadline = parse_timeout(timeout)?;
+
+        let mut db = database::lock(0);
+        for key in keys {
+            if let Some(elements) = pop_elements(&mut db, key, left, 1)? {
+                drop(db);
+                return self.write_popped(key, &elements, false);
+            }
+        }
+        drop(db);
+
+        let keys: Vec<Vec<u8>> = keys.iter().map(|k| k.to_vec()).collect();
+        self.block(&keys, deadline, BlockedCommand::Pop { left, count: None });
+
+        Ok(())
+    }
+
+    /// Handle LMPOP and BLMPOP, which pop up to count elements from the first non-empty list
+    ///
+    /// BLMPOP takes a timeout before the keys, and blocks until one of the lists is pushed to
+    pub(super) fn handle_lmpop(&mut self, args: &[RESPData], blocking: bool) -> Result<()> {
+        let command = if blocking { "blmpop" } else { "lmpop" };
+        log::debug!("Received {}", command.to_uppercase());
+
+        let args = bulk_strings(args)?;
+        let (deadline, args) = if blocking {
+            let Some((timeout, args)) = args.split_first() else {
+                return client_error!("wrong number of arguments for '{}' command", command);
+            };
+            (parse_timeout(timeout)?, args)
+        } else {
+            (None, &args[..])
+        };
+
+        let Some((numkeys, args)) = args.split_first() else {
+            return client_error!("wrong number of arguments for '{}' command", command);
+        };
+        let numkeys: i64 = parse_int(numkeys)?;
+        if numkeys <= 0 {
+            return client_error!("numkeys should be greater than 0");
+        }
+        let numkeys = numkeys as usize;
+        if args.len() <= numkeys {
+            return client_error!("syntax error");
+        }
+        let (keys, args) = args.split_at(numkeys);
+
+        let left = parse_end(args[0])?;
+        let count = match &args[1..] {
+            [] => 1,
+            [option, count] if option.eq_ignore_ascii_case(b"COUNT") => {
+                let count: i64 = parse_int(count)?;
+                if count <= 0 {
+                    return client_error!("count should be greater than 0");
+                }
+                count as usize
+            }
+            _ => return client_error!("syntax error"),
+        };
+
+        let mut db = database::lock(0);
+        for key in keys {
+            if let Some(elements) = pop_elements(&mut db, key, left, count)? {
+                drop(db);
+                return self.write_popped(key, &elements, true);
+            }
+        }
+        drop(db);
+
+        if !blocking {
+            self.stream.write_all(NULL_ARRAY)?;
+            return Ok(());
+        }
+
+        let keys: Vec<Vec<u8>> = keys.iter().map(|k| k.to_vec()).collect();
+        self.block(
+            &keys,
+            deadline,
+            BlockedCommand::Pop {
+                left,
+                count: Some(count),
+            },
+        );
+
+        Ok(())
+    }
+
+    pub(super) fn handle_blmove(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received BLMOVE");
+
+        let [RESPData::BulkString(source), RESPData::BulkString(destination), RESPData::BulkString(from), RESPData::BulkString(to), RESPData::BulkString(timeout)] =
+            args
+        else {
+            return client_error!("wrong number of arguments for 'blmove' command");
+        };
+
+        let from_left = parse_end(from)?;
+        let to_left = parse_end(to)?;
+        let deadline = parse_timeout(timeout)?;
+
+        self.blocking_move(source, destination, from_left, to_left, deadline)
+    }
+
+    pub(super) fn handle_brpoplpush(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received BRPOPLPUSH");
+
+        let [RESPData::BulkString(source), RESPData::BulkString(destination), RESPData::BulkString(timeout)] =
+            args
+        else {
+            return client_error!("wrong number of arguments for 'brpoplpush' command");
+        };
+
+        let deadline = parse_timeout(timeout)?;
+
+        self.blocking_move(source, destination, false, true, deadline)
+    }
+
+    /// Move an element between lists, or block until the source is pushed to
+    fn blocking_move(
+        &mut self,
+        source: &[u8],
+        destination: &[u8],
+        from_left: bool,
+        to_left: bool,
+        deadline: Option<Instant>,
+    ) -> Result<()> {
+        let element = move_element(
+            &mut database::lock(0),
+            source,
+            destination,
+            from_left,
+            to_left,
+        )?;
+
+        if element.is_some() {
+            self.signal_key_ready(destination);
+            return self.write_optional_bulk_string(element.as_deref());
+        }
+
+        self.block(
+            &[source.to_vec()],
+            deadline,
+            BlockedCommand::Move {
+                destination: destination.to_vec(),
+                from_left,
+                to_left,
+            },
+        );
+
+        Ok(())
+    }
+}
+
+/// Resolve an index, which can be negative to count from the end, into a list of the given length
+fn resolve_index(len: usize, index: i64) -> Option<usize> {
+    let index = if index < 0 { len as i64 + index } else { index };
+    if index < 0 || index >= len as i64 {
+        None
+    } else {
+        Some(index as usize)
+    }
+}
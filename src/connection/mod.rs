@@ -0,0 +1,964 @@
+mod acl;
+mod auth;
+mod bitmaps;
+mod blocking;
+mod commands;
+mod consumer_groups;
+mod functions;
+mod geo;
+mod hashes;
+mod hyperloglogs;
+mod keys;
+mod lists;
+mod pubsub;
+mod scripting;
+mod sets;
+mod sorted_sets;
+mod streams;
+mod strings;
+mod transactions;
+
+pub(crate) use acl::Acl;
+pub(crate) use blocking::BlockedClients;
+use pubsub::Kind;
+pub(crate) use pubsub::PubSub;
+pub(crate) use scripting::Scripts;
+use sets::SetOperation;
+use sorted_sets::{RangeBy, ZSetOperation};
+use transactions::Transaction;
+
+use crate::{database, error::RustisError, parsers, resp::RESPData, Config, Result};
+use nix::poll::PollFlags;
+use std::{
+    cell::RefCell,
+    io::{ErrorKind, Read, Write},
+    net::TcpStream,
+    ops::Range,
+    os::{
+        fd::BorrowedFd,
+        unix::io::{AsFd, AsRawFd},
+    },
+    rc::Rc,
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+const BUFFER_SIZE: usize = 32 * 1024;
+
+const CRLF: &[u8] = b"\r\n";
+const NULL: &[u8] = b"$-1\r\n";
+const NULL_ARRAY: &[u8] = b"*-1\r\n";
+const EMPTY_ARRAY: &[u8] = b"*0\r\n";
+const OK: &[u8] = b"+OK\r\n";
+
+/// The commands a client can run while it's subscribed to channels or patterns
+const ALLOWED_WHILE_SUBSCRIBED: &[&[u8]] = &[
+    b"SUBSCRIBE",
+    b"UNSUBSCRIBE",
+    b"PSUBSCRIBE",
+    b"PUNSUBSCRIBE",
+    b"SSUBSCRIBE",
+    b"SUNSUBSCRIBE",
+    b"PING",
+    b"QUIT",
+    b"RESET",
+];
+
+/// The maximum size of a string value, matching the default `proto-max-bulk-len` of 512MB
+const PROTO_MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+/// Client IDs are unique for the lifetime of the server, starting from 1
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// The client's socket, which can capture what's written to it instead, so that the replies to
+/// commands run by scripts go to the script
+struct Stream {
+    socket: TcpStream,
+    captured: Option<Vec<u8>>,
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.socket.read(buf)
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.captured.as_mut() {
+            Some(captured) => captured.write(buf),
+            None => self.socket.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.captured {
+            Some(_) => Ok(()),
+            None => self.socket.flush(),
+        }
+    }
+}
+
+pub(crate) struct Connection {
+    id: u64,
+    stream: Stream,
+    config: Rc<RefCell<Config>>,
+    blocked_clients: Rc<RefCell<BlockedClients>>,
+    pubsub: Rc<RefCell<PubSub>>,
+    scripts: Rc<RefCell<Scripts>>,
+    acl: Rc<RefCell<Acl>>,
+    /// Input that has been read but not yet processed, either because it's not a complete command
+    /// yet or because the client is blocked
+    read_buffer: Vec<u8>,
+    /// The command the client is blocked on, if any
+    blocked: Option<blocking::Blocked>,
+    /// The commands queued since MULTI, if the client is in a transaction
+    transaction: Option<Transaction>,
+    /// The keys watched for the next transaction
+    watched: Vec<Vec<u8>>,
+    /// Whether the client has authenticated, which it needs to do to run most commands when the
+    /// default user has a password
+    authenticated: bool,
+    /// The ACL user the client is authenticated as
+    user: String,
+}
+
+/// Parse a raw argument as an integer
+fn parse_int<T: FromStr>(raw: &[u8]) -> Result<T> {
+    let s = String::from_utf8_lossy(raw);
+    let Ok(s) = s.parse::<T>() else {
+        return client_error!("value is not an integer or out of range");
+    };
+    Ok(s)
+}
+
+/// Collect arguments that are all expected to be bulk strings, such as a list of keys
+fn bulk_strings<'a>(args: &[RESPData<'a>]) -> Result<Vec<&'a [u8]>> {
+    args.iter()
+        .map(|arg| match arg {
+            RESPData::BulkString(s) => Ok(*s),
+            _ => client_error!("syntax error"),
+        })
+        .collect()
+}
+
+/// Resolve inclusive start and end indexes, which can be negative to count from the end, into a
+/// range over a value of the given length
+///
+/// Out of range indexes are clamped, and an empty range is returned if start ends up after end
+fn resolve_range(len: usize, start: i64, end: i64) -> Range<usize> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let end = if end < 0 { len + end } else { end.min(len - 1) };
+
+    if len == 0 || start > end {
+        return 0..0;
+    }
+
+    start as usize..end as usize + 1
+}
+
+impl Connection {
+    pub(crate) fn new(
+        stream: TcpStream,
+        config: Rc<RefCell<Config>>,
+        blocked_clients: Rc<RefCell<BlockedClients>>,
+        pubsub: Rc<RefCell<PubSub>>,
+        scripts: Rc<RefCell<Scripts>>,
+        acl: Rc<RefCell<Acl>>,
+    ) -> Result<Self> {
+        stream.set_nonblocking(true)?;
+        let authenticated = acl.borrow().default_user_nopass();
+        Ok(Connection {
+            id: NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed),
+            stream: Stream {
+                socket: stream,
+                captured: None,
+            },
+            config,
+            blocked_clients,
+            pubsub,
+            scripts,
+            acl,
+            read_buffer: Vec::new(),
+            blocked: None,
+            transaction: None,
+            watched: Vec::new(),
+            authenticated,
+            user: acl::DEFAULT_USER.to_string(),
+        })
+    }
+
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub(crate) fn as_fd(&self) -> BorrowedFd<'_> {
+        self.stream.socket.as_fd()
+    }
+
+    pub(crate) fn as_raw_fd(&self) -> i32 {
+        self.stream.socket.as_raw_fd()
+    }
+
+    pub(crate) fn process_event(mut self, event: Option<&PollFlags>) -> Result<Self> {
+        let Some(revents) = event else {
+            return Ok(self);
+        };
+
+        if !revents.contains(PollFlags::POLLIN) {
+            return Ok(self);
+        }
+
+        // TODO: Is there a performance improvement to be done here? Reuse a buffer?
+        let mut buf = vec![0; BUFFER_SIZE].into_boxed_slice();
+        match self.stream.read(&mut buf) {
+            Ok(0) => {
+                log::info!("Connection closed");
+                return Err(RustisError::ClientDisconnected);
+            }
+            Ok(n) => {
+                log::trace!("Read {} bytes", n);
+                log::trace!("Data: {:?}", String::from_utf8_lossy(&buf[..n]));
+
+                self.read_buffer.extend_from_slice(&buf[..n]);
+                match self.process_input() {
+                    Err(RustisError::ClientDisconnected) => {
+                        log::info!("Connection closed by the client");
+                        return Err(RustisError::ClientDisconnected);
+                    }
+                    Err(e) => {
+                        log::error!("Error processing input: {}", e);
+                        return Err(e);
+                    }
+                    Ok(()) => {}
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                log::debug!("Read would block");
+            }
+            Err(e) => {
+                log::error!("Read error: {}", e);
+                return Err(RustisError::ReadError);
+            }
+        }
+        Ok(self)
+    }
+
+    /// Process all complete commands in the read buffer
+    ///
+    /// A client error only fails the command that caused it, the error is written to the client
+    /// and processing continues with the next command. Processing stops if a command blocks the
+    /// client, and anything left over is kept in the buffer until the client is unblocked or more
+    /// input arrives.
+    pub(crate) fn process_input(&mut self) -> Result<()> {
+        // The parsed data borrows from the buffer, so it is taken out while processing
+        let mut buffer = std::mem::take(&mut self.read_buffer);
+        let mut consumed = 0;
+
+        let result = loop {
+            if self.is_blocked() {
+                break Ok(());
+            }
+            let (data, length) = match parsers::resp_data::parse_one(&buffer[consumed..]) {
+                Ok(Some(message)) => message,
+                Ok(None) => break Ok(()),
+                Err(e) => break Err(e),
+            };
+            consumed += length;
+
+            if let Err(e) = self.deliver_pending() {
+                break Err(e);
+            }
+            let result = match data {
+                RESPData::SimpleString(s) => self.process_simple_string(s),
+                RESPData::Array(array) => self.process_array(&array[..]),
+                _ => client_error!("Protocol error: expected a command"),
+            };
+            self.publish_keyspace_events();
+            if let Err(e) = result.or_else(|e| self.write_command_error(e)) {
+                break Err(e);
+            }
+        };
+
+        buffer.drain(..consumed);
+        self.read_buffer = buffer;
+
+        result
+    }
+
+    /// Write the error of a failed command to the client
+    ///
+    /// Errors that aren't caused by the command, such as IO errors, are returned instead
+    fn write_command_error(&mut self, error: RustisError) -> Result<()> {
+        match error {
+            RustisError::ClientError(msg) => {
+                log::info!("Client error: {}", msg);
+                self.write_error(msg.as_bytes())
+            }
+            RustisError::ClientErrorWithCode(code, msg) => {
+                log::info!("Client error: {} {}", code, msg);
+                self.write_error_with_code(code.as_bytes(), msg.as_bytes())
+            }
+            e @ RustisError::WrongType => {
+                log::info!("Client error: {}", e);
+                self.write_error_with_code(b"WRONGTYPE", e.to_string().as_bytes())
+            }
+            e => Err(e),
+        }
+    }
+
+    /// Helper function to write an error to the client
+    ///
+    /// The function will write "-ERR " followed by the error message and a CRLF
+    fn write_error(&mut self, error: &[u8]) -> Result<()> {
+        self.write_error_with_code(b"ERR", error)
+    }
+
+    /// Helper function to write an error with a specific error code, such as "WRONGTYPE"
+    fn write_error_with_code(&mut self, code: &[u8], error: &[u8]) -> Result<()> {
+        let mut buf = Vec::with_capacity(code.len() + error.len() + 4);
+        buf.push(b'-');
+        buf.extend_from_slice(code);
+        buf.push(b' ');
+        buf.extend_from_slice(error);
+        buf.extend_from_slice(CRLF);
+        self.stream.write_all(&buf)?;
+        Ok(())
+    }
+
+    /// Helper function to write an Array to the client
+    fn write_array(&mut self, array: Vec<&[u8]>) -> Result<()> {
+        let element_count = array.len();
+
+        let mut buf = Vec::new();
+        buf.push(b'*');
+        write!(buf, "{}\r\n", element_count)?;
+
+        array.iter().for_each(|element| {
+            buf.push(b'$');
+            write!(buf, "{}\r\n", element.len()).unwrap();
+            buf.extend_from_slice(element);
+            buf.extend_from_slice(CRLF);
+        });
+
+        self.stream.write_all(&buf)?;
+
+        Ok(())
+    }
+
+    /// Helper function to write a BulkString
+    fn write_bulk_string(&mut self, data: &[u8]) -> Result<()> {
+        let mut buf = Vec::new();
+        buf.push(b'$');
+        write!(buf, "{}\r\n", data.len()).unwrap();
+        buf.extend_from_slice(data);
+        buf.extend_from_slice(CRLF);
+
+        self.stream.write_all(&buf)?;
+
+        Ok(())
+    }
+
+    /// Helper function to write any RESP data, such as arrays mixing different types
+    fn write_resp(&mut self, data: &RESPData) -> Result<()> {
+        let mut buf = Vec::new();
+        data.encode(&mut buf);
+        self.stream.write_all(&buf)?;
+        Ok(())
+    }
+
+    /// Helper function to write an Integer
+    fn write_integer(&mut self, value: i64) -> Result<()> {
+        self.write_resp(&RESPData::Integer(value))
+    }
+
+    /// Helper function to write a BulkString if there is a value, otherwise a null
+    fn write_optional_bulk_string(&mut self, data: Option<&[u8]>) -> Result<()> {
+        match data {
+            Some(data) => self.write_bulk_string(data),
+            None => {
+                self.stream.write_all(NULL)?;
+                Ok(())
+            }
+        }
+    }
+
+    fn process_simple_string(&mut self, string: &[u8]) -> Result<()> {
+        self.check_authenticated(string)?;
+        match string {
+            b"PING" => {
+                log::debug!("Received PING");
+                self.stream.write_all(b"+PONG\r\n")?;
+            }
+            buf => {
+                let data = String::from_utf8_lossy(buf);
+                log::error!("Unknown command: {}", data);
+                return Err(RustisError::UnknownCommand(data.to_string()));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn process_array(&mut self, array: &[RESPData]) -> Result<()> {
+        if let Some(RESPData::BulkString(s)) = array.first() {
+            let command = s.to_ascii_uppercase();
+            self.check_authenticated(&command)?;
+            if self.is_busy() && !scripting::allowed_while_busy(&command, array) {
+                return Err(self.busy_error());
+            }
+            if self.is_subscribed() && !ALLOWED_WHILE_SUBSCRIBED.contains(&command.as_slice()) {
+                return client_error!(
+                    "Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / \
+                     RESET are allowed in this context",
+                    String::from_utf8_lossy(s).to_lowercase()
+                );
+            }
+            self.check_permissions(array)?;
+            if self.in_transaction() && !transactions::NOT_QUEUED.contains(&command.as_slice()) {
+                return self.queue_command(&command, array);
+            }
+            match command.as_slice() {
+                b"PING" => self.handle_ping(&array[1..])?,
+                b"COMMAND" => self.handle_command()?,
+                b"ECHO" => self.handle_echo(&array[1..])?,
+                b"SET" => self.handle_set(&array[1..])?,
+                b"GET" => self.handle_get(&array[1..])?,
+                b"SETNX" => self.handle_setnx(&array[1..])?,
+                b"SETEX" => self.handle_setex(&array[1..], b"setex", 1000)?,
+                b"PSETEX" => self.handle_setex(&array[1..], b"psetex", 1)?,
+                b"GETSET" => self.handle_getset(&array[1..])?,
+                b"GETDEL" => self.handle_getdel(&array[1..])?,
+                b"GETEX" => self.handle_getex(&array[1..])?,
+                b"APPEND" => self.handle_append(&array[1..])?,
+                b"STRLEN" => self.handle_strlen(&array[1..])?,
+                b"GETRANGE" => self.handle_getrange(&array[1..])?,
+                b"SETRANGE" => self.handle_setrange(&array[1..])?,
+                command @ (b"INCR" | b"DECR" | b"INCRBY" | b"DECRBY") => {
+                    self.handle_incrby(&array[1..], command)?
+                }
+                b"INCRBYFLOAT" => self.handle_incrbyfloat(&array[1..])?,
+                b"MGET" => self.handle_mget(&array[1..])?,
+                b"MSET" => self.handle_mset(&array[1..])?,
+                b"MSETNX" => self.handle_msetnx(&array[1..])?,
+                b"SETBIT" => self.handle_setbit(&array[1..])?,
+                b"GETBIT" => self.handle_getbit(&array[1..])?,
+                b"BITCOUNT" => self.handle_bitcount(&array[1..])?,
+                b"BITPOS" => self.handle_bitpos(&array[1..])?,
+                b"BITOP" => self.handle_bitop(&array[1..])?,
+                b"BITFIELD" => self.handle_bitfield(&array[1..], false)?,
+                b"BITFIELD_RO" => self.handle_bitfield(&array[1..], true)?,
+                b"LPUSH" => self.handle_push(&array[1..], b"lpush", true, false)?,
+                b"RPUSH" => self.handle_push(&array[1..], b"rpush", false, false)?,
+                b"LPUSHX" => self.handle_push(&array[1..], b"lpushx", true, true)?,
+                b"RPUSHX" => self.handle_push(&array[1..], b"rpushx", false, true)?,
+                b"LPOP" => self.handle_pop(&array[1..], b"lpop", true)?,
+                b"RPOP" => self.handle_pop(&array[1..], b"rpop", false)?,
+                b"LLEN" => self.handle_llen(&array[1..])?,
+                b"LRANGE" => self.handle_lrange(&array[1..])?,
+                b"LINDEX" => self.handle_lindex(&array[1..])?,
+                b"LSET" => self.handle_lset(&array[1..])?,
+                b"LINSERT" => self.handle_linsert(&array[1..])?,
+                b"LREM" => self.handle_lrem(&array[1..])?,
+                b"LTRIM" => self.handle_ltrim(&array[1..])?,
+                b"LMOVE" => self.handle_lmove(&array[1..])?,
+                b"RPOPLPUSH" => self.handle_rpoplpush(&array[1..])?,
+                b"LPOS" => self.handle_lpos(&array[1..])?,
+                b"LMPOP" => self.handle_lmpop(&array[1..], false)?,
+                b"BLPOP" => self.handle_blpop(&array[1..], b"blpop", true)?,
+                b"BRPOP" => self.handle_blpop(&array[1..], b"brpop", false)?,
+                b"BLMPOP" => self.handle_lmpop(&array[1..], true)?,
+                b"BLMOVE" => self.handle_blmove(&array[1..])?,
+                b"BRPOPLPUSH" => self.handle_brpoplpush(&array[1..])?,
+                b"HSET" => self.handle_hset(&array[1..], b"hset")?,
+                b"HMSET" => self.handle_hset(&array[1..], b"hmset")?,
+                b"HSETNX" => self.handle_hsetnx(&array[1..])?,
+                b"HGET" => self.handle_hget(&array[1..])?,
+                b"HMGET" => self.handle_hmget(&array[1..])?,
+                b"HDEL" => self.handle_hdel(&array[1..])?,
+                b"HLEN" => self.handle_hlen(&array[1..])?,
+                b"HSTRLEN" => self.handle_hstrlen(&array[1..])?,
+                b"HEXISTS" => self.handle_hexists(&array[1..])?,
+                b"HGETALL" => self.handle_hgetall(&array[1..], b"hgetall", true, true)?,
+                b"HKEYS" => self.handle_hgetall(&array[1..], b"hkeys", true, false)?,
+                b"HVALS" => self.handle_hgetall(&array[1..], b"hvals", false, true)?,
+                b"HINCRBY" => self.handle_hincrby(&array[1..])?,
+                b"HINCRBYFLOAT" => self.handle_hincrbyfloat(&array[1..])?,
+                b"HRANDFIELD" => self.handle_hrandfield(&array[1..])?,
+                b"HEXPIRE" => self.handle_hexpire(&array[1..], b"hexpire", 1000, false)?,
+                b"HPEXPIRE" => self.handle_hexpire(&array[1..], b"hpexpire", 1, false)?,
+                b"HEXPIREAT" => self.handle_hexpire(&array[1..], b"hexpireat", 1000, true)?,
+                b"HPEXPIREAT" => self.handle_hexpire(&array[1..], b"hpexpireat", 1, true)?,
+                b"HTTL" => self.handle_httl(&array[1..], b"httl", 1000, false)?,
+                b"HPTTL" => self.handle_httl(&array[1..], b"hpttl", 1, false)?,
+                b"HEXPIRETIME" => self.handle_httl(&array[1..], b"hexpiretime", 1000, true)?,
+                b"HPEXPIRETIME" => self.handle_httl(&array[1..], b"hpexpiretime", 1, true)?,
+                b"HPERSIST" => self.handle_hpersist(&array[1..])?,
+                b"HGETEX" => self.handle_hgetex(&array[1..])?,
+                b"HSETEX" => self.handle_hsetex(&array[1..])?,
+                b"SADD" => self.handle_sadd(&array[1..])?,
+                b"SREM" => self.handle_srem(&array[1..])?,
+                b"SMEMBERS" => self.handle_smembers(&array[1..])?,
+                b"SISMEMBER" => self.handle_sismember(&array[1..])?,
+                b"SMISMEMBER" => self.handle_smismember(&array[1..])?,
+                b"SCARD" => self.handle_scard(&array[1..])?,
+                b"SPOP" => self.handle_spop(&array[1..])?,
+                b"SRANDMEMBER" => self.handle_srandmember(&array[1..])?,
+                b"SINTER" => {
+                    self.handle_set_operation(&array[1..], b"sinter", SetOperation::Inter)?
+                }
+                b"SUNION" => {
+                    self.handle_set_operation(&array[1..], b"sunion", SetOperation::Union)?
+                }
+                b"SDIFF" => self.handle_set_operation(&array[1..], b"sdiff", SetOperation::Diff)?,
+                b"SINTERSTORE" => self.handle_set_operation_store(
+                    &array[1..],
+                    b"sinterstore",
+                    SetOperation::Inter,
+                )?,
+                b"SUNIONSTORE" => self.handle_set_operation_store(
+                    &array[1..],
+                    b"sunionstore",
+                    SetOperation::Union,
+                )?,
+                b"SDIFFSTORE" => {
+                    self.handle_set_operation_store(&array[1..], b"sdiffstore", SetOperation::Diff)?
+                }
+                b"SINTERCARD" => self.handle_sintercard(&array[1..])?,
+                b"SMOVE" => self.handle_smove(&array[1..])?,
+                b"ZADD" => self.handle_zadd(&array[1..])?,
+                b"ZINCRBY" => self.handle_zincrby(&array[1..])?,
+                b"ZCARD" => self.handle_zcard(&array[1..])?,
+                b"ZSCORE" => self.handle_zscore(&array[1..])?,
+                b"ZMSCORE" => self.handle_zmscore(&array[1..])?,
+                b"ZRANK" => self.handle_zrank(&array[1..], b"zrank", false)?,
+                b"ZREVRANK" => self.handle_zrank(&array[1..], b"zrevrank", true)?,
+                b"ZREM" => self.handle_zrem(&array[1..])?,
+                b"ZRANGE" => self.handle_zrange(&array[1..], b"zrange", None)?,
+                b"ZREVRANGE" => {
+                    self.handle_zrange(&array[1..], b"zrevrange", Some((RangeBy::Rank, true)))?
+                }
+                b"ZRANGEBYSCORE" => self.handle_zrange(
+                    &array[1..],
+                    b"zrangebyscore",
+                    Some((RangeBy::Score, false)),
+                )?,
+                b"ZREVRANGEBYSCORE" => self.handle_zrange(
+                    &array[1..],
+                    b"zrevrangebyscore",
+                    Some((RangeBy::Score, true)),
+                )?,
+                b"ZRANGEBYLEX" => {
+                    self.handle_zrange(&array[1..], b"zrangebylex", Some((RangeBy::Lex, false)))?
+                }
+                b"ZREVRANGEBYLEX" => {
+                    self.handle_zrange(&array[1..], b"zrevrangebylex", Some((RangeBy::Lex, true)))?
+                }
+                b"ZCOUNT" => self.handle_zcount(&array[1..], b"zcount", RangeBy::Score)?,
+                b"ZLEXCOUNT" => self.handle_zcount(&array[1..], b"zlexcount", RangeBy::Lex)?,
+                b"ZREMRANGEBYRANK" => {
+                    self.handle_zremrange(&array[1..], b"zremrangebyrank", RangeBy::Rank)?
+                }
+                b"ZREMRANGEBYSCORE" => {
+                    self.handle_zremrange(&array[1..], b"zremrangebyscore", RangeBy::Score)?
+                }
+                b"ZREMRANGEBYLEX" => {
+                    self.handle_zremrange(&array[1..], b"zremrangebylex", RangeBy::Lex)?
+                }
+                b"ZPOPMIN" => self.handle_zpop(&array[1..], b"zpopmin", false)?,
+                b"ZPOPMAX" => self.handle_zpop(&array[1..], b"zpopmax", true)?,
+                b"BZPOPMIN" => self.handle_bzpop(&array[1..], b"bzpopmin", false)?,
+                b"BZPOPMAX" => self.handle_bzpop(&array[1..], b"bzpopmax", true)?,
+                b"ZUNION" => {
+                    self.handle_zset_operation(&array[1..], b"zunion", ZSetOperation::Union, false)?
+                }
+                b"ZINTER" => {
+                    self.handle_zset_operation(&array[1..], b"zinter", ZSetOperation::Inter, false)?
+                }
+                b"ZDIFF" => {
+                    self.handle_zset_operation(&array[1..], b"zdiff", ZSetOperation::Diff, false)?
+                }
+                b"ZUNIONSTORE" => self.handle_zset_operation(
+                    &array[1..],
+                    b"zunionstore",
+                    ZSetOperation::Union,
+                    true,
+                )?,
+                b"ZINTERSTORE" => self.handle_zset_operation(
+                    &array[1..],
+                    b"zinterstore",
+                    ZSetOperation::Inter,
+                    true,
+                )?,
+                b"ZDIFFSTORE" => self.handle_zset_operation(
+                    &array[1..],
+                    b"zdiffstore",
+                    ZSetOperation::Diff,
+                    true,
+                )?,
+                b"XADD" => self.handle_xadd(&array[1..])?,
+                b"XLEN" => self.handle_xlen(&array[1..])?,
+                b"XRANGE" => self.handle_xrange(&array[1..], b"xrange", false)?,
+                b"XREVRANGE" => self.handle_xrange(&array[1..], b"xrevrange", true)?,
+                b"XDEL" => self.handle_xdel(&array[1..])?,
+                b"XTRIM" => self.handle_xtrim(&array[1..])?,
+                b"XREAD" => self.handle_xread(&array[1..])?,
+                b"XGROUP" => self.handle_xgroup(&array[1..])?,
+                b"XREADGROUP" => self.handle_xreadgroup(&array[1..])?,
+                b"XACK" => self.handle_xack(&array[1..])?,
+                b"XPENDING" => self.handle_xpending(&array[1..])?,
+                b"XCLAIM" => self.handle_xclaim(&array[1..])?,
+                b"XAUTOCLAIM" => self.handle_xautoclaim(&array[1..])?,
+                b"XINFO" => self.handle_xinfo(&array[1..])?,
+                b"GEOADD" => self.handle_geoadd(&array[1..])?,
+                b"GEODIST" => self.handle_geodist(&array[1..])?,
+                b"GEOPOS" => self.handle_geopos(&array[1..])?,
+                b"GEOHASH" => self.handle_geohash(&array[1..])?,
+                b"GEOSEARCH" => self.handle_geosearch(&array[1..], false)?,
+                b"GEOSEARCHSTORE" => self.handle_geosearch(&array[1..], true)?,
+                b"PFADD" => self.handle_pfadd(&array[1..])?,
+                b"PFCOUNT" => self.handle_pfcount(&array[1..])?,
+                b"PFMERGE" => self.handle_pfmerge(&array[1..])?,
+                b"PFDEBUG" => self.handle_pfdebug(&array[1..])?,
+                b"PFSELFTEST" => self.handle_pfselftest(&array[1..])?,
+                b"SUBSCRIBE" => self.handle_subscribe(&array[1..], Kind::Channel)?,
+                b"UNSUBSCRIBE" => self.handle_unsubscribe(&array[1..], Kind::Channel)?,
+                b"PSUBSCRIBE" => self.handle_subscribe(&array[1..], Kind::Pattern)?,
+                b"PUNSUBSCRIBE" => self.handle_unsubscribe(&array[1..], Kind::Pattern)?,
+                b"PUBLISH" => self.handle_publish(&array[1..], false)?,
+                b"SSUBSCRIBE" => self.handle_subscribe(&array[1..], Kind::ShardChannel)?,
+                b"SUNSUBSCRIBE" => self.handle_unsubscribe(&array[1..], Kind::ShardChannel)?,
+                b"SPUBLISH" => self.handle_publish(&array[1..], true)?,
+                b"PUBSUB" => self.handle_pubsub(&array[1..])?,
+                b"MULTI" => self.handle_multi(&array[1..])?,
+                b"EXEC" => self.handle_exec(&array[1..])?,
+                b"DISCARD" => self.handle_discard(&array[1..])?,
+                b"WATCH" => self.handle_watch(&array[1..])?,
+                b"UNWATCH" => self.handle_unwatch(&array[1..])?,
+                b"TYPE" => self.handle_type(&array[1..])?,
+                b"OBJECT" => self.handle_object(&array[1..])?,
+                b"SAVE" => self.handle_save()?,
+                b"CONFIG" => self.handle_config(&array[1..])?,
+                b"CLIENT" => self.handle_client(&array[1..])?,
+                b"AUTH" => self.handle_auth(&array[1..])?,
+                b"HELLO" => self.handle_hello(&array[1..])?,
+                b"ACL" => self.handle_acl(&array[1..])?,
+                b"KEYS" => self.handle_keys(&array[1..])?,
+                b"DEL" => self.handle_del(&array[1..], "del")?,
+                b"UNLINK" => self.handle_del(&array[1..], "unlink")?,
+                b"EXPIRE" => self.handle_expire(&array[1..], "expire", 1000, true)?,
+                b"PEXPIRE" => self.handle_expire(&array[1..], "pexpire", 1, true)?,
+                b"EXPIREAT" => self.handle_expire(&array[1..], "expireat", 1000, false)?,
+                b"PEXPIREAT" => self.handle_expire(&array[1..], "pexpireat", 1, false)?,
+                b"TTL" => self.handle_ttl(&array[1..], "ttl", 1000)?,
+                b"PTTL" => self.handle_ttl(&array[1..], "pttl", 1)?,
+                b"PERSIST" => self.handle_persist(&array[1..])?,
+                b"FLUSHDB" => self.handle_flush(&array[1..], "flushdb")?,
+                b"FLUSHALL" => self.handle_flush(&array[1..], "flushall")?,
+                b"SWAPDB" => self.handle_swapdb(&array[1..])?,
+                b"EVAL" => self.handle_eval(&array[1..], b"eval", false, false)?,
+                b"EVALSHA" => self.handle_eval(&array[1..], b"evalsha", true, false)?,
+                b"EVAL_RO" => self.handle_eval(&array[1..], b"eval_ro", false, true)?,
+                b"EVALSHA_RO" => self.handle_eval(&array[1..], b"evalsha_ro", true, true)?,
+                b"SCRIPT" => self.handle_script(&array[1..])?,
+                b"FUNCTION" => self.handle_function(&array[1..])?,
+                b"FCALL" => self.handle_fcall(&array[1..], b"fcall", false)?,
+                b"FCALL_RO" => self.handle_fcall(&array[1..], b"fcall_ro", true)?,
+                b"QUIT" => self.handle_quit()?,
+                b"RESET" => self.handle_reset(&array[1..])?,
+                _ => {
+                    let args = bulk_strings(&array[1..])?;
+                    return Err(transactions::unknown_command(s, &args));
+                }
+            }
+        } else {
+            return client_error!("Protocol error: expected a command");
+        }
+
+        Ok(())
+    }
+
+    /// Reply to PING, which in subscribed mode is a "pong" array like the pushed messages
+    fn handle_ping(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received PING");
+
+        let message = match args {
+            [] => None,
+            [RESPData::BulkString(message)] => Some(*message),
+            _ => return client_error!("wrong number of arguments for 'ping' command"),
+        };
+
+        if self.is_subscribed() {
+            let reply = RESPData::Array(vec![
+                RESPData::BulkString(b"pong"),
+                RESPData::BulkString(message.unwrap_or_default()),
+            ]);
+            return self.write_resp(&reply);
+        }
+        match message {
+            Some(message) => self.write_bulk_string(message),
+            None => {
+                self.stream.write_all(b"+PONG\r\n")?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Handle QUIT, which closes the connection once the reply is written
+    fn handle_quit(&mut self) -> Result<()> {
+        log::debug!("Received QUIT");
+        self.stream.write_all(OK)?;
+        Err(RustisError::ClientDisconnected)
+    }
+
+    /// Handle RESET, which puts the client back in the state of a new connection
+    fn handle_reset(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received RESET");
+
+        if !args.is_empty() {
+            return client_error!("wrong number of arguments for 'reset' command");
+        }
+        self.transaction = None;
+        self.unwatch();
+        self.pubsub.borrow_mut().remove_client(self.id);
+        self.reset_authentication();
+        self.stream.write_all(b"+RESET\r\n")?;
+        Ok(())
+    }
+
+    fn handle_command(&mut self) -> Result<()> {
+        log::debug!("Received COMMAND");
+        self.stream.write_all(OK)?;
+        Ok(())
+    }
+
+    fn handle_echo(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received ECHO");
+        match args {
+            [RESPData::BulkString(msg)] => {
+                let mut buf = Vec::new();
+                buf.push(b'+');
+                buf.extend_from_slice(msg);
+                buf.extend_from_slice(CRLF);
+
+                self.stream.write_all(&buf)?;
+            }
+            _ => return client_error!("wrong number of arguments for 'echo' command"),
+        }
+
+        Ok(())
+    }
+
+    fn handle_config(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received CONFIG");
+
+        let Some((RESPData::BulkString(subcommand), args)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'config' command");
+        };
+
+        match subcommand.to_ascii_uppercase().as_slice() {
+            b"GET" => self.handle_config_get(args)?,
+            b"SET" => self.handle_config_set(args)?,
+            _ => {
+                return client_error!(
+                    "unknown subcommand '{}'. Try CONFIG HELP.",
+                    String::from_utf8_lossy(subcommand)
+                )
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_client(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received CLIENT");
+
+        let Some((RESPData::BulkString(subcommand), args)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'client' command");
+        };
+
+        match subcommand.to_ascii_uppercase().as_slice() {
+            b"ID" => self.write_integer(self.id as i64)?,
+            b"UNBLOCK" => self.handle_client_unblock(args)?,
+            // TODO: Set this somewhere.. Now we just tell the client that we've set this
+            _ => self.stream.write_all(OK)?,
+        }
+
+        Ok(())
+    }
+
+    fn handle_client_unblock(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received CLIENT UNBLOCK");
+
+        let (id, error) = match args {
+            [RESPData::BulkString(id)] => (id, false),
+            [RESPData::BulkString(id), RESPData::BulkString(reason)] => {
+                match reason.to_ascii_uppercase().as_slice() {
+                    b"TIMEOUT" => (id, false),
+                    b"ERROR" => (id, true),
+                    _ => return client_error!("CLIENT UNBLOCK reason should be TIMEOUT or ERROR"),
+                }
+            }
+            _ => return client_error!("wrong number of arguments for 'client|unblock' command"),
+        };
+        let id: u64 = parse_int(id)?;
+
+        // The client is unblocked by the server, as it owns the other connections
+        let unblocked = self.request_unblock(id, error);
+
+        self.write_integer(unblocked as i64)
+    }
+
+    fn handle_keys(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received KEYS");
+
+        let Some((RESPData::BulkString(pattern), _)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'keys' command");
+        };
+
+        // We will just support '*' for now
+        if pattern != b"*" {
+            return client_error!("only '*' is supported for now");
+        }
+
+        let keys = database::lock(0).keys();
+        self.write_array(keys.iter().map(|k| k.as_slice()).collect())?;
+
+        Ok(())
+    }
+
+    fn handle_type(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received TYPE");
+
+        let [RESPData::BulkString(key)] = args else {
+            return client_error!("wrong number of arguments for 'type' command");
+        };
+
+        let type_name = database::lock(0).get(key).map_or("none", |v| v.type_name());
+        self.write_resp(&RESPData::SimpleString(type_name.as_bytes()))?;
+
+        Ok(())
+    }
+
+    fn handle_object(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received OBJECT");
+
+        let Some((RESPData::BulkString(subcommand), args)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'object' command");
+        };
+
+        match (subcommand.to_ascii_uppercase().as_slice(), args) {
+            (b"ENCODING", [RESPData::BulkString(key)]) => {
+                let encoding = database::lock(0).get(key).map(|v| v.encoding());
+                self.write_optional_bulk_string(encoding.map(str::as_bytes))
+            }
+            (b"ENCODING", _) => {
+                client_error!("wrong number of arguments for 'object|encoding' command")
+            }
+            _ => client_error!(
+                "unknown subcommand '{}'. Try OBJECT HELP.",
+                String::from_utf8_lossy(subcommand)
+            ),
+        }
+    }
+
+    fn handle_save(&mut self) -> Result<()> {
+        log::debug!("Received SAVE");
+
+        let db_path = self.config.borrow().rdb_path();
+        let libraries = self.scripts.borrow().library_codes();
+        if let Err(e) = database::save_rdb(&db_path, &libraries) {
+            log::error!("Failed to save RDB file: {}", e);
+            return client_error!("failed to save the RDB file");
+        }
+        self.stream.write_all(OK)?;
+
+        Ok(())
+    }
+
+    fn handle_config_get(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received CONFIG GET");
+
+        let names = bulk_strings(args)?;
+        if names.is_empty() {
+            return client_error!("wrong number of arguments for 'config|get' command");
+        }
+
+        let pairs: Vec<(String, String)> = {
+            let config = self.config.borrow();
+            names
+                .iter()
+                .filter_map(|name| {
+                    let name = String::from_utf8_lossy(name).to_lowercase();
+                    config.get(&name).map(|value| (name, value))
+                })
+                .collect()
+        };
+
+        self.write_array(
+            pairs
+                .iter()
+                .flat_map(|(name, value)| [name.as_bytes(), value.as_bytes()])
+                .collect(),
+        )
+    }
+
+    fn handle_config_set(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received CONFIG SET");
+
+        let pairs = bulk_strings(args)?;
+        if pairs.is_empty() || pairs.len() % 2 != 0 {
+            return client_error!("wrong number of arguments for 'config|set' command");
+        }
+
+        {
+            let mut config = self.config.borrow_mut();
+            for pair in pairs.chunks_exact(2) {
+                let name = String::from_utf8_lossy(pair[0]).to_lowercase();
+                let value = String::from_utf8_lossy(pair[1]);
+                if let Err(e) = config.set(&name, &value) {
+                    return client_error!("{}", e);
+                }
+                // Only clients that connect afterwards need to authenticate with the new password
+                if name == "requirepass" {
+                    self.acl.borrow_mut().set_requirepass(&value);
+                }
+            }
+        }
+
+        self.stream.write_all(OK)?;
+
+        Ok(())
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // Disconnected clients should not be served anything they were blocked on
+        self.blocked_clients.borrow_mut().unblock(self.id);
+        self.pubsub.borrow_mut().remove_client(self.id);
+        self.unwatch();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_range() {
+        assert_eq!(resolve_range(5, 0, -1), 0..5);
+        assert_eq!(resolve_range(5, 1, 2), 1..3);
+        assert_eq!(resolve_range(5, -3, -2), 2..4);
+        assert_eq!(resolve_range(5, 0, 100), 0..5);
+        assert_eq!(resolve_range(5, -100, 1), 0..2);
+        assert_eq!(resolve_range(5, 3, 1), 0..0);
+        assert_eq!(resolve_range(5, -1, -3), 0..0);
+        assert_eq!(resolve_range(0, 0, -1), 0..0);
+    }
+}
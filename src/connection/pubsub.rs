@@ -0,0 +1,547 @@
+use super::{bulk_strings, Connection};
+use crate::{cluster, error::RustisError, glob, notify, resp::RESPData, Result};
+use std::{collections::HashMap, io::Write};
+
+/// Whether a subscription is to a channel by name, to all channels matching a pattern, or to a
+/// sharded channel
+///
+/// Sharded channels are assigned to hash slots like keys, so that in a cluster they would only be
+/// published to on the node owning the slot. Without a cluster they work like normal channels, but
+/// separately from them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Kind {
+    Channel,
+    Pattern,
+    ShardChannel,
+}
+
+impl Kind {
+    /// The name of the command, also used as the kind of its replies
+    fn subscribe_command(self) -> &'static [u8] {
+        match self {
+            Kind::Channel => b"subscribe",
+            Kind::Pattern => b"psubscribe",
+            Kind::ShardChannel => b"ssubscribe",
+        }
+    }
+
+    fn unsubscribe_command(self) -> &'static [u8] {
+        match self {
+            Kind::Channel => b"unsubscribe",
+            Kind::Pattern => b"punsubscribe",
+            Kind::ShardChannel => b"sunsubscribe",
+        }
+    }
+}
+
+/// The channels and patterns a client is subscribed to, in the order that it subscribed
+#[derive(Debug, Default)]
+struct Subscriptions {
+    channels: Vec<Vec<u8>>,
+    patterns: Vec<Vec<u8>>,
+    shard_channels: Vec<Vec<u8>>,
+}
+
+impl Subscriptions {
+    fn of_kind(&mut self, kind: Kind) -> &mut Vec<Vec<u8>> {
+        match kind {
+            Kind::Channel => &mut self.channels,
+            Kind::Pattern => &mut self.patterns,
+            Kind::ShardChannel => &mut self.shard_channels,
+        }
+    }
+
+    /// The number of subscriptions replied with when subscribing or unsubscribing, where sharded
+    /// channels are counted separately from the others
+    fn count(&self, kind: Kind) -> usize {
+        match kind {
+            Kind::Channel | Kind::Pattern => self.channels.len() + self.patterns.len(),
+            Kind::ShardChannel => self.shard_channels.len(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.channels.is_empty() && self.patterns.is_empty() && self.shard_channels.is_empty()
+    }
+}
+
+/// The registry of pub/sub subscriptions, shared between all connections
+///
+/// Connections can't write to each other, so published messages are queued for each receiving
+/// client, and the server delivers them once the publishing command has been processed.
+#[derive(Debug, Default)]
+pub(crate) struct PubSub {
+    /// The clients subscribed to each channel, in the order that they subscribed
+    channels: HashMap<Vec<u8>, Vec<u64>>,
+    /// The clients subscribed to each pattern, in the order that they subscribed
+    patterns: HashMap<Vec<u8>, Vec<u64>>,
+    /// The clients subscribed to each sharded channel, grouped by the hash slot of the channel
+    shard_channels: HashMap<u16, HashMap<Vec<u8>, Vec<u64>>>,
+    /// What each subscribed client is subscribed to
+    clients: HashMap<u64, Subscriptions>,
+    /// Encoded messages waiting to be delivered to each client
+    pending: HashMap<u64, Vec<u8>>,
+}
+
+impl PubSub {
+    /// The subscribers of the channels or patterns of a kind, or of the sharded channels in the
+    /// same slot as the name
+    fn subscribers(&mut self, kind: Kind, name: &[u8]) -> &mut HashMap<Vec<u8>, Vec<u64>> {
+        match kind {
+            Kind::Channel => &mut self.channels,
+            Kind::Pattern => &mut self.patterns,
+            Kind::ShardChannel => self
+                .shard_channels
+                .entry(cluster::key_slot(name))
+                .or_default(),
+        }
+    }
+
+    /// Subscribe a client to a channel or pattern, returning its number of subscriptions
+    fn subscribe(&mut self, id: u64, kind: Kind, name: &[u8]) -> usize {
+        let subscriptions = self.clients.entry(id).or_default();
+        if !subscriptions.of_kind(kind).iter().any(|n| n == name) {
+            subscriptions.of_kind(kind).push(name.to_vec());
+            self.subscribers(kind, name)
+                .entry(name.to_vec())
+                .or_default()
+                .push(id);
+        }
+        self.subscription_count(id, kind)
+    }
+
+    /// Unsubscribe a client from a channel or pattern, returning its number of subscriptions left
+    fn unsubscribe(&mut self, id: u64, kind: Kind, name: &[u8]) -> usize {
+        let Some(subscriptions) = self.clients.get_mut(&id) else {
+            return 0;
+        };
+        let names = subscriptions.of_kind(kind);
+        let Some(index) = names.iter().position(|n| n == name) else {
+            return subscriptions.count(kind);
+        };
+        names.remove(index);
+        let count = subscriptions.count(kind);
+        if subscriptions.is_empty() {
+            self.clients.remove(&id);
+        }
+
+        let subscribers = self.subscribers(kind, name);
+        if let Some(ids) = subscribers.get_mut(name) {
+            ids.retain(|&subscriber| subscriber != id);
+            if ids.is_empty() {
+                subscribers.remove(name);
+            }
+        }
+        if kind == Kind::ShardChannel {
+            let slot = cluster::key_slot(name);
+            if self
+                .shard_channels
+                .get(&slot)
+                .is_some_and(HashMap::is_empty)
+            {
+                self.shard_channels.remove(&slot);
+            }
+        }
+        count
+    }
+
+    /// The channels or patterns a client is subscribed to
+    fn subscriptions(&mut self, id: u64, kind: Kind) -> Vec<Vec<u8>> {
+        self.clients
+            .get_mut(&id)
+            .map(|subscriptions| subscriptions.of_kind(kind).clone())
+            .unwrap_or_default()
+    }
+
+    /// The number of subscriptions of a client, as replied with for the kind
+    fn subscription_count(&self, id: u64, kind: Kind) -> usize {
+        self.clients
+            .get(&id)
+            .map_or(0, |subscriptions| subscriptions.count(kind))
+    }
+
+    /// Whether a client has any subscriptions at all
+    fn is_subscribed(&self, id: u64) -> bool {
+        self.clients.contains_key(&id)
+    }
+
+    /// Remove all subscriptions of a client, and any messages waiting to be delivered to it
+    pub(crate) fn remove_client(&mut self, id: u64) {
+        for kind in [Kind::Channel, Kind::Pattern, Kind::ShardChannel] {
+            for name in self.subscriptions(id, kind) {
+                self.unsubscribe(id, kind, &name);
+            }
+        }
+        self.pending.remove(&id);
+    }
+
+    /// Queue a message for the subscribers of a channel and of the patterns matching it
+    ///
+    /// Returns the number of clients that will receive it, where a client subscribed more than
+    /// once through different patterns is counted for each of them.
+    fn publish(&mut self, channel: &[u8], message: &[u8]) -> usize {
+        let mut receivers = 0;
+
+        if let Some(ids) = self.channels.get(channel) {
+            let mut buf = Vec::new();
+            RESPData::Array(vec![
+                RESPData::BulkString(b"message"),
+                RESPData::BulkString(channel),
+                RESPData::BulkString(message),
+            ])
+            .encode(&mut buf);
+            for id in ids {
+                self.pending.entry(*id).or_default().extend_from_slice(&buf);
+            }
+            receivers += ids.len();
+        }
+
+        for (pattern, ids) in &self.patterns {
+            if !glob::matches(pattern, channel) {
+                continue;
+            }
+            let mut buf = Vec::new();
+            RESPData::Array(vec![
+                RESPData::BulkString(b"pmessage"),
+                RESPData::BulkString(pattern),
+                RESPData::BulkString(channel),
+                RESPData::BulkString(message),
+            ])
+            .encode(&mut buf);
+            for id in ids {
+                self.pending.entry(*id).or_default().extend_from_slice(&buf);
+            }
+            receivers += ids.len();
+        }
+
+        receivers
+    }
+
+    /// Queue a message for the subscribers of a sharded channel, returning how many there are
+    fn publish_sharded(&mut self, channel: &[u8], message: &[u8]) -> usize {
+        let slot = cluster::key_slot(channel);
+        let Some(ids) = self
+            .shard_channels
+            .get(&slot)
+            .and_then(|channels| channels.get(channel))
+        else {
+            return 0;
+        };
+
+        let mut buf = Vec::new();
+        RESPData::Array(vec![
+            RESPData::BulkString(b"smessage"),
+            RESPData::BulkString(channel),
+            RESPData::BulkString(message),
+        ])
+        .encode(&mut buf);
+        for id in ids {
+            self.pending.entry(*id).or_default().extend_from_slice(&buf);
+        }
+        ids.len()
+    }
+
+    /// Publish the keyspace events recorded since the last time, for the enabled classes
+    ///
+    /// Each event is published on the keyspace channel of its key and on the keyevent channel of
+    /// the event itself, if enabled.
+    pub(crate) fn publish_keyspace_events(&mut self, flags: u32) {
+        for event in notify::take_events() {
+            if flags & event.class == 0 {
+                continue;
+            }
+            if flags & notify::KEYSPACE != 0 {
+                let mut channel = format!("__keyspace@{}__:", event.db).into_bytes();
+                channel.extend_from_slice(&event.key);
+                self.publish(&channel, event.name.as_bytes());
+            }
+            if flags & notify::KEYEVENT != 0 {
+                let channel = format!("__keyevent@{}__:{}", event.db, event.name);
+                self.publish(channel.as_bytes(), &event.key);
+            }
+        }
+    }
+
+    pub(crate) fn take_pending(&mut self) -> HashMap<u64, Vec<u8>> {
+        std::mem::take(&mut self.pending)
+    }
+
+    /// Take the messages waiting to be delivered to a single client, if any
+    fn take_pending_for(&mut self, id: u64) -> Option<Vec<u8>> {
+        self.pending.remove(&id)
+    }
+
+    /// The channels, or sharded channels, with at least one subscriber, optionally only those
+    /// matching a pattern
+    fn active_channels(&self, sharded: bool, pattern: Option<&[u8]>) -> Vec<Vec<u8>> {
+        let channels: Box<dyn Iterator<Item = &Vec<u8>>> = if sharded {
+            Box::new(self.shard_channels.values().flat_map(HashMap::keys))
+        } else {
+            Box::new(self.channels.keys())
+        };
+        channels
+            .filter(|channel| pattern.is_none_or(|pattern| glob::matches(pattern, channel)))
+            .cloned()
+            .collect()
+    }
+
+    fn subscriber_count(&self, sharded: bool, channel: &[u8]) -> usize {
+        let subscribers = if sharded {
+            self.shard_channels
+                .get(&cluster::key_slot(channel))
+                .and_then(|channels| channels.get(channel))
+        } else {
+            self.channels.get(channel)
+        };
+        subscribers.map_or(0, Vec::len)
+    }
+
+    /// The number of distinct patterns that clients are subscribed to
+    fn pattern_count(&self) -> usize {
+        self.patterns.len()
+    }
+}
+
+/// Encode the reply to subscribing or unsubscribing, where the name is missing when
+/// unsubscribing from everything without having any subscriptions
+fn encode_subscription(buf: &mut Vec<u8>, kind: &[u8], name: Option<&[u8]>, count: usize) {
+    RESPData::Array(vec![
+        RESPData::BulkString(kind),
+        name.map_or(RESPData::Null, RESPData::BulkString),
+        RESPData::Integer(count as i64),
+    ])
+    .encode(buf);
+}
+
+impl Connection {
+    /// Whether the client has any subscriptions, in which case only a few commands are allowed
+    pub(super) fn is_subscribed(&self) -> bool {
+        self.pubsub.borrow().is_subscribed(self.id)
+    }
+
+    /// Publish the keyspace events recorded by the commands run since the last time
+    pub(super) fn publish_keyspace_events(&self) {
+        let flags = self.config.borrow().notify_keyspace_events();
+        self.pubsub.borrow_mut().publish_keyspace_events(flags);
+    }
+
+    /// Write messages published to the client
+    pub(crate) fn deliver(&mut self, messages: &[u8]) -> Result<()> {
+        self.stream.write_all(messages)?;
+        Ok(())
+    }
+
+    /// Write the messages published to the client so far, so that they come before the reply to
+    /// its next command rather than after it
+    pub(super) fn deliver_pending(&mut self) -> Result<()> {
+        let messages = self.pubsub.borrow_mut().take_pending_for(self.id);
+        match messages {
+            Some(messages) => self.deliver(&messages),
+            None => Ok(()),
+        }
+    }
+
+    pub(super) fn handle_subscribe(&mut self, args: &[RESPData], kind: Kind) -> Result<()> {
+        log::debug!(
+            "Received {}",
+            String::from_utf8_lossy(kind.subscribe_command())
+        );
+
+        let names = bulk_strings(args)?;
+        if names.is_empty() {
+            return client_error!(
+                "wrong number of arguments for '{}' command",
+                String::from_utf8_lossy(kind.subscribe_command())
+            );
+        }
+
+        let mut buf = Vec::new();
+        {
+            let mut pubsub = self.pubsub.borrow_mut();
+            for name in names {
+                let count = pubsub.subscribe(self.id, kind, name);
+                encode_subscription(&mut buf, kind.subscribe_command(), Some(name), count);
+            }
+        }
+        self.stream.write_all(&buf)?;
+        Ok(())
+    }
+
+    /// Unsubscribe from channels or patterns, or from all of them if none are given
+    pub(super) fn handle_unsubscribe(&mut self, args: &[RESPData], kind: Kind) -> Result<()> {
+        log::debug!(
+            "Received {}",
+            String::from_utf8_lossy(kind.unsubscribe_command())
+        );
+
+        let names = bulk_strings(args)?;
+        let mut buf = Vec::new();
+        {
+            let mut pubsub = self.pubsub.borrow_mut();
+            let names = if names.is_empty() {
+                pubsub.subscriptions(self.id, kind)
+            } else {
+                names.iter().map(|name| name.to_vec()).collect()
+            };
+            if names.is_empty() {
+                let count = pubsub.subscription_count(self.id, kind);
+                encode_subscription(&mut buf, kind.unsubscribe_command(), None, count);
+            }
+            for name in names {
+                let count = pubsub.unsubscribe(self.id, kind, &name);
+                encode_subscription(&mut buf, kind.unsubscribe_command(), Some(&name), count);
+            }
+        }
+        self.stream.write_all(&buf)?;
+        Ok(())
+    }
+
+    /// Publish a message to a channel, or to a sharded channel for SPUBLISH
+    pub(super) fn handle_publish(&mut self, args: &[RESPData], sharded: bool) -> Result<()> {
+        let command = if sharded { "spublish" } else { "publish" };
+        log::debug!("Received {}", command.to_uppercase());
+
+        let [RESPData::BulkString(channel), RESPData::BulkString(message)] = args else {
+            return client_error!("wrong number of arguments for '{}' command", command);
+        };
+
+        let mut pubsub = self.pubsub.borrow_mut();
+        let receivers = if sharded {
+            pubsub.publish_sharded(channel, message)
+        } else {
+            pubsub.publish(channel, message)
+        };
+        drop(pubsub);
+        self.write_integer(receivers as i64)
+    }
+
+    pub(super) fn handle_pubsub(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received PUBSUB");
+
+        let args = bulk_strings(args)?;
+        let Some((subcommand, args)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'pubsub' command");
+        };
+
+        match subcommand.to_ascii_uppercase().as_slice() {
+            subcommand @ (b"CHANNELS" | b"SHARDCHANNELS") => {
+                let sharded = subcommand == b"SHARDCHANNELS";
+                let pattern = match args {
+                    [] => None,
+                    [pattern] => Some(*pattern),
+                    _ => {
+                        return client_error!(
+                            "wrong number of arguments for 'pubsub|{}' command",
+                            String::from_utf8_lossy(subcommand).to_lowercase()
+                        )
+                    }
+                };
+                let channels = self.pubsub.borrow().active_channels(sharded, pattern);
+                self.write_array(channels.iter().map(Vec::as_slice).collect())
+            }
+            subcommand @ (b"NUMSUB" | b"SHARDNUMSUB") => {
+                let sharded = subcommand == b"SHARDNUMSUB";
+                let mut buf = Vec::new();
+                write!(buf, "*{}\r\n", args.len() * 2)?;
+                {
+                    let pubsub = self.pubsub.borrow();
+                    for channel in args {
+                        RESPData::BulkString(channel).encode(&mut buf);
+                        RESPData::Integer(pubsub.subscriber_count(sharded, channel) as i64)
+                            .encode(&mut buf);
+                    }
+                }
+                self.stream.write_all(&buf)?;
+                Ok(())
+            }
+            b"NUMPAT" => {
+                if !args.is_empty() {
+                    return client_error!("wrong number of arguments for 'pubsub|numpat' command");
+                }
+                let count = self.pubsub.borrow().pattern_count();
+                self.write_integer(count as i64)
+            }
+            _ => client_error!(
+                "unknown subcommand '{}'. Try PUBSUB HELP.",
+                String::from_utf8_lossy(subcommand)
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subscription_counts() {
+        let mut pubsub = PubSub::default();
+        assert_eq!(pubsub.subscribe(1, Kind::Channel, b"a"), 1);
+        assert_eq!(pubsub.subscribe(1, Kind::Channel, b"a"), 1);
+        assert_eq!(pubsub.subscribe(1, Kind::Pattern, b"a*"), 2);
+        assert_eq!(pubsub.subscribe(2, Kind::Channel, b"a"), 1);
+        assert_eq!(pubsub.subscriber_count(false, b"a"), 2);
+        assert_eq!(pubsub.pattern_count(), 1);
+
+        assert_eq!(pubsub.unsubscribe(1, Kind::Channel, b"b"), 2);
+        assert_eq!(pubsub.unsubscribe(1, Kind::Channel, b"a"), 1);
+        assert_eq!(pubsub.subscriber_count(false, b"a"), 1);
+
+        pubsub.remove_client(1);
+        assert!(!pubsub.is_subscribed(1));
+        assert_eq!(pubsub.pattern_count(), 0);
+        assert_eq!(pubsub.active_channels(false, None), vec![b"a".to_vec()]);
+    }
+
+    #[test]
+    fn test_shard_channels_are_separate() {
+        let mut pubsub = PubSub::default();
+        assert_eq!(pubsub.subscribe(1, Kind::Channel, b"a"), 1);
+        assert_eq!(pubsub.subscribe(1, Kind::ShardChannel, b"a"), 1);
+        assert_eq!(pubsub.subscribe(1, Kind::ShardChannel, b"{a}b"), 2);
+        assert_eq!(pubsub.subscriber_count(true, b"a"), 1);
+        assert_eq!(pubsub.subscriber_count(true, b"b"), 0);
+        assert_eq!(pubsub.shard_channels[&cluster::key_slot(b"a")].len(), 2);
+
+        assert_eq!(pubsub.publish(b"{a}b", b"hi"), 0);
+        assert_eq!(pubsub.publish_sharded(b"{a}b", b"hi"), 1);
+        assert_eq!(
+            pubsub.take_pending()[&1],
+            b"*3\r\n$8\r\nsmessage\r\n$4\r\n{a}b\r\n$2\r\nhi\r\n".to_vec()
+        );
+
+        assert_eq!(pubsub.unsubscribe(1, Kind::ShardChannel, b"a"), 1);
+        assert_eq!(pubsub.unsubscribe(1, Kind::ShardChannel, b"{a}b"), 0);
+        assert!(pubsub.shard_channels.is_empty());
+        assert!(pubsub.is_subscribed(1));
+        assert_eq!(pubsub.unsubscribe(1, Kind::Channel, b"a"), 0);
+        assert!(!pubsub.is_subscribed(1));
+    }
+
+    #[test]
+    fn test_publish_queues_messages() {
+        let mut pubsub = PubSub::default();
+        pubsub.subscribe(1, Kind::Channel, b"news");
+        pubsub.subscribe(2, Kind::Pattern, b"n*");
+        pubsub.subscribe(2, Kind::Pattern, b"*s");
+
+        assert_eq!(pubsub.publish(b"news", b"hi"), 3);
+        assert_eq!(pubsub.publish(b"other", b"hi"), 0);
+
+        let pending = pubsub.take_pending();
+        assert_eq!(
+            pending[&1],
+            b"*3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$2\r\nhi\r\n".to_vec()
+        );
+        assert_eq!(
+            pending[&2].len(),
+            2 * b"*4\r\n$8\r\npmessage\r\n$2\r\nn*\r\n$4\r\nnews\r\n$2\r\nhi\r\n".len()
+        );
+        assert!(pubsub.take_pending().is_empty());
+
+        pubsub.publish(b"news", b"hi");
+        assert!(pubsub.take_pending_for(1).is_some());
+        assert_eq!(pubsub.take_pending_for(1), None);
+        assert_eq!(pubsub.take_pending().len(), 1);
+    }
+}
@@ -0,0 +1,802 @@
+use super::{bulk_strings, commands, functions::Libraries, parse_int, Connection, OK};
+use crate::{
+    error::RustisError,
+    lua::{self, FunctionProto, Host, Lua, LuaError, LuaResult, Table, Value},
+    parsers,
+    resp::RESPData,
+    sha1::sha1_hex,
+    Result,
+};
+use std::{
+    collections::HashMap,
+    io::Write,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+/// How deeply tables returned by scripts can be nested, which stops tables that contain
+/// themselves from recursing forever
+const MAX_REPLY_DEPTH: usize = 1000;
+
+/// The log levels scripts pass to `redis.log`
+pub(super) const LOG_LEVELS: &[(&str, f64)] = &[
+    ("LOG_DEBUG", 0.0),
+    ("LOG_VERBOSE", 1.0),
+    ("LOG_NOTICE", 2.0),
+    ("LOG_WARNING", 3.0),
+];
+
+/// The replication flags scripts pass to `redis.set_repl`, which have no effect without replicas
+const REPL_FLAGS: &[(&str, f64)] = &[
+    ("REPL_NONE", 0.0),
+    ("REPL_AOF", 1.0),
+    ("REPL_SLAVE", 2.0),
+    ("REPL_REPLICA", 2.0),
+    ("REPL_ALL", 3.0),
+];
+
+/// A script in the cache, compiled once when it's loaded
+struct Script {
+    chunk: Rc<FunctionProto>,
+    /// Whether the shebang has the `no-writes` flag, which runs the script as if with EVAL_RO
+    no_writes: bool,
+}
+
+/// The script being run, by the client that ran it
+struct RunningScript {
+    client: u64,
+    started: Instant,
+    /// Whether it's a function run with FCALL, which is killed with FUNCTION KILL instead
+    function: bool,
+    /// Whether the script ran for longer than `busy-reply-threshold`, in which case other clients
+    /// are served while it runs, with BUSY errors
+    busy: bool,
+    /// Whether SCRIPT KILL was called, which stops the script at the next chance
+    killed: bool,
+    /// Whether the script ran a write command, in which case it can't be killed
+    wrote: bool,
+}
+
+impl RunningScript {
+    fn kill_command(&self) -> &'static str {
+        match self.function {
+            true => "FUNCTION KILL",
+            false => "SCRIPT KILL",
+        }
+    }
+}
+
+/// What a script runs, which is either the chunk of a script or the callback of a function
+pub(super) enum Entry<'a> {
+    Script {
+        sha: &'a str,
+        chunk: &'a Rc<FunctionProto>,
+    },
+    Function {
+        name: &'a str,
+        callback: &'a Value,
+    },
+}
+
+/// The scripts loaded with EVAL or SCRIPT LOAD, by their SHA1 digest, the libraries of functions
+/// and the script running
+#[derive(Default)]
+pub(crate) struct Scripts {
+    cache: HashMap<String, Rc<Script>>,
+    pub(super) libraries: Libraries,
+    running: Option<RunningScript>,
+    /// Serves the other clients while a script is busy, so that they can kill it
+    busy_handler: Option<Rc<dyn Fn()>>,
+}
+
+impl Scripts {
+    pub(crate) fn set_busy_handler(&mut self, handler: impl Fn() + 'static) {
+        self.busy_handler = Some(Rc::new(handler));
+    }
+
+    /// Compile a script and add it to the cache, returning its SHA1 digest
+    fn load(&mut self, body: &[u8]) -> Result<(String, Rc<Script>)> {
+        let sha = sha1_hex(body);
+        if let Some(script) = self.cache.get(&sha) {
+            return Ok((sha, Rc::clone(script)));
+        }
+
+        let (shebang_length, no_writes) = parse_shebang(body)?;
+        let chunk = match lua::compile(&body[shebang_length..], "user_script") {
+            Ok(chunk) => chunk,
+            Err(e) => return client_error!("Error compiling script (new function): {}", e),
+        };
+        let script = Rc::new(Script { chunk, no_writes });
+        self.cache.insert(sha.clone(), Rc::clone(&script));
+        Ok((sha, script))
+    }
+}
+
+/// Parse the shebang of a script, such as "#!lua flags=no-writes", returning its length and
+/// whether the script doesn't write
+///
+/// The newline ending the shebang is left for the script, so that line numbers stay the same.
+fn parse_shebang(body: &[u8]) -> Result<(usize, bool)> {
+    if !body.starts_with(b"#!") {
+        return Ok((0, false));
+    }
+    let end = body.iter().position(|&c| c == b'\n').unwrap_or(body.len());
+    let shebang = String::from_utf8_lossy(&body[2..end]);
+    let mut parts = shebang.split(' ').filter(|part| !part.is_empty());
+
+    let engine = parts.next().unwrap_or_default();
+    if engine != "lua" {
+        return client_error!("Unexpected engine in script shebang: {}", engine);
+    }
+
+    let mut no_writes = false;
+    for part in parts {
+        let Some(flags) = part.strip_prefix("flags=") else {
+            return client_error!("Unknown lua shebang option: {}", part);
+        };
+        for flag in flags.split(',').filter(|flag| !flag.is_empty()) {
+            match flag {
+                "no-writes" => no_writes = true,
+                "allow-oom" | "allow-stale" | "no-cluster" | "allow-cross-slot-keys" => {}
+                _ => return client_error!("Unexpected flag in script shebang: {}", flag),
+            }
+        }
+    }
+    Ok((end, no_writes))
+}
+
+/// Check whether a command can run while another client's script is busy, which is only
+/// SCRIPT KILL and FUNCTION KILL
+pub(super) fn allowed_while_busy(command: &[u8], array: &[RESPData]) -> bool {
+    (command == b"SCRIPT" || command == b"FUNCTION")
+        && matches!(array.get(1), Some(RESPData::BulkString(subcommand))
+            if subcommand.eq_ignore_ascii_case(b"KILL"))
+}
+
+/// An error as `redis.call` raises it, with the error code in the message, such as "ERR oops"
+fn error_table(message: impl AsRef<[u8]>) -> Value {
+    let mut table = Table::new();
+    table.set_str("err", Value::string(message));
+    Value::table(table)
+}
+
+fn status_table(message: impl AsRef<[u8]>) -> Value {
+    let mut table = Table::new();
+    table.set_str("ok", Value::string(message));
+    Value::table(table)
+}
+
+fn is_error_table(value: &Value) -> bool {
+    match value {
+        Value::Table(table) => matches!(table.borrow().get_str("err"), Value::String(_)),
+        _ => false,
+    }
+}
+
+/// Raise an error from the redis library, which like the replies of failed commands is a table
+pub(super) fn raise_error(lua: &Lua, message: &str) -> LuaError {
+    lua.raise(error_table(format!("ERR {}", message)))
+}
+
+/// Convert the reply of a command to a Lua value
+///
+/// Status replies and errors are tables with an "ok" and "err" field, and nulls are false.
+fn reply_to_value(data: &RESPData) -> Value {
+    match data {
+        RESPData::SimpleString(s) => status_table(s),
+        RESPData::SimpleError(e) => error_table(e),
+        RESPData::Integer(i) => Value::Number(*i as f64),
+        RESPData::BulkString(s) => Value::string(s),
+        RESPData::Array(elements) => Value::table(Table::from_array(
+            elements.iter().map(reply_to_value).collect(),
+        )),
+        RESPData::Null => Value::Boolean(false),
+    }
+}
+
+/// Replace newlines, which would break the protocol, in status replies and errors
+fn single_line(s: &[u8]) -> Vec<u8> {
+    s.iter()
+        .map(|&c| if c == b'\r' || c == b'\n' { b' ' } else { c })
+        .collect()
+}
+
+/// Convert the value returned by a script to a reply
+///
+/// Numbers are truncated to integers, true is 1 and false is null. Tables with an "err" or "ok"
+/// field are errors and status replies, and other tables are arrays up to their first nil.
+fn encode_value(value: &Value, depth: usize, buf: &mut Vec<u8>) {
+    match value {
+        Value::Number(n) => RESPData::Integer(*n as i64).encode(buf),
+        Value::String(s) => RESPData::BulkString(s).encode(buf),
+        Value::Boolean(true) => RESPData::Integer(1).encode(buf),
+        Value::Table(_) if depth >= MAX_REPLY_DEPTH => {
+            RESPData::SimpleError(b"ERR reached lua stack limit").encode(buf)
+        }
+        Value::Table(table) => {
+            let table = table.borrow();
+            if let Value::String(e) = table.get_str("err") {
+                return RESPData::SimpleError(&single_line(&e)).encode(buf);
+            }
+            if let Value::String(s) = table.get_str("ok") {
+                return RESPData::SimpleString(&single_line(&s)).encode(buf);
+            }
+            let elements: Vec<Value> = (1..)
+                .map(|i| table.get(&Value::Number(i as f64)))
+                .take_while(|value| !value.is_nil())
+                .collect();
+            write!(buf, "*{}\r\n", elements.len()).unwrap();
+            for element in &elements {
+                encode_value(element, depth + 1, buf);
+            }
+        }
+        _ => RESPData::Null.encode(buf),
+    }
+}
+
+/// The message of an error raised by a script, with the error code if it was a table like the
+/// replies of failed commands
+pub(super) fn error_message(error: &LuaError) -> String {
+    match &error.value {
+        Value::Table(table) => match table.borrow().get_str("err") {
+            Value::String(e) => String::from_utf8_lossy(&e).into_owned(),
+            _ => "ERR unknown error".to_string(),
+        },
+        _ => format!("ERR {}", error.message()),
+    }
+}
+
+/// The error reply for a script that failed, saying where it failed
+fn script_error(error: &LuaError, entry: &Entry) -> Vec<u8> {
+    let (name, chunk_name) = match entry {
+        Entry::Script { sha, .. } => (*sha, "user_script"),
+        Entry::Function { name, .. } => (*name, "user_function"),
+    };
+    let message = format!(
+        "{} script: {}, on @{}:{}.",
+        error_message(error),
+        name,
+        chunk_name,
+        error.line
+    );
+    single_line(message.as_bytes())
+}
+
+/// Convert the arguments to `redis.call`, which must be strings or numbers
+fn command_args(lua: &Lua, args: &[Value]) -> LuaResult<Vec<Vec<u8>>> {
+    if args.is_empty() {
+        return Err(raise_error(
+            lua,
+            "Please specify at least one argument for this redis lib call",
+        ));
+    }
+    args.iter()
+        .map(|arg| match arg {
+            Value::String(s) => Ok(s.to_vec()),
+            // Numbers are formatted without losing precision, unlike tostring
+            Value::Number(n) if n.fract() == 0.0 && n.abs() < 1e17 => {
+                Ok((*n as i64).to_string().into_bytes())
+            }
+            Value::Number(n) => Ok(n.to_string().into_bytes()),
+            _ => Err(raise_error(
+                lua,
+                "Lua redis lib command arguments must be strings or integers",
+            )),
+        })
+        .collect()
+}
+
+/// `redis.call` and `redis.pcall`, where errors are raised by the former and returned by the latter
+fn call(lua: &mut Lua, args: Vec<Value>, raise: bool) -> LuaResult<Vec<Value>> {
+    let args = command_args(lua, &args)?;
+    let reply = lua.host().call(args)?;
+    if raise && is_error_table(&reply) {
+        return Err(lua.raise(reply));
+    }
+    Ok(vec![reply])
+}
+
+fn error_reply(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let [Value::String(message)] = args.as_slice() else {
+        return Err(raise_error(lua, "wrong number or type of arguments"));
+    };
+    // The error code is the first word, as long as there's a message after it
+    let message = message.strip_prefix(b"-").unwrap_or(message);
+    let message = match message.contains(&b' ') {
+        true => message.to_vec(),
+        false => [b"ERR ", message].concat(),
+    };
+    Ok(vec![error_table(message)])
+}
+
+fn status_reply(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let [Value::String(message)] = args.as_slice() else {
+        return Err(raise_error(lua, "wrong number or type of arguments"));
+    };
+    Ok(vec![status_table(message)])
+}
+
+fn sha1hex(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let [value] = args.as_slice() else {
+        return Err(raise_error(lua, "wrong number of arguments"));
+    };
+    let data = value.to_bytes().unwrap_or_default();
+    Ok(vec![Value::string(sha1_hex(&data))])
+}
+
+pub(super) fn log(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    if args.len() < 2 {
+        return Err(raise_error(
+            lua,
+            "redis.log() requires two arguments or more.",
+        ));
+    }
+    let Value::Number(level) = args[0] else {
+        return Err(raise_error(lua, "First argument must be a number"));
+    };
+    let level = match level as i64 {
+        0 => log::Level::Debug,
+        1 => log::Level::Debug,
+        2 => log::Level::Info,
+        3 => log::Level::Warn,
+        _ => return Err(raise_error(lua, "Invalid debug level.")),
+    };
+    let message: Vec<String> = args[1..]
+        .iter()
+        .filter_map(Value::to_bytes)
+        .map(|s| String::from_utf8_lossy(&s).into_owned())
+        .collect();
+    log::log!(level, "{}", message.join(" "));
+    Ok(Vec::new())
+}
+
+/// `redis.setresp`, where either version is accepted, as replies are always RESP2
+fn setresp(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let [version] = args.as_slice() else {
+        return Err(raise_error(lua, "redis.setresp() requires one argument."));
+    };
+    if !matches!(version.to_number(), Some(n) if n == 2.0 || n == 3.0) {
+        return Err(raise_error(lua, "RESP version must be 2 or 3."));
+    }
+    Ok(Vec::new())
+}
+
+fn set_repl(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let [flags] = args.as_slice() else {
+        return Err(raise_error(lua, "redis.set_repl() requires one argument."));
+    };
+    if !matches!(flags.to_number(), Some(n) if (0.0..=3.0).contains(&n)) {
+        return Err(raise_error(
+            lua,
+            "Invalid replication flags. Use REPL_AOF, REPL_REPLICA, REPL_ALL or REPL_NONE.",
+        ));
+    }
+    Ok(Vec::new())
+}
+
+/// Load the `redis` library into an interpreter
+fn open_redis(lua: &mut Lua) {
+    let mut redis = Table::new();
+    redis.set_str(
+        "call",
+        Value::native("call", |lua, args| call(lua, args, true)),
+    );
+    redis.set_str(
+        "pcall",
+        Value::native("pcall", |lua, args| call(lua, args, false)),
+    );
+    redis.set_str("error_reply", Value::native("error_reply", error_reply));
+    redis.set_str("status_reply", Value::native("status_reply", status_reply));
+    redis.set_str("sha1hex", Value::native("sha1hex", sha1hex));
+    redis.set_str("log", Value::native("log", log));
+    redis.set_str("setresp", Value::native("setresp", setresp));
+    redis.set_str("set_repl", Value::native("set_repl", set_repl));
+    redis.set_str(
+        "replicate_commands",
+        Value::native("replicate_commands", |_, _| Ok(vec![Value::Boolean(true)])),
+    );
+    for &(name, value) in LOG_LEVELS.iter().chain(REPL_FLAGS) {
+        redis.set_str(name, Value::Number(value));
+    }
+    lua.set_global("redis", Value::table(redis));
+}
+
+/// Parse the number of keys given to EVAL and FCALL, which can't be more than the arguments
+pub(super) fn parse_numkeys(numkeys: &[u8], args: usize) -> Result<usize> {
+    let numkeys: i64 = parse_int(numkeys)?;
+    if numkeys < 0 {
+        return client_error!("Number of keys can't be negative");
+    }
+    if numkeys as usize > args {
+        return client_error!("Number of keys can't be greater than number of args");
+    }
+    Ok(numkeys as usize)
+}
+
+fn strings_table(strings: &[&[u8]]) -> Value {
+    Value::table(Table::from_array(
+        strings.iter().map(Value::string).collect(),
+    ))
+}
+
+/// Runs the commands of a script on the connection that ran it
+struct ScriptHost<'a> {
+    conn: &'a mut Connection,
+    read_only: bool,
+}
+
+impl ScriptHost<'_> {
+    /// Check whether a script can run a command, returning the error if it can't
+    fn check_command(&mut self, name: &[u8], args: usize) -> Option<&'static str> {
+        let Some(command) = commands::lookup(&name.to_ascii_uppercase()) else {
+            return Some("ERR Unknown Redis command called from script");
+        };
+        if !command.allowed_in_scripts() {
+            return Some("ERR This Redis command is not allowed from script");
+        }
+        if !command.arity_matches(args) {
+            return Some("ERR Wrong number of args calling Redis command from script");
+        }
+        if command.is_write() {
+            if self.read_only {
+                return Some("ERR Write commands are not allowed from read-only scripts.");
+            }
+            if let Some(running) = self.conn.scripts.borrow_mut().running.as_mut() {
+                running.wrote = true;
+            }
+        }
+        None
+    }
+}
+
+impl Host for ScriptHost<'_> {
+    /// Run a command as if the client sent it, capturing the reply instead of writing it
+    ///
+    /// Commands that would block reply as if they timed out right away, as in transactions.
+    fn call(&mut self, args: Vec<Vec<u8>>) -> LuaResult<Value> {
+        if let Some(error) = self.check_command(&args[0], args.len()) {
+            return Ok(error_table(error));
+        }
+
+        let conn = &mut *self.conn;
+        let array: Vec<RESPData> = args.iter().map(|arg| RESPData::BulkString(arg)).collect();
+        conn.stream.captured = Some(Vec::new());
+        let result = conn
+            .process_array(&array)
+            .or_else(|e| conn.write_command_error(e))
+            .and_then(|()| match conn.is_blocked() {
+                true => conn.time_out(),
+                false => Ok(()),
+            });
+        let captured = conn.stream.captured.take().unwrap_or_default();
+
+        if let Err(e) = result {
+            return Ok(error_table(format!("ERR {}", e)));
+        }
+        match parsers::resp_data::parse_one(&captured) {
+            Ok(Some((reply, _))) => Ok(reply_to_value(&reply)),
+            _ => Ok(error_table("ERR invalid reply from command")),
+        }
+    }
+
+    /// Serve the other clients once the script has run for too long, and stop it if they kill it
+    fn hook(&mut self) -> LuaResult<()> {
+        let threshold = Duration::from_millis(self.conn.config.borrow().busy_reply_threshold);
+        let handler = {
+            let mut scripts = self.conn.scripts.borrow_mut();
+            let Some(running) = scripts.running.as_mut() else {
+                return Ok(());
+            };
+            if running.started.elapsed() < threshold {
+                return Ok(());
+            }
+            if !running.busy {
+                log::warn!(
+                    "Slow script detected: still in execution after {} milliseconds. You can try \
+                     killing the script using the {} command.",
+                    running.started.elapsed().as_millis(),
+                    running.kill_command()
+                );
+                running.busy = true;
+            }
+            scripts.busy_handler.clone()
+        };
+        if let Some(handler) = handler {
+            handler();
+        }
+
+        let killed = self
+            .conn
+            .scripts
+            .borrow()
+            .running
+            .as_ref()
+            .is_some_and(|running| running.killed);
+        if killed {
+            return Err(LuaError {
+                value: error_table("ERR Script killed by user with SCRIPT KILL..."),
+                line: 0,
+                fatal: true,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Connection {
+    /// Check whether another client's script is busy, in which case commands are refused
+    pub(super) fn is_busy(&self) -> bool {
+        self.scripts
+            .borrow()
+            .running
+            .as_ref()
+            .is_some_and(|running| running.client != self.id)
+    }
+
+    /// The error for commands refused while another client's script is busy
+    pub(super) fn busy_error(&self) -> RustisError {
+        let kill_command = self
+            .scripts
+            .borrow()
+            .running
+            .as_ref()
+            .map_or("SCRIPT KILL", RunningScript::kill_command);
+        RustisError::ClientErrorWithCode(
+            "BUSY",
+            format!(
+                "Redis is busy running a script. You can only call {} or SHUTDOWN NOSAVE.",
+                kill_command
+            ),
+        )
+    }
+
+    /// Handle EVAL and its variants, which run a script given either in full or by its digest
+    pub(super) fn handle_eval(
+        &mut self,
+        args: &[RESPData],
+        name: &[u8],
+        by_sha: bool,
+        read_only: bool,
+    ) -> Result<()> {
+        log::debug!("Received {}", String::from_utf8_lossy(name).to_uppercase());
+
+        let [RESPData::BulkString(script), RESPData::BulkString(numkeys), rest @ ..] = args else {
+            return client_error!(
+                "wrong number of arguments for '{}' command",
+                String::from_utf8_lossy(name)
+            );
+        };
+        let rest = bulk_strings(rest)?;
+        let (keys, argv) = rest.split_at(parse_numkeys(numkeys, rest.len())?);
+
+        let (sha, script) = if by_sha {
+            let sha = String::from_utf8_lossy(script).to_lowercase();
+            let Some(script) = self.scripts.borrow().cache.get(&sha).cloned() else {
+                return Err(RustisError::ClientErrorWithCode(
+                    "NOSCRIPT",
+                    "No matching script. Please use EVAL.".to_string(),
+                ));
+            };
+            (sha, script)
+        } else {
+            self.scripts.borrow_mut().load(script)?
+        };
+
+        let entry = Entry::Script {
+            sha: &sha,
+            chunk: &script.chunk,
+        };
+        self.run_script(entry, keys, argv, read_only || script.no_writes)
+    }
+
+    /// Run a script, replying with what it returns or the error it raised
+    ///
+    /// Scripts get their keys and arguments in the KEYS and ARGV globals, and functions get them as
+    /// their two arguments.
+    pub(super) fn run_script(
+        &mut self,
+        entry: Entry,
+        keys: &[&[u8]],
+        argv: &[&[u8]],
+        read_only: bool,
+    ) -> Result<()> {
+        self.scripts.borrow_mut().running = Some(RunningScript {
+            client: self.id,
+            started: Instant::now(),
+            function: matches!(entry, Entry::Function { .. }),
+            busy: false,
+            killed: false,
+            wrote: false,
+        });
+
+        let result = {
+            let mut host = ScriptHost {
+                conn: self,
+                read_only,
+            };
+            match &entry {
+                Entry::Script { chunk, .. } => {
+                    let mut lua = Lua::new(&mut host, "user_script");
+                    open_redis(&mut lua);
+                    lua.set_global("KEYS", strings_table(keys));
+                    lua.set_global("ARGV", strings_table(argv));
+                    lua.protect_globals();
+                    lua.execute(chunk)
+                }
+                Entry::Function { callback, .. } => {
+                    let mut lua = Lua::new(&mut host, "user_function");
+                    open_redis(&mut lua);
+                    lua.protect_globals();
+                    lua.call(callback, vec![strings_table(keys), strings_table(argv)])
+                }
+            }
+        };
+
+        let running = self.scripts.borrow_mut().running.take();
+        if let Some(running) = running.filter(|running| running.busy) {
+            log::warn!(
+                "Slow script finished after {} milliseconds",
+                running.started.elapsed().as_millis()
+            );
+        }
+
+        let mut buf = Vec::new();
+        match result {
+            Ok(values) => encode_value(values.first().unwrap_or(&Value::Nil), 0, &mut buf),
+            Err(e) => RESPData::SimpleError(&script_error(&e, &entry)).encode(&mut buf),
+        }
+        self.stream.write_all(&buf)?;
+        Ok(())
+    }
+
+    pub(super) fn handle_script(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received SCRIPT");
+
+        let Some((RESPData::BulkString(subcommand), args)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'script' command");
+        };
+
+        match (subcommand.to_ascii_uppercase().as_slice(), args) {
+            (b"LOAD", [RESPData::BulkString(body)]) => {
+                let (sha, _) = self.scripts.borrow_mut().load(body)?;
+                self.write_bulk_string(sha.as_bytes())
+            }
+            (b"EXISTS", shas) if !shas.is_empty() => {
+                let shas = bulk_strings(shas)?;
+                let exists = {
+                    let scripts = self.scripts.borrow();
+                    shas.iter()
+                        .map(|sha| {
+                            let sha = String::from_utf8_lossy(sha).to_lowercase();
+                            RESPData::Integer(scripts.cache.contains_key(&sha) as i64)
+                        })
+                        .collect()
+                };
+                self.write_resp(&RESPData::Array(exists))
+            }
+            (b"FLUSH", []) => self.flush_scripts(),
+            (b"FLUSH", [RESPData::BulkString(mode)])
+                if mode.eq_ignore_ascii_case(b"ASYNC") || mode.eq_ignore_ascii_case(b"SYNC") =>
+            {
+                self.flush_scripts()
+            }
+            (b"FLUSH", [_]) => client_error!("SCRIPT FLUSH only support SYNC|ASYNC option"),
+            (b"KILL", []) => self.kill_script(false),
+            (subcommand @ (b"LOAD" | b"EXISTS" | b"FLUSH" | b"KILL"), _) => client_error!(
+                "wrong number of arguments for 'script|{}' command",
+                String::from_utf8_lossy(subcommand).to_lowercase()
+            ),
+            _ => client_error!(
+                "unknown subcommand '{}'. Try SCRIPT HELP.",
+                String::from_utf8_lossy(subcommand)
+            ),
+        }
+    }
+
+    fn flush_scripts(&mut self) -> Result<()> {
+        self.scripts.borrow_mut().cache.clear();
+        self.stream.write_all(OK)?;
+        Ok(())
+    }
+
+    /// Kill the running script, or function, which stops at its next chance unless it has written
+    pub(super) fn kill_script(&mut self, function: bool) -> Result<()> {
+        {
+            let mut scripts = self.scripts.borrow_mut();
+            match scripts.running.as_mut() {
+                None => {
+                    return Err(RustisError::ClientErrorWithCode(
+                        "NOTBUSY",
+                        "No scripts in execution right now.".to_string(),
+                    ))
+                }
+                Some(running) if running.function != function => {
+                    return Err(RustisError::ClientErrorWithCode(
+                        "BUSY",
+                        format!(
+                            "Redis is busy running a script. You can only call {} or SHUTDOWN \
+                             NOSAVE.",
+                            running.kill_command()
+                        ),
+                    ))
+                }
+                Some(running) if running.wrote => {
+                    return Err(RustisError::ClientErrorWithCode(
+                        "UNKILLABLE",
+                        "Sorry the script already executed write commands against the dataset. \
+                         You can either wait the script termination or kill the server in a hard \
+                         way using the SHUTDOWN NOSAVE command."
+                            .to_string(),
+                    ))
+                }
+                Some(running) => running.killed = true,
+            }
+        }
+        self.stream.write_all(OK)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded(value: &Value) -> Vec<u8> {
+        let mut buf = Vec::new();
+        encode_value(value, 0, &mut buf);
+        buf
+    }
+
+    #[test]
+    fn test_parse_shebang() {
+        assert_eq!(parse_shebang(b"return 1").unwrap(), (0, false));
+        assert_eq!(parse_shebang(b"#!lua\nreturn 1").unwrap(), (5, false));
+        assert_eq!(
+            parse_shebang(b"#!lua flags=no-writes,allow-oom\nreturn 1").unwrap(),
+            (31, true)
+        );
+        assert!(parse_shebang(b"#!python\nreturn 1").is_err());
+        assert!(parse_shebang(b"#!lua flags=nope\nreturn 1").is_err());
+        assert!(parse_shebang(b"#!lua name=x\nreturn 1").is_err());
+    }
+
+    #[test]
+    fn test_encode_value() {
+        assert_eq!(encoded(&Value::Number(3.9)), b":3\r\n");
+        assert_eq!(encoded(&Value::string("foo")), b"$3\r\nfoo\r\n");
+        assert_eq!(encoded(&Value::Boolean(true)), b":1\r\n");
+        assert_eq!(encoded(&Value::Boolean(false)), b"$-1\r\n");
+        assert_eq!(encoded(&Value::Nil), b"$-1\r\n");
+        assert_eq!(
+            encoded(&error_table("ERR bad\nthing")),
+            b"-ERR bad thing\r\n"
+        );
+        assert_eq!(encoded(&status_table("FINE")), b"+FINE\r\n");
+
+        let array = Table::from_array(vec![
+            Value::Number(1.0),
+            Value::string("two"),
+            Value::Nil,
+            Value::Number(4.0),
+        ]);
+        assert_eq!(encoded(&Value::table(array)), b"*2\r\n:1\r\n$3\r\ntwo\r\n");
+    }
+
+    #[test]
+    fn test_reply_to_value() {
+        assert!(matches!(reply_to_value(&RESPData::Integer(5)), Value::Number(n) if n == 5.0));
+        assert!(matches!(
+            reply_to_value(&RESPData::Null),
+            Value::Boolean(false)
+        ));
+        assert!(is_error_table(&reply_to_value(&RESPData::SimpleError(
+            b"ERR x"
+        ))));
+
+        let value = reply_to_value(&RESPData::Array(vec![
+            RESPData::BulkString(b"a"),
+            RESPData::SimpleString(b"OK"),
+        ]));
+        assert_eq!(encoded(&value), b"*2\r\n$1\r\na\r\n+OK\r\n");
+    }
+}
@@ -0,0 +1,457 @@
+use super::{bulk_strings, parse_int, Connection, EMPTY_ARRAY, NULL};
+use crate::{
+    database::{self, DbHandle, Set, Value},
+    error::RustisError,
+    notify, random,
+    resp::RESPData,
+    Result,
+};
+use std::{collections::HashSet, io::Write};
+
+/// How the members of multiple sets are combined, by SINTER, SUNION and SDIFF
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum SetOperation {
+    Inter,
+    Union,
+    Diff,
+}
+
+/// Remove a key if it holds a set that has become empty, as empty sets are never kept around
+fn remove_if_empty(db: &mut DbHandle, key: &[u8]) {
+    if matches!(db.get_mut(key), Some(Value::Set(set)) if set.is_empty()) {
+        db.remove(key);
+        db.notify(notify::GENERIC, "del", key);
+    }
+}
+
+/// Look up a set for modification, creating an empty one if the key doesn't exist
+fn get_or_create_set<'a>(db: &'a mut DbHandle, key: &[u8]) -> Result<&'a mut Set> {
+    if db.get_set_mut(key)?.is_none() {
+        db.insert(key, Value::Set(Set::new()));
+    }
+    Ok(db.get_set_mut(key)?.unwrap())
+}
+
+/// Look up the sets of all the keys, where keys that don't exist are `None`
+///
+/// Every key is type checked before anything is combined, so a WRONGTYPE error is returned even if
+/// an earlier key doesn't exist
+fn lookup_sets(db: &mut DbHandle, keys: &[&[u8]]) -> Result<Vec<Option<Set>>> {
+    keys.iter()
+        .map(|key| Ok(db.get_set(key)?.cloned()))
+        .collect()
+}
+
+/// Combine the members of the sets, where missing sets are treated as empty
+///
+/// The intersection is found by checking the members of the smallest set against the others
+fn combine(sets: &[Option<Set>], operation: SetOperation) -> Vec<Vec<u8>> {
+    match operation {
+        SetOperation::Inter => {
+            let Some(sets) = sets
+                .iter()
+                .map(Option::as_ref)
+                .collect::<Option<Vec<&Set>>>()
+            else {
+                return Vec::new();
+            };
+            let Some(smallest) = sets.iter().min_by_key(|set| set.len()) else {
+                return Vec::new();
+            };
+            smallest
+                .iter()
+                .filter(|member| sets.iter().all(|set| set.contains(member)))
+                .map(|member| member.into_owned())
+                .collect()
+        }
+        SetOperation::Union => {
+            let mut seen = HashSet::new();
+            let mut members = Vec::new();
+            for set in sets.iter().flatten() {
+                for member in set.iter() {
+                    if seen.insert(member.clone()) {
+                        members.push(member.into_owned());
+                    }
+                }
+            }
+            members
+        }
+        SetOperation::Diff => {
+            let Some((Some(first), rest)) = sets.split_first() else {
+                return Vec::new();
+            };
+            first
+                .iter()
+                .filter(|member| !rest.iter().flatten().any(|set| set.contains(member)))
+                .map(|member| member.into_owned())
+                .collect()
+        }
+    }
+}
+
+impl Connection {
+    /// The maximum number of members in an intset, from the config
+    fn set_max_intset_entries(&self) -> usize {
+        self.config.borrow().set_max_intset_entries
+    }
+
+    pub(super) fn handle_sadd(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received SADD");
+
+        let args = bulk_strings(args)?;
+        let Some((key, members)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'sadd' command");
+        };
+        if members.is_empty() {
+            return client_error!("wrong number of arguments for 'sadd' command");
+        }
+
+        let max_intset_entries = self.set_max_intset_entries();
+        let mut db = database::lock(0);
+        let set = get_or_create_set(&mut db, key)?;
+        let added = members
+            .iter()
+            .filter(|member| set.insert(member.to_vec(), max_intset_entries))
+            .count();
+        if added > 0 {
+            db.touch(key);
+            db.notify(notify::SET, "sadd", key);
+        }
+        drop(db);
+
+        self.write_integer(added as i64)
+    }
+
+    pub(super) fn handle_srem(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received SREM");
+
+        let args = bulk_strings(args)?;
+        let Some((key, members)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'srem' command");
+        };
+        if members.is_empty() {
+            return client_error!("wrong number of arguments for 'srem' command");
+        }
+
+        let mut db = database::lock(0);
+        let Some(set) = db.get_set_mut(key)? else {
+            return self.write_integer(0);
+        };
+        let removed = members.iter().filter(|member| set.remove(member)).count();
+        if removed > 0 {
+            db.touch(key);
+            db.notify(notify::SET, "srem", key);
+        }
+        remove_if_empty(&mut db, key);
+        drop(db);
+
+        self.write_integer(removed as i64)
+    }
+
+    pub(super) fn handle_smembers(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received SMEMBERS");
+
+        let [RESPData::BulkString(key)] = args else {
+            return client_error!("wrong number of arguments for 'smembers' command");
+        };
+
+        let mut db = database::lock(0);
+        let Some(set) = db.get_set(key)? else {
+            self.stream.write_all(EMPTY_ARRAY)?;
+            return Ok(());
+        };
+        let members: Vec<_> = set.iter().collect();
+
+        self.write_array(members.iter().map(|member| member.as_ref()).collect())
+    }
+
+    pub(super) fn handle_sismember(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received SISMEMBER");
+
+        let [RESPData::BulkString(key), RESPData::BulkString(member)] = args else {
+            return client_error!("wrong number of arguments for 'sismember' command");
+        };
+
+        let is_member = database::lock(0)
+            .get_set(key)?
+            .is_some_and(|set| set.contains(member));
+
+        self.write_integer(is_member as i64)
+    }
+
+    pub(super) fn handle_smismember(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received SMISMEMBER");
+
+        let args = bulk_strings(args)?;
+        let Some((key, members)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'smismember' command");
+        };
+        if members.is_empty() {
+            return client_error!("wrong number of arguments for 'smismember' command");
+        }
+
+        let mut db = database::lock(0);
+        let set = db.get_set(key)?;
+        let replies = members
+            .iter()
+            .map(|member| RESPData::Integer(set.is_some_and(|set| set.contains(member)) as i64))
+            .collect();
+        drop(db);
+
+        self.write_resp(&RESPData::Array(replies))
+    }
+
+    pub(super) fn handle_scard(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received SCARD");
+
+        let [RESPData::BulkString(key)] = args else {
+            return client_error!("wrong number of arguments for 'scard' command");
+        };
+
+        let length = database::lock(0).get_set(key)?.map_or(0, |set| set.len());
+
+        self.write_integer(length as i64)
+    }
+
+    pub(super) fn handle_spop(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received SPOP");
+
+        let (key, count) = match args {
+            [RESPData::BulkString(key)] => (key, None),
+            [RESPData::BulkString(key), RESPData::BulkString(count)] => {
+                match parse_int::<i64>(count)? {
+                    count if count < 0 => {
+                        return client_error!("value is out of range, must be positive")
+                    }
+                    count => (key, Some(count as usize)),
+                }
+            }
+            [_, _, _, ..] => return client_error!("syntax error"),
+            _ => return client_error!("wrong number of arguments for 'spop' command"),
+        };
+
+        let mut db = database::lock(0);
+        let Some(set) = db.get_set_mut(key)? else {
+            drop(db);
+            self.stream
+                .write_all(if count.is_some() { EMPTY_ARRAY } else { NULL })?;
+            return Ok(());
+        };
+
+        let members: Vec<Vec<u8>> = set.iter().map(|member| member.into_owned()).collect();
+        let popped: Vec<Vec<u8>> = random::distinct_indexes(members.len(), count.unwrap_or(1))
+            .into_iter()
+            .map(|index| members[index].clone())
+            .collect();
+        for member in &popped {
+            set.remove(member);
+        }
+        if !popped.is_empty() {
+            db.touch(key);
+            db.notify(notify::SET, "spop", key);
+        }
+        remove_if_empty(&mut db, key);
+        drop(db);
+
+        match count {
+            Some(_) => self.write_array(popped.iter().map(|member| member.as_slice()).collect()),
+            None => self.write_bulk_string(&popped[0]),
+        }
+    }
+
+    pub(super) fn handle_srandmember(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received SRANDMEMBER");
+
+        let (key, count) = match args {
+            [RESPData::BulkString(key)] => (key, None),
+            [RESPData::BulkString(key), RESPData::BulkString(count)] => {
+                (key, Some(parse_int::<i64>(count)?))
+            }
+            [_, _, _, ..] => return client_error!("syntax error"),
+            _ => return client_error!("wrong number of arguments for 'srandmember' command"),
+        };
+
+        // Keep the reply from growing unreasonably large, like Redis does
+        if count.is_some_and(|count| count.unsigned_abs() > i64::MAX as u64 / 2) {
+            return client_error!("value is out of range");
+        }
+
+        let mut db = database::lock(0);
+        let members: Vec<Vec<u8>> = match db.get_set(key)? {
+            Some(set) => set.iter().map(|member| member.into_owned()).collect(),
+            None => Vec::new(),
+        };
+        drop(db);
+
+        let Some(count) = count else {
+            if members.is_empty() {
+                self.stream.write_all(NULL)?;
+                return Ok(());
+            }
+            return self.write_bulk_string(&members[random::index(members.len())]);
+        };
+
+        // A positive count returns distinct members, while a negative count can repeat them
+        let picked: Vec<usize> = if members.is_empty() {
+            Vec::new()
+        } else if count >= 0 {
+            random::distinct_indexes(members.len(), count as usize)
+        } else {
+            (0..count.unsigned_abs())
+                .map(|_| random::index(members.len()))
+                .collect()
+        };
+
+        self.write_array(
+            picked
+                .into_iter()
+                .map(|index| members[index].as_slice())
+                .collect(),
+        )
+    }
+
+    /// Handle SINTER, SUNION and SDIFF
+    pub(super) fn handle_set_operation(
+        &mut self,
+        args: &[RESPData],
+        command: &[u8],
+        operation: SetOperation,
+    ) -> Result<()> {
+        let command = String::from_utf8_lossy(command);
+        log::debug!("Received {}", command.to_uppercase());
+
+        let keys = bulk_strings(args)?;
+        if keys.is_empty() {
+            return client_error!("wrong number of arguments for '{}' command", command);
+        }
+
+        let mut db = database::lock(0);
+        let sets = lookup_sets(&mut db, &keys)?;
+        drop(db);
+        let members = combine(&sets, operation);
+
+        self.write_array(members.iter().map(|member| member.as_slice()).collect())
+    }
+
+    /// Handle SINTERSTORE, SUNIONSTORE and SDIFFSTORE, which replace the destination with the
+    /// result, or delete it if the result is empty
+    pub(super) fn handle_set_operation_store(
+        &mut self,
+        args: &[RESPData],
+        command: &[u8],
+        operation: SetOperation,
+    ) -> Result<()> {
+        let command = String::from_utf8_lossy(command);
+        log::debug!("Received {}", command.to_uppercase());
+
+        let args = bulk_strings(args)?;
+        let Some((destination, keys)) = args.split_first() else {
+            return client_error!("wrong number of arguments for '{}' command", command);
+        };
+        if keys.is_empty() {
+            return client_error!("wrong number of arguments for '{}' command", command);
+        }
+
+        let max_intset_entries = self.set_max_intset_entries();
+        let mut db = database::lock(0);
+        let sets = lookup_sets(&mut db, keys)?;
+        let members = combine(&sets, operation);
+        let length = members.len();
+
+        let removed = db.remove(destination).is_some();
+        if length > 0 {
+            db.insert(
+                destination,
+                Value::Set(Set::from_members(members, max_intset_entries)),
+            );
+            let event = match operation {
+                SetOperation::Inter => "sinterstore",
+                SetOperation::Union => "sunionstore",
+                SetOperation::Diff => "sdiffstore",
+            };
+            db.notify(notify::SET, event, destination);
+        } else if removed {
+            db.notify(notify::GENERIC, "del", destination);
+        }
+        drop(db);
+
+        self.write_integer(length as i64)
+    }
+
+    /// SINTERCARD numkeys key [key ...] [LIMIT limit]
+    pub(super) fn handle_sintercard(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received SINTERCARD");
+
+        let args = bulk_strings(args)?;
+        let Some((numkeys, rest)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'sintercard' command");
+        };
+        if rest.is_empty() {
+            return client_error!("wrong number of arguments for 'sintercard' command");
+        }
+        let numkeys = match parse_int::<i64>(numkeys) {
+            Ok(numkeys) if numkeys > 0 => numkeys as usize,
+            _ => return client_error!("numkeys should be greater than 0"),
+        };
+        if numkeys > rest.len() {
+            return client_error!("Number of keys can't be greater than number of args");
+        }
+        let (keys, options) = rest.split_at(numkeys);
+
+        let limit = match options {
+            [] => None,
+            [option, limit] if option.eq_ignore_ascii_case(b"LIMIT") => {
+                match parse_int::<i64>(limit) {
+                    Ok(limit) if limit >= 0 => (limit > 0).then_some(limit as usize),
+                    _ => return client_error!("LIMIT can't be negative"),
+                }
+            }
+            _ => return client_error!("syntax error"),
+        };
+
+        let mut db = database::lock(0);
+        let sets = lookup_sets(&mut db, keys)?;
+        drop(db);
+        let mut length = combine(&sets, SetOperation::Inter).len();
+        if let Some(limit) = limit {
+            length = length.min(limit);
+        }
+
+        self.write_integer(length as i64)
+    }
+
+    pub(super) fn handle_smove(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received SMOVE");
+
+        let [RESPData::BulkString(source), RESPData::BulkString(destination), RESPData::BulkString(member)] =
+            args
+        else {
+            return client_error!("wrong number of arguments for 'smove' command");
+        };
+
+        let max_intset_entries = self.set_max_intset_entries();
+        let mut db = database::lock(0);
+        // Both keys are type checked before anything is moved
+        db.get_set_mut(destination)?;
+        let Some(set) = db.get_set_mut(source)? else {
+            return self.write_integer(0);
+        };
+        if source == destination {
+            let is_member = set.contains(member);
+            drop(db);
+            return self.write_integer(is_member as i64);
+        }
+        if !set.remove(member) {
+            return self.write_integer(0);
+        }
+        db.touch(source);
+        db.notify(notify::SET, "srem", source);
+        remove_if_empty(&mut db, source);
+        get_or_create_set(&mut db, destination)?.insert(member.to_vec(), max_intset_entries);
+        db.touch(destination);
+        db.notify(notify::SET, "sadd", destination);
+        drop(db);
+
+        self.write_integer(1)
+    }
+}
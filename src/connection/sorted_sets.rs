@@ -0,0 +1,1020 @@
+use super::{
+    blocking::BlockedCommand,
+    bulk_strings,
+    lists::parse_timeout,
+    parse_int, resolve_range,
+    strings::{format_float, parse_float},
+    Connection, EMPTY_ARRAY, NULL, NULL_ARRAY,
+};
+use crate::{
+    database::{self, DbHandle, ListpackLimits, SortedSet, Value},
+    error::RustisError,
+    notify,
+    resp::RESPData,
+    Result,
+};
+use std::{collections::HashMap, io::Write, ops::Range};
+
+/// What the start and stop of ZRANGE refer to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum RangeBy {
+    Rank,
+    Score,
+    Lex,
+}
+
+/// How the members of multiple sorted sets are combined, by ZUNION, ZINTER and ZDIFF
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum ZSetOperation {
+    Union,
+    Inter,
+    Diff,
+}
+
+/// How the scores of a member in multiple sorted sets are combined
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Aggregate {
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    fn apply(self, a: f64, b: f64) -> f64 {
+        match self {
+            // Adding infinities with opposite signs results in NaN, which is treated as zero
+            Aggregate::Sum => zero_if_nan(a + b),
+            Aggregate::Min => a.min(b),
+            Aggregate::Max => a.max(b),
+        }
+    }
+}
+
+fn zero_if_nan(score: f64) -> f64 {
+    if score.is_nan() {
+        0.0
+    } else {
+        score
+    }
+}
+
+/// A bound of a range of scores, which is inclusive unless it's prefixed with "("
+#[derive(Debug, Clone, Copy)]
+struct ScoreBound {
+    score: f64,
+    exclusive: bool,
+}
+
+impl ScoreBound {
+    fn parse(raw: &[u8]) -> Result<Self> {
+        let (raw, exclusive) = match raw.strip_prefix(b"(") {
+            Some(raw) => (raw, true),
+            None => (raw, false),
+        };
+        match parse_float(raw) {
+            Some(score) => Ok(ScoreBound { score, exclusive }),
+            None => client_error!("min or max is not a float"),
+        }
+    }
+
+    /// Whether a score comes before the bound, when it's the start of a range
+    fn is_before_start(&self, score: f64) -> bool {
+        score < self.score || (self.exclusive && score == self.score)
+    }
+
+    /// Whether a score doesn't go past the bound, when it's the end of a range
+    fn is_before_end(&self, score: f64) -> bool {
+        score < self.score || (!self.exclusive && score == self.score)
+    }
+}
+
+/// A bound of a range of members, which is "-" or "+" for the lowest and highest possible
+/// members, or a member prefixed with "[" when inclusive or "(" when exclusive
+#[derive(Debug, Clone, Copy)]
+enum LexBound<'a> {
+    Lowest,
+    Highest,
+    Inclusive(&'a [u8]),
+    Exclusive(&'a [u8]),
+}
+
+impl<'a> LexBound<'a> {
+    fn parse(raw: &'a [u8]) -> Result<Self> {
+        match raw {
+            b"-" => Ok(LexBound::Lowest),
+            b"+" => Ok(LexBound::Highest),
+            [b'[', member @ ..] => Ok(LexBound::Inclusive(member)),
+            [b'(', member @ ..] => Ok(LexBound::Exclusive(member)),
+            _ => client_error!("min or max not valid string range item"),
+        }
+    }
+
+    /// Whether a member comes before the bound, when it's the start of a range
+    fn is_before_start(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::Lowest => false,
+            LexBound::Highest => true,
+            LexBound::Inclusive(bound) => member < *bound,
+            LexBound::Exclusive(bound) => member <= *bound,
+        }
+    }
+
+    /// Whether a member doesn't go past the bound, when it's the end of a range
+    fn is_before_end(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::Lowest => false,
+            LexBound::Highest => true,
+            LexBound::Inclusive(bound) => member <= *bound,
+            LexBound::Exclusive(bound) => member < *bound,
+        }
+    }
+}
+
+/// A range of a sorted set, by rank, by score or by member
+///
+/// Ranges by member assume that all the members have the same score, otherwise the result is
+/// unspecified, like it is in Redis
+#[derive(Debug, Clone, Copy)]
+enum ZRange<'a> {
+    Rank(i64, i64),
+    Score(ScoreBound, ScoreBound),
+    Lex(LexBound<'a>, LexBound<'a>),
+}
+
+impl<'a> ZRange<'a> {
+    fn parse(by: RangeBy, start: &'a [u8], end: &'a [u8]) -> Result<Self> {
+        Ok(match by {
+            RangeBy::Rank => ZRange::Rank(parse_int(start)?, parse_int(end)?),
+            RangeBy::Score => ZRange::Score(ScoreBound::parse(start)?, ScoreBound::parse(end)?),
+            RangeBy::Lex => ZRange::Lex(LexBound::parse(start)?, LexBound::parse(end)?),
+        })
+    }
+
+    /// The ranks (in ascending order) of the members in the range
+    ///
+    /// When reversed, ranks count from the member with the highest score instead
+    fn ranks(&self, sorted_set: &SortedSet, rev: bool) -> Range<usize> {
+        let (start, end) = match self {
+            ZRange::Rank(start, end) => {
+                let len = sorted_set.len();
+                let range = resolve_range(len, *start, *end);
+                if rev {
+                    return len - range.end..len - range.start;
+                }
+                return range;
+            }
+            ZRange::Score(start, end) => (
+                sorted_set.count_while(|_, score| start.is_before_start(score)),
+                sorted_set.count_while(|_, score| end.is_before_end(score)),
+            ),
+            ZRange::Lex(start, end) => (
+                sorted_set.count_while(|member, _| start.is_before_start(member)),
+                sorted_set.count_while(|member, _| end.is_before_end(member)),
+            ),
+        };
+        start..end.max(start)
+    }
+}
+
+/// The members and scores within ranks, in ascending order or descending when reversed, after
+/// skipping offset members and taking up to count (all of them if count is negative)
+fn entries_in(
+    sorted_set: &SortedSet,
+    ranks: Range<usize>,
+    rev: bool,
+    (offset, count): (i64, i64),
+) -> Vec<(&[u8], f64)> {
+    if offset < 0 || offset as usize >= ranks.len() {
+        return Vec::new();
+    }
+    let offset = offset as usize;
+    let available = ranks.len() - offset;
+    let count = if count < 0 {
+        available
+    } else {
+        available.min(count as usize)
+    };
+    let entries = if rev {
+        sorted_set.iter_rev_from(ranks.end - 1 - offset)
+    } else {
+        sorted_set.iter_from(ranks.start + offset)
+    };
+    entries.take(count).collect()
+}
+
+/// Remove a key if it holds a sorted set that has become empty, as empty sorted sets are never
+/// kept around
+fn remove_if_empty(db: &mut DbHandle, key: &[u8]) {
+    if matches!(db.get_mut(key), Some(Value::SortedSet(sorted_set)) if sorted_set.is_empty()) {
+        db.remove(key);
+        db.notify(notify::GENERIC, "del", key);
+    }
+}
+
+/// The flags of ZADD, which ZINCRBY is the same as with INCR
+#[derive(Debug, Default, Clone, Copy)]
+pub(super) struct AddFlags {
+    pub(super) nx: bool,
+    pub(super) xx: bool,
+    pub(super) gt: bool,
+    pub(super) lt: bool,
+    pub(super) ch: bool,
+    pub(super) incr: bool,
+}
+
+/// Add members with scores or update their scores, following the ZADD flags, creating the sorted
+/// set if it doesn't exist
+///
+/// Returns how many members were added (or changed, with CH), along with the score of the last
+/// member if it was added or updated, which is the reply with INCR
+pub(super) fn add_members(
+    db: &mut DbHandle,
+    key: &[u8],
+    pairs: &[(f64, &[u8])],
+    flags: AddFlags,
+    limits: &ListpackLimits,
+) -> Result<(usize, Option<f64>)> {
+    // INCR only takes a single pair, and is checked before anything is created
+    if flags.incr {
+        let current = db.get_zset_mut(key)?.and_then(|z| z.score(pairs[0].1));
+        if current.is_some_and(|current| (current + pairs[0].0).is_nan()) {
+            return client_error!("resulting score is not a number (NaN)");
+        }
+    }
+    if db.get_zset_mut(key)?.is_none() {
+        db.insert(key, Value::SortedSet(SortedSet::new()));
+    }
+    let sorted_set = db.get_zset_mut(key)?.unwrap();
+
+    let mut changed = 0;
+    let mut modified = false;
+    let mut last_score = None;
+    for &(score, member) in pairs {
+        last_score = match sorted_set.score(member) {
+            Some(_) if flags.nx => None,
+            Some(current) => {
+                let score = if flags.incr { current + score } else { score };
+                if (flags.gt && score <= current) || (flags.lt && score >= current) {
+                    None
+                } else {
+                    if score != current {
+                        sorted_set.insert(member.to_vec(), score, limits);
+                        changed += flags.ch as usize;
+                        modified = true;
+                    }
+                    Some(score)
+                }
+            }
+            None if flags.xx => None,
+            None => {
+                sorted_set.insert(member.to_vec(), score, limits);
+                changed += 1;
+                modified = true;
+                Some(score)
+            }
+        };
+    }
+    if modified {
+        db.touch(key);
+        db.notify(notify::ZSET, if flags.incr { "zincr" } else { "zadd" }, key);
+    }
+    remove_if_empty(db, key);
+
+    Ok((changed, last_score))
+}
+
+/// Members of a sorted set along with their scores
+pub(super) type Entries = Vec<(Vec<u8>, f64)>;
+
+/// Pop up to count of the members with the lowest or highest scores, removing the key if it ends
+/// up empty
+///
+/// Returns `None` if the key does not exist
+pub(super) fn pop_entries(
+    db: &mut DbHandle,
+    key: &[u8],
+    max: bool,
+    count: usize,
+) -> Result<Option<Entries>> {
+    let Some(sorted_set) = db.get_zset_mut(key)? else {
+        return Ok(None);
+    };
+    let entries = if max {
+        sorted_set.iter_rev_from(sorted_set.len().saturating_sub(1))
+    } else {
+        sorted_set.iter()
+    };
+    let popped: Entries = entries
+        .take(count)
+        .map(|(member, score)| (member.to_vec(), score))
+        .collect();
+    for (member, _) in &popped {
+        sorted_set.remove(member);
+    }
+    if !popped.is_empty() {
+        db.touch(key);
+        db.notify(notify::ZSET, if max { "zpopmax" } else { "zpopmin" }, key);
+    }
+    remove_if_empty(db, key);
+    Ok(Some(popped))
+}
+
+/// The members of a sorted set, or of a set where every member has a score of 1
+type Inputs = Vec<Option<HashMap<Vec<u8>, f64>>>;
+
+/// Look up the inputs of ZUNION, ZINTER and ZDIFF, where keys that don't exist are `None`
+///
+/// Both sorted sets and sets are accepted, and every key is type checked before anything is
+/// combined
+fn lookup_inputs(db: &mut DbHandle, keys: &[&[u8]]) -> Result<Inputs> {
+    keys.iter()
+        .map(|key| match db.get(key) {
+            Some(Value::SortedSet(sorted_set)) => Ok(Some(
+                sorted_set
+                    .iter()
+                    .map(|(member, score)| (member.to_vec(), score))
+                    .collect(),
+            )),
+            Some(Value::Set(set)) => Ok(Some(
+                set.iter()
+                    .map(|member| (member.into_owned(), 1.0))
+                    .collect(),
+            )),
+            Some(_) => Err(RustisError::WrongType),
+            None => Ok(None),
+        })
+        .collect()
+}
+
+/// Combine the inputs, multiplying the scores of each input by its weight, where missing inputs
+/// are treated as empty
+fn combine(
+    inputs: &Inputs,
+    weights: &[f64],
+    operation: ZSetOperation,
+    aggregate: Aggregate,
+) -> Entries {
+    let weighted = |index: usize, score: f64| zero_if_nan(score * weights[index]);
+
+    match operation {
+        ZSetOperation::Union => {
+            let mut combined: HashMap<Vec<u8>, f64> = HashMap::new();
+            for (index, input) in inputs.iter().enumerate() {
+                for (member, &score) in input.iter().flatten() {
+                    let score = weighted(index, score);
+                    combined
+                        .entry(member.clone())
+                        .and_modify(|current| *current = aggregate.apply(*current, score))
+                        .or_insert(score);
+                }
+            }
+            combined.into_iter().collect()
+        }
+        ZSetOperation::Inter => {
+            let Some(inputs) = inputs
+                .iter()
+                .map(Option::as_ref)
+                .collect::<Option<Vec<_>>>()
+            else {
+                return Vec::new();
+            };
+            let Some((first, rest)) = inputs.split_first() else {
+                return Vec::new();
+            };
+            first
+                .iter()
+                .filter_map(|(member, &score)| {
+                    let mut score = weighted(0, score);
+                    for (index, input) in rest.iter().enumerate() {
+                        score = aggregate.apply(score, weighted(index + 1, *input.get(member)?));
+                    }
+                    Some((member.clone(), score))
+                })
+                .collect()
+        }
+        ZSetOperation::Diff => {
+            let Some((Some(first), rest)) = inputs.split_first() else {
+                return Vec::new();
+            };
+            first
+                .iter()
+                .filter(|(member, _)| {
+                    !rest
+                        .iter()
+                        .flatten()
+                        .any(|input| input.contains_key(*member))
+                })
+                .map(|(member, &score)| (member.clone(), score))
+                .collect()
+        }
+    }
+}
+
+impl Connection {
+    /// The limits of the listpack encoding for sorted sets, from the config
+    pub(super) fn zset_limits(&self) -> ListpackLimits {
+        let config = self.config.borrow();
+        ListpackLimits {
+            max_entries: config.zset_max_listpack_entries,
+            max_value: config.zset_max_listpack_value,
+        }
+    }
+
+    /// Write members, each followed by its score when with_scores is set
+    fn write_entries(&mut self, entries: &[(&[u8], f64)], with_scores: bool) -> Result<()> {
+        if !with_scores {
+            return self.write_array(entries.iter().map(|(member, _)| *member).collect());
+        }
+        let scores: Vec<Vec<u8>> = entries
+            .iter()
+            .map(|(_, score)| format_float(*score))
+            .collect();
+        self.write_array(
+            entries
+                .iter()
+                .zip(&scores)
+                .flat_map(|((member, _), score)| [*member, score.as_slice()])
+                .collect(),
+        )
+    }
+
+    /// ZADD key [NX | XX] [GT | LT] [CH] [INCR] score member [score member ...]
+    pub(super) fn handle_zadd(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received ZADD");
+
+        let args = bulk_strings(args)?;
+        if args.len() < 3 {
+            return client_error!("wrong number of arguments for 'zadd' command");
+        }
+        let key = args[0];
+        let mut flags = AddFlags::default();
+        let mut rest = &args[1..];
+        while let Some((option, remaining)) = rest.split_first() {
+            match option.to_ascii_uppercase().as_slice() {
+                b"NX" => flags.nx = true,
+                b"XX" => flags.xx = true,
+                b"GT" => flags.gt = true,
+                b"LT" => flags.lt = true,
+                b"CH" => flags.ch = true,
+                b"INCR" => flags.incr = true,
+                _ => break,
+            }
+            rest = remaining;
+        }
+
+        if rest.is_empty() || rest.len() % 2 != 0 {
+            return client_error!("syntax error");
+        }
+        if flags.nx && flags.xx {
+            return client_error!("XX and NX options at the same time are not compatible");
+        }
+        if [flags.gt, flags.lt, flags.nx]
+            .iter()
+            .filter(|&&flag| flag)
+            .count()
+            > 1
+        {
+            return client_error!("GT, LT, and/or NX options at the same time are not compatible");
+        }
+        if flags.incr && rest.len() > 2 {
+            return client_error!("INCR option supports a single increment-element pair");
+        }
+        let pairs = rest
+            .chunks_exact(2)
+            .map(|pair| match parse_float(pair[0]) {
+                Some(score) => Ok((score, pair[1])),
+                None => client_error!("value is not a valid float"),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let limits = self.zset_limits();
+        let (changed, score) = add_members(&mut database::lock(0), key, &pairs, flags, &limits)?;
+        self.signal_key_ready(key);
+
+        if flags.incr {
+            self.write_optional_bulk_string(score.map(format_float).as_deref())
+        } else {
+            self.write_integer(changed as i64)
+        }
+    }
+
+    pub(super) fn handle_zincrby(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received ZINCRBY");
+
+        let [RESPData::BulkString(key), RESPData::BulkString(increment), RESPData::BulkString(member)] =
+            args
+        else {
+            return client_error!("wrong number of arguments for 'zincrby' command");
+        };
+        let Some(increment) = parse_float(increment) else {
+            return client_error!("value is not a valid float");
+        };
+
+        let flags = AddFlags {
+            incr: true,
+            ..Default::default()
+        };
+        let limits = self.zset_limits();
+        let (_, score) = add_members(
+            &mut database::lock(0),
+            key,
+            &[(increment, member)],
+            flags,
+            &limits,
+        )?;
+        self.signal_key_ready(key);
+
+        self.write_optional_bulk_string(score.map(format_float).as_deref())
+    }
+
+    pub(super) fn handle_zcard(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received ZCARD");
+
+        let [RESPData::BulkString(key)] = args else {
+            return client_error!("wrong number of arguments for 'zcard' command");
+        };
+
+        let length = database::lock(0).get_zset(key)?.map_or(0, |z| z.len());
+
+        self.write_integer(length as i64)
+    }
+
+    pub(super) fn handle_zscore(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received ZSCORE");
+
+        let [RESPData::BulkString(key), RESPData::BulkString(member)] = args else {
+            return client_error!("wrong number of arguments for 'zscore' command");
+        };
+
+        let score = database::lock(0)
+            .get_zset(key)?
+            .and_then(|z| z.score(member));
+
+        self.write_optional_bulk_string(score.map(format_float).as_deref())
+    }
+
+    pub(super) fn handle_zmscore(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received ZMSCORE");
+
+        let args = bulk_strings(args)?;
+        let Some((key, members)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'zmscore' command");
+        };
+        if members.is_empty() {
+            return client_error!("wrong number of arguments for 'zmscore' command");
+        }
+
+        let mut db = database::lock(0);
+        let sorted_set = db.get_zset(key)?;
+        let scores: Vec<Option<Vec<u8>>> = members
+            .iter()
+            .map(|member| sorted_set.and_then(|z| z.score(member)).map(format_float))
+            .collect();
+        drop(db);
+
+        self.write_resp(&RESPData::Array(
+            scores
+                .iter()
+                .map(|score| match score {
+                    Some(score) => RESPData::BulkString(score),
+                    None => RESPData::Null,
+                })
+                .collect(),
+        ))
+    }
+
+    /// Handle ZRANK and ZREVRANK, where the rank counts from the highest score when reversed
+    pub(super) fn handle_zrank(
+        &mut self,
+        args: &[RESPData],
+        command: &[u8],
+        rev: bool,
+    ) -> Result<()> {
+        let command = String::from_utf8_lossy(command);
+        log::debug!("Received {}", command.to_uppercase());
+
+        let args = bulk_strings(args)?;
+        let (key, member, with_score) = match args.as_slice() {
+            [key, member] => (key, member, false),
+            [key, member, option] if option.eq_ignore_ascii_case(b"WITHSCORE") => {
+                (key, member, true)
+            }
+            [_, _, _] => return client_error!("syntax error"),
+            _ => return client_error!("wrong number of arguments for '{}' command", command),
+        };
+
+        let mut db = database::lock(0);
+        let found = db.get_zset(key)?.and_then(|sorted_set| {
+            let rank = sorted_set.rank(member)?;
+            let rank = if rev {
+                sorted_set.len() - 1 - rank
+            } else {
+                rank
+            };
+            Some((rank, sorted_set.score(member)?))
+        });
+        drop(db);
+
+        match (found, with_score) {
+            (None, false) => self.stream.write_all(NULL)?,
+            (None, true) => self.stream.write_all(NULL_ARRAY)?,
+            (Some((rank, _)), false) => self.write_integer(rank as i64)?,
+            (Some((rank, score)), true) => self.write_resp(&RESPData::Array(vec![
+                RESPData::Integer(rank as i64),
+                RESPData::BulkString(&format_float(score)),
+            ]))?,
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn handle_zrem(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received ZREM");
+
+        let args = bulk_strings(args)?;
+        let Some((key, members)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'zrem' command");
+        };
+        if members.is_empty() {
+            return client_error!("wrong number of arguments for 'zrem' command");
+        }
+
+        let mut db = database::lock(0);
+        let Some(sorted_set) = db.get_zset_mut(key)? else {
+            return self.write_integer(0);
+        };
+        let removed = members
+            .iter()
+            .filter(|member| sorted_set.remove(member))
+            .count();
+        if removed > 0 {
+            db.touch(key);
+            db.notify(notify::ZSET, "zrem", key);
+        }
+        remove_if_empty(&mut db, key);
+        drop(db);
+
+        self.write_integer(removed as i64)
+    }
+
+    /// Handle ZRANGE, along with ZREVRANGE, ZRANGEBYSCORE, ZREVRANGEBYSCORE, ZRANGEBYLEX and
+    /// ZREVRANGEBYLEX that it replaces
+    ///
+    /// Only ZRANGE takes BYSCORE, BYLEX and REV, the older commands have them implied. Ranges by
+    /// score or member are given from the end to the start when reversed.
+    pub(super) fn handle_zrange(
+        &mut self,
+        args: &[RESPData],
+        command: &[u8],
+        implied: Option<(RangeBy, bool)>,
+    ) -> Result<()> {
+        let command = String::from_utf8_lossy(command);
+        log::debug!("Received {}", command.to_uppercase());
+
+        let args = bulk_strings(args)?;
+        let [key, start, end, options @ ..] = args.as_slice() else {
+            return client_error!("wrong number of arguments for '{}' command", command);
+        };
+
+        let (mut by, mut rev) = implied.unwrap_or((RangeBy::Rank, false));
+        let mut limit = None;
+        let mut with_scores = false;
+        let mut options = options.iter();
+        while let Some(option) = options.next() {
+            match option.to_ascii_uppercase().as_slice() {
+                b"WITHSCORES" => with_scores = true,
+                b"LIMIT" => {
+                    let (Some(offset), Some(count)) = (options.next(), options.next()) else {
+                        return client_error!("syntax error");
+                    };
+                    limit = Some((parse_int(offset)?, parse_int(count)?));
+                }
+                b"BYSCORE" if implied.is_none() => by = RangeBy::Score,
+                b"BYLEX" if implied.is_none() => by = RangeBy::Lex,
+                b"REV" if implied.is_none() => rev = true,
+                _ => return client_error!("syntax error"),
+            }
+        }
+        if limit.is_some() && by == RangeBy::Rank {
+            return client_error!(
+                "syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX"
+            );
+        }
+        if with_scores && by == RangeBy::Lex {
+            return client_error!(
+                "syntax error, WITHSCORES not supported in combination with BYLEX"
+            );
+        }
+
+        let (start, end) = if rev && by != RangeBy::Rank {
+            (end, start)
+        } else {
+            (start, end)
+        };
+        let range = ZRange::parse(by, start, end)?;
+
+        let mut db = database::lock(0);
+        let Some(sorted_set) = db.get_zset(key)? else {
+            self.stream.write_all(EMPTY_ARRAY)?;
+            return Ok(());
+        };
+        let ranks = range.ranks(sorted_set, rev);
+        let entries = entries_in(sorted_set, ranks, rev, limit.unwrap_or((0, -1)));
+
+        self.write_entries(&entries, with_scores)
+    }
+
+    /// Handle ZCOUNT and ZLEXCOUNT
+    pub(super) fn handle_zcount(
+        &mut self,
+        args: &[RESPData],
+        command: &[u8],
+        by: RangeBy,
+    ) -> Result<()> {
+        let command = String::from_utf8_lossy(command);
+        log::debug!("Received {}", command.to_uppercase());
+
+        let [RESPData::BulkString(key), RESPData::BulkString(min), RESPData::BulkString(max)] =
+            args
+        else {
+            return client_error!("wrong number of arguments for '{}' command", command);
+        };
+        let range = ZRange::parse(by, min, max)?;
+
+        let count = database::lock(0)
+            .get_zset(key)?
+            .map_or(0, |sorted_set| range.ranks(sorted_set, false).len());
+
+        self.write_integer(count as i64)
+    }
+
+    /// Handle ZREMRANGEBYRANK, ZREMRANGEBYSCORE and ZREMRANGEBYLEX
+    pub(super) fn handle_zremrange(
+        &mut self,
+        args: &[RESPData],
+        command: &[u8],
+        by: RangeBy,
+    ) -> Result<()> {
+        let command = String::from_utf8_lossy(command);
+        log::debug!("Received {}", command.to_uppercase());
+
+        let [RESPData::BulkString(key), RESPData::BulkString(start), RESPData::BulkString(end)] =
+            args
+        else {
+            return client_error!("wrong number of arguments for '{}' command", command);
+        };
+        let range = ZRange::parse(by, start, end)?;
+
+        let mut db = database::lock(0);
+        let Some(sorted_set) = db.get_zset_mut(key)? else {
+            return self.write_integer(0);
+        };
+        let ranks = range.ranks(sorted_set, false);
+        let members: Vec<Vec<u8>> = sorted_set
+            .iter_from(ranks.start)
+            .take(ranks.len())
+            .map(|(member, _)| member.to_vec())
+            .collect();
+        for member in &members {
+            sorted_set.remove(member);
+        }
+        if !members.is_empty() {
+            db.touch(key);
+            let event = match by {
+                RangeBy::Rank => "zremrangebyrank",
+                RangeBy::Score => "zremrangebyscore",
+                RangeBy::Lex => "zremrangebylex",
+            };
+            db.notify(notify::ZSET, event, key);
+        }
+        remove_if_empty(&mut db, key);
+        drop(db);
+
+        self.write_integer(members.len() as i64)
+    }
+
+    /// Handle ZPOPMIN and ZPOPMAX, which reply with members and scores, even without a count
+    pub(super) fn handle_zpop(
+        &mut self,
+        args: &[RESPData],
+        command: &[u8],
+        max: bool,
+    ) -> Result<()> {
+        let command = String::from_utf8_lossy(command);
+        log::debug!("Received {}", command.to_uppercase());
+
+        let (key, count) = match args {
+            [RESPData::BulkString(key)] => (key, 1),
+            [RESPData::BulkString(key), RESPData::BulkString(count)] => {
+                match parse_int::<i64>(count)? {
+                    count if count < 0 => {
+                        return client_error!("value is out of range, must be positive")
+                    }
+                    count => (key, count as usize),
+                }
+            }
+            [_, _, _, ..] => return client_error!("syntax error"),
+            _ => return client_error!("wrong number of arguments for '{}' command", command),
+        };
+
+        let popped = pop_entries(&mut database::lock(0), key, max, count)?.unwrap_or_default();
+        let entries: Vec<(&[u8], f64)> = popped
+            .iter()
+            .map(|(member, score)| (member.as_slice(), *score))
+            .collect();
+
+        self.write_entries(&entries, true)
+    }
+
+    /// Reply to BZPOPMIN and BZPOPMAX with the key that was popped from, the member and its score
+    pub(super) fn write_zpopped(
+        &mut self,
+        key: &[u8],
+        (member, score): &(Vec<u8>, f64),
+    ) -> Result<()> {
+        self.write_array(vec![key, member, &format_float(*score)])
+    }
+
+    /// Handle BZPOPMIN and BZPOPMAX, which pop from the first non-empty sorted set or block until
+    /// one of the sorted sets is added to
+    pub(super) fn handle_bzpop(
+        &mut self,
+        args: &[RESPData],
+        command: &[u8],
+        max: bool,
+    ) -> Result<()> {
+        let command = String::from_utf8_lossy(command);
+        log::debug!("Received {}", command.to_uppercase());
+
+        let args = bulk_strings(args)?;
+        let Some((timeout, keys)) = args.split_last() else {
+            return client_error!("wrong number of arguments for '{}' command", command);
+        };
+        if keys.is_empty() {
+            return client_error!("wrong number of arguments for '{}' command", command);
+        }
+        let deadline = parse_timeout(timeout)?;
+
+        let mut db = database::lock(0);
+        for key in keys {
+            if let Some(popped) = pop_entries(&mut db, key, max, 1)? {
+                drop(db);
+                return self.write_zpopped(key, &popped[0]);
+            }
+        }
+        drop(db);
+
+        let keys: Vec<Vec<u8>> = keys.iter().map(|k| k.to_vec()).collect();
+        self.block(&keys, deadline, BlockedCommand::ZPop { max });
+
+        Ok(())
+    }
+
+    /// Handle ZUNION, ZINTER and ZDIFF, along with ZUNIONSTORE, ZINTERSTORE and ZDIFFSTORE which
+    /// replace the destination with the result, or delete it if the result is empty
+    ///
+    /// ZDIFF takes neither weights nor an aggregate, as only the scores of the first key are used
+    pub(super) fn handle_zset_operation(
+        &mut self,
+        args: &[RESPData],
+        command: &[u8],
+        operation: ZSetOperation,
+        store: bool,
+    ) -> Result<()> {
+        let command = String::from_utf8_lossy(command);
+        log::debug!("Received {}", command.to_uppercase());
+
+        let args = bulk_strings(args)?;
+        let (destination, args) = match (store, args.split_first()) {
+            (true, Some((destination, args))) => (Some(*destination), args),
+            (false, _) => (None, args.as_slice()),
+            (true, None) => {
+                return client_error!("wrong number of arguments for '{}' command", command)
+            }
+        };
+        let Some((numkeys, rest)) = args.split_first() else {
+            return client_error!("wrong number of arguments for '{}' command", command);
+        };
+        let numkeys: i64 = parse_int(numkeys)?;
+        if numkeys < 1 {
+            return client_error!("at least 1 input key is needed for '{}' command", command);
+        }
+        if numkeys as usize > rest.len() {
+            return client_error!("syntax error");
+        }
+        let (keys, options) = rest.split_at(numkeys as usize);
+
+        let mut weights = vec![1.0; keys.len()];
+        let mut aggregate = Aggregate::Sum;
+        let mut with_scores = false;
+        let mut options = options.iter();
+        while let Some(option) = options.next() {
+            match option.to_ascii_uppercase().as_slice() {
+                b"WEIGHTS" if operation != ZSetOperation::Diff => {
+                    for weight in weights.iter_mut() {
+                        let Some(raw) = options.next() else {
+                            return client_error!("syntax error");
+                        };
+                        let Some(value) = parse_float(raw) else {
+                            return client_error!("weight value is not a float");
+                        };
+                        *weight = value;
+                    }
+                }
+                b"AGGREGATE" if operation != ZSetOperation::Diff => {
+                    aggregate = match options.next().map(|a| a.to_ascii_uppercase()).as_deref() {
+                        Some(b"SUM") => Aggregate::Sum,
+                        Some(b"MIN") => Aggregate::Min,
+                        Some(b"MAX") => Aggregate::Max,
+                        _ => return client_error!("syntax error"),
+                    };
+                }
+                b"WITHSCORES" if !store => with_scores = true,
+                _ => return client_error!("syntax error"),
+            }
+        }
+
+        let limits = self.zset_limits();
+        let mut db = database::lock(0);
+        let inputs = lookup_inputs(&mut db, keys)?;
+        let result =
+            SortedSet::from_entries(combine(&inputs, &weights, operation, aggregate), &limits);
+
+        let Some(destination) = destination else {
+            drop(db);
+            let entries: Vec<(&[u8], f64)> = result.iter().collect();
+            return self.write_entries(&entries, with_scores);
+        };
+        let length = result.len();
+        let removed = db.remove(destination).is_some();
+        if length > 0 {
+            db.insert(destination, Value::SortedSet(result));
+            let event = match operation {
+                ZSetOperation::Union => "zunionstore",
+                ZSetOperation::Inter => "zinterstore",
+                ZSetOperation::Diff => "zdiffstore",
+            };
+            db.notify(notify::ZSET, event, destination);
+        } else if removed {
+            db.notify(notify::GENERIC, "del", destination);
+        }
+        drop(db);
+        self.signal_key_ready(destination);
+
+        self.write_integer(length as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_score_ranges() {
+        let limits = ListpackLimits::default();
+        let sorted_set = SortedSet::from_entries(
+            [
+                (b"a".to_vec(), 1.0),
+                (b"b".to_vec(), 2.0),
+                (b"c".to_vec(), 2.0),
+                (b"d".to_vec(), 3.0),
+            ],
+            &limits,
+        );
+        let ranks = |start: &[u8], end: &[u8]| {
+            ZRange::parse(RangeBy::Score, start, end)
+                .unwrap()
+                .ranks(&sorted_set, false)
+        };
+
+        assert_eq!(ranks(b"-inf", b"+inf"), 0..4);
+        assert_eq!(ranks(b"2", b"2"), 1..3);
+        assert_eq!(ranks(b"(1", b"(3"), 1..3);
+        assert_eq!(ranks(b"3", b"1"), 3..3);
+        assert!(ZRange::parse(RangeBy::Score, b"(", b"1").is_err());
+    }
+
+    #[test]
+    fn test_lex_and_rank_ranges() {
+        let limits = ListpackLimits::default();
+        let sorted_set = SortedSet::from_entries(
+            [b"a", b"b", b"c", b"d"].map(|member| (member.to_vec(), 0.0)),
+            &limits,
+        );
+        let ranks = |by, start: &[u8], end: &[u8], rev| {
+            ZRange::parse(by, start, end)
+                .unwrap()
+                .ranks(&sorted_set, rev)
+        };
+
+        assert_eq!(ranks(RangeBy::Lex, b"-", b"+", false), 0..4);
+        assert_eq!(ranks(RangeBy::Lex, b"[b", b"(d", false), 1..3);
+        assert_eq!(ranks(RangeBy::Lex, b"+", b"-", false), 4..4);
+        assert_eq!(ranks(RangeBy::Rank, b"0", b"0", true), 3..4);
+        assert!(ZRange::parse(RangeBy::Lex, b"b", b"+").is_err());
+
+        let entries = entries_in(&sorted_set, 0..4, true, (1, 2));
+        assert_eq!(entries, vec![(&b"c"[..], 0.0), (&b"b"[..], 0.0)]);
+        assert!(entries_in(&sorted_set, 0..4, false, (-1, 2)).is_empty());
+    }
+}
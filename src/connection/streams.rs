@@ -0,0 +1,657 @@
+use super::{blocking::BlockedCommand, bulk_strings, parse_int, Connection, NULL, NULL_ARRAY};
+use crate::{
+    database::{
+        self, now, DbHandle, Stream, StreamFields, StreamId, StreamNodeLimits, Trim, Value,
+    },
+    error::RustisError,
+    notify,
+    resp::RESPData,
+    Result,
+};
+use std::{
+    io::Write,
+    time::{Duration, Instant},
+};
+
+/// Entries of a stream, copied out so that they can be replied with after unlocking the database
+pub(super) type Entries = Vec<(StreamId, StreamFields)>;
+
+/// The limit of approximate trimming when the node limits don't give one
+const DEFAULT_TRIM_LIMIT: usize = 10_000;
+
+/// Parse a stream ID, "ms-seq", where the sequence number can be left out to use missing_seq
+pub(super) fn parse_id(raw: &[u8], missing_seq: u64) -> Result<StreamId> {
+    let parse = |part: &[u8]| std::str::from_utf8(part).ok()?.parse::<u64>().ok();
+    let id = match raw.iter().position(|&b| b == b'-') {
+        Some(index) => parse(&raw[..index])
+            .zip(parse(&raw[index + 1..]))
+            .map(|(ms, seq)| StreamId::new(ms, seq)),
+        None => parse(raw).map(|ms| StreamId::new(ms, missing_seq)),
+    };
+    match id {
+        Some(id) => Ok(id),
+        None => client_error!("Invalid stream ID specified as stream command argument"),
+    }
+}
+
+/// Parse the start of a range of IDs, which is "-" for the lowest possible ID, and is exclusive
+/// when prefixed with "("
+pub(super) fn parse_range_start(raw: &[u8]) -> Result<StreamId> {
+    match raw {
+        b"-" => Ok(StreamId::MIN),
+        b"+" => Ok(StreamId::MAX),
+        [b'(', id @ ..] => match parse_id(id, 0)?.next() {
+            Some(id) => Ok(id),
+            None => client_error!("invalid start ID for the interval"),
+        },
+        id => parse_id(id, 0),
+    }
+}
+
+/// Parse the end of a range of IDs, which is "+" for the highest possible ID, and is exclusive
+/// when prefixed with "("
+pub(super) fn parse_range_end(raw: &[u8]) -> Result<StreamId> {
+    match raw {
+        b"-" => Ok(StreamId::MIN),
+        b"+" => Ok(StreamId::MAX),
+        [b'(', id @ ..] => match parse_id(id, u64::MAX)?.prev() {
+            Some(id) => Ok(id),
+            None => client_error!("invalid end ID for the interval"),
+        },
+        id => parse_id(id, u64::MAX),
+    }
+}
+
+/// The ID given to XADD for the new entry
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum NewId {
+    /// "*", generated from the current time
+    Auto,
+    /// "ms-*", with the sequence number generated
+    AutoSeq(u64),
+    Explicit(StreamId),
+}
+
+impl NewId {
+    fn parse(raw: &[u8]) -> Result<Self> {
+        if raw == b"*" {
+            return Ok(NewId::Auto);
+        }
+        if let Some(ms) = raw.strip_suffix(b"-*") {
+            return Ok(NewId::AutoSeq(parse_id(ms, 0)?.ms));
+        }
+        match parse_id(raw, 0)? {
+            StreamId::MIN => client_error!("The ID specified in XADD must be greater than 0-0"),
+            id => Ok(NewId::Explicit(id)),
+        }
+    }
+
+    /// The ID of the new entry, which has to be greater than the last ID of the stream
+    fn resolve(self, stream: &Stream) -> Result<StreamId> {
+        let last_id = stream.last_id();
+        let id = match self {
+            NewId::Auto => match stream.next_id(now() as u64) {
+                Some(id) => id,
+                None => {
+                    return client_error!(
+                        "The stream has exhausted the last possible ID, unable to add more items"
+                    )
+                }
+            },
+            // Running out of sequence numbers leaves the ID equal to the last one, which is an error
+            NewId::AutoSeq(ms) if ms == last_id.ms => match last_id.seq.checked_add(1) {
+                Some(seq) => StreamId::new(ms, seq),
+                None => last_id,
+            },
+            NewId::AutoSeq(ms) => StreamId::new(ms, 0),
+            NewId::Explicit(id) => id,
+        };
+        if id <= last_id {
+            return client_error!(
+                "The ID specified in XADD is equal or smaller than the target stream top item"
+            );
+        }
+        Ok(id)
+    }
+}
+
+/// The trimming options of XADD and XTRIM
+#[derive(Debug, Default, Clone, Copy)]
+struct TrimArgs {
+    trim: Option<Trim>,
+    approximate: bool,
+    limit: Option<usize>,
+}
+
+impl TrimArgs {
+    /// Parse a trimming option at the start of the arguments, if there is one
+    ///
+    /// Returns how many of the arguments the option took up, which is zero if there was none
+    fn parse_option(&mut self, args: &[&[u8]]) -> Result<usize> {
+        let Some((option, rest)) = args.split_first() else {
+            return Ok(0);
+        };
+        match option.to_ascii_uppercase().as_slice() {
+            strategy @ (b"MAXLEN" | b"MINID") => {
+                let (approximate, rest) = match rest.split_first() {
+                    Some((&b"~", rest)) => (Some(true), rest),
+                    Some((&b"=", rest)) => (Some(false), rest),
+                    _ => (None, rest),
+                };
+                let Some(threshold) = rest.first() else {
+                    return client_error!("syntax error");
+                };
+                let trim = if strategy == b"MAXLEN" {
+                    match parse_int::<i64>(threshold)? {
+                        length if length < 0 => {
+                            return client_error!("The MAXLEN argument must be >= 0.")
+                        }
+                        length => Trim::MaxLen(length as usize),
+                    }
+                } else {
+                    Trim::MinId(parse_id(threshold, 0)?)
+                };
+                if self.trim.is_some_and(|current| {
+                    std::mem::discriminant(&current) != std::mem::discriminant(&trim)
+                }) {
+                    return client_error!(
+                        "syntax error, MAXLEN and MINID options at the same time are not compatible"
+                    );
+                }
+                self.trim = Some(trim);
+                self.approximate = approximate.unwrap_or(false);
+                Ok(2 + approximate.is_some() as usize)
+            }
+            b"LIMIT" => {
+                let Some(limit) = rest.first() else {
+                    return client_error!("syntax error");
+                };
+                match parse_int::<i64>(limit)? {
+                    limit if limit < 0 => client_error!("The LIMIT argument must be >= 0."),
+                    limit => {
+                        self.limit = Some(limit as usize);
+                        Ok(2)
+                    }
+                }
+            }
+            _ => Ok(0),
+        }
+    }
+
+    /// The most entries that approximate trimming can remove, where zero means no limit
+    ///
+    /// Without a LIMIT, approximate trimming removes up to a hundred nodes worth of entries
+    fn limit(&self, limits: &StreamNodeLimits) -> Result<usize> {
+        match self.limit {
+            Some(_) if !self.approximate => {
+                client_error!("syntax error, LIMIT cannot be used without the special ~ option")
+            }
+            Some(limit) => Ok(limit),
+            None if self.approximate && limits.max_entries > 0 => Ok(100 * limits.max_entries),
+            None if self.approximate => Ok(DEFAULT_TRIM_LIMIT),
+            None => Ok(0),
+        }
+    }
+
+    /// Trim the stream, returning how many entries were removed
+    fn apply(&self, stream: &mut Stream, limit: usize) -> usize {
+        match self.trim {
+            Some(trim) => stream.trim(trim, self.approximate, limit),
+            None => 0,
+        }
+    }
+}
+
+/// The options of XREAD and XREADGROUP, along with the keys and IDs of the streams to read
+#[derive(Debug)]
+pub(super) struct ReadArgs<'a> {
+    /// The most entries to read from each stream, where zero means all of them
+    pub(super) count: usize,
+    /// How long to block for in milliseconds, where zero means forever
+    pub(super) block: Option<u64>,
+    /// For XREADGROUP, whether the entries read are acknowledged straight away
+    pub(super) no_ack: bool,
+    pub(super) keys: &'a [&'a [u8]],
+    pub(super) ids: &'a [&'a [u8]],
+}
+
+impl<'a> ReadArgs<'a> {
+    /// Parse the arguments of XREAD, or those of XREADGROUP after the group and consumer
+    pub(super) fn parse(args: &'a [&'a [u8]], command: &str) -> Result<Self> {
+        let mut read = ReadArgs {
+            count: 0,
+            block: None,
+            no_ack: false,
+            keys: &[],
+            ids: &[],
+        };
+        let mut rest = args;
+        let streams = loop {
+            match rest {
+                [option, value, remaining @ ..] if option.eq_ignore_ascii_case(b"COUNT") => {
+                    read.count = parse_int::<i64>(value)?.max(0) as usize;
+                    rest = remaining;
+                }
+                [option, value, remaining @ ..] if option.eq_ignore_ascii_case(b"BLOCK") => {
+                    let timeout: i64 = parse_int(value)?;
+                    if timeout < 0 {
+                        return client_error!("timeout is negative");
+                    }
+                    read.block = Some(timeout as u64);
+                    rest = remaining;
+                }
+                [option, remaining @ ..]
+                    if command == "xreadgroup" && option.eq_ignore_ascii_case(b"NOACK") =>
+                {
+                    read.no_ack = true;
+                    rest = remaining;
+                }
+                [option, streams @ ..] if option.eq_ignore_ascii_case(b"STREAMS") => {
+                    break streams;
+                }
+                [] => return client_error!("wrong number of arguments for '{}' command", command),
+                _ => return client_error!("syntax error"),
+            }
+        };
+        if streams.is_empty() || streams.len() % 2 != 0 {
+            return client_error!(
+                "Unbalanced '{}' list of streams: for each stream key an ID or '{}' must be \
+                 specified.",
+                command,
+                if command == "xread" { "$" } else { ">" }
+            );
+        }
+        (read.keys, read.ids) = streams.split_at(streams.len() / 2);
+        Ok(read)
+    }
+
+    /// When to stop blocking, where a timeout of zero means blocking forever with no deadline
+    pub(super) fn deadline(&self) -> Option<Instant> {
+        match self.block {
+            Some(0) | None => None,
+            Some(timeout) => Instant::now().checked_add(Duration::from_millis(timeout)),
+        }
+    }
+}
+
+/// The entries of a stream with an ID greater than the given one, up to count of them, or all of
+/// them if count is zero
+///
+/// There are no entries if the key does not exist
+pub(super) fn read_entries(
+    db: &mut DbHandle,
+    key: &[u8],
+    after: StreamId,
+    count: usize,
+) -> Result<Entries> {
+    let (Some(stream), Some(start)) = (db.get_stream(key)?, after.next()) else {
+        return Ok(Vec::new());
+    };
+    let count = if count == 0 { usize::MAX } else { count };
+    Ok(stream
+        .range(start, StreamId::MAX)
+        .take(count)
+        .map(|(id, fields)| (id, fields.clone()))
+        .collect())
+}
+
+/// Encode an entry as its ID and an array of its fields and values, which is null if the entry
+/// has been deleted
+pub(super) fn encode_entry(buf: &mut Vec<u8>, id: StreamId, fields: Option<&StreamFields>) {
+    buf.extend_from_slice(b"*2\r\n");
+    RESPData::BulkString(id.to_string().as_bytes()).encode(buf);
+    match fields {
+        Some(fields) => RESPData::Array(
+            fields
+                .iter()
+                .flat_map(|(field, value)| {
+                    [RESPData::BulkString(field), RESPData::BulkString(value)]
+                })
+                .collect(),
+        )
+        .encode(buf),
+        None => buf.extend_from_slice(NULL_ARRAY),
+    }
+}
+
+/// Encode entries as an array of entries
+pub(super) fn encode_entries(buf: &mut Vec<u8>, entries: &Entries) {
+    write!(buf, "*{}\r\n", entries.len()).unwrap();
+    for (id, fields) in entries {
+        encode_entry(buf, *id, Some(fields));
+    }
+}
+
+impl Connection {
+    /// The limits of the nodes of streams, from the config
+    fn stream_limits(&self) -> StreamNodeLimits {
+        let config = self.config.borrow();
+        StreamNodeLimits {
+            max_entries: config.stream_node_max_entries,
+            max_bytes: config.stream_node_max_bytes,
+        }
+    }
+
+    fn write_stream_entries(&mut self, entries: &Entries) -> Result<()> {
+        let mut buf = Vec::new();
+        encode_entries(&mut buf, entries);
+        self.stream.write_all(&buf)?;
+        Ok(())
+    }
+
+    /// Reply to XREAD with the entries read from each stream, along with the key of the stream
+    pub(super) fn write_streams(&mut self, streams: &[(&[u8], Entries)]) -> Result<()> {
+        let mut buf = Vec::new();
+        write!(buf, "*{}\r\n", streams.len())?;
+        for (key, entries) in streams {
+            buf.extend_from_slice(b"*2\r\n");
+            RESPData::BulkString(key).encode(&mut buf);
+            encode_entries(&mut buf, entries);
+        }
+        self.stream.write_all(&buf)?;
+        Ok(())
+    }
+
+    /// XADD key [NOMKSTREAM] [MAXLEN | MINID [= | ~] threshold [LIMIT count]] * | id field value
+    /// [field value ...]
+    pub(super) fn handle_xadd(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received XADD");
+
+        let args = bulk_strings(args)?;
+        let Some((key, mut rest)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'xadd' command");
+        };
+        let mut nomkstream = false;
+        let mut trim = TrimArgs::default();
+        loop {
+            if rest
+                .first()
+                .is_some_and(|option| option.eq_ignore_ascii_case(b"NOMKSTREAM"))
+            {
+                nomkstream = true;
+                rest = &rest[1..];
+                continue;
+            }
+            match trim.parse_option(rest)? {
+                0 => break,
+                used => rest = &rest[used..],
+            }
+        }
+        let Some((id, pairs)) = rest.split_first() else {
+            return client_error!("wrong number of arguments for 'xadd' command");
+        };
+        if pairs.is_empty() || pairs.len() % 2 != 0 {
+            return client_error!("wrong number of arguments for 'xadd' command");
+        }
+        let new_id = NewId::parse(id)?;
+        let limits = self.stream_limits();
+        let trim_limit = trim.limit(&limits)?;
+
+        let mut db = database::lock(0);
+        let id = match db.get_stream_mut(key)? {
+            Some(stream) => new_id.resolve(stream)?,
+            None if nomkstream => {
+                drop(db);
+                self.stream.write_all(NULL)?;
+                return Ok(());
+            }
+            None => {
+                let id = new_id.resolve(&Stream::new())?;
+                db.insert(key, Value::Stream(Stream::new()));
+                id
+            }
+        };
+        let stream = db.get_stream_mut(key)?.unwrap();
+        let fields = pairs
+            .chunks_exact(2)
+            .map(|pair| (pair[0].to_vec(), pair[1].to_vec()))
+            .collect();
+        stream.append(id, fields, &limits);
+        let trimmed = trim.apply(stream, trim_limit);
+        db.touch(key);
+        db.notify(notify::STREAM, "xadd", key);
+        if trimmed > 0 {
+            db.notify(notify::STREAM, "xtrim", key);
+        }
+        drop(db);
+        self.signal_key_ready(key);
+
+        self.write_bulk_string(id.to_string().as_bytes())
+    }
+
+    pub(super) fn handle_xlen(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received XLEN");
+
+        let [RESPData::BulkString(key)] = args else {
+            return client_error!("wrong number of arguments for 'xlen' command");
+        };
+        let length = database::lock(0).get_stream(key)?.map_or(0, Stream::len);
+
+        self.write_integer(length as i64)
+    }
+
+    /// Handle XRANGE and XREVRANGE, where XREVRANGE takes the end of the range first and replies
+    /// with the entries in reverse order
+    pub(super) fn handle_xrange(
+        &mut self,
+        args: &[RESPData],
+        command: &[u8],
+        rev: bool,
+    ) -> Result<()> {
+        let command = String::from_utf8_lossy(command);
+        log::debug!("Received {}", command.to_uppercase());
+
+        let args = bulk_strings(args)?;
+        if args.len() < 3 {
+            return client_error!("wrong number of arguments for '{}' command", command);
+        }
+        let key = args[0];
+        let (start, end) = if rev {
+            (parse_range_start(args[2])?, parse_range_end(args[1])?)
+        } else {
+            (parse_range_start(args[1])?, parse_range_end(args[2])?)
+        };
+        let count = match &args[3..] {
+            [] => None,
+            [option, count] if option.eq_ignore_ascii_case(b"COUNT") => {
+                Some(parse_int::<i64>(count)?.max(0) as usize)
+            }
+            _ => return client_error!("syntax error"),
+        };
+
+        let mut db = database::lock(0);
+        let Some(stream) = db.get_stream(key)? else {
+            drop(db);
+            return self.write_stream_entries(&Vec::new());
+        };
+        if count == Some(0) {
+            drop(db);
+            self.stream.write_all(NULL_ARRAY)?;
+            return Ok(());
+        }
+        let range = stream.range(start, end);
+        let range: Box<dyn Iterator<Item = _>> = if rev {
+            Box::new(range.rev())
+        } else {
+            Box::new(range)
+        };
+        let entries: Entries = range
+            .take(count.unwrap_or(usize::MAX))
+            .map(|(id, fields)| (id, fields.clone()))
+            .collect();
+        drop(db);
+
+        self.write_stream_entries(&entries)
+    }
+
+    pub(super) fn handle_xdel(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received XDEL");
+
+        let args = bulk_strings(args)?;
+        let Some((key, ids)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'xdel' command");
+        };
+        if ids.is_empty() {
+            return client_error!("wrong number of arguments for 'xdel' command");
+        }
+        // Every ID is validated before any entry is deleted
+        let ids = ids
+            .iter()
+            .map(|id| parse_id(id, 0))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut db = database::lock(0);
+        let deleted = match db.get_stream_mut(key)? {
+            Some(stream) => ids.into_iter().filter(|id| stream.remove(*id)).count(),
+            None => 0,
+        };
+        if deleted > 0 {
+            db.touch(key);
+            db.notify(notify::STREAM, "xdel", key);
+        }
+        drop(db);
+
+        self.write_integer(deleted as i64)
+    }
+
+    /// XTRIM key MAXLEN | MINID [= | ~] threshold [LIMIT count]
+    pub(super) fn handle_xtrim(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received XTRIM");
+
+        let args = bulk_strings(args)?;
+        let Some((key, mut rest)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'xtrim' command");
+        };
+        if rest.is_empty() {
+            return client_error!("wrong number of arguments for 'xtrim' command");
+        }
+        let mut trim = TrimArgs::default();
+        while !rest.is_empty() {
+            match trim.parse_option(rest)? {
+                0 => return client_error!("syntax error"),
+                used => rest = &rest[used..],
+            }
+        }
+        if trim.trim.is_none() {
+            return client_error!("syntax error");
+        }
+        let limit = trim.limit(&self.stream_limits())?;
+
+        let mut db = database::lock(0);
+        let removed = match db.get_stream_mut(key)? {
+            Some(stream) => trim.apply(stream, limit),
+            None => 0,
+        };
+        if removed > 0 {
+            db.touch(key);
+            db.notify(notify::STREAM, "xtrim", key);
+        }
+        drop(db);
+
+        self.write_integer(removed as i64)
+    }
+
+    /// XREAD [COUNT count] [BLOCK milliseconds] STREAMS key [key ...] id [id ...]
+    ///
+    /// Replies with the entries after the given ID of each stream that has any. The ID "$" is the
+    /// last ID of the stream, for only reading entries added after the command. With BLOCK, the
+    /// client blocks until one of the streams is added to if none of them have entries to read.
+    pub(super) fn handle_xread(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received XREAD");
+
+        let args = bulk_strings(args)?;
+        let options = ReadArgs::parse(&args, "xread")?;
+        let (keys, ids, count) = (options.keys, options.ids, options.count);
+
+        let mut db = database::lock(0);
+        let mut after = Vec::with_capacity(keys.len());
+        for (key, id) in keys.iter().zip(ids) {
+            let id = match *id {
+                b"$" => db.get_stream(key)?.map_or(StreamId::MIN, Stream::last_id),
+                id => parse_id(id, 0)?,
+            };
+            after.push((key.to_vec(), id));
+        }
+        let mut read = Vec::new();
+        for (key, id) in &after {
+            let entries = read_entries(&mut db, key, *id, count)?;
+            if !entries.is_empty() {
+                read.push((key.as_slice(), entries));
+            }
+        }
+        drop(db);
+
+        if !read.is_empty() {
+            return self.write_streams(&read);
+        }
+        if options.block.is_none() {
+            self.stream.write_all(NULL_ARRAY)?;
+            return Ok(());
+        }
+        let keys: Vec<Vec<u8>> = after.iter().map(|(key, _)| key.clone()).collect();
+        self.block(
+            &keys,
+            options.deadline(),
+            BlockedCommand::XRead { after, count },
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_id() {
+        assert_eq!(parse_id(b"5-3", 0).unwrap(), StreamId::new(5, 3));
+        assert_eq!(parse_id(b"5", 0).unwrap(), StreamId::new(5, 0));
+        assert_eq!(
+            parse_id(b"5", u64::MAX).unwrap(),
+            StreamId::new(5, u64::MAX)
+        );
+        assert!(parse_id(b"5-", 0).is_err());
+        assert!(parse_id(b"-1", 0).is_err());
+        assert!(parse_id(b"a-1", 0).is_err());
+    }
+
+    #[test]
+    fn test_parse_range_bounds() {
+        assert_eq!(parse_range_start(b"-").unwrap(), StreamId::MIN);
+        assert_eq!(parse_range_end(b"+").unwrap(), StreamId::MAX);
+        assert_eq!(parse_range_start(b"(5-3").unwrap(), StreamId::new(5, 4));
+        assert_eq!(
+            parse_range_end(b"(5-0").unwrap(),
+            StreamId::new(4, u64::MAX)
+        );
+        assert_eq!(parse_range_end(b"5").unwrap(), StreamId::new(5, u64::MAX));
+        assert!(parse_range_end(b"(0-0").is_err());
+    }
+
+    #[test]
+    fn test_resolve_new_id() {
+        let mut stream = Stream::new();
+        let limits = StreamNodeLimits::default();
+        assert_eq!(
+            NewId::AutoSeq(0).resolve(&stream).unwrap(),
+            StreamId::new(0, 1)
+        );
+        assert!(NewId::parse(b"0-0").is_err());
+
+        stream.append(StreamId::new(5, 3), Vec::new(), &limits);
+        assert_eq!(
+            NewId::AutoSeq(5).resolve(&stream).unwrap(),
+            StreamId::new(5, 4)
+        );
+        assert_eq!(
+            NewId::AutoSeq(6).resolve(&stream).unwrap(),
+            StreamId::new(6, 0)
+        );
+        assert!(NewId::AutoSeq(4).resolve(&stream).is_err());
+        assert!(NewId::Explicit(StreamId::new(5, 3))
+            .resolve(&stream)
+            .is_err());
+        assert!(NewId::Auto.resolve(&stream).unwrap() > StreamId::new(5, 3));
+    }
+}
@@ -0,0 +1,699 @@
+use super::{bulk_strings, parse_int, resolve_range, Connection, NULL, OK, PROTO_MAX_BULK_LEN};
+use crate::{
+    database::{self, now, DbHandle, StringValue, Value},
+    error::RustisError,
+    notify,
+    resp::RESPData,
+    Result,
+};
+use std::io::Write;
+
+/// Options for SET, which are shared with the commands that are built on top of it
+#[derive(Default)]
+struct SetOptions {
+    ttl: Option<u128>,
+    nx: bool,
+    xx: bool,
+    keep_ttl: bool,
+    get: bool,
+}
+
+impl SetOptions {
+    fn parse(args: &[RESPData]) -> Result<Self> {
+        let mut options = SetOptions::default();
+
+        let mut iter = args.iter();
+        while let Some(RESPData::BulkString(arg)) = iter.next() {
+            match arg.to_ascii_uppercase().as_slice() {
+                b"NX" => {
+                    log::debug!("NX option");
+                    options.nx = true;
+                }
+                b"XX" => {
+                    log::debug!("XX option");
+                    options.xx = true;
+                }
+                b"GET" => {
+                    log::debug!("GET option");
+                    options.get = true;
+                }
+                b"EX" => {
+                    log::trace!("EX option");
+                    if options.ttl.is_some() {
+                        return client_error!("syntax error");
+                    }
+                    options.ttl = Some(now() + parse_expire_arg(&mut iter, "set")? * 1000);
+                }
+                b"PX" => {
+                    log::trace!("PX option");
+                    if options.ttl.is_some() {
+                        return client_error!("syntax error");
+                    }
+                    options.ttl = Some(now() + parse_expire_arg(&mut iter, "set")?);
+                }
+                b"EXAT" => {
+                    log::trace!("EXAT option");
+                    if options.ttl.is_some() {
+                        return client_error!("syntax error");
+                    }
+                    options.ttl = Some(parse_expire_arg(&mut iter, "set")? * 1000);
+                }
+                b"PXAT" => {
+                    log::trace!("PXAT option");
+                    if options.ttl.is_some() {
+                        return client_error!("syntax error");
+                    }
+                    options.ttl = Some(parse_expire_arg(&mut iter, "set")?);
+                }
+                b"KEEPTTL" => {
+                    log::debug!("KEEPTTL option");
+                    options.keep_ttl = true;
+                }
+                _ => {
+                    return client_error!("syntax error");
+                }
+            }
+        }
+
+        // These are mutually exclusive
+        if options.nx && options.xx {
+            return client_error!("syntax error");
+        }
+        // Can't keep TTL and set it
+        if options.keep_ttl && options.ttl.is_some() {
+            return client_error!("syntax error");
+        }
+
+        Ok(options)
+    }
+}
+
+/// Parse an expire time argument, which has to be a positive integer
+fn parse_expire_arg<'a, I>(iter: &mut I, command: &str) -> Result<u128>
+where
+    I: Iterator<Item = &'a RESPData<'a>>,
+{
+    let Some(RESPData::BulkString(raw)) = iter.next() else {
+        return client_error!("syntax error");
+    };
+    let value: i64 = parse_int(raw)?;
+    if value <= 0 {
+        return client_error!("invalid expire time in '{}' command", command);
+    }
+    Ok(value as u128)
+}
+
+/// Set a key to a value, respecting the conditions and expiry in the options
+///
+/// Returns whether the value was set, along with the previous value if the GET option was used.
+/// Any type of value is overwritten, unless the previous value is requested and is not a string.
+fn set_with_options(
+    db: &mut DbHandle,
+    key: &[u8],
+    value: &[u8],
+    options: &SetOptions,
+) -> Result<(bool, Option<Vec<u8>>)> {
+    let previous = if options.get {
+        db.get_string(key)?.map(|v| v.as_bytes().into_owned())
+    } else {
+        None
+    };
+    let exists = db.contains_key(key);
+
+    // If NX is set, then we only set the key if it does not already exist
+    if options.nx && exists {
+        log::trace!("Key already exists");
+        return Ok((false, previous));
+    }
+
+    // If XX is set, then we only set the key if it *does* already exist
+    if options.xx && !exists {
+        log::trace!("Key does not exist");
+        return Ok((false, previous));
+    }
+
+    db.insert(key, Value::String(value.to_vec().into()));
+    db.notify(notify::STRING, "set", key);
+
+    // If keep_ttl is set, we leave the existing TTL (if any) as is, otherwise we either set the
+    // new TTL or clear out the old one
+    if !options.keep_ttl {
+        log::trace!("Setting TTL: {:?}", options.ttl);
+        db.set_expiry(key, options.ttl);
+        if options.ttl.is_some() {
+            db.notify(notify::GENERIC, "expire", key);
+        }
+    }
+
+    Ok((true, previous))
+}
+
+impl Connection {
+    pub(super) fn handle_set(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received SET");
+
+        let Some((RESPData::BulkString(key), args)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'set' command");
+        };
+        let Some((RESPData::BulkString(value), args)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'set' command");
+        };
+
+        let options = SetOptions::parse(args)?;
+
+        let mut db = database::lock(0);
+
+        log::debug!(
+            "SET {:?} = {:?}",
+            String::from_utf8_lossy(key),
+            String::from_utf8_lossy(value)
+        );
+
+        let (was_set, previous) = set_with_options(&mut db, key, value, &options)?;
+
+        if options.get {
+            log::trace!("Responding with previous value");
+            self.write_optional_bulk_string(previous.as_deref())?;
+        } else if was_set {
+            log::trace!("Responding with OK");
+            self.stream.write_all(OK)?;
+        } else {
+            self.stream.write_all(NULL)?;
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn handle_setnx(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received SETNX");
+
+        let [RESPData::BulkString(key), RESPData::BulkString(value)] = args else {
+            return client_error!("wrong number of arguments for 'setnx' command");
+        };
+
+        let options = SetOptions {
+            nx: true,
+            ..Default::default()
+        };
+        let (was_set, _) = set_with_options(&mut database::lock(0), key, value, &options)?;
+
+        self.write_integer(was_set as i64)
+    }
+
+    /// Handle SETEX and PSETEX, which only differ in the unit of the expire time
+    pub(super) fn handle_setex(
+        &mut self,
+        args: &[RESPData],
+        command: &[u8],
+        unit_ms: u128,
+    ) -> Result<()> {
+        let command = String::from_utf8_lossy(command);
+        log::debug!("Received {}", command.to_uppercase());
+
+        let [RESPData::BulkString(key), ttl, RESPData::BulkString(value)] = args else {
+            return client_error!("wrong number of arguments for '{}' command", command);
+        };
+
+        let ttl = parse_expire_arg(&mut std::iter::once(ttl), &command)?;
+        let options = SetOptions {
+            ttl: Some(now() + ttl * unit_ms),
+            ..Default::default()
+        };
+        set_with_options(&mut database::lock(0), key, value, &options)?;
+
+        self.stream.write_all(OK)?;
+
+        Ok(())
+    }
+
+    pub(super) fn handle_get(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received GET");
+
+        let [RESPData::BulkString(key)] = args else {
+            return client_error!("wrong number of arguments for 'get' command");
+        };
+
+        let mut db = database::lock(0);
+
+        if let Some(value) = db.get_string(key)? {
+            log::debug!("Found value: {:?}", value);
+            self.write_bulk_string(&value.as_bytes())?;
+        } else {
+            log::debug!("Key not found");
+            self.stream.write_all(NULL)?;
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn handle_getset(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received GETSET");
+
+        let [RESPData::BulkString(key), RESPData::BulkString(value)] = args else {
+            return client_error!("wrong number of arguments for 'getset' command");
+        };
+
+        let options = SetOptions {
+            get: true,
+            ..Default::default()
+        };
+        let (_, previous) = set_with_options(&mut database::lock(0), key, value, &options)?;
+
+        self.write_optional_bulk_string(previous.as_deref())
+    }
+
+    pub(super) fn handle_getdel(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received GETDEL");
+
+        let [RESPData::BulkString(key)] = args else {
+            return client_error!("wrong number of arguments for 'getdel' command");
+        };
+
+        let mut db = database::lock(0);
+        let previous = db.get_string(key)?.map(|v| v.as_bytes().into_owned());
+        if previous.is_some() {
+            db.remove(key);
+            db.notify(notify::GENERIC, "del", key);
+        }
+
+        self.write_optional_bulk_string(previous.as_deref())
+    }
+
+    pub(super) fn handle_getex(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received GETEX");
+
+        let Some((RESPData::BulkString(key), args)) = args.split_first() else {
+            return client_error!("wrong number of arguments for 'getex' command");
+        };
+
+        // The outer option is whether the expiry should be changed at all, the inner is the new
+        // expiry, where None means that it should be removed
+        let mut new_expiry: Option<Option<u128>> = None;
+
+        let mut iter = args.iter();
+        while let Some(RESPData::BulkString(arg)) = iter.next() {
+            if new_expiry.is_some() {
+                return client_error!("syntax error");
+            }
+            new_expiry = Some(match arg.to_ascii_uppercase().as_slice() {
+                b"EX" => Some(now() + parse_expire_arg(&mut iter, "getex")? * 1000),
+                b"PX" => Some(now() + parse_expire_arg(&mut iter, "getex")?),
+                b"EXAT" => Some(parse_expire_arg(&mut iter, "getex")? * 1000),
+                b"PXAT" => Some(parse_expire_arg(&mut iter, "getex")?),
+                b"PERSIST" => None,
+                _ => return client_error!("syntax error"),
+            });
+        }
+
+        let mut db = database::lock(0);
+        let value = db.get_string(key)?.map(|v| v.as_bytes().into_owned());
+
+        if let (Some(_), Some(expiry)) = (&value, new_expiry) {
+            log::trace!("Setting TTL: {:?}", expiry);
+            let had_expiry = db.expiry(key).is_some();
+            db.set_expiry(key, expiry);
+            if expiry.is_some() {
+                db.notify(notify::GENERIC, "expire", key);
+            } else if had_expiry {
+                db.notify(notify::GENERIC, "persist", key);
+            }
+        }
+
+        self.write_optional_bulk_string(value.as_deref())
+    }
+
+    pub(super) fn handle_append(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received APPEND");
+
+        let [RESPData::BulkString(key), RESPData::BulkString(value)] = args else {
+            return client_error!("wrong number of arguments for 'append' command");
+        };
+
+        let mut db = database::lock(0);
+
+        let length = match db.get_string_mut(key)? {
+            Some(current) => {
+                if current.len() + value.len() > PROTO_MAX_BULK_LEN {
+                    return client_error!(
+                        "string exceeds maximum allowed size (proto-max-bulk-len)"
+                    );
+                }
+                let current = current.raw_mut();
+                current.extend_from_slice(value);
+                let length = current.len();
+                db.touch(key);
+                length
+            }
+            None => {
+                db.insert(key, Value::String(value.to_vec().into()));
+                value.len()
+            }
+        };
+        db.notify(notify::STRING, "append", key);
+
+        self.write_integer(length as i64)
+    }
+
+    pub(super) fn handle_strlen(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received STRLEN");
+
+        let [RESPData::BulkString(key)] = args else {
+            return client_error!("wrong number of arguments for 'strlen' command");
+        };
+
+        let length = database::lock(0).get_string(key)?.map_or(0, |v| v.len());
+
+        self.write_integer(length as i64)
+    }
+
+    pub(super) fn handle_getrange(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received GETRANGE");
+
+        let [RESPData::BulkString(key), RESPData::BulkString(start), RESPData::BulkString(end)] =
+            args
+        else {
+            return client_error!("wrong number of arguments for 'getrange' command");
+        };
+
+        let start: i64 = parse_int(start)?;
+        let end: i64 = parse_int(end)?;
+
+        let mut db = database::lock(0);
+        let value = db
+            .get_string(key)?
+            .map(|v| v.as_bytes())
+            .unwrap_or_default();
+        let range = resolve_range(value.len(), start, end);
+
+        self.write_bulk_string(&value[range])
+    }
+
+    pub(super) fn handle_setrange(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received SETRANGE");
+
+        let [RESPData::BulkString(key), RESPData::BulkString(offset), RESPData::BulkString(value)] =
+            args
+        else {
+            return client_error!("wrong number of arguments for 'setrange' command");
+        };
+
+        let offset: i64 = parse_int(offset)?;
+        if offset < 0 {
+            return client_error!("offset is out of range");
+        }
+        let offset = offset as usize;
+
+        let mut db = database::lock(0);
+
+        // Setting an empty value doesn't modify anything, not even create the key
+        if value.is_empty() {
+            let length = db.get_string_mut(key)?.map_or(0, |v| v.len());
+            return self.write_integer(length as i64);
+        }
+
+        if offset + value.len() > PROTO_MAX_BULK_LEN {
+            return client_error!("string exceeds maximum allowed size (proto-max-bulk-len)");
+        }
+
+        if db.get_string_mut(key)?.is_none() {
+            db.insert(key, Value::String(StringValue::Raw(Vec::new())));
+        }
+        let current = db.get_string_mut(key)?.unwrap().raw_mut();
+
+        // Any gap between the current end of the string and the offset is padded with zero bytes
+        if current.len() < offset + value.len() {
+            current.resize(offset + value.len(), 0);
+        }
+        current[offset..offset + value.len()].copy_from_slice(value);
+        let length = current.len();
+        db.touch(key);
+        db.notify(notify::STRING, "setrange", key);
+
+        self.write_integer(length as i64)
+    }
+
+    pub(super) fn handle_incrby(&mut self, args: &[RESPData], command: &[u8]) -> Result<()> {
+        log::debug!("Received {}", String::from_utf8_lossy(command));
+
+        let (key, increment) = match (command, args) {
+            (b"INCR", [RESPData::BulkString(key)]) => (key, 1),
+            (b"DECR", [RESPData::BulkString(key)]) => (key, -1),
+            (b"INCRBY", [RESPData::BulkString(key), RESPData::BulkString(increment)]) => {
+                (key, parse_int(increment)?)
+            }
+            (b"DECRBY", [RESPData::BulkString(key), RESPData::BulkString(decrement)]) => {
+                let decrement: i64 = parse_int(decrement)?;
+                let Some(increment) = decrement.checked_neg() else {
+                    return client_error!("decrement would overflow");
+                };
+                (key, increment)
+            }
+            _ => {
+                return client_error!(
+                    "wrong number of arguments for '{}' command",
+                    String::from_utf8_lossy(command).to_lowercase()
+                );
+            }
+        };
+
+        let mut db = database::lock(0);
+
+        let current = match db.get_string_mut(key)? {
+            Some(value) => match value.as_int() {
+                Some(current) => current,
+                None => return client_error!("value is not an integer or out of range"),
+            },
+            None => 0,
+        };
+        let Some(new) = current.checked_add(increment) else {
+            return client_error!("increment or decrement would overflow");
+        };
+
+        // Inserting leaves the TTL in place, which is what we want
+        db.insert(key, Value::String(StringValue::Int(new)));
+        db.notify(notify::STRING, "incrby", key);
+
+        self.write_integer(new)
+    }
+
+    pub(super) fn handle_incrbyfloat(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received INCRBYFLOAT");
+
+        let [RESPData::BulkString(key), RESPData::BulkString(increment)] = args else {
+            return client_error!("wrong number of arguments for 'incrbyfloat' command");
+        };
+
+        let Some(increment) = parse_float(increment) else {
+            return client_error!("value is not a valid float");
+        };
+
+        let mut db = database::lock(0);
+
+        let current = match db.get_string_mut(key)? {
+            Some(value) => match parse_float(&value.as_bytes()) {
+                Some(current) => current,
+                None => return client_error!("value is not a valid float"),
+            },
+            None => 0.0,
+        };
+        let new = current + increment;
+        if !new.is_finite() {
+            return client_error!("increment would produce NaN or Infinity");
+        }
+
+        let new = format_human_float(new);
+        db.insert(key, Value::String(new.clone().into()));
+        db.notify(notify::STRING, "incrbyfloat", key);
+
+        self.write_bulk_string(&new)
+    }
+
+    pub(super) fn handle_mget(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received MGET");
+
+        let keys = bulk_strings(args)?;
+        if keys.is_empty() {
+            return client_error!("wrong number of arguments for 'mget' command");
+        }
+
+        let values: Vec<Option<Vec<u8>>> = {
+            let mut db = database::lock(0);
+            // Keys holding other types are returned as nulls, rather than failing the command
+            keys.iter()
+                .map(|key| match db.get(key) {
+                    Some(Value::String(v)) => Some(v.as_bytes().into_owned()),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        self.write_resp(&RESPData::Array(
+            values
+                .iter()
+                .map(|value| match value {
+                    Some(value) => RESPData::BulkString(value),
+                    None => RESPData::Null,
+                })
+                .collect(),
+        ))
+    }
+
+    pub(super) fn handle_mset(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received MSET");
+
+        let pairs = bulk_strings(args)?;
+        if pairs.is_empty() || pairs.len() % 2 != 0 {
+            return client_error!("wrong number of arguments for 'mset' command");
+        }
+
+        let mut db = database::lock(0);
+        for pair in pairs.chunks_exact(2) {
+            set_with_options(&mut db, pair[0], pair[1], &SetOptions::default())?;
+        }
+
+        self.stream.write_all(OK)?;
+
+        Ok(())
+    }
+
+    pub(super) fn handle_msetnx(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received MSETNX");
+
+        let pairs = bulk_strings(args)?;
+        if pairs.is_empty() || pairs.len() % 2 != 0 {
+            return client_error!("wrong number of arguments for 'msetnx' command");
+        }
+
+        let mut db = database::lock(0);
+
+        // Either all of the keys are set, or none of them are
+        if pairs.chunks_exact(2).any(|pair| db.contains_key(pair[0])) {
+            return self.write_integer(0);
+        }
+        for pair in pairs.chunks_exact(2) {
+            set_with_options(&mut db, pair[0], pair[1], &SetOptions::default())?;
+        }
+
+        self.write_integer(1)
+    }
+}
+
+/// Parse bytes as a float, rejecting NaN
+pub(super) fn parse_float(raw: &[u8]) -> Option<f64> {
+    let value = std::str::from_utf8(raw).ok()?.parse::<f64>().ok()?;
+    if value.is_nan() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+/// Format a float like Redis does for scores, with "%.17g" and trailing zeros removed
+///
+/// The digits are the shortest ones that round-trip to the same float, which is never more than
+/// the 17 significant digits, so sums like 10.1 + 0.1 are "10.2". Like "%g", an exponent is only
+/// used when it's below -4 or at least the precision.
+pub(super) fn format_float(value: f64) -> Vec<u8> {
+    if value.is_infinite() {
+        return if value > 0.0 {
+            b"inf".to_vec()
+        } else {
+            b"-inf".to_vec()
+        };
+    }
+
+    // Scientific notation gives the shortest digits, like "-1.25e-7"
+    let scientific = format!("{:e}", value);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    let (sign, mantissa) = match mantissa.strip_prefix('-') {
+        Some(mantissa) => ("-", mantissa),
+        None => ("", mantissa),
+    };
+    let digits = mantissa.replace('.', "");
+
+    let formatted = if !(-4..17).contains(&exponent) {
+        let (first, rest) = digits.split_at(1);
+        let fraction = if rest.is_empty() {
+            String::new()
+        } else {
+            format!(".{}", rest)
+        };
+        let exponent_sign = if exponent < 0 { '-' } else { '+' };
+        format!(
+            "{}{}{}e{}{:02}",
+            sign,
+            first,
+            fraction,
+            exponent_sign,
+            exponent.unsigned_abs()
+        )
+    } else if exponent < 0 {
+        let zeros = "0".repeat(exponent.unsigned_abs() as usize - 1);
+        format!("{}0.{}{}", sign, zeros, digits)
+    } else {
+        let point = exponent as usize + 1;
+        if digits.len() > point {
+            format!("{}{}.{}", sign, &digits[..point], &digits[point..])
+        } else {
+            format!("{}{}{}", sign, digits, "0".repeat(point - digits.len()))
+        }
+    };
+    formatted.into_bytes()
+}
+
+/// Format a float like Redis does for INCRBYFLOAT, with "%.17Lf" and trailing zeros removed
+///
+/// An exponent is never used. The digits are the shortest ones that round-trip to the same float,
+/// so sums like 10.1 + 0.1 are "10.2" rather than showing the error of the binary sum.
+pub(super) fn format_human_float(value: f64) -> Vec<u8> {
+    // Display never uses an exponent, but tiny values can need more than 17 decimal places
+    let shortest = value.to_string();
+    let formatted = match shortest.split_once('.') {
+        Some((_, decimals)) if decimals.len() > 17 => {
+            let rounded = format!("{:.17}", value);
+            rounded
+                .trim_end_matches('0')
+                .trim_end_matches('.')
+                .to_string()
+        }
+        _ => shortest,
+    };
+    formatted.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_float() {
+        let format = |value: f64| String::from_utf8(format_float(value)).unwrap();
+        assert_eq!(format(10.1 + 0.1), "10.2");
+        assert_eq!(format(0.1 + 0.2), "0.30000000000000004");
+        assert_eq!(format(5.0), "5");
+        assert_eq!(format(-0.0), "-0");
+        assert_eq!(format(-2.5), "-2.5");
+        assert_eq!(format(1234.5678), "1234.5678");
+        assert_eq!(format(0.0001), "0.0001");
+        assert_eq!(format(0.00001), "1e-05");
+        assert_eq!(format(1e16), "10000000000000000");
+        assert_eq!(format(1e17), "1e+17");
+        assert_eq!(format(-1.5e300), "-1.5e+300");
+        assert_eq!(format(f64::INFINITY), "inf");
+        assert_eq!(format(f64::NEG_INFINITY), "-inf");
+    }
+
+    #[test]
+    fn test_format_human_float() {
+        let format = |value: f64| String::from_utf8(format_human_float(value)).unwrap();
+        assert_eq!(format(10.1 + 0.1), "10.2");
+        assert_eq!(format(0.1 + 0.2), "0.30000000000000004");
+        assert_eq!(format(5.0), "5");
+        assert_eq!(format(-2.5), "-2.5");
+        assert_eq!(format(1e20), "100000000000000000000");
+        assert_eq!(format(1.5e-10), "0.00000000015");
+        assert_eq!(format(1.25e-17), "0.00000000000000001");
+        assert_eq!(format(1e-20), "0");
+    }
+}
@@ -0,0 +1,211 @@
+use super::{bulk_strings, commands, Connection, NULL_ARRAY, OK};
+use crate::{database, error::RustisError, resp::RESPData, watch, Result};
+use std::io::Write;
+
+/// The commands queued by a client after MULTI, to be run together on EXEC
+#[derive(Debug, Default)]
+pub(super) struct Transaction {
+    /// The arguments of each queued command, starting with the command name
+    queued: Vec<Vec<Vec<u8>>>,
+    /// Whether a command failed to be queued, in which case EXEC discards the transaction
+    failed: bool,
+}
+
+/// The commands that are run right away while in a transaction, instead of being queued
+pub(super) const NOT_QUEUED: &[&[u8]] =
+    &[b"MULTI", b"EXEC", b"DISCARD", b"WATCH", b"QUIT", b"RESET"];
+
+/// Format the error for a command that isn't supported, like Redis does
+pub(super) fn unknown_command(name: &[u8], args: &[&[u8]]) -> RustisError {
+    let args: String = args
+        .iter()
+        .map(|arg| format!("'{}' ", String::from_utf8_lossy(arg)))
+        .collect();
+    RustisError::ClientError(format!(
+        "unknown command '{}', with args beginning with: {}",
+        String::from_utf8_lossy(name),
+        args
+    ))
+}
+
+impl Connection {
+    pub(super) fn in_transaction(&self) -> bool {
+        self.transaction.is_some()
+    }
+
+    /// Make EXEC discard the transaction, when a command can't be queued
+    pub(super) fn fail_transaction(&mut self) {
+        if let Some(transaction) = self.transaction.as_mut() {
+            transaction.failed = true;
+        }
+    }
+
+    /// Queue a command in the transaction, checking that it exists and has a valid number of
+    /// arguments, so that a transaction with errors is never run
+    pub(super) fn queue_command(&mut self, command: &[u8], array: &[RESPData]) -> Result<()> {
+        let Some(transaction) = self.transaction.as_mut() else {
+            return Ok(());
+        };
+
+        let args: Option<Vec<&[u8]>> = array
+            .iter()
+            .map(|arg| match arg {
+                RESPData::BulkString(arg) => Some(*arg),
+                _ => None,
+            })
+            .collect();
+        let Some(args) = args else {
+            transaction.failed = true;
+            return client_error!("syntax error");
+        };
+
+        match commands::lookup(command) {
+            None => {
+                transaction.failed = true;
+                return Err(unknown_command(args[0], &args[1..]));
+            }
+            Some(command) if !command.arity_matches(args.len()) => {
+                transaction.failed = true;
+                return client_error!(
+                    "wrong number of arguments for '{}' command",
+                    String::from_utf8_lossy(args[0]).to_lowercase()
+                );
+            }
+            Some(_) => {}
+        }
+
+        transaction
+            .queued
+            .push(args.iter().map(|arg| arg.to_vec()).collect());
+        self.stream.write_all(b"+QUEUED\r\n")?;
+        Ok(())
+    }
+
+    pub(super) fn handle_multi(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received MULTI");
+
+        if !args.is_empty() {
+            return client_error!("wrong number of arguments for 'multi' command");
+        }
+        if self.in_transaction() {
+            return client_error!("MULTI calls can not be nested");
+        }
+
+        self.transaction = Some(Transaction::default());
+        self.stream.write_all(OK)?;
+        Ok(())
+    }
+
+    pub(super) fn handle_discard(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received DISCARD");
+
+        if !args.is_empty() {
+            return client_error!("wrong number of arguments for 'discard' command");
+        }
+        if self.transaction.take().is_none() {
+            return client_error!("DISCARD without MULTI");
+        }
+        self.unwatch();
+
+        self.stream.write_all(OK)?;
+        Ok(())
+    }
+
+    /// Run the queued commands one after the other, replying with an array of their replies
+    ///
+    /// Nothing else runs in between, as the server is single-threaded. Commands that would block
+    /// reply as if they timed out right away. If a watched key was modified since WATCH, nothing
+    /// is run and the reply is a null array.
+    pub(super) fn handle_exec(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received EXEC");
+
+        if !args.is_empty() {
+            return client_error!("wrong number of arguments for 'exec' command");
+        }
+        let Some(transaction) = self.transaction.take() else {
+            return client_error!("EXEC without MULTI");
+        };
+        // Watched keys that have expired since WATCH count as modified, so evict them first
+        {
+            let mut db = database::lock(0);
+            for key in &self.watched {
+                db.contains_key(key);
+            }
+        }
+        let dirty = watch::is_dirty(self.id);
+        self.unwatch();
+
+        if transaction.failed {
+            return Err(RustisError::ClientErrorWithCode(
+                "EXECABORT",
+                "Transaction discarded because of previous errors.".to_string(),
+            ));
+        }
+
+        if dirty {
+            self.stream.write_all(NULL_ARRAY)?;
+            return Ok(());
+        }
+
+        write!(self.stream, "*{}\r\n", transaction.queued.len())?;
+        for command in &transaction.queued {
+            let array: Vec<RESPData> = command
+                .iter()
+                .map(|arg| RESPData::BulkString(arg))
+                .collect();
+            if let Err(e) = self.process_array(&array) {
+                self.write_command_error(e)?;
+            }
+            if self.is_blocked() {
+                self.time_out()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Watch keys, so that the next transaction only runs if none of them are modified until EXEC
+    pub(super) fn handle_watch(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received WATCH");
+
+        if args.is_empty() {
+            return client_error!("wrong number of arguments for 'watch' command");
+        }
+        if self.in_transaction() {
+            return client_error!("WATCH inside MULTI is not allowed");
+        }
+        let keys = bulk_strings(args)?;
+
+        let mut db = database::lock(0);
+        for key in keys {
+            if self.watched.iter().any(|watched| watched == key) {
+                continue;
+            }
+            // Evict the key now if it has already expired, so it's not seen as modified later
+            db.contains_key(key);
+            watch::watch(self.id, 0, key);
+            self.watched.push(key.to_vec());
+        }
+
+        self.stream.write_all(OK)?;
+        Ok(())
+    }
+
+    pub(super) fn handle_unwatch(&mut self, args: &[RESPData]) -> Result<()> {
+        log::debug!("Received UNWATCH");
+
+        if !args.is_empty() {
+            return client_error!("wrong number of arguments for 'unwatch' command");
+        }
+        self.unwatch();
+
+        self.stream.write_all(OK)?;
+        Ok(())
+    }
+
+    /// Stop watching all keys
+    pub(super) fn unwatch(&mut self) {
+        watch::unwatch(self.id, 0, &self.watched);
+        self.watched.clear();
+    }
+}
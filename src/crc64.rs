@@ -0,0 +1,30 @@
+/// The CRC64 checksum (Jones polynomial, reflected) that Redis appends to DUMP payloads
+pub(crate) fn crc64(data: &[u8]) -> u64 {
+    // The Jones polynomial 0xad93d23594c935a9, bit reversed
+    const POLY: u64 = 0x95ac9329ac4bc9b5;
+
+    let mut crc: u64 = 0;
+    for &byte in data {
+        crc ^= byte as u64;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc64() {
+        assert_eq!(crc64(b""), 0);
+        // The check value from Redis' own tests
+        assert_eq!(crc64(b"123456789"), 0xe9c6d914c4b8d9ca);
+    }
+}
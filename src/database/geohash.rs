@@ -0,0 +1,464 @@
+//! Geohash encoding of coordinates into sorted set scores, in exactly the same way as Redis
+//!
+//! A position is stored as a 52 bit geohash, interleaving 26 bits of latitude with 26 bits of
+//! longitude, which fits exactly in the score of a sorted set. Latitudes are limited to the range
+//! that's covered by the Web Mercator projection, rather than -90 to 90 like standard geohashes.
+//!
+//! Searching for the positions in an area finds the geohash box at a precision where the box is
+//! about the size of the area, then looks at the members with scores within that box and its eight
+//! neighbours, filtering out the ones that are too far away.
+
+use std::ops::Range;
+
+pub(crate) const LONGITUDE_MIN: f64 = -180.0;
+pub(crate) const LONGITUDE_MAX: f64 = 180.0;
+pub(crate) const LATITUDE_MIN: f64 = -85.05112878;
+pub(crate) const LATITUDE_MAX: f64 = 85.05112878;
+
+/// The number of bits used for each of the latitude and longitude
+const STEP_MAX: u8 = 26;
+/// The radius of the Earth used for distances, which are calculated as if it's a sphere
+const EARTH_RADIUS_IN_METERS: f64 = 6372797.560856;
+/// Half the circumference of the Earth in the Mercator projection
+const MERCATOR_MAX: f64 = 20037726.37;
+const GEO_ALPHABET: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// The bounds of the coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Bounds {
+    longitude: (f64, f64),
+    latitude: (f64, f64),
+}
+
+const MERCATOR_BOUNDS: Bounds = Bounds {
+    longitude: (LONGITUDE_MIN, LONGITUDE_MAX),
+    latitude: (LATITUDE_MIN, LATITUDE_MAX),
+};
+
+/// The bounds used by standard geohashes, as returned by GEOHASH
+const STANDARD_BOUNDS: Bounds = Bounds {
+    longitude: (-180.0, 180.0),
+    latitude: (-90.0, 90.0),
+};
+
+/// A geohash at a precision of `step` bits for each of the latitude and longitude
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct GeoHash {
+    bits: u64,
+    step: u8,
+}
+
+impl GeoHash {
+    fn encode(bounds: &Bounds, longitude: f64, latitude: f64, step: u8) -> Option<GeoHash> {
+        if !(LONGITUDE_MIN..=LONGITUDE_MAX).contains(&longitude)
+            || !(LATITUDE_MIN..=LATITUDE_MAX).contains(&latitude)
+            || !(bounds.longitude.0..=bounds.longitude.1).contains(&longitude)
+            || !(bounds.latitude.0..=bounds.latitude.1).contains(&latitude)
+        {
+            return None;
+        }
+        let scale = (1u64 << step) as f64;
+        let latitude_offset =
+            (latitude - bounds.latitude.0) / (bounds.latitude.1 - bounds.latitude.0) * scale;
+        let longitude_offset =
+            (longitude - bounds.longitude.0) / (bounds.longitude.1 - bounds.longitude.0) * scale;
+        Some(GeoHash {
+            bits: interleave(latitude_offset as u32, longitude_offset as u32),
+            step,
+        })
+    }
+
+    /// The area covered by the geohash
+    fn decode(self, bounds: &Bounds) -> Bounds {
+        let (latitude, longitude) = deinterleave(self.bits);
+        let scale = (1u64 << self.step) as f64;
+        let latitude_scale = bounds.latitude.1 - bounds.latitude.0;
+        let longitude_scale = bounds.longitude.1 - bounds.longitude.0;
+        Bounds {
+            longitude: (
+                bounds.longitude.0 + (longitude as f64 / scale) * longitude_scale,
+                bounds.longitude.0 + ((longitude as f64 + 1.0) / scale) * longitude_scale,
+            ),
+            latitude: (
+                bounds.latitude.0 + (latitude as f64 / scale) * latitude_scale,
+                bounds.latitude.0 + ((latitude as f64 + 1.0) / scale) * latitude_scale,
+            ),
+        }
+    }
+
+    /// The score range of the members within the geohash
+    fn scores(self) -> Range<u64> {
+        let shift = 2 * (STEP_MAX - self.step) as u32;
+        (self.bits << shift)..((self.bits + 1) << shift)
+    }
+
+    /// Move by one box east (positive) or west (negative), wrapping around
+    fn move_x(self, d: i8) -> GeoHash {
+        let x = self.bits & 0xaaaa_aaaa_aaaa_aaaa;
+        let y = self.bits & 0x5555_5555_5555_5555;
+        let zz = 0x5555_5555_5555_5555u64 >> (64 - self.step as u32 * 2);
+        let x = if d > 0 {
+            x.wrapping_add(zz + 1)
+        } else {
+            (x | zz).wrapping_sub(zz + 1)
+        };
+        let x = x & (0xaaaa_aaaa_aaaa_aaaau64 >> (64 - self.step as u32 * 2));
+        GeoHash {
+            bits: x | y,
+            step: self.step,
+        }
+    }
+
+    /// Move by one box north (positive) or south (negative), wrapping around
+    fn move_y(self, d: i8) -> GeoHash {
+        let x = self.bits & 0xaaaa_aaaa_aaaa_aaaa;
+        let y = self.bits & 0x5555_5555_5555_5555;
+        let zz = 0xaaaa_aaaa_aaaa_aaaau64 >> (64 - self.step as u32 * 2);
+        let y = if d > 0 {
+            y.wrapping_add(zz + 1)
+        } else {
+            (y | zz).wrapping_sub(zz + 1)
+        };
+        let y = y & (0x5555_5555_5555_5555u64 >> (64 - self.step as u32 * 2));
+        GeoHash {
+            bits: x | y,
+            step: self.step,
+        }
+    }
+
+    fn shifted(self, dx: i8, dy: i8) -> GeoHash {
+        let mut hash = self;
+        if dx != 0 {
+            hash = hash.move_x(dx);
+        }
+        if dy != 0 {
+            hash = hash.move_y(dy);
+        }
+        hash
+    }
+}
+
+/// Interleave the bits of two 32 bit integers, with the first in the even bits
+fn interleave(x: u32, y: u32) -> u64 {
+    fn spread(value: u32) -> u64 {
+        let mut v = value as u64;
+        v = (v | (v << 16)) & 0x0000_FFFF_0000_FFFF;
+        v = (v | (v << 8)) & 0x00FF_00FF_00FF_00FF;
+        v = (v | (v << 4)) & 0x0F0F_0F0F_0F0F_0F0F;
+        v = (v | (v << 2)) & 0x3333_3333_3333_3333;
+        (v | (v << 1)) & 0x5555_5555_5555_5555
+    }
+    spread(x) | (spread(y) << 1)
+}
+
+/// Split the even and odd bits of an interleaved integer
+fn deinterleave(interleaved: u64) -> (u32, u32) {
+    fn squash(value: u64) -> u32 {
+        let mut v = value & 0x5555_5555_5555_5555;
+        v = (v | (v >> 1)) & 0x3333_3333_3333_3333;
+        v = (v | (v >> 2)) & 0x0F0F_0F0F_0F0F_0F0F;
+        v = (v | (v >> 4)) & 0x00FF_00FF_00FF_00FF;
+        v = (v | (v >> 8)) & 0x0000_FFFF_0000_FFFF;
+        ((v | (v >> 16)) & 0x0000_0000_FFFF_FFFF) as u32
+    }
+    (squash(interleaved), squash(interleaved >> 1))
+}
+
+/// Encode a position as the score of a sorted set, if it's within the valid range
+pub(crate) fn encode(longitude: f64, latitude: f64) -> Option<f64> {
+    GeoHash::encode(&MERCATOR_BOUNDS, longitude, latitude, STEP_MAX).map(|hash| hash.bits as f64)
+}
+
+/// Decode the score of a sorted set into a position, which is the centre of the geohash box
+pub(crate) fn decode(score: f64) -> (f64, f64) {
+    let area = GeoHash {
+        bits: score as u64,
+        step: STEP_MAX,
+    }
+    .decode(&MERCATOR_BOUNDS);
+    let longitude =
+        ((area.longitude.0 + area.longitude.1) / 2.0).clamp(LONGITUDE_MIN, LONGITUDE_MAX);
+    let latitude = ((area.latitude.0 + area.latitude.1) / 2.0).clamp(LATITUDE_MIN, LATITUDE_MAX);
+    (longitude, latitude)
+}
+
+/// The standard 11 character geohash of the position stored in a score, as returned by GEOHASH
+pub(crate) fn to_base32(score: f64) -> String {
+    let (longitude, latitude) = decode(score);
+    let bits = GeoHash::encode(&STANDARD_BOUNDS, longitude, latitude, STEP_MAX)
+        .map_or(0, |hash| hash.bits);
+    (0..11)
+        .map(|i| {
+            // There are only 52 bits, so the last character is always zero
+            let index = if i == 10 {
+                0
+            } else {
+                (bits >> (52 - (i + 1) * 5)) & 0x1f
+            };
+            GEO_ALPHABET[index as usize] as char
+        })
+        .collect()
+}
+
+/// Convert radians to degrees by dividing rather than multiplying, which rounds differently to
+/// `f64::to_degrees` in some cases
+fn radians_to_degrees(radians: f64) -> f64 {
+    radians / (std::f64::consts::PI / 180.0)
+}
+
+fn latitude_distance(latitude1: f64, latitude2: f64) -> f64 {
+    EARTH_RADIUS_IN_METERS * (latitude2.to_radians() - latitude1.to_radians()).abs()
+}
+
+/// The distance in meters between two positions, using the haversine formula
+pub(crate) fn distance(longitude1: f64, latitude1: f64, longitude2: f64, latitude2: f64) -> f64 {
+    let v = ((longitude2.to_radians() - longitude1.to_radians()) / 2.0).sin();
+    // The longitudes are practically the same, so the expensive part can be skipped
+    if v == 0.0 {
+        return latitude_distance(latitude1, latitude2);
+    }
+    let latitude1 = latitude1.to_radians();
+    let latitude2 = latitude2.to_radians();
+    let u = ((latitude2 - latitude1) / 2.0).sin();
+    let a = u * u + latitude1.cos() * latitude2.cos() * v * v;
+    2.0 * EARTH_RADIUS_IN_METERS * a.sqrt().asin()
+}
+
+/// The shape of an area to search, in some unit of distance
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Shape {
+    Radius(f64),
+    Box { width: f64, height: f64 },
+}
+
+/// An area to search for positions in, centred on a position
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Area {
+    pub(crate) longitude: f64,
+    pub(crate) latitude: f64,
+    pub(crate) shape: Shape,
+    /// How many meters there are in the unit of the shape
+    pub(crate) to_meters: f64,
+}
+
+impl Area {
+    /// The distance in meters from the centre to a position, if the position is within the area
+    pub(crate) fn distance_to(&self, longitude: f64, latitude: f64) -> Option<f64> {
+        match self.shape {
+            Shape::Radius(radius) => {
+                let distance = distance(self.longitude, self.latitude, longitude, latitude);
+                (distance <= radius * self.to_meters).then_some(distance)
+            }
+            Shape::Box { width, height } => {
+                // The latitude distance is cheaper to calculate, so it's checked first
+                if latitude_distance(latitude, self.latitude) > height * self.to_meters / 2.0 {
+                    return None;
+                }
+                let longitude_distance = distance(longitude, latitude, self.longitude, latitude);
+                if longitude_distance > width * self.to_meters / 2.0 {
+                    return None;
+                }
+                Some(distance(self.longitude, self.latitude, longitude, latitude))
+            }
+        }
+    }
+
+    /// The bounding box of the area, as the minimum and maximum longitude and latitude
+    fn bounding_box(&self) -> Bounds {
+        let (width, height) = match self.shape {
+            Shape::Radius(radius) => (self.to_meters * radius, self.to_meters * radius),
+            Shape::Box { width, height } => (
+                self.to_meters * (width / 2.0),
+                self.to_meters * (height / 2.0),
+            ),
+        };
+        let latitude_delta = radians_to_degrees(height / EARTH_RADIUS_IN_METERS);
+        let longitude_delta_top = radians_to_degrees(
+            width / EARTH_RADIUS_IN_METERS / (self.latitude + latitude_delta).to_radians().cos(),
+        );
+        let longitude_delta_bottom = radians_to_degrees(
+            width / EARTH_RADIUS_IN_METERS / (self.latitude - latitude_delta).to_radians().cos(),
+        );
+        // The widest part of the area is the side nearest the equator
+        let longitude_delta = if self.latitude < 0.0 {
+            longitude_delta_bottom
+        } else {
+            longitude_delta_top
+        };
+        Bounds {
+            longitude: (
+                self.longitude - longitude_delta,
+                self.longitude + longitude_delta,
+            ),
+            latitude: (
+                self.latitude - latitude_delta,
+                self.latitude + latitude_delta,
+            ),
+        }
+    }
+
+    /// The ranges of scores to look at for positions within the area, in the order to look at
+    /// them
+    ///
+    /// These are the geohash box that the centre is in, followed by its neighbours to the north,
+    /// south, east, west, north east, north west, south east and south west, leaving out the
+    /// neighbours that can't overlap the area.
+    pub(crate) fn score_ranges(&self) -> Vec<Range<u64>> {
+        let bounds = self.bounding_box();
+        let radius = match self.shape {
+            Shape::Radius(radius) => radius,
+            Shape::Box { width, height } => {
+                ((width / 2.0) * (width / 2.0) + (height / 2.0) * (height / 2.0)).sqrt()
+            }
+        } * self.to_meters;
+
+        let mut step = estimate_step(radius, self.latitude);
+        let Some(mut hash) = GeoHash::encode(&MERCATOR_BOUNDS, self.longitude, self.latitude, step)
+        else {
+            return Vec::new();
+        };
+
+        // The step may not be small enough when the area is near the edge of the box, where one of
+        // the neighbours doesn't cover the rest of the area
+        let north = hash.shifted(0, 1).decode(&MERCATOR_BOUNDS);
+        let south = hash.shifted(0, -1).decode(&MERCATOR_BOUNDS);
+        let east = hash.shifted(1, 0).decode(&MERCATOR_BOUNDS);
+        let west = hash.shifted(-1, 0).decode(&MERCATOR_BOUNDS);
+        if step > 1
+            && (north.latitude.1 < bounds.latitude.1
+                || south.latitude.0 > bounds.latitude.0
+                || east.longitude.1 < bounds.longitude.1
+                || west.longitude.0 > bounds.longitude.0)
+        {
+            step -= 1;
+            hash = GeoHash::encode(&MERCATOR_BOUNDS, self.longitude, self.latitude, step).unwrap();
+        }
+        let area = hash.decode(&MERCATOR_BOUNDS);
+
+        // Neighbours that are entirely outside of the bounding box aren't needed
+        let (mut north, mut south, mut east, mut west) = (true, true, true, true);
+        if step >= 2 {
+            south = area.latitude.0 >= bounds.latitude.0;
+            north = area.latitude.1 <= bounds.latitude.1;
+            west = area.longitude.0 >= bounds.longitude.0;
+            east = area.longitude.1 <= bounds.longitude.1;
+        }
+        let neighbours = [
+            (0, 0, true),
+            (0, 1, north),
+            (0, -1, south),
+            (1, 0, east),
+            (-1, 0, west),
+            (1, 1, north && east),
+            (-1, 1, north && west),
+            (1, -1, south && east),
+            (-1, -1, south && west),
+        ];
+
+        // With a huge radius, adjacent neighbours can be the same box
+        let mut boxes: Vec<GeoHash> = Vec::with_capacity(neighbours.len());
+        for (dx, dy, needed) in neighbours {
+            let neighbour = hash.shifted(dx, dy);
+            if !needed {
+                continue;
+            }
+            if boxes.len() > 1 && boxes.last() == Some(&neighbour) {
+                continue;
+            }
+            boxes.push(neighbour);
+        }
+        boxes.into_iter().map(GeoHash::scores).collect()
+    }
+}
+
+/// The number of bits of precision to use for a geohash box about the size of the radius
+fn estimate_step(mut radius: f64, latitude: f64) -> u8 {
+    if radius == 0.0 {
+        return STEP_MAX;
+    }
+    let mut step: i32 = 1;
+    while radius < MERCATOR_MAX {
+        radius *= 2.0;
+        step += 1;
+    }
+    // Make sure the radius is included in most cases
+    step -= 2;
+
+    // The boxes get narrower towards the poles
+    if !(-66.0..=66.0).contains(&latitude) {
+        step -= 1;
+        if !(-80.0..=80.0).contains(&latitude) {
+            step -= 1;
+        }
+    }
+    step.clamp(1, STEP_MAX as i32) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_and_decode() {
+        // The positions from the Redis documentation
+        let palermo = encode(13.361389, 38.115556).unwrap();
+        assert_eq!(palermo, 3479099956230698.0);
+        let catania = encode(15.087269, 37.502669).unwrap();
+        assert_eq!(catania, 3479447370796909.0);
+
+        let (longitude, latitude) = decode(palermo);
+        assert_eq!(format!("{:.17}", longitude), "13.36138933897018433");
+        assert_eq!(format!("{:.17}", latitude), "38.11555639549629859");
+        assert_eq!(to_base32(palermo), "sqc8b49rny0");
+        assert_eq!(to_base32(catania), "sqdtr74hyu0");
+
+        assert_eq!(encode(181.0, 0.0), None);
+        assert_eq!(encode(0.0, 85.06), None);
+    }
+
+    #[test]
+    fn test_distance() {
+        let (lon1, lat1) = decode(encode(13.361389, 38.115556).unwrap());
+        let (lon2, lat2) = decode(encode(15.087269, 37.502669).unwrap());
+        assert_eq!(
+            format!("{:.4}", distance(lon1, lat1, lon2, lat2)),
+            "166274.1516"
+        );
+        assert_eq!(distance(lon1, lat1, lon1, lat1), 0.0);
+    }
+
+    #[test]
+    fn test_interleave() {
+        assert_eq!(interleave(0b11, 0), 0b0101);
+        assert_eq!(interleave(0, 0b11), 0b1010);
+        assert_eq!(deinterleave(interleave(12345, 67890)), (12345, 67890));
+    }
+
+    #[test]
+    fn test_neighbours_wrap_around() {
+        let hash = GeoHash { bits: 0, step: 2 };
+        // Moving west from the westmost box wraps around to the eastmost one
+        let west = hash.shifted(-1, 0);
+        assert_eq!(deinterleave(west.bits), (0, 3));
+        let north = hash.shifted(0, 1);
+        assert_eq!(deinterleave(north.bits), (1, 0));
+    }
+
+    #[test]
+    fn test_score_ranges_cover_the_area() {
+        let area = Area {
+            longitude: 15.0,
+            latitude: 37.0,
+            shape: Shape::Radius(200.0),
+            to_meters: 1000.0,
+        };
+        let ranges = area.score_ranges();
+        assert!(!ranges.is_empty() && ranges.len() <= 9);
+        for (longitude, latitude) in [(13.361389, 38.115556), (15.087269, 37.502669)] {
+            let score = encode(longitude, latitude).unwrap() as u64;
+            assert!(ranges.iter().any(|range| range.contains(&score)));
+            let (longitude, latitude) = decode(score as f64);
+            assert!(area.distance_to(longitude, latitude).is_some());
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use super::ListpackLimits;
+use std::collections::HashMap;
+
+/// A hash, mapping fields to values, where each field can have its own expiry
+///
+/// Small hashes are stored as a flat list of field-value pairs in insertion order, similar to the
+/// listpack encoding in Redis, as scanning a few pairs is cheaper than hashing. Once the hash grows
+/// past the limits it's converted to a hash table, and is never converted back.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Hash {
+    entries: Entries,
+    /// The expiry (unix time in milliseconds) of the fields that have one
+    expiries: HashMap<Vec<u8>, u128>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Entries {
+    Listpack(Vec<(Vec<u8>, Vec<u8>)>),
+    Table(HashMap<Vec<u8>, Vec<u8>>),
+}
+
+impl Default for Entries {
+    fn default() -> Self {
+        Entries::Listpack(Vec::new())
+    }
+}
+
+impl Hash {
+    pub(crate) fn new() -> Self {
+        Hash::default()
+    }
+
+    /// Create a hash from field-value pairs, picking the encoding based on the limits
+    pub(crate) fn from_pairs(pairs: Vec<(Vec<u8>, Vec<u8>)>, limits: &ListpackLimits) -> Self {
+        let mut hash = Hash::new();
+        for (field, value) in pairs {
+            hash.insert(field, value, limits);
+        }
+        hash
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match &self.entries {
+            Entries::Listpack(pairs) => pairs.len(),
+            Entries::Table(table) => table.len(),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the hash is still compactly encoded
+    pub(crate) fn is_listpack(&self) -> bool {
+        matches!(self.entries, Entries::Listpack(_))
+    }
+
+    /// The name of the encoding, as returned by OBJECT ENCODING
+    pub(crate) fn encoding(&self) -> &'static str {
+        match &self.entries {
+            Entries::Listpack(_) if self.has_expiries() => "listpackex",
+            Entries::Listpack(_) => "listpack",
+            Entries::Table(_) => "hashtable",
+        }
+    }
+
+    pub(crate) fn get(&self, field: &[u8]) -> Option<&Vec<u8>> {
+        match &self.entries {
+            Entries::Listpack(pairs) => pairs.iter().find(|(f, _)| f == field).map(|(_, v)| v),
+            Entries::Table(table) => table.get(field),
+        }
+    }
+
+    pub(crate) fn contains(&self, field: &[u8]) -> bool {
+        self.get(field).is_some()
+    }
+
+    /// Set a field to a value, removing any expiry the field had
+    ///
+    /// Returns true if the field is new
+    pub(crate) fn insert(
+        &mut self,
+        field: Vec<u8>,
+        value: Vec<u8>,
+        limits: &ListpackLimits,
+    ) -> bool {
+        self.expiries.remove(&field);
+        self.insert_keep_expiry(field, value, limits)
+    }
+
+    /// Set a field to a value, converting to a hash table if the hash outgrows the limits
+    ///
+    /// Any expiry the field had is kept, which is what commands that modify the value in place,
+    /// like HINCRBY, expect. Returns true if the field is new
+    pub(crate) fn insert_keep_expiry(
+        &mut self,
+        field: Vec<u8>,
+        value: Vec<u8>,
+        limits: &ListpackLimits,
+    ) -> bool {
+        if let Entries::Listpack(pairs) = &mut self.entries {
+            if let Some((_, current)) = pairs.iter_mut().find(|(f, _)| *f == field) {
+                *current = value;
+                if current.len() > limits.max_value {
+                    self.convert_to_table();
+                }
+                return false;
+            }
+
+            if pairs.len() < limits.max_entries
+                && field.len() <= limits.max_value
+                && value.len() <= limits.max_value
+            {
+                pairs.push((field, value));
+                return true;
+            }
+            self.convert_to_table();
+        }
+
+        match &mut self.entries {
+            Entries::Table(table) => table.insert(field, value).is_none(),
+            Entries::Listpack(_) => unreachable!(),
+        }
+    }
+
+    /// Remove a field along with its expiry, returning true if it existed
+    pub(crate) fn remove(&mut self, field: &[u8]) -> bool {
+        self.expiries.remove(field);
+        match &mut self.entries {
+            Entries::Listpack(pairs) => match pairs.iter().position(|(f, _)| f == field) {
+                Some(index) => {
+                    pairs.remove(index);
+                    true
+                }
+                None => false,
+            },
+            Entries::Table(table) => table.remove(field).is_some(),
+        }
+    }
+
+    /// Iterate over the field-value pairs, in insertion order for listpacks
+    pub(crate) fn iter(&self) -> Box<dyn Iterator<Item = (&Vec<u8>, &Vec<u8>)> + '_> {
+        match &self.entries {
+            Entries::Listpack(pairs) => Box::new(pairs.iter().map(|(f, v)| (f, v))),
+            Entries::Table(table) => Box::new(table.iter()),
+        }
+    }
+
+    /// The expiry of a field, if it exists and has one
+    pub(crate) fn expiry(&self, field: &[u8]) -> Option<u128> {
+        self.expiries.get(field).copied()
+    }
+
+    /// Set or clear the expiry (unix time in milliseconds) of an existing field
+    ///
+    /// Returns false if the field does not exist
+    pub(crate) fn set_expiry(&mut self, field: &[u8], expiry: Option<u128>) -> bool {
+        if !self.contains(field) {
+            return false;
+        }
+        match expiry {
+            Some(expiry) => {
+                self.expiries.insert(field.to_vec(), expiry);
+            }
+            None => {
+                self.expiries.remove(field);
+            }
+        }
+        true
+    }
+
+    /// Whether any of the fields have an expiry
+    pub(crate) fn has_expiries(&self) -> bool {
+        !self.expiries.is_empty()
+    }
+
+    /// The earliest expiry of any field
+    pub(crate) fn min_expiry(&self) -> Option<u128> {
+        self.expiries.values().min().copied()
+    }
+
+    /// Remove the fields that have an expiry in the past, returning how many were removed
+    pub(crate) fn remove_expired(&mut self, now: u128) -> usize {
+        if self.expiries.is_empty() {
+            return 0;
+        }
+        let expired: Vec<Vec<u8>> = self
+            .expiries
+            .iter()
+            .filter(|(_, &expiry)| now > expiry)
+            .map(|(field, _)| field.clone())
+            .collect();
+        for field in &expired {
+            self.remove(field);
+        }
+        expired.len()
+    }
+
+    fn convert_to_table(&mut self) {
+        if let Entries::Listpack(pairs) = &mut self.entries {
+            log::trace!(
+                "Converting hash with {} fields to a hash table",
+                pairs.len()
+            );
+            self.entries = Entries::Table(std::mem::take(pairs).into_iter().collect());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: ListpackLimits = ListpackLimits {
+        max_entries: 2,
+        max_value: 8,
+    };
+
+    #[test]
+    fn test_insert_and_remove() {
+        let mut hash = Hash::new();
+        assert!(hash.insert(b"a".to_vec(), b"1".to_vec(), &LIMITS));
+        assert!(!hash.insert(b"a".to_vec(), b"2".to_vec(), &LIMITS));
+        assert_eq!(hash.get(b"a"), Some(&b"2".to_vec()));
+        assert_eq!(hash.len(), 1);
+
+        assert!(hash.remove(b"a"));
+        assert!(!hash.remove(b"a"));
+        assert!(hash.is_empty());
+    }
+
+    #[test]
+    fn test_converts_past_max_entries() {
+        let mut hash = Hash::new();
+        hash.insert(b"a".to_vec(), b"1".to_vec(), &LIMITS);
+        hash.insert(b"b".to_vec(), b"2".to_vec(), &LIMITS);
+        assert_eq!(hash.encoding(), "listpack");
+
+        hash.insert(b"c".to_vec(), b"3".to_vec(), &LIMITS);
+        assert_eq!(hash.encoding(), "hashtable");
+        assert_eq!(hash.len(), 3);
+        assert_eq!(hash.get(b"a"), Some(&b"1".to_vec()));
+
+        // Never converted back, even once it's small again
+        hash.remove(b"c");
+        assert_eq!(hash.encoding(), "hashtable");
+    }
+
+    #[test]
+    fn test_converts_past_max_value() {
+        let mut hash = Hash::new();
+        hash.insert(b"a".to_vec(), b"short".to_vec(), &LIMITS);
+        assert_eq!(hash.encoding(), "listpack");
+
+        hash.insert(b"a".to_vec(), b"much too long".to_vec(), &LIMITS);
+        assert_eq!(hash.encoding(), "hashtable");
+
+        let mut hash = Hash::new();
+        hash.insert(b"a field that is too long".to_vec(), b"1".to_vec(), &LIMITS);
+        assert_eq!(hash.encoding(), "hashtable");
+    }
+
+    #[test]
+    fn test_listpack_keeps_insertion_order() {
+        let limits = ListpackLimits::default();
+        let hash = Hash::from_pairs(
+            vec![
+                (b"z".to_vec(), b"1".to_vec()),
+                (b"a".to_vec(), b"2".to_vec()),
+            ],
+            &limits,
+        );
+        let fields: Vec<&Vec<u8>> = hash.iter().map(|(f, _)| f).collect();
+        assert_eq!(fields, vec![&b"z".to_vec(), &b"a".to_vec()]);
+    }
+
+    #[test]
+    fn test_field_expiries() {
+        let mut hash = Hash::new();
+        hash.insert(b"a".to_vec(), b"1".to_vec(), &LIMITS);
+        hash.insert(b"b".to_vec(), b"2".to_vec(), &LIMITS);
+        assert!(!hash.set_expiry(b"missing", Some(10)));
+        assert!(hash.set_expiry(b"a", Some(10)));
+        assert!(hash.set_expiry(b"b", Some(20)));
+        assert_eq!(hash.encoding(), "listpackex");
+        assert_eq!(hash.min_expiry(), Some(10));
+
+        // Modifying in place keeps the expiry, while setting the field removes it
+        hash.insert_keep_expiry(b"a".to_vec(), b"3".to_vec(), &LIMITS);
+        assert_eq!(hash.expiry(b"a"), Some(10));
+        hash.insert(b"b".to_vec(), b"4".to_vec(), &LIMITS);
+        assert_eq!(hash.expiry(b"b"), None);
+
+        assert_eq!(hash.remove_expired(10), 0);
+        assert_eq!(hash.remove_expired(11), 1);
+        assert!(!hash.contains(b"a"));
+        assert!(!hash.has_expiries());
+        assert_eq!(hash.encoding(), "listpack");
+    }
+}
@@ -0,0 +1,764 @@
+//! HyperLogLog cardinality estimation, stored in string values in exactly the same format as Redis
+//!
+//! A HyperLogLog is a 16 byte header followed by 16384 registers. The header starts with the magic
+//! "HYLL", followed by the encoding and the cached cardinality, stored little endian, where the
+//! most significant bit being set means the cache is invalid.
+//!
+//! The registers are either dense, packed as 6 bit integers, or sparse, as a sequence of opcodes
+//! describing runs of registers:
+//!
+//! * ZERO `00xxxxxx`: between 1 and 64 registers set to zero
+//! * XZERO `01xxxxxx yyyyyyyy`: between 1 and 16384 registers set to zero
+//! * VAL `1vvvvvxx`: between 1 and 4 registers set to a value between 1 and 32
+//!
+//! HyperLogLogs start out sparse, and are converted to dense once a register can't be represented
+//! by the sparse encoding, or the sparse encoding grows past the configured limit. The sparse
+//! encoding is updated in place in the same way as Redis does, so that the bytes are always
+//! identical to what Redis would store.
+
+use crate::random;
+use std::fmt::Write;
+
+const HEADER_SIZE: usize = 16;
+const MAGIC: &[u8] = b"HYLL";
+const ENCODING_DENSE: u8 = 0;
+const ENCODING_SPARSE: u8 = 1;
+/// The byte of the header with the bit that marks the cached cardinality as invalid
+const CACHE_INVALID_BYTE: usize = 15;
+
+/// The number of bits of the hash used to pick a register
+const P: u32 = 14;
+/// The number of bits of the hash used to count the run of zeros
+const Q: u32 = 64 - P;
+/// The number of registers
+pub(crate) const REGISTERS: usize = 1 << P;
+const REGISTER_BITS: usize = 6;
+const REGISTER_MAX: u8 = (1 << REGISTER_BITS) - 1;
+/// The size of a dense HyperLogLog, including the header
+const DENSE_SIZE: usize = HEADER_SIZE + (REGISTERS * REGISTER_BITS).div_ceil(8);
+/// 0.5 / ln(2), the bias correction of the estimator as the number of registers goes to infinity
+const ALPHA_INF: f64 = 0.721_347_520_444_481_7;
+const HASH_SEED: u64 = 0xadc8_3b19;
+
+const SPARSE_VAL_MAX_VALUE: u8 = 32;
+const SPARSE_VAL_MAX_LEN: usize = 4;
+const SPARSE_ZERO_MAX_LEN: usize = 64;
+const SPARSE_XZERO_MAX_LEN: usize = 16384;
+
+/// The registers of a HyperLogLog are not valid, so it can't be used
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Corrupted;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Encoding {
+    Dense,
+    Sparse,
+}
+
+/// An opcode of the sparse encoding
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Opcode {
+    Zero(usize),
+    XZero(usize),
+    Val(u8, usize),
+}
+
+impl Opcode {
+    /// Decode the opcode at the start of the bytes
+    fn decode(bytes: &[u8]) -> Option<Opcode> {
+        let first = *bytes.first()?;
+        let opcode = if first & 0x80 != 0 {
+            Opcode::Val(((first >> 2) & 0x1f) + 1, (first & 0x03) as usize + 1)
+        } else if first & 0x40 != 0 {
+            Opcode::XZero(((((first & 0x3f) as usize) << 8) | *bytes.get(1)? as usize) + 1)
+        } else {
+            Opcode::Zero((first & 0x3f) as usize + 1)
+        };
+        Some(opcode)
+    }
+
+    /// An opcode for a run of zeros, which has to be at most 16384 registers long
+    fn zeros(len: usize) -> Opcode {
+        if len > SPARSE_ZERO_MAX_LEN {
+            Opcode::XZero(len)
+        } else {
+            Opcode::Zero(len)
+        }
+    }
+
+    fn encode(self, out: &mut Vec<u8>) {
+        match self {
+            Opcode::Zero(len) => out.push((len - 1) as u8),
+            Opcode::XZero(len) => {
+                out.push(((len - 1) >> 8) as u8 | 0x40);
+                out.push(((len - 1) & 0xff) as u8);
+            }
+            Opcode::Val(value, len) => out.push(val_byte(value, len)),
+        }
+    }
+
+    /// The number of registers covered by the opcode
+    fn span(self) -> usize {
+        match self {
+            Opcode::Zero(len) | Opcode::XZero(len) | Opcode::Val(_, len) => len,
+        }
+    }
+
+    /// The number of bytes the opcode takes up
+    fn size(self) -> usize {
+        match self {
+            Opcode::XZero(_) => 2,
+            _ => 1,
+        }
+    }
+}
+
+fn val_byte(value: u8, len: usize) -> u8 {
+    ((value - 1) << 2) | (len - 1) as u8 | 0x80
+}
+
+/// Iterate over the opcodes of sparse registers, stopping at the first invalid one
+fn opcodes(sparse: &[u8]) -> impl Iterator<Item = Result<Opcode, Corrupted>> + '_ {
+    let mut offset = 0;
+    std::iter::from_fn(move || {
+        if offset >= sparse.len() {
+            return None;
+        }
+        match Opcode::decode(&sparse[offset..]) {
+            Some(opcode) => {
+                offset += opcode.size();
+                Some(Ok(opcode))
+            }
+            None => {
+                offset = sparse.len();
+                Some(Err(Corrupted))
+            }
+        }
+    })
+}
+
+/// Call the function with the index and value of every register that isn't zero, checking that
+/// the sparse registers cover exactly every register
+fn for_each_sparse(sparse: &[u8], mut f: impl FnMut(usize, u8)) -> Result<(), Corrupted> {
+    let mut index = 0;
+    for opcode in opcodes(sparse) {
+        let opcode = opcode?;
+        if let Opcode::Val(value, len) = opcode {
+            if index + len > REGISTERS {
+                return Err(Corrupted);
+            }
+            for i in index..index + len {
+                f(i, value);
+            }
+        }
+        index += opcode.span();
+    }
+    if index == REGISTERS {
+        Ok(())
+    } else {
+        Err(Corrupted)
+    }
+}
+
+fn dense_get(registers: &[u8], index: usize) -> u8 {
+    let byte = index * REGISTER_BITS / 8;
+    let first_bit = (index * REGISTER_BITS) & 7;
+    let b0 = registers[byte] as u32;
+    // The last register never spills into the next byte, which doesn't exist
+    let b1 = registers.get(byte + 1).copied().unwrap_or(0) as u32;
+    (((b0 >> first_bit) | (b1 << (8 - first_bit))) & REGISTER_MAX as u32) as u8
+}
+
+fn dense_put(registers: &mut [u8], index: usize, value: u8) {
+    let byte = index * REGISTER_BITS / 8;
+    let first_bit = (index * REGISTER_BITS) & 7;
+    let value = value as u32;
+    let max = REGISTER_MAX as u32;
+    registers[byte] &= !((max << first_bit) as u8);
+    registers[byte] |= (value << first_bit) as u8;
+    if let Some(next) = registers.get_mut(byte + 1) {
+        *next &= !((max >> (8 - first_bit)) as u8);
+        *next |= (value >> (8 - first_bit)) as u8;
+    }
+}
+
+/// Set a dense register if the count is greater than its current value
+fn dense_set(registers: &mut [u8], index: usize, count: u8) -> bool {
+    if count > dense_get(registers, index) {
+        dense_put(registers, index, count);
+        true
+    } else {
+        false
+    }
+}
+
+/// MurmurHash64A, reading the input as little endian words like Redis does on x86
+fn murmurhash64a(key: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4_a793_5bd1_e995;
+    const R: u32 = 47;
+
+    let mut h = seed ^ (key.len() as u64).wrapping_mul(M);
+
+    let mut chunks = key.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, byte) in tail.iter().enumerate() {
+            h ^= (*byte as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+/// Hash an element, returning the register it belongs to and the length of the run of zeros in the
+/// rest of the hash plus one
+fn pattern_len(element: &[u8]) -> (usize, u8) {
+    let hash = murmurhash64a(element, HASH_SEED);
+    let index = (hash & (REGISTERS as u64 - 1)) as usize;
+    // Setting the bit past the end makes sure the count is at most Q + 1
+    let hash = (hash >> P) | (1 << Q);
+    (index, hash.trailing_zeros() as u8 + 1)
+}
+
+/// An empty HyperLogLog, which starts out sparse
+pub(crate) fn new() -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(ENCODING_SPARSE);
+    bytes.resize(HEADER_SIZE, 0);
+    let mut remaining = REGISTERS;
+    while remaining > 0 {
+        let len = remaining.min(SPARSE_XZERO_MAX_LEN);
+        Opcode::XZero(len).encode(&mut bytes);
+        remaining -= len;
+    }
+    bytes
+}
+
+/// Whether a string has the header of a HyperLogLog
+///
+/// Sparse registers are only checked when they're used, but dense registers have to be the exact
+/// size.
+pub(crate) fn is_valid(bytes: &[u8]) -> bool {
+    if bytes.len() < HEADER_SIZE || &bytes[..MAGIC.len()] != MAGIC {
+        return false;
+    }
+    match bytes[4] {
+        ENCODING_DENSE => bytes.len() == DENSE_SIZE,
+        ENCODING_SPARSE => true,
+        _ => false,
+    }
+}
+
+/// The encoding of a valid HyperLogLog
+pub(crate) fn encoding(bytes: &[u8]) -> Encoding {
+    if bytes[4] == ENCODING_DENSE {
+        Encoding::Dense
+    } else {
+        Encoding::Sparse
+    }
+}
+
+/// The cardinality cached in the header, unless it's been invalidated since it was computed
+pub(crate) fn cached_count(bytes: &[u8]) -> Option<u64> {
+    if bytes[CACHE_INVALID_BYTE] & 0x80 != 0 {
+        return None;
+    }
+    Some(u64::from_le_bytes(
+        bytes[8..HEADER_SIZE].try_into().unwrap(),
+    ))
+}
+
+pub(crate) fn set_cached_count(bytes: &mut [u8], count: u64) {
+    bytes[8..HEADER_SIZE].copy_from_slice(&count.to_le_bytes());
+}
+
+pub(crate) fn invalidate_cache(bytes: &mut [u8]) {
+    bytes[CACHE_INVALID_BYTE] |= 0x80;
+}
+
+/// Add an element to a valid HyperLogLog, returning whether any register changed
+pub(crate) fn add(
+    bytes: &mut Vec<u8>,
+    element: &[u8],
+    sparse_max_bytes: usize,
+) -> Result<bool, Corrupted> {
+    let (index, count) = pattern_len(element);
+    set(bytes, index, count, sparse_max_bytes)
+}
+
+/// Set a register if the count is greater than its current value
+fn set(
+    bytes: &mut Vec<u8>,
+    index: usize,
+    count: u8,
+    sparse_max_bytes: usize,
+) -> Result<bool, Corrupted> {
+    match encoding(bytes) {
+        Encoding::Dense => Ok(dense_set(&mut bytes[HEADER_SIZE..], index, count)),
+        Encoding::Sparse => sparse_set(bytes, index, count, sparse_max_bytes),
+    }
+}
+
+/// Set a sparse register if the count is greater than its current value, converting to dense if
+/// the count is too large or the registers would grow past the limit
+///
+/// The opcode covering the register is split into the opcodes for the registers before it, the
+/// register itself, and the registers after it, and then adjacent VAL opcodes with the same value
+/// are merged.
+fn sparse_set(
+    bytes: &mut Vec<u8>,
+    index: usize,
+    count: u8,
+    sparse_max_bytes: usize,
+) -> Result<bool, Corrupted> {
+    if count > SPARSE_VAL_MAX_VALUE {
+        return promote(bytes, index, count);
+    }
+
+    // Find the opcode covering the register
+    let mut offset = HEADER_SIZE;
+    let mut first = 0;
+    let mut previous = None;
+    let opcode = loop {
+        let opcode = Opcode::decode(bytes.get(offset..).unwrap_or_default()).ok_or(Corrupted)?;
+        if index < first + opcode.span() {
+            break opcode;
+        }
+        previous = Some(offset);
+        offset += opcode.size();
+        first += opcode.span();
+    };
+
+    match opcode {
+        Opcode::Val(value, _) if value >= count => return Ok(false),
+        Opcode::Val(_, 1) | Opcode::Zero(1) => bytes[offset] = val_byte(count, 1),
+        _ => {
+            let last = first + opcode.span() - 1;
+            let mut sequence = Vec::with_capacity(5);
+            let before = index - first;
+            let after = last - index;
+            match opcode {
+                Opcode::Val(value, _) => {
+                    if before > 0 {
+                        Opcode::Val(value, before).encode(&mut sequence);
+                    }
+                    Opcode::Val(count, 1).encode(&mut sequence);
+                    if after > 0 {
+                        Opcode::Val(value, after).encode(&mut sequence);
+                    }
+                }
+                _ => {
+                    if before > 0 {
+                        Opcode::zeros(before).encode(&mut sequence);
+                    }
+                    Opcode::Val(count, 1).encode(&mut sequence);
+                    if after > 0 {
+                        Opcode::zeros(after).encode(&mut sequence);
+                    }
+                }
+            }
+
+            if sequence.len() > opcode.size()
+                && bytes.len() + sequence.len() - opcode.size() > sparse_max_bytes
+            {
+                return promote(bytes, index, count);
+            }
+            bytes.splice(offset..offset + opcode.size(), sequence);
+        }
+    }
+
+    // Merge adjacent VAL opcodes with the same value, scanning up to five opcodes from the one
+    // before the one that was changed
+    let mut offset = previous.unwrap_or(HEADER_SIZE);
+    for _ in 0..5 {
+        let Some(opcode) = Opcode::decode(bytes.get(offset..).unwrap_or_default()) else {
+            break;
+        };
+        let Opcode::Val(value, len) = opcode else {
+            offset += opcode.size();
+            continue;
+        };
+        if let Some(Opcode::Val(next_value, next_len)) =
+            Opcode::decode(bytes.get(offset + 1..).unwrap_or_default())
+        {
+            if value == next_value && len + next_len <= SPARSE_VAL_MAX_LEN {
+                bytes[offset + 1] = val_byte(value, len + next_len);
+                bytes.remove(offset);
+                // Try merging the merged opcode with the one after it too
+                continue;
+            }
+        }
+        offset += 1;
+    }
+
+    invalidate_cache(bytes);
+    Ok(true)
+}
+
+/// Convert to dense and then set the register, which is only done when the register is changing
+fn promote(bytes: &mut Vec<u8>, index: usize, count: u8) -> Result<bool, Corrupted> {
+    to_dense(bytes)?;
+    Ok(dense_set(&mut bytes[HEADER_SIZE..], index, count))
+}
+
+/// Convert a valid HyperLogLog to the dense encoding, returning whether it was sparse
+///
+/// The header is kept as it is, including the cached cardinality.
+pub(crate) fn to_dense(bytes: &mut Vec<u8>) -> Result<bool, Corrupted> {
+    if encoding(bytes) == Encoding::Dense {
+        return Ok(false);
+    }
+    let mut dense = vec![0; DENSE_SIZE];
+    dense[..HEADER_SIZE].copy_from_slice(&bytes[..HEADER_SIZE]);
+    dense[4] = ENCODING_DENSE;
+    let registers = &mut dense[HEADER_SIZE..];
+    for_each_sparse(&bytes[HEADER_SIZE..], |index, value| {
+        dense_put(registers, index, value)
+    })?;
+    *bytes = dense;
+    Ok(true)
+}
+
+/// Raise each register in `max`, which has a byte per register, to the value of the register in a
+/// valid HyperLogLog
+pub(crate) fn merge(max: &mut [u8], bytes: &[u8]) -> Result<(), Corrupted> {
+    let registers = &bytes[HEADER_SIZE..];
+    match encoding(bytes) {
+        Encoding::Dense => {
+            for (index, max) in max.iter_mut().enumerate() {
+                *max = (*max).max(dense_get(registers, index));
+            }
+            Ok(())
+        }
+        Encoding::Sparse => {
+            for_each_sparse(registers, |index, value| max[index] = max[index].max(value))
+        }
+    }
+}
+
+/// The value of every register of a valid HyperLogLog, with a byte per register
+pub(crate) fn registers(bytes: &[u8]) -> Result<Vec<u8>, Corrupted> {
+    let mut registers = vec![0; REGISTERS];
+    merge(&mut registers, bytes)?;
+    Ok(registers)
+}
+
+/// Raise the registers of a valid HyperLogLog to the values in `max`, which has a byte per
+/// register, as done by PFMERGE
+///
+/// If `dense` is set, the HyperLogLog is converted to dense first and every register is
+/// overwritten, otherwise the registers are set one at a time, which may convert it to dense.
+pub(crate) fn set_registers(
+    bytes: &mut Vec<u8>,
+    max: &[u8],
+    dense: bool,
+    sparse_max_bytes: usize,
+) -> Result<(), Corrupted> {
+    if dense {
+        to_dense(bytes)?;
+        let registers = &mut bytes[HEADER_SIZE..];
+        for (index, value) in max.iter().enumerate() {
+            dense_put(registers, index, *value);
+        }
+    } else {
+        for (index, value) in max.iter().enumerate() {
+            if *value != 0 {
+                set(bytes, index, *value, sparse_max_bytes)?;
+            }
+        }
+    }
+    invalidate_cache(bytes);
+    Ok(())
+}
+
+/// Estimate the cardinality of a valid HyperLogLog, ignoring the cached cardinality
+pub(crate) fn count(bytes: &[u8]) -> Result<u64, Corrupted> {
+    let registers = &bytes[HEADER_SIZE..];
+    let mut histogram = [0u32; 64];
+    match encoding(bytes) {
+        Encoding::Dense => {
+            for index in 0..REGISTERS {
+                histogram[dense_get(registers, index) as usize] += 1;
+            }
+        }
+        Encoding::Sparse => {
+            let mut index = 0;
+            for opcode in opcodes(registers) {
+                let opcode = opcode?;
+                match opcode {
+                    Opcode::Zero(len) | Opcode::XZero(len) => histogram[0] += len as u32,
+                    Opcode::Val(value, len) => histogram[value as usize] += len as u32,
+                }
+                index += opcode.span();
+            }
+            if index != REGISTERS {
+                return Err(Corrupted);
+            }
+        }
+    }
+    Ok(estimate(&histogram))
+}
+
+/// Estimate the cardinality of registers with a byte per register, such as those merged by
+/// [`merge`]
+pub(crate) fn count_registers(registers: &[u8]) -> u64 {
+    let mut histogram = [0u32; 64];
+    for value in registers {
+        histogram[*value as usize] += 1;
+    }
+    estimate(&histogram)
+}
+
+/// Estimate the cardinality from how many registers have each value, using the estimator from
+/// "New cardinality estimation algorithms for HyperLogLog sketches" by Otmar Ertl
+fn estimate(histogram: &[u32; 64]) -> u64 {
+    let m = REGISTERS as f64;
+    let mut z = m * tau((m - histogram[Q as usize + 1] as f64) / m);
+    for j in (1..=Q as usize).rev() {
+        z += histogram[j] as f64;
+        z *= 0.5;
+    }
+    z += m * sigma(histogram[0] as f64 / m);
+    (ALPHA_INF * m * m / z).round() as u64
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let mut y = 1.0;
+    let mut z = x;
+    loop {
+        x *= x;
+        let previous = z;
+        z += x * y;
+        y += y;
+        if z == previous {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let mut y = 1.0;
+    let mut z = 1.0 - x;
+    loop {
+        x = x.sqrt();
+        let previous = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if z == previous {
+            return z / 3.0;
+        }
+    }
+}
+
+/// Describe the opcodes of a valid sparse HyperLogLog, as returned by PFDEBUG DECODE
+pub(crate) fn decode_sparse(bytes: &[u8]) -> Result<String, Corrupted> {
+    let mut decoded = String::new();
+    for opcode in opcodes(&bytes[HEADER_SIZE..]) {
+        if !decoded.is_empty() {
+            decoded.push(' ');
+        }
+        match opcode? {
+            Opcode::Zero(len) => write!(decoded, "z:{}", len),
+            Opcode::XZero(len) => write!(decoded, "Z:{}", len),
+            Opcode::Val(value, len) => write!(decoded, "v:{},{}", value, len),
+        }
+        .unwrap();
+    }
+    Ok(decoded)
+}
+
+/// Check that the registers are accessed correctly and the estimates are within the expected
+/// error, as done by PFSELFTEST
+///
+/// Elements are added to a dense and a sparse HyperLogLog at the same time, checking that they
+/// agree, up to a cardinality of ten million.
+pub(crate) fn self_test(sparse_max_bytes: usize) -> Result<(), String> {
+    let mut registers = vec![0; DENSE_SIZE - HEADER_SIZE];
+    let mut expected = vec![0; REGISTERS];
+    for _ in 0..1000 {
+        for (index, expected) in expected.iter_mut().enumerate() {
+            *expected = random::next_u64() as u8 & REGISTER_MAX;
+            dense_put(&mut registers, index, *expected);
+        }
+        for (index, expected) in expected.iter().enumerate() {
+            let value = dense_get(&registers, index);
+            if value != *expected {
+                return Err(format!(
+                    "Register {} should be {} but is {}",
+                    index, expected, value
+                ));
+            }
+        }
+    }
+
+    let mut dense = new();
+    to_dense(&mut dense).map_err(|_| "dense conversion failed".to_string())?;
+    dense[HEADER_SIZE..].fill(0);
+    let mut sparse = new();
+    let relative_error = 1.04 / (REGISTERS as f64).sqrt();
+    let seed = random::next_u64();
+    let mut checkpoint = 1u64;
+    for j in 1..=10_000_000u64 {
+        let element = (j ^ seed).to_le_bytes();
+        let corrupted = |_| "HLL corrupted".to_string();
+        add(&mut dense, &element, sparse_max_bytes).map_err(corrupted)?;
+        add(&mut sparse, &element, sparse_max_bytes).map_err(corrupted)?;
+        if j != checkpoint {
+            continue;
+        }
+
+        // Small cardinalities should use the sparse encoding
+        if (j as usize) < sparse_max_bytes / 2 && encoding(&sparse) != Encoding::Sparse {
+            return Err("sparse encoding not used".to_string());
+        }
+        let estimate = count(&dense).map_err(corrupted)?;
+        if estimate != count(&sparse).map_err(corrupted)? {
+            return Err("dense/sparse disagree".to_string());
+        }
+        // A cardinality of ten is likely to have a larger error from collisions now and then
+        let max_error = if j == 10 {
+            1
+        } else {
+            (relative_error * 6.0 * checkpoint as f64).ceil() as u64
+        };
+        let error = checkpoint.abs_diff(estimate);
+        if error > max_error {
+            return Err(format!(
+                "Too big error. card:{} abserr:{}",
+                checkpoint, error
+            ));
+        }
+        checkpoint *= 10;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_is_sparse_and_empty() {
+        let hll = new();
+        assert_eq!(
+            hll,
+            b"HYLL\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x7f\xff"
+        );
+        assert!(is_valid(&hll));
+        assert_eq!(encoding(&hll), Encoding::Sparse);
+        assert_eq!(cached_count(&hll), Some(0));
+        assert_eq!(count(&hll), Ok(0));
+        assert_eq!(decode_sparse(&hll).unwrap(), "Z:16384");
+    }
+
+    #[test]
+    fn test_sparse_set_splits_and_merges() {
+        let mut hll = new();
+        assert_eq!(sparse_set(&mut hll, 100, 3, 3000), Ok(true));
+        assert_eq!(decode_sparse(&hll).unwrap(), "Z:100 v:3,1 Z:16283");
+        assert_eq!(sparse_set(&mut hll, 100, 2, 3000), Ok(false));
+        assert_eq!(sparse_set(&mut hll, 101, 3, 3000), Ok(true));
+        assert_eq!(decode_sparse(&hll).unwrap(), "Z:100 v:3,2 Z:16282");
+        assert_eq!(sparse_set(&mut hll, 0, 1, 3000), Ok(true));
+        assert_eq!(decode_sparse(&hll).unwrap(), "v:1,1 Z:99 v:3,2 Z:16282");
+        assert_eq!(cached_count(&hll), None);
+
+        // Setting registers right to left merges differently than left to right
+        let mut hll = new();
+        for index in (0..5).rev() {
+            sparse_set(&mut hll, index, 5, 3000).unwrap();
+        }
+        assert_eq!(decode_sparse(&hll).unwrap(), "v:5,1 v:5,4 Z:16379");
+    }
+
+    #[test]
+    fn test_promotion_to_dense() {
+        let mut hll = new();
+        assert_eq!(sparse_set(&mut hll, 7, 33, 3000), Ok(true));
+        assert_eq!(encoding(&hll), Encoding::Dense);
+        assert_eq!(hll.len(), DENSE_SIZE);
+        assert!(is_valid(&hll));
+        let values = registers(&hll).unwrap();
+        assert_eq!(values[7], 33);
+        assert_eq!(values.iter().filter(|r| **r != 0).count(), 1);
+
+        // Growing past the size limit converts too
+        let mut hll = new();
+        for index in 0..20 {
+            set(&mut hll, index * 3, 1, 24).unwrap();
+        }
+        assert_eq!(encoding(&hll), Encoding::Dense);
+        assert_eq!(
+            registers(&hll).unwrap().iter().filter(|r| **r == 1).count(),
+            20
+        );
+    }
+
+    #[test]
+    fn test_dense_registers() {
+        let mut registers = vec![0; DENSE_SIZE - HEADER_SIZE];
+        for index in 0..REGISTERS {
+            dense_put(&mut registers, index, (index % 64) as u8);
+        }
+        for index in 0..REGISTERS {
+            assert_eq!(dense_get(&registers, index), (index % 64) as u8);
+        }
+    }
+
+    #[test]
+    fn test_murmurhash() {
+        assert_eq!(murmurhash64a(b"", 0), 0);
+        let (index, count) = pattern_len(b"a");
+        assert!(index < REGISTERS);
+        assert!((1..=Q as u8 + 1).contains(&count));
+    }
+
+    #[test]
+    fn test_estimate() {
+        let mut sparse = new();
+        let mut dense = new();
+        to_dense(&mut dense).unwrap();
+        for i in 0..1000 {
+            add(&mut sparse, format!("element:{}", i).as_bytes(), 3000).unwrap();
+            add(&mut dense, format!("element:{}", i).as_bytes(), 3000).unwrap();
+        }
+        let estimate = count(&sparse).unwrap();
+        assert_eq!(estimate, count(&dense).unwrap());
+        assert!(estimate.abs_diff(1000) < 30, "estimate {}", estimate);
+
+        let mut max = vec![0; REGISTERS];
+        merge(&mut max, &sparse).unwrap();
+        assert_eq!(count_registers(&max), estimate);
+    }
+
+    #[test]
+    fn test_corrupted_sparse() {
+        let mut hll = new();
+        hll.truncate(HEADER_SIZE + 1);
+        assert!(is_valid(&hll));
+        assert_eq!(count(&hll), Err(Corrupted));
+        let mut short = new();
+        short[HEADER_SIZE + 1] = 0;
+        assert_eq!(count(&short), Err(Corrupted));
+        assert_eq!(to_dense(&mut short), Err(Corrupted));
+    }
+}
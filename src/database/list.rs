@@ -0,0 +1,304 @@
+use std::collections::VecDeque;
+
+/// The maximum number of elements in a single node of a list
+const NODE_SIZE: usize = 128;
+
+type Node = VecDeque<Vec<u8>>;
+
+/// A list, stored as a sequence of nodes that each hold a bounded number of elements
+///
+/// This is similar to the quicklist in Redis. Pushing and popping at either end only touches the
+/// first or last node, while inserting or removing in the middle only has to shift the elements
+/// within a single node. Nodes that grow past `NODE_SIZE` are split in half, and nodes that end up
+/// empty are removed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct List {
+    nodes: VecDeque<Node>,
+    len: usize,
+}
+
+impl List {
+    pub(crate) fn new() -> Self {
+        List::default()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub(crate) fn push_front(&mut self, value: Vec<u8>) {
+        match self.nodes.front_mut() {
+            Some(node) if node.len() < NODE_SIZE => node.push_front(value),
+            _ => self.nodes.push_front(VecDeque::from([value])),
+        }
+        self.len += 1;
+    }
+
+    pub(crate) fn push_back(&mut self, value: Vec<u8>) {
+        match self.nodes.back_mut() {
+            Some(node) if node.len() < NODE_SIZE => node.push_back(value),
+            _ => self.nodes.push_back(VecDeque::from([value])),
+        }
+        self.len += 1;
+    }
+
+    pub(crate) fn pop_front(&mut self) -> Option<Vec<u8>> {
+        let node = self.nodes.front_mut()?;
+        let value = node.pop_front();
+        if node.is_empty() {
+            self.nodes.pop_front();
+        }
+        self.len -= 1;
+        value
+    }
+
+    pub(crate) fn pop_back(&mut self) -> Option<Vec<u8>> {
+        let node = self.nodes.back_mut()?;
+        let value = node.pop_back();
+        if node.is_empty() {
+            self.nodes.pop_back();
+        }
+        self.len -= 1;
+        value
+    }
+
+    /// Find the node holding the element at the index, and the offset of the element in the node
+    ///
+    /// The nodes are walked from whichever end of the list is closer to the index
+    fn locate(&self, index: usize) -> Option<(usize, usize)> {
+        if index >= self.len {
+            return None;
+        }
+
+        if index < self.len / 2 {
+            let mut index = index;
+            for (i, node) in self.nodes.iter().enumerate() {
+                if index < node.len() {
+                    return Some((i, index));
+                }
+                index -= node.len();
+            }
+        } else {
+            let mut from_end = self.len - 1 - index;
+            for (i, node) in self.nodes.iter().enumerate().rev() {
+                if from_end < node.len() {
+                    return Some((i, node.len() - 1 - from_end));
+                }
+                from_end -= node.len();
+            }
+        }
+
+        None
+    }
+
+    pub(crate) fn get(&self, index: usize) -> Option<&Vec<u8>> {
+        let (node, offset) = self.locate(index)?;
+        self.nodes[node].get(offset)
+    }
+
+    pub(crate) fn get_mut(&mut self, index: usize) -> Option<&mut Vec<u8>> {
+        let (node, offset) = self.locate(index)?;
+        self.nodes[node].get_mut(offset)
+    }
+
+    /// Insert an element so that it ends up at the index, which can be at most the length
+    pub(crate) fn insert(&mut self, index: usize, value: Vec<u8>) {
+        if index == 0 {
+            return self.push_front(value);
+        }
+        if index >= self.len {
+            return self.push_back(value);
+        }
+
+        let (node_index, offset) = self.locate(index).unwrap();
+        let node = &mut self.nodes[node_index];
+        node.insert(offset, value);
+        if node.len() > NODE_SIZE {
+            let half = node.split_off(node.len() / 2);
+            self.nodes.insert(node_index + 1, half);
+        }
+        self.len += 1;
+    }
+
+    /// Remove elements equal to the value, returning how many were removed
+    ///
+    /// A positive count removes up to that many elements starting from the head, a negative count
+    /// starting from the tail, and zero removes all of them.
+    pub(crate) fn remove_matching(&mut self, value: &[u8], count: i64) -> usize {
+        let limit = if count == 0 {
+            usize::MAX
+        } else {
+            count.unsigned_abs() as usize
+        };
+        let mut removed = 0;
+
+        if count >= 0 {
+            for node in self.nodes.iter_mut() {
+                let mut i = 0;
+                while i < node.len() && removed < limit {
+                    if node[i] == value {
+                        node.remove(i);
+                        removed += 1;
+                    } else {
+                        i += 1;
+                    }
+                }
+                if removed == limit {
+                    break;
+                }
+            }
+        } else {
+            for node in self.nodes.iter_mut().rev() {
+                let mut i = node.len();
+                while i > 0 && removed < limit {
+                    i -= 1;
+                    if node[i] == value {
+                        node.remove(i);
+                        removed += 1;
+                    }
+                }
+                if removed == limit {
+                    break;
+                }
+            }
+        }
+
+        self.nodes.retain(|node| !node.is_empty());
+        self.len -= removed;
+        removed
+    }
+
+    /// Only keep the elements within the range of indexes, dropping whole nodes where possible
+    pub(crate) fn retain_range(&mut self, start: usize, end: usize) {
+        let end = end.min(self.len);
+        if start >= end {
+            self.nodes.clear();
+            self.len = 0;
+            return;
+        }
+
+        let mut from_back = self.len - end;
+        while from_back > 0 {
+            let node = self.nodes.back_mut().unwrap();
+            if node.len() <= from_back {
+                from_back -= node.len();
+                self.nodes.pop_back();
+            } else {
+                node.truncate(node.len() - from_back);
+                from_back = 0;
+            }
+        }
+
+        let mut from_front = start;
+        while from_front > 0 {
+            let node = self.nodes.front_mut().unwrap();
+            if node.len() <= from_front {
+                from_front -= node.len();
+                self.nodes.pop_front();
+            } else {
+                node.drain(..from_front);
+                from_front = 0;
+            }
+        }
+
+        self.len = end - start;
+    }
+
+    pub(crate) fn iter(&self) -> impl DoubleEndedIterator<Item = &Vec<u8>> {
+        self.nodes.iter().flat_map(|node| node.iter())
+    }
+}
+
+impl FromIterator<Vec<u8>> for List {
+    fn from_iter<I: IntoIterator<Item = Vec<u8>>>(iter: I) -> Self {
+        let mut list = List::new();
+        for value in iter {
+            list.push_back(value);
+        }
+        list
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list_of(count: usize) -> List {
+        (0..count).map(|i| i.to_string().into_bytes()).collect()
+    }
+
+    fn contents(list: &List) -> Vec<String> {
+        list.iter()
+            .map(|v| String::from_utf8(v.clone()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_push_and_pop() {
+        let mut list = List::new();
+        list.push_back(b"b".to_vec());
+        list.push_front(b"a".to_vec());
+        list.push_back(b"c".to_vec());
+        assert_eq!(list.len(), 3);
+        assert_eq!(list.pop_front(), Some(b"a".to_vec()));
+        assert_eq!(list.pop_back(), Some(b"c".to_vec()));
+        assert_eq!(list.pop_back(), Some(b"b".to_vec()));
+        assert_eq!(list.pop_back(), None);
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn test_spans_multiple_nodes() {
+        let list = list_of(NODE_SIZE * 3 + 5);
+        assert_eq!(list.nodes.len(), 4);
+        assert_eq!(list.get(0), Some(&b"0".to_vec()));
+        assert_eq!(
+            list.get(NODE_SIZE + 1),
+            Some(&(NODE_SIZE + 1).to_string().into_bytes())
+        );
+        assert_eq!(
+            list.get(list.len() - 1),
+            Some(&(NODE_SIZE * 3 + 4).to_string().into_bytes())
+        );
+        assert_eq!(list.get(list.len()), None);
+    }
+
+    #[test]
+    fn test_insert_splits_full_nodes() {
+        let mut list = list_of(NODE_SIZE);
+        list.insert(10, b"x".to_vec());
+        assert_eq!(list.nodes.len(), 2);
+        assert_eq!(list.len(), NODE_SIZE + 1);
+        assert_eq!(list.get(10), Some(&b"x".to_vec()));
+        assert_eq!(list.get(11), Some(&b"10".to_vec()));
+    }
+
+    #[test]
+    fn test_remove_matching() {
+        let mut list: List = ["a", "b", "a", "c", "a"]
+            .iter()
+            .map(|v| v.as_bytes().to_vec())
+            .collect();
+        assert_eq!(list.remove_matching(b"a", -2), 2);
+        assert_eq!(contents(&list), vec!["a", "b", "c"]);
+        assert_eq!(list.remove_matching(b"a", 0), 1);
+        assert_eq!(contents(&list), vec!["b", "c"]);
+        assert_eq!(list.len(), 2);
+    }
+
+    #[test]
+    fn test_retain_range() {
+        let mut list = list_of(NODE_SIZE * 3);
+        list.retain_range(NODE_SIZE + 2, NODE_SIZE * 2 + 1);
+        assert_eq!(list.len(), NODE_SIZE - 1);
+        assert_eq!(list.get(0), Some(&(NODE_SIZE + 2).to_string().into_bytes()));
+        assert_eq!(list.iter().count(), NODE_SIZE - 1);
+
+        list.retain_range(5, 5);
+        assert!(list.is_empty());
+    }
+}
@@ -0,0 +1,972 @@
+pub(crate) mod geohash;
+mod hash;
+pub(crate) mod hyperloglog;
+mod list;
+mod set;
+mod skiplist;
+mod snapshot;
+mod sorted_set;
+mod stream;
+
+use crate::error::{Result, RustisError};
+use crate::notify;
+use crate::parsers::rdb;
+use crate::random;
+use crate::watch;
+use memmap2::Mmap;
+use once_cell::sync::Lazy;
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fs::File,
+    sync::{RwLock, RwLockWriteGuard},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+const DEFAULT_DATABASES: usize = 16;
+
+/// How many keys with an expiry are sampled at a time by the active expiry cycle
+const ACTIVE_EXPIRE_SAMPLE: usize = 20;
+
+/// The share of a sample that has to have expired for the active expiry cycle to sample again
+const ACTIVE_EXPIRE_STALE_PERCENT: usize = 10;
+
+/// How long the active expiry cycle can run for, so that it doesn't keep clients waiting
+const ACTIVE_EXPIRE_TIME_LIMIT: Duration = Duration::from_millis(25);
+
+/// The longest decimal representation of an i64 (with sign), longer strings are never integers
+const MAX_INTEGER_STRING_LEN: usize = 20;
+
+/// A string value, which is stored as an integer when it is the canonical representation of one
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum StringValue {
+    Raw(Vec<u8>),
+    Int(i64),
+}
+
+impl From<Vec<u8>> for StringValue {
+    fn from(bytes: Vec<u8>) -> Self {
+        if bytes.len() <= MAX_INTEGER_STRING_LEN {
+            if let Some(value) = parse_canonical_int(&bytes) {
+                return StringValue::Int(value);
+            }
+        }
+        StringValue::Raw(bytes)
+    }
+}
+
+impl StringValue {
+    pub(crate) fn as_bytes(&self) -> Cow<'_, [u8]> {
+        match self {
+            StringValue::Raw(bytes) => Cow::Borrowed(bytes),
+            StringValue::Int(value) => Cow::Owned(value.to_string().into_bytes()),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            StringValue::Raw(bytes) => bytes.len(),
+            StringValue::Int(value) => value.to_string().len(),
+        }
+    }
+
+    /// The value as an integer, if it is one
+    pub(crate) fn as_int(&self) -> Option<i64> {
+        match self {
+            StringValue::Raw(bytes) => parse_canonical_int(bytes),
+            StringValue::Int(value) => Some(*value),
+        }
+    }
+
+    /// Mutable access to the raw bytes, converting an integer encoded value to raw first
+    pub(crate) fn raw_mut(&mut self) -> &mut Vec<u8> {
+        if let StringValue::Int(value) = self {
+            *self = StringValue::Raw(value.to_string().into_bytes());
+        }
+        match self {
+            StringValue::Raw(bytes) => bytes,
+            StringValue::Int(_) => unreachable!(),
+        }
+    }
+}
+
+/// Parse bytes as an i64, only if they are exactly how the integer would be formatted
+///
+/// This means that values like "+1", "01" or "-0" are not treated as integers, so that they are
+/// returned exactly as they were set.
+fn parse_canonical_int(bytes: &[u8]) -> Option<i64> {
+    let value = std::str::from_utf8(bytes).ok()?.parse::<i64>().ok()?;
+    if value.to_string().as_bytes() == bytes {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+pub(crate) use hash::Hash;
+pub(crate) use list::List;
+pub(crate) use set::Set;
+pub(crate) use snapshot::{dump_functions, restore_functions, save_rdb};
+pub(crate) use sorted_set::SortedSet;
+pub(crate) use stream::{
+    Consumer, ConsumerGroup, PendingEntry, Stream, StreamFields, StreamId, StreamNodeLimits, Trim,
+};
+
+/// The longest string that Redis stores in the same allocation as its object, reported as "embstr"
+const EMBSTR_SIZE_LIMIT: usize = 44;
+
+/// The limits for how large a compactly encoded collection can grow before it's converted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ListpackLimits {
+    /// The maximum number of entries
+    pub(crate) max_entries: usize,
+    /// The maximum length of any single entry
+    pub(crate) max_value: usize,
+}
+
+/// The default for how many members a set of integers can have before it's converted to a hash set
+pub(crate) const DEFAULT_SET_MAX_INTSET_ENTRIES: usize = 512;
+
+impl Default for ListpackLimits {
+    fn default() -> Self {
+        ListpackLimits {
+            max_entries: 128,
+            max_value: 64,
+        }
+    }
+}
+
+/// A value stored in the keyspace
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    String(StringValue),
+    List(List),
+    Hash(Hash),
+    Set(Set),
+    SortedSet(SortedSet),
+    Stream(Stream),
+}
+
+impl Value {
+    /// The name of the type, as returned by the TYPE command
+    pub(crate) fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+            Value::Hash(_) => "hash",
+            Value::Set(_) => "set",
+            Value::SortedSet(_) => "zset",
+            Value::Stream(_) => "stream",
+        }
+    }
+
+    /// The name of the encoding, as returned by OBJECT ENCODING
+    pub(crate) fn encoding(&self) -> &'static str {
+        match self {
+            Value::String(StringValue::Int(_)) => "int",
+            Value::String(s) if s.len() <= EMBSTR_SIZE_LIMIT => "embstr",
+            Value::String(_) => "raw",
+            Value::List(_) => "quicklist",
+            Value::Hash(hash) => hash.encoding(),
+            Value::Set(set) => set.encoding(),
+            Value::SortedSet(sorted_set) => sorted_set.encoding(),
+            Value::Stream(_) => "stream",
+        }
+    }
+}
+
+impl From<rdb::RdbValue> for Value {
+    fn from(value: rdb::RdbValue) -> Self {
+        match value {
+            rdb::RdbValue::String(s) => Value::String(s.into()),
+            rdb::RdbValue::List(elements) => Value::List(elements.into_iter().collect()),
+            rdb::RdbValue::Hash(pairs) => {
+                Value::Hash(Hash::from_pairs(pairs, &ListpackLimits::default()))
+            }
+            rdb::RdbValue::HashWithExpiries(entries) => {
+                let limits = ListpackLimits::default();
+                let mut hash = Hash::new();
+                for (field, value, expiry) in entries {
+                    if let Some(expiry) = expiry {
+                        hash.insert(field.clone(), value, &limits);
+                        hash.set_expiry(&field, Some(expiry as u128));
+                    } else {
+                        hash.insert(field, value, &limits);
+                    }
+                }
+                Value::Hash(hash)
+            }
+            rdb::RdbValue::Set(members) => {
+                Value::Set(Set::from_members(members, DEFAULT_SET_MAX_INTSET_ENTRIES))
+            }
+            rdb::RdbValue::SortedSet(entries) => {
+                Value::SortedSet(SortedSet::from_entries(entries, &ListpackLimits::default()))
+            }
+            rdb::RdbValue::Stream(stream) => {
+                let id = |(ms, seq)| StreamId::new(ms, seq);
+                let mut value = Stream::from_entries(
+                    stream
+                        .entries
+                        .into_iter()
+                        .map(|(entry_id, fields)| (id(entry_id), fields)),
+                    id(stream.last_id),
+                    id(stream.max_deleted_id),
+                    stream.entries_added,
+                    &StreamNodeLimits::default(),
+                );
+                for rdb_group in stream.groups {
+                    let mut group =
+                        ConsumerGroup::new(id(rdb_group.last_id), rdb_group.entries_read);
+                    // The pending entries are stored once for the group, and then by ID for each
+                    // consumer, which is where their owner comes from
+                    let mut deliveries: HashMap<StreamId, (u64, u64)> = rdb_group
+                        .pending
+                        .into_iter()
+                        .map(|(entry_id, time, count)| (id(entry_id), (time, count)))
+                        .collect();
+                    for rdb_consumer in rdb_group.consumers {
+                        let mut consumer = Consumer::new(rdb_consumer.seen_time as u128);
+                        consumer.active_time = rdb_consumer.active_time.map(u128::from);
+                        for entry_id in rdb_consumer.pending.into_iter().map(id) {
+                            let Some((time, count)) = deliveries.remove(&entry_id) else {
+                                continue;
+                            };
+                            consumer.pending.insert(entry_id);
+                            group.pending.insert(
+                                entry_id,
+                                PendingEntry {
+                                    consumer: rdb_consumer.name.clone(),
+                                    delivery_time: time as u128,
+                                    delivery_count: count,
+                                },
+                            );
+                        }
+                        group.consumers.insert(rdb_consumer.name, consumer);
+                    }
+                    value.create_group(&rdb_group.name, group);
+                }
+                Value::Stream(value)
+            }
+        }
+    }
+}
+
+pub(crate) type Database = HashMap<Vec<u8>, Value>;
+pub(crate) type Expiry = HashMap<Vec<u8>, u128>;
+pub(crate) static DATABASES: Lazy<RwLock<Vec<Database>>> = Lazy::new(|| {
+    let mut dbs = Vec::with_capacity(DEFAULT_DATABASES);
+    for _ in 0..DEFAULT_DATABASES {
+        dbs.push(HashMap::new());
+    }
+    RwLock::new(dbs)
+});
+pub(crate) static EXPIRY: Lazy<RwLock<Vec<Expiry>>> = Lazy::new(|| {
+    let mut dbs: Vec<Expiry> = Vec::with_capacity(DEFAULT_DATABASES);
+    for _ in 0..DEFAULT_DATABASES {
+        dbs.push(HashMap::new());
+    }
+    RwLock::new(dbs)
+});
+/// The keys of the hashes that have had fields with an expiry, for the active expiry cycle to
+/// sample from
+///
+/// Keys are added whenever they're touched while holding such a hash, and only removed once the
+/// active expiry cycle finds that they no longer do.
+pub(crate) type FieldExpiry = HashSet<Vec<u8>>;
+static FIELD_EXPIRY: Lazy<RwLock<Vec<FieldExpiry>>> =
+    Lazy::new(|| RwLock::new(vec![HashSet::new(); DEFAULT_DATABASES]));
+
+/// Current unix time in milliseconds
+pub(crate) fn now() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis()
+}
+
+/// A single selected database, holding the write locks for `DATABASES`, `EXPIRY` and
+/// `FIELD_EXPIRY`
+///
+/// All key access from commands should go through this handle, as it is responsible for lazily
+/// evicting keys that have expired, keeping the keyspace and the expiries consistent.
+pub(crate) struct DbHandle {
+    dbs: RwLockWriteGuard<'static, Vec<Database>>,
+    expiries: RwLockWriteGuard<'static, Vec<Expiry>>,
+    field_expiries: RwLockWriteGuard<'static, Vec<FieldExpiry>>,
+    index: usize,
+}
+
+/// Lock the database with the given index
+///
+/// The locks are always taken in the same order (`DATABASES`, `EXPIRY` and then `FIELD_EXPIRY`)
+/// and are held until the handle is dropped, so a command should only lock once.
+pub(crate) fn lock(index: usize) -> DbHandle {
+    let dbs = DATABASES.write().unwrap();
+    let expiries = EXPIRY.write().unwrap();
+    let field_expiries = FIELD_EXPIRY.write().unwrap();
+    DbHandle {
+        dbs,
+        expiries,
+        field_expiries,
+        index,
+    }
+}
+
+impl DbHandle {
+    fn db(&mut self) -> &mut Database {
+        &mut self.dbs[self.index]
+    }
+
+    fn expiry_map(&mut self) -> &mut Expiry {
+        &mut self.expiries[self.index]
+    }
+
+    fn field_expiry_set(&mut self) -> &mut FieldExpiry {
+        &mut self.field_expiries[self.index]
+    }
+
+    /// Evict the key if it has an expiry in the past, or if it's a hash where every field has
+    /// expired
+    ///
+    /// Returns true if the key was evicted
+    fn expire_if_needed(&mut self, key: &[u8]) -> bool {
+        if let Some(&ttl) = self.expiry_map().get(key) {
+            if now() > ttl {
+                log::debug!("Key {:?} has expired", String::from_utf8_lossy(key));
+                self.expiry_map().remove(key);
+                self.db().remove(key);
+                self.notify(notify::EXPIRED, "expired", key);
+                self.touch(key);
+                return true;
+            }
+        }
+        self.expire_fields_if_needed(key)
+    }
+
+    /// Remove the expired fields of a hash, evicting the key if none are left
+    ///
+    /// Returns true if the key was evicted
+    fn expire_fields_if_needed(&mut self, key: &[u8]) -> bool {
+        let Some(Value::Hash(hash)) = self.db().get_mut(key) else {
+            return false;
+        };
+        if hash.remove_expired(now()) == 0 {
+            return false;
+        }
+        let emptied = hash.is_empty();
+        self.notify(notify::HASH, "hexpired", key);
+        self.touch(key);
+        if !emptied {
+            return false;
+        }
+        log::debug!(
+            "All fields of hash {:?} have expired",
+            String::from_utf8_lossy(key)
+        );
+        self.expiry_map().remove(key);
+        self.db().remove(key);
+        self.notify(notify::GENERIC, "del", key);
+        true
+    }
+
+    /// Record a keyspace event for a key in this database
+    pub(crate) fn notify(&self, class: u32, event: &'static str, key: &[u8]) {
+        notify::notify(class, event, key, self.index);
+    }
+
+    /// Signal that a key was modified, for the clients watching it
+    ///
+    /// This is done by the methods that replace or remove keys, but not by the lookups for
+    /// modification, so commands that modify a value in place have to call it once they have.
+    /// Hashes with fields that can expire are also tracked here, for the active expiry cycle.
+    pub(crate) fn touch(&mut self, key: &[u8]) {
+        watch::touch(self.index, key);
+        if matches!(self.db().get(key), Some(Value::Hash(hash)) if hash.has_expiries()) {
+            self.field_expiry_set().insert(key.to_vec());
+        }
+    }
+
+    /// Look up a key for reading, returning `None` if it does not exist or has expired
+    ///
+    /// A miss is recorded as a keymiss event, so commands that go on to write the key should
+    /// look it up for modification instead.
+    pub(crate) fn get(&mut self, key: &[u8]) -> Option<&Value> {
+        self.expire_if_needed(key);
+        if !self.db().contains_key(key) {
+            self.notify(notify::KEY_MISS, "keymiss", key);
+        }
+        self.db().get(key)
+    }
+
+    /// Look up a key for modification, returning `None` if it does not exist or has expired
+    ///
+    /// The key has to be touched if it's actually modified, for the clients watching it.
+    pub(crate) fn get_mut(&mut self, key: &[u8]) -> Option<&mut Value> {
+        self.expire_if_needed(key);
+        self.db().get_mut(key)
+    }
+
+    /// Look up a string, returning a WRONGTYPE error if the key holds another type
+    pub(crate) fn get_string(&mut self, key: &[u8]) -> Result<Option<&StringValue>> {
+        match self.get(key) {
+            Some(Value::String(s)) => Ok(Some(s)),
+            Some(_) => Err(RustisError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Look up a string for modification, returning a WRONGTYPE error if the key holds another
+    /// type
+    pub(crate) fn get_string_mut(&mut self, key: &[u8]) -> Result<Option<&mut StringValue>> {
+        match self.get_mut(key) {
+            Some(Value::String(s)) => Ok(Some(s)),
+            Some(_) => Err(RustisError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Look up a list, returning a WRONGTYPE error if the key holds another type
+    pub(crate) fn get_list(&mut self, key: &[u8]) -> Result<Option<&List>> {
+        match self.get(key) {
+            Some(Value::List(l)) => Ok(Some(l)),
+            Some(_) => Err(RustisError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Look up a list for modification, returning a WRONGTYPE error if the key holds another type
+    pub(crate) fn get_list_mut(&mut self, key: &[u8]) -> Result<Option<&mut List>> {
+        match self.get_mut(key) {
+            Some(Value::List(l)) => Ok(Some(l)),
+            Some(_) => Err(RustisError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Look up a hash, returning a WRONGTYPE error if the key holds another type
+    pub(crate) fn get_hash(&mut self, key: &[u8]) -> Result<Option<&Hash>> {
+        match self.get(key) {
+            Some(Value::Hash(h)) => Ok(Some(h)),
+            Some(_) => Err(RustisError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Look up a hash for modification, returning a WRONGTYPE error if the key holds another type
+    pub(crate) fn get_hash_mut(&mut self, key: &[u8]) -> Result<Option<&mut Hash>> {
+        match self.get_mut(key) {
+            Some(Value::Hash(h)) => Ok(Some(h)),
+            Some(_) => Err(RustisError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Look up a set, returning a WRONGTYPE error if the key holds another type
+    pub(crate) fn get_set(&mut self, key: &[u8]) -> Result<Option<&Set>> {
+        match self.get(key) {
+            Some(Value::Set(s)) => Ok(Some(s)),
+            Some(_) => Err(RustisError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Look up a set for modification, returning a WRONGTYPE error if the key holds another type
+    pub(crate) fn get_set_mut(&mut self, key: &[u8]) -> Result<Option<&mut Set>> {
+        match self.get_mut(key) {
+            Some(Value::Set(s)) => Ok(Some(s)),
+            Some(_) => Err(RustisError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Look up a sorted set, returning a WRONGTYPE error if the key holds another type
+    pub(crate) fn get_zset(&mut self, key: &[u8]) -> Result<Option<&SortedSet>> {
+        match self.get(key) {
+            Some(Value::SortedSet(z)) => Ok(Some(z)),
+            Some(_) => Err(RustisError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Look up a sorted set for modification, returning a WRONGTYPE error if the key holds another
+    /// type
+    pub(crate) fn get_zset_mut(&mut self, key: &[u8]) -> Result<Option<&mut SortedSet>> {
+        match self.get_mut(key) {
+            Some(Value::SortedSet(z)) => Ok(Some(z)),
+            Some(_) => Err(RustisError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Look up a stream, returning a WRONGTYPE error if the key holds another type
+    pub(crate) fn get_stream(&mut self, key: &[u8]) -> Result<Option<&Stream>> {
+        match self.get(key) {
+            Some(Value::Stream(s)) => Ok(Some(s)),
+            Some(_) => Err(RustisError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Look up a stream for modification, returning a WRONGTYPE error if the key holds another type
+    pub(crate) fn get_stream_mut(&mut self, key: &[u8]) -> Result<Option<&mut Stream>> {
+        match self.get_mut(key) {
+            Some(Value::Stream(s)) => Ok(Some(s)),
+            Some(_) => Err(RustisError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Check if a key exists and has not expired
+    pub(crate) fn contains_key(&mut self, key: &[u8]) -> bool {
+        self.expire_if_needed(key);
+        self.db().contains_key(key)
+    }
+
+    /// Insert a value for a key, leaving any existing expiry in place
+    ///
+    /// Returns the previous value, if the key existed and had not expired
+    pub(crate) fn insert(&mut self, key: &[u8], value: Value) -> Option<Value> {
+        self.expire_if_needed(key);
+        let previous = self.db().insert(key.to_vec(), value);
+        self.touch(key);
+        if previous.is_none() {
+            self.notify(notify::NEW, "new", key);
+        }
+        previous
+    }
+
+    /// Remove a key along with any expiry it has
+    ///
+    /// Returns the removed value, if the key existed and had not expired
+    pub(crate) fn remove(&mut self, key: &[u8]) -> Option<Value> {
+        if self.expire_if_needed(key) {
+            return None;
+        }
+        self.expiry_map().remove(key);
+        let removed = self.db().remove(key);
+        if removed.is_some() {
+            self.touch(key);
+        }
+        removed
+    }
+
+    /// The expiry (unix time in milliseconds) of a key, if it exists and has one
+    pub(crate) fn expiry(&mut self, key: &[u8]) -> Option<u128> {
+        self.expire_if_needed(key);
+        self.expiry_map().get(key).copied()
+    }
+
+    /// Set or clear the expiry (unix time in milliseconds) of an existing key
+    ///
+    /// Returns false if the key does not exist
+    pub(crate) fn set_expiry(&mut self, key: &[u8], expiry: Option<u128>) -> bool {
+        if !self.contains_key(key) {
+            return false;
+        }
+        match expiry {
+            Some(expiry) => {
+                self.expiry_map().insert(key.to_vec(), expiry);
+            }
+            None => {
+                self.expiry_map().remove(key);
+            }
+        }
+        self.touch(key);
+        true
+    }
+
+    /// Remove every key from the database, as FLUSHDB does
+    pub(crate) fn flush(&mut self) {
+        let index = self.index;
+        let db = &self.dbs[index];
+        watch::touch_database(index, |key| db.contains_key(key));
+        self.db().clear();
+        self.expiry_map().clear();
+        self.field_expiry_set().clear();
+    }
+
+    /// Swap the contents of this database with another one, as SWAPDB does
+    ///
+    /// Returns false if either index is out of range
+    pub(crate) fn swap(&mut self, other: usize) -> bool {
+        let index = self.index;
+        if index >= self.dbs.len() || other >= self.dbs.len() {
+            return false;
+        }
+        // Keys that exist in either database have a different value (or none) after the swap
+        let dbs = &self.dbs;
+        let existed = |key: &[u8]| dbs[index].contains_key(key) || dbs[other].contains_key(key);
+        watch::touch_database(index, existed);
+        watch::touch_database(other, existed);
+        self.dbs.swap(index, other);
+        self.expiries.swap(index, other);
+        self.field_expiries.swap(index, other);
+        true
+    }
+
+    /// Evict the keys that have expired out of a random sample of the keys with an expiry
+    ///
+    /// Returns how many keys were evicted
+    fn expire_sample(&mut self) -> usize {
+        let len = self.expiry_map().len();
+        if len == 0 {
+            return 0;
+        }
+        let now = now();
+        let expired: Vec<Vec<u8>> = self
+            .expiry_map()
+            .iter()
+            .cycle()
+            .skip(random::index(len))
+            .take(ACTIVE_EXPIRE_SAMPLE.min(len))
+            .filter(|(_, &ttl)| now > ttl)
+            .map(|(k, _)| k.clone())
+            .collect();
+        for key in &expired {
+            self.expire_if_needed(key);
+        }
+        expired.len()
+    }
+
+    /// Remove the expired fields out of a random sample of the hashes with fields that can expire,
+    /// forgetting the ones that no longer have any
+    ///
+    /// Returns how many hashes had fields removed
+    fn expire_field_sample(&mut self) -> usize {
+        let len = self.field_expiry_set().len();
+        if len == 0 {
+            return 0;
+        }
+        let sample: Vec<Vec<u8>> = self
+            .field_expiry_set()
+            .iter()
+            .cycle()
+            .skip(random::index(len))
+            .take(ACTIVE_EXPIRE_SAMPLE.min(len))
+            .cloned()
+            .collect();
+        let now = now();
+        let mut expired = 0;
+        for key in sample {
+            match self.db().get(&key) {
+                Some(Value::Hash(hash)) if hash.has_expiries() => {
+                    if hash.min_expiry().is_some_and(|min| now > min) {
+                        self.expire_fields_if_needed(&key);
+                        expired += 1;
+                    }
+                }
+                _ => {
+                    self.field_expiry_set().remove(&key);
+                }
+            }
+        }
+        expired
+    }
+
+    /// All keys in the database that have not expired, evicting any that have
+    pub(crate) fn keys(&mut self) -> Vec<Vec<u8>> {
+        let now = now();
+        let expired: Vec<Vec<u8>> = self
+            .expiry_map()
+            .iter()
+            .filter(|(_, &ttl)| now > ttl)
+            .map(|(k, _)| k.clone())
+            .collect();
+        for key in expired {
+            self.expiry_map().remove(&key);
+            self.db().remove(&key);
+            self.notify(notify::EXPIRED, "expired", &key);
+            self.touch(&key);
+        }
+        let hashes_with_expiries: Vec<Vec<u8>> = self
+            .db()
+            .iter()
+            .filter(|(_, value)| matches!(value, Value::Hash(hash) if hash.has_expiries()))
+            .map(|(k, _)| k.clone())
+            .collect();
+        for key in hashes_with_expiries {
+            self.expire_fields_if_needed(&key);
+        }
+        self.db().keys().cloned().collect()
+    }
+}
+
+/// Remove every key from every database, as FLUSHALL does
+pub(crate) fn flush_all() {
+    for index in 0..DEFAULT_DATABASES {
+        lock(index).flush();
+    }
+}
+
+/// Evict keys and hash fields that have expired even if no client reads them, which would
+/// otherwise leave them in memory until they are
+///
+/// Like Redis, this samples keys with an expiry at random from each database and evicts the ones
+/// that have expired, sampling again as long as enough of them had, within a time limit. Hashes
+/// with fields that can expire are sampled the same way.
+pub(crate) fn active_expire_cycle() {
+    let start = Instant::now();
+    for index in 0..DEFAULT_DATABASES {
+        let mut db = lock(index);
+        while start.elapsed() < ACTIVE_EXPIRE_TIME_LIMIT {
+            let expired = db.expire_sample();
+            if expired * 100 <= ACTIVE_EXPIRE_SAMPLE * ACTIVE_EXPIRE_STALE_PERCENT {
+                break;
+            }
+        }
+        // Hashes are sampled separately, as their fields can expire without the key having an
+        // expiry
+        while start.elapsed() < ACTIVE_EXPIRE_TIME_LIMIT {
+            let expired = db.expire_field_sample();
+            if expired * 100 <= ACTIVE_EXPIRE_SAMPLE * ACTIVE_EXPIRE_STALE_PERCENT {
+                break;
+            }
+        }
+    }
+}
+
+/// Load a RDB file from disk
+///
+/// The contents of the RDB file will completely replace the contents of the in-memory databases,
+/// meaning that anything that is in the database at the time of calling this function will be
+/// cleared out first. The code of the libraries of functions in it is returned, to be loaded by
+/// the caller
+pub fn load_rdb(path: &str) -> Result<Vec<Vec<u8>>> {
+    // Clear out the existing databases
+    log::debug!("Loading RDB file: {}", path);
+    log::trace!("Clearing out databases");
+    let mut dbs = DATABASES.write().unwrap();
+    dbs.iter_mut().for_each(|db| {
+        db.clear();
+    });
+    let mut expiries = EXPIRY.write().unwrap();
+    expiries.iter_mut().for_each(|expiry| {
+        expiry.clear();
+    });
+    let mut field_expiries = FIELD_EXPIRY.write().unwrap();
+    field_expiries.iter_mut().for_each(|keys| {
+        keys.clear();
+    });
+
+    log::trace!("Reading RDB file with Mmap");
+    let file = File::open(path)?;
+    let mmap = unsafe { Mmap::map(&file)? };
+
+    // Start with the header
+    log::trace!("Parsing RDB header");
+    let input = &mmap[..];
+    let (mut input, version) = rdb::nom_rdb_header(input)?;
+    log::debug!("RDB version: {}", version);
+
+    let mut db_num = 0;
+    let mut key_expiry = None;
+    let mut libraries = Vec::new();
+    let current_timestamp = now();
+
+    loop {
+        match rdb::nom_opcode_or_value_type(input) {
+            Ok((rest, rdb::ParsedOpCodeOrValueType::OpCode(op_code))) => {
+                input = rest;
+                log::trace!("Parsed OpCode: {:?}", op_code);
+                match op_code {
+                    rdb::OpCode::EOF => {
+                        log::trace!("Parsed EOF OpCode, stopping");
+                        // TODO: Handle CRC64
+                        break;
+                    }
+                    rdb::OpCode::SELECTDB => {
+                        let (rest, encoded_db) = rdb::nom_size_encoding(input)?;
+                        input = rest;
+                        match encoded_db {
+                            rdb::EncodedLength::Length(next_db) => {
+                                log::trace!("Parsed SELECTDB OpCode, switching to DB: {}", next_db);
+                                db_num = next_db;
+                            }
+                            _ => {
+                                log::error!("Invalid SELECTDB OpCode, expected Length");
+                                break;
+                            }
+                        }
+                    }
+                    rdb::OpCode::RESIZEDB => {
+                        let (rest, hash_table_size) = rdb::nom_size_encoding(input)?;
+                        input = rest;
+                        log::trace!(
+                            "Parsed RESIZEDB OpCode, hash table size: {}",
+                            hash_table_size.as_usize()
+                        );
+                        let db = dbs.get_mut(db_num).unwrap();
+                        db.reserve(hash_table_size.as_usize());
+
+                        let (rest, expiry_hash_table_size) = rdb::nom_size_encoding(input)?;
+                        input = rest;
+                        log::trace!(
+                            "Parsed RESIZEDB OpCode, expiry hash table size: {}",
+                            expiry_hash_table_size.as_usize()
+                        );
+                        let expiry = expiries.get_mut(db_num).unwrap();
+                        expiry.reserve(expiry_hash_table_size.as_usize());
+                    }
+                    rdb::OpCode::EXPIRETIME => {
+                        let (rest, expiry) = rdb::nom_le_int(input)?;
+                        input = rest;
+                        key_expiry = Some(expiry as u128 * 1000);
+                        log::trace!("Parsed EXPIRETIME OpCode, expiry: {}", expiry);
+                    }
+                    rdb::OpCode::EXPIRETIMEMS => {
+                        // Read in 8 byte unsigned long (little endian)
+                        let (rest, expiry) = rdb::nom_le_long(input)?;
+                        input = rest;
+                        key_expiry = Some(expiry as u128);
+                        log::trace!("Parsed EXPIRETIMEMS OpCode, expiry: {}", expiry);
+                    }
+                    rdb::OpCode::AUX => {
+                        let (rest, (key, value)) = rdb::nom_metadata_section(input).unwrap();
+
+                        input = rest;
+                        log::trace!(
+                            "Parsed AUX OpCode, key: {:?}, value: {:?}",
+                            String::from_utf8_lossy(&key.to_bytes()),
+                            String::from_utf8_lossy(&value.to_bytes())
+                        );
+                    }
+                    rdb::OpCode::FUNCTION2 => {
+                        let (rest, code) = rdb::nom_size_encoded_string(input)?;
+                        input = rest;
+                        log::trace!("Parsed FUNCTION2 OpCode");
+                        libraries.push(code.to_bytes());
+                    }
+                }
+            }
+            Ok((rest, rdb::ParsedOpCodeOrValueType::ValueType(value_type))) => {
+                log::trace!("Parsed ValueType: {:?}", value_type);
+                let (rest, key) = rdb::nom_size_encoded_string(rest)?;
+                let key = key.to_bytes();
+                // Skipping a value isn't possible without parsing it, so a value that can't be
+                // loaded fails the whole file rather than silently dropping the rest of it
+                let Ok((rest, value)) = rdb::nom_value(rest, &value_type) else {
+                    return Err(RustisError::InvalidInput(format!(
+                        "Can't load value of type {:?} for key {:?} from RDB file",
+                        value_type,
+                        String::from_utf8_lossy(&key)
+                    )));
+                };
+                input = rest;
+
+                log::trace!(
+                    "Setting key: {:?}, value: {:?}",
+                    String::from_utf8_lossy(&key),
+                    value
+                );
+
+                // Set the value in the current selected db. The expiry only applies to the key
+                // following it, so it's consumed here
+                if let Some(key_expiry) = key_expiry.take() {
+                    if key_expiry < current_timestamp {
+                        log::trace!(
+                            "Key: {:?} has expired, not setting value",
+                            String::from_utf8_lossy(&key)
+                        );
+                        continue;
+                    }
+                    log::trace!(
+                        "Setting expiry for key: {:?} to {:?}",
+                        String::from_utf8_lossy(&key),
+                        key_expiry
+                    );
+                    let expiry = expiries.get_mut(db_num).unwrap();
+                    expiry.insert(key.clone(), key_expiry);
+                }
+                // Fields of a hash can expire on their own, and there's no point keeping a hash
+                // where all of them have
+                let mut value: Value = value.into();
+                if let Value::Hash(hash) = &mut value {
+                    if hash.remove_expired(current_timestamp) > 0 && hash.is_empty() {
+                        log::trace!(
+                            "All fields of hash {:?} have expired, not setting value",
+                            String::from_utf8_lossy(&key)
+                        );
+                        continue;
+                    }
+                    if hash.has_expiries() {
+                        field_expiries[db_num].insert(key.clone());
+                    }
+                }
+                let db = dbs.get_mut(db_num).unwrap();
+                db.insert(key, value);
+            }
+            Err(_) => {
+                let offset = mmap.len() - input.len();
+                return Err(RustisError::InvalidInput(match input.first() {
+                    Some(byte) => format!(
+                        "Unknown value type or opcode {:#04x} at offset {} of RDB file",
+                        byte, offset
+                    ),
+                    None => "Unexpected end of RDB file".to_string(),
+                }));
+            }
+        }
+    }
+
+    log::trace!("Finished parsing RDB file");
+
+    Ok(libraries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RDB_FILE: &str = "tests/files/simple.rdb";
+
+    #[test]
+    fn test_load_rdb() {
+        load_rdb(RDB_FILE).unwrap();
+    }
+
+    #[test]
+    fn test_load_rdb_unsupported_value_type() {
+        let dir = std::env::temp_dir().join(format!("rustis-rdb-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // A zipmap, which is too old to be supported, and a type byte that doesn't exist
+        for (name, value_type) in [("zipmap.rdb", 0x09), ("unknown.rdb", 0x42)] {
+            let path = dir.join(name);
+            let mut contents = b"REDIS0011\xFE\x00".to_vec();
+            contents.extend([value_type, 0x03, b'k', b'e', b'y', 0x00, 0xFF]);
+            std::fs::write(&path, contents).unwrap();
+
+            let err = load_rdb(path.to_str().unwrap()).unwrap_err();
+            assert!(matches!(err, RustisError::InvalidInput(_)), "{err}");
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_string_value_integer_encoding() {
+        assert_eq!(StringValue::from(b"123".to_vec()), StringValue::Int(123));
+        assert_eq!(StringValue::from(b"-42".to_vec()), StringValue::Int(-42));
+        assert_eq!(
+            StringValue::from(b"012".to_vec()),
+            StringValue::Raw(b"012".to_vec())
+        );
+        assert_eq!(
+            StringValue::from(b"+1".to_vec()),
+            StringValue::Raw(b"+1".to_vec())
+        );
+        assert_eq!(
+            StringValue::from(b"99999999999999999999".to_vec()),
+            StringValue::Raw(b"99999999999999999999".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_string_value_raw_mut() {
+        let mut value = StringValue::Int(10);
+        value.raw_mut().push(b'0');
+        assert_eq!(value, StringValue::Raw(b"100".to_vec()));
+        assert_eq!(value.as_int(), Some(100));
+        assert_eq!(value.len(), 3);
+    }
+}
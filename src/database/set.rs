@@ -0,0 +1,176 @@
+use super::parse_canonical_int;
+use std::{borrow::Cow, collections::HashSet};
+
+/// A set of unique members
+///
+/// Small sets where every member is an integer are stored as a sorted array of integers, similar
+/// to the intset encoding in Redis, which is both compact and can be searched with a binary search.
+/// Once a member that isn't an integer is added, or the set grows past the limit, it's converted
+/// to a hash set, and is never converted back.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Set {
+    Intset(Vec<i64>),
+    Table(HashSet<Vec<u8>>),
+}
+
+impl Default for Set {
+    fn default() -> Self {
+        Set::Intset(Vec::new())
+    }
+}
+
+impl Set {
+    pub(crate) fn new() -> Self {
+        Set::default()
+    }
+
+    /// Create a set from members, picking the encoding based on the intset limit
+    pub(crate) fn from_members(
+        members: impl IntoIterator<Item = Vec<u8>>,
+        max_intset_entries: usize,
+    ) -> Self {
+        let mut set = Set::new();
+        for member in members {
+            set.insert(member, max_intset_entries);
+        }
+        set
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Set::Intset(values) => values.len(),
+            Set::Table(table) => table.len(),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The name of the encoding, as returned by OBJECT ENCODING
+    pub(crate) fn encoding(&self) -> &'static str {
+        match self {
+            Set::Intset(_) => "intset",
+            Set::Table(_) => "hashtable",
+        }
+    }
+
+    pub(crate) fn contains(&self, member: &[u8]) -> bool {
+        match self {
+            Set::Intset(values) => parse_canonical_int(member)
+                .is_some_and(|value| values.binary_search(&value).is_ok()),
+            Set::Table(table) => table.contains(member),
+        }
+    }
+
+    /// Add a member, converting to a hash set if it can no longer be an intset
+    ///
+    /// Returns true if the member is new
+    pub(crate) fn insert(&mut self, member: Vec<u8>, max_intset_entries: usize) -> bool {
+        if let Set::Intset(values) = self {
+            if let Some(value) = parse_canonical_int(&member) {
+                match values.binary_search(&value) {
+                    Ok(_) => return false,
+                    Err(index) if values.len() < max_intset_entries => {
+                        values.insert(index, value);
+                        return true;
+                    }
+                    Err(_) => {}
+                }
+            }
+            self.convert_to_table();
+        }
+
+        match self {
+            Set::Table(table) => table.insert(member),
+            Set::Intset(_) => unreachable!(),
+        }
+    }
+
+    /// Remove a member, returning true if it existed
+    pub(crate) fn remove(&mut self, member: &[u8]) -> bool {
+        match self {
+            Set::Intset(values) => {
+                let Some(value) = parse_canonical_int(member) else {
+                    return false;
+                };
+                match values.binary_search(&value) {
+                    Ok(index) => {
+                        values.remove(index);
+                        true
+                    }
+                    Err(_) => false,
+                }
+            }
+            Set::Table(table) => table.remove(member),
+        }
+    }
+
+    /// Iterate over the members, in ascending order for intsets
+    pub(crate) fn iter(&self) -> Box<dyn Iterator<Item = Cow<'_, [u8]>> + '_> {
+        match self {
+            Set::Intset(values) => Box::new(
+                values
+                    .iter()
+                    .map(|value| Cow::Owned(value.to_string().into_bytes())),
+            ),
+            Set::Table(table) => Box::new(table.iter().map(|member| Cow::Borrowed(&member[..]))),
+        }
+    }
+
+    fn convert_to_table(&mut self) {
+        if let Set::Intset(values) = self {
+            log::trace!(
+                "Converting intset with {} members to a hash set",
+                values.len()
+            );
+            *self = Set::Table(
+                values
+                    .iter()
+                    .map(|value| value.to_string().into_bytes())
+                    .collect(),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intset_stays_sorted() {
+        let mut set = Set::new();
+        assert!(set.insert(b"10".to_vec(), 4));
+        assert!(set.insert(b"-5".to_vec(), 4));
+        assert!(set.insert(b"3".to_vec(), 4));
+        assert!(!set.insert(b"3".to_vec(), 4));
+        assert_eq!(set, Set::Intset(vec![-5, 3, 10]));
+        assert!(set.contains(b"10"));
+        assert!(!set.contains(b"010"));
+
+        assert!(set.remove(b"3"));
+        assert!(!set.remove(b"3"));
+        assert!(!set.remove(b"nope"));
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn test_converts_to_table() {
+        let mut set = Set::from_members([b"1".to_vec(), b"2".to_vec()], 2);
+        assert_eq!(set.encoding(), "intset");
+        set.insert(b"3".to_vec(), 2);
+        assert_eq!(set.encoding(), "hashtable");
+        assert!(set.contains(b"1"));
+
+        let mut set = Set::from_members([b"1".to_vec()], 2);
+        set.insert(b"a".to_vec(), 2);
+        assert_eq!(set.encoding(), "hashtable");
+
+        // Integers that aren't in their canonical form are kept as they are
+        let set = Set::from_members([b"01".to_vec()], 2);
+        assert_eq!(set.encoding(), "hashtable");
+        assert!(set.contains(b"01"));
+        assert!(!set.contains(b"1"));
+    }
+}
@@ -0,0 +1,352 @@
+use crate::random;
+use std::cmp::Ordering;
+
+/// The maximum number of levels of a skiplist, enough for 2^64 elements with P = 1/4
+const MAX_LEVEL: usize = 32;
+
+/// The index of the header node, which holds no element
+const HEADER: usize = 0;
+
+/// Compare two elements of a sorted set, by score and then by member
+pub(crate) fn compare(a: (&[u8], f64), b: (&[u8], f64)) -> Ordering {
+    a.1.partial_cmp(&b.1)
+        .unwrap_or(Ordering::Equal)
+        .then_with(|| a.0.cmp(b.0))
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Link {
+    forward: Option<usize>,
+    /// How many elements the link skips over, used to find the rank of elements
+    span: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    member: Vec<u8>,
+    score: f64,
+    backward: Option<usize>,
+    levels: Vec<Link>,
+}
+
+/// A skiplist of members ordered by score, where every link keeps track of its span
+///
+/// This follows the skiplist that backs sorted sets in Redis. The spans make it possible to find
+/// the rank of an element, or the element at a rank, in O(log n). The nodes are stored in an arena
+/// and refer to each other by index, with the header at index 0, and the slots of removed nodes
+/// are reused.
+#[derive(Debug, Clone)]
+pub(crate) struct SkipList {
+    nodes: Vec<Node>,
+    free: Vec<usize>,
+    tail: Option<usize>,
+    level: usize,
+    len: usize,
+}
+
+impl Default for SkipList {
+    fn default() -> Self {
+        SkipList {
+            nodes: vec![Node {
+                member: Vec::new(),
+                score: 0.0,
+                backward: None,
+                levels: vec![Link::default(); MAX_LEVEL],
+            }],
+            free: Vec::new(),
+            tail: None,
+            level: 1,
+            len: 0,
+        }
+    }
+}
+
+// The layout of the nodes depends on the random levels, so only the elements are compared
+impl PartialEq for SkipList {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter_from(0).eq(other.iter_from(0))
+    }
+}
+
+/// A random level for a new node, where each level is a quarter as likely as the one below
+fn random_level() -> usize {
+    let mut level = 1;
+    while level < MAX_LEVEL && random::next_u64() & 0xFFFF < 0xFFFF / 4 {
+        level += 1;
+    }
+    level
+}
+
+impl SkipList {
+    pub(crate) fn new() -> Self {
+        SkipList::default()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the node comes before the element
+    fn is_before(&self, node: usize, member: &[u8], score: f64) -> bool {
+        let node = &self.nodes[node];
+        compare((&node.member, node.score), (member, score)) == Ordering::Less
+    }
+
+    /// Insert an element, which must not already be in the list
+    pub(crate) fn insert(&mut self, member: Vec<u8>, score: f64) {
+        let mut update = [HEADER; MAX_LEVEL];
+        let mut rank = [0; MAX_LEVEL];
+
+        let mut x = HEADER;
+        for i in (0..self.level).rev() {
+            rank[i] = if i == self.level - 1 { 0 } else { rank[i + 1] };
+            while let Some(next) = self.nodes[x].levels[i].forward {
+                if !self.is_before(next, &member, score) {
+                    break;
+                }
+                rank[i] += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            update[i] = x;
+        }
+
+        let level = random_level();
+        if level > self.level {
+            for i in self.level..level {
+                rank[i] = 0;
+                update[i] = HEADER;
+                self.nodes[HEADER].levels[i].span = self.len;
+            }
+            self.level = level;
+        }
+
+        let node = Node {
+            member,
+            score,
+            backward: (update[0] != HEADER).then_some(update[0]),
+            levels: vec![Link::default(); level],
+        };
+        let new = match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+
+        for i in 0..level {
+            let previous = self.nodes[update[i]].levels[i];
+            self.nodes[new].levels[i] = Link {
+                forward: previous.forward,
+                span: previous.span - (rank[0] - rank[i]),
+            };
+            self.nodes[update[i]].levels[i] = Link {
+                forward: Some(new),
+                span: rank[0] - rank[i] + 1,
+            };
+        }
+        // The levels above the new node now skip over one more element
+        for (i, &node) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[node].levels[i].span += 1;
+        }
+
+        match self.nodes[new].levels[0].forward {
+            Some(next) => self.nodes[next].backward = Some(new),
+            None => self.tail = Some(new),
+        }
+        self.len += 1;
+    }
+
+    /// Remove an element, returning false if it isn't in the list
+    pub(crate) fn remove(&mut self, member: &[u8], score: f64) -> bool {
+        let mut update = [HEADER; MAX_LEVEL];
+
+        let mut x = HEADER;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].forward {
+                if !self.is_before(next, member, score) {
+                    break;
+                }
+                x = next;
+            }
+            update[i] = x;
+        }
+
+        let Some(target) = self.nodes[x].levels[0].forward else {
+            return false;
+        };
+        if self.nodes[target].member != member || self.nodes[target].score != score {
+            return false;
+        }
+
+        for (i, &node) in update.iter().enumerate().take(self.level) {
+            if self.nodes[node].levels[i].forward == Some(target) {
+                let removed = self.nodes[target].levels[i];
+                self.nodes[node].levels[i] = Link {
+                    forward: removed.forward,
+                    span: self.nodes[node].levels[i].span + removed.span - 1,
+                };
+            } else {
+                self.nodes[node].levels[i].span -= 1;
+            }
+        }
+
+        match self.nodes[target].levels[0].forward {
+            Some(next) => self.nodes[next].backward = self.nodes[target].backward,
+            None => self.tail = self.nodes[target].backward,
+        }
+        while self.level > 1 && self.nodes[HEADER].levels[self.level - 1].forward.is_none() {
+            self.level -= 1;
+        }
+
+        self.nodes[target].member = Vec::new();
+        self.nodes[target].levels = Vec::new();
+        self.free.push(target);
+        self.len -= 1;
+        true
+    }
+
+    /// The rank (from 0) of an element, if it's in the list
+    pub(crate) fn rank(&self, member: &[u8], score: f64) -> Option<usize> {
+        let mut rank = 0;
+        let mut x = HEADER;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].forward {
+                let node = &self.nodes[next];
+                if compare((&node.member, node.score), (member, score)) == Ordering::Greater {
+                    break;
+                }
+                rank += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            if x != HEADER && self.nodes[x].member == member {
+                return Some(rank - 1);
+            }
+        }
+        None
+    }
+
+    /// Count the elements from the start of the list that match the predicate
+    ///
+    /// The predicate has to be true for a prefix of the list and false after that, like checking
+    /// if the elements are below some bound
+    pub(crate) fn count_while(&self, predicate: impl Fn(&[u8], f64) -> bool) -> usize {
+        let mut count = 0;
+        let mut x = HEADER;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].forward {
+                let node = &self.nodes[next];
+                if !predicate(&node.member, node.score) {
+                    break;
+                }
+                count += self.nodes[x].levels[i].span;
+                x = next;
+            }
+        }
+        count
+    }
+
+    /// The node at a rank (from 1, as the header is at rank 0)
+    fn node_at(&self, rank: usize) -> Option<usize> {
+        let mut traversed = 0;
+        let mut x = HEADER;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].forward {
+                if traversed + self.nodes[x].levels[i].span > rank {
+                    break;
+                }
+                traversed += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            if traversed == rank {
+                return (x != HEADER).then_some(x);
+            }
+        }
+        None
+    }
+
+    /// Iterate over the elements in order, starting at a rank (from 0)
+    pub(crate) fn iter_from(&self, rank: usize) -> impl Iterator<Item = (&[u8], f64)> + '_ {
+        let mut next = self.node_at(rank + 1);
+        std::iter::from_fn(move || {
+            let node = &self.nodes[next?];
+            next = node.levels[0].forward;
+            Some((node.member.as_slice(), node.score))
+        })
+    }
+
+    /// Iterate over the elements in reverse order, starting at a rank (from 0)
+    pub(crate) fn iter_rev_from(&self, rank: usize) -> impl Iterator<Item = (&[u8], f64)> + '_ {
+        let mut next = if rank + 1 == self.len {
+            self.tail
+        } else {
+            self.node_at(rank + 1)
+        };
+        std::iter::from_fn(move || {
+            let node = &self.nodes[next?];
+            next = node.backward;
+            Some((node.member.as_slice(), node.score))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn members(list: &SkipList) -> Vec<(Vec<u8>, f64)> {
+        list.iter_from(0).map(|(m, s)| (m.to_vec(), s)).collect()
+    }
+
+    #[test]
+    fn test_insert_keeps_order() {
+        let mut list = SkipList::new();
+        for (member, score) in [("c", 3.0), ("a", 1.0), ("b", 1.0), ("d", -1.0)] {
+            list.insert(member.as_bytes().to_vec(), score);
+        }
+        assert_eq!(
+            members(&list),
+            vec![
+                (b"d".to_vec(), -1.0),
+                (b"a".to_vec(), 1.0),
+                (b"b".to_vec(), 1.0),
+                (b"c".to_vec(), 3.0),
+            ]
+        );
+        let reversed: Vec<&[u8]> = list.iter_rev_from(3).map(|(m, _)| m).collect();
+        assert_eq!(reversed, vec![&b"c"[..], b"b", b"a", b"d"]);
+    }
+
+    #[test]
+    fn test_ranks_with_many_elements() {
+        let mut list = SkipList::new();
+        // Insert in a scrambled order, so that the spans are updated in the middle of the list
+        for i in 0..1000 {
+            let value = (i * 7919) % 1000;
+            list.insert(format!("{:04}", value).into_bytes(), value as f64);
+        }
+        assert_eq!(list.len(), 1000);
+
+        for value in [0, 1, 499, 998, 999] {
+            let member = format!("{:04}", value).into_bytes();
+            assert_eq!(list.rank(&member, value as f64), Some(value));
+            let (at_rank, score) = list.iter_from(value).next().unwrap();
+            assert_eq!(at_rank, member);
+            assert_eq!(score, value as f64);
+        }
+        assert_eq!(list.rank(b"missing", 5.0), None);
+        assert_eq!(list.count_while(|_, score| score < 250.0), 250);
+
+        for value in (0..1000).step_by(2) {
+            assert!(list.remove(format!("{:04}", value).as_bytes(), value as f64));
+        }
+        assert!(!list.remove(b"0000", 0.0));
+        assert_eq!(list.len(), 500);
+        assert_eq!(list.rank(b"0999", 999.0), Some(499));
+        assert_eq!(list.iter_rev_from(499).count(), 500);
+        assert_eq!(list.iter_from(250).next().unwrap().0, b"0501");
+    }
+}
@@ -0,0 +1,600 @@
+use super::{
+    now, ConsumerGroup, Hash, Set, SortedSet, Stream, StreamId, StringValue, Value, DATABASES,
+    EXPIRY,
+};
+use crate::{crc64::crc64, error::Result, parsers::rdb};
+use std::{fs, io::Write};
+
+const RDB_VERSION: u16 = 12;
+
+const OPCODE_FUNCTION2: u8 = 0xF5;
+const OPCODE_AUX: u8 = 0xFA;
+const OPCODE_RESIZEDB: u8 = 0xFB;
+const OPCODE_EXPIRETIMEMS: u8 = 0xFC;
+const OPCODE_SELECTDB: u8 = 0xFE;
+const OPCODE_EOF: u8 = 0xFF;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_HASH: u8 = 4;
+const TYPE_ZSET_2: u8 = 5;
+const TYPE_SET_INTSET: u8 = 11;
+const TYPE_ZSET_ZIPLIST: u8 = 12;
+const TYPE_HASH_ZIPLIST: u8 = 13;
+const TYPE_STREAM_LISTPACKS_3: u8 = 21;
+const TYPE_HASH_METADATA: u8 = 24;
+const TYPE_HASH_LISTPACK_EX: u8 = 25;
+
+/// The size of the ziplist header: total bytes (4), offset to the tail (4) and entry count (2)
+const ZIPLIST_HEADER_SIZE: usize = 10;
+
+/// The size of the listpack header: total bytes (4) and entry count (2)
+const LISTPACK_HEADER_SIZE: usize = 6;
+
+/// Write a length with the RDB size encoding
+fn write_length(buf: &mut Vec<u8>, length: usize) {
+    if length < 1 << 6 {
+        buf.push(length as u8);
+    } else if length < 1 << 14 {
+        buf.push(0x40 | (length >> 8) as u8);
+        buf.push(length as u8);
+    } else if length <= u32::MAX as usize {
+        buf.push(0x80);
+        buf.extend_from_slice(&(length as u32).to_be_bytes());
+    } else {
+        buf.push(0x81);
+        buf.extend_from_slice(&(length as u64).to_be_bytes());
+    }
+}
+
+/// Write a length prefixed string
+fn write_string(buf: &mut Vec<u8>, value: &[u8]) {
+    write_length(buf, value.len());
+    buf.extend_from_slice(value);
+}
+
+/// Encode entries as a ziplist, with every entry encoded as a string
+///
+/// See `parsers::rdb::nom_ziplist` for the format. Integer encodings are optional, so they're not
+/// used, which means that the entries are stored exactly as they are.
+fn encode_ziplist<'a>(entries: impl Iterator<Item = &'a [u8]>) -> Vec<u8> {
+    let mut buf = vec![0; ZIPLIST_HEADER_SIZE];
+    let mut count = 0;
+    let mut previous_length = 0;
+    let mut tail = ZIPLIST_HEADER_SIZE;
+
+    for entry in entries {
+        tail = buf.len();
+
+        if previous_length < 254 {
+            buf.push(previous_length as u8);
+        } else {
+            buf.push(0xFE);
+            buf.extend_from_slice(&(previous_length as u32).to_le_bytes());
+        }
+
+        let length = entry.len();
+        if length < 1 << 6 {
+            buf.push(length as u8);
+        } else if length < 1 << 14 {
+            buf.push(0x40 | (length >> 8) as u8);
+            buf.push(length as u8);
+        } else {
+            buf.push(0x80);
+            buf.extend_from_slice(&(length as u32).to_be_bytes());
+        }
+        buf.extend_from_slice(entry);
+
+        previous_length = buf.len() - tail;
+        count += 1;
+    }
+    buf.push(0xFF);
+
+    let total = buf.len() as u32;
+    buf[0..4].copy_from_slice(&total.to_le_bytes());
+    buf[4..8].copy_from_slice(&(tail as u32).to_le_bytes());
+    // The count saturates, in which case the entries have to be counted when loading
+    buf[8..10].copy_from_slice(&(count.min(u16::MAX as usize) as u16).to_le_bytes());
+    buf
+}
+
+/// Encode sorted integers as an intset
+///
+/// See `parsers::rdb::nom_intset` for the format. The integers all use the smallest size that fits
+/// every one of them.
+fn encode_intset(values: &[i64]) -> Vec<u8> {
+    let fits = |bits: u32| {
+        values
+            .iter()
+            .all(|&value| value >= -(1 << (bits - 1)) && value < 1 << (bits - 1))
+    };
+    let size: usize = if fits(16) {
+        2
+    } else if fits(32) {
+        4
+    } else {
+        8
+    };
+
+    let mut buf = Vec::with_capacity(8 + values.len() * size);
+    buf.extend_from_slice(&(size as u32).to_le_bytes());
+    buf.extend_from_slice(&(values.len() as u32).to_le_bytes());
+    for value in values {
+        buf.extend_from_slice(&value.to_le_bytes()[..size]);
+    }
+    buf
+}
+
+/// An entry to encode in a listpack
+enum ListpackEntry<'a> {
+    String(&'a [u8]),
+    Integer(u64),
+}
+
+/// Encode entries as a listpack
+///
+/// See `parsers::rdb::nom_listpack` for the format. Strings are stored as they are, and integers
+/// use either the 7 bit or the 64 bit encoding.
+fn encode_listpack<'a>(entries: impl Iterator<Item = ListpackEntry<'a>>) -> Vec<u8> {
+    let mut buf = vec![0; LISTPACK_HEADER_SIZE];
+    let mut count = 0;
+
+    for entry in entries {
+        let start = buf.len();
+        match entry {
+            ListpackEntry::Integer(value) if value < 1 << 7 => buf.push(value as u8),
+            ListpackEntry::Integer(value) => {
+                buf.push(0xF4);
+                buf.extend_from_slice(&value.to_le_bytes());
+            }
+            ListpackEntry::String(string) => {
+                let length = string.len();
+                if length < 1 << 6 {
+                    buf.push(0x80 | length as u8);
+                } else if length < 1 << 12 {
+                    buf.push(0xE0 | (length >> 8) as u8);
+                    buf.push(length as u8);
+                } else {
+                    buf.push(0xF0);
+                    buf.extend_from_slice(&(length as u32).to_le_bytes());
+                }
+                buf.extend_from_slice(string);
+            }
+        }
+
+        // The length of the entry follows it, 7 bits per byte with the most significant first,
+        // so that it can be read backwards. Every byte but the most significant one has the high
+        // bit set, to mark that more bytes follow when reading backwards
+        let length = buf.len() - start;
+        let backlen_size = match length {
+            0..=127 => 1,
+            128..=16_382 => 2,
+            16_383..=2_097_150 => 3,
+            2_097_151..=268_435_454 => 4,
+            _ => 5,
+        };
+        for i in (0..backlen_size).rev() {
+            let byte = ((length >> (7 * i)) & 0x7F) as u8;
+            buf.push(if i == backlen_size - 1 {
+                byte
+            } else {
+                byte | 0x80
+            });
+        }
+        count += 1;
+    }
+    buf.push(0xFF);
+
+    let total = buf.len() as u32;
+    buf[0..4].copy_from_slice(&total.to_le_bytes());
+    buf[4..6].copy_from_slice(&(count.min(u16::MAX as usize) as u16).to_le_bytes());
+    buf
+}
+
+fn write_value(buf: &mut Vec<u8>, key: &[u8], value: &Value) {
+    match value {
+        Value::String(s) => {
+            buf.push(TYPE_STRING);
+            write_string(buf, key);
+            match s {
+                StringValue::Raw(bytes) => write_string(buf, bytes),
+                StringValue::Int(_) => write_string(buf, &s.as_bytes()),
+            }
+        }
+        Value::List(list) => {
+            buf.push(TYPE_LIST);
+            write_string(buf, key);
+            write_length(buf, list.len());
+            for element in list.iter() {
+                write_string(buf, element);
+            }
+        }
+        Value::Set(Set::Intset(values)) => {
+            buf.push(TYPE_SET_INTSET);
+            write_string(buf, key);
+            write_string(buf, &encode_intset(values));
+        }
+        Value::Set(set) => {
+            buf.push(TYPE_SET);
+            write_string(buf, key);
+            write_length(buf, set.len());
+            for member in set.iter() {
+                write_string(buf, &member);
+            }
+        }
+        Value::SortedSet(sorted_set @ SortedSet::Listpack(_)) => {
+            buf.push(TYPE_ZSET_ZIPLIST);
+            write_string(buf, key);
+            let scores: Vec<String> = sorted_set.iter().map(|(_, s)| s.to_string()).collect();
+            let entries = sorted_set
+                .iter()
+                .zip(&scores)
+                .flat_map(|((member, _), score)| [member, score.as_bytes()]);
+            write_string(buf, &encode_ziplist(entries));
+        }
+        Value::SortedSet(sorted_set) => {
+            buf.push(TYPE_ZSET_2);
+            write_string(buf, key);
+            write_length(buf, sorted_set.len());
+            for (member, score) in sorted_set.iter() {
+                write_string(buf, member);
+                buf.extend_from_slice(&score.to_le_bytes());
+            }
+        }
+        Value::Stream(stream) => {
+            buf.push(TYPE_STREAM_LISTPACKS_3);
+            write_string(buf, key);
+            write_stream(buf, stream);
+        }
+        Value::Hash(hash) if hash.has_expiries() => write_hash_with_expiries(buf, key, hash),
+        Value::Hash(hash) if hash.is_listpack() => {
+            buf.push(TYPE_HASH_ZIPLIST);
+            write_string(buf, key);
+            let entries = hash
+                .iter()
+                .flat_map(|(field, value)| [field.as_slice(), value.as_slice()]);
+            write_string(buf, &encode_ziplist(entries));
+        }
+        Value::Hash(hash) => {
+            buf.push(TYPE_HASH);
+            write_string(buf, key);
+            write_length(buf, hash.len());
+            for (field, value) in hash.iter() {
+                write_string(buf, field);
+                write_string(buf, value);
+            }
+        }
+    }
+}
+
+/// Write a hash where some fields have an expiry, in the encodings added in Redis 7.4
+///
+/// Both encodings start with the earliest expiry. Listpacks store field, value and expiry
+/// triplets, while hash tables store the expiry before each field-value pair relative to the
+/// earliest one. Either way an expiry of zero means that the field has none.
+fn write_hash_with_expiries(buf: &mut Vec<u8>, key: &[u8], hash: &Hash) {
+    let min_expiry = hash.min_expiry().unwrap_or_default() as u64;
+
+    if hash.is_listpack() {
+        buf.push(TYPE_HASH_LISTPACK_EX);
+        write_string(buf, key);
+        buf.extend_from_slice(&min_expiry.to_le_bytes());
+        let entries = hash.iter().flat_map(|(field, value)| {
+            [
+                ListpackEntry::String(field),
+                ListpackEntry::String(value),
+                ListpackEntry::Integer(hash.expiry(field).unwrap_or_default() as u64),
+            ]
+        });
+        write_string(buf, &encode_listpack(entries));
+    } else {
+        buf.push(TYPE_HASH_METADATA);
+        write_string(buf, key);
+        buf.extend_from_slice(&min_expiry.to_le_bytes());
+        write_length(buf, hash.len());
+        for (field, value) in hash.iter() {
+            let expiry = hash
+                .expiry(field)
+                .map_or(0, |expiry| expiry as u64 - min_expiry + 1);
+            write_length(buf, expiry as usize);
+            write_string(buf, field);
+            write_string(buf, value);
+        }
+    }
+}
+
+/// Write a stream ID as two lengths
+fn write_stream_id(buf: &mut Vec<u8>, id: StreamId) {
+    write_length(buf, id.ms as usize);
+    write_length(buf, id.seq as usize);
+}
+
+/// Write a stream, with a listpack for each of its nodes
+///
+/// See `parsers::rdb::nom_stream` for the format. The master entry of each listpack has no
+/// fields, so every entry is written with its own fields, and the IDs are relative to the first
+/// entry in the listpack.
+fn write_stream(buf: &mut Vec<u8>, stream: &Stream) {
+    write_length(buf, stream.nodes().count());
+    for entries in stream.nodes() {
+        let master_id = entries[0].0;
+        let mut master_key = Vec::with_capacity(16);
+        write_raw_stream_id(&mut master_key, master_id);
+        write_string(buf, &master_key);
+
+        let master = [
+            ListpackEntry::Integer(entries.len() as u64),
+            ListpackEntry::Integer(0),
+            ListpackEntry::Integer(0),
+            ListpackEntry::Integer(0),
+        ];
+        let items = entries.iter().flat_map(|(id, fields)| {
+            // The sequence number can go down when the milliseconds go up, in which case the
+            // difference wraps around and is read back as a negative number
+            [
+                ListpackEntry::Integer(0),
+                ListpackEntry::Integer(id.ms - master_id.ms),
+                ListpackEntry::Integer(id.seq.wrapping_sub(master_id.seq)),
+                ListpackEntry::Integer(fields.len() as u64),
+            ]
+            .into_iter()
+            .chain(fields.iter().flat_map(|(field, value)| {
+                [ListpackEntry::String(field), ListpackEntry::String(value)]
+            }))
+            .chain([ListpackEntry::Integer(fields.len() as u64 * 2 + 4)])
+        });
+        write_string(buf, &encode_listpack(master.into_iter().chain(items)));
+    }
+
+    write_length(buf, stream.len());
+    write_stream_id(buf, stream.last_id());
+    write_stream_id(buf, stream.first_id());
+    write_stream_id(buf, stream.max_deleted_id());
+    write_length(buf, stream.entries_added() as usize);
+    write_length(buf, stream.groups().len());
+    for (name, group) in stream.groups() {
+        write_string(buf, name);
+        write_consumer_group(buf, group);
+    }
+}
+
+/// Write a stream ID as 16 bytes, the milliseconds and sequence number big-endian
+fn write_raw_stream_id(buf: &mut Vec<u8>, id: StreamId) {
+    buf.extend_from_slice(&id.ms.to_be_bytes());
+    buf.extend_from_slice(&id.seq.to_be_bytes());
+}
+
+/// Write a consumer group of a stream, after its name
+///
+/// The group's pending entries are written with their delivery, and each consumer only has the
+/// IDs of the entries pending for it. Unknown counts and times are written as -1.
+fn write_consumer_group(buf: &mut Vec<u8>, group: &ConsumerGroup) {
+    write_stream_id(buf, group.last_id);
+    write_length(
+        buf,
+        group.entries_read.map_or(usize::MAX, |read| read as usize),
+    );
+    write_length(buf, group.pending.len());
+    for (id, entry) in &group.pending {
+        write_raw_stream_id(buf, *id);
+        buf.extend_from_slice(&(entry.delivery_time as u64).to_le_bytes());
+        write_length(buf, entry.delivery_count as usize);
+    }
+    write_length(buf, group.consumers.len());
+    for (name, consumer) in &group.consumers {
+        write_string(buf, name);
+        buf.extend_from_slice(&(consumer.seen_time as u64).to_le_bytes());
+        let active_time = consumer.active_time.map_or(u64::MAX, |time| time as u64);
+        buf.extend_from_slice(&active_time.to_le_bytes());
+        write_length(buf, consumer.pending.len());
+        for id in &consumer.pending {
+            write_raw_stream_id(buf, *id);
+        }
+    }
+}
+
+/// Serialise libraries of functions by their code, as they are in RDB files
+fn write_functions(buf: &mut Vec<u8>, libraries: &[Vec<u8>]) {
+    for code in libraries {
+        buf.push(OPCODE_FUNCTION2);
+        write_string(buf, code);
+    }
+}
+
+/// Serialise libraries of functions for FUNCTION DUMP, followed by the RDB version and a checksum
+/// like the payloads of DUMP
+pub(crate) fn dump_functions(libraries: &[Vec<u8>]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    write_functions(&mut buf, libraries);
+    buf.extend_from_slice(&RDB_VERSION.to_le_bytes());
+    let checksum = crc64(&buf);
+    buf.extend_from_slice(&checksum.to_le_bytes());
+    buf
+}
+
+/// Parse a payload of FUNCTION DUMP back into the code of its libraries
+pub(crate) fn restore_functions(payload: &[u8]) -> std::result::Result<Vec<Vec<u8>>, &'static str> {
+    const WRONG_PAYLOAD: &str = "payload version or checksum are wrong";
+    if payload.len() < 10 {
+        return Err(WRONG_PAYLOAD);
+    }
+    let (data, checksum) = payload.split_at(payload.len() - 8);
+    let (mut input, version) = data.split_at(data.len() - 2);
+    if u16::from_le_bytes([version[0], version[1]]) > RDB_VERSION
+        || crc64(data).to_le_bytes() != checksum
+    {
+        return Err(WRONG_PAYLOAD);
+    }
+
+    let mut libraries = Vec::new();
+    while let Some((&opcode, rest)) = input.split_first() {
+        if opcode != OPCODE_FUNCTION2 {
+            return Err("given type is not a function");
+        }
+        let Ok((rest, code)) = rdb::nom_size_encoded_string(rest) else {
+            return Err("failed loading the library");
+        };
+        libraries.push(code.to_bytes());
+        input = rest;
+    }
+    Ok(libraries)
+}
+
+/// Serialise all the databases, and the libraries of functions, in the RDB format
+fn dump(libraries: &[Vec<u8>]) -> Vec<u8> {
+    let dbs = DATABASES.read().unwrap();
+    let expiries = EXPIRY.read().unwrap();
+    let now = now();
+
+    let mut buf = Vec::new();
+    buf.extend_from_slice(b"REDIS");
+    buf.extend_from_slice(format!("{:04}", RDB_VERSION).as_bytes());
+
+    for (key, value) in [
+        (&b"redis-ver"[..], &b"7.4.0"[..]),
+        (b"redis-bits", b"64"),
+        (b"ctime", (now / 1000).to_string().as_bytes()),
+    ] {
+        buf.push(OPCODE_AUX);
+        write_string(&mut buf, key);
+        write_string(&mut buf, value);
+    }
+    write_functions(&mut buf, libraries);
+
+    for (index, (db, expiry)) in dbs.iter().zip(expiries.iter()).enumerate() {
+        if db.is_empty() {
+            continue;
+        }
+        buf.push(OPCODE_SELECTDB);
+        write_length(&mut buf, index);
+        buf.push(OPCODE_RESIZEDB);
+        write_length(&mut buf, db.len());
+        write_length(&mut buf, expiry.len());
+
+        for (key, value) in db.iter() {
+            if let Some(&ttl) = expiry.get(key) {
+                if now > ttl {
+                    continue;
+                }
+                buf.push(OPCODE_EXPIRETIMEMS);
+                buf.extend_from_slice(&(ttl as u64).to_le_bytes());
+            }
+            write_value(&mut buf, key, value);
+        }
+    }
+
+    buf.push(OPCODE_EOF);
+    // A checksum of zero means that the checksum is not checked on load
+    buf.extend_from_slice(&[0; 8]);
+    buf
+}
+
+/// Save all the databases, and the libraries of functions, to a RDB file on disk
+///
+/// The snapshot is written to a temporary file first, which then replaces the file at the path,
+/// so that a failed save never leaves a partially written RDB file behind
+pub(crate) fn save_rdb(path: &str, libraries: &[Vec<u8>]) -> Result<()> {
+    log::debug!("Saving RDB file: {}", path);
+    let buf = dump(libraries);
+
+    let tmp_path = format!("{}.tmp-{}", path, std::process::id());
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(&buf)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+
+    log::debug!("Saved {} bytes to RDB file", buf.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::rdb::{nom_intset, nom_listpack, nom_ziplist};
+
+    #[test]
+    fn test_encode_ziplist_round_trip() {
+        let long = vec![b'x'; 300];
+        let entries: Vec<&[u8]> = vec![b"field", b"12", &long, b""];
+        let encoded = encode_ziplist(entries.iter().copied());
+
+        let (rest, decoded) = nom_ziplist(&encoded).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(
+            decoded,
+            entries.iter().map(|e| e.to_vec()).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            u32::from_le_bytes(encoded[0..4].try_into().unwrap()) as usize,
+            encoded.len()
+        );
+    }
+
+    #[test]
+    fn test_encode_listpack_round_trip() {
+        let long = vec![b'x'; 5000];
+        let entries = vec![
+            ListpackEntry::String(b"field"),
+            ListpackEntry::Integer(0),
+            ListpackEntry::String(&long),
+            ListpackEntry::Integer(1_700_000_000_000),
+            ListpackEntry::String(b""),
+        ];
+        let encoded = encode_listpack(entries.into_iter());
+
+        let (rest, decoded) = nom_listpack(&encoded).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(
+            decoded,
+            vec![
+                b"field".to_vec(),
+                b"0".to_vec(),
+                long,
+                b"1700000000000".to_vec(),
+                b"".to_vec()
+            ]
+        );
+        assert_eq!(
+            u32::from_le_bytes(encoded[0..4].try_into().unwrap()) as usize,
+            encoded.len()
+        );
+    }
+
+    #[test]
+    fn test_encode_intset_round_trip() {
+        for values in [
+            vec![-3, 0, 100],
+            vec![-70_000, 5],
+            vec![i64::MIN, 1, i64::MAX],
+        ] {
+            let encoded = encode_intset(&values);
+            let (rest, decoded) = nom_intset(&encoded).unwrap();
+            assert!(rest.is_empty());
+            assert_eq!(
+                decoded,
+                values
+                    .iter()
+                    .map(|v| v.to_string().into_bytes())
+                    .collect::<Vec<_>>()
+            );
+        }
+        assert_eq!(encode_intset(&[1, 2])[0], 2);
+        assert_eq!(encode_intset(&[1, 1 << 20])[0], 4);
+    }
+
+    #[test]
+    fn test_write_length() {
+        let mut buf = Vec::new();
+        write_length(&mut buf, 10);
+        assert_eq!(buf, vec![10]);
+
+        let mut buf = Vec::new();
+        write_length(&mut buf, 700);
+        assert_eq!(buf, vec![0x42, 0xBC]);
+
+        let mut buf = Vec::new();
+        write_length(&mut buf, 17000);
+        assert_eq!(buf, vec![0x80, 0, 0, 0x42, 0x68]);
+    }
+}
@@ -0,0 +1,234 @@
+use super::{
+    skiplist::{compare, SkipList},
+    ListpackLimits,
+};
+use std::{cmp::Ordering, collections::HashMap};
+
+/// A set of unique members, each with a score that the members are ordered by
+///
+/// Small sorted sets are stored as a flat list of member-score pairs, kept sorted, similar to the
+/// listpack encoding in Redis. Once the sorted set grows past the limits it's converted to a
+/// skiplist, along with a hash table to look up the score of a member, and is never converted back.
+///
+/// Members with the same score are ordered by the members themselves, byte by byte.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum SortedSet {
+    Listpack(Vec<(Vec<u8>, f64)>),
+    Skiplist {
+        scores: HashMap<Vec<u8>, f64>,
+        list: SkipList,
+    },
+}
+
+impl Default for SortedSet {
+    fn default() -> Self {
+        SortedSet::Listpack(Vec::new())
+    }
+}
+
+impl SortedSet {
+    pub(crate) fn new() -> Self {
+        SortedSet::default()
+    }
+
+    /// Create a sorted set from member-score pairs, picking the encoding based on the limits
+    pub(crate) fn from_entries(
+        entries: impl IntoIterator<Item = (Vec<u8>, f64)>,
+        limits: &ListpackLimits,
+    ) -> Self {
+        let mut sorted_set = SortedSet::new();
+        for (member, score) in entries {
+            sorted_set.insert(member, score, limits);
+        }
+        sorted_set
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            SortedSet::Listpack(entries) => entries.len(),
+            SortedSet::Skiplist { list, .. } => list.len(),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The name of the encoding, as returned by OBJECT ENCODING
+    pub(crate) fn encoding(&self) -> &'static str {
+        match self {
+            SortedSet::Listpack(_) => "listpack",
+            SortedSet::Skiplist { .. } => "skiplist",
+        }
+    }
+
+    pub(crate) fn score(&self, member: &[u8]) -> Option<f64> {
+        match self {
+            SortedSet::Listpack(entries) => entries
+                .iter()
+                .find(|(m, _)| m == member)
+                .map(|(_, score)| *score),
+            SortedSet::Skiplist { scores, .. } => scores.get(member).copied(),
+        }
+    }
+
+    /// Add a member or update its score, converting to a skiplist if the sorted set outgrows the
+    /// limits
+    ///
+    /// Returns true if the member is new
+    pub(crate) fn insert(&mut self, member: Vec<u8>, score: f64, limits: &ListpackLimits) -> bool {
+        let existed = self.remove(&member);
+
+        if let SortedSet::Listpack(entries) = self {
+            if entries.len() < limits.max_entries && member.len() <= limits.max_value {
+                let index = entries
+                    .partition_point(|(m, s)| compare((m, *s), (&member, score)) == Ordering::Less);
+                entries.insert(index, (member, score));
+                return !existed;
+            }
+            self.convert_to_skiplist();
+        }
+
+        match self {
+            SortedSet::Skiplist { scores, list } => {
+                list.insert(member.clone(), score);
+                scores.insert(member, score);
+            }
+            SortedSet::Listpack(_) => unreachable!(),
+        }
+        !existed
+    }
+
+    /// Remove a member, returning true if it existed
+    pub(crate) fn remove(&mut self, member: &[u8]) -> bool {
+        match self {
+            SortedSet::Listpack(entries) => match entries.iter().position(|(m, _)| m == member) {
+                Some(index) => {
+                    entries.remove(index);
+                    true
+                }
+                None => false,
+            },
+            SortedSet::Skiplist { scores, list } => match scores.remove(member) {
+                Some(score) => list.remove(member, score),
+                None => false,
+            },
+        }
+    }
+
+    /// The rank (from 0, in ascending order) of a member, if it's in the sorted set
+    pub(crate) fn rank(&self, member: &[u8]) -> Option<usize> {
+        match self {
+            SortedSet::Listpack(entries) => entries.iter().position(|(m, _)| m == member),
+            SortedSet::Skiplist { scores, list } => list.rank(member, *scores.get(member)?),
+        }
+    }
+
+    /// Count the members from the lowest rank that match the predicate
+    ///
+    /// The predicate has to be true for a prefix of the members in order and false after that,
+    /// which makes it possible to find where a range of scores or members starts and ends
+    pub(crate) fn count_while(&self, predicate: impl Fn(&[u8], f64) -> bool) -> usize {
+        match self {
+            SortedSet::Listpack(entries) => entries.partition_point(|(m, s)| predicate(m, *s)),
+            SortedSet::Skiplist { list, .. } => list.count_while(predicate),
+        }
+    }
+
+    /// Iterate over the members and their scores in ascending order, starting at a rank
+    pub(crate) fn iter_from(&self, rank: usize) -> Box<dyn Iterator<Item = (&[u8], f64)> + '_> {
+        match self {
+            SortedSet::Listpack(entries) => Box::new(
+                entries
+                    .iter()
+                    .skip(rank)
+                    .map(|(member, score)| (member.as_slice(), *score)),
+            ),
+            SortedSet::Skiplist { list, .. } => Box::new(list.iter_from(rank)),
+        }
+    }
+
+    /// Iterate over the members and their scores in descending order, starting at a rank
+    pub(crate) fn iter_rev_from(&self, rank: usize) -> Box<dyn Iterator<Item = (&[u8], f64)> + '_> {
+        match self {
+            SortedSet::Listpack(entries) => Box::new(
+                entries
+                    .iter()
+                    .take(rank + 1)
+                    .rev()
+                    .map(|(member, score)| (member.as_slice(), *score)),
+            ),
+            SortedSet::Skiplist { list, .. } => Box::new(list.iter_rev_from(rank)),
+        }
+    }
+
+    pub(crate) fn iter(&self) -> Box<dyn Iterator<Item = (&[u8], f64)> + '_> {
+        self.iter_from(0)
+    }
+
+    fn convert_to_skiplist(&mut self) {
+        if let SortedSet::Listpack(entries) = self {
+            log::trace!(
+                "Converting sorted set with {} members to a skiplist",
+                entries.len()
+            );
+            let mut list = SkipList::new();
+            let mut scores = HashMap::with_capacity(entries.len());
+            for (member, score) in entries.drain(..) {
+                list.insert(member.clone(), score);
+                scores.insert(member, score);
+            }
+            *self = SortedSet::Skiplist { scores, list };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn members(sorted_set: &SortedSet) -> Vec<&[u8]> {
+        sorted_set.iter().map(|(member, _)| member).collect()
+    }
+
+    #[test]
+    fn test_encodings_behave_the_same() {
+        let small = ListpackLimits::default();
+        let tiny = ListpackLimits {
+            max_entries: 2,
+            max_value: 64,
+        };
+        for limits in [small, tiny] {
+            let mut sorted_set = SortedSet::new();
+            assert!(sorted_set.insert(b"b".to_vec(), 2.0, &limits));
+            assert!(sorted_set.insert(b"a".to_vec(), 2.0, &limits));
+            assert!(sorted_set.insert(b"c".to_vec(), 1.0, &limits));
+            assert!(!sorted_set.insert(b"c".to_vec(), 3.0, &limits));
+
+            assert_eq!(members(&sorted_set), vec![&b"a"[..], b"b", b"c"]);
+            assert_eq!(sorted_set.score(b"c"), Some(3.0));
+            assert_eq!(sorted_set.rank(b"b"), Some(1));
+            assert_eq!(sorted_set.rank(b"missing"), None);
+            assert_eq!(sorted_set.count_while(|_, score| score <= 2.0), 2);
+            let reversed: Vec<&[u8]> = sorted_set.iter_rev_from(1).map(|(m, _)| m).collect();
+            assert_eq!(reversed, vec![&b"b"[..], b"a"]);
+
+            assert!(sorted_set.remove(b"a"));
+            assert!(!sorted_set.remove(b"a"));
+            assert_eq!(sorted_set.len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_converts_to_skiplist() {
+        let limits = ListpackLimits {
+            max_entries: 2,
+            max_value: 4,
+        };
+        let mut sorted_set = SortedSet::from_entries([(b"a".to_vec(), 1.0)], &limits);
+        assert_eq!(sorted_set.encoding(), "listpack");
+        sorted_set.insert(b"long-member".to_vec(), 2.0, &limits);
+        assert_eq!(sorted_set.encoding(), "skiplist");
+        assert_eq!(members(&sorted_set), vec![&b"a"[..], b"long-member"]);
+    }
+}
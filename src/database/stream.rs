@@ -0,0 +1,770 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+/// The ID of a stream entry, which is the unix time in milliseconds that it was added, along with
+/// a sequence number for entries added within the same millisecond
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) struct StreamId {
+    pub(crate) ms: u64,
+    pub(crate) seq: u64,
+}
+
+impl StreamId {
+    pub(crate) const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub(crate) const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    pub(crate) fn new(ms: u64, seq: u64) -> Self {
+        StreamId { ms, seq }
+    }
+
+    /// The smallest ID that is greater than this one, if there is one
+    pub(crate) fn next(self) -> Option<StreamId> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(StreamId::new(self.ms, seq)),
+            None => Some(StreamId::new(self.ms.checked_add(1)?, 0)),
+        }
+    }
+
+    /// The greatest ID that is smaller than this one, if there is one
+    pub(crate) fn prev(self) -> Option<StreamId> {
+        match self.seq.checked_sub(1) {
+            Some(seq) => Some(StreamId::new(self.ms, seq)),
+            None => Some(StreamId::new(self.ms.checked_sub(1)?, u64::MAX)),
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// The field-value pairs of a stream entry
+pub(crate) type StreamFields = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// The limits for how large a node of a stream can grow before a new node is started, where a
+/// limit of zero means that there is no limit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct StreamNodeLimits {
+    /// The maximum number of entries in a node
+    pub(crate) max_entries: usize,
+    /// The maximum size in bytes of the fields and values in a node
+    pub(crate) max_bytes: usize,
+}
+
+impl Default for StreamNodeLimits {
+    fn default() -> Self {
+        StreamNodeLimits {
+            max_entries: 100,
+            max_bytes: 4096,
+        }
+    }
+}
+
+/// How a stream is trimmed, by XTRIM or by XADD
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Trim {
+    /// Keep at most this many entries
+    MaxLen(usize),
+    /// Remove the entries with an ID lower than this one
+    MinId(StreamId),
+}
+
+/// An entry that was delivered to a consumer of a group and hasn't been acknowledged yet
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct PendingEntry {
+    /// The consumer that the entry was last delivered to
+    pub(crate) consumer: Vec<u8>,
+    /// When the entry was last delivered, in unix time milliseconds
+    pub(crate) delivery_time: u128,
+    /// How many times the entry has been delivered
+    pub(crate) delivery_count: u64,
+}
+
+/// A consumer of a group, where the times are in unix time milliseconds
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Consumer {
+    /// When the consumer last tried to read or claim entries
+    pub(crate) seen_time: u128,
+    /// When the consumer last read or claimed entries, or `None` if it never has
+    pub(crate) active_time: Option<u128>,
+    /// The IDs of the entries that are pending for the consumer
+    pub(crate) pending: BTreeSet<StreamId>,
+}
+
+impl Consumer {
+    pub(crate) fn new(now: u128) -> Self {
+        Consumer {
+            seen_time: now,
+            active_time: None,
+            pending: BTreeSet::new(),
+        }
+    }
+}
+
+/// A group of consumers that share the entries of a stream, where each entry is delivered to one
+/// of the consumers and stays pending until it's acknowledged
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct ConsumerGroup {
+    /// The ID of the last entry delivered to the group
+    pub(crate) last_id: StreamId,
+    /// How many entries of the stream the group has read, or `None` if that can't be known, such
+    /// as after the last ID is set to an arbitrary ID
+    pub(crate) entries_read: Option<u64>,
+    /// The entries delivered to the consumers that haven't been acknowledged yet
+    pub(crate) pending: BTreeMap<StreamId, PendingEntry>,
+    pub(crate) consumers: BTreeMap<Vec<u8>, Consumer>,
+}
+
+impl ConsumerGroup {
+    pub(crate) fn new(last_id: StreamId, entries_read: Option<u64>) -> Self {
+        ConsumerGroup {
+            last_id,
+            entries_read,
+            ..Default::default()
+        }
+    }
+
+    /// Look up a consumer that is trying to read or claim entries, creating it if it doesn't exist
+    pub(crate) fn consumer_mut(&mut self, name: &[u8], now: u128) -> &mut Consumer {
+        let consumer = self
+            .consumers
+            .entry(name.to_vec())
+            .or_insert_with(|| Consumer::new(now));
+        consumer.seen_time = now;
+        consumer
+    }
+
+    /// Create a consumer, returning false if it already exists
+    pub(crate) fn create_consumer(&mut self, name: &[u8], now: u128) -> bool {
+        if self.consumers.contains_key(name) {
+            return false;
+        }
+        self.consumers.insert(name.to_vec(), Consumer::new(now));
+        true
+    }
+
+    /// Delete a consumer along with its pending entries, returning how many entries it had
+    pub(crate) fn remove_consumer(&mut self, name: &[u8]) -> Option<usize> {
+        let consumer = self.consumers.remove(name)?;
+        for id in &consumer.pending {
+            self.pending.remove(id);
+        }
+        Some(consumer.pending.len())
+    }
+
+    /// Acknowledge an entry, returning false if it wasn't pending
+    pub(crate) fn ack(&mut self, id: StreamId) -> bool {
+        let Some(entry) = self.pending.remove(&id) else {
+            return false;
+        };
+        if let Some(consumer) = self.consumers.get_mut(&entry.consumer) {
+            consumer.pending.remove(&id);
+        }
+        true
+    }
+
+    /// Make an entry pending for a consumer, taking it from the consumer it was pending for
+    ///
+    /// Returns the pending entry, which has a delivery count of zero if it wasn't pending before,
+    /// so that the caller can update its delivery.
+    pub(crate) fn assign(&mut self, id: StreamId, consumer: &[u8], now: u128) -> &mut PendingEntry {
+        let entry = self.pending.entry(id).or_insert_with(|| PendingEntry {
+            consumer: consumer.to_vec(),
+            delivery_time: now,
+            delivery_count: 0,
+        });
+        if entry.consumer != consumer {
+            if let Some(previous) = self.consumers.get_mut(&entry.consumer) {
+                previous.pending.remove(&id);
+            }
+            entry.consumer = consumer.to_vec();
+        }
+        self.consumers
+            .entry(consumer.to_vec())
+            .or_insert_with(|| Consumer::new(now))
+            .pending
+            .insert(id);
+        entry
+    }
+}
+
+/// A run of consecutive entries, which is the unit that approximate trimming removes
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Node {
+    entries: Vec<(StreamId, StreamFields)>,
+    bytes: usize,
+}
+
+fn fields_size(fields: &StreamFields) -> usize {
+    fields
+        .iter()
+        .map(|(field, value)| field.len() + value.len())
+        .sum()
+}
+
+/// An append-only log of entries, each with a unique ID that is greater than all the IDs before it
+///
+/// The entries are grouped into nodes that are keyed by the ID of the first entry added to them,
+/// similar to the radix tree of listpacks in Redis, so that a range of entries can be found
+/// without going through every entry, and trimming can drop whole nodes at a time.
+///
+/// The stream remembers the last ID that was added even when that entry has since been deleted,
+/// as new entries must always have a greater ID.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Stream {
+    nodes: BTreeMap<StreamId, Node>,
+    length: usize,
+    last_id: StreamId,
+    max_deleted_id: StreamId,
+    entries_added: u64,
+    groups: BTreeMap<Vec<u8>, ConsumerGroup>,
+}
+
+impl Stream {
+    pub(crate) fn new() -> Self {
+        Stream::default()
+    }
+
+    /// Create a stream from entries in ID order, along with the state that is kept about entries
+    /// that no longer exist, such as when loading it from an RDB file
+    pub(crate) fn from_entries(
+        entries: impl IntoIterator<Item = (StreamId, StreamFields)>,
+        last_id: StreamId,
+        max_deleted_id: StreamId,
+        entries_added: u64,
+        limits: &StreamNodeLimits,
+    ) -> Self {
+        let mut stream = Stream::new();
+        for (id, fields) in entries {
+            stream.append(id, fields, limits);
+        }
+        stream.last_id = stream.last_id.max(last_id);
+        stream.max_deleted_id = max_deleted_id;
+        stream.entries_added = stream.entries_added.max(entries_added);
+        stream
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.length
+    }
+
+    /// The ID of the last entry that was added, which might have been deleted since
+    pub(crate) fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    /// The greatest ID of an entry that was deleted with XDEL
+    pub(crate) fn max_deleted_id(&self) -> StreamId {
+        self.max_deleted_id
+    }
+
+    /// How many entries have been added to the stream over its lifetime
+    pub(crate) fn entries_added(&self) -> u64 {
+        self.entries_added
+    }
+
+    /// The ID of the first entry, or 0-0 if the stream is empty
+    pub(crate) fn first_id(&self) -> StreamId {
+        self.iter().next().map_or(StreamId::MIN, |(id, _)| id)
+    }
+
+    /// The ID for an entry added at the given unix time in milliseconds
+    ///
+    /// The ID never goes backwards, even if the clock does, so entries added in the same (or an
+    /// earlier) millisecond as the last one get the next sequence number. Returns `None` if the
+    /// stream has used up every possible ID.
+    pub(crate) fn next_id(&self, ms: u64) -> Option<StreamId> {
+        if ms > self.last_id.ms {
+            Some(StreamId::new(ms, 0))
+        } else {
+            self.last_id.next()
+        }
+    }
+
+    /// Add an entry to the end of the stream, which must have an ID greater than the last one
+    pub(crate) fn append(&mut self, id: StreamId, fields: StreamFields, limits: &StreamNodeLimits) {
+        debug_assert!(self.length == 0 || id > self.last_id);
+        let size = fields_size(&fields);
+        let node = match self.nodes.last_entry() {
+            Some(node)
+                if (limits.max_entries == 0 || node.get().entries.len() < limits.max_entries)
+                    && (limits.max_bytes == 0 || node.get().bytes + size <= limits.max_bytes) =>
+            {
+                node.into_mut()
+            }
+            _ => self.nodes.entry(id).or_default(),
+        };
+        node.entries.push((id, fields));
+        node.bytes += size;
+        self.length += 1;
+        self.last_id = id;
+        self.entries_added += 1;
+    }
+
+    /// Iterate over the entries with IDs from start to end, both inclusive, which can be reversed
+    pub(crate) fn range(
+        &self,
+        start: StreamId,
+        end: StreamId,
+    ) -> impl DoubleEndedIterator<Item = (StreamId, &StreamFields)> + '_ {
+        // The node holding the start is the last one keyed at or before it
+        let first = self
+            .nodes
+            .range(..=start)
+            .next_back()
+            .map_or(StreamId::MIN, |(id, _)| *id);
+        let nodes = if start <= end {
+            self.nodes.range(first..=end)
+        } else {
+            self.nodes.range(first..first)
+        };
+        nodes
+            .flat_map(|(_, node)| node.entries.iter())
+            .filter(move |(id, _)| start <= *id && *id <= end)
+            .map(|(id, fields)| (*id, fields))
+    }
+
+    /// Look up an entry by its ID
+    pub(crate) fn get(&self, id: StreamId) -> Option<&StreamFields> {
+        self.range(id, id).next().map(|(_, fields)| fields)
+    }
+
+    pub(crate) fn iter(&self) -> impl DoubleEndedIterator<Item = (StreamId, &StreamFields)> + '_ {
+        self.range(StreamId::MIN, StreamId::MAX)
+    }
+
+    /// Iterate over the nodes, with the entries in each of them
+    pub(crate) fn nodes(&self) -> impl Iterator<Item = &[(StreamId, StreamFields)]> + '_ {
+        self.nodes.values().map(|node| node.entries.as_slice())
+    }
+
+    /// Delete an entry, returning true if it existed
+    pub(crate) fn remove(&mut self, id: StreamId) -> bool {
+        let Some((&node_id, node)) = self.nodes.range_mut(..=id).next_back() else {
+            return false;
+        };
+        let Ok(index) = node.entries.binary_search_by_key(&id, |(id, _)| *id) else {
+            return false;
+        };
+        let (_, fields) = node.entries.remove(index);
+        node.bytes -= fields_size(&fields);
+        if node.entries.is_empty() {
+            self.nodes.remove(&node_id);
+        }
+        self.length -= 1;
+        self.max_deleted_id = self.max_deleted_id.max(id);
+        true
+    }
+
+    /// Remove entries from the start of the stream, returning how many were removed
+    ///
+    /// Approximate trimming only removes whole nodes, so it can leave some entries that should
+    /// have been trimmed, and stops before removing more than the limit (unless it's zero). Exact
+    /// trimming also removes entries from the node where the trimming ends.
+    pub(crate) fn trim(&mut self, trim: Trim, approximate: bool, limit: usize) -> usize {
+        let mut removed = 0;
+        while let Some(mut node) = self.nodes.first_entry() {
+            let node_length = node.get().entries.len();
+            let whole = match trim {
+                Trim::MaxLen(max_length) => self.length - node_length >= max_length,
+                Trim::MinId(min_id) => node
+                    .get()
+                    .entries
+                    .last()
+                    .is_some_and(|(id, _)| *id < min_id),
+            };
+            if whole {
+                if approximate && limit > 0 && removed + node_length > limit {
+                    break;
+                }
+                node.remove();
+                self.length -= node_length;
+                removed += node_length;
+                continue;
+            }
+            if approximate {
+                break;
+            }
+
+            let node = node.get_mut();
+            let count = match trim {
+                Trim::MaxLen(max_length) => self.length.saturating_sub(max_length),
+                Trim::MinId(min_id) => node.entries.partition_point(|(id, _)| *id < min_id),
+            };
+            for (_, fields) in node.entries.drain(..count) {
+                node.bytes -= fields_size(&fields);
+            }
+            self.length -= count;
+            removed += count;
+            break;
+        }
+        removed
+    }
+
+    /// The consumer groups, by name
+    pub(crate) fn groups(&self) -> &BTreeMap<Vec<u8>, ConsumerGroup> {
+        &self.groups
+    }
+
+    pub(crate) fn group(&self, name: &[u8]) -> Option<&ConsumerGroup> {
+        self.groups.get(name)
+    }
+
+    pub(crate) fn group_mut(&mut self, name: &[u8]) -> Option<&mut ConsumerGroup> {
+        self.groups.get_mut(name)
+    }
+
+    /// Add a consumer group, returning false if there already is one with the name
+    pub(crate) fn create_group(&mut self, name: &[u8], group: ConsumerGroup) -> bool {
+        if self.groups.contains_key(name) {
+            return false;
+        }
+        self.groups.insert(name.to_vec(), group);
+        true
+    }
+
+    /// Delete a consumer group, returning false if it didn't exist
+    pub(crate) fn remove_group(&mut self, name: &[u8]) -> bool {
+        self.groups.remove(name).is_some()
+    }
+
+    /// Whether any entries from the ID up to the last ID might have been deleted
+    fn has_deletions_after(&self, id: StreamId) -> bool {
+        self.length > 0
+            && self.max_deleted_id != StreamId::MIN
+            && id <= self.max_deleted_id
+            && self.max_deleted_id <= self.last_id
+    }
+
+    /// How many entries were added to the stream up to and including the ID, if that can be
+    /// worked out from the number of entries ever added
+    ///
+    /// It can only be worked out for the IDs at either end of the stream, and only when no
+    /// entries have been deleted from the middle of it.
+    pub(crate) fn entries_added_until(&self, id: StreamId) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        if self.length == 0 && id <= self.last_id {
+            return Some(self.entries_added);
+        }
+        match id.cmp(&self.last_id) {
+            Ordering::Equal => return Some(self.entries_added),
+            Ordering::Greater => return None,
+            Ordering::Less => {}
+        }
+        let first_id = self.first_id();
+        if self.max_deleted_id == StreamId::MIN || self.max_deleted_id < first_id {
+            let before_first = self.entries_added - self.length as u64;
+            match id.cmp(&first_id) {
+                Ordering::Less => return Some(before_first),
+                Ordering::Equal => return Some(before_first + 1),
+                Ordering::Greater => {}
+            }
+        }
+        None
+    }
+
+    /// How many entries a group has left to read, if that can be known
+    pub(crate) fn lag(&self, group: &ConsumerGroup) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        let entries_read = match group.entries_read {
+            Some(read) if !self.has_deletions_after(group.last_id) => read,
+            _ => self.entries_added_until(group.last_id)?,
+        };
+        Some(self.entries_added.saturating_sub(entries_read))
+    }
+
+    /// Deliver the entries after the last ID of a group to one of its consumers, up to count of
+    /// them, or all of them if count is zero
+    ///
+    /// The entries become pending for the consumer, unless no_ack is set. Returns `None` if there
+    /// is no such group.
+    pub(crate) fn read_group(
+        &mut self,
+        name: &[u8],
+        consumer: &[u8],
+        count: usize,
+        no_ack: bool,
+        now: u128,
+    ) -> Option<Vec<(StreamId, StreamFields)>> {
+        let group = self.groups.get_mut(name)?;
+        group.consumer_mut(consumer, now);
+        let count = if count == 0 { usize::MAX } else { count };
+        let entries: Vec<(StreamId, StreamFields)> = match group.last_id.next() {
+            Some(start) => self
+                .range(start, StreamId::MAX)
+                .take(count)
+                .map(|(id, fields)| (id, fields.clone()))
+                .collect(),
+            None => Vec::new(),
+        };
+
+        for (id, _) in &entries {
+            // The count of entries read is kept up to date for as long as it can be
+            let entries_read = match self.groups[name].entries_read {
+                Some(read) if !self.has_deletions_after(*id) => Some(read + 1),
+                _ => self.entries_added_until(*id),
+            };
+            let group = self.groups.get_mut(name).unwrap();
+            group.entries_read = entries_read;
+            group.last_id = *id;
+            if !no_ack {
+                let entry = group.assign(*id, consumer, now);
+                entry.delivery_time = now;
+                entry.delivery_count = 1;
+            }
+        }
+        if !entries.is_empty() {
+            let group = self.groups.get_mut(name).unwrap();
+            group.consumer_mut(consumer, now).active_time = Some(now);
+        }
+        Some(entries)
+    }
+
+    /// Deliver the entries that are pending for a consumer of a group again, starting after the
+    /// ID, up to count of them, or all of them if count is zero
+    ///
+    /// Entries that have been deleted since they were delivered have no fields. Returns `None` if
+    /// there is no such group.
+    pub(crate) fn read_group_history(
+        &mut self,
+        name: &[u8],
+        consumer: &[u8],
+        after: StreamId,
+        count: usize,
+        now: u128,
+    ) -> Option<Vec<(StreamId, Option<StreamFields>)>> {
+        let group = self.groups.get_mut(name)?;
+        let consumer = group.consumer_mut(consumer, now);
+        let count = if count == 0 { usize::MAX } else { count };
+        let ids: Vec<StreamId> = match after.next() {
+            Some(start) => consumer
+                .pending
+                .range(start..)
+                .take(count)
+                .copied()
+                .collect(),
+            None => Vec::new(),
+        };
+        for id in &ids {
+            if let Some(entry) = group.pending.get_mut(id) {
+                entry.delivery_time = now;
+                entry.delivery_count += 1;
+            }
+        }
+        Some(
+            ids.into_iter()
+                .map(|id| (id, self.get(id).cloned()))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(value: &str) -> StreamFields {
+        vec![(b"field".to_vec(), value.as_bytes().to_vec())]
+    }
+
+    fn ids(stream: &Stream) -> Vec<u64> {
+        stream.iter().map(|(id, _)| id.ms).collect()
+    }
+
+    /// A stream with the entries 1-0 to count-0, in nodes of three entries
+    fn stream_of(count: u64) -> Stream {
+        let limits = StreamNodeLimits {
+            max_entries: 3,
+            max_bytes: 0,
+        };
+        let mut stream = Stream::new();
+        for ms in 1..=count {
+            stream.append(StreamId::new(ms, 0), fields("value"), &limits);
+        }
+        stream
+    }
+
+    #[test]
+    fn test_next_and_prev_id() {
+        assert_eq!(StreamId::new(1, 5).next(), Some(StreamId::new(1, 6)));
+        assert_eq!(StreamId::new(1, u64::MAX).next(), Some(StreamId::new(2, 0)));
+        assert_eq!(StreamId::MAX.next(), None);
+        assert_eq!(StreamId::new(2, 0).prev(), Some(StreamId::new(1, u64::MAX)));
+        assert_eq!(StreamId::MIN.prev(), None);
+        assert_eq!(
+            StreamId::new(1526919030474, 55).to_string(),
+            "1526919030474-55"
+        );
+    }
+
+    #[test]
+    fn test_next_id_never_goes_backwards() {
+        let mut stream = Stream::new();
+        assert_eq!(stream.next_id(100), Some(StreamId::new(100, 0)));
+        stream.append(
+            StreamId::new(100, 0),
+            fields("a"),
+            &StreamNodeLimits::default(),
+        );
+        assert_eq!(stream.next_id(100), Some(StreamId::new(100, 1)));
+        assert_eq!(stream.next_id(50), Some(StreamId::new(100, 1)));
+        assert_eq!(stream.next_id(101), Some(StreamId::new(101, 0)));
+    }
+
+    #[test]
+    fn test_range_across_nodes() {
+        let stream = stream_of(10);
+        assert_eq!(stream.nodes().count(), 4);
+        assert_eq!(ids(&stream), (1..=10).collect::<Vec<_>>());
+
+        let range: Vec<u64> = stream
+            .range(StreamId::new(3, 0), StreamId::new(7, 0))
+            .map(|(id, _)| id.ms)
+            .collect();
+        assert_eq!(range, vec![3, 4, 5, 6, 7]);
+        let reversed: Vec<u64> = stream
+            .range(StreamId::new(3, 1), StreamId::MAX)
+            .rev()
+            .take(2)
+            .map(|(id, _)| id.ms)
+            .collect();
+        assert_eq!(reversed, vec![10, 9]);
+        assert_eq!(
+            stream
+                .range(StreamId::new(5, 0), StreamId::new(4, 0))
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_remove_keeps_last_id() {
+        let mut stream = stream_of(4);
+        assert!(stream.remove(StreamId::new(4, 0)));
+        assert!(!stream.remove(StreamId::new(4, 0)));
+        assert!(stream.remove(StreamId::new(2, 0)));
+        assert_eq!(ids(&stream), vec![1, 3]);
+        assert_eq!(stream.len(), 2);
+        assert_eq!(stream.last_id(), StreamId::new(4, 0));
+        assert_eq!(stream.max_deleted_id(), StreamId::new(4, 0));
+        assert_eq!(stream.entries_added(), 4);
+    }
+
+    #[test]
+    fn test_exact_trim() {
+        let mut stream = stream_of(10);
+        assert_eq!(stream.trim(Trim::MaxLen(5), false, 0), 5);
+        assert_eq!(ids(&stream), vec![6, 7, 8, 9, 10]);
+        assert_eq!(stream.trim(Trim::MinId(StreamId::new(8, 0)), false, 0), 2);
+        assert_eq!(ids(&stream), vec![8, 9, 10]);
+        assert_eq!(stream.trim(Trim::MaxLen(0), false, 0), 3);
+        assert_eq!(stream.len(), 0);
+    }
+
+    #[test]
+    fn test_approximate_trim_removes_whole_nodes() {
+        let mut stream = stream_of(10);
+        // Only the first node can go without leaving fewer than 5 entries
+        assert_eq!(stream.trim(Trim::MaxLen(5), true, 0), 3);
+        assert_eq!(stream.len(), 7);
+        assert_eq!(stream.trim(Trim::MinId(StreamId::new(9, 0)), true, 0), 3);
+        assert_eq!(ids(&stream), vec![7, 8, 9, 10]);
+
+        let mut stream = stream_of(10);
+        assert_eq!(stream.trim(Trim::MaxLen(0), true, 7), 6);
+        assert_eq!(stream.len(), 4);
+    }
+
+    #[test]
+    fn test_read_group_delivers_each_entry_once() {
+        let mut stream = stream_of(5);
+        stream.create_group(b"group", ConsumerGroup::new(StreamId::MIN, Some(0)));
+
+        let read = stream
+            .read_group(b"group", b"alice", 2, false, 100)
+            .unwrap();
+        assert_eq!(
+            read.iter().map(|(id, _)| id.ms).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        let read = stream.read_group(b"group", b"bob", 0, false, 200).unwrap();
+        assert_eq!(
+            read.iter().map(|(id, _)| id.ms).collect::<Vec<_>>(),
+            vec![3, 4, 5]
+        );
+        assert!(stream
+            .read_group(b"group", b"bob", 0, false, 300)
+            .unwrap()
+            .is_empty());
+        assert!(stream
+            .read_group(b"missing", b"bob", 0, false, 300)
+            .is_none());
+
+        let group = stream.group(b"group").unwrap();
+        assert_eq!(group.last_id, StreamId::new(5, 0));
+        assert_eq!(group.entries_read, Some(5));
+        assert_eq!(group.pending.len(), 5);
+        assert_eq!(group.consumers[&b"alice"[..]].pending.len(), 2);
+        assert_eq!(group.consumers[&b"bob"[..]].seen_time, 300);
+        assert_eq!(group.consumers[&b"bob"[..]].active_time, Some(200));
+    }
+
+    #[test]
+    fn test_history_and_ack() {
+        let mut stream = stream_of(3);
+        stream.create_group(b"group", ConsumerGroup::new(StreamId::MIN, Some(0)));
+        stream.read_group(b"group", b"alice", 0, false, 100);
+        stream.remove(StreamId::new(2, 0));
+
+        let history = stream
+            .read_group_history(b"group", b"alice", StreamId::MIN, 0, 200)
+            .unwrap();
+        assert_eq!(history.len(), 3);
+        assert!(history[1].1.is_none());
+        let group = stream.group_mut(b"group").unwrap();
+        assert_eq!(group.pending[&StreamId::new(1, 0)].delivery_count, 2);
+        assert_eq!(group.pending[&StreamId::new(1, 0)].delivery_time, 200);
+
+        assert!(group.ack(StreamId::new(1, 0)));
+        assert!(!group.ack(StreamId::new(1, 0)));
+        group.assign(StreamId::new(3, 0), b"bob", 300);
+        assert_eq!(group.consumers[&b"alice"[..]].pending.len(), 1);
+        assert_eq!(group.remove_consumer(b"bob"), Some(1));
+        assert_eq!(group.pending.len(), 1);
+    }
+
+    #[test]
+    fn test_lag() {
+        let mut stream = stream_of(5);
+        let group = ConsumerGroup::new(StreamId::MIN, Some(0));
+        assert_eq!(stream.lag(&group), Some(5));
+        // An arbitrary ID can be worked out at the ends of the stream only
+        assert_eq!(
+            stream.lag(&ConsumerGroup::new(StreamId::new(5, 0), None)),
+            Some(0)
+        );
+        assert_eq!(
+            stream.lag(&ConsumerGroup::new(StreamId::new(3, 0), None)),
+            None
+        );
+
+        stream.remove(StreamId::new(3, 0));
+        let group = ConsumerGroup::new(StreamId::new(1, 0), Some(1));
+        assert_eq!(stream.lag(&group), None);
+        assert_eq!(stream.entries_added_until(StreamId::new(1, 0)), None);
+        stream.trim(Trim::MaxLen(0), false, 0);
+        assert_eq!(stream.entries_added_until(StreamId::new(4, 0)), Some(5));
+    }
+}
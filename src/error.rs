@@ -20,6 +20,11 @@ pub enum RustisError {
     ReadError,
     #[error("Client error: {0}")]
     ClientError(String),
+    /// A client error with a code other than ERR, such as NOGROUP
+    #[error("Client error: {0} {1}")]
+    ClientErrorWithCode(&'static str, String),
+    #[error("Operation against a key holding the wrong kind of value")]
+    WrongType,
     #[error("Poll error: {0}")]
     PollError(#[from] nix::Error),
     #[error("Parse int error")]
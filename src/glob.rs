@@ -0,0 +1,135 @@
+/// Match a string against a glob-style pattern, the same way as Redis does for KEYS and PSUBSCRIBE
+///
+/// Supported are:
+///     * `*` to match any number of any characters
+///     * `?` to match any single character
+///     * `[abc]` to match one of the characters, `[^abc]` to match any character but them, and
+///       `[a-c]` to match a range of characters
+///     * `\` to escape the next character, so it is matched literally
+pub(crate) fn matches(pattern: &[u8], string: &[u8]) -> bool {
+    let mut p = 0;
+    let mut s = 0;
+
+    while p < pattern.len() {
+        match pattern[p] {
+            b'*' => {
+                // Consecutive stars are the same as one
+                while pattern.get(p + 1) == Some(&b'*') {
+                    p += 1;
+                }
+                if p + 1 == pattern.len() {
+                    return true;
+                }
+                return (s..=string.len())
+                    .any(|start| matches(&pattern[p + 1..], &string[start..]));
+            }
+            b'?' => {
+                if s == string.len() {
+                    return false;
+                }
+                s += 1;
+            }
+            b'[' => {
+                let Some(&c) = string.get(s) else {
+                    return false;
+                };
+                p += 1;
+                let negate = pattern.get(p) == Some(&b'^');
+                if negate {
+                    p += 1;
+                }
+                let mut matched = false;
+                loop {
+                    match pattern.get(p) {
+                        // An unterminated class ends with the pattern
+                        None => {
+                            p -= 1;
+                            break;
+                        }
+                        Some(b']') => break,
+                        Some(b'\\') if p + 1 < pattern.len() => {
+                            p += 1;
+                            matched |= pattern[p] == c;
+                        }
+                        Some(&start)
+                            if pattern.get(p + 1) == Some(&b'-') && p + 2 < pattern.len() =>
+                        {
+                            let end = pattern[p + 2];
+                            let (low, high) = if start > end {
+                                (end, start)
+                            } else {
+                                (start, end)
+                            };
+                            p += 2;
+                            matched |= (low..=high).contains(&c);
+                        }
+                        Some(&other) => matched |= other == c,
+                    }
+                    p += 1;
+                }
+                if matched == negate {
+                    return false;
+                }
+                s += 1;
+            }
+            b'\\' if p + 1 < pattern.len() => {
+                p += 1;
+                if string.get(s) != Some(&pattern[p]) {
+                    return false;
+                }
+                s += 1;
+            }
+            c => {
+                if string.get(s) != Some(&c) {
+                    return false;
+                }
+                s += 1;
+            }
+        }
+        p += 1;
+    }
+
+    s == string.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_literals_and_wildcards() {
+        assert!(matches(b"news", b"news"));
+        assert!(!matches(b"news", b"new"));
+        assert!(!matches(b"news", b"newss"));
+        assert!(matches(b"*", b""));
+        assert!(matches(b"news.*", b"news.tech"));
+        assert!(matches(b"news.*", b"news."));
+        assert!(!matches(b"news.*", b"sport.tech"));
+        assert!(matches(b"*.tech", b"news.tech"));
+        assert!(matches(b"n**s*h", b"news.tech"));
+        assert!(matches(b"h?llo", b"hello"));
+        assert!(!matches(b"h?llo", b"hllo"));
+    }
+
+    #[test]
+    fn test_classes() {
+        assert!(matches(b"h[ae]llo", b"hello"));
+        assert!(matches(b"h[ae]llo", b"hallo"));
+        assert!(!matches(b"h[ae]llo", b"hillo"));
+        assert!(matches(b"h[^e]llo", b"hallo"));
+        assert!(!matches(b"h[^e]llo", b"hello"));
+        assert!(matches(b"h[a-b]llo", b"hbllo"));
+        assert!(matches(b"h[b-a]llo", b"hallo"));
+        assert!(!matches(b"h[a-b]llo", b"hcllo"));
+        assert!(matches(b"h[\\]]llo", b"h]llo"));
+        assert!(!matches(b"h[a]llo", b"h"));
+    }
+
+    #[test]
+    fn test_escapes() {
+        assert!(matches(b"h\\*llo", b"h*llo"));
+        assert!(!matches(b"h\\*llo", b"hello"));
+        assert!(matches(b"h\\?", b"h?"));
+        assert!(!matches(b"h\\?", b"ha"));
+    }
+}
@@ -1,11 +1,20 @@
 #[macro_use]
 mod error;
+mod cluster;
 mod config;
 mod connection;
+mod crc64;
 mod database;
+mod glob;
+mod lua;
+mod notify;
 mod parsers;
+mod random;
 mod resp;
 mod server;
+mod sha1;
+mod sha256;
+mod watch;
 
 pub use config::Config;
 pub use error::{Result, RustisError};
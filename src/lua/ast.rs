@@ -0,0 +1,146 @@
+//! The syntax tree of a chunk, with names already resolved to locals, upvalues or globals
+//!
+//! Every local variable of a function gets its own slot in the function's frame, so blocks don't
+//! reuse slots. A variable is given a fresh cell each time its declaration runs, which is what
+//! makes closures created in a loop capture a different variable on each iteration.
+
+use std::rc::Rc;
+
+pub(crate) struct FunctionProto {
+    /// Parameters take up the first slots
+    pub(super) params: usize,
+    pub(super) is_vararg: bool,
+    /// The names of all slots, for error messages
+    pub(super) slot_names: Vec<Rc<str>>,
+    pub(super) upvalues: Vec<Upvalue>,
+    pub(super) upvalue_names: Vec<Rc<str>>,
+    pub(super) body: Block,
+}
+
+/// Where a closure gets an upvalue from when it's created
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Upvalue {
+    /// A local of the enclosing function
+    Local(usize),
+    /// An upvalue of the enclosing function
+    Upvalue(usize),
+}
+
+pub(super) type Block = Vec<Stmt>;
+
+pub(super) enum Stmt {
+    Local {
+        slots: Vec<usize>,
+        exprs: Vec<Expr>,
+        line: u32,
+    },
+    Assign {
+        targets: Vec<Target>,
+        exprs: Vec<Expr>,
+        line: u32,
+    },
+    Call(Expr, u32),
+    Do(Block),
+    While {
+        cond: Expr,
+        body: Block,
+        line: u32,
+    },
+    Repeat {
+        body: Block,
+        cond: Expr,
+        line: u32,
+    },
+    If {
+        branches: Vec<(Expr, Block)>,
+        otherwise: Option<Block>,
+        line: u32,
+    },
+    NumericFor {
+        slot: usize,
+        start: Expr,
+        limit: Expr,
+        step: Option<Expr>,
+        body: Block,
+        line: u32,
+    },
+    GenericFor {
+        slots: Vec<usize>,
+        exprs: Vec<Expr>,
+        body: Block,
+        line: u32,
+    },
+    LocalFunction {
+        slot: usize,
+        function: Rc<FunctionProto>,
+        line: u32,
+    },
+    Return(Vec<Expr>, u32),
+    Break,
+}
+
+pub(super) enum Target {
+    Local(usize),
+    Upvalue(usize),
+    Global(Rc<[u8]>),
+    Index(Expr, Expr),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    Concat,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum UnOp {
+    Neg,
+    Not,
+    Len,
+}
+
+pub(super) enum Expr {
+    Nil,
+    True,
+    False,
+    Number(f64),
+    String(Rc<[u8]>),
+    Vararg,
+    Function(Rc<FunctionProto>),
+    Local(usize),
+    Upvalue(usize),
+    Global(Rc<[u8]>),
+    Index(Box<Expr>, Box<Expr>),
+    Call(Box<Expr>, Vec<Expr>),
+    Method(Box<Expr>, Rc<[u8]>, Vec<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Unary(UnOp, Box<Expr>),
+    Table(Vec<Field>),
+    /// A parenthesized expression, which only keeps the first value of calls and varargs
+    Paren(Box<Expr>),
+}
+
+pub(super) enum Field {
+    Positional(Expr),
+    Keyed(Expr, Expr),
+}
+
+impl Expr {
+    /// Whether the expression can result in multiple values, when it's last in a list
+    pub(super) fn is_multi(&self) -> bool {
+        matches!(self, Expr::Call(..) | Expr::Method(..) | Expr::Vararg)
+    }
+}
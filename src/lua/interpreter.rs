@@ -0,0 +1,874 @@
+use super::{
+    ast::{BinOp, Block, Expr, Field, FunctionProto, Stmt, Target, UnOp, Upvalue},
+    stdlib,
+    value::{Function, LuaError, LuaResult, Table, TableRef, Value},
+};
+use std::{cell::RefCell, rc::Rc};
+
+/// How deeply functions can call each other
+const MAX_DEPTH: usize = 200;
+
+/// How many statements run between calls to `Host::hook`
+const HOOK_INTERVAL: u64 = 10000;
+
+type Cell = Rc<RefCell<Value>>;
+
+/// What the interpreter is embedded in, which scripts interact with through native functions
+pub(crate) trait Host {
+    /// Run a command for `redis.call` and `redis.pcall`, with the reply converted to a value
+    fn call(&mut self, args: Vec<Vec<u8>>) -> LuaResult<Value>;
+
+    /// Called periodically while a script runs, which can stop it by returning an error
+    fn hook(&mut self) -> LuaResult<()>;
+}
+
+/// A function along with the variables it captured
+pub(crate) struct Closure {
+    proto: Rc<FunctionProto>,
+    upvalues: Vec<Cell>,
+}
+
+/// The variables of a running function
+struct Frame<'a> {
+    closure: &'a Closure,
+    slots: Vec<Cell>,
+    varargs: Vec<Value>,
+}
+
+/// How a statement finished
+enum Flow {
+    Normal,
+    Break,
+    Return(Vec<Value>),
+}
+
+/// A Lua interpreter, running a script on behalf of a host
+pub(crate) struct Lua<'h> {
+    globals: TableRef,
+    /// The metatable shared by all strings, which makes `s:upper()` work
+    string_metatable: TableRef,
+    host: &'h mut dyn Host,
+    depth: usize,
+    steps: u64,
+    /// The line of the statement running, for error messages
+    line: u32,
+    chunk_name: Rc<str>,
+}
+
+impl<'h> Lua<'h> {
+    /// Make an interpreter with the standard libraries loaded
+    pub(crate) fn new(host: &'h mut dyn Host, chunk_name: &str) -> Self {
+        let mut lua = Lua {
+            globals: Rc::new(RefCell::new(Table::new())),
+            string_metatable: Rc::new(RefCell::new(Table::new())),
+            host,
+            depth: 0,
+            steps: 0,
+            line: 0,
+            chunk_name: Rc::from(chunk_name),
+        };
+        stdlib::open(&mut lua);
+        lua
+    }
+
+    pub(crate) fn host(&mut self) -> &mut dyn Host {
+        self.host
+    }
+
+    pub(crate) fn globals(&self) -> &TableRef {
+        &self.globals
+    }
+
+    pub(crate) fn set_global(&mut self, name: &str, value: Value) {
+        self.globals.borrow_mut().set_str(name, value);
+    }
+
+    pub(crate) fn set_string_metatable(&mut self, metatable: Table) {
+        self.string_metatable = Rc::new(RefCell::new(metatable));
+    }
+
+    /// Make the globals and the libraries read only, and reading missing globals an error
+    pub(crate) fn protect_globals(&mut self) {
+        let mut metatable = Table::new();
+        metatable.set_str(
+            "__index",
+            Value::native("__index", |lua, args| {
+                let name = args.get(1).cloned().unwrap_or_default().to_display();
+                Err(lua.error(format!(
+                    "Script attempted to access nonexistent global variable '{}'",
+                    String::from_utf8_lossy(&name)
+                )))
+            }),
+        );
+        let mut globals = self.globals.borrow_mut();
+        globals.metatable = Some(Rc::new(RefCell::new(metatable)));
+        globals.readonly = true;
+        let mut key = Value::Nil;
+        while let Ok(Some((k, v))) = globals.next(&key) {
+            if let Value::Table(table) = &v {
+                if !Rc::ptr_eq(table, &self.globals) {
+                    table.borrow_mut().readonly = true;
+                }
+            }
+            key = k;
+        }
+    }
+
+    /// An error with the position of the running statement, as raised by `error`
+    pub(crate) fn error(&self, message: impl AsRef<str>) -> LuaError {
+        LuaError {
+            value: Value::string(format!(
+                "{}:{}: {}",
+                self.chunk_name,
+                self.line,
+                message.as_ref()
+            )),
+            line: self.line,
+            fatal: false,
+        }
+    }
+
+    /// The error raised by `error` with a value that isn't a string
+    pub(crate) fn raise(&self, value: Value) -> LuaError {
+        LuaError {
+            value,
+            line: self.line,
+            fatal: false,
+        }
+    }
+
+    /// Run a compiled chunk
+    pub(crate) fn execute(&mut self, chunk: &Rc<FunctionProto>) -> LuaResult<Vec<Value>> {
+        let closure = Rc::new(Closure {
+            proto: Rc::clone(chunk),
+            upvalues: Vec::new(),
+        });
+        self.call(&Value::Function(Function::Lua(closure)), Vec::new())
+    }
+
+    /// Call a function, or a value with a `__call` metamethod
+    pub(crate) fn call(&mut self, function: &Value, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+        self.call_described(function, args, None)
+    }
+
+    fn call_described(
+        &mut self,
+        function: &Value,
+        mut args: Vec<Value>,
+        description: Option<String>,
+    ) -> LuaResult<Vec<Value>> {
+        match function {
+            Value::Function(Function::Lua(closure)) => self.call_closure(closure, args),
+            Value::Function(Function::Native(native)) => {
+                self.enter()?;
+                let result = (native.func)(self, args);
+                self.depth -= 1;
+                result
+            }
+            _ => match self.metamethod(function, "__call") {
+                Some(handler) => {
+                    args.insert(0, function.clone());
+                    self.call(&handler, args)
+                }
+                None => Err(self.type_error("call", function, description)),
+            },
+        }
+    }
+
+    fn enter(&mut self) -> LuaResult<()> {
+        if self.depth >= MAX_DEPTH {
+            return Err(self.error("stack overflow"));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn call_closure(&mut self, closure: &Closure, mut args: Vec<Value>) -> LuaResult<Vec<Value>> {
+        self.enter()?;
+        let proto = &closure.proto;
+        let varargs = if proto.is_vararg && args.len() > proto.params {
+            args.split_off(proto.params)
+        } else {
+            Vec::new()
+        };
+        args.resize(proto.params, Value::Nil);
+        let mut slots: Vec<Cell> = args
+            .into_iter()
+            .map(|value| Rc::new(RefCell::new(value)))
+            .collect();
+        slots.resize_with(proto.slot_names.len(), Default::default);
+        let mut frame = Frame {
+            closure,
+            slots,
+            varargs,
+        };
+
+        let line = self.line;
+        let result = self.exec_block(&proto.body, &mut frame);
+        self.line = line;
+        self.depth -= 1;
+        match result? {
+            Flow::Return(values) => Ok(values),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    fn step(&mut self) -> LuaResult<()> {
+        self.steps += 1;
+        if self.steps.is_multiple_of(HOOK_INTERVAL) {
+            let line = self.line;
+            self.host.hook().map_err(|mut e| {
+                if e.line == 0 {
+                    e.line = line;
+                }
+                e
+            })?;
+        }
+        Ok(())
+    }
+
+    fn exec_block(&mut self, block: &Block, frame: &mut Frame) -> LuaResult<Flow> {
+        for stmt in block {
+            match self.exec(stmt, frame)? {
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn exec(&mut self, stmt: &Stmt, frame: &mut Frame) -> LuaResult<Flow> {
+        self.step()?;
+        match stmt {
+            Stmt::Local { slots, exprs, line } => {
+                self.line = *line;
+                let values = self.eval_list(exprs, slots.len(), frame)?;
+                for (&slot, value) in slots.iter().zip(values) {
+                    frame.slots[slot] = Rc::new(RefCell::new(value));
+                }
+            }
+            Stmt::Assign {
+                targets,
+                exprs,
+                line,
+            } => {
+                self.line = *line;
+                self.assign(targets, exprs, frame)?;
+            }
+            Stmt::Call(expr, line) => {
+                self.line = *line;
+                self.eval_multi(expr, frame)?;
+            }
+            Stmt::Do(body) => return self.exec_block(body, frame),
+            Stmt::While { cond, body, line } => loop {
+                self.line = *line;
+                self.step()?;
+                if !self.eval(cond, frame)?.is_truthy() {
+                    break;
+                }
+                match self.exec_block(body, frame)? {
+                    Flow::Normal => {}
+                    Flow::Break => break,
+                    flow => return Ok(flow),
+                }
+            },
+            Stmt::Repeat { body, cond, line } => loop {
+                self.step()?;
+                match self.exec_block(body, frame)? {
+                    Flow::Normal => {}
+                    Flow::Break => break,
+                    flow => return Ok(flow),
+                }
+                self.line = *line;
+                if self.eval(cond, frame)?.is_truthy() {
+                    break;
+                }
+            },
+            Stmt::If {
+                branches,
+                otherwise,
+                line,
+            } => {
+                self.line = *line;
+                for (cond, body) in branches {
+                    if self.eval(cond, frame)?.is_truthy() {
+                        return self.exec_block(body, frame);
+                    }
+                }
+                if let Some(body) = otherwise {
+                    return self.exec_block(body, frame);
+                }
+            }
+            Stmt::NumericFor {
+                slot,
+                start,
+                limit,
+                step,
+                body,
+                line,
+            } => {
+                self.line = *line;
+                let start = self.eval(start, frame)?.to_number();
+                let start =
+                    start.ok_or_else(|| self.error("'for' initial value must be a number"))?;
+                let limit = self.eval(limit, frame)?.to_number();
+                let limit = limit.ok_or_else(|| self.error("'for' limit must be a number"))?;
+                let step = match step {
+                    Some(step) => self.eval(step, frame)?.to_number(),
+                    None => Some(1.0),
+                };
+                let step = step.ok_or_else(|| self.error("'for' step must be a number"))?;
+
+                let mut i = start;
+                while (step > 0.0 && i <= limit) || (step <= 0.0 && i >= limit) {
+                    self.step()?;
+                    frame.slots[*slot] = Rc::new(RefCell::new(Value::Number(i)));
+                    match self.exec_block(body, frame)? {
+                        Flow::Normal => {}
+                        Flow::Break => break,
+                        flow => return Ok(flow),
+                    }
+                    i += step;
+                }
+            }
+            Stmt::GenericFor {
+                slots,
+                exprs,
+                body,
+                line,
+            } => {
+                self.line = *line;
+                let mut values = self.eval_list(exprs, 3, frame)?.into_iter();
+                let (function, state) = (values.next().unwrap(), values.next().unwrap());
+                let mut control = values.next().unwrap();
+                loop {
+                    self.step()?;
+                    self.line = *line;
+                    let mut results = self.call(&function, vec![state.clone(), control.clone()])?;
+                    results.resize(slots.len(), Value::Nil);
+                    if results[0].is_nil() {
+                        break;
+                    }
+                    control = results[0].clone();
+                    for (&slot, value) in slots.iter().zip(results) {
+                        frame.slots[slot] = Rc::new(RefCell::new(value));
+                    }
+                    match self.exec_block(body, frame)? {
+                        Flow::Normal => {}
+                        Flow::Break => break,
+                        flow => return Ok(flow),
+                    }
+                }
+            }
+            Stmt::LocalFunction {
+                slot,
+                function,
+                line,
+            } => {
+                self.line = *line;
+                // The closure captures its own variable, so that it can call itself
+                frame.slots[*slot] = Rc::default();
+                let closure = self.closure(function, frame);
+                *frame.slots[*slot].borrow_mut() = closure;
+            }
+            Stmt::Return(exprs, line) => {
+                self.line = *line;
+                return Ok(Flow::Return(self.eval_all(exprs, frame)?));
+            }
+            Stmt::Break => return Ok(Flow::Break),
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn assign(&mut self, targets: &[Target], exprs: &[Expr], frame: &mut Frame) -> LuaResult<()> {
+        // Tables and keys are evaluated before the values, as the Lua compiler does
+        let mut places = Vec::with_capacity(targets.len());
+        for target in targets {
+            places.push(match target {
+                Target::Index(table, key) => Some((
+                    self.eval(table, frame)?,
+                    self.eval(key, frame)?,
+                    describe(table, frame),
+                )),
+                _ => None,
+            });
+        }
+        let values = self.eval_list(exprs, targets.len(), frame)?;
+        for ((target, place), value) in targets.iter().zip(places).zip(values) {
+            match target {
+                Target::Local(slot) => *frame.slots[*slot].borrow_mut() = value,
+                Target::Upvalue(i) => *frame.closure.upvalues[*i].borrow_mut() = value,
+                Target::Global(name) => {
+                    let globals = Value::Table(Rc::clone(&self.globals));
+                    self.set_index(&globals, Value::String(Rc::clone(name)), value, None)?;
+                }
+                Target::Index(..) => {
+                    let (table, key, description) = place.unwrap();
+                    self.set_index(&table, key, value, description)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn closure(&self, proto: &Rc<FunctionProto>, frame: &Frame) -> Value {
+        let upvalues = proto
+            .upvalues
+            .iter()
+            .map(|upvalue| match upvalue {
+                Upvalue::Local(slot) => Rc::clone(&frame.slots[*slot]),
+                Upvalue::Upvalue(i) => Rc::clone(&frame.closure.upvalues[*i]),
+            })
+            .collect();
+        Value::Function(Function::Lua(Rc::new(Closure {
+            proto: Rc::clone(proto),
+            upvalues,
+        })))
+    }
+
+    /// Evaluate expressions, expanding the last one if it has multiple values
+    fn eval_all(&mut self, exprs: &[Expr], frame: &mut Frame) -> LuaResult<Vec<Value>> {
+        let mut values = Vec::with_capacity(exprs.len());
+        for (i, expr) in exprs.iter().enumerate() {
+            if i == exprs.len() - 1 {
+                values.extend(self.eval_multi(expr, frame)?);
+            } else {
+                values.push(self.eval(expr, frame)?);
+            }
+        }
+        Ok(values)
+    }
+
+    /// Evaluate expressions, adjusted to a number of values
+    fn eval_list(
+        &mut self,
+        exprs: &[Expr],
+        count: usize,
+        frame: &mut Frame,
+    ) -> LuaResult<Vec<Value>> {
+        let mut values = self.eval_all(exprs, frame)?;
+        values.resize(count, Value::Nil);
+        Ok(values)
+    }
+
+    /// Evaluate an expression that can result in multiple values
+    fn eval_multi(&mut self, expr: &Expr, frame: &mut Frame) -> LuaResult<Vec<Value>> {
+        match expr {
+            Expr::Vararg => Ok(frame.varargs.clone()),
+            Expr::Call(function, args) => {
+                let function_value = self.eval(function, frame)?;
+                let args = self.eval_all(args, frame)?;
+                let description = describe(function, frame);
+                self.call_described(&function_value, args, description)
+            }
+            Expr::Method(object, name, args) => {
+                let object = self.eval(object, frame)?;
+                let key = Value::String(Rc::clone(name));
+                let function = self.index(&object, &key, None)?;
+                let mut values = vec![object];
+                values.extend(self.eval_all(args, frame)?);
+                let description = format!("method '{}'", String::from_utf8_lossy(name));
+                self.call_described(&function, values, Some(description))
+            }
+            expr => Ok(vec![self.eval(expr, frame)?]),
+        }
+    }
+
+    fn eval(&mut self, expr: &Expr, frame: &mut Frame) -> LuaResult<Value> {
+        Ok(match expr {
+            Expr::Nil => Value::Nil,
+            Expr::True => Value::Boolean(true),
+            Expr::False => Value::Boolean(false),
+            Expr::Number(n) => Value::Number(*n),
+            Expr::String(s) => Value::String(Rc::clone(s)),
+            Expr::Vararg => frame.varargs.first().cloned().unwrap_or_default(),
+            Expr::Function(proto) => self.closure(proto, frame),
+            Expr::Local(slot) => frame.slots[*slot].borrow().clone(),
+            Expr::Upvalue(i) => frame.closure.upvalues[*i].borrow().clone(),
+            Expr::Global(name) => {
+                let globals = Value::Table(Rc::clone(&self.globals));
+                self.index(&globals, &Value::String(Rc::clone(name)), None)?
+            }
+            Expr::Index(table, key) => {
+                let table_value = self.eval(table, frame)?;
+                let key = self.eval(key, frame)?;
+                self.index(&table_value, &key, describe(table, frame))?
+            }
+            Expr::Call(..) | Expr::Method(..) => self
+                .eval_multi(expr, frame)?
+                .into_iter()
+                .next()
+                .unwrap_or_default(),
+            Expr::Paren(expr) => self.eval(expr, frame)?,
+            Expr::And(left, right) => {
+                let left = self.eval(left, frame)?;
+                if !left.is_truthy() {
+                    return Ok(left);
+                }
+                self.eval(right, frame)?
+            }
+            Expr::Or(left, right) => {
+                let left = self.eval(left, frame)?;
+                if left.is_truthy() {
+                    return Ok(left);
+                }
+                self.eval(right, frame)?
+            }
+            Expr::Binary(op, left_expr, right_expr) => {
+                let left = self.eval(left_expr, frame)?;
+                let right = self.eval(right_expr, frame)?;
+                self.binary(*op, left, right, left_expr, right_expr, frame)?
+            }
+            Expr::Unary(op, operand_expr) => {
+                let operand = self.eval(operand_expr, frame)?;
+                match op {
+                    UnOp::Not => Value::Boolean(!operand.is_truthy()),
+                    UnOp::Neg => match operand.to_number() {
+                        Some(n) => Value::Number(-n),
+                        None => {
+                            let description = describe(operand_expr, frame);
+                            return Err(self.type_error(
+                                "perform arithmetic on",
+                                &operand,
+                                description,
+                            ));
+                        }
+                    },
+                    UnOp::Len => match &operand {
+                        Value::String(s) => Value::Number(s.len() as f64),
+                        Value::Table(t) => Value::Number(t.borrow().len() as f64),
+                        _ => {
+                            let description = describe(operand_expr, frame);
+                            return Err(self.type_error("get length of", &operand, description));
+                        }
+                    },
+                }
+            }
+            Expr::Table(fields) => self.table(fields, frame)?,
+        })
+    }
+
+    fn table(&mut self, fields: &[Field], frame: &mut Frame) -> LuaResult<Value> {
+        let mut table = Table::new();
+        let mut n = 0.0;
+        for (i, field) in fields.iter().enumerate() {
+            match field {
+                Field::Positional(expr) if i == fields.len() - 1 && expr.is_multi() => {
+                    for value in self.eval_multi(expr, frame)? {
+                        n += 1.0;
+                        table.set(Value::Number(n), value).unwrap();
+                    }
+                }
+                Field::Positional(expr) => {
+                    n += 1.0;
+                    let value = self.eval(expr, frame)?;
+                    table.set(Value::Number(n), value).unwrap();
+                }
+                Field::Keyed(key, value) => {
+                    let key = self.eval(key, frame)?;
+                    let value = self.eval(value, frame)?;
+                    table.set(key, value).map_err(|e| self.error(e))?;
+                }
+            }
+        }
+        Ok(Value::table(table))
+    }
+
+    fn binary(
+        &mut self,
+        op: BinOp,
+        left: Value,
+        right: Value,
+        left_expr: &Expr,
+        right_expr: &Expr,
+        frame: &Frame,
+    ) -> LuaResult<Value> {
+        let arithmetic = |f: fn(f64, f64) -> f64| match (left.to_number(), right.to_number()) {
+            (Some(a), Some(b)) => Ok(Value::Number(f(a, b))),
+            // The error is about the first operand that isn't a number
+            (None, _) => {
+                Err(self.type_error("perform arithmetic on", &left, describe(left_expr, frame)))
+            }
+            _ => Err(self.type_error("perform arithmetic on", &right, describe(right_expr, frame))),
+        };
+        match op {
+            BinOp::Add => arithmetic(|a, b| a + b),
+            BinOp::Sub => arithmetic(|a, b| a - b),
+            BinOp::Mul => arithmetic(|a, b| a * b),
+            BinOp::Div => arithmetic(|a, b| a / b),
+            BinOp::Mod => arithmetic(|a, b| a - (a / b).floor() * b),
+            BinOp::Pow => arithmetic(f64::powf),
+            BinOp::Concat => match (left.to_bytes(), right.to_bytes()) {
+                (Some(a), Some(b)) => Ok(Value::string([&a[..], &b[..]].concat())),
+                (None, _) => Err(self.type_error("concatenate", &left, describe(left_expr, frame))),
+                _ => Err(self.type_error("concatenate", &right, describe(right_expr, frame))),
+            },
+            BinOp::Eq => Ok(Value::Boolean(left.raw_equals(&right))),
+            BinOp::Ne => Ok(Value::Boolean(!left.raw_equals(&right))),
+            BinOp::Lt => self.less_than(&left, &right).map(Value::Boolean),
+            BinOp::Le => self.less_equal(&left, &right).map(Value::Boolean),
+            BinOp::Gt => self.less_than(&right, &left).map(Value::Boolean),
+            BinOp::Ge => self.less_equal(&right, &left).map(Value::Boolean),
+        }
+    }
+
+    pub(crate) fn less_than(&self, left: &Value, right: &Value) -> LuaResult<bool> {
+        match (left, right) {
+            (Value::Number(a), Value::Number(b)) => Ok(a < b),
+            (Value::String(a), Value::String(b)) => Ok(a < b),
+            _ => Err(self.compare_error(left, right)),
+        }
+    }
+
+    fn less_equal(&self, left: &Value, right: &Value) -> LuaResult<bool> {
+        match (left, right) {
+            (Value::Number(a), Value::Number(b)) => Ok(a <= b),
+            (Value::String(a), Value::String(b)) => Ok(a <= b),
+            _ => Err(self.compare_error(left, right)),
+        }
+    }
+
+    fn compare_error(&self, left: &Value, right: &Value) -> LuaError {
+        let (a, b) = (left.type_name(), right.type_name());
+        if a == b {
+            self.error(format!("attempt to compare two {} values", a))
+        } else {
+            self.error(format!("attempt to compare {} with {}", a, b))
+        }
+    }
+
+    /// An error such as "attempt to index global 'x' (a nil value)"
+    fn type_error(&self, operation: &str, value: &Value, description: Option<String>) -> LuaError {
+        match description {
+            Some(description) => self.error(format!(
+                "attempt to {} {} (a {} value)",
+                operation,
+                description,
+                value.type_name()
+            )),
+            None => self.error(format!(
+                "attempt to {} a {} value",
+                operation,
+                value.type_name()
+            )),
+        }
+    }
+
+    fn metamethod(&self, value: &Value, event: &str) -> Option<Value> {
+        let metatable = match value {
+            Value::Table(table) => table.borrow().metatable.clone()?,
+            Value::String(_) => Rc::clone(&self.string_metatable),
+            _ => return None,
+        };
+        let handler = metatable.borrow().get_str(event);
+        (!handler.is_nil()).then_some(handler)
+    }
+
+    /// The metamethod `__tostring` of a value
+    pub(crate) fn to_string_metamethod(&self, value: &Value) -> Option<Value> {
+        match value {
+            Value::Table(_) => self.metamethod(value, "__tostring"),
+            _ => None,
+        }
+    }
+
+    /// Index a value, as `t[k]` does
+    pub(crate) fn index(
+        &mut self,
+        object: &Value,
+        key: &Value,
+        description: Option<String>,
+    ) -> LuaResult<Value> {
+        let mut object = object.clone();
+        for _ in 0..100 {
+            if let Value::Table(table) = &object {
+                let value = table.borrow().get(key);
+                if !value.is_nil() {
+                    return Ok(value);
+                }
+            }
+            let handler = match self.metamethod(&object, "__index") {
+                Some(handler) => handler,
+                None if matches!(object, Value::Table(_)) => return Ok(Value::Nil),
+                None => return Err(self.type_error("index", &object, description)),
+            };
+            if let Value::Function(_) = handler {
+                let values = self.call(&handler, vec![object, key.clone()])?;
+                return Ok(values.into_iter().next().unwrap_or_default());
+            }
+            object = handler;
+        }
+        Err(self.error("loop in gettable"))
+    }
+
+    /// Set a field, as `t[k] = v` does
+    pub(crate) fn set_index(
+        &mut self,
+        object: &Value,
+        key: Value,
+        value: Value,
+        description: Option<String>,
+    ) -> LuaResult<()> {
+        let mut object = object.clone();
+        for _ in 0..100 {
+            let Value::Table(table) = &object else {
+                return Err(self.type_error("index", &object, description));
+            };
+            if table.borrow().readonly {
+                return Err(self.error("Attempt to modify a readonly table"));
+            }
+            let handler = match table.borrow().get(&key).is_nil() {
+                true => self.metamethod(&object, "__newindex"),
+                false => None,
+            };
+            match handler {
+                None => {
+                    let result = table.borrow_mut().set(key, value);
+                    return result.map_err(|e| self.error(e));
+                }
+                Some(handler @ Value::Function(_)) => {
+                    self.call(&handler, vec![object, key, value])?;
+                    return Ok(());
+                }
+                Some(handler) => object = handler,
+            }
+        }
+        Err(self.error("loop in settable"))
+    }
+}
+
+/// How an expression is named in errors, such as "global 'x'"
+fn describe(expr: &Expr, frame: &Frame) -> Option<String> {
+    let proto = &frame.closure.proto;
+    match expr {
+        Expr::Local(slot) => Some(format!("local '{}'", proto.slot_names[*slot])),
+        Expr::Upvalue(i) => Some(format!("upvalue '{}'", proto.upvalue_names[*i])),
+        Expr::Global(name) => Some(format!("global '{}'", String::from_utf8_lossy(name))),
+        Expr::Index(_, key) => match &**key {
+            Expr::String(name) => Some(format!("field '{}'", String::from_utf8_lossy(name))),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua::compile;
+
+    struct TestHost;
+
+    impl Host for TestHost {
+        fn call(&mut self, args: Vec<Vec<u8>>) -> LuaResult<Value> {
+            Ok(Value::Number(args.len() as f64))
+        }
+
+        fn hook(&mut self) -> LuaResult<()> {
+            Ok(())
+        }
+    }
+
+    /// Run a script, returning its results as shown by Debug, or its error message
+    ///
+    /// Scripts run on a thread with a stack as big as the main thread's, which the server runs
+    /// scripts on, so that unoptimized builds can reach the call depth limit.
+    fn run(source: &str) -> Result<Vec<String>, String> {
+        let source = source.to_string();
+        let thread = std::thread::Builder::new().stack_size(8 << 20);
+        let handle = thread.spawn(move || {
+            let chunk = compile(source.as_bytes(), "user_script")?;
+            let mut host = TestHost;
+            let mut lua = Lua::new(&mut host, "user_script");
+            lua.protect_globals();
+            match lua.execute(&chunk) {
+                Ok(values) => Ok(values.iter().map(|v| format!("{:?}", v)).collect()),
+                Err(e) => Err(e.message()),
+            }
+        });
+        handle.unwrap().join().unwrap()
+    }
+
+    #[test]
+    fn test_execute() {
+        assert_eq!(
+            run("return 1 + 2 * 3 ^ 2, 7 % 3, 2 .. 'x'").unwrap(),
+            ["19", "1", "\"2x\""]
+        );
+        assert_eq!(
+            run("local function fib(n) if n < 2 then return n end return fib(n - 1) + fib(n - 2) end\n\
+                 return fib(15)")
+            .unwrap(),
+            ["610"]
+        );
+        assert_eq!(
+            run("local t = {}\n\
+                 for i = 1, 3 do t[#t + 1] = function() return i end end\n\
+                 return t[1]() + t[3]()")
+            .unwrap(),
+            ["4"]
+        );
+        assert_eq!(
+            run("local t = {b = 2, a = 1, 3}\n\
+                 local n = 0\n\
+                 for k, v in pairs(t) do n = n + v end\n\
+                 return n, select('#', unpack({1, 2, 3}))")
+            .unwrap(),
+            ["6", "3"]
+        );
+        assert_eq!(
+            run("local ok, e = pcall(error, {code = 1})\nreturn ok, e.code").unwrap(),
+            ["false", "1"]
+        );
+        assert_eq!(
+            run("return string.format('%5.1f|%-3d|%s', 3.14159, 7, 'x'), ('abc'):upper()").unwrap(),
+            ["\"  3.1|7  |x\"", "\"ABC\""]
+        );
+        assert_eq!(
+            run("return string.gsub('hello world', '(%w+)', '<%1>')").unwrap(),
+            ["\"<hello> <world>\"", "2"]
+        );
+        assert_eq!(
+            run("local t = {3, 1, 2}\ntable.sort(t)\nreturn table.concat(t, ',')").unwrap(),
+            ["\"1,2,3\""]
+        );
+        assert_eq!(
+            run("return cjson.encode({1, 'a', {x = true}}), cjson.decode('[1,{\"a\":null}]')[2].a == cjson.null")
+                .unwrap(),
+            ["\"[1,\\\"a\\\",{\\\"x\\\":true}]\"", "true"]
+        );
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        assert_eq!(
+            run("local x\nreturn x.y").unwrap_err(),
+            "user_script:2: attempt to index local 'x' (a nil value)"
+        );
+        assert_eq!(
+            run("return nosuch()").unwrap_err(),
+            "user_script:1: Script attempted to access nonexistent global variable 'nosuch'"
+        );
+        assert_eq!(
+            run("x = 1").unwrap_err(),
+            "user_script:1: Attempt to modify a readonly table"
+        );
+        assert_eq!(
+            run("string.foo = 1").unwrap_err(),
+            "user_script:1: Attempt to modify a readonly table"
+        );
+        assert_eq!(
+            run("return 1 < 'x'").unwrap_err(),
+            "user_script:1: attempt to compare number with string"
+        );
+        assert_eq!(run("\nerror('boom')").unwrap_err(), "user_script:2: boom");
+        assert_eq!(
+            run("local function f() return f() + 1 end\nreturn f()").unwrap_err(),
+            "user_script:1: stack overflow"
+        );
+        assert_eq!(
+            run("return ('x'):bad()").unwrap_err(),
+            "user_script:1: attempt to call method 'bad' (a nil value)"
+        );
+    }
+}
@@ -0,0 +1,449 @@
+use std::rc::Rc;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Token {
+    Name(Rc<str>),
+    Number(f64),
+    String(Rc<[u8]>),
+    And,
+    Break,
+    Do,
+    Else,
+    Elseif,
+    End,
+    False,
+    For,
+    Function,
+    If,
+    In,
+    Local,
+    Nil,
+    Not,
+    Or,
+    Repeat,
+    Return,
+    Then,
+    True,
+    Until,
+    While,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    Caret,
+    Hash,
+    Eq,
+    Ne,
+    Le,
+    Ge,
+    Lt,
+    Gt,
+    Assign,
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    LBracket,
+    RBracket,
+    Semicolon,
+    Colon,
+    Comma,
+    Dot,
+    Concat,
+    Ellipsis,
+    Eof,
+}
+
+impl Token {
+    /// How the token is shown in syntax errors, such as "near 'end'"
+    pub(super) fn describe(&self) -> String {
+        let s = match self {
+            Token::Name(name) => return name.to_string(),
+            Token::Number(n) => return super::value::format_number(*n),
+            Token::String(s) => return String::from_utf8_lossy(s).into_owned(),
+            Token::And => "and",
+            Token::Break => "break",
+            Token::Do => "do",
+            Token::Else => "else",
+            Token::Elseif => "elseif",
+            Token::End => "end",
+            Token::False => "false",
+            Token::For => "for",
+            Token::Function => "function",
+            Token::If => "if",
+            Token::In => "in",
+            Token::Local => "local",
+            Token::Nil => "nil",
+            Token::Not => "not",
+            Token::Or => "or",
+            Token::Repeat => "repeat",
+            Token::Return => "return",
+            Token::Then => "then",
+            Token::True => "true",
+            Token::Until => "until",
+            Token::While => "while",
+            Token::Plus => "+",
+            Token::Minus => "-",
+            Token::Star => "*",
+            Token::Slash => "/",
+            Token::Percent => "%",
+            Token::Caret => "^",
+            Token::Hash => "#",
+            Token::Eq => "==",
+            Token::Ne => "~=",
+            Token::Le => "<=",
+            Token::Ge => ">=",
+            Token::Lt => "<",
+            Token::Gt => ">",
+            Token::Assign => "=",
+            Token::LParen => "(",
+            Token::RParen => ")",
+            Token::LBrace => "{",
+            Token::RBrace => "}",
+            Token::LBracket => "[",
+            Token::RBracket => "]",
+            Token::Semicolon => ";",
+            Token::Colon => ":",
+            Token::Comma => ",",
+            Token::Dot => ".",
+            Token::Concat => "..",
+            Token::Ellipsis => "...",
+            Token::Eof => "<eof>",
+        };
+        s.to_string()
+    }
+}
+
+fn keyword(name: &str) -> Option<Token> {
+    Some(match name {
+        "and" => Token::And,
+        "break" => Token::Break,
+        "do" => Token::Do,
+        "else" => Token::Else,
+        "elseif" => Token::Elseif,
+        "end" => Token::End,
+        "false" => Token::False,
+        "for" => Token::For,
+        "function" => Token::Function,
+        "if" => Token::If,
+        "in" => Token::In,
+        "local" => Token::Local,
+        "nil" => Token::Nil,
+        "not" => Token::Not,
+        "or" => Token::Or,
+        "repeat" => Token::Repeat,
+        "return" => Token::Return,
+        "then" => Token::Then,
+        "true" => Token::True,
+        "until" => Token::Until,
+        "while" => Token::While,
+        _ => return None,
+    })
+}
+
+/// Split source code into tokens, along with the line each one is on
+pub(super) fn tokenize(source: &[u8], chunk_name: &str) -> Result<Vec<(Token, u32)>, String> {
+    Lexer {
+        source,
+        pos: 0,
+        line: 1,
+        chunk_name,
+    }
+    .tokenize()
+}
+
+struct Lexer<'a> {
+    source: &'a [u8],
+    pos: usize,
+    line: u32,
+    chunk_name: &'a str,
+}
+
+impl Lexer<'_> {
+    fn peek(&self, offset: usize) -> u8 {
+        self.source.get(self.pos + offset).copied().unwrap_or(0)
+    }
+
+    fn error(&self, message: &str, near: &[u8]) -> String {
+        format!(
+            "{}:{}: {} near '{}'",
+            self.chunk_name,
+            self.line,
+            message,
+            String::from_utf8_lossy(near)
+        )
+    }
+
+    fn tokenize(mut self) -> Result<Vec<(Token, u32)>, String> {
+        let mut tokens = Vec::new();
+        loop {
+            self.skip_whitespace_and_comments()?;
+            let line = self.line;
+            let Some(&c) = self.source.get(self.pos) else {
+                tokens.push((Token::Eof, line));
+                return Ok(tokens);
+            };
+
+            let token = match c {
+                b'a'..=b'z' | b'A'..=b'Z' | b'_' => {
+                    let start = self.pos;
+                    while matches!(self.peek(0), b'a'..=b'z' | b'A'..=b'Z' | b'_' | b'0'..=b'9') {
+                        self.pos += 1;
+                    }
+                    let name = std::str::from_utf8(&self.source[start..self.pos]).unwrap();
+                    keyword(name).unwrap_or_else(|| Token::Name(Rc::from(name)))
+                }
+                b'0'..=b'9' => self.number()?,
+                b'.' if self.peek(1).is_ascii_digit() => self.number()?,
+                b'"' | b'\'' => self.string(c)?,
+                b'[' if matches!(self.peek(1), b'[' | b'=')
+                    && self.long_bracket_level().is_some() =>
+                {
+                    Token::String(Rc::from(self.long_string()?))
+                }
+                _ => self.symbol()?,
+            };
+            tokens.push((token, line));
+        }
+    }
+
+    fn skip_whitespace_and_comments(&mut self) -> Result<(), String> {
+        loop {
+            match self.peek(0) {
+                b'\n' => {
+                    self.line += 1;
+                    self.pos += 1;
+                }
+                b' ' | b'\t' | b'\r' | 0x0b | 0x0c => self.pos += 1,
+                b'-' if self.peek(1) == b'-' => {
+                    self.pos += 2;
+                    if self.peek(0) == b'[' && self.long_bracket_level().is_some() {
+                        self.long_string()?;
+                    } else {
+                        while self.pos < self.source.len() && self.peek(0) != b'\n' {
+                            self.pos += 1;
+                        }
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// The level of a long bracket, the number of '=' in "[==[", if one starts here
+    fn long_bracket_level(&self) -> Option<usize> {
+        let mut level = 0;
+        while self.peek(1 + level) == b'=' {
+            level += 1;
+        }
+        (self.peek(1 + level) == b'[').then_some(level)
+    }
+
+    fn long_string(&mut self) -> Result<Vec<u8>, String> {
+        let level = self.long_bracket_level().unwrap();
+        self.pos += level + 2;
+        // A newline right after the opening bracket is skipped
+        if self.peek(0) == b'\r' {
+            self.pos += 1;
+        }
+        if self.peek(0) == b'\n' {
+            self.line += 1;
+            self.pos += 1;
+        }
+        let mut s = Vec::new();
+        loop {
+            match self.source.get(self.pos) {
+                None => return Err(self.error("unfinished long string", b"<eof>")),
+                Some(b']') => {
+                    let closing =
+                        (1..=level).all(|i| self.peek(i) == b'=') && self.peek(level + 1) == b']';
+                    if closing {
+                        self.pos += level + 2;
+                        return Ok(s);
+                    }
+                    s.push(b']');
+                    self.pos += 1;
+                }
+                Some(&c) => {
+                    if c == b'\n' {
+                        self.line += 1;
+                    }
+                    s.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Token, String> {
+        let start = self.pos;
+        if self.peek(0) == b'0' && matches!(self.peek(1), b'x' | b'X') {
+            self.pos += 2;
+        }
+        loop {
+            match self.peek(0) {
+                b'e' | b'E' if matches!(self.peek(1), b'+' | b'-') => self.pos += 2,
+                c if c.is_ascii_alphanumeric() || c == b'.' || c == b'_' => self.pos += 1,
+                _ => break,
+            }
+        }
+        let text = &self.source[start..self.pos];
+        match super::value::parse_number(text) {
+            Some(n) => Ok(Token::Number(n)),
+            None => Err(self.error("malformed number", text)),
+        }
+    }
+
+    fn string(&mut self, quote: u8) -> Result<Token, String> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut s = Vec::new();
+        loop {
+            let Some(&c) = self.source.get(self.pos) else {
+                return Err(self.error("unfinished string", &self.source[start..self.pos]));
+            };
+            match c {
+                b'\n' => return Err(self.error("unfinished string", &self.source[start..self.pos])),
+                b'\\' => {
+                    self.pos += 1;
+                    let escaped = self.peek(0);
+                    match escaped {
+                        b'n' => s.push(b'\n'),
+                        b't' => s.push(b'\t'),
+                        b'r' => s.push(b'\r'),
+                        b'a' => s.push(0x07),
+                        b'b' => s.push(0x08),
+                        b'f' => s.push(0x0c),
+                        b'v' => s.push(0x0b),
+                        b'\\' | b'"' | b'\'' => s.push(escaped),
+                        b'\n' => {
+                            self.line += 1;
+                            s.push(b'\n');
+                        }
+                        b'0'..=b'9' => {
+                            let mut value: u32 = 0;
+                            let mut digits = 0;
+                            while digits < 3 && self.peek(0).is_ascii_digit() {
+                                value = value * 10 + (self.peek(0) - b'0') as u32;
+                                self.pos += 1;
+                                digits += 1;
+                            }
+                            if value > 255 {
+                                return Err(self.error(
+                                    "escape sequence too large",
+                                    &self.source[start..self.pos],
+                                ));
+                            }
+                            s.push(value as u8);
+                            continue;
+                        }
+                        0 if self.pos >= self.source.len() => continue,
+                        // Unknown escapes are kept as the character itself, as in Lua 5.1
+                        c => s.push(c),
+                    }
+                    self.pos += 1;
+                }
+                c if c == quote => {
+                    self.pos += 1;
+                    return Ok(Token::String(Rc::from(s)));
+                }
+                c => {
+                    s.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    fn symbol(&mut self) -> Result<Token, String> {
+        let c = self.peek(0);
+        let next = self.peek(1);
+        let (token, length) = match (c, next) {
+            (b'.', b'.') if self.peek(2) == b'.' => (Token::Ellipsis, 3),
+            (b'.', b'.') => (Token::Concat, 2),
+            (b'=', b'=') => (Token::Eq, 2),
+            (b'~', b'=') => (Token::Ne, 2),
+            (b'<', b'=') => (Token::Le, 2),
+            (b'>', b'=') => (Token::Ge, 2),
+            (b'+', _) => (Token::Plus, 1),
+            (b'-', _) => (Token::Minus, 1),
+            (b'*', _) => (Token::Star, 1),
+            (b'/', _) => (Token::Slash, 1),
+            (b'%', _) => (Token::Percent, 1),
+            (b'^', _) => (Token::Caret, 1),
+            (b'#', _) => (Token::Hash, 1),
+            (b'<', _) => (Token::Lt, 1),
+            (b'>', _) => (Token::Gt, 1),
+            (b'=', _) => (Token::Assign, 1),
+            (b'(', _) => (Token::LParen, 1),
+            (b')', _) => (Token::RParen, 1),
+            (b'{', _) => (Token::LBrace, 1),
+            (b'}', _) => (Token::RBrace, 1),
+            (b'[', _) => (Token::LBracket, 1),
+            (b']', _) => (Token::RBracket, 1),
+            (b';', _) => (Token::Semicolon, 1),
+            (b':', _) => (Token::Colon, 1),
+            (b',', _) => (Token::Comma, 1),
+            (b'.', _) => (Token::Dot, 1),
+            _ => return Err(self.error("unexpected symbol", &[c])),
+        };
+        self.pos += length;
+        Ok(token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(source: &str) -> Vec<Token> {
+        tokenize(source.as_bytes(), "test")
+            .unwrap()
+            .into_iter()
+            .map(|(token, _)| token)
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokens("local x = 1.5 .. 'a\\n' -- comment\nreturn #x ~= ..."),
+            vec![
+                Token::Local,
+                Token::Name(Rc::from("x")),
+                Token::Assign,
+                Token::Number(1.5),
+                Token::Concat,
+                Token::String(Rc::from(&b"a\n"[..])),
+                Token::Return,
+                Token::Hash,
+                Token::Name(Rc::from("x")),
+                Token::Ne,
+                Token::Ellipsis,
+                Token::Eof,
+            ]
+        );
+        assert_eq!(
+            tokens("[==[\nlong ]] string]==] --[[ block\ncomment ]] 0x10 \"\\65\""),
+            vec![
+                Token::String(Rc::from(&b"long ]] string"[..])),
+                Token::Number(16.0),
+                Token::String(Rc::from(&b"A"[..])),
+                Token::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_errors() {
+        assert_eq!(
+            tokenize(b"x = 'abc", "user_script").unwrap_err(),
+            "user_script:1: unfinished string near ''abc'"
+        );
+        assert_eq!(
+            tokenize(b"\nx = 3x", "user_script").unwrap_err(),
+            "user_script:2: malformed number near '3x'"
+        );
+    }
+}
@@ -0,0 +1,22 @@
+//! A Lua 5.1 interpreter for scripts run with EVAL
+//!
+//! Scripts are parsed into a syntax tree with names resolved to slots, which is then evaluated
+//! directly. Only the parts of the standard library that Redis makes available are provided.
+
+mod ast;
+mod interpreter;
+mod lexer;
+mod parser;
+mod stdlib;
+mod value;
+
+pub(crate) use ast::FunctionProto;
+pub(crate) use interpreter::{Host, Lua};
+pub(crate) use value::{LuaError, LuaResult, Table, Value};
+
+use std::rc::Rc;
+
+/// Compile a script, returning the syntax error message if it doesn't compile
+pub(crate) fn compile(source: &[u8], chunk_name: &str) -> Result<Rc<FunctionProto>, String> {
+    parser::parse(source, chunk_name)
+}
@@ -0,0 +1,769 @@
+use super::{
+    ast::{BinOp, Block, Expr, Field, FunctionProto, Stmt, Target, UnOp, Upvalue},
+    lexer::{tokenize, Token},
+};
+use std::rc::Rc;
+
+/// How deeply statements and expressions can be nested, as in Lua's LUAI_MAXCCALLS
+const MAX_DEPTH: usize = 200;
+
+/// Parse a chunk into the function that runs it, which takes a variable number of arguments
+pub(crate) fn parse(source: &[u8], chunk_name: &str) -> Result<Rc<FunctionProto>, String> {
+    let tokens = tokenize(source, chunk_name)?;
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        chunk_name,
+        functions: Vec::new(),
+        depth: 0,
+    };
+    parser.open_function(true);
+    let body = parser.block()?;
+    parser.expect(Token::Eof)?;
+    Ok(Rc::new(parser.close_function(0, body)))
+}
+
+/// The scopes of a function being parsed
+#[derive(Default)]
+struct FunctionState {
+    /// The locals visible in each nested block, innermost last
+    blocks: Vec<Vec<(Rc<str>, usize)>>,
+    slot_names: Vec<Rc<str>>,
+    upvalues: Vec<(Rc<str>, Upvalue)>,
+    is_vararg: bool,
+    /// How many loops the current position is in, to check `break`
+    loops: usize,
+}
+
+/// What a name refers to in the function being parsed
+enum Resolved {
+    Local(usize),
+    Upvalue(usize),
+}
+
+struct Parser<'a> {
+    tokens: Vec<(Token, u32)>,
+    pos: usize,
+    chunk_name: &'a str,
+    functions: Vec<FunctionState>,
+    depth: usize,
+}
+
+/// The left and right priorities of binary operators, as in Lua 5.1
+fn binary_op(token: &Token) -> Option<(BinOp, u8, u8)> {
+    Some(match token {
+        Token::Plus => (BinOp::Add, 6, 6),
+        Token::Minus => (BinOp::Sub, 6, 6),
+        Token::Star => (BinOp::Mul, 7, 7),
+        Token::Slash => (BinOp::Div, 7, 7),
+        Token::Percent => (BinOp::Mod, 7, 7),
+        Token::Caret => (BinOp::Pow, 10, 9),
+        Token::Concat => (BinOp::Concat, 5, 4),
+        Token::Eq => (BinOp::Eq, 3, 3),
+        Token::Ne => (BinOp::Ne, 3, 3),
+        Token::Lt => (BinOp::Lt, 3, 3),
+        Token::Le => (BinOp::Le, 3, 3),
+        Token::Gt => (BinOp::Gt, 3, 3),
+        Token::Ge => (BinOp::Ge, 3, 3),
+        // `and` and `or` short-circuit, so they get their own expressions
+        Token::And => (BinOp::Eq, 2, 2),
+        Token::Or => (BinOp::Eq, 1, 1),
+        _ => return None,
+    })
+}
+
+const UNARY_PRIORITY: u8 = 8;
+
+impl Parser<'_> {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].0
+    }
+
+    fn line(&self) -> u32 {
+        self.tokens[self.pos].1
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.pos].0.clone();
+        if self.pos < self.tokens.len() - 1 {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn check(&mut self, token: Token) -> bool {
+        if *self.peek() == token {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, String> {
+        Err(format!(
+            "{}:{}: {} near '{}'",
+            self.chunk_name,
+            self.line(),
+            message,
+            self.peek().describe()
+        ))
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), String> {
+        if self.check(token.clone()) {
+            Ok(())
+        } else {
+            self.error(&format!("'{}' expected", token.describe()))
+        }
+    }
+
+    /// Expect the token closing a construct that was opened on another line, such as "end"
+    fn expect_closing(&mut self, token: Token, opening: Token, line: u32) -> Result<(), String> {
+        if line == self.line() {
+            return self.expect(token);
+        }
+        if self.check(token.clone()) {
+            return Ok(());
+        }
+        self.error(&format!(
+            "'{}' expected (to close '{}' at line {})",
+            token.describe(),
+            opening.describe(),
+            line
+        ))
+    }
+
+    fn name(&mut self) -> Result<Rc<str>, String> {
+        match self.peek().clone() {
+            Token::Name(name) => {
+                self.advance();
+                Ok(name)
+            }
+            _ => self.error("<name> expected"),
+        }
+    }
+
+    fn enter(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return self.error("chunk has too many syntax levels");
+        }
+        Ok(())
+    }
+
+    fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    fn function(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().unwrap()
+    }
+
+    fn open_function(&mut self, is_vararg: bool) {
+        self.functions.push(FunctionState {
+            blocks: vec![Vec::new()],
+            is_vararg,
+            ..Default::default()
+        });
+    }
+
+    fn close_function(&mut self, params: usize, body: Block) -> FunctionProto {
+        let state = self.functions.pop().unwrap();
+        let (upvalue_names, upvalues) = state.upvalues.into_iter().unzip();
+        FunctionProto {
+            params,
+            is_vararg: state.is_vararg,
+            slot_names: state.slot_names,
+            upvalues,
+            upvalue_names,
+            body,
+        }
+    }
+
+    fn declare(&mut self, name: Rc<str>) -> usize {
+        let function = self.function();
+        let slot = function.slot_names.len();
+        function.slot_names.push(Rc::clone(&name));
+        function.blocks.last_mut().unwrap().push((name, slot));
+        slot
+    }
+
+    fn resolve_in(&mut self, level: usize, name: &str) -> Option<Resolved> {
+        let function = &self.functions[level];
+        for block in function.blocks.iter().rev() {
+            if let Some((_, slot)) = block.iter().rev().find(|(n, _)| &**n == name) {
+                return Some(Resolved::Local(*slot));
+            }
+        }
+        if let Some(i) = function.upvalues.iter().position(|(n, _)| &**n == name) {
+            return Some(Resolved::Upvalue(i));
+        }
+        if level == 0 {
+            return None;
+        }
+        let upvalue = match self.resolve_in(level - 1, name)? {
+            Resolved::Local(slot) => Upvalue::Local(slot),
+            Resolved::Upvalue(i) => Upvalue::Upvalue(i),
+        };
+        let upvalues = &mut self.functions[level].upvalues;
+        upvalues.push((Rc::from(name), upvalue));
+        Some(Resolved::Upvalue(upvalues.len() - 1))
+    }
+
+    fn resolve(&mut self, name: &str) -> Expr {
+        match self.resolve_in(self.functions.len() - 1, name) {
+            Some(Resolved::Local(slot)) => Expr::Local(slot),
+            Some(Resolved::Upvalue(i)) => Expr::Upvalue(i),
+            None => Expr::Global(Rc::from(name.as_bytes())),
+        }
+    }
+
+    fn block_follows(&self) -> bool {
+        matches!(
+            self.peek(),
+            Token::Else | Token::Elseif | Token::End | Token::Until | Token::Eof
+        )
+    }
+
+    /// Parse a block in a new scope
+    fn scoped_block(&mut self) -> Result<Block, String> {
+        self.function().blocks.push(Vec::new());
+        let block = self.block();
+        self.function().blocks.pop();
+        block
+    }
+
+    fn block(&mut self) -> Result<Block, String> {
+        self.enter()?;
+        let mut block = Vec::new();
+        while !self.block_follows() {
+            if self.check(Token::Semicolon) {
+                continue;
+            }
+            let line = self.line();
+            if self.check(Token::Return) {
+                let exprs = if self.block_follows() || *self.peek() == Token::Semicolon {
+                    Vec::new()
+                } else {
+                    self.expr_list()?
+                };
+                self.check(Token::Semicolon);
+                block.push(Stmt::Return(exprs, line));
+                if !self.block_follows() {
+                    return self.error("'end' expected");
+                }
+                break;
+            }
+            block.push(self.statement()?);
+        }
+        self.leave();
+        Ok(block)
+    }
+
+    fn statement(&mut self) -> Result<Stmt, String> {
+        let line = self.line();
+        match self.peek() {
+            Token::If => self.if_statement(line),
+            Token::While => {
+                self.advance();
+                let cond = self.expr()?;
+                self.expect(Token::Do)?;
+                let body = self.loop_body()?;
+                self.expect_closing(Token::End, Token::While, line)?;
+                Ok(Stmt::While { cond, body, line })
+            }
+            Token::Do => {
+                self.advance();
+                let body = self.scoped_block()?;
+                self.expect_closing(Token::End, Token::Do, line)?;
+                Ok(Stmt::Do(body))
+            }
+            Token::For => self.for_statement(line),
+            Token::Repeat => {
+                self.advance();
+                // The condition can refer to the locals of the body
+                self.function().blocks.push(Vec::new());
+                self.function().loops += 1;
+                let body = self.block();
+                self.function().loops -= 1;
+                let cond = body.and_then(|body| {
+                    self.expect_closing(Token::Until, Token::Repeat, line)?;
+                    Ok((body, self.expr()?))
+                });
+                self.function().blocks.pop();
+                let (body, cond) = cond?;
+                Ok(Stmt::Repeat { body, cond, line })
+            }
+            Token::Function => {
+                self.advance();
+                let name = self.name()?;
+                let mut target = self.resolve(&name);
+                let mut is_method = false;
+                while matches!(self.peek(), Token::Dot | Token::Colon) {
+                    is_method = self.advance() == Token::Colon;
+                    let key = self.name()?;
+                    target = Expr::Index(
+                        Box::new(target),
+                        Box::new(Expr::String(Rc::from(key.as_bytes()))),
+                    );
+                    if is_method {
+                        break;
+                    }
+                }
+                let function = self.function_body(is_method, line)?;
+                Ok(Stmt::Assign {
+                    targets: vec![self.target(target)?],
+                    exprs: vec![Expr::Function(function)],
+                    line,
+                })
+            }
+            Token::Local => {
+                self.advance();
+                if self.check(Token::Function) {
+                    let name = self.name()?;
+                    // Declared before the body, so that the function can call itself
+                    let slot = self.declare(Rc::clone(&name));
+                    let function = self.function_body(false, line)?;
+                    return Ok(Stmt::LocalFunction {
+                        slot,
+                        function,
+                        line,
+                    });
+                }
+                let mut names = vec![self.name()?];
+                while self.check(Token::Comma) {
+                    names.push(self.name()?);
+                }
+                let exprs = if self.check(Token::Assign) {
+                    self.expr_list()?
+                } else {
+                    Vec::new()
+                };
+                let slots = names.into_iter().map(|name| self.declare(name)).collect();
+                Ok(Stmt::Local { slots, exprs, line })
+            }
+            Token::Break => {
+                self.advance();
+                if self.function().loops == 0 {
+                    return self.error("no loop to break");
+                }
+                Ok(Stmt::Break)
+            }
+            _ => self.expression_statement(line),
+        }
+    }
+
+    fn loop_body(&mut self) -> Result<Block, String> {
+        self.function().loops += 1;
+        let body = self.scoped_block();
+        self.function().loops -= 1;
+        body
+    }
+
+    fn if_statement(&mut self, line: u32) -> Result<Stmt, String> {
+        self.advance();
+        let mut branches = Vec::new();
+        let cond = self.expr()?;
+        self.expect(Token::Then)?;
+        branches.push((cond, self.scoped_block()?));
+        let mut otherwise = None;
+        loop {
+            if self.check(Token::Elseif) {
+                let cond = self.expr()?;
+                self.expect(Token::Then)?;
+                branches.push((cond, self.scoped_block()?));
+            } else if self.check(Token::Else) {
+                otherwise = Some(self.scoped_block()?);
+                self.expect_closing(Token::End, Token::If, line)?;
+                break;
+            } else {
+                self.expect_closing(Token::End, Token::If, line)?;
+                break;
+            }
+        }
+        Ok(Stmt::If {
+            branches,
+            otherwise,
+            line,
+        })
+    }
+
+    fn for_statement(&mut self, line: u32) -> Result<Stmt, String> {
+        self.advance();
+        let first = self.name()?;
+        if self.check(Token::Assign) {
+            let start = self.expr()?;
+            self.expect(Token::Comma)?;
+            let limit = self.expr()?;
+            let step = if self.check(Token::Comma) {
+                Some(self.expr()?)
+            } else {
+                None
+            };
+            self.expect(Token::Do)?;
+            self.function().blocks.push(Vec::new());
+            let slot = self.declare(first);
+            let body = self.loop_body();
+            self.function().blocks.pop();
+            let body = body?;
+            self.expect_closing(Token::End, Token::For, line)?;
+            return Ok(Stmt::NumericFor {
+                slot,
+                start,
+                limit,
+                step,
+                body,
+                line,
+            });
+        }
+
+        let mut names = vec![first];
+        while self.check(Token::Comma) {
+            names.push(self.name()?);
+        }
+        if *self.peek() != Token::In {
+            return self.error("'=' or 'in' expected");
+        }
+        self.advance();
+        let exprs = self.expr_list()?;
+        self.expect(Token::Do)?;
+        self.function().blocks.push(Vec::new());
+        let slots = names.into_iter().map(|name| self.declare(name)).collect();
+        let body = self.loop_body();
+        self.function().blocks.pop();
+        let body = body?;
+        self.expect_closing(Token::End, Token::For, line)?;
+        Ok(Stmt::GenericFor {
+            slots,
+            exprs,
+            body,
+            line,
+        })
+    }
+
+    fn expression_statement(&mut self, line: u32) -> Result<Stmt, String> {
+        let expr = self.suffixed_expr()?;
+        if matches!(self.peek(), Token::Assign | Token::Comma) {
+            let mut targets = vec![self.target(expr)?];
+            while self.check(Token::Comma) {
+                let expr = self.suffixed_expr()?;
+                targets.push(self.target(expr)?);
+            }
+            self.expect(Token::Assign)?;
+            let exprs = self.expr_list()?;
+            return Ok(Stmt::Assign {
+                targets,
+                exprs,
+                line,
+            });
+        }
+        if !matches!(expr, Expr::Call(..) | Expr::Method(..)) {
+            return self.error("syntax error");
+        }
+        Ok(Stmt::Call(expr, line))
+    }
+
+    fn target(&self, expr: Expr) -> Result<Target, String> {
+        match expr {
+            Expr::Local(slot) => Ok(Target::Local(slot)),
+            Expr::Upvalue(i) => Ok(Target::Upvalue(i)),
+            Expr::Global(name) => Ok(Target::Global(name)),
+            Expr::Index(table, key) => Ok(Target::Index(*table, *key)),
+            _ => self.error("syntax error"),
+        }
+    }
+
+    fn function_body(&mut self, is_method: bool, line: u32) -> Result<Rc<FunctionProto>, String> {
+        self.open_function(false);
+        let result = self.function_body_inner(is_method, line);
+        let (params, body) = match result {
+            Ok(result) => result,
+            Err(e) => {
+                self.functions.pop();
+                return Err(e);
+            }
+        };
+        Ok(Rc::new(self.close_function(params, body)))
+    }
+
+    fn function_body_inner(
+        &mut self,
+        is_method: bool,
+        line: u32,
+    ) -> Result<(usize, Block), String> {
+        if is_method {
+            self.declare(Rc::from("self"));
+        }
+        self.expect(Token::LParen)?;
+        if *self.peek() != Token::RParen {
+            loop {
+                match self.peek() {
+                    Token::Ellipsis => {
+                        self.advance();
+                        self.function().is_vararg = true;
+                        break;
+                    }
+                    _ => {
+                        let name = self.name()?;
+                        self.declare(name);
+                    }
+                }
+                if !self.check(Token::Comma) {
+                    break;
+                }
+            }
+        }
+        let params = self.function().slot_names.len();
+        self.expect(Token::RParen)?;
+        let body = self.block()?;
+        self.expect_closing(Token::End, Token::Function, line)?;
+        Ok((params, body))
+    }
+
+    fn expr_list(&mut self) -> Result<Vec<Expr>, String> {
+        let mut exprs = vec![self.expr()?];
+        while self.check(Token::Comma) {
+            exprs.push(self.expr()?);
+        }
+        Ok(exprs)
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        self.sub_expr(0)
+    }
+
+    fn sub_expr(&mut self, limit: u8) -> Result<Expr, String> {
+        self.enter()?;
+        let unary = match self.peek() {
+            Token::Not => Some(UnOp::Not),
+            Token::Minus => Some(UnOp::Neg),
+            Token::Hash => Some(UnOp::Len),
+            _ => None,
+        };
+        let mut left = match unary {
+            Some(op) => {
+                self.advance();
+                let operand = self.sub_expr(UNARY_PRIORITY)?;
+                match (op, operand) {
+                    // Fold negative number literals
+                    (UnOp::Neg, Expr::Number(n)) => Expr::Number(-n),
+                    (op, operand) => Expr::Unary(op, Box::new(operand)),
+                }
+            }
+            None => self.simple_expr()?,
+        };
+
+        while let Some((op, left_priority, right_priority)) = binary_op(self.peek()) {
+            if left_priority <= limit {
+                break;
+            }
+            let token = self.advance();
+            let right = self.sub_expr(right_priority)?;
+            left = match token {
+                Token::And => Expr::And(Box::new(left), Box::new(right)),
+                Token::Or => Expr::Or(Box::new(left), Box::new(right)),
+                _ => Expr::Binary(op, Box::new(left), Box::new(right)),
+            };
+        }
+        self.leave();
+        Ok(left)
+    }
+
+    fn simple_expr(&mut self) -> Result<Expr, String> {
+        let line = self.line();
+        let expr = match self.peek().clone() {
+            Token::Number(n) => Expr::Number(n),
+            Token::String(s) => Expr::String(s),
+            Token::Nil => Expr::Nil,
+            Token::True => Expr::True,
+            Token::False => Expr::False,
+            Token::Ellipsis => {
+                if !self.function().is_vararg {
+                    return self.error("cannot use '...' outside a vararg function");
+                }
+                Expr::Vararg
+            }
+            Token::LBrace => return self.table_constructor(),
+            Token::Function => {
+                self.advance();
+                return Ok(Expr::Function(self.function_body(false, line)?));
+            }
+            _ => return self.suffixed_expr(),
+        };
+        self.advance();
+        Ok(expr)
+    }
+
+    fn primary_expr(&mut self) -> Result<Expr, String> {
+        match self.peek().clone() {
+            Token::Name(name) => {
+                self.advance();
+                Ok(self.resolve(&name))
+            }
+            Token::LParen => {
+                let line = self.line();
+                self.advance();
+                let expr = self.expr()?;
+                self.expect_closing(Token::RParen, Token::LParen, line)?;
+                Ok(match expr {
+                    expr if expr.is_multi() => Expr::Paren(Box::new(expr)),
+                    expr => expr,
+                })
+            }
+            _ => self.error("unexpected symbol"),
+        }
+    }
+
+    fn suffixed_expr(&mut self) -> Result<Expr, String> {
+        self.enter()?;
+        let mut expr = self.primary_expr()?;
+        loop {
+            expr = match self.peek() {
+                Token::Dot => {
+                    self.advance();
+                    let key = self.name()?;
+                    Expr::Index(
+                        Box::new(expr),
+                        Box::new(Expr::String(Rc::from(key.as_bytes()))),
+                    )
+                }
+                Token::LBracket => {
+                    self.advance();
+                    let key = self.expr()?;
+                    self.expect(Token::RBracket)?;
+                    Expr::Index(Box::new(expr), Box::new(key))
+                }
+                Token::Colon => {
+                    self.advance();
+                    let name = self.name()?;
+                    let args = self.call_args()?;
+                    Expr::Method(Box::new(expr), Rc::from(name.as_bytes()), args)
+                }
+                Token::LParen | Token::String(_) | Token::LBrace => {
+                    let args = self.call_args()?;
+                    Expr::Call(Box::new(expr), args)
+                }
+                _ => break,
+            };
+        }
+        self.leave();
+        Ok(expr)
+    }
+
+    fn call_args(&mut self) -> Result<Vec<Expr>, String> {
+        match self.peek().clone() {
+            Token::String(s) => {
+                self.advance();
+                Ok(vec![Expr::String(s)])
+            }
+            Token::LBrace => Ok(vec![self.table_constructor()?]),
+            Token::LParen => {
+                let line = self.line();
+                self.advance();
+                if self.check(Token::RParen) {
+                    return Ok(Vec::new());
+                }
+                let args = self.expr_list()?;
+                self.expect_closing(Token::RParen, Token::LParen, line)?;
+                Ok(args)
+            }
+            _ => self.error("function arguments expected"),
+        }
+    }
+
+    fn table_constructor(&mut self) -> Result<Expr, String> {
+        let line = self.line();
+        self.expect(Token::LBrace)?;
+        let mut fields = Vec::new();
+        while *self.peek() != Token::RBrace {
+            let field = match self.peek() {
+                Token::LBracket => {
+                    self.advance();
+                    let key = self.expr()?;
+                    self.expect(Token::RBracket)?;
+                    self.expect(Token::Assign)?;
+                    Field::Keyed(key, self.expr()?)
+                }
+                Token::Name(name) if self.tokens[self.pos + 1].0 == Token::Assign => {
+                    let key = Expr::String(Rc::from(name.as_bytes()));
+                    self.advance();
+                    self.advance();
+                    Field::Keyed(key, self.expr()?)
+                }
+                _ => Field::Positional(self.expr()?),
+            };
+            fields.push(field);
+            if !self.check(Token::Comma) && !self.check(Token::Semicolon) {
+                break;
+            }
+        }
+        self.expect_closing(Token::RBrace, Token::LBrace, line)?;
+        Ok(Expr::Table(fields))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source: &str) -> String {
+        match parse(source.as_bytes(), "user_script") {
+            Ok(_) => panic!("{source:?} parsed"),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let chunk = parse(
+            b"local a, b = 1, 2\n\
+              local function f(x, ...) return x + a, ... end\n\
+              for i = 1, 10 do local c = i end\n\
+              t = {1, 2; x = 3, [4] = 5}\n\
+              t.x, t[1] = f(1), #t\n\
+              repeat local d = 1 until d == 1\n\
+              return f(1, 2, 3)",
+            "user_script",
+        )
+        .unwrap();
+        assert_eq!(chunk.body.len(), 7);
+        // a, b, f, i, c, d
+        assert_eq!(chunk.slot_names.len(), 6);
+        let Stmt::LocalFunction { function, .. } = &chunk.body[1] else {
+            panic!("expected a local function");
+        };
+        assert_eq!(function.params, 1);
+        assert!(function.is_vararg);
+        assert!(matches!(function.upvalues[..], [Upvalue::Local(0)]));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            error("x ="),
+            "user_script:1: unexpected symbol near '<eof>'"
+        );
+        assert_eq!(
+            error("local = 1"),
+            "user_script:1: <name> expected near '='"
+        );
+        assert_eq!(
+            error("if x then\nreturn 1"),
+            "user_script:2: 'end' expected (to close 'if' at line 1) near '<eof>'"
+        );
+        assert_eq!(error("x"), "user_script:1: syntax error near '<eof>'");
+        assert_eq!(
+            error("function f() return ... end"),
+            "user_script:1: cannot use '...' outside a vararg function near '...'"
+        );
+        assert_eq!(
+            error("break"),
+            "user_script:1: no loop to break near '<eof>'"
+        );
+        assert_eq!(
+            error(&format!("return {}1{}", "(".repeat(300), ")".repeat(300))),
+            "user_script:1: chunk has too many syntax levels near '('"
+        );
+    }
+}
@@ -0,0 +1,258 @@
+use super::{
+    arg, arg_error, check_integer, check_table, check_writable, opt_integer, type_error, Builtin,
+};
+use crate::lua::{
+    value::{LuaResult, Value},
+    Lua,
+};
+use std::rc::Rc;
+
+pub(super) fn open(lua: &mut Lua) {
+    let functions: &[(&'static str, Builtin)] = &[
+        ("assert", assert),
+        ("error", error),
+        ("getmetatable", getmetatable),
+        ("ipairs", ipairs),
+        ("next", next),
+        ("pairs", pairs),
+        ("pcall", pcall),
+        ("rawequal", rawequal),
+        ("rawget", rawget),
+        ("rawset", rawset),
+        ("select", select),
+        ("setmetatable", setmetatable),
+        ("tonumber", tonumber),
+        ("tostring", tostring),
+        ("type", type_),
+        ("unpack", unpack),
+        ("xpcall", xpcall),
+    ];
+    for &(name, function) in functions {
+        lua.set_global(name, Value::native(name, function));
+    }
+    let globals = Value::Table(Rc::clone(lua.globals()));
+    lua.set_global("_G", globals);
+    lua.set_global("_VERSION", "Lua 5.1".into());
+}
+
+/// Convert a value to a string as `tostring` does, using the `__tostring` metamethod
+pub(super) fn to_string(lua: &mut Lua, value: &Value) -> LuaResult<Value> {
+    match lua.to_string_metamethod(value) {
+        Some(handler) => {
+            let result = lua.call(&handler, vec![value.clone()])?;
+            match result.into_iter().next() {
+                Some(value @ Value::String(_)) => Ok(value),
+                _ => Err(lua.error("'__tostring' must return a string")),
+            }
+        }
+        None => Ok(Value::string(value.to_display())),
+    }
+}
+
+fn assert(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    if args.is_empty() {
+        return Err(arg_error(lua, 0, "assert", "value expected"));
+    }
+    if args[0].is_truthy() {
+        return Ok(args);
+    }
+    match args.get(1) {
+        Some(message) if !message.is_nil() => Err(lua.raise(message.clone())),
+        _ => Err(lua.error("assertion failed!")),
+    }
+}
+
+fn error(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let level = opt_integer(lua, &args, 1, "error", 1)?;
+    match arg(&args, 0) {
+        Value::String(message) if level > 0 => Err(lua.error(String::from_utf8_lossy(&message))),
+        value => Err(lua.raise(value)),
+    }
+}
+
+fn getmetatable(_lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    Ok(vec![match arg(&args, 0) {
+        Value::Table(table) => table
+            .borrow()
+            .metatable
+            .clone()
+            .map_or(Value::Nil, Value::Table),
+        _ => Value::Nil,
+    }])
+}
+
+fn setmetatable(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let table = check_table(lua, &args, 0, "setmetatable")?;
+    let metatable = match arg(&args, 1) {
+        Value::Nil => None,
+        Value::Table(metatable) => Some(metatable),
+        _ => return Err(arg_error(lua, 1, "setmetatable", "nil or table expected")),
+    };
+    check_writable(lua, &table)?;
+    table.borrow_mut().metatable = metatable;
+    Ok(vec![Value::Table(table)])
+}
+
+fn ipairs(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let table = check_table(lua, &args, 0, "ipairs")?;
+    let iterator = Value::native("ipairs_aux", |lua, args| {
+        let table = check_table(lua, &args, 0, "ipairs_aux")?;
+        let i = check_integer(lua, &args, 1, "ipairs_aux")? + 1;
+        let value = table.borrow().get(&Value::Number(i as f64));
+        Ok(match value {
+            Value::Nil => vec![Value::Nil],
+            value => vec![Value::Number(i as f64), value],
+        })
+    });
+    Ok(vec![iterator, Value::Table(table), Value::Number(0.0)])
+}
+
+fn next(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let table = check_table(lua, &args, 0, "next")?;
+    let entry = table.borrow().next(&arg(&args, 1));
+    match entry {
+        Ok(Some((key, value))) => Ok(vec![key, value]),
+        Ok(None) => Ok(vec![Value::Nil]),
+        Err(()) => Err(lua.error("invalid key to 'next'")),
+    }
+}
+
+fn pairs(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let table = check_table(lua, &args, 0, "pairs")?;
+    Ok(vec![
+        Value::native("next", next),
+        Value::Table(table),
+        Value::Nil,
+    ])
+}
+
+fn pcall(lua: &mut Lua, mut args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    if args.is_empty() {
+        return Err(arg_error(lua, 0, "pcall", "value expected"));
+    }
+    let function = args.remove(0);
+    match lua.call(&function, args) {
+        Ok(mut values) => {
+            values.insert(0, Value::Boolean(true));
+            Ok(values)
+        }
+        Err(e) if e.fatal => Err(e),
+        Err(e) => Ok(vec![Value::Boolean(false), e.value]),
+    }
+}
+
+fn xpcall(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let (function, handler) = (arg(&args, 0), arg(&args, 1));
+    match lua.call(&function, Vec::new()) {
+        Ok(mut values) => {
+            values.insert(0, Value::Boolean(true));
+            Ok(values)
+        }
+        Err(e) if e.fatal => Err(e),
+        Err(e) => {
+            let mut values = lua.call(&handler, vec![e.value])?;
+            values.truncate(1);
+            values.insert(0, Value::Boolean(false));
+            Ok(values)
+        }
+    }
+}
+
+fn rawequal(_lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    Ok(vec![Value::Boolean(
+        arg(&args, 0).raw_equals(&arg(&args, 1)),
+    )])
+}
+
+fn rawget(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let table = check_table(lua, &args, 0, "rawget")?;
+    let value = table.borrow().get(&arg(&args, 1));
+    Ok(vec![value])
+}
+
+fn rawset(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let table = check_table(lua, &args, 0, "rawset")?;
+    check_writable(lua, &table)?;
+    let result = table.borrow_mut().set(arg(&args, 1), arg(&args, 2));
+    result.map_err(|e| lua.error(e))?;
+    Ok(vec![Value::Table(table)])
+}
+
+fn select(lua: &mut Lua, mut args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    if let Some(Value::String(s)) = args.first() {
+        if &s[..] == b"#" {
+            return Ok(vec![Value::Number((args.len() - 1) as f64)]);
+        }
+    }
+    let n = check_integer(lua, &args, 0, "select")?;
+    let count = args.len() as i64 - 1;
+    let start = match n {
+        n if n < 0 && -n <= count => count + n + 1,
+        n if n > 0 => n.min(count + 1),
+        _ => return Err(arg_error(lua, 0, "select", "index out of range")),
+    };
+    Ok(args.split_off(start as usize))
+}
+
+fn tonumber(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let base = opt_integer(lua, &args, 1, "tonumber", 10)?;
+    if base == 10 {
+        return Ok(vec![arg(&args, 0)
+            .to_number()
+            .map_or(Value::Nil, Value::Number)]);
+    }
+    if !(2..=36).contains(&base) {
+        return Err(arg_error(lua, 1, "tonumber", "base out of range"));
+    }
+    let Some(s) = arg(&args, 0).to_bytes() else {
+        return Err(type_error(lua, &args, 0, "tonumber", "string"));
+    };
+    let s = String::from_utf8_lossy(&s);
+    let s = s.trim();
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s),
+    };
+    if digits.is_empty() {
+        return Ok(vec![Value::Nil]);
+    }
+    let mut n = 0.0;
+    for c in digits.chars() {
+        match c.to_digit(base as u32) {
+            Some(digit) => n = n * base as f64 + digit as f64,
+            None => return Ok(vec![Value::Nil]),
+        }
+    }
+    Ok(vec![Value::Number(if negative { -n } else { n })])
+}
+
+fn tostring(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    if args.is_empty() {
+        return Err(arg_error(lua, 0, "tostring", "value expected"));
+    }
+    Ok(vec![to_string(lua, &args[0])?])
+}
+
+fn type_(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    match args.first() {
+        Some(value) => Ok(vec![value.type_name().into()]),
+        None => Err(arg_error(lua, 0, "type", "value expected")),
+    }
+}
+
+fn unpack(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let table = check_table(lua, &args, 0, "unpack")?;
+    let length = table.borrow().len() as i64;
+    let start = opt_integer(lua, &args, 1, "unpack", 1)?;
+    let end = opt_integer(lua, &args, 2, "unpack", length)?;
+    if start > end {
+        return Ok(Vec::new());
+    }
+    if end - start >= 8000 {
+        return Err(lua.error("too many results to unpack"));
+    }
+    let table = table.borrow();
+    Ok((start..=end)
+        .map(|i| table.get(&Value::Number(i as f64)))
+        .collect())
+}
@@ -0,0 +1,358 @@
+//! The cjson library, which converts between Lua values and JSON as lua-cjson does
+
+use super::{arg_error, check_string, library};
+use crate::lua::{
+    value::{format_number, LuaResult, Table, Value},
+    Lua,
+};
+
+/// How deeply tables can be nested when encoding and decoding
+const MAX_DEPTH: usize = 1000;
+
+/// `cjson.null`, which stands for JSON's null in tables
+pub(crate) const NULL: Value = Value::LightUserData(0);
+
+pub(super) fn open(lua: &mut Lua) {
+    let mut cjson = library(&[("encode", encode), ("decode", decode)]);
+    cjson.set_str("null", NULL);
+    lua.set_global("cjson", Value::table(cjson));
+}
+
+fn encode(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    if args.len() != 1 {
+        return Err(arg_error(lua, 0, "encode", "expected 1 argument"));
+    }
+    let mut json = Vec::new();
+    encode_value(&args[0], 0, &mut json).map_err(|e| lua.error(e))?;
+    Ok(vec![Value::string(json)])
+}
+
+fn encode_value(value: &Value, depth: usize, json: &mut Vec<u8>) -> Result<(), String> {
+    match value {
+        Value::Nil | Value::LightUserData(0) => json.extend_from_slice(b"null"),
+        Value::Boolean(b) => json.extend_from_slice(if *b { b"true" } else { b"false" }),
+        Value::Number(n) if !n.is_finite() => {
+            return Err("Cannot serialise number: must not be NaN or Inf".to_string())
+        }
+        Value::Number(n) => json.extend_from_slice(format_number(*n).as_bytes()),
+        Value::String(s) => encode_string(s, json),
+        Value::Table(table) => {
+            if depth >= MAX_DEPTH {
+                return Err(format!(
+                    "Cannot serialise, excessive nesting ({})",
+                    depth + 1
+                ));
+            }
+            let table = table.borrow();
+            match array_length(&table)? {
+                Some(length) => {
+                    json.push(b'[');
+                    for i in 1..=length {
+                        if i > 1 {
+                            json.push(b',');
+                        }
+                        encode_value(&table.get(&Value::Number(i as f64)), depth + 1, json)?;
+                    }
+                    json.push(b']');
+                }
+                None => {
+                    json.push(b'{');
+                    let mut key = Value::Nil;
+                    let mut first = true;
+                    while let Ok(Some((k, v))) = table.next(&key) {
+                        if !first {
+                            json.push(b',');
+                        }
+                        first = false;
+                        match &k {
+                            Value::String(s) => encode_string(s, json),
+                            Value::Number(_) => encode_string(&k.to_bytes().unwrap(), json),
+                            _ => {
+                                return Err(
+                                    "Cannot serialise table: table key must be a number or string"
+                                        .to_string(),
+                                )
+                            }
+                        }
+                        json.push(b':');
+                        encode_value(&v, depth + 1, json)?;
+                        key = k;
+                    }
+                    json.push(b'}');
+                }
+            }
+        }
+        value => {
+            return Err(format!(
+                "Cannot serialise {}: type not supported",
+                value.type_name()
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// The length of a table that's encoded as an array, which is one whose keys are all positive
+/// integers, or `None` for one encoded as an object
+fn array_length(table: &Table) -> Result<Option<usize>, String> {
+    let mut max = 0;
+    let mut count = 0;
+    let mut key = Value::Nil;
+    while let Ok(Some((k, _))) = table.next(&key) {
+        match k {
+            Value::Number(n) if n >= 1.0 && n.fract() == 0.0 => {
+                max = max.max(n as usize);
+                count += 1;
+            }
+            _ => return Ok(None),
+        }
+        key = k;
+    }
+    if count == 0 {
+        return Ok(None);
+    }
+    // As lua-cjson's default of encoding sparse arrays with a ratio of 2 and a safe size of 10
+    if max > 10 && max > count * 2 {
+        return Err("Cannot serialise table: excessively sparse array".to_string());
+    }
+    Ok(Some(max))
+}
+
+fn encode_string(s: &[u8], json: &mut Vec<u8>) {
+    json.push(b'"');
+    for &c in s {
+        match c {
+            b'"' => json.extend_from_slice(b"\\\""),
+            b'\\' => json.extend_from_slice(b"\\\\"),
+            b'/' => json.extend_from_slice(b"\\/"),
+            b'\n' => json.extend_from_slice(b"\\n"),
+            b'\r' => json.extend_from_slice(b"\\r"),
+            b'\t' => json.extend_from_slice(b"\\t"),
+            0x08 => json.extend_from_slice(b"\\b"),
+            0x0c => json.extend_from_slice(b"\\f"),
+            c if c < 0x20 || c == 0x7f => {
+                json.extend_from_slice(format!("\\u{:04x}", c).as_bytes())
+            }
+            c => json.push(c),
+        }
+    }
+    json.push(b'"');
+}
+
+fn decode(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let json = check_string(lua, &args, 0, "decode")?;
+    let mut decoder = Decoder {
+        json: &json,
+        pos: 0,
+    };
+    let value = decoder
+        .value(0)
+        .and_then(|value| {
+            decoder.skip_whitespace();
+            match decoder.pos < json.len() {
+                true => Err(decoder.error("the end")),
+                false => Ok(value),
+            }
+        })
+        .map_err(|e| lua.error(e))?;
+    Ok(vec![value])
+}
+
+struct Decoder<'a> {
+    json: &'a [u8],
+    pos: usize,
+}
+
+impl Decoder<'_> {
+    fn error(&self, expected: &str) -> String {
+        let found = match self.json.get(self.pos) {
+            None => "T_END".to_string(),
+            Some(_) => "invalid token".to_string(),
+        };
+        format!(
+            "Expected {} but found {} at character {}",
+            expected,
+            found,
+            self.pos + 1
+        )
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.json.get(self.pos), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn literal(&mut self, literal: &[u8], value: Value) -> Result<Value, String> {
+        if self.json[self.pos..].starts_with(literal) {
+            self.pos += literal.len();
+            Ok(value)
+        } else {
+            Err(self.error("value"))
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, String> {
+        self.skip_whitespace();
+        match self.json.get(self.pos) {
+            Some(b'{') | Some(b'[') if depth >= MAX_DEPTH => Err(format!(
+                "Found too many nested data structures ({}) at character {}",
+                depth + 1,
+                self.pos + 1
+            )),
+            Some(b'{') => self.object(depth),
+            Some(b'[') => self.array(depth),
+            Some(b'"') => Ok(Value::string(self.string()?)),
+            Some(b't') => self.literal(b"true", Value::Boolean(true)),
+            Some(b'f') => self.literal(b"false", Value::Boolean(false)),
+            Some(b'n') => self.literal(b"null", NULL),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ => Err(self.error("value")),
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        while matches!(
+            self.json.get(self.pos),
+            Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')
+        ) {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.json[start..self.pos])
+            .ok()
+            .and_then(|s| s.parse::<f64>().ok())
+            .map(Value::Number)
+            .ok_or_else(|| {
+                self.pos = start;
+                self.error("value")
+            })
+    }
+
+    fn string(&mut self) -> Result<Vec<u8>, String> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut s = Vec::new();
+        loop {
+            let Some(&c) = self.json.get(self.pos) else {
+                self.pos = start;
+                return Err(self.error("value"));
+            };
+            self.pos += 1;
+            match c {
+                b'"' => return Ok(s),
+                b'\\' => {
+                    let Some(&escape) = self.json.get(self.pos) else {
+                        continue;
+                    };
+                    self.pos += 1;
+                    match escape {
+                        b'"' | b'\\' | b'/' => s.push(escape),
+                        b'b' => s.push(0x08),
+                        b'f' => s.push(0x0c),
+                        b'n' => s.push(b'\n'),
+                        b'r' => s.push(b'\r'),
+                        b't' => s.push(b'\t'),
+                        b'u' => {
+                            let c = self.unicode_escape().ok_or_else(|| {
+                                self.pos = start;
+                                self.error("value")
+                            })?;
+                            let mut buffer = [0; 4];
+                            s.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                        }
+                        _ => {
+                            self.pos = start;
+                            return Err(self.error("value"));
+                        }
+                    }
+                }
+                c => s.push(c),
+            }
+        }
+    }
+
+    /// Decode "\uXXXX", which was read up to the 'u', including surrogate pairs
+    fn unicode_escape(&mut self) -> Option<char> {
+        let hex = |decoder: &mut Self| -> Option<u32> {
+            let digits = decoder.json.get(decoder.pos..decoder.pos + 4)?;
+            let code = u32::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()?;
+            decoder.pos += 4;
+            Some(code)
+        };
+        let code = hex(self)?;
+        if (0xD800..0xDC00).contains(&code) {
+            if !self.json[self.pos..].starts_with(b"\\u") {
+                return None;
+            }
+            self.pos += 2;
+            let low = hex(self)?;
+            if !(0xDC00..0xE000).contains(&low) {
+                return None;
+            }
+            return char::from_u32(0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00));
+        }
+        char::from_u32(code)
+    }
+
+    fn array(&mut self, depth: usize) -> Result<Value, String> {
+        self.pos += 1;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.json.get(self.pos) == Some(&b']') {
+            self.pos += 1;
+            return Ok(Value::table(Table::new()));
+        }
+        loop {
+            values.push(self.value(depth + 1)?);
+            self.skip_whitespace();
+            match self.json.get(self.pos) {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    break;
+                }
+                _ => return Err(self.error("comma or array end")),
+            }
+        }
+        let mut table = Table::new();
+        for (i, value) in values.into_iter().enumerate() {
+            table.set(Value::Number((i + 1) as f64), value).unwrap();
+        }
+        Ok(Value::table(table))
+    }
+
+    fn object(&mut self, depth: usize) -> Result<Value, String> {
+        self.pos += 1;
+        let mut table = Table::new();
+        self.skip_whitespace();
+        if self.json.get(self.pos) == Some(&b'}') {
+            self.pos += 1;
+            return Ok(Value::table(table));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.json.get(self.pos) != Some(&b'"') {
+                return Err(self.error("object key string"));
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            if self.json.get(self.pos) != Some(&b':') {
+                return Err(self.error("colon"));
+            }
+            self.pos += 1;
+            let value = self.value(depth + 1)?;
+            table.set(Value::string(key), value).unwrap();
+            self.skip_whitespace();
+            match self.json.get(self.pos) {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    break;
+                }
+                _ => return Err(self.error("comma or object end")),
+            }
+        }
+        Ok(Value::table(table))
+    }
+}
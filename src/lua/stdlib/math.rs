@@ -0,0 +1,121 @@
+use super::{arg_error, check_integer, check_number, library};
+use crate::lua::{
+    value::{LuaResult, Value},
+    Lua,
+};
+use std::{cell::Cell, rc::Rc};
+
+pub(super) fn open(lua: &mut Lua) {
+    let mut math = library(&[
+        ("abs", |lua, args| unary(lua, args, "abs", f64::abs)),
+        ("acos", |lua, args| unary(lua, args, "acos", f64::acos)),
+        ("asin", |lua, args| unary(lua, args, "asin", f64::asin)),
+        ("atan", |lua, args| unary(lua, args, "atan", f64::atan)),
+        ("ceil", |lua, args| unary(lua, args, "ceil", f64::ceil)),
+        ("cos", |lua, args| unary(lua, args, "cos", f64::cos)),
+        ("deg", |lua, args| unary(lua, args, "deg", f64::to_degrees)),
+        ("exp", |lua, args| unary(lua, args, "exp", f64::exp)),
+        ("floor", |lua, args| unary(lua, args, "floor", f64::floor)),
+        ("log", |lua, args| unary(lua, args, "log", f64::ln)),
+        ("log10", |lua, args| unary(lua, args, "log10", f64::log10)),
+        ("rad", |lua, args| unary(lua, args, "rad", f64::to_radians)),
+        ("sin", |lua, args| unary(lua, args, "sin", f64::sin)),
+        ("sqrt", |lua, args| unary(lua, args, "sqrt", f64::sqrt)),
+        ("tan", |lua, args| unary(lua, args, "tan", f64::tan)),
+        ("atan2", |lua, args| binary(lua, args, "atan2", f64::atan2)),
+        ("fmod", |lua, args| binary(lua, args, "fmod", |a, b| a % b)),
+        ("pow", |lua, args| binary(lua, args, "pow", f64::powf)),
+        ("max", |lua, args| fold(lua, args, "max", f64::max)),
+        ("min", |lua, args| fold(lua, args, "min", f64::min)),
+        ("modf", modf),
+    ]);
+    math.set_str("huge", Value::Number(f64::INFINITY));
+    math.set_str("pi", Value::Number(std::f64::consts::PI));
+
+    // Every script starts from the same seed, so that scripts are deterministic
+    let state = Rc::new(Cell::new(0));
+    seed(&state, 0);
+    let random_state = Rc::clone(&state);
+    math.set_str(
+        "random",
+        Value::native("random", move |lua, args| random(lua, args, &random_state)),
+    );
+    math.set_str(
+        "randomseed",
+        Value::native("randomseed", move |lua, args| {
+            seed(&state, check_integer(lua, &args, 0, "randomseed")? as i32);
+            Ok(Vec::new())
+        }),
+    );
+    lua.set_global("math", Value::table(math));
+}
+
+fn unary(lua: &mut Lua, args: Vec<Value>, name: &str, f: fn(f64) -> f64) -> LuaResult<Vec<Value>> {
+    let n = check_number(lua, &args, 0, name)?;
+    Ok(vec![Value::Number(f(n))])
+}
+
+fn binary(
+    lua: &mut Lua,
+    args: Vec<Value>,
+    name: &str,
+    f: fn(f64, f64) -> f64,
+) -> LuaResult<Vec<Value>> {
+    let a = check_number(lua, &args, 0, name)?;
+    let b = check_number(lua, &args, 1, name)?;
+    Ok(vec![Value::Number(f(a, b))])
+}
+
+fn fold(
+    lua: &mut Lua,
+    args: Vec<Value>,
+    name: &str,
+    f: fn(f64, f64) -> f64,
+) -> LuaResult<Vec<Value>> {
+    let mut result = check_number(lua, &args, 0, name)?;
+    for i in 1..args.len() {
+        result = f(result, check_number(lua, &args, i, name)?);
+    }
+    Ok(vec![Value::Number(result)])
+}
+
+fn modf(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let n = check_number(lua, &args, 0, "modf")?;
+    Ok(vec![Value::Number(n.trunc()), Value::Number(n.fract())])
+}
+
+/// Seed the generator as `srand48` does, which Redis uses for `math.random`
+fn seed(state: &Cell<u64>, seed: i32) {
+    state.set(((seed as u32 as u64) << 16) | 0x330E);
+}
+
+/// The next number from the generator, as `lrand48` returns it
+fn next(state: &Cell<u64>) -> i32 {
+    let x = state.get().wrapping_mul(0x5DEECE66D).wrapping_add(0xB) & ((1 << 48) - 1);
+    state.set(x);
+    (x >> 17) as i32
+}
+
+fn random(lua: &mut Lua, args: Vec<Value>, state: &Cell<u64>) -> LuaResult<Vec<Value>> {
+    let r = (next(state) % i32::MAX) as f64 / i32::MAX as f64;
+    let n = match args.len() {
+        0 => r,
+        1 => {
+            let upper = check_integer(lua, &args, 0, "random")?;
+            if upper < 1 {
+                return Err(arg_error(lua, 0, "random", "interval is empty"));
+            }
+            (r * upper as f64).floor() + 1.0
+        }
+        2 => {
+            let lower = check_integer(lua, &args, 0, "random")?;
+            let upper = check_integer(lua, &args, 1, "random")?;
+            if lower > upper {
+                return Err(arg_error(lua, 1, "random", "interval is empty"));
+            }
+            (r * (upper - lower + 1) as f64).floor() + lower as f64
+        }
+        _ => return Err(lua.error("wrong number of arguments")),
+    };
+    Ok(vec![Value::Number(n)])
+}
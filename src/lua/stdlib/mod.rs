@@ -0,0 +1,116 @@
+//! The libraries available to scripts, a subset of the Lua 5.1 standard libraries along with cjson
+
+mod base;
+mod cjson;
+mod math;
+mod pattern;
+mod string;
+mod table;
+
+use super::{
+    value::{LuaResult, Table, TableRef, Value},
+    Lua,
+};
+use std::rc::Rc;
+
+type Builtin = fn(&mut Lua<'_>, Vec<Value>) -> LuaResult<Vec<Value>>;
+
+/// Load the libraries into the globals of an interpreter
+pub(super) fn open(lua: &mut Lua) {
+    base::open(lua);
+    let string = string::open(lua);
+    let mut metatable = Table::new();
+    metatable.set_str("__index", Value::Table(string));
+    lua.set_string_metatable(metatable);
+    table::open(lua);
+    math::open(lua);
+    cjson::open(lua);
+}
+
+/// Make a library table from its functions
+fn library(functions: &[(&'static str, Builtin)]) -> Table {
+    let mut table = Table::new();
+    for &(name, function) in functions {
+        table.set_str(name, Value::native(name, function));
+    }
+    table
+}
+
+fn arg(args: &[Value], i: usize) -> Value {
+    args.get(i).cloned().unwrap_or_default()
+}
+
+/// An error about an argument, such as "bad argument #1 to 'insert' (table expected, got nil)"
+fn arg_error(lua: &Lua, i: usize, function: &str, message: &str) -> super::value::LuaError {
+    lua.error(format!(
+        "bad argument #{} to '{}' ({})",
+        i + 1,
+        function,
+        message
+    ))
+}
+
+fn type_error(
+    lua: &Lua,
+    args: &[Value],
+    i: usize,
+    function: &str,
+    expected: &str,
+) -> super::value::LuaError {
+    let got = match args.get(i) {
+        Some(value) => value.type_name(),
+        None => "no value",
+    };
+    arg_error(
+        lua,
+        i,
+        function,
+        &format!("{} expected, got {}", expected, got),
+    )
+}
+
+fn check_table(lua: &Lua, args: &[Value], i: usize, function: &str) -> LuaResult<TableRef> {
+    match args.get(i) {
+        Some(Value::Table(table)) => Ok(Rc::clone(table)),
+        _ => Err(type_error(lua, args, i, function, "table")),
+    }
+}
+
+fn check_number(lua: &Lua, args: &[Value], i: usize, function: &str) -> LuaResult<f64> {
+    args.get(i)
+        .and_then(Value::to_number)
+        .ok_or_else(|| type_error(lua, args, i, function, "number"))
+}
+
+/// A number argument truncated to an integer, as `luaL_checkinteger` does
+fn check_integer(lua: &Lua, args: &[Value], i: usize, function: &str) -> LuaResult<i64> {
+    check_number(lua, args, i, function).map(|n| n as i64)
+}
+
+fn opt_integer(
+    lua: &Lua,
+    args: &[Value],
+    i: usize,
+    function: &str,
+    default: i64,
+) -> LuaResult<i64> {
+    match args.get(i) {
+        None | Some(Value::Nil) => Ok(default),
+        Some(_) => check_integer(lua, args, i, function),
+    }
+}
+
+/// A string argument, converting numbers as Lua does
+fn check_string(lua: &Lua, args: &[Value], i: usize, function: &str) -> LuaResult<Rc<[u8]>> {
+    args.get(i)
+        .and_then(Value::to_bytes)
+        .ok_or_else(|| type_error(lua, args, i, function, "string"))
+}
+
+/// Check that a table can be modified, as the globals and libraries can't
+fn check_writable(lua: &Lua, table: &TableRef) -> LuaResult<()> {
+    if table.borrow().readonly {
+        return Err(lua.error("Attempt to modify a readonly table"));
+    }
+    Ok(())
+}
@@ -0,0 +1,402 @@
+//! Lua patterns, as used by `string.find`, `string.match`, `string.gmatch` and `string.gsub`
+//!
+//! This follows the matcher of Lua 5.1's lstrlib.c, including its error messages.
+
+use crate::lua::value::Value;
+
+const MAX_CAPTURES: usize = 32;
+
+/// The special characters, without which a pattern is searched for as plain text
+pub(super) const SPECIALS: &[u8] = b"^$*+?.([%-";
+
+#[derive(Clone, Copy)]
+enum CaptureLength {
+    Unfinished,
+    /// A position capture, `()`
+    Position,
+    Finished(usize),
+}
+
+pub(super) struct Matcher<'a> {
+    src: &'a [u8],
+    pattern: &'a [u8],
+    captures: Vec<(usize, CaptureLength)>,
+}
+
+type MatchResult = Result<Option<usize>, String>;
+
+impl<'a> Matcher<'a> {
+    pub(super) fn new(src: &'a [u8], pattern: &'a [u8]) -> Self {
+        Matcher {
+            src,
+            pattern,
+            captures: Vec::new(),
+        }
+    }
+
+    /// Match the pattern from `p` against the subject from `s`, returning where the match ends
+    pub(super) fn find_at(&mut self, s: usize, p: usize) -> MatchResult {
+        self.captures.clear();
+        self.do_match(s, p)
+    }
+
+    fn do_match(&mut self, mut s: usize, mut p: usize) -> MatchResult {
+        let pattern = self.pattern;
+        loop {
+            if p == pattern.len() {
+                return Ok(Some(s));
+            }
+            match pattern[p] {
+                b'(' => {
+                    return if pattern.get(p + 1) == Some(&b')') {
+                        self.start_capture(s, p + 2, CaptureLength::Position)
+                    } else {
+                        self.start_capture(s, p + 1, CaptureLength::Unfinished)
+                    };
+                }
+                b')' => return self.end_capture(s, p + 1),
+                b'%' if p + 1 < pattern.len() => match pattern[p + 1] {
+                    b'b' => match self.match_balance(s, p + 2)? {
+                        Some(end) => {
+                            s = end;
+                            p += 4;
+                            continue;
+                        }
+                        None => return Ok(None),
+                    },
+                    b'f' => {
+                        p += 2;
+                        if pattern.get(p) != Some(&b'[') {
+                            return Err("missing '[' after '%f' in pattern".to_string());
+                        }
+                        let end = self.class_end(p)?;
+                        let previous = if s == 0 { 0 } else { self.src[s - 1] };
+                        let current = self.src.get(s).copied().unwrap_or(0);
+                        if !self.match_bracket_class(previous, p, end - 1)
+                            && self.match_bracket_class(current, p, end - 1)
+                        {
+                            p = end;
+                            continue;
+                        }
+                        return Ok(None);
+                    }
+                    c if c.is_ascii_digit() => match self.match_capture(s, c)? {
+                        Some(end) => {
+                            s = end;
+                            p += 2;
+                            continue;
+                        }
+                        None => return Ok(None),
+                    },
+                    _ => {}
+                },
+                b'$' if p + 1 == pattern.len() => {
+                    return Ok((s == self.src.len()).then_some(s));
+                }
+                _ => {}
+            }
+
+            let end = self.class_end(p)?;
+            let matched = s < self.src.len() && self.single_match(self.src[s], p, end);
+            match pattern.get(end) {
+                Some(b'?') => {
+                    if matched {
+                        if let Some(result) = self.do_match(s + 1, end + 1)? {
+                            return Ok(Some(result));
+                        }
+                    }
+                    p = end + 1;
+                }
+                Some(b'*') => return self.max_expand(s, p, end),
+                Some(b'+') => {
+                    return if matched {
+                        self.max_expand(s + 1, p, end)
+                    } else {
+                        Ok(None)
+                    };
+                }
+                Some(b'-') => return self.min_expand(s, p, end),
+                _ => {
+                    if !matched {
+                        return Ok(None);
+                    }
+                    s += 1;
+                    p = end;
+                }
+            }
+        }
+    }
+
+    /// Where the single character class starting at `p` ends
+    fn class_end(&self, mut p: usize) -> Result<usize, String> {
+        let pattern = self.pattern;
+        let c = pattern[p];
+        p += 1;
+        if c == b'%' {
+            if p >= pattern.len() {
+                return Err("malformed pattern (ends with '%')".to_string());
+            }
+            return Ok(p + 1);
+        }
+        if c == b'[' {
+            if pattern.get(p) == Some(&b'^') {
+                p += 1;
+            }
+            // The first character is part of the set even if it's ']'
+            loop {
+                if p >= pattern.len() {
+                    return Err("malformed pattern (missing ']')".to_string());
+                }
+                let c = pattern[p];
+                p += 1;
+                if c == b'%' && p < pattern.len() {
+                    p += 1;
+                }
+                if pattern.get(p) == Some(&b']') {
+                    return Ok(p + 1);
+                }
+            }
+        }
+        Ok(p)
+    }
+
+    fn single_match(&self, c: u8, p: usize, end: usize) -> bool {
+        match self.pattern[p] {
+            b'.' => true,
+            b'%' => match_class(c, self.pattern[p + 1]),
+            b'[' => self.match_bracket_class(c, p, end - 1),
+            pc => pc == c,
+        }
+    }
+
+    /// Match a set such as "[^a-z%d]", from the '[' at `p` to the ']' at `end`
+    fn match_bracket_class(&self, c: u8, mut p: usize, end: usize) -> bool {
+        let pattern = self.pattern;
+        let mut found = true;
+        if pattern[p + 1] == b'^' {
+            found = false;
+            p += 1;
+        }
+        p += 1;
+        while p < end {
+            if pattern[p] == b'%' {
+                p += 1;
+                if match_class(c, pattern[p]) {
+                    return found;
+                }
+            } else if pattern[p + 1] == b'-' && p + 2 < end {
+                if pattern[p] <= c && c <= pattern[p + 2] {
+                    return found;
+                }
+                p += 2;
+            } else if pattern[p] == c {
+                return found;
+            }
+            p += 1;
+        }
+        !found
+    }
+
+    fn max_expand(&mut self, s: usize, p: usize, end: usize) -> MatchResult {
+        let mut count = 0;
+        while s + count < self.src.len() && self.single_match(self.src[s + count], p, end) {
+            count += 1;
+        }
+        // Try the longest repetition first
+        loop {
+            if let Some(result) = self.do_match(s + count, end + 1)? {
+                return Ok(Some(result));
+            }
+            if count == 0 {
+                return Ok(None);
+            }
+            count -= 1;
+        }
+    }
+
+    fn min_expand(&mut self, mut s: usize, p: usize, end: usize) -> MatchResult {
+        loop {
+            if let Some(result) = self.do_match(s, end + 1)? {
+                return Ok(Some(result));
+            }
+            if s < self.src.len() && self.single_match(self.src[s], p, end) {
+                s += 1;
+            } else {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn start_capture(&mut self, s: usize, p: usize, length: CaptureLength) -> MatchResult {
+        if self.captures.len() >= MAX_CAPTURES {
+            return Err("too many captures".to_string());
+        }
+        self.captures.push((s, length));
+        let result = self.do_match(s, p)?;
+        if result.is_none() {
+            self.captures.pop();
+        }
+        Ok(result)
+    }
+
+    fn end_capture(&mut self, s: usize, p: usize) -> MatchResult {
+        let Some(i) = self
+            .captures
+            .iter()
+            .rposition(|(_, length)| matches!(length, CaptureLength::Unfinished))
+        else {
+            return Err("invalid pattern capture".to_string());
+        };
+        self.captures[i].1 = CaptureLength::Finished(s - self.captures[i].0);
+        let result = self.do_match(s, p)?;
+        if result.is_none() {
+            self.captures[i].1 = CaptureLength::Unfinished;
+        }
+        Ok(result)
+    }
+
+    fn match_balance(&self, s: usize, p: usize) -> MatchResult {
+        if p + 1 >= self.pattern.len() {
+            return Err("unbalanced pattern".to_string());
+        }
+        if self.src.get(s) != Some(&self.pattern[p]) {
+            return Ok(None);
+        }
+        let (open, close) = (self.pattern[p], self.pattern[p + 1]);
+        let mut depth = 1;
+        for i in s + 1..self.src.len() {
+            let c = self.src[i];
+            if c == close {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(Some(i + 1));
+                }
+            } else if c == open {
+                depth += 1;
+            }
+        }
+        Ok(None)
+    }
+
+    /// Match a back reference such as "%1"
+    fn match_capture(&self, s: usize, digit: u8) -> MatchResult {
+        let i = (digit as usize).wrapping_sub(b'1' as usize);
+        let Some(&(start, CaptureLength::Finished(length))) = self.captures.get(i) else {
+            return Err("invalid capture index".to_string());
+        };
+        let captured = &self.src[start..start + length];
+        Ok(self.src[s..].starts_with(captured).then_some(s + length))
+    }
+
+    /// The text of a match from `s` to `e`
+    pub(super) fn matched(&self, s: usize, e: usize) -> &'a [u8] {
+        &self.src[s..e]
+    }
+
+    /// A capture of the last match, which went from `s` to `e`
+    ///
+    /// Without captures, the first one is the whole match.
+    pub(super) fn capture(&self, i: usize, s: usize, e: usize) -> Result<Value, String> {
+        if i >= self.captures.len() {
+            if i == 0 {
+                return Ok(Value::string(&self.src[s..e]));
+            }
+            return Err("invalid capture index".to_string());
+        }
+        match self.captures[i] {
+            (_, CaptureLength::Unfinished) => Err("unfinished capture".to_string()),
+            (start, CaptureLength::Position) => Ok(Value::Number((start + 1) as f64)),
+            (start, CaptureLength::Finished(length)) => {
+                Ok(Value::string(&self.src[start..start + length]))
+            }
+        }
+    }
+
+    /// The captures of the last match, or the whole match if there were none and `whole` is set
+    pub(super) fn captures(&self, s: usize, e: usize, whole: bool) -> Result<Vec<Value>, String> {
+        let count = match self.captures.len() {
+            0 if whole => 1,
+            count => count,
+        };
+        (0..count).map(|i| self.capture(i, s, e)).collect()
+    }
+}
+
+/// Match a character class such as "%d", where upper case negates the class
+fn match_class(c: u8, class: u8) -> bool {
+    let matches = match class.to_ascii_lowercase() {
+        b'a' => c.is_ascii_alphabetic(),
+        b'c' => c.is_ascii_control(),
+        b'd' => c.is_ascii_digit(),
+        b'l' => c.is_ascii_lowercase(),
+        b'p' => c.is_ascii_punctuation(),
+        b's' => matches!(c, b' ' | b'\t' | b'\n' | b'\r' | 0x0b | 0x0c),
+        b'u' => c.is_ascii_uppercase(),
+        b'w' => c.is_ascii_alphanumeric(),
+        b'x' => c.is_ascii_hexdigit(),
+        b'z' => c == 0,
+        _ => return class == c,
+    };
+    if class.is_ascii_uppercase() {
+        !matches
+    } else {
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The first match of a pattern, as string.match returns it
+    fn first_match(src: &str, pattern: &str) -> Option<Vec<String>> {
+        let mut matcher = Matcher::new(src.as_bytes(), pattern.as_bytes());
+        let anchored = pattern.starts_with('^');
+        let end = if anchored { 0 } else { src.len() };
+        for s in 0..=end {
+            if let Some(e) = matcher.find_at(s, anchored as usize).unwrap() {
+                let captures = matcher.captures(s, e, true).unwrap();
+                return Some(captures.iter().map(|v| format!("{:?}", v)).collect());
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn test_patterns() {
+        assert_eq!(
+            first_match("hello world", "o w"),
+            Some(vec!["\"o w\"".into()])
+        );
+        assert_eq!(
+            first_match("key=value", "(%w+)=(%w+)"),
+            Some(vec!["\"key\"".into(), "\"value\"".into()])
+        );
+        assert_eq!(first_match("  x", "^%s*()"), Some(vec!["3".into()]));
+        assert_eq!(
+            first_match("f(a(b)c)", "%b()"),
+            Some(vec!["\"(a(b)c)\"".into()])
+        );
+        assert_eq!(first_match("aaab", "a-b"), Some(vec!["\"aaab\"".into()]));
+        assert_eq!(first_match("abab", "(ab)%1"), Some(vec!["\"ab\"".into()]));
+        assert_eq!(
+            first_match("THE (quick) fox", "%f[%a]%a+"),
+            Some(vec!["\"THE\"".into()])
+        );
+        assert_eq!(first_match("x]y", "[]x]+"), Some(vec!["\"x]\"".into()]));
+        assert_eq!(first_match("abc", "^b"), None);
+        assert_eq!(first_match("abc", "c$"), Some(vec!["\"c\"".into()]));
+    }
+
+    #[test]
+    fn test_pattern_errors() {
+        let error = |pattern: &str| Matcher::new(b"abc", pattern.as_bytes()).find_at(0, 0);
+        assert_eq!(error("[a").unwrap_err(), "malformed pattern (missing ']')");
+        assert_eq!(
+            error("a%").unwrap_err(),
+            "malformed pattern (ends with '%')"
+        );
+        assert_eq!(error("a)").unwrap_err(), "invalid pattern capture");
+        assert_eq!(error("%1").unwrap_err(), "invalid capture index");
+    }
+}
@@ -0,0 +1,508 @@
+use super::{
+    arg, arg_error,
+    base::to_string,
+    check_integer, check_number, check_string, library, opt_integer,
+    pattern::{Matcher, SPECIALS},
+    type_error,
+};
+use crate::lua::{
+    value::{format_g, LuaResult, TableRef, Value},
+    Lua,
+};
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
+
+/// The longest string `string.rep` can make, as the longest string Redis accepts
+const MAX_STRING_LENGTH: usize = 512 * 1024 * 1024;
+
+pub(super) fn open(lua: &mut Lua) -> TableRef {
+    let string = Rc::new(RefCell::new(library(&[
+        ("byte", byte),
+        ("char", char),
+        ("find", find),
+        ("format", format),
+        ("gmatch", gmatch),
+        ("gsub", gsub),
+        ("len", len),
+        ("lower", lower),
+        ("match", match_),
+        ("rep", rep),
+        ("reverse", reverse),
+        ("sub", sub),
+        ("upper", upper),
+    ])));
+    lua.set_global("string", Value::Table(Rc::clone(&string)));
+    string
+}
+
+/// Convert a relative string position, where negative positions count from the end
+fn relative_position(position: i64, length: usize) -> i64 {
+    if position < 0 {
+        length as i64 + position + 1
+    } else {
+        position
+    }
+}
+
+fn byte(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let s = check_string(lua, &args, 0, "byte")?;
+    let start = relative_position(opt_integer(lua, &args, 1, "byte", 1)?, s.len()).max(1);
+    let end = relative_position(opt_integer(lua, &args, 2, "byte", start)?, s.len());
+    let end = end.min(s.len() as i64);
+    if start > end {
+        return Ok(Vec::new());
+    }
+    Ok(s[start as usize - 1..end as usize]
+        .iter()
+        .map(|&b| Value::Number(b as f64))
+        .collect())
+}
+
+fn char(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let mut s = Vec::with_capacity(args.len());
+    for i in 0..args.len() {
+        let c = check_integer(lua, &args, i, "char")?;
+        if !(0..=255).contains(&c) {
+            return Err(arg_error(lua, i, "char", "invalid value"));
+        }
+        s.push(c as u8);
+    }
+    Ok(vec![Value::string(s)])
+}
+
+fn find(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    find_or_match(lua, args, true)
+}
+
+fn match_(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    find_or_match(lua, args, false)
+}
+
+fn find_or_match(lua: &mut Lua, args: Vec<Value>, find: bool) -> LuaResult<Vec<Value>> {
+    let name = if find { "find" } else { "match" };
+    let s = check_string(lua, &args, 0, name)?;
+    let pattern = check_string(lua, &args, 1, name)?;
+    let init = relative_position(opt_integer(lua, &args, 2, name, 1)?, s.len()).max(1) as usize;
+    if init > s.len() + 1 {
+        return Ok(vec![Value::Nil]);
+    }
+
+    let plain = arg(&args, 3).is_truthy() || !pattern.iter().any(|c| SPECIALS.contains(c));
+    if find && plain {
+        let position = if pattern.is_empty() {
+            Some(0)
+        } else {
+            s[init - 1..]
+                .windows(pattern.len())
+                .position(|window| window == &pattern[..])
+        };
+        return Ok(match position {
+            Some(i) => vec![
+                Value::Number((init + i) as f64),
+                Value::Number((init - 1 + i + pattern.len()) as f64),
+            ],
+            None => vec![Value::Nil],
+        });
+    }
+
+    let anchored = pattern.first() == Some(&b'^');
+    let p = anchored as usize;
+    let mut matcher = Matcher::new(&s, &pattern);
+    let mut start = init - 1;
+    loop {
+        if let Some(end) = matcher.find_at(start, p).map_err(|e| lua.error(e))? {
+            if find {
+                let mut values = vec![Value::Number((start + 1) as f64), Value::Number(end as f64)];
+                values.extend(
+                    matcher
+                        .captures(start, end, false)
+                        .map_err(|e| lua.error(e))?,
+                );
+                return Ok(values);
+            }
+            return matcher.captures(start, end, true).map_err(|e| lua.error(e));
+        }
+        start += 1;
+        if anchored || start > s.len() {
+            return Ok(vec![Value::Nil]);
+        }
+    }
+}
+
+fn gmatch(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let s = check_string(lua, &args, 0, "gmatch")?;
+    let pattern = check_string(lua, &args, 1, "gmatch")?;
+    let position = Cell::new(0);
+    let iterator = Value::native("gmatch_aux", move |lua, _| {
+        let mut matcher = Matcher::new(&s, &pattern);
+        for start in position.get()..=s.len() {
+            if let Some(end) = matcher.find_at(start, 0).map_err(|e| lua.error(e))? {
+                // An empty match moves on by one character, so that it doesn't repeat
+                position.set(if end == start { end + 1 } else { end });
+                return matcher.captures(start, end, true).map_err(|e| lua.error(e));
+            }
+        }
+        position.set(s.len() + 1);
+        Ok(vec![Value::Nil])
+    });
+    Ok(vec![iterator])
+}
+
+fn gsub(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let s = check_string(lua, &args, 0, "gsub")?;
+    let pattern = check_string(lua, &args, 1, "gsub")?;
+    let replacement = arg(&args, 2);
+    if !matches!(
+        replacement,
+        Value::Number(_) | Value::String(_) | Value::Table(_) | Value::Function(_)
+    ) {
+        return Err(arg_error(lua, 2, "gsub", "string/function/table expected"));
+    }
+    let max = match args.get(3) {
+        None | Some(Value::Nil) => usize::MAX,
+        Some(_) => check_integer(lua, &args, 3, "gsub")?.max(0) as usize,
+    };
+
+    let anchored = pattern.first() == Some(&b'^');
+    let p = anchored as usize;
+    let mut matcher = Matcher::new(&s, &pattern);
+    let mut result = Vec::with_capacity(s.len());
+    let mut start = 0;
+    let mut count = 0;
+    while count < max {
+        let end = matcher.find_at(start, p).map_err(|e| lua.error(e))?;
+        if let Some(end) = end {
+            count += 1;
+            add_replacement(lua, &matcher, start, end, &replacement, &mut result)?;
+        }
+        match end {
+            Some(end) if end > start => start = end,
+            _ if start < s.len() => {
+                result.push(s[start]);
+                start += 1;
+            }
+            _ => break,
+        }
+        if anchored {
+            break;
+        }
+    }
+    result.extend_from_slice(&s[start.min(s.len())..]);
+    Ok(vec![Value::string(result), Value::Number(count as f64)])
+}
+
+fn add_replacement(
+    lua: &mut Lua,
+    matcher: &Matcher,
+    start: usize,
+    end: usize,
+    replacement: &Value,
+    result: &mut Vec<u8>,
+) -> LuaResult<()> {
+    let value = match replacement {
+        Value::Table(_) => {
+            let key = matcher.capture(0, start, end).map_err(|e| lua.error(e))?;
+            lua.index(replacement, &key, None)?
+        }
+        Value::Function(_) => {
+            let captures = matcher
+                .captures(start, end, true)
+                .map_err(|e| lua.error(e))?;
+            lua.call(replacement, captures)?
+                .into_iter()
+                .next()
+                .unwrap_or_default()
+        }
+        _ => {
+            // A string, where "%1" is replaced with a capture and "%0" with the whole match
+            let replacement = replacement.to_bytes().unwrap();
+            let mut i = 0;
+            while i < replacement.len() {
+                let c = replacement[i];
+                i += 1;
+                if c != b'%' || i == replacement.len() {
+                    result.push(c);
+                    continue;
+                }
+                let c = replacement[i];
+                i += 1;
+                if !c.is_ascii_digit() {
+                    result.push(c);
+                } else if c == b'0' {
+                    result.extend_from_slice(matcher.matched(start, end));
+                } else {
+                    let capture = matcher
+                        .capture((c - b'1') as usize, start, end)
+                        .map_err(|e| lua.error(e))?;
+                    result.extend_from_slice(&capture.to_bytes().unwrap());
+                }
+            }
+            return Ok(());
+        }
+    };
+    match value {
+        // Keep the original text
+        Value::Nil | Value::Boolean(false) => result.extend_from_slice(matcher.matched(start, end)),
+        value => match value.to_bytes() {
+            Some(s) => result.extend_from_slice(&s),
+            None => {
+                return Err(lua.error(format!(
+                    "invalid replacement value (a {})",
+                    value.type_name()
+                )))
+            }
+        },
+    }
+    Ok(())
+}
+
+fn len(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let s = check_string(lua, &args, 0, "len")?;
+    Ok(vec![Value::Number(s.len() as f64)])
+}
+
+fn lower(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let s = check_string(lua, &args, 0, "lower")?;
+    Ok(vec![Value::string(s.to_ascii_lowercase())])
+}
+
+fn upper(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let s = check_string(lua, &args, 0, "upper")?;
+    Ok(vec![Value::string(s.to_ascii_uppercase())])
+}
+
+fn rep(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let s = check_string(lua, &args, 0, "rep")?;
+    let n = check_integer(lua, &args, 1, "rep")?.max(0) as usize;
+    if s.len().saturating_mul(n) > MAX_STRING_LENGTH {
+        return Err(lua.error("resulting string too large"));
+    }
+    Ok(vec![Value::string(s.repeat(n))])
+}
+
+fn reverse(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let s = check_string(lua, &args, 0, "reverse")?;
+    Ok(vec![Value::string(
+        s.iter().rev().copied().collect::<Vec<_>>(),
+    )])
+}
+
+fn sub(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let s = check_string(lua, &args, 0, "sub")?;
+    let start = relative_position(check_integer(lua, &args, 1, "sub")?, s.len()).max(1);
+    let end = relative_position(opt_integer(lua, &args, 2, "sub", -1)?, s.len());
+    let end = end.min(s.len() as i64);
+    if start > end {
+        return Ok(vec![Value::string("")]);
+    }
+    Ok(vec![Value::string(&s[start as usize - 1..end as usize])])
+}
+
+/// A conversion specification of `string.format`, such as "%-5.2f"
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    /// Pad a formatted value to the width
+    fn pad(&self, sign: &str, body: String, numeric: bool) -> Vec<u8> {
+        let length = sign.len() + body.len();
+        if length >= self.width {
+            return format!("{}{}", sign, body).into_bytes();
+        }
+        let padding = self.width - length;
+        if self.left {
+            format!("{}{}{}", sign, body, " ".repeat(padding)).into_bytes()
+        } else if self.zero && numeric {
+            format!("{}{}{}", sign, "0".repeat(padding), body).into_bytes()
+        } else {
+            format!("{}{}{}", " ".repeat(padding), sign, body).into_bytes()
+        }
+    }
+
+    fn sign(&self, negative: bool) -> &'static str {
+        match negative {
+            true => "-",
+            false if self.plus => "+",
+            false if self.space => " ",
+            false => "",
+        }
+    }
+}
+
+fn format(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let format = check_string(lua, &args, 0, "format")?;
+    let mut result = Vec::with_capacity(format.len());
+    let mut arg_index = 0;
+    let mut i = 0;
+    while i < format.len() {
+        let c = format[i];
+        i += 1;
+        if c != b'%' {
+            result.push(c);
+            continue;
+        }
+        if format.get(i) == Some(&b'%') {
+            result.push(b'%');
+            i += 1;
+            continue;
+        }
+
+        let mut spec = Spec::default();
+        while let Some(&flag) = format.get(i) {
+            match flag {
+                b'-' => spec.left = true,
+                b'0' => spec.zero = true,
+                b'+' => spec.plus = true,
+                b' ' => spec.space = true,
+                b'#' => spec.alternate = true,
+                _ => break,
+            }
+            i += 1;
+        }
+        while let Some(digit) = format.get(i).filter(|c| c.is_ascii_digit()) {
+            spec.width = spec.width * 10 + (digit - b'0') as usize;
+            i += 1;
+        }
+        if format.get(i) == Some(&b'.') {
+            i += 1;
+            let mut precision = 0;
+            while let Some(digit) = format.get(i).filter(|c| c.is_ascii_digit()) {
+                precision = precision * 10 + (digit - b'0') as usize;
+                i += 1;
+            }
+            spec.precision = Some(precision);
+        }
+        if spec.width > 99 || spec.precision.is_some_and(|p| p > 99) {
+            return Err(lua.error("invalid format (width or precision too long)"));
+        }
+
+        let Some(&conversion) = format.get(i) else {
+            return Err(lua.error("invalid option '%' to 'format'"));
+        };
+        i += 1;
+        arg_index += 1;
+        if arg_index >= args.len() && conversion != b'%' {
+            return Err(arg_error(lua, arg_index, "format", "no value"));
+        }
+        let formatted = match conversion {
+            b'd' | b'i' | b'u' => {
+                let n = check_number(lua, &args, arg_index, "format")? as i64;
+                let mut digits = n.unsigned_abs().to_string();
+                if let Some(precision) = spec.precision {
+                    if digits.len() < precision {
+                        digits = format!("{}{}", "0".repeat(precision - digits.len()), digits);
+                    }
+                }
+                spec.zero &= spec.precision.is_none();
+                spec.pad(spec.sign(n < 0), digits, true)
+            }
+            b'c' => {
+                let n = check_number(lua, &args, arg_index, "format")? as i64;
+                let mut bytes = spec.pad("", " ".to_string(), false);
+                let position = if spec.left { 0 } else { bytes.len() - 1 };
+                bytes[position] = n as u8;
+                bytes
+            }
+            b'o' | b'x' | b'X' => {
+                let n = check_number(lua, &args, arg_index, "format")? as i64 as u64;
+                let (digits, prefix) = match conversion {
+                    b'o' => (format!("{:o}", n), "0"),
+                    b'x' => (format!("{:x}", n), "0x"),
+                    _ => (format!("{:X}", n), "0X"),
+                };
+                let prefix = if spec.alternate && n != 0 { prefix } else { "" };
+                spec.pad(prefix, digits, true)
+            }
+            b'e' | b'E' | b'f' | b'g' | b'G' => {
+                let n = check_number(lua, &args, arg_index, "format")?;
+                let precision = spec.precision.unwrap_or(6);
+                let body = match conversion {
+                    _ if !n.is_finite() => format_g(n.abs(), precision, false),
+                    b'f' => format!("{:.*}", precision, n.abs()),
+                    b'e' | b'E' => format_e(n.abs(), precision),
+                    _ => format_g(n.abs(), precision, spec.alternate),
+                };
+                let body = if conversion.is_ascii_uppercase() {
+                    body.to_uppercase()
+                } else {
+                    body
+                };
+                spec.zero &= n.is_finite();
+                spec.pad(spec.sign(n.is_sign_negative() && !n.is_nan()), body, true)
+            }
+            b'q' => {
+                let s = check_string(lua, &args, arg_index, "format")?;
+                quote(&s)
+            }
+            b's' => {
+                let value = args[arg_index].clone();
+                let s = match value {
+                    Value::String(_) | Value::Number(_) => value.to_bytes().unwrap(),
+                    Value::Table(_) if lua.to_string_metamethod(&value).is_some() => {
+                        to_string(lua, &value)?.to_bytes().unwrap()
+                    }
+                    _ => return Err(type_error(lua, &args, arg_index, "format", "string")),
+                };
+                let s = match spec.precision {
+                    Some(precision) if precision < s.len() => &s[..precision],
+                    _ => &s[..],
+                };
+                let body = String::from_utf8_lossy(s).len();
+                let mut bytes = Vec::with_capacity(s.len().max(spec.width));
+                let padding = spec.width.saturating_sub(body);
+                if !spec.left {
+                    bytes.resize(padding, b' ');
+                }
+                bytes.extend_from_slice(s);
+                if spec.left {
+                    bytes.resize(bytes.len() + padding, b' ');
+                }
+                bytes
+            }
+            _ => {
+                return Err(lua.error(format!(
+                    "invalid option '%{}' to 'format'",
+                    conversion as char
+                )))
+            }
+        };
+        result.extend_from_slice(&formatted);
+    }
+    Ok(vec![Value::string(result)])
+}
+
+/// Format a number like "%e" in C, with an exponent of at least two digits
+fn format_e(n: f64, precision: usize) -> String {
+    let formatted = format!("{:.*e}", precision, n);
+    let (mantissa, exponent) = formatted.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    let sign = if exponent < 0 { '-' } else { '+' };
+    format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+}
+
+/// Quote a string so that Lua can read it back, as "%q" does
+fn quote(s: &[u8]) -> Vec<u8> {
+    let mut quoted = Vec::with_capacity(s.len() + 2);
+    quoted.push(b'"');
+    for &c in s {
+        match c {
+            b'"' | b'\\' => quoted.extend_from_slice(&[b'\\', c]),
+            b'\n' => quoted.extend_from_slice(b"\\\n"),
+            b'\r' => quoted.extend_from_slice(b"\\r"),
+            0 => quoted.extend_from_slice(b"\\000"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push(b'"');
+    quoted
+}
@@ -0,0 +1,151 @@
+use super::{arg, check_integer, check_table, check_writable, library, opt_integer};
+use crate::lua::{
+    value::{LuaResult, Value},
+    Lua,
+};
+
+pub(super) fn open(lua: &mut Lua) {
+    let table = library(&[
+        ("concat", concat),
+        ("getn", getn),
+        ("insert", insert),
+        ("maxn", maxn),
+        ("remove", remove),
+        ("sort", sort),
+    ]);
+    lua.set_global("table", Value::table(table));
+}
+
+fn concat(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let table = check_table(lua, &args, 0, "concat")?;
+    let separator = match args.get(1) {
+        None | Some(Value::Nil) => None,
+        Some(_) => Some(super::check_string(lua, &args, 1, "concat")?),
+    };
+    let length = table.borrow().len() as i64;
+    let start = opt_integer(lua, &args, 2, "concat", 1)?;
+    let end = opt_integer(lua, &args, 3, "concat", length)?;
+
+    let mut result = Vec::new();
+    for i in start..=end {
+        let value = table.borrow().get(&Value::Number(i as f64));
+        match value.to_bytes() {
+            Some(s) => result.extend_from_slice(&s),
+            None => {
+                return Err(lua.error(format!(
+                    "invalid value (at index {}) in table for 'concat'",
+                    i
+                )))
+            }
+        }
+        if i < end {
+            if let Some(separator) = &separator {
+                result.extend_from_slice(separator);
+            }
+        }
+    }
+    Ok(vec![Value::string(result)])
+}
+
+fn getn(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let table = check_table(lua, &args, 0, "getn")?;
+    let length = table.borrow().len();
+    Ok(vec![Value::Number(length as f64)])
+}
+
+fn maxn(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let table = check_table(lua, &args, 0, "maxn")?;
+    let max = table.borrow().maxn();
+    Ok(vec![Value::Number(max)])
+}
+
+fn insert(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let table = check_table(lua, &args, 0, "insert")?;
+    check_writable(lua, &table)?;
+    let end = table.borrow().len() as i64 + 1;
+    let (position, value) = match args.len() {
+        2 => (end, arg(&args, 1)),
+        3 => (check_integer(lua, &args, 1, "insert")?, arg(&args, 2)),
+        _ => return Err(lua.error("wrong number of arguments to 'insert'")),
+    };
+    let mut table = table.borrow_mut();
+    // Move the values up to make room
+    for i in (position..end).rev() {
+        let moved = table.get(&Value::Number(i as f64));
+        table.set(Value::Number((i + 1) as f64), moved).unwrap();
+    }
+    table.set(Value::Number(position as f64), value).unwrap();
+    Ok(Vec::new())
+}
+
+fn remove(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let table = check_table(lua, &args, 0, "remove")?;
+    check_writable(lua, &table)?;
+    let end = table.borrow().len() as i64;
+    let position = opt_integer(lua, &args, 1, "remove", end)?;
+    if end == 0 {
+        return Ok(Vec::new());
+    }
+    let mut table = table.borrow_mut();
+    let removed = table.get(&Value::Number(position as f64));
+    for i in position..end {
+        let moved = table.get(&Value::Number((i + 1) as f64));
+        table.set(Value::Number(i as f64), moved).unwrap();
+    }
+    table.set(Value::Number(end as f64), Value::Nil).unwrap();
+    Ok(vec![removed])
+}
+
+fn sort(lua: &mut Lua, args: Vec<Value>) -> LuaResult<Vec<Value>> {
+    let table = check_table(lua, &args, 0, "sort")?;
+    check_writable(lua, &table)?;
+    let comparator = arg(&args, 1);
+    let length = table.borrow().len();
+    let mut values: Vec<Value> = (1..=length)
+        .map(|i| table.borrow().get(&Value::Number(i as f64)))
+        .collect();
+
+    let mut less = |lua: &mut Lua, a: &Value, b: &Value| -> LuaResult<bool> {
+        if comparator.is_nil() {
+            return lua.less_than(a, b);
+        }
+        let result = lua.call(&comparator, vec![a.clone(), b.clone()])?;
+        Ok(result.first().is_some_and(Value::is_truthy))
+    };
+    merge_sort(lua, &mut values, &mut less)?;
+
+    let mut table = table.borrow_mut();
+    for (i, value) in values.into_iter().enumerate() {
+        table.set(Value::Number((i + 1) as f64), value).unwrap();
+    }
+    Ok(Vec::new())
+}
+
+/// A merge sort with a comparison that can fail, which the standard sorts don't allow, and
+/// which copes with inconsistent comparisons
+fn merge_sort<F>(lua: &mut Lua, values: &mut Vec<Value>, less: &mut F) -> LuaResult<()>
+where
+    F: FnMut(&mut Lua, &Value, &Value) -> LuaResult<bool>,
+{
+    if values.len() <= 1 {
+        return Ok(());
+    }
+    let mut right = values.split_off(values.len() / 2);
+    let mut left = std::mem::take(values);
+    merge_sort(lua, &mut left, less)?;
+    merge_sort(lua, &mut right, less)?;
+
+    values.reserve(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        if less(lua, b, a)? {
+            values.push(right.next().unwrap());
+        } else {
+            values.push(left.next().unwrap());
+        }
+    }
+    values.extend(left);
+    values.extend(right);
+    Ok(())
+}
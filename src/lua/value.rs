@@ -0,0 +1,581 @@
+use super::{interpreter::Closure, Lua};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    hash::{Hash, Hasher},
+    rc::Rc,
+};
+
+pub(crate) type TableRef = Rc<RefCell<Table>>;
+pub(crate) type LuaResult<T> = Result<T, LuaError>;
+
+/// A function implemented in Rust, which gets the interpreter and its arguments
+pub(crate) type NativeFn = dyn Fn(&mut Lua<'_>, Vec<Value>) -> LuaResult<Vec<Value>>;
+
+/// A Lua value
+///
+/// Strings are byte strings, as in Lua, and all numbers are doubles, as in Lua 5.1.
+#[derive(Clone, Default)]
+pub(crate) enum Value {
+    #[default]
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    String(Rc<[u8]>),
+    Table(TableRef),
+    Function(Function),
+    /// A light userdata, only used for `cjson.null`
+    LightUserData(usize),
+}
+
+#[derive(Clone)]
+pub(crate) enum Function {
+    Lua(Rc<Closure>),
+    Native(Rc<NativeFunction>),
+}
+
+pub(crate) struct NativeFunction {
+    pub(crate) name: &'static str,
+    pub(crate) func: Box<NativeFn>,
+}
+
+impl Value {
+    pub(crate) fn string(s: impl AsRef<[u8]>) -> Value {
+        Value::String(Rc::from(s.as_ref()))
+    }
+
+    pub(crate) fn table(table: Table) -> Value {
+        Value::Table(Rc::new(RefCell::new(table)))
+    }
+
+    pub(crate) fn native<F>(name: &'static str, func: F) -> Value
+    where
+        F: Fn(&mut Lua<'_>, Vec<Value>) -> LuaResult<Vec<Value>> + 'static,
+    {
+        Value::Function(Function::Native(Rc::new(NativeFunction {
+            name,
+            func: Box::new(func),
+        })))
+    }
+
+    pub(crate) fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Boolean(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Table(_) => "table",
+            Value::Function(_) => "function",
+            Value::LightUserData(_) => "userdata",
+        }
+    }
+
+    pub(crate) fn is_nil(&self) -> bool {
+        matches!(self, Value::Nil)
+    }
+
+    /// Whether the value counts as true in conditions, which is anything but nil and false
+    pub(crate) fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Boolean(false))
+    }
+
+    /// The value as a number, converting strings that hold a number as Lua does for arithmetic
+    pub(crate) fn to_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            Value::String(s) => parse_number(s),
+            _ => None,
+        }
+    }
+
+    /// The value as a string, converting numbers as Lua does for concatenation
+    pub(crate) fn to_bytes(&self) -> Option<Rc<[u8]>> {
+        match self {
+            Value::String(s) => Some(Rc::clone(s)),
+            Value::Number(n) => Some(Rc::from(format_number(*n).as_bytes())),
+            _ => None,
+        }
+    }
+
+    /// Raw equality, without metamethods
+    pub(crate) fn raw_equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Table(a), Value::Table(b)) => Rc::ptr_eq(a, b),
+            (Value::Function(a), Value::Function(b)) => a.ptr() == b.ptr(),
+            (Value::LightUserData(a), Value::LightUserData(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    /// The address shown by tostring for tables and functions
+    fn address(&self) -> usize {
+        match self {
+            Value::Table(t) => Rc::as_ptr(t) as *const u8 as usize,
+            Value::Function(f) => f.ptr(),
+            Value::LightUserData(p) => *p,
+            _ => 0,
+        }
+    }
+
+    /// Convert to a string as the `tostring` function does, without metamethods
+    pub(crate) fn to_display(&self) -> Vec<u8> {
+        match self {
+            Value::Nil => b"nil".to_vec(),
+            Value::Boolean(b) => b.to_string().into_bytes(),
+            Value::Number(n) => format_number(*n).into_bytes(),
+            Value::String(s) => s.to_vec(),
+            Value::Function(Function::Native(_)) => {
+                format!("function: builtin: {:#x}", self.address()).into_bytes()
+            }
+            _ => format!("{}: {:#x}", self.type_name(), self.address()).into_bytes(),
+        }
+    }
+}
+
+impl Function {
+    fn ptr(&self) -> usize {
+        match self {
+            Function::Lua(f) => Rc::as_ptr(f) as *const u8 as usize,
+            Function::Native(f) => Rc::as_ptr(f) as *const u8 as usize,
+        }
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::String(s) => write!(f, "{:?}", String::from_utf8_lossy(s)),
+            Value::Function(Function::Native(native)) => write!(f, "builtin: {}", native.name),
+            _ => write!(f, "{}", String::from_utf8_lossy(&self.to_display())),
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Boolean(b)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Value::Number(n)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::string(s)
+    }
+}
+
+/// An error raised while running a script, which can be any value
+#[derive(Debug, Clone)]
+pub(crate) struct LuaError {
+    pub(crate) value: Value,
+    /// The line that was running when the error was raised
+    pub(crate) line: u32,
+    /// Errors that pcall can't catch, such as when the script is killed
+    pub(crate) fatal: bool,
+}
+
+impl LuaError {
+    /// The error as a message, for errors that aren't strings or numbers
+    pub(crate) fn message(&self) -> String {
+        match self.value.to_bytes() {
+            Some(s) => String::from_utf8_lossy(&s).into_owned(),
+            None => format!("(error object is a {} value)", self.value.type_name()),
+        }
+    }
+}
+
+/// A value used as a table key, which can't be nil or NaN
+///
+/// Numbers are compared by value, with -0 being the same key as 0, and tables and functions by
+/// identity.
+#[derive(Clone)]
+pub(crate) struct Key(Value);
+
+impl Key {
+    /// Make a key from a value, returning `None` for nil and NaN
+    pub(crate) fn new(value: Value) -> Option<Key> {
+        match value {
+            Value::Nil => None,
+            Value::Number(n) if n.is_nan() => None,
+            Value::Number(0.0) => Some(Key(Value::Number(0.0))),
+            value => Some(Key(value)),
+        }
+    }
+
+    pub(crate) fn value(&self) -> &Value {
+        &self.0
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.raw_equals(&other.0)
+    }
+}
+
+impl Eq for Key {}
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match &self.0 {
+            Value::Boolean(b) => b.hash(state),
+            Value::Number(n) => n.to_bits().hash(state),
+            Value::String(s) => s.hash(state),
+            value => value.address().hash(state),
+        }
+    }
+}
+
+/// The index of the array part a number key belongs to, if it's a positive integer
+fn array_index(key: &Value) -> Option<usize> {
+    match key {
+        Value::Number(n) if *n >= 1.0 && n.fract() == 0.0 && *n <= usize::MAX as f64 => {
+            Some(*n as usize - 1)
+        }
+        _ => None,
+    }
+}
+
+/// A Lua table, with an array part for the keys 1 to n and a hash part for the rest
+///
+/// The hash part keeps the insertion order, so that `next` can continue from any key. Removed
+/// entries are left as nil until the next new key is inserted, so that fields can be cleared
+/// while iterating, as Lua allows.
+#[derive(Default)]
+pub(crate) struct Table {
+    array: Vec<Value>,
+    entries: Vec<(Key, Value)>,
+    index: HashMap<Key, usize>,
+    removed: usize,
+    pub(crate) metatable: Option<TableRef>,
+    /// Set for the globals and libraries, which scripts can't modify
+    pub(crate) readonly: bool,
+}
+
+impl Table {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make a table holding a sequence of values
+    pub(crate) fn from_array(values: Vec<Value>) -> Self {
+        let mut table = Table::new();
+        for (i, value) in values.into_iter().enumerate() {
+            table.set(Value::Number((i + 1) as f64), value).unwrap();
+        }
+        table
+    }
+
+    pub(crate) fn get(&self, key: &Value) -> Value {
+        if let Some(i) = array_index(key) {
+            if i < self.array.len() {
+                return self.array[i].clone();
+            }
+        }
+        let Some(key) = Key::new(key.clone()) else {
+            return Value::Nil;
+        };
+        self.index
+            .get(&key)
+            .map_or(Value::Nil, |&i| self.entries[i].1.clone())
+    }
+
+    pub(crate) fn get_str(&self, key: &str) -> Value {
+        self.get(&Value::string(key))
+    }
+
+    /// Set a key, failing with the error message if the key is nil or NaN
+    pub(crate) fn set(&mut self, key: Value, value: Value) -> Result<(), &'static str> {
+        if let Some(i) = array_index(&key) {
+            if i < self.array.len() {
+                self.array[i] = value;
+                while self.array.last().is_some_and(Value::is_nil) {
+                    self.array.pop();
+                }
+                return Ok(());
+            }
+            if i == self.array.len() && !value.is_nil() {
+                self.array.push(value);
+                self.remove_entry(&key);
+                self.migrate_to_array();
+                return Ok(());
+            }
+        }
+
+        let key = match Key::new(key) {
+            Some(key) => key,
+            None if matches!(value, Value::Nil) => return Ok(()),
+            None => return Err("table index is nil or NaN"),
+        };
+        if let Some(&i) = self.index.get(&key) {
+            if value.is_nil() && !self.entries[i].1.is_nil() {
+                self.removed += 1;
+            } else if !value.is_nil() && self.entries[i].1.is_nil() {
+                self.removed -= 1;
+            }
+            self.entries[i].1 = value;
+        } else if !value.is_nil() {
+            self.compact();
+            self.index.insert(key.clone(), self.entries.len());
+            self.entries.push((key, value));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn set_str(&mut self, key: &str, value: Value) {
+        self.set(Value::string(key), value).unwrap();
+    }
+
+    /// Remove a hash entry for a key that moved to the array part
+    fn remove_entry(&mut self, key: &Value) {
+        if let Some(key) = Key::new(key.clone()) {
+            if let Some(&i) = self.index.get(&key) {
+                if !self.entries[i].1.is_nil() {
+                    self.entries[i].1 = Value::Nil;
+                    self.removed += 1;
+                }
+            }
+        }
+    }
+
+    /// Move the keys following the array part from the hash part into it
+    fn migrate_to_array(&mut self) {
+        loop {
+            let next = Value::Number((self.array.len() + 1) as f64);
+            let value = self.get_entry(&next);
+            if value.is_nil() {
+                break;
+            }
+            self.remove_entry(&next);
+            self.array.push(value);
+        }
+    }
+
+    fn get_entry(&self, key: &Value) -> Value {
+        Key::new(key.clone())
+            .and_then(|key| self.index.get(&key))
+            .map_or(Value::Nil, |&i| self.entries[i].1.clone())
+    }
+
+    /// Drop the removed entries, which is only done when inserting new keys, as Lua doesn't
+    /// allow that while iterating
+    fn compact(&mut self) {
+        if self.removed == 0 || self.removed < self.entries.len() / 2 {
+            return;
+        }
+        self.entries.retain(|(_, value)| !value.is_nil());
+        self.index = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, (key, _))| (key.clone(), i))
+            .collect();
+        self.removed = 0;
+    }
+
+    /// The length of the sequence, as returned by the `#` operator
+    pub(crate) fn len(&self) -> usize {
+        self.array.len()
+    }
+
+    /// The key and value after a key, or the first ones for nil, as the `next` function does
+    ///
+    /// Returns `Err` if the key isn't in the table.
+    pub(crate) fn next(&self, key: &Value) -> Result<Option<(Value, Value)>, ()> {
+        // Where to continue in the array part and in the hash part
+        let (array_start, hash_start) = match key {
+            Value::Nil => (0, 0),
+            key => {
+                let entry = Key::new(key.clone()).and_then(|key| self.index.get(&key));
+                match (array_index(key), entry) {
+                    (Some(i), _) if i < self.array.len() => (i + 1, 0),
+                    (_, Some(&i)) => (self.array.len(), i + 1),
+                    // The array part shrinks when its last values are cleared while iterating
+                    (Some(_), None) => (self.array.len(), 0),
+                    (None, None) => return Err(()),
+                }
+            }
+        };
+
+        for i in array_start..self.array.len() {
+            if !self.array[i].is_nil() {
+                return Ok(Some((Value::Number((i + 1) as f64), self.array[i].clone())));
+            }
+        }
+        Ok(self.entries[hash_start..]
+            .iter()
+            .find(|(_, value)| !value.is_nil())
+            .map(|(key, value)| (key.value().clone(), value.clone())))
+    }
+
+    /// The largest positive integer key, as returned by `table.maxn`
+    pub(crate) fn maxn(&self) -> f64 {
+        let mut max = self.array.len() as f64;
+        for (key, value) in &self.entries {
+            if let Value::Number(n) = key.value() {
+                if !value.is_nil() && *n > max {
+                    max = *n;
+                }
+            }
+        }
+        max
+    }
+}
+
+/// Parse a number as Lua does when converting strings, allowing surrounding whitespace and hex
+pub(crate) fn parse_number(s: &[u8]) -> Option<f64> {
+    let s = std::str::from_utf8(s).ok()?;
+    let s = s.trim_matches(|c: char| c.is_ascii_whitespace());
+    if s.is_empty() {
+        return None;
+    }
+    let (negative, unsigned) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let value = if let Some(hex) = unsigned
+        .strip_prefix("0x")
+        .or_else(|| unsigned.strip_prefix("0X"))
+    {
+        if hex.is_empty() || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        hex.bytes().fold(0.0, |n, b| {
+            n * 16.0 + (b as char).to_digit(16).unwrap() as f64
+        })
+    } else {
+        // Rust accepts "inf" and "nan", which Lua doesn't
+        if !unsigned
+            .bytes()
+            .all(|b| b.is_ascii_digit() || matches!(b, b'.' | b'e' | b'E' | b'+' | b'-'))
+        {
+            return None;
+        }
+        unsigned.parse::<f64>().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+/// Format a number as Lua does, which is like "%.14g" in C
+pub(crate) fn format_number(n: f64) -> String {
+    if n.fract() == 0.0 && n.abs() < 1e15 {
+        if n == 0.0 && n.is_sign_negative() {
+            return "-0".to_string();
+        }
+        return format!("{}", n as i64);
+    }
+    format_g(n, 14, false)
+}
+
+/// Format a number like "%g" in C, with a precision in significant digits
+///
+/// With `alternate`, trailing zeros are kept, like "%#g".
+pub(crate) fn format_g(n: f64, precision: usize, alternate: bool) -> String {
+    if n.is_nan() {
+        return if n.is_sign_negative() { "-nan" } else { "nan" }.to_string();
+    }
+    if n.is_infinite() {
+        return if n < 0.0 { "-inf" } else { "inf" }.to_string();
+    }
+    let precision = precision.max(1);
+    let scientific = format!("{:.*e}", precision - 1, n);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+
+    let strip = |s: String| -> String {
+        if alternate || !s.contains('.') {
+            return s;
+        }
+        s.trim_end_matches('0').trim_end_matches('.').to_string()
+    };
+
+    if exponent < -4 || exponent >= precision as i32 {
+        let mantissa = strip(mantissa.to_string());
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+    } else {
+        let decimals = (precision as i32 - 1 - exponent).max(0) as usize;
+        strip(format!("{:.*}", decimals, n))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(n: f64) -> Value {
+        Value::Number(n)
+    }
+
+    #[test]
+    fn test_table_array_and_hash_parts() {
+        let mut table = Table::new();
+        table.set(number(2.0), "b".into()).unwrap();
+        assert_eq!(table.len(), 0);
+        table.set(number(1.0), "a".into()).unwrap();
+        // The key 2 moves from the hash part to the array part
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.get(&number(2.0)).to_display(), b"b");
+
+        table.set(number(2.0), Value::Nil).unwrap();
+        assert_eq!(table.len(), 1);
+        table.set("x".into(), "y".into()).unwrap();
+        assert_eq!(table.get_str("x").to_display(), b"y");
+        assert!(table.set(Value::Nil, number(1.0)).is_err());
+        assert!(table.set(number(f64::NAN), number(1.0)).is_err());
+    }
+
+    #[test]
+    fn test_table_next() {
+        let mut table = Table::from_array(vec![number(10.0), number(20.0)]);
+        table.set_str("a", number(1.0));
+        table.set_str("b", number(2.0));
+
+        let mut keys = Vec::new();
+        let mut key = Value::Nil;
+        while let Some((k, _)) = table.next(&key).unwrap() {
+            keys.push(k.to_display());
+            // Clearing fields while iterating is allowed
+            table.set(k.clone(), Value::Nil).unwrap();
+            key = k;
+        }
+        assert_eq!(
+            keys,
+            vec![b"1".to_vec(), b"2".to_vec(), b"a".to_vec(), b"b".to_vec()]
+        );
+        assert!(table.next(&"missing".into()).is_err());
+    }
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number(b" 10 "), Some(10.0));
+        assert_eq!(parse_number(b"-1.5e2"), Some(-150.0));
+        assert_eq!(parse_number(b"0x1F"), Some(31.0));
+        assert_eq!(parse_number(b"inf"), None);
+        assert_eq!(parse_number(b"1a"), None);
+        assert_eq!(parse_number(b""), None);
+    }
+
+    #[test]
+    fn test_format_number() {
+        assert_eq!(format_number(10.0), "10");
+        assert_eq!(format_number(-3.0), "-3");
+        assert_eq!(format_number(0.1), "0.1");
+        assert_eq!(format_number(1.0 / 3.0), "0.33333333333333");
+        assert_eq!(format_number(1e15), "1e+15");
+        assert_eq!(format_number(123456.789), "123456.789");
+        assert_eq!(format_number(1e-5), "1e-05");
+        assert_eq!(format_number(f64::INFINITY), "inf");
+        assert_eq!(format_g(100.0, 6, true), "100.000");
+    }
+}
@@ -24,6 +24,59 @@ struct Args {
     // Snapshot interval
     #[arg(long, default_value = "300")]
     snapshot_interval: u64,
+
+    /// The maximum number of fields in a hash before it's converted to a hash table
+    #[arg(long, default_value = "128")]
+    hash_max_listpack_entries: usize,
+
+    /// The maximum length of a field or value in a hash before it's converted to a hash table
+    #[arg(long, default_value = "64")]
+    hash_max_listpack_value: usize,
+
+    /// The maximum number of members in a set of integers before it's converted to a hash set
+    #[arg(long, default_value = "512")]
+    set_max_intset_entries: usize,
+
+    /// The maximum number of members in a sorted set before it's converted to a skiplist
+    #[arg(long, default_value = "128")]
+    zset_max_listpack_entries: usize,
+
+    /// The maximum length of a member in a sorted set before it's converted to a skiplist
+    #[arg(long, default_value = "64")]
+    zset_max_listpack_value: usize,
+
+    /// The maximum number of entries in a single node of a stream
+    #[arg(long, default_value = "100")]
+    stream_node_max_entries: usize,
+
+    /// The maximum size in bytes of a single node of a stream
+    #[arg(long, default_value = "4096")]
+    stream_node_max_bytes: usize,
+
+    /// The maximum size in bytes of a sparse HyperLogLog before it's converted to dense
+    #[arg(long, default_value = "3000")]
+    hll_sparse_max_bytes: usize,
+
+    /// The classes of keyspace events to publish, such as "KEA" for all of them
+    #[arg(long, default_value = "")]
+    notify_keyspace_events: String,
+
+    /// How many milliseconds a script runs before other clients are served with BUSY errors
+    #[arg(long, default_value = "5000")]
+    busy_reply_threshold: u64,
+
+    /// The password clients authenticate with using AUTH, where none is required if it's empty
+    #[arg(long, default_value = "")]
+    requirepass: String,
+
+    /// The file ACL users are loaded from at startup, and by ACL LOAD and ACL SAVE
+    #[arg(long, default_value = "")]
+    aclfile: String,
+
+    /// Whether to refuse clients that aren't local when listening on a public interface while the
+    /// default user has no password
+    #[arg(long, default_value = "yes", value_parser = ["yes", "no"])]
+    protected_mode: String,
 }
 
 fn main() -> Result<()> {
@@ -37,6 +90,19 @@ fn main() -> Result<()> {
         host: args.host,
         port: args.port,
         snapshot_interval: args.snapshot_interval,
+        hash_max_listpack_entries: args.hash_max_listpack_entries,
+        hash_max_listpack_value: args.hash_max_listpack_value,
+        set_max_intset_entries: args.set_max_intset_entries,
+        zset_max_listpack_entries: args.zset_max_listpack_entries,
+        zset_max_listpack_value: args.zset_max_listpack_value,
+        stream_node_max_entries: args.stream_node_max_entries,
+        stream_node_max_bytes: args.stream_node_max_bytes,
+        hll_sparse_max_bytes: args.hll_sparse_max_bytes,
+        notify_keyspace_events: args.notify_keyspace_events,
+        busy_reply_threshold: args.busy_reply_threshold,
+        requirepass: args.requirepass,
+        aclfile: args.aclfile,
+        protected_mode: args.protected_mode == "yes",
     }));
 
     let mut server = Server::new(config)?;
@@ -0,0 +1,133 @@
+//! Keyspace notifications, which publish an event on pub/sub channels whenever a key is changed
+//!
+//! Events are recorded where they happen, mostly from within the database, and then published by
+//! the connection or server once the command is done. Which events are published is configured
+//! with `notify-keyspace-events`, a string of flags for the classes of events.
+
+use std::sync::Mutex;
+
+/// Publish events on `__keyspace@<db>__:<key>`, with the event as the message
+pub(crate) const KEYSPACE: u32 = 1 << 0;
+/// Publish events on `__keyevent@<db>__:<event>`, with the key as the message
+pub(crate) const KEYEVENT: u32 = 1 << 1;
+/// Commands that aren't specific to a type, such as DEL and EXPIRE
+pub(crate) const GENERIC: u32 = 1 << 2;
+pub(crate) const STRING: u32 = 1 << 3;
+pub(crate) const LIST: u32 = 1 << 4;
+pub(crate) const SET: u32 = 1 << 5;
+pub(crate) const HASH: u32 = 1 << 6;
+pub(crate) const ZSET: u32 = 1 << 7;
+/// Keys that were removed because they expired
+pub(crate) const EXPIRED: u32 = 1 << 8;
+/// Keys that were removed to free up memory
+pub(crate) const EVICTED: u32 = 1 << 9;
+pub(crate) const STREAM: u32 = 1 << 10;
+/// Lookups of keys that don't exist
+pub(crate) const KEY_MISS: u32 = 1 << 12;
+/// Keys that were created
+pub(crate) const NEW: u32 = 1 << 14;
+/// The classes enabled by "A", which leaves out key misses and new keys, as they are noisy
+const ALL: u32 = GENERIC | STRING | LIST | SET | HASH | ZSET | EXPIRED | EVICTED | STREAM;
+
+/// An event for a key, waiting to be published
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Event {
+    pub(crate) class: u32,
+    pub(crate) name: &'static str,
+    pub(crate) key: Vec<u8>,
+    pub(crate) db: usize,
+}
+
+static EVENTS: Mutex<Vec<Event>> = Mutex::new(Vec::new());
+
+/// Record an event for a key, to be published if its class is enabled
+pub(crate) fn notify(class: u32, name: &'static str, key: &[u8], db: usize) {
+    EVENTS.lock().unwrap().push(Event {
+        class,
+        name,
+        key: key.to_vec(),
+        db,
+    });
+}
+
+/// Take all the events recorded since the last time, in the order they happened
+pub(crate) fn take_events() -> Vec<Event> {
+    std::mem::take(&mut *EVENTS.lock().unwrap())
+}
+
+/// Parse the flags of `notify-keyspace-events`, returning `None` if there's an unknown flag
+pub(crate) fn parse_flags(flags: &str) -> Option<u32> {
+    flags.chars().try_fold(0, |parsed, flag| {
+        let class = match flag {
+            'A' => ALL,
+            'g' => GENERIC,
+            '$' => STRING,
+            'l' => LIST,
+            's' => SET,
+            'h' => HASH,
+            'z' => ZSET,
+            'x' => EXPIRED,
+            'e' => EVICTED,
+            't' => STREAM,
+            'K' => KEYSPACE,
+            'E' => KEYEVENT,
+            'm' => KEY_MISS,
+            'n' => NEW,
+            _ => return None,
+        };
+        Some(parsed | class)
+    })
+}
+
+/// Format flags in the canonical form returned by CONFIG GET, using "A" where possible
+pub(crate) fn format_flags(flags: u32) -> String {
+    let mut formatted = String::new();
+    if flags & ALL == ALL {
+        formatted.push('A');
+    } else {
+        for (class, flag) in [
+            (GENERIC, 'g'),
+            (STRING, '$'),
+            (LIST, 'l'),
+            (SET, 's'),
+            (HASH, 'h'),
+            (ZSET, 'z'),
+            (EXPIRED, 'x'),
+            (EVICTED, 'e'),
+            (STREAM, 't'),
+        ] {
+            if flags & class != 0 {
+                formatted.push(flag);
+            }
+        }
+    }
+    for (class, flag) in [
+        (KEYSPACE, 'K'),
+        (KEYEVENT, 'E'),
+        (KEY_MISS, 'm'),
+        (NEW, 'n'),
+    ] {
+        if flags & class != 0 {
+            formatted.push(flag);
+        }
+    }
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_format_flags() {
+        assert_eq!(parse_flags(""), Some(0));
+        assert_eq!(parse_flags("KEA"), Some(KEYSPACE | KEYEVENT | ALL));
+        assert_eq!(parse_flags("Ex"), Some(KEYEVENT | EXPIRED));
+        assert_eq!(parse_flags("Kq"), None);
+
+        assert_eq!(format_flags(parse_flags("KEA").unwrap()), "AKE");
+        assert_eq!(format_flags(parse_flags("xEg$").unwrap()), "g$xE");
+        assert_eq!(format_flags(parse_flags("g$lshzxetKEmn").unwrap()), "AKEmn");
+        assert_eq!(format_flags(0), "");
+    }
+}
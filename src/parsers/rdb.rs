@@ -2,6 +2,8 @@ use nom::{
     branch::alt,
     bytes::complete::{tag, take},
     combinator::{map, value},
+    error::{Error, ErrorKind},
+    multi::count,
     IResult, Parser,
 };
 
@@ -14,6 +16,8 @@ pub(crate) enum OpCode {
     EXPIRETIMEMS,
     RESIZEDB,
     AUX,
+    /// A library of functions, given by its code
+    FUNCTION2,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -22,6 +26,8 @@ pub(crate) enum ValueTypeEncoding {
     List,
     Set,
     SortedSet,
+    /// Sorted set with the scores stored as binary doubles
+    SortedSet2,
     Hash,
     Zipmap,
     Ziplist,
@@ -29,6 +35,17 @@ pub(crate) enum ValueTypeEncoding {
     SortedSetInZiplist,
     HashmapInZiplist,
     ListInQuicklist,
+    HashListpack,
+    SortedSetListpack,
+    SetListpack,
+    ListInQuicklist2,
+    StreamListpacks,
+    /// Streams with the first ID, the greatest deleted ID and the number of entries ever added
+    StreamListpacks2,
+    /// Streams where consumers also have their active time
+    StreamListpacks3,
+    HashMetadata,
+    HashListpackEx,
 }
 
 /// Helper enum to store either an OpCode or a ValueType
@@ -44,6 +61,23 @@ pub(crate) enum EncodedString<'a> {
     U8(u8),
     U16(u16),
     U32(u32),
+    /// An LZF compressed string, which has already been decompressed
+    Decompressed(Vec<u8>),
+}
+
+impl EncodedString<'_> {
+    /// The string as bytes, with integer encoded strings converted to their decimal form
+    ///
+    /// Integer encoded strings are signed, so they are reinterpreted before formatting
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            EncodedString::String(s) => s.to_vec(),
+            EncodedString::U8(v) => (*v as i8).to_string().into_bytes(),
+            EncodedString::U16(v) => (*v as i16).to_string().into_bytes(),
+            EncodedString::U32(v) => (*v as i32).to_string().into_bytes(),
+            EncodedString::Decompressed(s) => s.clone(),
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -52,6 +86,8 @@ pub(crate) enum EncodedLength {
     U8(u8),
     U16(u16),
     U32(u32),
+    /// Marks an LZF compressed string, the lengths and data follow
+    Lzf,
 }
 
 impl EncodedLength {
@@ -61,10 +97,73 @@ impl EncodedLength {
             EncodedLength::U8(v) => *v as usize,
             EncodedLength::U16(v) => *v as usize,
             EncodedLength::U32(v) => *v as usize,
+            EncodedLength::Lzf => 0,
         }
     }
 }
 
+/// Field-value pairs of a hash
+pub(crate) type FieldValuePairs = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// Field-value pairs of a hash along with the expiry of each field, in unix time milliseconds
+pub(crate) type FieldValueExpiries = Vec<(Vec<u8>, Vec<u8>, Option<u64>)>;
+
+/// Member-score pairs of a sorted set
+pub(crate) type MemberScores = Vec<(Vec<u8>, f64)>;
+
+/// The ID of a stream entry, as its milliseconds and sequence number
+pub(crate) type StreamEntryId = (u64, u64);
+
+/// Entries of a stream along with their IDs
+pub(crate) type StreamEntries = Vec<(StreamEntryId, FieldValuePairs)>;
+
+/// A stream parsed from an RDB file
+#[derive(Debug, PartialEq)]
+pub(crate) struct RdbStream {
+    /// The entries that haven't been deleted, in ID order
+    pub(crate) entries: StreamEntries,
+    pub(crate) last_id: StreamEntryId,
+    pub(crate) max_deleted_id: StreamEntryId,
+    pub(crate) entries_added: u64,
+    pub(crate) groups: Vec<RdbConsumerGroup>,
+}
+
+/// A consumer group of a stream parsed from an RDB file, where times are unix time milliseconds
+#[derive(Debug, PartialEq)]
+pub(crate) struct RdbConsumerGroup {
+    pub(crate) name: Vec<u8>,
+    pub(crate) last_id: StreamEntryId,
+    /// How many entries the group has read, or `None` if that isn't known
+    pub(crate) entries_read: Option<u64>,
+    /// The pending entries, with when they were last delivered and how many times
+    pub(crate) pending: Vec<(StreamEntryId, u64, u64)>,
+    pub(crate) consumers: Vec<RdbConsumer>,
+}
+
+/// A consumer of a stream consumer group parsed from an RDB file
+#[derive(Debug, PartialEq)]
+pub(crate) struct RdbConsumer {
+    pub(crate) name: Vec<u8>,
+    pub(crate) seen_time: u64,
+    /// When the consumer last read or claimed entries, or `None` if it never has
+    pub(crate) active_time: Option<u64>,
+    /// The IDs of the group's pending entries that belong to the consumer
+    pub(crate) pending: Vec<StreamEntryId>,
+}
+
+/// A value parsed from an RDB file, independent of how it was encoded
+#[derive(Debug, PartialEq)]
+pub(crate) enum RdbValue {
+    String(Vec<u8>),
+    List(Vec<Vec<u8>>),
+    Hash(FieldValuePairs),
+    /// A hash where at least one field has an expiry
+    HashWithExpiries(FieldValueExpiries),
+    Set(Vec<Vec<u8>>),
+    SortedSet(MemberScores),
+    Stream(RdbStream),
+}
+
 /// Parse header
 ///
 /// Parses the magic string "REDIS" and version, returning the version
@@ -82,7 +181,9 @@ pub(crate) fn nom_rdb_header(input: &[u8]) -> IResult<&[u8], u16> {
 ///
 /// Parse the contents of a metadata section, this does *not* parse the actual OpCode, it is
 /// expected to be matched elsewhere before parsing the actual section itself
-pub(crate) fn nom_metadata_section(input: &[u8]) -> IResult<&[u8], (EncodedString, EncodedString)> {
+pub(crate) fn nom_metadata_section(
+    input: &[u8],
+) -> IResult<&[u8], (EncodedString<'_>, EncodedString<'_>)> {
     let (input, key) = nom_size_encoded_string(input)?;
     let (input, value) = nom_size_encoded_string(input)?;
     Ok((input, (key, value)))
@@ -91,6 +192,7 @@ pub(crate) fn nom_metadata_section(input: &[u8]) -> IResult<&[u8], (EncodedStrin
 /// Parse RDB Op Code
 pub(crate) fn nom_rdb_op_code(input: &[u8]) -> IResult<&[u8], OpCode> {
     let (input, op_code) = alt((
+        value(OpCode::FUNCTION2, tag(&[0xF5][..])),
         value(OpCode::AUX, tag(&[0xFA][..])),
         value(OpCode::RESIZEDB, tag(&[0xFB][..])),
         value(OpCode::EXPIRETIMEMS, tag(&[0xFC][..])),
@@ -111,12 +213,22 @@ pub(crate) fn nom_value_type(input: &[u8]) -> IResult<&[u8], ValueTypeEncoding>
         value(ValueTypeEncoding::Set, tag(&[0x02][..])),
         value(ValueTypeEncoding::SortedSet, tag(&[0x03][..])),
         value(ValueTypeEncoding::Hash, tag(&[0x04][..])),
+        value(ValueTypeEncoding::SortedSet2, tag(&[0x05][..])),
         value(ValueTypeEncoding::Zipmap, tag(&[0x09][..])),
         value(ValueTypeEncoding::Ziplist, tag(&[0x0A][..])),
         value(ValueTypeEncoding::Intset, tag(&[0x0B][..])),
         value(ValueTypeEncoding::SortedSetInZiplist, tag(&[0x0C][..])),
         value(ValueTypeEncoding::HashmapInZiplist, tag(&[0x0D][..])),
         value(ValueTypeEncoding::ListInQuicklist, tag(&[0x0E][..])),
+        value(ValueTypeEncoding::StreamListpacks, tag(&[0x0F][..])),
+        value(ValueTypeEncoding::HashListpack, tag(&[0x10][..])),
+        value(ValueTypeEncoding::SortedSetListpack, tag(&[0x11][..])),
+        value(ValueTypeEncoding::SetListpack, tag(&[0x14][..])),
+        value(ValueTypeEncoding::ListInQuicklist2, tag(&[0x12][..])),
+        value(ValueTypeEncoding::StreamListpacks2, tag(&[0x13][..])),
+        value(ValueTypeEncoding::StreamListpacks3, tag(&[0x15][..])),
+        value(ValueTypeEncoding::HashMetadata, tag(&[0x18][..])),
+        value(ValueTypeEncoding::HashListpackEx, tag(&[0x19][..])),
     ))
     .parse(input)?;
 
@@ -146,7 +258,7 @@ pub(crate) fn nom_opcode_or_value_type(input: &[u8]) -> IResult<&[u8], ParsedOpC
 ///     If the first byte is 0xC0 (0b11000000): 8-bits
 ///     If the first byte is 0xC1 (0b11000001): 16-bits
 ///     If the first byte is 0xC2 (0b11000010): 32-bits
-///     If the first byte is 0xC3 (0b11000011): LZF-compressed (not supported)
+///     If the first byte is 0xC3 (0b11000011): LZF-compressed
 ///
 /// If the first 8 bits are 0b10000000:
 ///     Size is 32 bit (next 4 bytes), big endian:
@@ -211,10 +323,7 @@ pub(crate) fn nom_size_encoding(input: &[u8]) -> IResult<&[u8], EncodedLength> {
                 EncodedLength::U32(u32::from_le_bytes(bytes.try_into().unwrap())),
             ))
         }
-        (0b11, 3) => {
-            // LZF-compressed
-            unimplemented!()
-        }
+        (0b11, 3) => Ok((input, EncodedLength::Lzf)),
         _ => unreachable!(),
     }
 }
@@ -222,7 +331,7 @@ pub(crate) fn nom_size_encoding(input: &[u8]) -> IResult<&[u8], EncodedLength> {
 /// Parse size-encoded string
 ///
 /// Note: We work with values as &[u8], that includes strings
-pub(crate) fn nom_size_encoded_string(input: &[u8]) -> IResult<&[u8], EncodedString> {
+pub(crate) fn nom_size_encoded_string(input: &[u8]) -> IResult<&[u8], EncodedString<'_>> {
     let (input, encoded_length) = nom_size_encoding(input)?;
 
     match encoded_length {
@@ -233,9 +342,637 @@ pub(crate) fn nom_size_encoded_string(input: &[u8]) -> IResult<&[u8], EncodedStr
         EncodedLength::U8(val) => Ok((input, EncodedString::U8(val))),
         EncodedLength::U16(val) => Ok((input, EncodedString::U16(val))),
         EncodedLength::U32(val) => Ok((input, EncodedString::U32(val))),
+        EncodedLength::Lzf => {
+            let (input, compressed_length) = nom_size_encoding(input)?;
+            let (input, length) = nom_size_encoding(input)?;
+            let (input, compressed) = take(compressed_length.as_usize()).parse(input)?;
+            match lzf_decompress(compressed, length.as_usize()) {
+                Some(string) => Ok((input, EncodedString::Decompressed(string))),
+                None => Err(failure(input)),
+            }
+        }
+    }
+}
+
+fn failure(input: &[u8]) -> nom::Err<Error<&[u8]>> {
+    nom::Err::Failure(Error::new(input, ErrorKind::Verify))
+}
+
+/// Decompress LZF compressed data, returning None if the data is malformed
+///
+/// The compressed data is a sequence of chunks, each starting with a control byte. If the top 3
+/// bits of the control byte are zero, it's a literal run of (control + 1) bytes. Otherwise it's a
+/// back reference, where the top 3 bits are the length (with 7 meaning that the next byte should
+/// be added to it) and the remaining bits along with the following byte are the offset back into
+/// the output.
+fn lzf_decompress(input: &[u8], length: usize) -> Option<Vec<u8>> {
+    let mut output = Vec::with_capacity(length);
+    let mut i = 0;
+
+    while i < input.len() {
+        let control = input[i] as usize;
+        i += 1;
+
+        if control < 32 {
+            let run = input.get(i..i + control + 1)?;
+            output.extend_from_slice(run);
+            i += control + 1;
+        } else {
+            let mut run = control >> 5;
+            if run == 7 {
+                run += *input.get(i)? as usize;
+                i += 1;
+            }
+            let offset = ((control & 0x1F) << 8) + *input.get(i)? as usize + 1;
+            i += 1;
+
+            let start = output.len().checked_sub(offset)?;
+            // The reference can overlap with the bytes being written, so copy one at a time
+            for j in 0..run + 2 {
+                output.push(output[start + j]);
+            }
+        }
+    }
+
+    (output.len() == length).then_some(output)
+}
+
+/// Parse a string and then run a parser over its contents, for values that are encoded as blobs
+///
+/// Errors from the inner parser can't reference the blob, so they're reported at the input
+fn nom_blob<T>(input: &[u8], parser: impl Fn(&[u8]) -> IResult<&[u8], T>) -> IResult<&[u8], T> {
+    let (input, blob) = nom_size_encoded_string(input)?;
+    match parser(&blob.to_bytes()) {
+        Ok((_, value)) => Ok((input, value)),
+        Err(_) => Err(failure(input)),
     }
 }
 
+/// Parse a ziplist, the legacy compact encoding of lists, hashes and sorted sets
+///
+/// A ziplist has a header of the total bytes (4), the offset to the tail (4) and the number of
+/// entries (2), followed by the entries and a terminating 0xFF. Each entry starts with the length
+/// of the previous entry, either 1 byte or 0xFE followed by 4 bytes, and then the encoding of the
+/// entry itself, which is either a string length or an integer type.
+pub(crate) fn nom_ziplist(input: &[u8]) -> IResult<&[u8], Vec<Vec<u8>>> {
+    let (mut input, _header) = take(10usize).parse(input)?;
+    let mut entries = Vec::new();
+
+    loop {
+        let (rest, first) = take(1usize).parse(input)?;
+        if first[0] == 0xFF {
+            return Ok((rest, entries));
+        }
+        let rest = if first[0] == 0xFE {
+            take(4usize).parse(rest)?.0
+        } else {
+            rest
+        };
+
+        let (rest, encoding) = take(1usize).parse(rest)?;
+        let encoding = encoding[0];
+        let (rest, entry) = match encoding >> 6 {
+            0b00 => map(take(encoding & 0x3F), <[u8]>::to_vec).parse(rest)?,
+            0b01 => {
+                let (rest, next) = take(1usize).parse(rest)?;
+                let length = (((encoding & 0x3F) as usize) << 8) | next[0] as usize;
+                map(take(length), <[u8]>::to_vec).parse(rest)?
+            }
+            0b10 => {
+                let (rest, length) = take(4usize).parse(rest)?;
+                let length = u32::from_be_bytes(length.try_into().unwrap()) as usize;
+                map(take(length), <[u8]>::to_vec).parse(rest)?
+            }
+            _ => {
+                let (rest, value) = match encoding {
+                    0xC0 => nom_le_signed(rest, 2)?,
+                    0xD0 => nom_le_signed(rest, 4)?,
+                    0xE0 => nom_le_signed(rest, 8)?,
+                    0xF0 => nom_le_signed(rest, 3)?,
+                    0xFE => nom_le_signed(rest, 1)?,
+                    // Immediate 4 bit integer, offset by one so that 0b0000 is not used
+                    0xF1..=0xFD => (rest, (encoding & 0x0F) as i64 - 1),
+                    _ => return Err(failure(rest)),
+                };
+                (rest, value.to_string().into_bytes())
+            }
+        };
+
+        entries.push(entry);
+        input = rest;
+    }
+}
+
+/// Parse a listpack, the compact encoding of small lists, hashes, sets and sorted sets
+///
+/// A listpack has a header of the total bytes (4) and the number of elements (2), followed by
+/// the elements and a terminating 0xFF. Each element is an encoding byte, which determines
+/// whether it's an integer or a string and its size, the data, and then the length of the
+/// encoding and data (the "backlen") in a variable length encoding so it can be traversed
+/// backwards.
+pub(crate) fn nom_listpack(input: &[u8]) -> IResult<&[u8], Vec<Vec<u8>>> {
+    let (mut input, _header) = take(6usize).parse(input)?;
+    let mut entries = Vec::new();
+
+    loop {
+        let (rest, first) = take(1usize).parse(input)?;
+        let encoding = first[0];
+
+        let (rest, entry, entry_length) = match encoding {
+            0xFF => return Ok((rest, entries)),
+            // 7 bit unsigned integer
+            0x00..=0x7F => (rest, encoding.to_string().into_bytes(), 1),
+            // 6 bit string length
+            0x80..=0xBF => {
+                let length = (encoding & 0x3F) as usize;
+                let (rest, string) = take(length).parse(rest)?;
+                (rest, string.to_vec(), 1 + length)
+            }
+            // 13 bit signed integer
+            0xC0..=0xDF => {
+                let (rest, next) = take(1usize).parse(rest)?;
+                let value = (((encoding & 0x1F) as i64) << 8) | next[0] as i64;
+                let value = if value >= 1 << 12 {
+                    value - (1 << 13)
+                } else {
+                    value
+                };
+                (rest, value.to_string().into_bytes(), 2)
+            }
+            // 12 bit string length
+            0xE0..=0xEF => {
+                let (rest, next) = take(1usize).parse(rest)?;
+                let length = (((encoding & 0x0F) as usize) << 8) | next[0] as usize;
+                let (rest, string) = take(length).parse(rest)?;
+                (rest, string.to_vec(), 2 + length)
+            }
+            // 32 bit string length
+            0xF0 => {
+                let (rest, length) = nom_le_int(rest)?;
+                let (rest, string) = take(length as usize).parse(rest)?;
+                (rest, string.to_vec(), 5 + length as usize)
+            }
+            0xF1..=0xF4 => {
+                let size = match encoding {
+                    0xF1 => 2,
+                    0xF2 => 3,
+                    0xF3 => 4,
+                    _ => 8,
+                };
+                let (rest, value) = nom_le_signed(rest, size)?;
+                (rest, value.to_string().into_bytes(), 1 + size)
+            }
+            _ => return Err(failure(rest)),
+        };
+
+        // Skip over the backlen, which uses 7 bits per byte
+        let backlen_size: usize = match entry_length {
+            0..=127 => 1,
+            128..=16_382 => 2,
+            16_383..=2_097_150 => 3,
+            2_097_151..=268_435_454 => 4,
+            _ => 5,
+        };
+        let (rest, _backlen) = take(backlen_size).parse(rest)?;
+
+        entries.push(entry);
+        input = rest;
+    }
+}
+
+/// Parse an intset, returning the integers formatted as strings
+///
+/// The intset starts with the size of each integer in bytes (2, 4 or 8) and the number of
+/// integers, both as 4 byte little-endian integers, followed by the sorted integers, little-endian
+pub(crate) fn nom_intset(input: &[u8]) -> IResult<&[u8], Vec<Vec<u8>>> {
+    let (input, size) = nom_le_int(input)?;
+    if !matches!(size, 2 | 4 | 8) {
+        return Err(failure(input));
+    }
+    let (input, length) = nom_le_int(input)?;
+    let (input, values) =
+        count(|i| nom_le_signed(i, size as usize), length as usize).parse(input)?;
+    Ok((
+        input,
+        values
+            .into_iter()
+            .map(|value| value.to_string().into_bytes())
+            .collect(),
+    ))
+}
+
+/// Parse a score of a sorted set stored as a string, in the legacy sorted set encoding
+///
+/// The length of the string is a single byte, where 253, 254 and 255 are reserved for NaN,
+/// positive infinity and negative infinity, which have no string
+pub(crate) fn nom_string_score(input: &[u8]) -> IResult<&[u8], f64> {
+    let (input, length) = take(1usize).parse(input)?;
+    match length[0] {
+        253 => Ok((input, f64::NAN)),
+        254 => Ok((input, f64::INFINITY)),
+        255 => Ok((input, f64::NEG_INFINITY)),
+        length => {
+            let (rest, score) = take(length as usize).parse(input)?;
+            match parse_score(score) {
+                Some(score) => Ok((rest, score)),
+                None => Err(failure(input)),
+            }
+        }
+    }
+}
+
+/// Parse a score formatted as a string, as they are in ziplists and listpacks
+fn parse_score(score: &[u8]) -> Option<f64> {
+    std::str::from_utf8(score).ok()?.parse().ok()
+}
+
+/// Pair up the entries of a ziplist or listpack holding a sorted set, which alternate between
+/// members and scores
+fn into_member_scores(
+    input: &[u8],
+    entries: Vec<Vec<u8>>,
+) -> Result<MemberScores, nom::Err<Error<&[u8]>>> {
+    into_pairs(input, entries)?
+        .into_iter()
+        .map(|(member, score)| Ok((member, parse_score(&score).ok_or_else(|| failure(input))?)))
+        .collect()
+}
+
+/// Parse a little-endian signed integer of the given size in bytes
+fn nom_le_signed(input: &[u8], size: usize) -> IResult<&[u8], i64> {
+    let (input, bytes) = take(size).parse(input)?;
+    let mut buf = [0u8; 8];
+    buf[..size].copy_from_slice(bytes);
+    // Shift up and back down to sign extend
+    let shift = 64 - size * 8;
+    Ok((input, (i64::from_le_bytes(buf) << shift) >> shift))
+}
+
+/// Parse a value of the given type
+pub(crate) fn nom_value<'a>(
+    input: &'a [u8],
+    value_type: &ValueTypeEncoding,
+) -> IResult<&'a [u8], RdbValue> {
+    match value_type {
+        ValueTypeEncoding::String => {
+            let (input, value) = nom_size_encoded_string(input)?;
+            Ok((input, RdbValue::String(value.to_bytes())))
+        }
+        ValueTypeEncoding::List => {
+            let (input, length) = nom_size_encoding(input)?;
+            let (input, elements) =
+                count(nom_size_encoded_string, length.as_usize()).parse(input)?;
+            Ok((
+                input,
+                RdbValue::List(elements.iter().map(EncodedString::to_bytes).collect()),
+            ))
+        }
+        ValueTypeEncoding::Ziplist => {
+            let (input, elements) = nom_blob(input, nom_ziplist)?;
+            Ok((input, RdbValue::List(elements)))
+        }
+        ValueTypeEncoding::ListInQuicklist => {
+            let (input, length) = nom_size_encoding(input)?;
+            let (input, nodes) =
+                count(|i| nom_blob(i, nom_ziplist), length.as_usize()).parse(input)?;
+            Ok((input, RdbValue::List(nodes.into_iter().flatten().collect())))
+        }
+        ValueTypeEncoding::ListInQuicklist2 => {
+            let (mut input, length) = nom_size_encoding(input)?;
+            let mut elements = Vec::new();
+            for _ in 0..length.as_usize() {
+                // Nodes are either a single plain element (1) or a listpack (2)
+                let (rest, container) = nom_size_encoding(input)?;
+                if container.as_usize() == 1 {
+                    let (rest, element) = nom_size_encoded_string(rest)?;
+                    elements.push(element.to_bytes());
+                    input = rest;
+                } else {
+                    let (rest, node) = nom_blob(rest, nom_listpack)?;
+                    elements.extend(node);
+                    input = rest;
+                }
+            }
+            Ok((input, RdbValue::List(elements)))
+        }
+        ValueTypeEncoding::Hash => {
+            let (input, length) = nom_size_encoding(input)?;
+            let (input, pairs) = count(
+                (nom_size_encoded_string, nom_size_encoded_string),
+                length.as_usize(),
+            )
+            .parse(input)?;
+            Ok((
+                input,
+                RdbValue::Hash(
+                    pairs
+                        .iter()
+                        .map(|(field, value)| (field.to_bytes(), value.to_bytes()))
+                        .collect(),
+                ),
+            ))
+        }
+        ValueTypeEncoding::HashmapInZiplist => {
+            let (rest, entries) = nom_blob(input, nom_ziplist)?;
+            Ok((rest, RdbValue::Hash(into_pairs(input, entries)?)))
+        }
+        ValueTypeEncoding::HashListpack => {
+            let (rest, entries) = nom_blob(input, nom_listpack)?;
+            Ok((rest, RdbValue::Hash(into_pairs(input, entries)?)))
+        }
+        ValueTypeEncoding::Set => {
+            let (input, length) = nom_size_encoding(input)?;
+            let (input, members) =
+                count(nom_size_encoded_string, length.as_usize()).parse(input)?;
+            Ok((
+                input,
+                RdbValue::Set(members.iter().map(EncodedString::to_bytes).collect()),
+            ))
+        }
+        ValueTypeEncoding::Intset => {
+            let (input, members) = nom_blob(input, nom_intset)?;
+            Ok((input, RdbValue::Set(members)))
+        }
+        ValueTypeEncoding::SetListpack => {
+            let (input, members) = nom_blob(input, nom_listpack)?;
+            Ok((input, RdbValue::Set(members)))
+        }
+        ValueTypeEncoding::SortedSet => {
+            let (input, length) = nom_size_encoding(input)?;
+            let (input, entries) = count(
+                (nom_size_encoded_string, nom_string_score),
+                length.as_usize(),
+            )
+            .parse(input)?;
+            Ok((
+                input,
+                RdbValue::SortedSet(
+                    entries
+                        .iter()
+                        .map(|(member, score)| (member.to_bytes(), *score))
+                        .collect(),
+                ),
+            ))
+        }
+        ValueTypeEncoding::SortedSet2 => {
+            let (input, length) = nom_size_encoding(input)?;
+            let (input, entries) =
+                count((nom_size_encoded_string, nom_le_long), length.as_usize()).parse(input)?;
+            Ok((
+                input,
+                RdbValue::SortedSet(
+                    entries
+                        .iter()
+                        .map(|(member, score)| (member.to_bytes(), f64::from_bits(*score)))
+                        .collect(),
+                ),
+            ))
+        }
+        ValueTypeEncoding::SortedSetInZiplist => {
+            let (rest, entries) = nom_blob(input, nom_ziplist)?;
+            Ok((
+                rest,
+                RdbValue::SortedSet(into_member_scores(input, entries)?),
+            ))
+        }
+        ValueTypeEncoding::SortedSetListpack => {
+            let (rest, entries) = nom_blob(input, nom_listpack)?;
+            Ok((
+                rest,
+                RdbValue::SortedSet(into_member_scores(input, entries)?),
+            ))
+        }
+        ValueTypeEncoding::HashMetadata => {
+            // The expiries are stored relative to the earliest one, plus one so that zero can mean
+            // that the field has no expiry
+            let (input, min_expiry) = nom_le_long(input)?;
+            let (mut input, length) = nom_size_encoding(input)?;
+            let mut entries = Vec::with_capacity(length.as_usize());
+            for _ in 0..length.as_usize() {
+                let (rest, expiry) = nom_size_encoding(input)?;
+                let (rest, (field, value)) =
+                    (nom_size_encoded_string, nom_size_encoded_string).parse(rest)?;
+                let expiry = match expiry.as_usize() as u64 {
+                    0 => None,
+                    relative => Some(min_expiry + relative - 1),
+                };
+                entries.push((field.to_bytes(), value.to_bytes(), expiry));
+                input = rest;
+            }
+            Ok((input, RdbValue::HashWithExpiries(entries)))
+        }
+        ValueTypeEncoding::HashListpackEx => {
+            // The listpack holds field, value and expiry triplets, where an expiry of zero means
+            // that the field has none
+            let (input, _min_expiry) = nom_le_long(input)?;
+            let (rest, entries) = nom_blob(input, nom_listpack)?;
+            if !entries.len().is_multiple_of(3) {
+                return Err(failure(input));
+            }
+            let mut triplets = Vec::with_capacity(entries.len() / 3);
+            let mut entries = entries.into_iter();
+            while let (Some(field), Some(value), Some(expiry)) =
+                (entries.next(), entries.next(), entries.next())
+            {
+                let expiry = std::str::from_utf8(&expiry)
+                    .ok()
+                    .and_then(|expiry| expiry.parse::<u64>().ok())
+                    .ok_or_else(|| failure(input))?;
+                triplets.push((field, value, (expiry != 0).then_some(expiry)));
+            }
+            Ok((rest, RdbValue::HashWithExpiries(triplets)))
+        }
+        ValueTypeEncoding::StreamListpacks => nom_stream(input, 1),
+        ValueTypeEncoding::StreamListpacks2 => nom_stream(input, 2),
+        ValueTypeEncoding::StreamListpacks3 => nom_stream(input, 3),
+        _ => Err(nom::Err::Failure(Error::new(input, ErrorKind::Switch))),
+    }
+}
+
+/// Flag of a stream entry in a listpack that has been deleted
+const STREAM_ITEM_FLAG_DELETED: u64 = 1;
+/// Flag of a stream entry in a listpack that has the same fields as the master entry
+const STREAM_ITEM_FLAG_SAMEFIELDS: u64 = 2;
+
+/// Parse a length that is stored as a size encoding
+fn nom_length(input: &[u8]) -> IResult<&[u8], u64> {
+    map(nom_size_encoding, |length| length.as_usize() as u64).parse(input)
+}
+
+/// Parse a stream ID that is stored as two lengths
+fn nom_stream_id(input: &[u8]) -> IResult<&[u8], StreamEntryId> {
+    (nom_length, nom_length).parse(input)
+}
+
+/// Parse a stream ID that is stored as 16 bytes, the milliseconds and sequence number big-endian
+fn nom_raw_stream_id(input: &[u8]) -> IResult<&[u8], StreamEntryId> {
+    let (input, bytes) = take(16usize).parse(input)?;
+    let ms = u64::from_be_bytes(bytes[..8].try_into().unwrap());
+    let seq = u64::from_be_bytes(bytes[8..].try_into().unwrap());
+    Ok((input, (ms, seq)))
+}
+
+/// Parse a stream in one of the listpack encodings, where the version is 1, 2 or 3
+///
+/// The entries are stored in listpacks, each keyed by the ID of its master entry as a 16 byte
+/// string. See `stream_listpack_entries` for the format of the listpacks. The entries are followed
+/// by the metadata of the stream and its consumer groups.
+fn nom_stream(input: &[u8], version: u8) -> IResult<&[u8], RdbValue> {
+    let (mut input, nodes) = nom_length(input)?;
+    let mut entries = Vec::new();
+    for _ in 0..nodes {
+        let (rest, master_id) = nom_blob(input, nom_raw_stream_id)?;
+        let (rest, listpack) = nom_blob(rest, nom_listpack)?;
+        entries.extend(stream_listpack_entries(input, master_id, listpack)?);
+        input = rest;
+    }
+
+    let (input, _length) = nom_length(input)?;
+    let (mut input, last_id) = nom_stream_id(input)?;
+    let mut max_deleted_id = (0, 0);
+    let mut entries_added = entries.len() as u64;
+    if version >= 2 {
+        let (rest, (_first_id, deleted, added)) =
+            (nom_stream_id, nom_stream_id, nom_length).parse(input)?;
+        max_deleted_id = deleted;
+        entries_added = added;
+        input = rest;
+    }
+
+    let (input, groups) = nom_length(input)?;
+    let (input, groups) =
+        count(|input| nom_consumer_group(input, version), groups as usize).parse(input)?;
+    Ok((
+        input,
+        RdbValue::Stream(RdbStream {
+            entries,
+            last_id,
+            max_deleted_id,
+            entries_added,
+            groups,
+        }),
+    ))
+}
+
+/// Parse a consumer group of a stream, with its pending entries and consumers
+///
+/// Counts of entries read are only stored from version 2, and the active times of consumers from
+/// version 3. Older consumers are taken to have been active when they were last seen. Unknown
+/// counts and times are stored as -1.
+fn nom_consumer_group(input: &[u8], version: u8) -> IResult<&[u8], RdbConsumerGroup> {
+    let (input, (name, last_id)) = (nom_size_encoded_string, nom_stream_id).parse(input)?;
+    let (input, entries_read) = if version >= 2 {
+        map(nom_length, |read| (read != u64::MAX).then_some(read)).parse(input)?
+    } else {
+        (input, None)
+    };
+    let (input, pending) = nom_length(input)?;
+    let (input, pending) = count(
+        (nom_raw_stream_id, nom_le_long, nom_length),
+        pending as usize,
+    )
+    .parse(input)?;
+
+    let (mut input, consumers) = nom_length(input)?;
+    let mut group_consumers = Vec::with_capacity(consumers as usize);
+    for _ in 0..consumers {
+        let (rest, (name, seen_time)) = (nom_size_encoded_string, nom_le_long).parse(input)?;
+        let (rest, active_time) = if version >= 3 {
+            map(nom_le_long, |time| (time != u64::MAX).then_some(time)).parse(rest)?
+        } else {
+            (rest, Some(seen_time))
+        };
+        let (rest, pending) = nom_length(rest)?;
+        let (rest, pending) = count(nom_raw_stream_id, pending as usize).parse(rest)?;
+        group_consumers.push(RdbConsumer {
+            name: name.to_bytes(),
+            seen_time,
+            active_time,
+            pending,
+        });
+        input = rest;
+    }
+
+    Ok((
+        input,
+        RdbConsumerGroup {
+            name: name.to_bytes(),
+            last_id,
+            entries_read,
+            pending,
+            consumers: group_consumers,
+        },
+    ))
+}
+
+/// Read the entries out of a listpack of a stream, skipping those that have been deleted
+///
+/// The listpack starts with the master entry: the number of valid and deleted entries, the number
+/// of master fields, the master fields, and a zero. Every entry then has flags, the difference of
+/// its ID to the master ID, and either just the values if it has the same fields as the master
+/// entry, or the number of fields followed by field-value pairs. Each entry ends with the number
+/// of listpack elements it took up, not counting that number itself.
+fn stream_listpack_entries(
+    input: &[u8],
+    (master_ms, master_seq): StreamEntryId,
+    listpack: Vec<Vec<u8>>,
+) -> Result<StreamEntries, nom::Err<Error<&[u8]>>> {
+    let mut elements = listpack.into_iter();
+    let mut next = || elements.next().ok_or_else(|| failure(input));
+    let integer = |element: Vec<u8>| {
+        std::str::from_utf8(&element)
+            .ok()
+            .and_then(|element| element.parse::<i64>().ok())
+            .ok_or_else(|| failure(input))
+    };
+
+    let valid = integer(next()?)?;
+    let deleted = integer(next()?)?;
+    let master_fields = (0..integer(next()?)?)
+        .map(|_| next())
+        .collect::<Result<Vec<_>, _>>()?;
+    next()?;
+
+    let mut entries = Vec::with_capacity(valid.max(0) as usize);
+    for _ in 0..valid + deleted {
+        let flags = integer(next()?)? as u64;
+        // The differences can be negative, and the IDs are unsigned, so they wrap around
+        let ms = master_ms.wrapping_add(integer(next()?)? as u64);
+        let seq = master_seq.wrapping_add(integer(next()?)? as u64);
+        let fields = if flags & STREAM_ITEM_FLAG_SAMEFIELDS != 0 {
+            master_fields
+                .iter()
+                .map(|field| Ok((field.clone(), next()?)))
+                .collect::<Result<Vec<_>, _>>()?
+        } else {
+            (0..integer(next()?)?)
+                .map(|_| Ok((next()?, next()?)))
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        next()?;
+        if flags & STREAM_ITEM_FLAG_DELETED == 0 {
+            entries.push(((ms, seq), fields));
+        }
+    }
+    Ok(entries)
+}
+
+/// Pair up the entries of a ziplist or listpack, which alternate between fields and values
+fn into_pairs(
+    input: &[u8],
+    entries: Vec<Vec<u8>>,
+) -> Result<FieldValuePairs, nom::Err<Error<&[u8]>>> {
+    if !entries.len().is_multiple_of(2) {
+        return Err(failure(input));
+    }
+    let mut entries = entries.into_iter();
+    let mut pairs = Vec::with_capacity(entries.len() / 2);
+    while let (Some(field), Some(value)) = (entries.next(), entries.next()) {
+        pairs.push((field, value));
+    }
+    Ok(pairs)
+}
+
 /// Parse little-endian 4-byte unsigned integer
 pub(crate) fn nom_le_int(input: &[u8]) -> IResult<&[u8], u32> {
     let (input, bytes) = take(4usize).parse(input)?;
@@ -269,6 +1006,7 @@ mod tests {
         assert_eq!(nom_rdb_op_code(&[0xFD]), Ok((&b""[..], OpCode::EXPIRETIME)));
         assert_eq!(nom_rdb_op_code(&[0xFE]), Ok((&b""[..], OpCode::SELECTDB)));
         assert_eq!(nom_rdb_op_code(&[0xFF]), Ok((&b""[..], OpCode::EOF)));
+        assert_eq!(nom_rdb_op_code(&[0xF5]), Ok((&b""[..], OpCode::FUNCTION2)));
     }
 
     #[test]
@@ -409,4 +1147,70 @@ mod tests {
         assert_eq!(key, EncodedString::String(&b"redis-bits"[..]));
         assert_eq!(value, EncodedString::U8(64));
     }
+
+    #[test]
+    fn test_lzf_decompress() {
+        // A literal run of "abc" followed by a back reference copying it again
+        let data = &[0x02, b'a', b'b', b'c', 0x20, 0x02];
+        assert_eq!(lzf_decompress(data, 6), Some(b"abcabc".to_vec()));
+        // The output has to match the expected length
+        assert_eq!(lzf_decompress(data, 7), None);
+        // A back reference before the start of the output is invalid
+        assert_eq!(lzf_decompress(&[0x20, 0x05], 3), None);
+    }
+
+    #[test]
+    fn test_nom_ziplist() {
+        let mut data = vec![0; 10];
+        data.extend_from_slice(&[0x00, 0x02, b'h', b'i']);
+        data.extend_from_slice(&[0x04, 0xF3]);
+        data.extend_from_slice(&[0x02, 0xC0, 0x39, 0x30]);
+        data.push(0xFF);
+
+        let (input, entries) = nom_ziplist(&data).unwrap();
+        assert_eq!(input, &b""[..]);
+        assert_eq!(
+            entries,
+            vec![b"hi".to_vec(), b"2".to_vec(), b"12345".to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_nom_listpack() {
+        let mut data = vec![0; 6];
+        data.extend_from_slice(&[0x05, 0x01]);
+        data.extend_from_slice(&[0x82, b'h', b'i', 0x03]);
+        data.extend_from_slice(&[0xDF, 0xFF, 0x02]);
+        data.push(0xFF);
+
+        let (input, entries) = nom_listpack(&data).unwrap();
+        assert_eq!(input, &b""[..]);
+        assert_eq!(entries, vec![b"5".to_vec(), b"hi".to_vec(), b"-1".to_vec()]);
+    }
+
+    #[test]
+    fn test_nom_intset() {
+        let mut data = Vec::new();
+        data.extend_from_slice(&2u32.to_le_bytes());
+        data.extend_from_slice(&3u32.to_le_bytes());
+        for value in [-300i16, 7, 1000] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+
+        let (input, members) = nom_intset(&data).unwrap();
+        assert_eq!(input, &b""[..]);
+        assert_eq!(
+            members,
+            vec![b"-300".to_vec(), b"7".to_vec(), b"1000".to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_nom_string_score() {
+        assert_eq!(nom_string_score(b"\x031.5rest"), Ok((&b"rest"[..], 1.5)));
+        assert_eq!(nom_string_score(&[254]), Ok((&b""[..], f64::INFINITY)));
+        assert_eq!(nom_string_score(&[255]), Ok((&b""[..], f64::NEG_INFINITY)));
+        assert!(nom_string_score(&[253]).unwrap().1.is_nan());
+        assert!(nom_string_score(b"\x02ab").is_err());
+    }
 }
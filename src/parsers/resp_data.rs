@@ -2,8 +2,8 @@ use crate::error::{Result, RustisError};
 use crate::resp::RESPData;
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take, take_until},
-    character::complete::digit1,
+    bytes::streaming::{tag, take, take_until},
+    character::streaming::digit1,
     combinator::{map, map_res},
     multi::count,
     sequence::delimited,
@@ -20,7 +20,7 @@ use nom::{
 /// ```ignore
 /// +OK\r\n
 /// ```
-fn nom_simple_string(input: &[u8]) -> IResult<&[u8], RESPData> {
+fn nom_simple_string(input: &[u8]) -> IResult<&[u8], RESPData<'_>> {
     map(
         delimited(tag(&b"+"[..]), take_until(&b"\r\n"[..]), tag(&b"\r\n"[..])),
         |s: &[u8]| RESPData::SimpleString(s),
@@ -40,7 +40,7 @@ fn nom_simple_string(input: &[u8]) -> IResult<&[u8], RESPData> {
 /// ```ignore
 /// -Error message\r\n
 /// ```
-fn nom_simple_error(input: &[u8]) -> IResult<&[u8], RESPData> {
+fn nom_simple_error(input: &[u8]) -> IResult<&[u8], RESPData<'_>> {
     map(
         delimited(tag(&b"-"[..]), take_until(&b"\r\n"[..]), tag(&b"\r\n"[..])),
         |s: &[u8]| RESPData::SimpleError(s),
@@ -48,6 +48,42 @@ fn nom_simple_error(input: &[u8]) -> IResult<&[u8], RESPData> {
     .parse(input)
 }
 
+/// Parse an integer
+///
+/// > This type is a CRLF-terminated string that represents a signed, base-10, 64-bit integer.
+///
+/// Example:
+///
+/// ```ignore
+/// :-42\r\n
+/// ```
+fn nom_integer(input: &[u8]) -> IResult<&[u8], RESPData<'_>> {
+    map_res(
+        delimited(tag(&b":"[..]), take_until(&b"\r\n"[..]), tag(&b"\r\n"[..])),
+        |digits: &[u8]| {
+            std::str::from_utf8(digits)
+                .map_err(|e| e.to_string())
+                .and_then(|s| s.parse::<i64>().map_err(|e| e.to_string()))
+                .map(RESPData::Integer)
+        },
+    )
+    .parse(input)
+}
+
+/// Parse a RESP2 null, which is either a null bulk string or a null array
+///
+/// Example:
+///
+/// ```ignore
+/// $-1\r\n
+/// ```
+fn nom_null(input: &[u8]) -> IResult<&[u8], RESPData<'_>> {
+    map(alt((tag(&b"$-1\r\n"[..]), tag(&b"*-1\r\n"[..]))), |_| {
+        RESPData::Null
+    })
+    .parse(input)
+}
+
 /// Parse a bulk string
 ///
 /// > A bulk string represents a single binary string. The string can be of any size, but by
@@ -63,7 +99,7 @@ fn nom_simple_error(input: &[u8]) -> IResult<&[u8], RESPData> {
 /// ```ignore
 /// $5\r\nhello\r\n
 /// ```
-fn nom_bulk_string(input: &[u8]) -> IResult<&[u8], RESPData> {
+fn nom_bulk_string(input: &[u8]) -> IResult<&[u8], RESPData<'_>> {
     let (input, length) = map_res(
         delimited(tag(&b"$"[..]), digit1, tag(&b"\r\n"[..])),
         |digits: &[u8]| {
@@ -90,7 +126,7 @@ fn nom_bulk_string(input: &[u8]) -> IResult<&[u8], RESPData> {
 /// ```ignore
 /// *<number-of-elements>\r\n<element-1>...<element-n>
 /// ```
-fn nom_array(input: &[u8]) -> IResult<&[u8], RESPData> {
+fn nom_array(input: &[u8]) -> IResult<&[u8], RESPData<'_>> {
     let (input, length) = map_res(
         delimited(tag(&b"*"[..]), digit1, tag(&b"\r\n"[..])),
         |digits: &[u8]| {
@@ -106,14 +142,14 @@ fn nom_array(input: &[u8]) -> IResult<&[u8], RESPData> {
     Ok((input, RESPData::Array(elements)))
 }
 
-fn nom_data(input: &[u8]) -> IResult<&[u8], RESPData> {
+fn nom_data(input: &[u8]) -> IResult<&[u8], RESPData<'_>> {
     alt((
         nom_simple_string,
         nom_simple_error,
+        nom_null,
         nom_bulk_string,
-        // nom_integer,
+        nom_integer,
         nom_array,
-        // nom_null,
         // nom_boolean,
         // nom_double,
         // nom_big_number,
@@ -127,28 +163,38 @@ fn nom_data(input: &[u8]) -> IResult<&[u8], RESPData> {
     .parse(input)
 }
 
-/// Parse input into `RESPData`
-pub(crate) fn parse(input: &[u8]) -> Result<Vec<RESPData>> {
-    let mut data = vec![];
-    let mut input = input;
-
-    while !input.is_empty() {
-        match nom_data(input) {
-            Ok((remaining, d)) => {
-                data.push(d);
-                input = remaining;
-            }
-            Err(err) => return Err(RustisError::InvalidInput(format!("{:?}", err))),
-        }
+/// Parse a single message from the start of the input
+///
+/// Returns `None` if the input doesn't hold a complete message yet, otherwise the message along
+/// with the number of bytes it took up.
+pub(crate) fn parse_one(input: &[u8]) -> Result<Option<(RESPData<'_>, usize)>> {
+    if input.is_empty() {
+        return Ok(None);
+    }
+    match nom_data(input) {
+        Ok((rest, data)) => Ok(Some((data, input.len() - rest.len()))),
+        Err(nom::Err::Incomplete(_)) => Ok(None),
+        Err(err) => Err(RustisError::InvalidInput(format!("{:?}", err))),
     }
-
-    Ok(data)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Parse as many complete messages as possible, returning them with the bytes consumed
+    fn parse(input: &[u8]) -> Result<(Vec<RESPData<'_>>, usize)> {
+        let mut data = vec![];
+        let mut consumed = 0;
+
+        while let Some((d, length)) = parse_one(&input[consumed..])? {
+            data.push(d);
+            consumed += length;
+        }
+
+        Ok((data, consumed))
+    }
+
     #[test]
     fn test_nom_simple_string() {
         assert_eq!(
@@ -191,6 +237,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_nom_integer() {
+        assert_eq!(
+            nom_integer(b":1000\r\n"),
+            Ok((&b""[..], RESPData::Integer(1000)))
+        );
+        assert_eq!(
+            nom_integer(b":-42\r\n"),
+            Ok((&b""[..], RESPData::Integer(-42)))
+        );
+        assert!(nom_integer(b":abc\r\n").is_err());
+    }
+
+    #[test]
+    fn test_nom_null() {
+        assert_eq!(nom_null(b"$-1\r\n"), Ok((&b""[..], RESPData::Null)));
+        assert_eq!(nom_null(b"*-1\r\n"), Ok((&b""[..], RESPData::Null)));
+    }
+
     #[test]
     fn test_nom_array_empty() {
         assert_eq!(
@@ -215,15 +280,28 @@ mod tests {
 
     #[test]
     fn test_parse() {
-        assert_eq!(parse(b"+OK\r\n").unwrap()[0], RESPData::SimpleString(b"OK"));
         assert_eq!(
-            parse(b"-Error message\r\n").unwrap()[0],
-            RESPData::SimpleError(b"Error message")
+            parse(b"+OK\r\n").unwrap(),
+            (vec![RESPData::SimpleString(b"OK")], 5)
+        );
+        assert_eq!(
+            parse(b"-Error message\r\n").unwrap(),
+            (vec![RESPData::SimpleError(b"Error message")], 16)
+        );
+        assert_eq!(
+            parse(b"$5\r\nhello\r\n").unwrap(),
+            (vec![RESPData::BulkString(b"hello")], 11)
         );
+    }
+
+    #[test]
+    fn test_parse_incomplete() {
+        assert_eq!(parse(b"*2\r\n$3\r\nfoo\r\n$3\r\nba").unwrap(), (vec![], 0));
         assert_eq!(
-            parse(b"$5\r\nhello\r\n").unwrap()[0],
-            RESPData::BulkString(b"hello")
+            parse(b"+OK\r\n$5\r\nhel").unwrap(),
+            (vec![RESPData::SimpleString(b"OK")], 5)
         );
+        assert_eq!(parse(b"*1").unwrap(), (vec![], 0));
     }
 
     #[test]
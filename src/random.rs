@@ -0,0 +1,78 @@
+use std::{
+    cell::Cell,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+thread_local! {
+    static STATE: Cell<u64> = Cell::new(seed());
+}
+
+/// Seed the generator from the current time and process ID, it only needs to differ between runs
+fn seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64;
+    // Zero is the one state that xorshift never leaves
+    (nanos ^ ((std::process::id() as u64) << 32)) | 1
+}
+
+/// A pseudo-random number from a xorshift64* generator
+///
+/// This is not cryptographically secure, it's only used for things like picking random members
+pub(crate) fn next_u64() -> u64 {
+    STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    })
+}
+
+/// A random index below the bound, which has to be greater than zero
+pub(crate) fn index(bound: usize) -> usize {
+    (next_u64() % bound as u64) as usize
+}
+
+/// Pick `count` distinct indexes below the bound, in random order
+///
+/// If the count is at least the bound, then all the indexes are returned
+pub(crate) fn distinct_indexes(bound: usize, count: usize) -> Vec<usize> {
+    let mut indexes: Vec<usize> = (0..bound).collect();
+    let count = count.min(bound);
+    // A partial Fisher-Yates shuffle, only shuffling as many as are needed
+    for i in 0..count {
+        let j = i + index(bound - i);
+        indexes.swap(i, j);
+    }
+    indexes.truncate(count);
+    indexes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_is_within_bound() {
+        for _ in 0..1000 {
+            assert!(index(7) < 7);
+        }
+    }
+
+    #[test]
+    fn test_distinct_indexes() {
+        let mut indexes = distinct_indexes(10, 4);
+        assert_eq!(indexes.len(), 4);
+        indexes.sort();
+        indexes.dedup();
+        assert_eq!(indexes.len(), 4);
+        assert!(indexes.iter().all(|&i| i < 10));
+
+        let mut indexes = distinct_indexes(3, 10);
+        indexes.sort();
+        assert_eq!(indexes, vec![0, 1, 2]);
+    }
+}
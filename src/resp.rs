@@ -1,11 +1,13 @@
+use std::io::Write;
+
 #[derive(Debug, PartialEq, Eq)]
 pub(crate) enum RESPData<'a> {
     SimpleString(&'a [u8]),
     SimpleError(&'a [u8]),
-    // Integer(i64),
+    Integer(i64),
     BulkString(&'a [u8]),
     Array(Vec<RESPData<'a>>),
-    // Null,
+    Null,
     // Boolean(bool),
     // Double(f64),
     // BigNumber(BigInt),
@@ -16,3 +18,70 @@ pub(crate) enum RESPData<'a> {
     // Set(Vec<RESPDataType>),
     // Push(Vec<RESPDataType>),
 }
+
+impl RESPData<'_> {
+    /// Encode the data as RESP2 and append it to the buffer
+    ///
+    /// Null is encoded as a null bulk string, as RESP2 has no dedicated null type
+    pub(crate) fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            RESPData::SimpleString(s) => {
+                buf.push(b'+');
+                buf.extend_from_slice(s);
+                buf.extend_from_slice(b"\r\n");
+            }
+            RESPData::SimpleError(e) => {
+                buf.push(b'-');
+                buf.extend_from_slice(e);
+                buf.extend_from_slice(b"\r\n");
+            }
+            RESPData::Integer(i) => {
+                write!(buf, ":{}\r\n", i).unwrap();
+            }
+            RESPData::BulkString(s) => {
+                write!(buf, "${}\r\n", s.len()).unwrap();
+                buf.extend_from_slice(s);
+                buf.extend_from_slice(b"\r\n");
+            }
+            RESPData::Array(elements) => {
+                write!(buf, "*{}\r\n", elements.len()).unwrap();
+                elements.iter().for_each(|element| element.encode(buf));
+            }
+            RESPData::Null => {
+                buf.extend_from_slice(b"$-1\r\n");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded(data: RESPData) -> Vec<u8> {
+        let mut buf = Vec::new();
+        data.encode(&mut buf);
+        buf
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encoded(RESPData::SimpleString(b"OK")), b"+OK\r\n");
+        assert_eq!(encoded(RESPData::SimpleError(b"ERR bad")), b"-ERR bad\r\n");
+        assert_eq!(encoded(RESPData::Integer(-12)), b":-12\r\n");
+        assert_eq!(encoded(RESPData::BulkString(b"foo")), b"$3\r\nfoo\r\n");
+        assert_eq!(encoded(RESPData::Null), b"$-1\r\n");
+    }
+
+    #[test]
+    fn test_encode_mixed_array() {
+        assert_eq!(
+            encoded(RESPData::Array(vec![
+                RESPData::BulkString(b"foo"),
+                RESPData::Null,
+                RESPData::Integer(1),
+            ])),
+            b"*3\r\n$3\r\nfoo\r\n$-1\r\n:1\r\n"
+        );
+    }
+}
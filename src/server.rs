@@ -1,12 +1,16 @@
-use crate::{connection::Connection, database::load_rdb, Config, Result};
+use crate::{
+    connection::{Acl, BlockedClients, Connection, PubSub, Scripts},
+    database::{self, load_rdb, save_rdb},
+    Config, Result, RustisError,
+};
 use nix::{
     poll::{poll, PollFd, PollFlags, PollTimeout},
     unistd::{close, fork, ForkResult},
 };
 use std::{
     cell::RefCell,
-    io::ErrorKind,
-    net::TcpListener,
+    io::{ErrorKind, Write},
+    net::{IpAddr, TcpListener},
     os::unix::io::{AsFd, AsRawFd},
     path::Path,
     process,
@@ -16,12 +20,49 @@ use std::{
 
 const POLL_TIMEOUT: u16 = 1000;
 
+/// How often keys that have expired are looked for, as Redis does by default (`hz 10`)
+const EXPIRE_CYCLE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The error clients that aren't local are refused with in protected mode, as Redis words it
+const PROTECTED_MODE_DENIED: &[u8] = b"-DENIED Redis is running in protected mode because \
+protected mode is enabled and no password is set for the default user. In this mode connections \
+are only accepted from the loopback interface. If you want to connect from external computers to \
+Redis you may adopt one of the following solutions: 1) Just disable protected mode sending the \
+command 'CONFIG SET protected-mode no' from the loopback interface by connecting to Redis from the \
+same host the server is running, however MAKE SURE Redis is not publicly accessible from internet \
+if you do so. Use CONFIG REWRITE to make this change permanent. 2) Alternatively you can just \
+disable the protected mode by editing the Redis configuration file, and setting the protected mode \
+option to 'no', and then restarting the server. 3) If you started the server manually just for \
+testing, restart it with the '--protected-mode no' option. 4) Set up an authentication password \
+for the default user. NOTE: You only need to do one of the above things in order for the server to \
+start accepting connections from the outside.\r\n";
+
+/// Check whether the host the server listens on only accepts local clients
+fn is_loopback_host(host: &str) -> bool {
+    match host.parse::<IpAddr>() {
+        Ok(ip) => ip.is_loopback(),
+        Err(_) => host.eq_ignore_ascii_case("localhost"),
+    }
+}
+
 pub struct Server {
-    listener: TcpListener,
-    connections: Vec<Connection>,
+    clients: Rc<Clients>,
     last_snapshot: Instant,
     snapshot_interval: Duration,
+    last_expire_cycle: Instant,
+}
+
+/// The listener and the connections, which are shared with the scripts, so that other clients can
+/// be served while a script is busy
+struct Clients {
+    listener: TcpListener,
+    /// The connections, except for the one being processed, which is taken out meanwhile
+    connections: RefCell<Vec<Connection>>,
     config: Rc<RefCell<Config>>,
+    blocked_clients: Rc<RefCell<BlockedClients>>,
+    pubsub: Rc<RefCell<PubSub>>,
+    scripts: Rc<RefCell<Scripts>>,
+    acl: Rc<RefCell<Acl>>,
 }
 
 impl Server {
@@ -38,23 +79,49 @@ impl Server {
         listener.set_nonblocking(true)?;
 
         // Check if the dbfilename exists
-        let db_path = {
-            let config = config.borrow();
-            format!("{}/{}", config.dir(), config.dbfilename())
-        };
+        let db_path = config.borrow().rdb_path();
+        let mut scripts = Scripts::default();
         if Path::new(&db_path).exists() {
             log::info!("Loading RDB file: {}", db_path);
-            load_rdb(&db_path)?;
+            let libraries = load_rdb(&db_path)?;
+            scripts.load_libraries(libraries);
         } else {
             log::debug!("No RDB file found at: {}", db_path);
         }
 
-        Ok(Server {
+        let (requirepass, aclfile) = {
+            let config = config.borrow();
+            (config.requirepass.clone(), config.aclfile.clone())
+        };
+        let mut acl = Acl::new(&requirepass);
+        if !aclfile.is_empty() {
+            log::info!("Loading ACL file: {}", aclfile);
+            acl.load_file(&aclfile).map_err(RustisError::InvalidInput)?;
+        }
+
+        let clients = Rc::new(Clients {
             listener,
-            connections: Vec::new(),
+            connections: RefCell::new(Vec::new()),
+            config,
+            blocked_clients: Rc::new(RefCell::new(BlockedClients::default())),
+            pubsub: Rc::new(RefCell::new(PubSub::default())),
+            scripts: Rc::new(RefCell::new(scripts)),
+            acl: Rc::new(RefCell::new(acl)),
+        });
+        let weak = Rc::downgrade(&clients);
+        clients.scripts.borrow_mut().set_busy_handler(move || {
+            if let Some(clients) = weak.upgrade() {
+                if let Err(e) = clients.poll_once(PollTimeout::ZERO) {
+                    log::error!("Error serving clients while a script is busy: {}", e);
+                }
+            }
+        });
+
+        Ok(Server {
+            clients,
             last_snapshot: Instant::now(),
             snapshot_interval,
-            config,
+            last_expire_cycle: Instant::now(),
         })
     }
 
@@ -63,43 +130,18 @@ impl Server {
     /// This will:
     ///     * Poll for events on the listener, accepting new connections
     ///     * Poll for events on the existing connections, processing them
+    ///     * Serve, time out or unblock clients that are blocked on keys
+    ///     * Evict keys that have expired (at a fixed interval), without waiting for them to be read
+    ///     * Deliver messages published to subscribed clients
     ///     * Fork the process (at a configurable interval), save a snapshot and exit (the child)
     pub fn run_once(&mut self) -> Result<()> {
-        // We need to keep track of how many connections exist when we poll, so that we can only
-        // drain those when we handle existing connections
-        let polled_count = self.connections.len();
-
-        let (listener_event, connection_events) = {
-            let mut poll_fds = Vec::with_capacity(1 + polled_count);
-            poll_fds.push(PollFd::new(self.listener.as_fd(), PollFlags::POLLIN));
-            for conn in &self.connections {
-                poll_fds.push(PollFd::new(conn.as_fd(), PollFlags::POLLIN));
-            }
-            match poll(&mut poll_fds, PollTimeout::from(POLL_TIMEOUT)) {
-                Ok(n) => {
-                    log::trace!("Events: {}", n);
-                    let listener_event = poll_fds[0].revents();
-                    let connection_events = poll_fds
-                        .iter()
-                        .skip(1)
-                        .map(|pfd| pfd.revents())
-                        .collect::<Vec<_>>();
-                    (listener_event, connection_events)
-                }
-                Err(e) => {
-                    log::error!("Poll error: {}", e);
-                    return Err(e.into());
-                }
-            }
-        };
-
-        if let Some(revents) = listener_event {
-            if revents.contains(PollFlags::POLLIN) {
-                self.accept_new_connections()?;
-            }
+        self.clients.poll_once(self.poll_timeout())?;
+        self.process_blocked_clients();
+        if self.last_expire_cycle.elapsed() >= EXPIRE_CYCLE_INTERVAL {
+            database::active_expire_cycle();
+            self.last_expire_cycle = Instant::now();
         }
-
-        self.process_existing_connections(&connection_events, polled_count);
+        self.deliver_messages();
 
         if self.last_snapshot.elapsed() >= self.snapshot_interval {
             self.fork_and_save();
@@ -122,12 +164,17 @@ impl Server {
                 log::debug!("Saving snapshot");
 
                 log::trace!("Closing all connections in forked snapshot saver");
-                for conn in &self.connections {
+                for conn in self.clients.connections.borrow().iter() {
                     let _ = close(conn.as_raw_fd());
                 }
-                let _ = close(self.listener.as_raw_fd());
+                let _ = close(self.clients.listener.as_raw_fd());
 
-                // TODO: Save snapshot
+                let db_path = self.clients.config.borrow().rdb_path();
+                let libraries = self.clients.scripts.borrow().library_codes();
+                if let Err(e) = save_rdb(&db_path, &libraries) {
+                    log::error!("Failed to save snapshot: {}", e);
+                    process::exit(1);
+                }
 
                 process::exit(0);
             }
@@ -140,13 +187,209 @@ impl Server {
         }
     }
 
-    fn accept_new_connections(&mut self) -> Result<()> {
+    /// How long to wait for events, which is cut short when a blocked client is about to time out
+    /// or the next expiry cycle is due
+    fn poll_timeout(&self) -> PollTimeout {
+        let timeout = Duration::from_millis(POLL_TIMEOUT as u64)
+            .min(EXPIRE_CYCLE_INTERVAL.saturating_sub(self.last_expire_cycle.elapsed()));
+        let timeout = self
+            .clients
+            .connections
+            .borrow()
+            .iter()
+            .filter_map(|conn| conn.blocked_deadline())
+            .min()
+            .map_or(timeout, |deadline| {
+                deadline
+                    .saturating_duration_since(Instant::now())
+                    .min(timeout)
+            });
+        // Round up, so that we don't wake up just before the deadline
+        let millis = timeout.as_millis() as u16 + (timeout.subsec_nanos() % 1_000_000 > 0) as u16;
+        PollTimeout::from(millis)
+    }
+
+    /// Process clients that are blocked on keys
+    ///
+    /// This will:
+    ///     * Unblock clients that were requested to be unblocked with CLIENT UNBLOCK
+    ///     * Time out clients whose deadline has passed
+    ///     * Serve clients blocked on keys that have been pushed to, in the order they blocked
+    ///
+    /// Unblocked clients then process any input they received while blocked, which can make more
+    /// keys ready, so this repeats until no keys are ready. Connections that fail while doing this
+    /// are closed.
+    fn process_blocked_clients(&mut self) {
+        let clients = &self.clients;
+        let mut unblocked = Vec::new();
+        let mut failed = Vec::new();
+
+        let requests = clients.blocked_clients.borrow_mut().take_unblock_requests();
+        {
+            let mut connections = clients.connections.borrow_mut();
+            for (id, error) in requests {
+                if let Some(conn) = connections.iter_mut().find(|c| c.id() == id) {
+                    match conn.force_unblock(error) {
+                        Ok(()) => unblocked.push(id),
+                        Err(_) => failed.push(id),
+                    }
+                }
+            }
+
+            let now = Instant::now();
+            for conn in connections.iter_mut() {
+                if conn
+                    .blocked_deadline()
+                    .is_some_and(|deadline| deadline <= now)
+                {
+                    match conn.time_out() {
+                        Ok(()) => unblocked.push(conn.id()),
+                        Err(_) => failed.push(conn.id()),
+                    }
+                }
+            }
+        }
+
+        loop {
+            // Processing input can run scripts, so the connection is taken out meanwhile
+            for id in unblocked.drain(..) {
+                clients.with_connection(id, |mut conn| match conn.process_input() {
+                    Ok(()) => Ok(conn),
+                    Err(e) => {
+                        log::error!("Error processing input: {}", e);
+                        Err(e)
+                    }
+                });
+            }
+
+            let ready_keys = clients.blocked_clients.borrow_mut().take_ready_keys();
+            if ready_keys.is_empty() {
+                break;
+            }
+
+            let mut connections = clients.connections.borrow_mut();
+            for key in ready_keys {
+                let waiting = clients.blocked_clients.borrow().waiting_on(&key);
+                for id in waiting {
+                    let Some(conn) = connections.iter_mut().find(|c| c.id() == id) else {
+                        continue;
+                    };
+                    match conn.serve_blocked(&key) {
+                        Ok(true) => unblocked.push(id),
+                        Ok(false) => {}
+                        Err(_) => failed.push(id),
+                    }
+                }
+            }
+        }
+
+        if !failed.is_empty() {
+            clients
+                .connections
+                .borrow_mut()
+                .retain(|conn| !failed.contains(&conn.id()));
+        }
+    }
+
+    /// Write the messages published since the last time to the clients subscribed to them
+    ///
+    /// This includes keyspace events that happened outside of commands, such as when serving
+    /// blocked clients. Clients that send another command before this get their messages ahead
+    /// of its reply instead. Connections that fail while doing this are closed.
+    fn deliver_messages(&mut self) {
+        let flags = self.clients.config.borrow().notify_keyspace_events();
+        let pending = {
+            let mut pubsub = self.clients.pubsub.borrow_mut();
+            pubsub.publish_keyspace_events(flags);
+            pubsub.take_pending()
+        };
+        if pending.is_empty() {
+            return;
+        }
+
+        self.clients.connections.borrow_mut().retain_mut(|conn| {
+            let Some(messages) = pending.get(&conn.id()) else {
+                return true;
+            };
+            match conn.deliver(messages) {
+                Ok(()) => true,
+                Err(e) => {
+                    log::error!("Error delivering messages: {}", e);
+                    false
+                }
+            }
+        });
+    }
+}
+
+impl Clients {
+    /// Poll for events once, accepting new connections and processing the existing ones
+    fn poll_once(&self, timeout: PollTimeout) -> Result<()> {
+        let (listener_event, connection_events) = {
+            let connections = self.connections.borrow();
+            let mut poll_fds = Vec::with_capacity(1 + connections.len());
+            poll_fds.push(PollFd::new(self.listener.as_fd(), PollFlags::POLLIN));
+            for conn in connections.iter() {
+                poll_fds.push(PollFd::new(conn.as_fd(), PollFlags::POLLIN));
+            }
+            match poll(&mut poll_fds, timeout) {
+                Ok(n) => {
+                    log::trace!("Events: {}", n);
+                    let listener_event = poll_fds[0].revents();
+                    let connection_events = connections
+                        .iter()
+                        .zip(poll_fds.iter().skip(1))
+                        .map(|(conn, pfd)| (conn.id(), pfd.revents()))
+                        .collect::<Vec<_>>();
+                    (listener_event, connection_events)
+                }
+                Err(e) => {
+                    log::error!("Poll error: {}", e);
+                    return Err(e.into());
+                }
+            }
+        };
+
+        if let Some(revents) = listener_event {
+            if revents.contains(PollFlags::POLLIN) {
+                self.accept_new_connections()?;
+            }
+        }
+
+        self.process_existing_connections(&connection_events);
+        Ok(())
+    }
+
+    /// Whether only local clients are accepted, which is the case in protected mode when the server
+    /// listens on a public interface while the default user has no password
+    fn is_protected(&self) -> bool {
+        let config = self.config.borrow();
+        config.protected_mode
+            && !is_loopback_host(&config.host)
+            && self.acl.borrow().default_user_nopass()
+    }
+
+    fn accept_new_connections(&self) -> Result<()> {
         loop {
             match self.listener.accept() {
-                Ok((stream, addr)) => {
+                Ok((mut stream, addr)) => {
                     log::info!("Accepted connection from: {}", addr);
-                    self.connections
-                        .push(Connection::new(stream, Rc::clone(&self.config))?);
+                    if self.is_protected() && !addr.ip().to_canonical().is_loopback() {
+                        log::warn!("Refusing connection from {} in protected mode", addr);
+                        if let Err(e) = stream.write_all(PROTECTED_MODE_DENIED) {
+                            log::error!("Write error: {}", e);
+                        }
+                        continue;
+                    }
+                    let conn = Connection::new(
+                        stream,
+                        Rc::clone(&self.config),
+                        Rc::clone(&self.blocked_clients),
+                        Rc::clone(&self.pubsub),
+                        Rc::clone(&self.scripts),
+                        Rc::clone(&self.acl),
+                    )?;
+                    self.connections.borrow_mut().push(conn);
                 }
                 Err(e) if e.kind() == ErrorKind::WouldBlock => break,
                 Err(e) => {
@@ -158,26 +401,49 @@ impl Server {
         Ok(())
     }
 
-    /// Process existing connections, removing any that have been closed
-    ///
-    /// We do this by:
+    /// Process the events polled for existing connections, closing any that fail
+    fn process_existing_connections(&self, events: &[(u64, Option<PollFlags>)]) {
+        for (id, event) in events {
+            self.with_connection(*id, |conn| conn.process_event(event.as_ref()));
+        }
+    }
+
+    /// Process a connection, which is taken out of the connections meanwhile, so that the others
+    /// can be processed if it runs a busy script
     ///
-    /// 1. Take the connections that were polled
-    /// 2. Zip them with the events that were polled
-    /// 3. Filter out any connections that have been closed
-    /// 4. Put the remaining connections back into the connections list
-    fn process_existing_connections(&mut self, events: &[Option<PollFlags>], polled_count: usize) {
-        let mut polled_conns: Vec<Connection> = self.connections.drain(..polled_count).collect();
-
-        polled_conns = polled_conns
-            .into_iter()
-            .zip(events.iter())
-            .filter_map(|(conn, event)| conn.process_event(event.as_ref()).ok())
-            .collect();
-
-        self.connections = polled_conns
-            .into_iter()
-            .chain(self.connections.drain(..))
-            .collect();
+    /// The connection is put back unless processing it failed, which closes it.
+    fn with_connection<F>(&self, id: u64, process: F) -> bool
+    where
+        F: FnOnce(Connection) -> Result<Connection>,
+    {
+        let conn = {
+            let mut connections = self.connections.borrow_mut();
+            let Some(index) = connections.iter().position(|conn| conn.id() == id) else {
+                return false;
+            };
+            connections.swap_remove(index)
+        };
+        match process(conn) {
+            Ok(conn) => {
+                self.connections.borrow_mut().push(conn);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_loopback_host() {
+        assert!(is_loopback_host("127.0.0.1"));
+        assert!(is_loopback_host("::1"));
+        assert!(is_loopback_host("localhost"));
+        assert!(!is_loopback_host("0.0.0.0"));
+        assert!(!is_loopback_host("::"));
+        assert!(!is_loopback_host("192.168.1.10"));
     }
 }
@@ -0,0 +1,96 @@
+//! Keys watched by clients with WATCH, for optimistic locking in transactions
+//!
+//! The database touches a key whenever it is written to, expires or is evicted, which marks every
+//! client watching it as dirty. EXEC then refuses to run the transaction of a dirty client.
+
+use once_cell::sync::Lazy;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
+#[derive(Debug, Default)]
+struct Watched {
+    /// The clients watching each key, by database index and key
+    keys: HashMap<(usize, Vec<u8>), Vec<u64>>,
+    /// The clients with a watched key that was touched since they watched it
+    dirty: HashSet<u64>,
+}
+
+static WATCHED: Lazy<Mutex<Watched>> = Lazy::new(Mutex::default);
+
+/// Start watching a key for a client
+pub(crate) fn watch(client: u64, db: usize, key: &[u8]) {
+    let mut watched = WATCHED.lock().unwrap();
+    let clients = watched.keys.entry((db, key.to_vec())).or_default();
+    if !clients.contains(&client) {
+        clients.push(client);
+    }
+}
+
+/// Stop watching keys for a client, which is no longer dirty afterwards
+pub(crate) fn unwatch(client: u64, db: usize, keys: &[Vec<u8>]) {
+    let mut watched = WATCHED.lock().unwrap();
+    for key in keys {
+        let entry = (db, key.clone());
+        if let Some(clients) = watched.keys.get_mut(&entry) {
+            clients.retain(|&id| id != client);
+            if clients.is_empty() {
+                watched.keys.remove(&entry);
+            }
+        }
+    }
+    watched.dirty.remove(&client);
+}
+
+/// Signal that a key was modified, marking the clients watching it as dirty
+pub(crate) fn touch(db: usize, key: &[u8]) {
+    let mut watched = WATCHED.lock().unwrap();
+    let Watched { keys, dirty } = &mut *watched;
+    if let Some(clients) = keys.get(&(db, key.to_vec())) {
+        dirty.extend(clients);
+    }
+}
+
+/// Signal that a whole database was replaced, such as by FLUSHDB or SWAPDB, marking the clients
+/// watching a key in it as dirty if the key existed before or exists now
+pub(crate) fn touch_database(db: usize, existed: impl Fn(&[u8]) -> bool) {
+    let mut watched = WATCHED.lock().unwrap();
+    let Watched { keys, dirty } = &mut *watched;
+    for ((index, key), clients) in keys.iter() {
+        if *index == db && existed(key) {
+            dirty.extend(clients);
+        }
+    }
+}
+
+/// Check whether a key watched by the client was touched since it was watched
+pub(crate) fn is_dirty(client: u64) -> bool {
+    WATCHED.lock().unwrap().dirty.contains(&client)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_touch_watched_keys() {
+        // Use a database index that no other test touches, as the registry is global
+        watch(1001, 15, b"a");
+        watch(1002, 15, b"b");
+        assert!(!is_dirty(1001));
+
+        touch(15, b"a");
+        touch(14, b"b");
+        assert!(is_dirty(1001));
+        assert!(!is_dirty(1002));
+
+        unwatch(1001, 15, &[b"a".to_vec()]);
+        assert!(!is_dirty(1001));
+        touch(15, b"a");
+        assert!(!is_dirty(1001));
+
+        unwatch(1002, 15, &[b"b".to_vec()]);
+        assert!(WATCHED.lock().unwrap().keys.keys().all(|&(db, _)| db != 15));
+    }
+}
@@ -0,0 +1,276 @@
+mod common;
+
+use common::TestServer;
+use redis::{Commands, Value};
+
+/// The code and message of an error
+fn error(result: redis::RedisResult<Value>) -> String {
+    let e = result.unwrap_err();
+    format!(
+        "{} {}",
+        e.code().unwrap_or_default(),
+        e.detail().unwrap_or_default()
+    )
+}
+
+fn acl(conn: &mut redis::Connection, args: &[&str]) -> redis::RedisResult<Value> {
+    redis::cmd("ACL").arg(args).query(conn)
+}
+
+fn auth(conn: &mut redis::Connection, user: &str, password: &str) -> redis::RedisResult<Value> {
+    redis::cmd("AUTH").arg(user).arg(password).query(conn)
+}
+
+#[test]
+fn test_acl_users() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    assert_eq!(
+        acl(&mut conn, &["WHOAMI"]).unwrap(),
+        Value::BulkString(b"default".to_vec())
+    );
+    assert_eq!(
+        acl(
+            &mut conn,
+            &["SETUSER", "alice", "on", ">pass", "~app:*", "+@read", "+set"]
+        )
+        .unwrap(),
+        Value::Okay
+    );
+    let users: Vec<String> = redis::cmd("ACL").arg("USERS").query(&mut conn).unwrap();
+    assert_eq!(users, ["alice", "default"]);
+    let list: Vec<String> = redis::cmd("ACL").arg("LIST").query(&mut conn).unwrap();
+    assert_eq!(
+        list,
+        [
+            "user alice on #d74ff0ee8da3b9806b18c877dbf29bbde50b5bd8e4dad7a3a725000feb82e8f1 \
+             ~app:* resetchannels -@all +@read +set",
+            "user default on nopass ~* &* +@all",
+        ]
+    );
+
+    let bulk = |s: &str| Value::BulkString(s.as_bytes().to_vec());
+    assert_eq!(
+        acl(&mut conn, &["GETUSER", "alice"]).unwrap(),
+        Value::Array(vec![
+            bulk("flags"),
+            Value::Array(vec![bulk("on")]),
+            bulk("passwords"),
+            Value::Array(vec![bulk(
+                "d74ff0ee8da3b9806b18c877dbf29bbde50b5bd8e4dad7a3a725000feb82e8f1"
+            )]),
+            bulk("commands"),
+            bulk("-@all +@read +set"),
+            bulk("keys"),
+            bulk("~app:*"),
+            bulk("channels"),
+            bulk(""),
+            bulk("selectors"),
+            Value::Array(vec![]),
+        ])
+    );
+    assert_eq!(acl(&mut conn, &["GETUSER", "bob"]).unwrap(), Value::Nil);
+
+    assert_eq!(
+        error(acl(&mut conn, &["SETUSER", "alice", "+nope"])),
+        "ERR Error in ACL SETUSER modifier '+nope': Unknown command or category name in ACL"
+    );
+    assert_eq!(
+        error(acl(&mut conn, &["DELUSER", "default"])),
+        "ERR The 'default' user cannot be removed"
+    );
+    assert_eq!(
+        error(acl(&mut conn, &["CAT", "nope"])),
+        "ERR Unknown category 'nope'"
+    );
+    assert_eq!(
+        error(acl(&mut conn, &["NOPE"])),
+        "ERR unknown subcommand 'NOPE'. Try ACL HELP."
+    );
+    let strings: Vec<String> = redis::cmd("ACL")
+        .arg("CAT")
+        .arg("string")
+        .query(&mut conn)
+        .unwrap();
+    assert!(strings.contains(&"get".to_string()));
+    assert!(!strings.contains(&"lpush".to_string()));
+
+    // Users that are deleted are disconnected
+    let mut alice = client.get_connection().unwrap();
+    auth(&mut alice, "alice", "pass").unwrap();
+    assert_eq!(
+        acl(&mut conn, &["DELUSER", "alice", "bob"]).unwrap(),
+        Value::Int(1)
+    );
+    assert!(redis::cmd("GET")
+        .arg("app:1")
+        .query::<Value>(&mut alice)
+        .is_err());
+}
+
+#[test]
+fn test_acl_permissions() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    acl(
+        &mut conn,
+        &[
+            "SETUSER",
+            "alice",
+            "on",
+            ">pass",
+            "~app:*",
+            "%R~shared:*",
+            "&news.*",
+            "+@read",
+            "+set",
+            "+publish",
+            "+config|get",
+            "+multi",
+            "+exec",
+        ],
+    )
+    .unwrap();
+    let mut alice = client.get_connection().unwrap();
+    assert_eq!(
+        error(auth(&mut alice, "alice", "wrong")),
+        "WRONGPASS invalid username-password pair or user is disabled."
+    );
+    auth(&mut alice, "alice", "pass").unwrap();
+
+    let _: () = alice.set("app:1", "x").unwrap();
+    let _: Option<String> = alice.get("shared:1").unwrap();
+    assert_eq!(
+        error(redis::cmd("SET").arg("shared:1").arg("x").query(&mut alice)),
+        "NOPERM No permissions to access a key"
+    );
+    assert_eq!(
+        error(redis::cmd("GET").arg("other").query(&mut alice)),
+        "NOPERM No permissions to access a key"
+    );
+    assert_eq!(
+        error(
+            redis::cmd("LPUSH")
+                .arg("app:list")
+                .arg("x")
+                .query(&mut alice)
+        ),
+        "NOPERM User alice has no permissions to run the 'lpush' command"
+    );
+    assert_eq!(
+        error(
+            redis::cmd("CONFIG")
+                .arg("SET")
+                .arg("dir")
+                .arg("/")
+                .query(&mut alice)
+        ),
+        "NOPERM User alice has no permissions to run the 'config|set' command"
+    );
+    let _: Value = redis::cmd("CONFIG")
+        .arg("GET")
+        .arg("dir")
+        .query(&mut alice)
+        .unwrap();
+    let _: i64 = alice.publish("news.sports", "goal").unwrap();
+    assert_eq!(
+        error(
+            redis::cmd("PUBLISH")
+                .arg("weather")
+                .arg("rain")
+                .query(&mut alice)
+        ),
+        "NOPERM No permissions to access a channel"
+    );
+
+    // A denied command fails the transaction it's queued in
+    let _: () = redis::cmd("MULTI").query(&mut alice).unwrap();
+    assert!(redis::cmd("LPUSH")
+        .arg("app:list")
+        .arg("x")
+        .query::<Value>(&mut alice)
+        .is_err());
+    assert!(error(redis::cmd("EXEC").query(&mut alice)).starts_with("EXECABORT"));
+
+    // The denials are logged, newest first, with similar ones grouped together
+    assert!(redis::cmd("GET")
+        .arg("other")
+        .query::<Value>(&mut alice)
+        .is_err());
+    let log: Vec<Vec<Value>> = redis::cmd("ACL").arg("LOG").query(&mut conn).unwrap();
+    let field = |entry: &[Value], name: &str| {
+        let index = entry
+            .iter()
+            .position(|v| *v == Value::BulkString(name.as_bytes().to_vec()))
+            .unwrap();
+        entry[index + 1].clone()
+    };
+    let bulk = |s: &str| Value::BulkString(s.as_bytes().to_vec());
+    assert_eq!(field(&log[0], "reason"), bulk("key"));
+    assert_eq!(field(&log[0], "object"), bulk("other"));
+    assert_eq!(field(&log[0], "count"), Value::Int(2));
+    assert_eq!(field(&log[0], "username"), bulk("alice"));
+    assert_eq!(field(&log[1], "reason"), bulk("command"));
+    assert_eq!(field(&log[1], "context"), bulk("multi"));
+    assert_eq!(field(&log[1], "object"), bulk("lpush"));
+    let auth_entry = log.last().unwrap();
+    assert_eq!(field(auth_entry, "reason"), bulk("auth"));
+    assert_eq!(field(auth_entry, "object"), bulk("AUTH"));
+
+    let log: Vec<Value> = redis::cmd("ACL")
+        .arg("LOG")
+        .arg(1)
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(log.len(), 1);
+    assert_eq!(acl(&mut conn, &["LOG", "RESET"]).unwrap(), Value::Okay);
+    let log: Vec<Value> = redis::cmd("ACL").arg("LOG").query(&mut conn).unwrap();
+    assert!(log.is_empty());
+}
+
+#[test]
+fn test_aclfile() {
+    let dir = std::env::temp_dir().join(format!("rustis-acl-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("users.acl");
+    std::fs::write(&path, "user alice on >pass ~* +@all\n").unwrap();
+    let path = path.to_str().unwrap();
+
+    let server = TestServer::start(Some(vec!["--aclfile", path]));
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    auth(&mut conn, "alice", "pass").unwrap();
+    acl(&mut conn, &["SETUSER", "bob", "on", "nopass", "+get"]).unwrap();
+    assert_eq!(acl(&mut conn, &["SAVE"]).unwrap(), Value::Okay);
+    let saved = std::fs::read_to_string(path).unwrap();
+    assert!(saved.contains("user bob on nopass resetchannels -@all +get\n"));
+
+    acl(&mut conn, &["DELUSER", "bob"]).unwrap();
+    assert_eq!(acl(&mut conn, &["LOAD"]).unwrap(), Value::Okay);
+    let users: Vec<String> = redis::cmd("ACL").arg("USERS").query(&mut conn).unwrap();
+    assert_eq!(users, ["alice", "bob", "default"]);
+
+    std::fs::write(path, "user carol bogus\n").unwrap();
+    assert_eq!(
+        error(acl(&mut conn, &["LOAD"])),
+        format!(
+            "ERR {}:1: Error in applying operation 'bogus': Syntax error",
+            path
+        )
+    );
+}
+
+#[test]
+fn test_acl_without_aclfile() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    assert!(error(acl(&mut conn, &["SAVE"]))
+        .starts_with("ERR This Redis instance is not configured to use an ACL file."));
+}
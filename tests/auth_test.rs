@@ -0,0 +1,168 @@
+mod common;
+
+use common::TestServer;
+use redis::{Commands, Value};
+
+/// The code and message of an error
+fn error(result: redis::RedisResult<Value>) -> String {
+    let e = result.unwrap_err();
+    format!(
+        "{} {}",
+        e.code().unwrap_or_default(),
+        e.detail().unwrap_or_default()
+    )
+}
+
+fn auth(conn: &mut redis::Connection, args: &[&str]) -> redis::RedisResult<Value> {
+    redis::cmd("AUTH").arg(args).query(conn)
+}
+
+#[test]
+fn test_commands_require_authentication() {
+    let server = TestServer::start(Some(vec!["--requirepass", "s3cret"]));
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    assert_eq!(
+        error(redis::cmd("GET").arg("foo").query(&mut conn)),
+        "NOAUTH Authentication required."
+    );
+    assert_eq!(
+        error(redis::cmd("PING").query(&mut conn)),
+        "NOAUTH Authentication required."
+    );
+
+    assert_eq!(
+        error(auth(&mut conn, &["wrong"])),
+        "WRONGPASS invalid username-password pair or user is disabled."
+    );
+    assert_eq!(
+        error(auth(&mut conn, &["someone", "s3cret"])),
+        "WRONGPASS invalid username-password pair or user is disabled."
+    );
+    assert_eq!(
+        error(redis::cmd("GET").arg("foo").query(&mut conn)),
+        "NOAUTH Authentication required."
+    );
+
+    assert_eq!(auth(&mut conn, &["s3cret"]).unwrap(), Value::Okay);
+    let _: () = conn.set("foo", "bar").unwrap();
+    let value: String = conn.get("foo").unwrap();
+    assert_eq!(value, "bar");
+
+    // The default user authenticates with the same password
+    let mut conn = client.get_connection().unwrap();
+    assert_eq!(
+        auth(&mut conn, &["default", "s3cret"]).unwrap(),
+        Value::Okay
+    );
+    let value: String = conn.get("foo").unwrap();
+    assert_eq!(value, "bar");
+
+    // Clients can authenticate when connecting
+    let client = redis::Client::open(format!(
+        "redis://:s3cret@{}",
+        server.connection_string().trim_start_matches("redis://")
+    ))
+    .unwrap();
+    let mut conn = client.get_connection().unwrap();
+    let value: String = conn.get("foo").unwrap();
+    assert_eq!(value, "bar");
+}
+
+#[test]
+fn test_auth_without_requirepass() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    assert_eq!(
+        error(auth(&mut conn, &["anything"])),
+        "ERR AUTH <password> called without any password configured for the default user. Are \
+         you sure your configuration is correct?"
+    );
+    assert_eq!(
+        auth(&mut conn, &["default", "anything"]).unwrap(),
+        Value::Okay
+    );
+    assert_eq!(error(auth(&mut conn, &["a", "b", "c"])), "ERR syntax error");
+
+    // Setting a password applies to the clients that connect afterwards
+    let _: () = redis::cmd("CONFIG")
+        .arg("SET")
+        .arg("requirepass")
+        .arg("later")
+        .query(&mut conn)
+        .unwrap();
+    let pong: String = redis::cmd("PING").query(&mut conn).unwrap();
+    assert_eq!(pong, "PONG");
+
+    let mut other = client.get_connection().unwrap();
+    assert_eq!(
+        error(redis::cmd("PING").query(&mut other)),
+        "NOAUTH Authentication required."
+    );
+    assert_eq!(auth(&mut other, &["later"]).unwrap(), Value::Okay);
+}
+
+#[test]
+fn test_commands_allowed_before_authentication() {
+    let server = TestServer::start(Some(vec!["--requirepass", "s3cret"]));
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    assert_eq!(
+        error(redis::cmd("HELLO").arg(2).query(&mut conn)),
+        "NOAUTH HELLO must be called with the client already authenticated, otherwise the HELLO \
+         <proto> AUTH <user> <pass> option can be used to authenticate the client and select the \
+         RESP protocol version at the same time"
+    );
+    assert_eq!(
+        error(redis::cmd("HELLO").arg(3).query(&mut conn)),
+        "NOPROTO unsupported protocol version"
+    );
+    assert_eq!(
+        error(
+            redis::cmd("HELLO")
+                .arg(2)
+                .arg("AUTH")
+                .arg("default")
+                .arg("wrong")
+                .query(&mut conn)
+        ),
+        "WRONGPASS invalid username-password pair or user is disabled."
+    );
+    let hello: Vec<Value> = redis::cmd("HELLO")
+        .arg(2)
+        .arg("AUTH")
+        .arg("default")
+        .arg("s3cret")
+        .arg("SETNAME")
+        .arg("me")
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(hello[0], Value::BulkString(b"server".to_vec()));
+    assert_eq!(hello[5], Value::Int(2));
+    let _: () = conn.set("foo", "bar").unwrap();
+
+    // RESET logs the client out of the default user when it has a password
+    let reset: String = redis::cmd("RESET").query(&mut conn).unwrap();
+    assert_eq!(reset, "RESET");
+    assert_eq!(
+        error(redis::cmd("GET").arg("foo").query(&mut conn)),
+        "NOAUTH Authentication required."
+    );
+    assert_eq!(
+        error(redis::cmd("HELLO").arg(2).arg("NOPE").query(&mut conn)),
+        "ERR syntax error in HELLO option 'NOPE'"
+    );
+    assert_eq!(auth(&mut conn, &["s3cret"]).unwrap(), Value::Okay);
+    let hello: Vec<Value> = redis::cmd("HELLO").query(&mut conn).unwrap();
+    assert_eq!(hello.len(), 14);
+
+    let mut other = client.get_connection().unwrap();
+    assert_eq!(
+        redis::cmd("QUIT").query::<Value>(&mut other).unwrap(),
+        Value::Okay
+    );
+}
@@ -0,0 +1,148 @@
+mod common;
+
+use common::TestServer;
+use redis::Commands;
+
+#[test]
+fn test_setbit_and_getbit() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let previous: u8 = conn.setbit("bitmap", 7, true).unwrap();
+    assert_eq!(previous, 0);
+    let previous: u8 = conn.setbit("bitmap", 7, false).unwrap();
+    assert_eq!(previous, 1);
+    let _: u8 = conn.setbit("bitmap", 9, true).unwrap();
+
+    let bit: u8 = conn.getbit("bitmap", 9).unwrap();
+    assert_eq!(bit, 1);
+    let bit: u8 = conn.getbit("bitmap", 100).unwrap();
+    assert_eq!(bit, 0);
+
+    let value: Vec<u8> = conn.get("bitmap").unwrap();
+    assert_eq!(value, vec![0x00, 0x40]);
+}
+
+#[test]
+fn test_bitcount_and_bitpos() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let _: () = conn.set("mykey", "foobar").unwrap();
+
+    let count: i64 = conn.bitcount("mykey").unwrap();
+    assert_eq!(count, 26);
+    let count: i64 = conn.bitcount_range("mykey", 1, 1).unwrap();
+    assert_eq!(count, 6);
+    let count: i64 = redis::cmd("BITCOUNT")
+        .arg("mykey")
+        .arg(5)
+        .arg(30)
+        .arg("BIT")
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(count, 17);
+
+    let _: () = conn.set("ones", b"\xff\xf0\x00".as_slice()).unwrap();
+    let position: i64 = redis::cmd("BITPOS")
+        .arg("ones")
+        .arg(0)
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(position, 12);
+    let position: i64 = redis::cmd("BITPOS")
+        .arg("ones")
+        .arg(1)
+        .arg(2)
+        .arg(-1)
+        .arg("BYTE")
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(position, -1);
+
+    // When looking for a clear bit with no end, the string is padded with clear bits
+    let _: () = conn.set("all_ones", b"\xff".as_slice()).unwrap();
+    let position: i64 = redis::cmd("BITPOS")
+        .arg("all_ones")
+        .arg(0)
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(position, 8);
+}
+
+#[test]
+fn test_bitop() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let _: () = conn.set("a", b"\xf0\x0f".as_slice()).unwrap();
+    let _: () = conn.set("b", b"\x3c".as_slice()).unwrap();
+
+    let length: i64 = conn.bit_and("and", &["a", "b"]).unwrap();
+    assert_eq!(length, 2);
+    let value: Vec<u8> = conn.get("and").unwrap();
+    assert_eq!(value, vec![0x30, 0x00]);
+
+    let _: i64 = conn.bit_or("or", &["a", "b"]).unwrap();
+    let value: Vec<u8> = conn.get("or").unwrap();
+    assert_eq!(value, vec![0xfc, 0x0f]);
+
+    let _: i64 = conn.bit_xor("xor", &["a", "b"]).unwrap();
+    let value: Vec<u8> = conn.get("xor").unwrap();
+    assert_eq!(value, vec![0xcc, 0x0f]);
+
+    let _: i64 = conn.bit_not("not", "a").unwrap();
+    let value: Vec<u8> = conn.get("not").unwrap();
+    assert_eq!(value, vec![0x0f, 0xf0]);
+
+    let _: i64 = redis::cmd("BITOP")
+        .arg("DIFF")
+        .arg("diff")
+        .arg("a")
+        .arg("b")
+        .query(&mut conn)
+        .unwrap();
+    let value: Vec<u8> = conn.get("diff").unwrap();
+    assert_eq!(value, vec![0xc0, 0x0f]);
+}
+
+#[test]
+fn test_bitfield() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let result: Vec<Option<i64>> = redis::cmd("BITFIELD")
+        .arg("bitfield")
+        .arg(&["SET", "u8", "0", "255"])
+        .arg(&["GET", "u8", "0"])
+        .arg(&["GET", "i8", "0"])
+        .arg(&["INCRBY", "u8", "0", "10"])
+        .arg(&["OVERFLOW", "SAT", "INCRBY", "u8", "#1", "300"])
+        .arg(&["OVERFLOW", "FAIL", "INCRBY", "u8", "#1", "1"])
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(
+        result,
+        vec![Some(0), Some(255), Some(-1), Some(9), Some(255), None]
+    );
+
+    let result: Vec<i64> = redis::cmd("BITFIELD_RO")
+        .arg("bitfield")
+        .arg(&["GET", "u4", "4"])
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(result, vec![9]);
+
+    let result: redis::RedisResult<Vec<i64>> = redis::cmd("BITFIELD_RO")
+        .arg("bitfield")
+        .arg(&["SET", "u8", "0", "1"])
+        .query(&mut conn);
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("BITFIELD_RO only supports the GET subcommand"));
+}
@@ -0,0 +1,174 @@
+mod common;
+
+use common::TestServer;
+use redis::Commands;
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+fn client_id(conn: &mut redis::Connection) -> i64 {
+    redis::cmd("CLIENT").arg("ID").query(conn).unwrap()
+}
+
+#[test]
+fn test_blpop_returns_immediately_when_list_has_elements() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let _: i64 = conn.rpush("second", &["a", "b"]).unwrap();
+
+    let result: (String, String) = conn.blpop(&["first", "second"], 1.0).unwrap();
+    assert_eq!(result, ("second".to_string(), "a".to_string()));
+    let result: (String, String) = conn.brpop(&["first", "second"], 1.0).unwrap();
+    assert_eq!(result, ("second".to_string(), "b".to_string()));
+}
+
+#[test]
+fn test_blpop_times_out() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let start = Instant::now();
+    let result: Option<(String, String)> = conn.blpop("missing", 0.2).unwrap();
+    assert_eq!(result, None);
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    assert!(start.elapsed() < Duration::from_millis(1000));
+
+    // The connection is usable again after timing out
+    let pong: String = redis::cmd("PING").query(&mut conn).unwrap();
+    assert_eq!(pong, "PONG");
+}
+
+#[test]
+fn test_blpop_is_served_by_push_in_fifo_order() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+
+    let mut waiters = Vec::new();
+    for _ in 0..2 {
+        let mut conn = client.get_connection().unwrap();
+        waiters.push(thread::spawn(move || {
+            let result: (String, String) = conn.blpop("queue", 5.0).unwrap();
+            result.1
+        }));
+        // Make sure the clients block in order
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    let mut conn = client.get_connection().unwrap();
+    let length: i64 = conn.rpush("queue", &["first", "second", "third"]).unwrap();
+    assert_eq!(length, 3);
+
+    let results: Vec<String> = waiters.into_iter().map(|w| w.join().unwrap()).collect();
+    assert_eq!(results, vec!["first", "second"]);
+
+    let remaining: Vec<String> = conn.lrange("queue", 0, -1).unwrap();
+    assert_eq!(remaining, vec!["third"]);
+}
+
+#[test]
+fn test_blmove_and_blmpop() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+
+    let mut blocked = client.get_connection().unwrap();
+    let waiter = thread::spawn(move || {
+        let element: String = blocked
+            .blmove(
+                "source",
+                "dest",
+                redis::Direction::Left,
+                redis::Direction::Left,
+                5.0,
+            )
+            .unwrap();
+        element
+    });
+    thread::sleep(Duration::from_millis(100));
+
+    let mut conn = client.get_connection().unwrap();
+    let _: i64 = conn.rpush("source", "moved").unwrap();
+    assert_eq!(waiter.join().unwrap(), "moved");
+    let dest: Vec<String> = conn.lrange("dest", 0, -1).unwrap();
+    assert_eq!(dest, vec!["moved"]);
+
+    let _: i64 = conn.rpush("multi", &["a", "b", "c"]).unwrap();
+    let result: (String, Vec<String>) = redis::cmd("LMPOP")
+        .arg(&["2", "missing", "multi", "RIGHT", "COUNT", "2"])
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(result, ("multi".to_string(), vec!["c".into(), "b".into()]));
+    let result: Option<(String, Vec<String>)> = redis::cmd("BLMPOP")
+        .arg(&["0.1", "1", "missing", "LEFT"])
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(result, None);
+}
+
+#[test]
+fn test_client_unblock() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+
+    let mut blocked = client.get_connection().unwrap();
+    let id = client_id(&mut blocked);
+    let waiter = thread::spawn(move || {
+        let result: redis::RedisResult<Option<(String, String)>> = blocked.blpop("queue", 0.0);
+        let error = result.unwrap_err();
+        // The connection keeps working after being unblocked
+        let pong: String = redis::cmd("PING").query(&mut blocked).unwrap();
+        (error.code().map(str::to_string), pong)
+    });
+
+    thread::sleep(Duration::from_millis(100));
+
+    let mut conn = client.get_connection().unwrap();
+
+    let unblocked: i64 = redis::cmd("CLIENT")
+        .arg(&["UNBLOCK", &id.to_string(), "ERROR"])
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(unblocked, 1);
+
+    let (code, pong) = waiter.join().unwrap();
+    assert_eq!(code.as_deref(), Some("UNBLOCKED"));
+    assert_eq!(pong, "PONG");
+
+    // Clients that aren't blocked can't be unblocked
+    let unblocked: i64 = redis::cmd("CLIENT")
+        .arg(&["UNBLOCK", &id.to_string()])
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(unblocked, 0);
+}
+
+#[test]
+fn test_pipelined_commands_wait_for_blocked_command() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+
+    let mut blocked = client.get_connection().unwrap();
+    let waiter = thread::spawn(move || {
+        let results: (Option<(String, String)>, i64) = redis::pipe()
+            .cmd("BLPOP")
+            .arg("queue")
+            .arg("5")
+            .cmd("LLEN")
+            .arg("queue")
+            .query(&mut blocked)
+            .unwrap();
+        results
+    });
+    thread::sleep(Duration::from_millis(100));
+
+    let mut conn = client.get_connection().unwrap();
+    let _: i64 = conn.rpush("queue", &["a", "b"]).unwrap();
+
+    let (popped, length) = waiter.join().unwrap();
+    assert_eq!(popped, Some(("queue".to_string(), "a".to_string())));
+    // The LLEN only runs after the BLPOP has been served
+    assert_eq!(length, 1);
+}
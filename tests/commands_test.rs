@@ -39,3 +39,50 @@ fn test_echo() {
 
     assert_eq!(result, "Hello, world!");
 }
+
+#[test]
+fn test_set_nx_on_expired_key() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let _: () = redis::cmd("SET")
+        .arg("my_key")
+        .arg("old")
+        .arg("PX")
+        .arg(50)
+        .query(&mut conn)
+        .unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(100));
+
+    let result: Option<String> = redis::cmd("SET")
+        .arg("my_key")
+        .arg("new")
+        .arg("NX")
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(result, Some("OK".to_string()));
+
+    let result: String = conn.get("my_key").unwrap();
+    assert_eq!(result, "new");
+}
+
+#[test]
+fn test_keys_skips_expired_keys() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let _: () = conn.set("live", "value").unwrap();
+    let _: () = redis::cmd("SET")
+        .arg("expiring")
+        .arg("value")
+        .arg("PX")
+        .arg(50)
+        .query(&mut conn)
+        .unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(100));
+
+    let keys: Vec<String> = conn.keys("*").unwrap();
+    assert_eq!(keys, vec!["live".to_string()]);
+}
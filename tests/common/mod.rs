@@ -1,6 +1,9 @@
+// Each test crate only uses some of the helpers
+#![allow(dead_code)]
+
 use std::{
-    io::{BufRead, BufReader},
-    net::TcpListener,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
     path::PathBuf,
     process::{Child, Command, Stdio},
     sync::{Arc, Mutex},
@@ -100,3 +103,55 @@ impl Drop for TestServer {
         let _ = self.child.kill();
     }
 }
+
+/// Run a command given as a string of whitespace separated arguments
+pub fn query<T: redis::FromRedisValue>(conn: &mut redis::Connection, command: &str) -> T {
+    let args: Vec<&str> = command.split_whitespace().collect();
+    redis::cmd(args[0]).arg(&args[1..]).query(conn).unwrap()
+}
+
+/// Run a command given as a string of whitespace separated arguments, which is expected to fail
+pub fn error(conn: &mut redis::Connection, command: &str) -> redis::RedisError {
+    let args: Vec<&str> = command.split_whitespace().collect();
+    let result: redis::RedisResult<redis::Value> = redis::cmd(args[0]).arg(&args[1..]).query(conn);
+    result.unwrap_err()
+}
+
+/// The message of the error a command fails with, without the code
+pub fn error_message(conn: &mut redis::Connection, command: &str) -> String {
+    error(conn, command)
+        .detail()
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Open a raw connection to the server, for tests that need to see the exact replies
+pub fn connect(server: &TestServer) -> TcpStream {
+    let stream =
+        TcpStream::connect(server.connection_string().trim_start_matches("redis://")).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream
+}
+
+/// Send a command over a raw connection, and read back exactly the expected reply
+pub fn send(stream: &mut TcpStream, command: &str, expected: &[u8]) {
+    let args: Vec<&str> = command.split_whitespace().collect();
+    let mut buf = format!("*{}\r\n", args.len());
+    for arg in args {
+        buf.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+    }
+    stream.write_all(buf.as_bytes()).unwrap();
+    expect(stream, expected);
+}
+
+/// Read back exactly the expected data from a raw connection, such as a pushed message
+pub fn expect(stream: &mut TcpStream, expected: &[u8]) {
+    let mut reply = vec![0; expected.len()];
+    stream.read_exact(&mut reply).unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&reply),
+        String::from_utf8_lossy(expected)
+    );
+}
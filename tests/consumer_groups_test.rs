@@ -0,0 +1,399 @@
+mod common;
+
+use common::{error, query, TestServer};
+use redis::Value;
+use std::{collections::HashMap, thread, time::Duration};
+
+type Entry = (String, Vec<String>);
+type Streams = Vec<(String, Vec<Entry>)>;
+type Pending = Vec<(String, String, i64, i64)>;
+/// Streams read from the pending entries of a consumer, where deleted entries have no fields
+type History = Vec<(String, Vec<(String, Option<Vec<String>>)>)>;
+
+fn ids(entries: &[Entry]) -> Vec<&str> {
+    entries.iter().map(|(id, _)| id.as_str()).collect()
+}
+
+/// Add entries with the IDs 1-0 to count-0
+fn add_entries(conn: &mut redis::Connection, key: &str, count: u64) {
+    for ms in 1..=count {
+        let _: String = query(conn, &format!("XADD {} {}-0 n {}", key, ms, ms));
+    }
+}
+
+/// Turn a flat array of field-value pairs, as XINFO replies with, into a map
+fn info_map(value: Value) -> HashMap<String, Value> {
+    let Value::Array(values) = value else {
+        panic!("Expected an array, got {:?}", value);
+    };
+    values
+        .chunks_exact(2)
+        .map(|pair| {
+            let name: String = redis::from_redis_value(&pair[0]).unwrap();
+            (name, pair[1].clone())
+        })
+        .collect()
+}
+
+fn info_int(info: &HashMap<String, Value>, name: &str) -> Option<i64> {
+    redis::from_redis_value(&info[name]).unwrap()
+}
+
+#[test]
+fn test_xgroup() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let err = error(&mut conn, "XGROUP CREATE events workers $");
+    assert!(err.to_string().contains("requires the key to exist"));
+    let _: () = query(&mut conn, "XGROUP CREATE events workers $ MKSTREAM");
+    let kind: String = query(&mut conn, "TYPE events");
+    assert_eq!(kind, "stream");
+    let err = error(&mut conn, "XGROUP CREATE events workers 0");
+    assert_eq!(err.code(), Some("BUSYGROUP"));
+
+    let created: i64 = query(&mut conn, "XGROUP CREATECONSUMER events workers alice");
+    assert_eq!(created, 1);
+    let created: i64 = query(&mut conn, "XGROUP CREATECONSUMER events workers alice");
+    assert_eq!(created, 0);
+    let err = error(&mut conn, "XGROUP CREATECONSUMER events missing alice");
+    assert_eq!(err.code(), Some("NOGROUP"));
+
+    add_entries(&mut conn, "events", 3);
+    let _: Streams = query(&mut conn, "XREADGROUP GROUP workers alice STREAMS events >");
+    let deleted: i64 = query(&mut conn, "XGROUP DELCONSUMER events workers alice");
+    assert_eq!(deleted, 3);
+
+    let _: () = query(&mut conn, "XGROUP SETID events workers 1-0");
+    let read: Streams = query(&mut conn, "XREADGROUP GROUP workers bob STREAMS events >");
+    assert_eq!(ids(&read[0].1), vec!["2-0", "3-0"]);
+
+    let destroyed: i64 = query(&mut conn, "XGROUP DESTROY events workers");
+    assert_eq!(destroyed, 1);
+    let destroyed: i64 = query(&mut conn, "XGROUP DESTROY events workers");
+    assert_eq!(destroyed, 0);
+    assert!(error(&mut conn, "XGROUP FOO events")
+        .to_string()
+        .contains("unknown subcommand"));
+}
+
+#[test]
+fn test_xreadgroup_and_xack() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    add_entries(&mut conn, "events", 5);
+    let _: () = query(&mut conn, "XGROUP CREATE events workers 0");
+
+    // Each new entry is delivered to only one consumer
+    let read: Streams = query(
+        &mut conn,
+        "XREADGROUP GROUP workers alice COUNT 2 STREAMS events >",
+    );
+    assert_eq!(read[0].0, "events");
+    assert_eq!(ids(&read[0].1), vec!["1-0", "2-0"]);
+    let read: Streams = query(&mut conn, "XREADGROUP GROUP workers bob STREAMS events >");
+    assert_eq!(ids(&read[0].1), vec!["3-0", "4-0", "5-0"]);
+    let read: Option<Streams> = query(&mut conn, "XREADGROUP GROUP workers bob STREAMS events >");
+    assert_eq!(read, None);
+
+    // Reading from an ID reads the consumer's own pending entries
+    let read: Streams = query(&mut conn, "XREADGROUP GROUP workers alice STREAMS events 0");
+    assert_eq!(ids(&read[0].1), vec!["1-0", "2-0"]);
+
+    let acked: i64 = query(&mut conn, "XACK events workers 1-0 9-0");
+    assert_eq!(acked, 1);
+    let acked: i64 = query(&mut conn, "XACK events missing 2-0");
+    assert_eq!(acked, 0);
+
+    // Pending entries deleted from the stream are read without their fields
+    let _: i64 = query(&mut conn, "XDEL events 2-0");
+    let read: History = query(&mut conn, "XREADGROUP GROUP workers alice STREAMS events 0");
+    assert_eq!(read[0].1, vec![("2-0".to_string(), None)]);
+
+    // NOACK doesn't leave the entries pending
+    add_entries(&mut conn, "other", 2);
+    let _: () = query(&mut conn, "XGROUP CREATE other workers 0");
+    let _: Streams = query(
+        &mut conn,
+        "XREADGROUP GROUP workers alice NOACK STREAMS other >",
+    );
+    let pending: (i64, Option<String>, Option<String>, Option<Vec<String>>) =
+        query(&mut conn, "XPENDING other workers");
+    assert_eq!(pending, (0, None, None, None));
+
+    let err = error(&mut conn, "XREADGROUP GROUP missing alice STREAMS events >");
+    assert_eq!(err.code(), Some("NOGROUP"));
+    assert!(
+        error(&mut conn, "XREADGROUP GROUP workers alice STREAMS events $")
+            .to_string()
+            .contains("meaningless")
+    );
+}
+
+#[test]
+fn test_xreadgroup_blocks_until_xadd() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let _: () = query(&mut conn, "XGROUP CREATE events workers $ MKSTREAM");
+    let read: Option<Streams> = query(
+        &mut conn,
+        "XREADGROUP GROUP workers alice BLOCK 100 STREAMS events >",
+    );
+    assert_eq!(read, None);
+
+    let mut readers = Vec::new();
+    for consumer in ["alice", "bob"] {
+        let mut reader = client.get_connection().unwrap();
+        readers.push(thread::spawn(move || {
+            let command = format!(
+                "XREADGROUP GROUP workers {} BLOCK 500 STREAMS events >",
+                consumer
+            );
+            let read: Option<Streams> = query(&mut reader, &command);
+            read
+        }));
+        thread::sleep(Duration::from_millis(50));
+    }
+    thread::sleep(Duration::from_millis(50));
+
+    // Only the first consumer to block gets the new entry, as it's delivered to the group once
+    let _: String = query(&mut conn, "XADD events 1-0 n new");
+    let mut readers = readers.into_iter();
+    let read = readers.next().unwrap().join().unwrap().unwrap();
+    assert_eq!(ids(&read[0].1), vec!["1-0"]);
+    assert_eq!(readers.next().unwrap().join().unwrap(), None);
+
+    // Destroying the group unblocks its readers with an error
+    let mut reader = client.get_connection().unwrap();
+    let blocked = thread::spawn(move || {
+        error(
+            &mut reader,
+            "XREADGROUP GROUP workers alice BLOCK 0 STREAMS events >",
+        )
+    });
+    thread::sleep(Duration::from_millis(100));
+    let _: i64 = query(&mut conn, "XGROUP DESTROY events workers");
+    assert_eq!(blocked.join().unwrap().code(), Some("NOGROUP"));
+}
+
+#[test]
+fn test_xpending() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    add_entries(&mut conn, "events", 4);
+    let _: () = query(&mut conn, "XGROUP CREATE events workers 0");
+    let _: Streams = query(
+        &mut conn,
+        "XREADGROUP GROUP workers alice COUNT 3 STREAMS events >",
+    );
+    let _: Streams = query(&mut conn, "XREADGROUP GROUP workers bob STREAMS events >");
+
+    let summary: (i64, String, String, Vec<(String, String)>) =
+        query(&mut conn, "XPENDING events workers");
+    assert_eq!(
+        summary,
+        (
+            4,
+            "1-0".to_string(),
+            "4-0".to_string(),
+            vec![("alice".into(), "3".into()), ("bob".into(), "1".into())]
+        )
+    );
+
+    let pending: Pending = query(&mut conn, "XPENDING events workers - + 10");
+    assert_eq!(pending.len(), 4);
+    assert_eq!(pending[3].0, "4-0");
+    assert_eq!(pending[3].1, "bob");
+    assert_eq!(pending[3].3, 1);
+
+    let pending: Pending = query(&mut conn, "XPENDING events workers (1-0 + 1 alice");
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].0, "2-0");
+
+    thread::sleep(Duration::from_millis(50));
+    let pending: Pending = query(&mut conn, "XPENDING events workers IDLE 10000 - + 10");
+    assert!(pending.is_empty());
+    let pending: Pending = query(&mut conn, "XPENDING events workers IDLE 40 - + 10");
+    assert_eq!(pending.len(), 4);
+    assert!(pending[0].2 >= 40);
+
+    let err = error(&mut conn, "XPENDING events missing");
+    assert_eq!(err.code(), Some("NOGROUP"));
+}
+
+#[test]
+fn test_xclaim_and_xautoclaim() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    add_entries(&mut conn, "events", 5);
+    let _: () = query(&mut conn, "XGROUP CREATE events workers 0");
+    let _: Streams = query(&mut conn, "XREADGROUP GROUP workers alice STREAMS events >");
+
+    // Entries that haven't been idle long enough are left alone
+    let claimed: Vec<Entry> = query(&mut conn, "XCLAIM events workers bob 10000 1-0");
+    assert!(claimed.is_empty());
+
+    thread::sleep(Duration::from_millis(50));
+    let claimed: Vec<Entry> = query(&mut conn, "XCLAIM events workers bob 20 1-0 2-0");
+    assert_eq!(ids(&claimed), vec!["1-0", "2-0"]);
+    assert_eq!(claimed[0].1, vec!["n", "1"]);
+    let claimed: Vec<String> = query(
+        &mut conn,
+        "XCLAIM events workers carol 0 1-0 RETRYCOUNT 7 JUSTID",
+    );
+    assert_eq!(claimed, vec!["1-0"]);
+    let pending: Pending = query(&mut conn, "XPENDING events workers - + 10");
+    assert_eq!((pending[0].1.as_str(), pending[0].3), ("carol", 7));
+    assert_eq!((pending[1].1.as_str(), pending[1].3), ("bob", 2));
+
+    // XAUTOCLAIM goes through the pending entries a page at a time, and drops deleted ones
+    let _: i64 = query(&mut conn, "XDEL events 3-0");
+    let (next, claimed, deleted): (String, Vec<Entry>, Vec<String>) =
+        query(&mut conn, "XAUTOCLAIM events workers dave 0 - COUNT 2");
+    assert_eq!(next, "3-0");
+    assert_eq!(ids(&claimed), vec!["1-0", "2-0"]);
+    assert!(deleted.is_empty());
+    let (next, claimed, deleted): (String, Vec<String>, Vec<String>) = query(
+        &mut conn,
+        &format!("XAUTOCLAIM events workers dave 0 {} JUSTID", next),
+    );
+    assert_eq!(next, "0-0");
+    assert_eq!(claimed, vec!["4-0", "5-0"]);
+    assert_eq!(deleted, vec!["3-0"]);
+
+    let summary: (i64, String, String, Vec<(String, String)>) =
+        query(&mut conn, "XPENDING events workers");
+    assert_eq!(summary.0, 4);
+    assert_eq!(summary.3, vec![("dave".into(), "4".into())]);
+
+    assert!(
+        error(&mut conn, "XAUTOCLAIM events workers dave 0 - COUNT 0")
+            .to_string()
+            .contains("COUNT must be > 0")
+    );
+    assert!(error(&mut conn, "XCLAIM events workers dave 0 1-0 BOGUS")
+        .to_string()
+        .contains("Unrecognized XCLAIM option"));
+}
+
+#[test]
+fn test_xinfo() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    add_entries(&mut conn, "events", 3);
+    let _: () = query(&mut conn, "XGROUP CREATE events workers 0");
+    let _: Streams = query(
+        &mut conn,
+        "XREADGROUP GROUP workers alice COUNT 2 STREAMS events >",
+    );
+    let _: i64 = query(&mut conn, "XGROUP CREATECONSUMER events workers bob");
+
+    let info = info_map(query(&mut conn, "XINFO STREAM events"));
+    assert_eq!(info_int(&info, "length"), Some(3));
+    assert_eq!(info_int(&info, "groups"), Some(1));
+    assert_eq!(info_int(&info, "entries-added"), Some(3));
+    let last_id: String = redis::from_redis_value(&info["last-generated-id"]).unwrap();
+    assert_eq!(last_id, "3-0");
+    let first: Entry = redis::from_redis_value(&info["first-entry"]).unwrap();
+    assert_eq!(first.0, "1-0");
+
+    let groups: Vec<Value> = query(&mut conn, "XINFO GROUPS events");
+    let group = info_map(groups[0].clone());
+    assert_eq!(info_int(&group, "consumers"), Some(2));
+    assert_eq!(info_int(&group, "pending"), Some(2));
+    assert_eq!(info_int(&group, "entries-read"), Some(2));
+    assert_eq!(info_int(&group, "lag"), Some(1));
+
+    let consumers: Vec<Value> = query(&mut conn, "XINFO CONSUMERS events workers");
+    let alice = info_map(consumers[0].clone());
+    let bob = info_map(consumers[1].clone());
+    assert_eq!(info_int(&alice, "pending"), Some(2));
+    assert!(info_int(&alice, "inactive").unwrap() >= 0);
+    assert_eq!(info_int(&bob, "inactive"), Some(-1));
+
+    let full = info_map(query(&mut conn, "XINFO STREAM events FULL COUNT 1"));
+    let entries: Vec<Entry> = redis::from_redis_value(&full["entries"]).unwrap();
+    assert_eq!(ids(&entries), vec!["1-0"]);
+    let groups: Vec<Value> = redis::from_redis_value(&full["groups"]).unwrap();
+    let group = info_map(groups[0].clone());
+    assert_eq!(info_int(&group, "pel-count"), Some(2));
+    let pending: Pending = redis::from_redis_value(&group["pending"]).unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].1, "alice");
+
+    assert!(error(&mut conn, "XINFO STREAM missing")
+        .to_string()
+        .contains("no such key"));
+    let err = error(&mut conn, "XINFO CONSUMERS events missing");
+    assert_eq!(err.code(), Some("NOGROUP"));
+}
+
+#[test]
+fn test_groups_survive_save_and_restart() {
+    let dir = std::env::temp_dir().join(format!("rustis-groups-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let dir = dir.to_str().unwrap();
+    let args = vec!["--dir", dir, "--dbfilename", "groups.rdb"];
+
+    {
+        let server = TestServer::start(Some(args.clone()));
+        let client = redis::Client::open(server.connection_string()).unwrap();
+        let mut conn = client.get_connection().unwrap();
+
+        add_entries(&mut conn, "events", 4);
+        let _: () = query(&mut conn, "XGROUP CREATE events workers 0");
+        let _: () = query(&mut conn, "XGROUP CREATE events audit $");
+        let _: Streams = query(
+            &mut conn,
+            "XREADGROUP GROUP workers alice COUNT 2 STREAMS events >",
+        );
+        let _: Streams = query(
+            &mut conn,
+            "XREADGROUP GROUP workers bob COUNT 1 STREAMS events >",
+        );
+        let _: i64 = query(&mut conn, "XACK events workers 1-0");
+        let _: i64 = query(&mut conn, "XGROUP CREATECONSUMER events workers carol");
+        let _: () = query(&mut conn, "SAVE");
+    }
+
+    let server = TestServer::start(Some(args));
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let pending: Pending = query(&mut conn, "XPENDING events workers - + 10");
+    let pending: Vec<(&str, &str)> = pending
+        .iter()
+        .map(|(id, consumer, _, _)| (id.as_str(), consumer.as_str()))
+        .collect();
+    assert_eq!(pending, vec![("2-0", "alice"), ("3-0", "bob")]);
+
+    let groups: Vec<Value> = query(&mut conn, "XINFO GROUPS events");
+    let names: Vec<String> = groups
+        .into_iter()
+        .map(|group| redis::from_redis_value(&info_map(group)["name"]).unwrap())
+        .collect();
+    assert_eq!(names, vec!["audit", "workers"]);
+    let consumers: Vec<Value> = query(&mut conn, "XINFO CONSUMERS events workers");
+    assert_eq!(consumers.len(), 3);
+    assert_eq!(
+        info_int(&info_map(consumers[2].clone()), "inactive"),
+        Some(-1)
+    );
+
+    // The group carries on from where it was
+    let read: Streams = query(&mut conn, "XREADGROUP GROUP workers carol STREAMS events >");
+    assert_eq!(ids(&read[0].1), vec!["4-0"]);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
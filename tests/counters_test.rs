@@ -0,0 +1,93 @@
+mod common;
+
+use common::TestServer;
+use redis::Commands;
+
+#[test]
+fn test_incr_and_decr() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let result: i64 = conn.incr("counter", 1).unwrap();
+    assert_eq!(result, 1);
+    let result: i64 = conn.incr("counter", 10).unwrap();
+    assert_eq!(result, 11);
+    let result: i64 = conn.decr("counter", 1).unwrap();
+    assert_eq!(result, 10);
+    let result: i64 = conn.decr("counter", 20).unwrap();
+    assert_eq!(result, -10);
+
+    let result: String = conn.get("counter").unwrap();
+    assert_eq!(result, "-10");
+}
+
+#[test]
+fn test_incr_errors() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let _: () = conn.set("not_a_number", "abc").unwrap();
+    let result: redis::RedisResult<i64> = conn.incr("not_a_number", 1);
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("value is not an integer or out of range"));
+
+    let _: () = conn.set("max", i64::MAX).unwrap();
+    let result: redis::RedisResult<i64> = conn.incr("max", 1);
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("increment or decrement would overflow"));
+}
+
+#[test]
+fn test_incrbyfloat() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let _: () = conn.set("float", "10.50").unwrap();
+    let result: String = redis::cmd("INCRBYFLOAT")
+        .arg("float")
+        .arg("0.1")
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(result, "10.6");
+
+    let result: String = redis::cmd("INCRBYFLOAT")
+        .arg("float")
+        .arg("-5.6")
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(result, "5");
+
+    // Results are never formatted with an exponent, and are stored the same way
+    let _: () = conn.set("float", "10.1").unwrap();
+    let result: String = redis::cmd("INCRBYFLOAT")
+        .arg("float")
+        .arg("0.1")
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(result, "10.2");
+    let _: () = conn.set("float", "0").unwrap();
+    let result: String = redis::cmd("INCRBYFLOAT")
+        .arg("float")
+        .arg("1e20")
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(result, "100000000000000000000");
+    let stored: String = conn.get("float").unwrap();
+    assert_eq!(stored, "100000000000000000000");
+
+    let result: redis::RedisResult<String> = redis::cmd("INCRBYFLOAT")
+        .arg("float")
+        .arg("abc")
+        .query(&mut conn);
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("value is not a valid float"));
+}
@@ -0,0 +1,345 @@
+mod common;
+
+use common::TestServer;
+use redis::Value;
+use std::{thread, time::Duration};
+
+const LIBRARY: &str = "#!lua name=mylib
+local function incr(keys, args)
+    return redis.call('INCRBY', keys[1], args[1])
+end
+redis.register_function('myincr', incr)
+redis.register_function{
+    function_name = 'myget',
+    callback = function(keys) return redis.call('GET', keys[1]) end,
+    description = 'Get a key',
+    flags = {'no-writes'},
+}";
+
+fn function_load(conn: &mut redis::Connection, code: &str) -> redis::RedisResult<String> {
+    redis::cmd("FUNCTION").arg("LOAD").arg(code).query(conn)
+}
+
+fn fcall(
+    conn: &mut redis::Connection,
+    command: &str,
+    function: &str,
+    keys: &[&str],
+    args: &[&str],
+) -> redis::RedisResult<Value> {
+    redis::cmd(command)
+        .arg(function)
+        .arg(keys.len())
+        .arg(keys)
+        .arg(args)
+        .query(conn)
+}
+
+/// The code and message of an error
+fn error(result: redis::RedisResult<impl std::fmt::Debug>) -> String {
+    let e = result.unwrap_err();
+    format!(
+        "{} {}",
+        e.code().unwrap_or_default(),
+        e.detail().unwrap_or_default()
+    )
+}
+
+#[test]
+fn test_function_load_and_fcall() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    assert_eq!(function_load(&mut conn, LIBRARY).unwrap(), "mylib");
+    assert_eq!(
+        fcall(&mut conn, "FCALL", "myincr", &["n"], &["5"]).unwrap(),
+        Value::Int(5)
+    );
+    assert_eq!(
+        fcall(&mut conn, "FCALL_RO", "myget", &["n"], &[]).unwrap(),
+        Value::BulkString(b"5".to_vec())
+    );
+
+    assert_eq!(
+        error(fcall(&mut conn, "FCALL", "nope", &[], &[])),
+        "ERR Function not found"
+    );
+    assert_eq!(
+        error(fcall(&mut conn, "FCALL_RO", "myincr", &["n"], &["1"])),
+        "ERR Can not execute a script with write flag using *_ro command."
+    );
+    assert_eq!(
+        error(
+            redis::cmd("FCALL")
+                .arg("myincr")
+                .arg(2)
+                .arg("n")
+                .query::<Value>(&mut conn)
+        ),
+        "ERR Number of keys can't be greater than number of args"
+    );
+
+    // Errors say which function failed, and where
+    function_load(
+        &mut conn,
+        "#!lua name=failing\nredis.register_function('fail', function()\n  error('oops')\nend)",
+    )
+    .unwrap();
+    assert_eq!(
+        error(fcall(&mut conn, "FCALL", "fail", &[], &[])),
+        "ERR user_function:3: oops script: fail, on @user_function:3."
+    );
+}
+
+#[test]
+fn test_function_load_errors() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    assert_eq!(
+        error(function_load(&mut conn, "return 1")),
+        "ERR Missing library metadata"
+    );
+    assert_eq!(
+        error(function_load(&mut conn, "#!lua\nreturn 1")),
+        "ERR Library name was not given"
+    );
+    assert_eq!(
+        error(function_load(&mut conn, "#!js name=lib\nreturn 1")),
+        "ERR Engine 'js' not found"
+    );
+    assert_eq!(
+        error(function_load(&mut conn, "#!lua name=lib\nlocal x = 1")),
+        "ERR No functions registered"
+    );
+    assert_eq!(
+        error(function_load(
+            &mut conn,
+            "#!lua name=lib\nredis.register_function('my-func', function() end)"
+        )),
+        "ERR Error registering functions: Function names can only contain letters, numbers, or \
+         underscores(_) and must be at least one character long"
+    );
+    assert!(error(function_load(&mut conn, "#!lua name=lib\nreturn ("))
+        .starts_with("ERR Error compiling function: "));
+
+    // Libraries can only be replaced explicitly, and functions can't clash with other libraries
+    function_load(&mut conn, LIBRARY).unwrap();
+    assert_eq!(
+        error(function_load(&mut conn, LIBRARY)),
+        "ERR Library 'mylib' already exists"
+    );
+    let replaced: String = redis::cmd("FUNCTION")
+        .arg("LOAD")
+        .arg("REPLACE")
+        .arg(LIBRARY)
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(replaced, "mylib");
+    assert_eq!(
+        error(function_load(
+            &mut conn,
+            "#!lua name=other\nredis.register_function('myget', function() end)"
+        )),
+        "ERR Function myget already exists"
+    );
+}
+
+#[test]
+fn test_function_list_and_delete() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    function_load(&mut conn, LIBRARY).unwrap();
+    let list: Value = redis::cmd("FUNCTION").arg("LIST").query(&mut conn).unwrap();
+    let bulk = |s: &str| Value::BulkString(s.as_bytes().to_vec());
+    assert_eq!(
+        list,
+        Value::Array(vec![Value::Array(vec![
+            bulk("library_name"),
+            bulk("mylib"),
+            bulk("engine"),
+            bulk("LUA"),
+            bulk("functions"),
+            Value::Array(vec![
+                Value::Array(vec![
+                    bulk("name"),
+                    bulk("myget"),
+                    bulk("description"),
+                    bulk("Get a key"),
+                    bulk("flags"),
+                    Value::Array(vec![bulk("no-writes")]),
+                ]),
+                Value::Array(vec![
+                    bulk("name"),
+                    bulk("myincr"),
+                    bulk("description"),
+                    Value::Nil,
+                    bulk("flags"),
+                    Value::Array(vec![]),
+                ]),
+            ]),
+        ])])
+    );
+
+    let list: Vec<Vec<Value>> = redis::cmd("FUNCTION")
+        .arg("LIST")
+        .arg("WITHCODE")
+        .arg("LIBRARYNAME")
+        .arg("my*")
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(list[0][7], bulk(LIBRARY));
+    let list: Vec<Value> = redis::cmd("FUNCTION")
+        .arg("LIST")
+        .arg("LIBRARYNAME")
+        .arg("other*")
+        .query(&mut conn)
+        .unwrap();
+    assert!(list.is_empty());
+
+    let _: () = redis::cmd("FUNCTION")
+        .arg("DELETE")
+        .arg("mylib")
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(
+        error(
+            redis::cmd("FUNCTION")
+                .arg("DELETE")
+                .arg("mylib")
+                .query::<Value>(&mut conn)
+        ),
+        "ERR Library not found"
+    );
+    assert_eq!(
+        error(fcall(&mut conn, "FCALL", "myincr", &["n"], &["1"])),
+        "ERR Function not found"
+    );
+}
+
+#[test]
+fn test_function_dump_and_restore() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    function_load(&mut conn, LIBRARY).unwrap();
+    let payload: Vec<u8> = redis::cmd("FUNCTION").arg("DUMP").query(&mut conn).unwrap();
+    let _: () = redis::cmd("FUNCTION")
+        .arg("FLUSH")
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(
+        error(fcall(&mut conn, "FCALL", "myincr", &["n"], &["1"])),
+        "ERR Function not found"
+    );
+
+    let _: () = redis::cmd("FUNCTION")
+        .arg("RESTORE")
+        .arg(&payload)
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(
+        fcall(&mut conn, "FCALL", "myincr", &["n"], &["2"]).unwrap(),
+        Value::Int(2)
+    );
+
+    // Appending fails as the library exists, but it can be replaced
+    assert_eq!(
+        error(
+            redis::cmd("FUNCTION")
+                .arg("RESTORE")
+                .arg(&payload)
+                .query::<Value>(&mut conn)
+        ),
+        "ERR Library 'mylib' already exists"
+    );
+    let _: () = redis::cmd("FUNCTION")
+        .arg("RESTORE")
+        .arg(&payload)
+        .arg("REPLACE")
+        .query(&mut conn)
+        .unwrap();
+
+    let mut corrupted = payload.clone();
+    let last = corrupted.len() - 1;
+    corrupted[last] ^= 0xFF;
+    assert_eq!(
+        error(
+            redis::cmd("FUNCTION")
+                .arg("RESTORE")
+                .arg(&corrupted)
+                .arg("FLUSH")
+                .query::<Value>(&mut conn)
+        ),
+        "ERR payload version or checksum are wrong"
+    );
+}
+
+#[test]
+fn test_functions_survive_save_and_restart() {
+    let dir = std::env::temp_dir().join(format!("rustis-functions-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let dir = dir.to_str().unwrap();
+    let args = vec!["--dir", dir, "--dbfilename", "functions.rdb"];
+
+    {
+        let server = TestServer::start(Some(args.clone()));
+        let client = redis::Client::open(server.connection_string()).unwrap();
+        let mut conn = client.get_connection().unwrap();
+
+        function_load(&mut conn, LIBRARY).unwrap();
+        let _: () = redis::cmd("SET").arg("n").arg(10).query(&mut conn).unwrap();
+        let _: () = redis::cmd("SAVE").query(&mut conn).unwrap();
+    }
+    thread::sleep(Duration::from_millis(300));
+
+    let server = TestServer::start(Some(args));
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    assert_eq!(
+        fcall(&mut conn, "FCALL", "myincr", &["n"], &["1"]).unwrap(),
+        Value::Int(11)
+    );
+}
+
+#[test]
+fn test_function_kill() {
+    let server = TestServer::start(Some(vec!["--busy-reply-threshold", "100"]));
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    function_load(
+        &mut conn,
+        "#!lua name=loop\nredis.register_function('spin', function() while true do end end)",
+    )
+    .unwrap();
+    let mut function_conn = client.get_connection().unwrap();
+    let function =
+        thread::spawn(move || error(fcall(&mut function_conn, "FCALL", "spin", &[], &[])));
+    thread::sleep(Duration::from_millis(300));
+
+    assert_eq!(
+        error(redis::cmd("PING").query::<Value>(&mut conn)),
+        "BUSY Redis is busy running a script. You can only call FUNCTION KILL or SHUTDOWN NOSAVE."
+    );
+    assert_eq!(
+        error(redis::cmd("SCRIPT").arg("KILL").query::<Value>(&mut conn)),
+        "BUSY Redis is busy running a script. You can only call FUNCTION KILL or SHUTDOWN NOSAVE."
+    );
+    let _: () = redis::cmd("FUNCTION").arg("KILL").query(&mut conn).unwrap();
+
+    assert!(function
+        .join()
+        .unwrap()
+        .starts_with("ERR Script killed by user with SCRIPT KILL... script: spin"));
+    assert_eq!(
+        error(redis::cmd("FUNCTION").arg("KILL").query::<Value>(&mut conn)),
+        "NOTBUSY No scripts in execution right now."
+    );
+}
@@ -0,0 +1,283 @@
+mod common;
+
+use common::{error, query, TestServer};
+
+/// Add the positions used by the examples in the Redis documentation
+fn add_sicily(conn: &mut redis::Connection) {
+    let added: i64 = query(
+        conn,
+        "GEOADD Sicily 13.361389 38.115556 Palermo 15.087269 37.502669 Catania",
+    );
+    assert_eq!(added, 2);
+    let added: i64 = query(
+        conn,
+        "GEOADD Sicily 12.758489 38.788135 edge1 17.241510 38.788135 edge2",
+    );
+    assert_eq!(added, 2);
+}
+
+#[test]
+fn test_geoadd_and_lookups() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    add_sicily(&mut conn);
+    let score: String = query(&mut conn, "ZSCORE Sicily Palermo");
+    assert_eq!(score, "3479099956230698");
+    let score: String = query(&mut conn, "ZSCORE Sicily Catania");
+    assert_eq!(score, "3479447370796909");
+
+    let distance: String = query(&mut conn, "GEODIST Sicily Palermo Catania");
+    assert_eq!(distance, "166274.1516");
+    let distance: String = query(&mut conn, "GEODIST Sicily Palermo Catania km");
+    assert_eq!(distance, "166.2742");
+    let distance: String = query(&mut conn, "GEODIST Sicily Palermo Catania MI");
+    assert_eq!(distance, "103.3182");
+    let distance: Option<String> = query(&mut conn, "GEODIST Sicily Foo Bar");
+    assert_eq!(distance, None);
+
+    let positions: Vec<Option<(String, String)>> =
+        query(&mut conn, "GEOPOS Sicily Palermo Catania NonExisting");
+    assert_eq!(
+        positions,
+        vec![
+            Some((
+                "13.36138933897018433".to_string(),
+                "38.11555639549629859".to_string()
+            )),
+            Some((
+                "15.08726745843887329".to_string(),
+                "37.50266842333162032".to_string()
+            )),
+            None,
+        ]
+    );
+    let positions: Vec<Option<(String, String)>> = query(&mut conn, "GEOPOS Missing a");
+    assert_eq!(positions, vec![None]);
+
+    let hashes: Vec<Option<String>> = query(&mut conn, "GEOHASH Sicily Palermo Catania Foo");
+    assert_eq!(
+        hashes,
+        vec![
+            Some("sqc8b49rny0".to_string()),
+            Some("sqdtr74hyu0".to_string()),
+            None
+        ]
+    );
+
+    // Existing members are updated, and the flags work like with ZADD
+    let added: i64 = query(
+        &mut conn,
+        "GEOADD Sicily XX CH 13.4 38.1 Palermo 1 1 Nowhere",
+    );
+    assert_eq!(added, 1);
+    assert_eq!(query::<i64>(&mut conn, "ZCARD Sicily"), 4);
+
+    let err = error(&mut conn, "GEOADD Sicily 200 100 Nowhere");
+    assert_eq!(
+        err.detail(),
+        Some("invalid longitude,latitude pair 200.000000,100.000000")
+    );
+    let err = error(&mut conn, "GEOADD Sicily NX XX 1 1 Nowhere");
+    assert_eq!(err.detail(), Some("syntax error"));
+    let err = error(&mut conn, "GEODIST Sicily Palermo Catania parsecs");
+    assert_eq!(
+        err.detail(),
+        Some("unsupported unit provided. please use M, KM, FT, MI")
+    );
+}
+
+#[test]
+fn test_geosearch() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    add_sicily(&mut conn);
+
+    let members: Vec<String> = query(
+        &mut conn,
+        "GEOSEARCH Sicily FROMLONLAT 15 37 BYRADIUS 200 km ASC",
+    );
+    assert_eq!(members, vec!["Catania", "Palermo"]);
+    let members: Vec<String> = query(
+        &mut conn,
+        "GEOSEARCH Sicily FROMLONLAT 15 37 BYRADIUS 200 km DESC",
+    );
+    assert_eq!(members, vec!["Palermo", "Catania"]);
+
+    type WithCoordAndDist = Vec<(String, String, (String, String))>;
+    let found: WithCoordAndDist = query(
+        &mut conn,
+        "GEOSEARCH Sicily FROMLONLAT 15 37 BYBOX 400 400 km ASC WITHCOORD WITHDIST",
+    );
+    let expected = [
+        (
+            "Catania",
+            "56.4413",
+            "15.08726745843887329",
+            "37.50266842333162032",
+        ),
+        (
+            "Palermo",
+            "190.4424",
+            "13.36138933897018433",
+            "38.11555639549629859",
+        ),
+        (
+            "edge2",
+            "279.7403",
+            "17.24151045083999634",
+            "38.78813451624225195",
+        ),
+        (
+            "edge1",
+            "279.7405",
+            "12.7584877610206604",
+            "38.78813451624225195",
+        ),
+    ];
+    assert_eq!(found.len(), expected.len());
+    for (found, expected) in found.iter().zip(expected) {
+        assert_eq!(found.0, expected.0);
+        assert_eq!(found.1, expected.1);
+        assert_eq!(found.2 .0, expected.2);
+        assert_eq!(found.2 .1, expected.3);
+    }
+
+    let found: Vec<(String, String, i64)> = query(
+        &mut conn,
+        "GEOSEARCH Sicily FROMMEMBER Palermo BYRADIUS 200 km COUNT 1 WITHDIST WITHHASH",
+    );
+    assert_eq!(
+        found,
+        vec![(
+            "Palermo".to_string(),
+            "0.0000".to_string(),
+            3479099956230698
+        )]
+    );
+
+    // COUNT without ANY returns the nearest, with ANY whichever are found first
+    let members: Vec<String> = query(
+        &mut conn,
+        "GEOSEARCH Sicily FROMLONLAT 15 37 BYBOX 400 400 km COUNT 2",
+    );
+    assert_eq!(members, vec!["Catania", "Palermo"]);
+    let members: Vec<String> = query(
+        &mut conn,
+        "GEOSEARCH Sicily FROMLONLAT 15 37 BYBOX 400 400 km COUNT 1 ANY",
+    );
+    assert_eq!(members.len(), 1);
+
+    let members: Vec<String> = query(&mut conn, "GEOSEARCH Sicily FROMLONLAT 0 0 BYRADIUS 10 km");
+    assert!(members.is_empty());
+    let members: Vec<String> = query(&mut conn, "GEOSEARCH Missing FROMLONLAT 0 0 BYRADIUS 10 km");
+    assert!(members.is_empty());
+
+    for (command, message) in [
+        (
+            "GEOSEARCH Sicily BYRADIUS 10 km ASC WITHDIST",
+            "exactly one of FROMMEMBER or FROMLONLAT can be specified for geosearch",
+        ),
+        (
+            "GEOSEARCH Sicily FROMLONLAT 15 37 ASC WITHDIST",
+            "exactly one of BYRADIUS and BYBOX can be specified for geosearch",
+        ),
+        (
+            "GEOSEARCH Sicily FROMLONLAT 15 37 BYRADIUS 10 km ANY",
+            "the ANY argument requires COUNT argument",
+        ),
+        (
+            "GEOSEARCH Sicily FROMLONLAT 15 37 BYRADIUS 10 km COUNT 0",
+            "COUNT must be > 0",
+        ),
+        (
+            "GEOSEARCH Sicily FROMMEMBER Rome BYRADIUS 10 km",
+            "could not decode requested zset member",
+        ),
+        (
+            "GEOSEARCH Sicily FROMLONLAT 15 37 BYRADIUS -1 km",
+            "radius cannot be negative",
+        ),
+        (
+            "GEOSEARCH Sicily FROMLONLAT 15 37 BYRADIUS 10 km FROMMEMBER Palermo",
+            "syntax error",
+        ),
+    ] {
+        let err = error(&mut conn, command);
+        assert_eq!(err.detail(), Some(message), "{}", command);
+    }
+}
+
+#[test]
+fn test_geosearchstore() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    add_sicily(&mut conn);
+
+    let stored: i64 = query(
+        &mut conn,
+        "GEOSEARCHSTORE key1 Sicily FROMLONLAT 15 37 BYBOX 400 400 km ASC COUNT 3",
+    );
+    assert_eq!(stored, 3);
+    let hashes: Vec<(String, String, i64)> = query(
+        &mut conn,
+        "GEOSEARCH key1 FROMLONLAT 15 37 BYBOX 400 400 km ASC WITHDIST WITHHASH",
+    );
+    assert_eq!(
+        hashes,
+        vec![
+            (
+                "Catania".to_string(),
+                "56.4413".to_string(),
+                3479447370796909
+            ),
+            (
+                "Palermo".to_string(),
+                "190.4424".to_string(),
+                3479099956230698
+            ),
+            (
+                "edge2".to_string(),
+                "279.7403".to_string(),
+                3481342659049484
+            ),
+        ]
+    );
+
+    let stored: i64 = query(
+        &mut conn,
+        "GEOSEARCHSTORE key2 Sicily FROMLONLAT 15 37 BYBOX 400 400 km ASC COUNT 3 STOREDIST",
+    );
+    assert_eq!(stored, 3);
+    let scores: Vec<(String, String)> = query(&mut conn, "ZRANGE key2 0 -1 WITHSCORES");
+    assert_eq!(
+        scores,
+        vec![
+            ("Catania".to_string(), "56.4412578701582".to_string()),
+            ("Palermo".to_string(), "190.44242984775784".to_string()),
+            ("edge2".to_string(), "279.7403417843143".to_string()),
+        ]
+    );
+
+    // Nothing found removes the destination
+    let stored: i64 = query(
+        &mut conn,
+        "GEOSEARCHSTORE key2 Sicily FROMLONLAT 0 0 BYRADIUS 1 km",
+    );
+    assert_eq!(stored, 0);
+    assert_eq!(query::<String>(&mut conn, "TYPE key2"), "none");
+
+    let err = error(
+        &mut conn,
+        "GEOSEARCHSTORE key3 Sicily FROMLONLAT 15 37 BYRADIUS 10 km WITHDIST",
+    );
+    assert_eq!(
+        err.detail(),
+        Some("GEOSEARCHSTORE is not compatible with WITHDIST, WITHHASH and WITHCOORD options")
+    );
+}
@@ -0,0 +1,197 @@
+mod common;
+
+use common::{query, TestServer};
+use redis::Commands;
+use std::{collections::HashMap, thread::sleep, time::Duration};
+
+#[test]
+fn test_hexpire_httl_and_hpersist() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let replies: Vec<i64> = query(&mut conn, "HEXPIRE missing 100 FIELDS 1 a");
+    assert_eq!(replies, vec![-2]);
+
+    let _: () = conn
+        .hset_multiple("session", &[("a", "1"), ("b", "2"), ("c", "3")])
+        .unwrap();
+
+    let replies: Vec<i64> = query(&mut conn, "HEXPIRE session 100 FIELDS 2 a missing");
+    assert_eq!(replies, vec![1, -2]);
+
+    // NX only sets fields without an expiry, and GT treats no expiry as the latest
+    let replies: Vec<i64> = query(&mut conn, "HEXPIRE session 200 NX FIELDS 2 a b");
+    assert_eq!(replies, vec![0, 1]);
+    let replies: Vec<i64> = query(&mut conn, "HEXPIRE session 300 GT FIELDS 2 a c");
+    assert_eq!(replies, vec![1, 0]);
+
+    let ttls: Vec<i64> = query(&mut conn, "HTTL session FIELDS 3 a b c");
+    assert_eq!(ttls, vec![300, 200, -1]);
+    let ttls: Vec<i64> = query(&mut conn, "HPTTL session FIELDS 1 b");
+    assert!(ttls[0] > 199_000 && ttls[0] <= 200_000);
+
+    let replies: Vec<i64> = query(&mut conn, "HPERSIST session FIELDS 3 a c missing");
+    assert_eq!(replies, vec![1, -1, -2]);
+
+    // An expire time in the past deletes the field right away
+    let replies: Vec<i64> = query(&mut conn, "HEXPIRE session 0 FIELDS 1 c");
+    assert_eq!(replies, vec![2]);
+    let length: i64 = conn.hlen("session").unwrap();
+    assert_eq!(length, 2);
+
+    let result: redis::RedisResult<Vec<i64>> = redis::cmd("HEXPIRE")
+        .arg(&["session", "100", "FIELDS", "2", "a"])
+        .query(&mut conn);
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("The `numfields` parameter must match the number of arguments"));
+    let result: redis::RedisResult<Vec<i64>> = redis::cmd("HEXPIRE")
+        .arg(&["session", "100", "NX", "1", "a"])
+        .query(&mut conn);
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("Mandatory argument FIELDS is missing or not at the right position"));
+}
+
+#[test]
+fn test_fields_expire() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let _: () = conn
+        .hset_multiple(
+            "tokens",
+            &[("phone", "1"), ("laptop", "2"), ("tablet", "3")],
+        )
+        .unwrap();
+    let _: Vec<i64> = query(
+        &mut conn,
+        "HPEXPIRE tokens 100 FIELDS 3 phone laptop tablet",
+    );
+
+    // Setting a field removes its expiry, while incrementing it keeps it
+    let _: () = conn.hset("tokens", "phone", "4").unwrap();
+    let _: i64 = conn.hincr("tokens", "laptop", 1).unwrap();
+    let ttls: Vec<i64> = query(&mut conn, "HTTL tokens FIELDS 2 phone laptop");
+    assert_eq!(ttls[0], -1);
+    assert!(ttls[1] >= 0);
+
+    sleep(Duration::from_millis(200));
+    let remaining: HashMap<String, String> = conn.hgetall("tokens").unwrap();
+    assert_eq!(remaining, HashMap::from([("phone".into(), "4".into())]));
+
+    // The key is removed once all the fields have expired
+    let _: Vec<i64> = query(&mut conn, "HPEXPIRE tokens 50 FIELDS 1 phone");
+    sleep(Duration::from_millis(100));
+    let keys: Vec<String> = conn.keys("*").unwrap();
+    assert!(keys.is_empty());
+}
+
+#[test]
+fn test_hgetex_and_hsetex() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let set: i64 = query(&mut conn, "HSETEX user FNX EX 100 FIELDS 2 a 1 b 2");
+    assert_eq!(set, 1);
+    let ttls: Vec<i64> = query(&mut conn, "HTTL user FIELDS 2 a b");
+    assert_eq!(ttls, vec![100, 100]);
+
+    // FNX fails if any of the fields exist, and FXX if any of them don't
+    let set: i64 = query(&mut conn, "HSETEX user FNX FIELDS 2 a 3 c 3");
+    assert_eq!(set, 0);
+    let set: i64 = query(&mut conn, "HSETEX user FXX FIELDS 1 c 3");
+    assert_eq!(set, 0);
+
+    // KEEPTTL keeps the expiry, otherwise setting a field removes it
+    let set: i64 = query(&mut conn, "HSETEX user FXX KEEPTTL FIELDS 1 a 10");
+    assert_eq!(set, 1);
+    let set: i64 = query(&mut conn, "HSETEX user FIELDS 1 b 20");
+    assert_eq!(set, 1);
+    let ttls: Vec<i64> = query(&mut conn, "HTTL user FIELDS 2 a b");
+    assert_eq!(ttls, vec![100, -1]);
+
+    let values: Vec<Option<String>> = query(&mut conn, "HGETEX user PX 5000 FIELDS 3 a b missing");
+    assert_eq!(values, vec![Some("10".into()), Some("20".into()), None]);
+    let ttls: Vec<i64> = query(&mut conn, "HTTL user FIELDS 2 a b");
+    assert_eq!(ttls, vec![5, 5]);
+
+    let values: Vec<Option<String>> = query(&mut conn, "HGETEX user PERSIST FIELDS 1 a");
+    assert_eq!(values, vec![Some("10".into())]);
+    let ttls: Vec<i64> = query(&mut conn, "HTTL user FIELDS 1 a");
+    assert_eq!(ttls, vec![-1]);
+
+    // An expire time in the past deletes the fields after they are returned
+    let values: Vec<Option<String>> = query(&mut conn, "HGETEX user PXAT 1 FIELDS 2 a b");
+    assert_eq!(values, vec![Some("10".into()), Some("20".into())]);
+    let key_type: String = query(&mut conn, "TYPE user");
+    assert_eq!(key_type, "none");
+
+    let result: redis::RedisResult<Vec<Option<String>>> = redis::cmd("HGETEX")
+        .arg(&["user", "EX", "10", "PERSIST", "FIELDS", "1", "a"])
+        .query(&mut conn);
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("Only one of EX, PX, EXAT, PXAT or PERSIST arguments can be specified"));
+}
+
+#[test]
+fn test_field_expiries_survive_save_and_restart() {
+    let dir = std::env::temp_dir().join(format!("rustis-hash-expiry-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let dir = dir.to_str().unwrap();
+    let args = vec!["--dir", dir, "--dbfilename", "hash-expiry.rdb"];
+
+    {
+        let server = TestServer::start(Some(args.clone()));
+        let client = redis::Client::open(server.connection_string()).unwrap();
+        let mut conn = client.get_connection().unwrap();
+
+        let _: () = conn
+            .hset_multiple("small", &[("a", "1"), ("b", "2"), ("soon", "3")])
+            .unwrap();
+        let _: Vec<i64> = query(&mut conn, "HEXPIRE small 100 FIELDS 1 a");
+        let _: Vec<i64> = query(&mut conn, "HPEXPIRE small 200 FIELDS 1 soon");
+
+        let large: Vec<(String, String)> = (0..200)
+            .map(|i| (format!("field-{}", i), i.to_string()))
+            .collect();
+        let _: () = conn.hset_multiple("large", &large).unwrap();
+        let _: Vec<i64> = query(&mut conn, "HEXPIRE large 1000 FIELDS 2 field-1 field-2");
+        let _: Vec<i64> = query(&mut conn, "HEXPIRE large 50 FIELDS 1 field-3");
+
+        let _: () = redis::cmd("SAVE").query(&mut conn).unwrap();
+    }
+    sleep(Duration::from_millis(300));
+
+    let server = TestServer::start(Some(args));
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let small: Vec<String> = query(&mut conn, "HGETALL small");
+    assert_eq!(small, vec!["a", "1", "b", "2"]);
+    let encoding: String = query(&mut conn, "OBJECT ENCODING small");
+    assert_eq!(encoding, "listpackex");
+    let ttls: Vec<i64> = query(&mut conn, "HTTL small FIELDS 2 a b");
+    assert!(ttls[0] > 0 && ttls[0] <= 100);
+    assert_eq!(ttls[1], -1);
+
+    let length: i64 = conn.hlen("large").unwrap();
+    assert_eq!(length, 200);
+    let ttls: Vec<i64> = query(
+        &mut conn,
+        "HTTL large FIELDS 4 field-0 field-1 field-2 field-3",
+    );
+    assert_eq!(ttls[0], -1);
+    assert!(ttls[1] > 990 && ttls[1] <= 1000);
+    assert!(ttls[2] > 990 && ttls[2] <= 1000);
+    assert!(ttls[3] > 45 && ttls[3] <= 50);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
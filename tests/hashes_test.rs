@@ -0,0 +1,248 @@
+mod common;
+
+use common::TestServer;
+use redis::Commands;
+use std::collections::HashMap;
+
+fn object_encoding(conn: &mut redis::Connection, key: &str) -> String {
+    redis::cmd("OBJECT")
+        .arg("ENCODING")
+        .arg(key)
+        .query(conn)
+        .unwrap()
+}
+
+#[test]
+fn test_hset_hget_and_hdel() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let _: () = conn
+        .hset_multiple("user", &[("name", "alice"), ("age", "30")])
+        .unwrap();
+    let added: i64 = redis::cmd("HSET")
+        .arg(&["user", "name", "bob", "city", "paris"])
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(added, 1);
+
+    let name: String = conn.hget("user", "name").unwrap();
+    assert_eq!(name, "bob");
+    let values: Vec<Option<String>> = redis::cmd("HMGET")
+        .arg(&["user", "name", "missing", "age"])
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(
+        values,
+        vec![Some("bob".to_string()), None, Some("30".to_string())]
+    );
+
+    let length: i64 = conn.hlen("user").unwrap();
+    assert_eq!(length, 3);
+    let exists: bool = conn.hexists("user", "city").unwrap();
+    assert!(exists);
+    let strlen: i64 = redis::cmd("HSTRLEN")
+        .arg(&["user", "city"])
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(strlen, 5);
+
+    let all: HashMap<String, String> = conn.hgetall("user").unwrap();
+    assert_eq!(all.len(), 3);
+    assert_eq!(all["age"], "30");
+    let keys: Vec<String> = conn.hkeys("user").unwrap();
+    assert_eq!(keys, vec!["name", "age", "city"]);
+    let values: Vec<String> = conn.hvals("user").unwrap();
+    assert_eq!(values, vec!["bob", "30", "paris"]);
+
+    let set: bool = conn.hset_nx("user", "name", "carol").unwrap();
+    assert!(!set);
+    let set: bool = conn.hset_nx("user", "email", "bob@example.com").unwrap();
+    assert!(set);
+
+    let removed: i64 = conn.hdel("user", &["name", "age", "missing"]).unwrap();
+    assert_eq!(removed, 2);
+
+    // Removing the last fields removes the key
+    let removed: i64 = conn.hdel("user", &["city", "email"]).unwrap();
+    assert_eq!(removed, 2);
+    let key_type: String = redis::cmd("TYPE").arg("user").query(&mut conn).unwrap();
+    assert_eq!(key_type, "none");
+}
+
+#[test]
+fn test_hincrby_and_hincrbyfloat() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let value: i64 = conn.hincr("counters", "visits", 5).unwrap();
+    assert_eq!(value, 5);
+    let value: i64 = conn.hincr("counters", "visits", -7).unwrap();
+    assert_eq!(value, -2);
+
+    let value: f64 = conn.hincr("counters", "score", 1.5).unwrap();
+    assert_eq!(value, 1.5);
+    let value: String = redis::cmd("HINCRBYFLOAT")
+        .arg(&["counters", "score", "0.25"])
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(value, "1.75");
+    let value: String = redis::cmd("HINCRBYFLOAT")
+        .arg(&["counters", "score", "1e20"])
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(value, "100000000000000000000");
+    let value: String = conn.hget("counters", "score").unwrap();
+    assert_eq!(value, "100000000000000000000");
+
+    let _: () = conn.hset("counters", "name", "text").unwrap();
+    let result: redis::RedisResult<i64> = conn.hincr("counters", "name", 1);
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("hash value is not an integer"));
+
+    let _: () = conn.hset("counters", "big", i64::MAX).unwrap();
+    let result: redis::RedisResult<i64> = conn.hincr("counters", "big", 1);
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("increment or decrement would overflow"));
+}
+
+#[test]
+fn test_hrandfield() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let missing: Option<String> = redis::cmd("HRANDFIELD")
+        .arg("missing")
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(missing, None);
+
+    let _: () = conn
+        .hset_multiple("hash", &[("a", "1"), ("b", "2"), ("c", "3")])
+        .unwrap();
+
+    let field: String = redis::cmd("HRANDFIELD")
+        .arg("hash")
+        .query(&mut conn)
+        .unwrap();
+    assert!(["a", "b", "c"].contains(&field.as_str()));
+
+    // A positive count returns distinct fields, capped at the size of the hash
+    let mut fields: Vec<String> = redis::cmd("HRANDFIELD")
+        .arg(&["hash", "10"])
+        .query(&mut conn)
+        .unwrap();
+    fields.sort();
+    assert_eq!(fields, vec!["a", "b", "c"]);
+
+    // A negative count can return the same field more than once
+    let fields: Vec<String> = redis::cmd("HRANDFIELD")
+        .arg(&["hash", "-10"])
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(fields.len(), 10);
+
+    let pairs: Vec<String> = redis::cmd("HRANDFIELD")
+        .arg(&["hash", "2", "WITHVALUES"])
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(pairs.len(), 4);
+    for pair in pairs.chunks(2) {
+        let expected: String = conn.hget("hash", &pair[0]).unwrap();
+        assert_eq!(pair[1], expected);
+    }
+}
+
+#[test]
+fn test_encoding_conversion() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let _: () = redis::cmd("CONFIG")
+        .arg(&["SET", "hash-max-listpack-entries", "4"])
+        .query(&mut conn)
+        .unwrap();
+    let config: Vec<String> = redis::cmd("CONFIG")
+        .arg(&[
+            "GET",
+            "hash-max-listpack-entries",
+            "hash-max-listpack-value",
+        ])
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(
+        config,
+        vec![
+            "hash-max-listpack-entries",
+            "4",
+            "hash-max-listpack-value",
+            "64"
+        ]
+    );
+
+    for i in 0..4 {
+        let _: () = conn.hset("small", i, i).unwrap();
+    }
+    assert_eq!(object_encoding(&mut conn, "small"), "listpack");
+    let _: () = conn.hset("small", 4, 4).unwrap();
+    assert_eq!(object_encoding(&mut conn, "small"), "hashtable");
+
+    let _: () = conn.hset("long", "field", "x".repeat(65)).unwrap();
+    assert_eq!(object_encoding(&mut conn, "long"), "hashtable");
+
+    let result: redis::RedisResult<()> = redis::cmd("CONFIG")
+        .arg(&["SET", "hash-max-listpack-value", "nope"])
+        .query(&mut conn);
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("argument couldn't be parsed into an integer"));
+
+    let result: redis::RedisResult<i64> = conn.lpush("small", "a");
+    assert_eq!(result.unwrap_err().code(), Some("WRONGTYPE"));
+}
+
+#[test]
+fn test_hashes_survive_save_and_restart() {
+    let dir = std::env::temp_dir().join(format!("rustis-hashes-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let dir = dir.to_str().unwrap();
+    let args = vec!["--dir", dir, "--dbfilename", "hashes.rdb"];
+
+    let large: Vec<(String, String)> = (0..200)
+        .map(|i| (format!("field-{}", i), format!("value-{}", i)))
+        .collect();
+    {
+        let server = TestServer::start(Some(args.clone()));
+        let client = redis::Client::open(server.connection_string()).unwrap();
+        let mut conn = client.get_connection().unwrap();
+
+        let _: () = conn
+            .hset_multiple("small", &[("a", "1"), ("b", "-20"), ("c", "")])
+            .unwrap();
+        let _: () = conn.hset_multiple("large", &large).unwrap();
+        let _: () = redis::cmd("SAVE").query(&mut conn).unwrap();
+    }
+
+    let server = TestServer::start(Some(args));
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let small: Vec<String> = redis::cmd("HGETALL").arg("small").query(&mut conn).unwrap();
+    assert_eq!(small, vec!["a", "1", "b", "-20", "c", ""]);
+    assert_eq!(object_encoding(&mut conn, "small"), "listpack");
+
+    let loaded: HashMap<String, String> = conn.hgetall("large").unwrap();
+    assert_eq!(loaded, large.into_iter().collect());
+    assert_eq!(object_encoding(&mut conn, "large"), "hashtable");
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
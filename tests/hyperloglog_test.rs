@@ -0,0 +1,209 @@
+mod common;
+
+use common::{error, query, TestServer};
+
+/// Add the elements "prefix:0" to "prefix:count-1"
+fn add_elements(conn: &mut redis::Connection, key: &str, prefix: &str, count: usize) {
+    let elements: Vec<String> = (0..count).map(|i| format!("{}:{}", prefix, i)).collect();
+    for chunk in elements.chunks(1000) {
+        let _: i64 = redis::cmd("PFADD").arg(key).arg(chunk).query(conn).unwrap();
+    }
+}
+
+#[test]
+fn test_pfadd_and_pfcount() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    assert_eq!(query::<i64>(&mut conn, "PFCOUNT visitors"), 0);
+    assert_eq!(query::<i64>(&mut conn, "PFADD visitors a b c"), 1);
+    assert_eq!(query::<i64>(&mut conn, "PFADD visitors a b"), 0);
+    assert_eq!(query::<i64>(&mut conn, "PFCOUNT visitors"), 3);
+
+    // Creating a key without elements counts as an update
+    assert_eq!(query::<i64>(&mut conn, "PFADD empty"), 1);
+    assert_eq!(query::<i64>(&mut conn, "PFADD empty"), 0);
+    assert_eq!(query::<i64>(&mut conn, "PFCOUNT empty"), 0);
+    assert_eq!(query::<String>(&mut conn, "TYPE empty"), "string");
+
+    // The cardinality is cached in the header, and invalidated by changes
+    let header: Vec<u8> = query(&mut conn, "GETRANGE visitors 0 15");
+    assert_eq!(&header[..5], b"HYLL\x01");
+    assert_eq!(&header[8..], &[3, 0, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(query::<i64>(&mut conn, "PFADD visitors d"), 1);
+    let header: Vec<u8> = query(&mut conn, "GETRANGE visitors 0 15");
+    assert_eq!(header[15] & 0x80, 0x80);
+    assert_eq!(query::<i64>(&mut conn, "PFCOUNT visitors"), 4);
+
+    add_elements(&mut conn, "large", "user", 100_000);
+    let count: i64 = query(&mut conn, "PFCOUNT large");
+    assert!((count - 100_000).abs() < 2_000, "count {}", count);
+
+    add_elements(&mut conn, "small", "user", 100);
+    let count: i64 = query(&mut conn, "PFCOUNT small");
+    assert!((count - 100).abs() <= 2, "count {}", count);
+
+    // Counting multiple keys counts the union, which here is the same as the larger one
+    add_elements(&mut conn, "other", "user", 150);
+    let count: i64 = query(&mut conn, "PFCOUNT other");
+    assert!((count - 150).abs() <= 3, "count {}", count);
+    assert_eq!(
+        query::<i64>(&mut conn, "PFCOUNT small other missing"),
+        count
+    );
+}
+
+#[test]
+fn test_pfmerge() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let _: i64 = query(&mut conn, "PFADD monday alice bob carol");
+    let _: i64 = query(&mut conn, "PFADD tuesday carol dave");
+    let _: () = query(&mut conn, "PFMERGE week monday tuesday missing");
+    assert_eq!(query::<i64>(&mut conn, "PFCOUNT week"), 4);
+    assert_eq!(
+        query::<String>(&mut conn, "PFDEBUG ENCODING week"),
+        "sparse"
+    );
+
+    // The destination is merged in too
+    let _: i64 = query(&mut conn, "PFADD wednesday erin");
+    let _: () = query(&mut conn, "PFMERGE week wednesday");
+    assert_eq!(query::<i64>(&mut conn, "PFCOUNT week"), 5);
+    let _: () = query(&mut conn, "PFMERGE nothing");
+    assert_eq!(query::<i64>(&mut conn, "PFCOUNT nothing"), 0);
+
+    // Merging a dense HyperLogLog makes the destination dense
+    add_elements(&mut conn, "month", "user", 5_000);
+    assert_eq!(
+        query::<String>(&mut conn, "PFDEBUG ENCODING month"),
+        "dense"
+    );
+    let _: () = query(&mut conn, "PFMERGE week month");
+    assert_eq!(query::<String>(&mut conn, "PFDEBUG ENCODING week"), "dense");
+    let merged: i64 = query(&mut conn, "PFCOUNT week");
+    let union: i64 = query(&mut conn, "PFCOUNT month monday tuesday wednesday");
+    assert_eq!(merged, union);
+}
+
+#[test]
+fn test_pfdebug() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let _: i64 = query(&mut conn, "PFADD hll a b c");
+    assert_eq!(query::<String>(&mut conn, "PFDEBUG ENCODING hll"), "sparse");
+    let decoded: String = query(&mut conn, "PFDEBUG DECODE hll");
+    let registers: usize = decoded
+        .split(' ')
+        .map(|opcode| {
+            let (kind, rest) = opcode.split_once(':').unwrap();
+            match kind {
+                "z" | "Z" => rest.parse::<usize>().unwrap(),
+                _ => rest.split_once(',').unwrap().1.parse::<usize>().unwrap(),
+            }
+        })
+        .sum();
+    assert_eq!(registers, 16384);
+    assert_eq!(decoded.matches("v:").count(), 3);
+
+    assert_eq!(query::<i64>(&mut conn, "PFDEBUG TODENSE hll"), 1);
+    assert_eq!(query::<i64>(&mut conn, "PFDEBUG TODENSE hll"), 0);
+    assert_eq!(query::<String>(&mut conn, "PFDEBUG ENCODING hll"), "dense");
+    assert_eq!(query::<usize>(&mut conn, "STRLEN hll"), 12304);
+    assert_eq!(query::<i64>(&mut conn, "PFCOUNT hll"), 3);
+    let registers: Vec<i64> = query(&mut conn, "PFDEBUG GETREG hll");
+    assert_eq!(registers.len(), 16384);
+    assert_eq!(registers.iter().filter(|r| **r != 0).count(), 3);
+
+    let err = error(&mut conn, "PFDEBUG DECODE hll");
+    assert_eq!(err.detail(), Some("HLL encoding is not sparse"));
+    let err = error(&mut conn, "PFDEBUG ENCODING missing");
+    assert_eq!(err.detail(), Some("The specified key does not exist"));
+    let err = error(&mut conn, "PFDEBUG FOO hll");
+    assert_eq!(err.detail(), Some("Unknown PFDEBUG subcommand 'FOO'"));
+
+    assert_eq!(query::<String>(&mut conn, "PFSELFTEST"), "OK");
+}
+
+#[test]
+fn test_invalid_values() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let _: () = query(&mut conn, "SET plain hello");
+    let _: () = query(&mut conn, "SET number 12345");
+    let _: i64 = query(&mut conn, "LPUSH list a");
+    for command in [
+        "PFADD plain a",
+        "PFCOUNT number",
+        "PFMERGE dest plain",
+        "PFCOUNT list",
+    ] {
+        let err = error(&mut conn, command);
+        assert_eq!(err.code(), Some("WRONGTYPE"), "{}", command);
+    }
+    let err = error(&mut conn, "PFADD plain a");
+    assert_eq!(
+        err.detail(),
+        Some("Key is not a valid HyperLogLog string value.")
+    );
+
+    // A sparse HyperLogLog that doesn't cover every register
+    let _: i64 = query(&mut conn, "PFADD hll a");
+    let mut bytes: Vec<u8> = query(&mut conn, "GET hll");
+    bytes.truncate(bytes.len() - 1);
+    let _: () = redis::cmd("SET")
+        .arg("hll")
+        .arg(bytes)
+        .query(&mut conn)
+        .unwrap();
+    let err = error(&mut conn, "PFCOUNT hll");
+    assert_eq!(err.code(), Some("INVALIDOBJ"));
+    assert_eq!(err.detail(), Some("Corrupted HLL object detected"));
+}
+
+#[test]
+fn test_hyperloglogs_survive_save_and_restart() {
+    let dir = std::env::temp_dir().join(format!("rustis-hll-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let dir = dir.to_str().unwrap();
+    let args = vec!["--dir", dir, "--dbfilename", "hll.rdb"];
+
+    let (sparse, dense): (Vec<u8>, Vec<u8>) = {
+        let server = TestServer::start(Some(args.clone()));
+        let client = redis::Client::open(server.connection_string()).unwrap();
+        let mut conn = client.get_connection().unwrap();
+
+        add_elements(&mut conn, "sparse", "page", 50);
+        add_elements(&mut conn, "dense", "page", 20_000);
+        let _: () = query(&mut conn, "SAVE");
+        (
+            query(&mut conn, "GET sparse"),
+            query(&mut conn, "GET dense"),
+        )
+    };
+
+    let server = TestServer::start(Some(args));
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    assert_eq!(query::<Vec<u8>>(&mut conn, "GET sparse"), sparse);
+    assert_eq!(query::<Vec<u8>>(&mut conn, "GET dense"), dense);
+    assert_eq!(
+        query::<String>(&mut conn, "PFDEBUG ENCODING sparse"),
+        "sparse"
+    );
+    assert_eq!(
+        query::<String>(&mut conn, "PFDEBUG ENCODING dense"),
+        "dense"
+    );
+    assert_eq!(query::<i64>(&mut conn, "PFCOUNT sparse"), 50);
+    let count: i64 = query(&mut conn, "PFCOUNT dense");
+    assert!((count - 20_000).abs() < 400, "count {}", count);
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use common::{error_message, query, TestServer};
+use std::{thread, time::Duration};
+
+#[test]
+fn test_del() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let _: () = query(&mut conn, "SET a 1");
+    let _: i64 = query(&mut conn, "RPUSH b x");
+    let _: () = query(&mut conn, "SET c 1");
+    assert_eq!(query::<i64>(&mut conn, "DEL a b missing"), 2);
+    assert_eq!(query::<i64>(&mut conn, "UNLINK c"), 1);
+    assert_eq!(query::<i64>(&mut conn, "DEL a"), 0);
+    assert_eq!(
+        query::<Vec<String>>(&mut conn, "KEYS *"),
+        Vec::<String>::new()
+    );
+}
+
+#[test]
+fn test_expire_and_ttl() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let _: () = query(&mut conn, "SET foo bar");
+    assert_eq!(query::<i64>(&mut conn, "TTL foo"), -1);
+    assert_eq!(query::<i64>(&mut conn, "TTL missing"), -2);
+    assert_eq!(query::<i64>(&mut conn, "EXPIRE missing 100"), 0);
+
+    assert_eq!(query::<i64>(&mut conn, "EXPIRE foo 100"), 1);
+    assert_eq!(query::<i64>(&mut conn, "TTL foo"), 100);
+    let pttl: i64 = query(&mut conn, "PTTL foo");
+    assert!(pttl > 99_000 && pttl <= 100_000);
+
+    // The conditions compare against the current expiry
+    assert_eq!(query::<i64>(&mut conn, "EXPIRE foo 200 NX"), 0);
+    assert_eq!(query::<i64>(&mut conn, "EXPIRE foo 50 GT"), 0);
+    assert_eq!(query::<i64>(&mut conn, "EXPIRE foo 200 GT"), 1);
+    assert_eq!(query::<i64>(&mut conn, "EXPIRE foo 300 LT"), 0);
+    assert_eq!(query::<i64>(&mut conn, "EXPIRE foo 150 LT XX"), 1);
+    assert_eq!(query::<i64>(&mut conn, "TTL foo"), 150);
+
+    assert_eq!(query::<i64>(&mut conn, "PERSIST foo"), 1);
+    assert_eq!(query::<i64>(&mut conn, "PERSIST foo"), 0);
+    assert_eq!(query::<i64>(&mut conn, "TTL foo"), -1);
+    // Keys without an expiry have an infinite TTL, which nothing is greater than
+    assert_eq!(query::<i64>(&mut conn, "EXPIRE foo 100 XX"), 0);
+    assert_eq!(query::<i64>(&mut conn, "EXPIRE foo 100 GT"), 0);
+    assert_eq!(query::<i64>(&mut conn, "EXPIRE foo 100 LT"), 1);
+
+    let at = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        + 1000;
+    assert_eq!(query::<i64>(&mut conn, &format!("EXPIREAT foo {}", at)), 1);
+    let ttl: i64 = query(&mut conn, "TTL foo");
+    assert!(ttl > 998 && ttl <= 1000);
+
+    let _: () = query(&mut conn, "SET short lived");
+    assert_eq!(query::<i64>(&mut conn, "PEXPIRE short 50"), 1);
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(query::<i64>(&mut conn, "PTTL short"), -2);
+
+    // A time in the past deletes the key right away
+    assert_eq!(query::<i64>(&mut conn, "EXPIRE foo -1"), 1);
+    assert_eq!(query::<i64>(&mut conn, "TTL foo"), -2);
+    let _: () = query(&mut conn, "SET foo bar");
+    assert_eq!(query::<i64>(&mut conn, "PEXPIREAT foo 1"), 1);
+    assert_eq!(query::<Option<String>>(&mut conn, "GET foo"), None);
+
+    let _: () = query(&mut conn, "SET foo bar");
+    assert_eq!(
+        error_message(&mut conn, "EXPIRE foo 100 NX XX"),
+        "NX and XX, GT or LT options at the same time are not compatible"
+    );
+    assert_eq!(
+        error_message(&mut conn, "EXPIRE foo 100 GT LT"),
+        "GT and LT options at the same time are not compatible"
+    );
+    assert_eq!(
+        error_message(&mut conn, "EXPIRE foo 100 NOPE"),
+        "Unsupported option NOPE"
+    );
+    assert_eq!(
+        error_message(&mut conn, "EXPIRE foo ten"),
+        "value is not an integer or out of range"
+    );
+    assert_eq!(
+        error_message(&mut conn, "EXPIRE foo 9223372036854775807"),
+        "invalid expire time in 'expire' command"
+    );
+}
@@ -0,0 +1,215 @@
+mod common;
+
+use common::TestServer;
+use redis::Commands;
+
+#[test]
+fn test_push_pop_and_range() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let length: i64 = conn.rpush("list", &["b", "c"]).unwrap();
+    assert_eq!(length, 2);
+    let length: i64 = conn.lpush("list", &["a", "z"]).unwrap();
+    assert_eq!(length, 4);
+
+    let elements: Vec<String> = conn.lrange("list", 0, -1).unwrap();
+    assert_eq!(elements, vec!["z", "a", "b", "c"]);
+    let elements: Vec<String> = conn.lrange("list", -2, 100).unwrap();
+    assert_eq!(elements, vec!["b", "c"]);
+
+    let element: String = conn.lpop("list", None).unwrap();
+    assert_eq!(element, "z");
+    let elements: Vec<String> = conn.rpop("list", std::num::NonZeroUsize::new(2)).unwrap();
+    assert_eq!(elements, vec!["c", "b"]);
+
+    let element: String = conn.lindex("list", -1).unwrap();
+    assert_eq!(element, "a");
+    let length: i64 = conn.llen("list").unwrap();
+    assert_eq!(length, 1);
+
+    // Popping the last element removes the key
+    let _: String = conn.lpop("list", None).unwrap();
+    let exists: String = redis::cmd("TYPE").arg("list").query(&mut conn).unwrap();
+    assert_eq!(exists, "none");
+
+    // The X variants only push to existing lists
+    let length: i64 = conn.lpush_exists("list", "a").unwrap();
+    assert_eq!(length, 0);
+
+    let missing: Option<String> = conn.lpop("list", None).unwrap();
+    assert_eq!(missing, None);
+    let missing: redis::Value = redis::cmd("LPOP")
+        .arg("list")
+        .arg(2)
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(missing, redis::Value::Nil);
+}
+
+#[test]
+fn test_lset_linsert_lrem_and_ltrim() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let _: i64 = conn.rpush("list", &["a", "b", "a", "c", "a"]).unwrap();
+
+    let _: () = conn.lset("list", 1, "B").unwrap();
+    let result: redis::RedisResult<()> = conn.lset("list", 10, "x");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "An error was signalled by the server - ResponseError: index out of range"
+    );
+
+    let length: i64 = conn.linsert_before("list", "c", "before-c").unwrap();
+    assert_eq!(length, 6);
+    let length: i64 = conn.linsert_after("list", "missing", "x").unwrap();
+    assert_eq!(length, -1);
+
+    let removed: i64 = conn.lrem("list", -2, "a").unwrap();
+    assert_eq!(removed, 2);
+    let elements: Vec<String> = conn.lrange("list", 0, -1).unwrap();
+    assert_eq!(elements, vec!["a", "B", "before-c", "c"]);
+
+    let _: () = conn.ltrim("list", 1, -2).unwrap();
+    let elements: Vec<String> = conn.lrange("list", 0, -1).unwrap();
+    assert_eq!(elements, vec!["B", "before-c"]);
+
+    // Trimming to an empty range removes the key
+    let _: () = conn.ltrim("list", 5, 10).unwrap();
+    let length: i64 = conn.llen("list").unwrap();
+    assert_eq!(length, 0);
+}
+
+#[test]
+fn test_lmove_and_lpos() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let _: i64 = conn.rpush("source", &["a", "b", "c"]).unwrap();
+
+    let element: String = conn
+        .lmove(
+            "source",
+            "dest",
+            redis::Direction::Left,
+            redis::Direction::Right,
+        )
+        .unwrap();
+    assert_eq!(element, "a");
+    let element: String = conn.rpoplpush("source", "dest").unwrap();
+    assert_eq!(element, "c");
+    let elements: Vec<String> = conn.lrange("dest", 0, -1).unwrap();
+    assert_eq!(elements, vec!["c", "a"]);
+
+    // Rotating a list in place
+    let element: String = conn
+        .lmove(
+            "dest",
+            "dest",
+            redis::Direction::Left,
+            redis::Direction::Right,
+        )
+        .unwrap();
+    assert_eq!(element, "c");
+    let elements: Vec<String> = conn.lrange("dest", 0, -1).unwrap();
+    assert_eq!(elements, vec!["a", "c"]);
+
+    let _: i64 = conn.rpush("pos", &["a", "b", "c", "b", "b"]).unwrap();
+    let index: Option<i64> = redis::cmd("LPOS")
+        .arg("pos")
+        .arg("b")
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(index, Some(1));
+    let indexes: Vec<i64> = redis::cmd("LPOS")
+        .arg(&["pos", "b", "RANK", "-1", "COUNT", "2"])
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(indexes, vec![4, 3]);
+    let indexes: Vec<i64> = redis::cmd("LPOS")
+        .arg(&["pos", "b", "COUNT", "0", "MAXLEN", "2"])
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(indexes, vec![1]);
+    let index: Option<i64> = redis::cmd("LPOS")
+        .arg("pos")
+        .arg("z")
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(index, None);
+
+    let result: redis::RedisResult<Option<i64>> = redis::cmd("LPOS")
+        .arg(&["pos", "b", "RANK", "0"])
+        .query(&mut conn);
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("RANK can't be zero"));
+}
+
+#[test]
+fn test_wrong_type_errors() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let _: i64 = conn.rpush("list", "a").unwrap();
+    let _: () = conn.set("string", "value").unwrap();
+
+    let result: redis::RedisResult<String> = conn.get("list");
+    let error = result.unwrap_err();
+    assert_eq!(error.code(), Some("WRONGTYPE"));
+
+    let result: redis::RedisResult<i64> = conn.lpush("string", "a");
+    assert_eq!(result.unwrap_err().code(), Some("WRONGTYPE"));
+
+    // A failed move doesn't lose the element
+    let result: redis::RedisResult<String> = conn.rpoplpush("list", "string");
+    assert_eq!(result.unwrap_err().code(), Some("WRONGTYPE"));
+    let length: i64 = conn.llen("list").unwrap();
+    assert_eq!(length, 1);
+
+    // MGET returns nulls for other types, and SET overwrites them
+    let values: Vec<Option<String>> = conn.mget(&["string", "list"]).unwrap();
+    assert_eq!(values, vec![Some("value".to_string()), None]);
+    let _: () = conn.set("list", "now a string").unwrap();
+    let value: String = conn.get("list").unwrap();
+    assert_eq!(value, "now a string");
+}
+
+#[test]
+fn test_lists_survive_save_and_restart() {
+    let dir = std::env::temp_dir().join(format!("rustis-lists-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let dir = dir.to_str().unwrap();
+    let args = vec!["--dir", dir, "--dbfilename", "lists.rdb"];
+
+    let elements: Vec<String> = (0..300).map(|i| format!("element-{}", i)).collect();
+    {
+        let server = TestServer::start(Some(args.clone()));
+        let client = redis::Client::open(server.connection_string()).unwrap();
+        let mut conn = client.get_connection().unwrap();
+
+        let _: i64 = conn.rpush("list", &elements).unwrap();
+        let _: i64 = conn.rpush("short", &["1", "two", "-3"]).unwrap();
+        let _: () = conn.set("string", "value").unwrap();
+        let _: () = redis::cmd("SAVE").query(&mut conn).unwrap();
+    }
+
+    let server = TestServer::start(Some(args));
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let loaded: Vec<String> = conn.lrange("list", 0, -1).unwrap();
+    assert_eq!(loaded, elements);
+    let loaded: Vec<String> = conn.lrange("short", 0, -1).unwrap();
+    assert_eq!(loaded, vec!["1", "two", "-3"]);
+    let value: String = conn.get("string").unwrap();
+    assert_eq!(value, "value");
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,62 @@
+mod common;
+
+use common::TestServer;
+use redis::Commands;
+
+#[test]
+fn test_mset_and_mget() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let _: () = conn
+        .mset(&[("key1", "value1"), ("key2", "value2")])
+        .unwrap();
+
+    let result: Vec<Option<String>> = conn.mget(&["key1", "missing", "key2"]).unwrap();
+    assert_eq!(
+        result,
+        vec![Some("value1".to_string()), None, Some("value2".to_string())]
+    );
+}
+
+#[test]
+fn test_msetnx_is_all_or_nothing() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let result: bool = conn
+        .mset_nx(&[("key1", "value1"), ("key2", "value2")])
+        .unwrap();
+    assert!(result);
+
+    let result: bool = conn
+        .mset_nx(&[("key2", "other"), ("key3", "value3")])
+        .unwrap();
+    assert!(!result);
+
+    let result: Vec<Option<String>> = conn.mget(&["key1", "key2", "key3"]).unwrap();
+    assert_eq!(
+        result,
+        vec![Some("value1".to_string()), Some("value2".to_string()), None]
+    );
+}
+
+#[test]
+fn test_mset_and_mget_larger_than_read_buffer() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let value = "x".repeat(1024);
+    let pairs: Vec<(String, String)> = (0..500)
+        .map(|i| (format!("key{i}"), value.clone()))
+        .collect();
+    let _: () = conn.mset(&pairs).unwrap();
+
+    let keys: Vec<String> = pairs.iter().map(|(k, _)| k.clone()).collect();
+    let result: Vec<String> = conn.mget(&keys).unwrap();
+    assert_eq!(result.len(), 500);
+    assert!(result.iter().all(|v| *v == value));
+}
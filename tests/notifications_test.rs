@@ -0,0 +1,489 @@
+mod common;
+
+use common::{error, query, TestServer};
+use std::{thread, time::Duration};
+
+/// Subscribe to every keyspace and keyevent channel, as well as a marker channel that's published
+/// to after the commands under test, so that we know no more events are coming
+fn subscribe(pubsub: &mut redis::PubSub) {
+    pubsub
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    pubsub.psubscribe("__key*__:*").unwrap();
+    pubsub.subscribe("marker").unwrap();
+}
+
+/// Collect the (channel, payload) of the messages received until the marker
+fn events_until_marker(
+    conn: &mut redis::Connection,
+    pubsub: &mut redis::PubSub,
+) -> Vec<(String, String)> {
+    // Make sure the subscriptions are in place before the marker is published
+    while query::<i64>(conn, "PUBLISH marker done") == 0 {
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let mut events = Vec::new();
+    loop {
+        let message = pubsub.get_message().unwrap();
+        let channel = message.get_channel_name().to_string();
+        if channel == "marker" {
+            return events;
+        }
+        events.push((channel, message.get_payload().unwrap()));
+    }
+}
+
+fn event(channel: &str, payload: &str) -> (String, String) {
+    (channel.to_string(), payload.to_string())
+}
+
+#[test]
+fn test_notify_keyspace_events_config() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let config: Vec<String> = query(&mut conn, "CONFIG GET notify-keyspace-events");
+    assert_eq!(config, vec!["notify-keyspace-events", ""]);
+
+    for (flags, expected) in [("KEA", "AKE"), ("xEg$", "g$xE"), ("Kn", "Kn"), ("", "")] {
+        let _: () = redis::cmd("CONFIG")
+            .arg("SET")
+            .arg("notify-keyspace-events")
+            .arg(flags)
+            .query(&mut conn)
+            .unwrap();
+        let config: Vec<String> = query(&mut conn, "CONFIG GET notify-keyspace-events");
+        assert_eq!(config[1], expected);
+    }
+
+    let err = error(&mut conn, "CONFIG SET notify-keyspace-events KEq");
+    assert_eq!(
+        err.detail(),
+        Some(
+            "CONFIG SET failed (possibly related to argument 'notify-keyspace-events') - Invalid \
+             event class character. Use 'Ag$lshzxeKEtmn'."
+        )
+    );
+}
+
+#[test]
+fn test_string_and_generic_events() {
+    let server = TestServer::start(Some(vec!["--notify-keyspace-events", "KEA"]));
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+    let mut subscriber = client.get_connection().unwrap();
+    let mut subscriber = subscriber.as_pubsub();
+    subscribe(&mut subscriber);
+
+    let _: () = query(&mut conn, "SET foo bar EX 100");
+    let _: i64 = query(&mut conn, "INCR counter");
+    let _: Option<String> = query(&mut conn, "GETEX foo PERSIST");
+    let _: Option<String> = query(&mut conn, "GETDEL foo");
+    // Nothing happens for reads or for keys that don't exist
+    let _: Option<String> = query(&mut conn, "GET counter");
+    let _: Option<String> = query(&mut conn, "GETDEL foo");
+
+    assert_eq!(
+        events_until_marker(&mut conn, &mut subscriber),
+        vec![
+            event("__keyspace@0__:foo", "set"),
+            event("__keyevent@0__:set", "foo"),
+            event("__keyspace@0__:foo", "expire"),
+            event("__keyevent@0__:expire", "foo"),
+            event("__keyspace@0__:counter", "incrby"),
+            event("__keyevent@0__:incrby", "counter"),
+            event("__keyspace@0__:foo", "persist"),
+            event("__keyevent@0__:persist", "foo"),
+            event("__keyspace@0__:foo", "del"),
+            event("__keyevent@0__:del", "foo"),
+        ]
+    );
+}
+
+#[test]
+fn test_expired_and_new_events() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+    let mut subscriber = client.get_connection().unwrap();
+    let mut subscriber = subscriber.as_pubsub();
+    subscribe(&mut subscriber);
+
+    // Only key events for expired and new keys
+    let _: () = query(&mut conn, "CONFIG SET notify-keyspace-events Exn");
+    let _: () = query(&mut conn, "SET short lived PX 50");
+    let _: () = query(&mut conn, "SET short again");
+    thread::sleep(Duration::from_millis(100));
+    let _: Option<String> = query(&mut conn, "GET short");
+
+    let _: i64 = query(&mut conn, "HSETEX fields PX 50 FIELDS 2 a 1 b 2");
+    thread::sleep(Duration::from_millis(100));
+    let _: i64 = query(&mut conn, "HLEN fields");
+
+    assert_eq!(
+        events_until_marker(&mut conn, &mut subscriber),
+        vec![
+            event("__keyevent@0__:new", "short"),
+            event("__keyevent@0__:new", "fields"),
+        ]
+    );
+
+    let _: () = query(&mut conn, "CONFIG SET notify-keyspace-events KEA");
+    let _: () = query(&mut conn, "SET short lived PX 50");
+    thread::sleep(Duration::from_millis(100));
+    let _: Option<String> = query(&mut conn, "GET short");
+
+    assert_eq!(
+        events_until_marker(&mut conn, &mut subscriber),
+        vec![
+            event("__keyspace@0__:short", "set"),
+            event("__keyevent@0__:set", "short"),
+            event("__keyspace@0__:short", "expire"),
+            event("__keyevent@0__:expire", "short"),
+            event("__keyspace@0__:short", "expired"),
+            event("__keyevent@0__:expired", "short"),
+        ]
+    );
+}
+
+#[test]
+fn test_hash_field_expiry_events() {
+    let server = TestServer::start(Some(vec!["--notify-keyspace-events", "Egh"]));
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+    let mut subscriber = client.get_connection().unwrap();
+    let mut subscriber = subscriber.as_pubsub();
+    subscribe(&mut subscriber);
+
+    let _: i64 = query(&mut conn, "HSET fields a 1 b 2");
+    let _: Vec<i64> = query(&mut conn, "HPEXPIRE fields 50 FIELDS 1 a");
+    thread::sleep(Duration::from_millis(100));
+    let _: i64 = query(&mut conn, "HLEN fields");
+    let _: Vec<i64> = query(&mut conn, "HPEXPIRE fields 50 FIELDS 1 b");
+    thread::sleep(Duration::from_millis(100));
+    let _: i64 = query(&mut conn, "HLEN fields");
+
+    assert_eq!(
+        events_until_marker(&mut conn, &mut subscriber),
+        vec![
+            event("__keyevent@0__:hset", "fields"),
+            event("__keyevent@0__:hexpire", "fields"),
+            event("__keyevent@0__:hexpired", "fields"),
+            event("__keyevent@0__:hexpire", "fields"),
+            event("__keyevent@0__:hexpired", "fields"),
+            event("__keyevent@0__:del", "fields"),
+        ]
+    );
+}
+
+#[test]
+fn test_key_events() {
+    let server = TestServer::start(Some(vec!["--notify-keyspace-events", "E$g"]));
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+    let mut subscriber = client.get_connection().unwrap();
+    let mut subscriber = subscriber.as_pubsub();
+    subscribe(&mut subscriber);
+
+    let _: () = query(&mut conn, "SET foo bar");
+    let _: i64 = query(&mut conn, "EXPIRE foo 100");
+    let _: i64 = query(&mut conn, "PERSIST foo");
+    let _: i64 = query(&mut conn, "DEL foo missing");
+    let _: () = query(&mut conn, "SET foo bar");
+    let _: i64 = query(&mut conn, "PEXPIREAT foo 1");
+    let _: i64 = query(&mut conn, "SETBIT bits 7 1");
+    let _: Vec<i64> = query(&mut conn, "BITFIELD bits GET u8 0");
+    let _: Vec<i64> = query(&mut conn, "BITFIELD bits SET u8 0 255");
+    let _: i64 = query(&mut conn, "BITOP NOT inverted bits");
+    let _: i64 = query(&mut conn, "BITOP AND inverted missing");
+    // Nothing happens for keys that don't exist or don't change
+    let _: i64 = query(&mut conn, "EXPIRE missing 100");
+    let _: i64 = query(&mut conn, "PERSIST bits");
+
+    assert_eq!(
+        events_until_marker(&mut conn, &mut subscriber),
+        vec![
+            event("__keyevent@0__:set", "foo"),
+            event("__keyevent@0__:expire", "foo"),
+            event("__keyevent@0__:persist", "foo"),
+            event("__keyevent@0__:del", "foo"),
+            event("__keyevent@0__:set", "foo"),
+            event("__keyevent@0__:del", "foo"),
+            event("__keyevent@0__:setbit", "bits"),
+            event("__keyevent@0__:setbit", "bits"),
+            event("__keyevent@0__:set", "inverted"),
+            event("__keyevent@0__:del", "inverted"),
+        ]
+    );
+}
+
+#[test]
+fn test_active_expiry_events() {
+    let server = TestServer::start(Some(vec!["--notify-keyspace-events", "Exgh"]));
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+    let mut subscriber = client.get_connection().unwrap();
+    let mut subscriber = subscriber.as_pubsub();
+    subscribe(&mut subscriber);
+
+    // The keys and hash fields are evicted without anyone reading them
+    let _: () = query(&mut conn, "SET a 1 PX 50");
+    let _: () = query(&mut conn, "SET b 2 PX 50");
+    let _: () = query(&mut conn, "SET c 3");
+    let _: i64 = query(&mut conn, "HSETEX gone PX 50 FIELDS 1 f 1");
+    let _: i64 = query(&mut conn, "HSET kept f 1 g 2");
+    let _: Vec<i64> = query(&mut conn, "HPEXPIRE kept 50 FIELDS 1 f");
+    thread::sleep(Duration::from_millis(500));
+
+    let mut events = events_until_marker(&mut conn, &mut subscriber);
+    events.sort();
+    assert_eq!(
+        events,
+        vec![
+            event("__keyevent@0__:del", "gone"),
+            event("__keyevent@0__:expire", "a"),
+            event("__keyevent@0__:expire", "b"),
+            event("__keyevent@0__:expired", "a"),
+            event("__keyevent@0__:expired", "b"),
+            event("__keyevent@0__:hexpire", "gone"),
+            event("__keyevent@0__:hexpire", "kept"),
+            event("__keyevent@0__:hexpired", "gone"),
+            event("__keyevent@0__:hexpired", "kept"),
+            event("__keyevent@0__:hset", "gone"),
+            event("__keyevent@0__:hset", "kept"),
+        ]
+    );
+    let fields: Vec<String> = query(&mut conn, "HKEYS kept");
+    assert_eq!(fields, ["g"]);
+}
+
+#[test]
+fn test_list_events() {
+    let server = TestServer::start(Some(vec!["--notify-keyspace-events", "Elg"]));
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+    let mut subscriber = client.get_connection().unwrap();
+    let mut subscriber = subscriber.as_pubsub();
+    subscribe(&mut subscriber);
+
+    let _: i64 = query(&mut conn, "RPUSH list a b c d");
+    let _: i64 = query(&mut conn, "LPUSH list z");
+    let _: () = query(&mut conn, "LSET list 0 y");
+    let _: i64 = query(&mut conn, "LINSERT list BEFORE b x");
+    let _: i64 = query(&mut conn, "LREM list 0 x");
+    let _: () = query(&mut conn, "LTRIM list 0 3");
+    let _: String = query(&mut conn, "LMOVE list other LEFT RIGHT");
+    let _: String = query(&mut conn, "RPOP list");
+    let _: Vec<String> = query(&mut conn, "LPOP list 5");
+    // Nothing happens for lists that don't exist or don't change
+    let _: i64 = query(&mut conn, "LPUSHX missing a");
+    let _: i64 = query(&mut conn, "LREM other 0 missing");
+
+    assert_eq!(
+        events_until_marker(&mut conn, &mut subscriber),
+        vec![
+            event("__keyevent@0__:rpush", "list"),
+            event("__keyevent@0__:lpush", "list"),
+            event("__keyevent@0__:lset", "list"),
+            event("__keyevent@0__:linsert", "list"),
+            event("__keyevent@0__:lrem", "list"),
+            event("__keyevent@0__:ltrim", "list"),
+            event("__keyevent@0__:lpop", "list"),
+            event("__keyevent@0__:rpush", "other"),
+            event("__keyevent@0__:rpop", "list"),
+            event("__keyevent@0__:lpop", "list"),
+            event("__keyevent@0__:del", "list"),
+        ]
+    );
+}
+
+#[test]
+fn test_hash_events() {
+    let server = TestServer::start(Some(vec!["--notify-keyspace-events", "Ehg"]));
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+    let mut subscriber = client.get_connection().unwrap();
+    let mut subscriber = subscriber.as_pubsub();
+    subscribe(&mut subscriber);
+
+    let _: i64 = query(&mut conn, "HSET hash a 1 b 2");
+    let _: i64 = query(&mut conn, "HSETNX hash c 3");
+    let _: i64 = query(&mut conn, "HINCRBY hash a 1");
+    let _: String = query(&mut conn, "HINCRBYFLOAT hash b 0.5");
+    let _: Vec<i64> = query(&mut conn, "HEXPIRE hash 100 FIELDS 1 a");
+    let _: Vec<i64> = query(&mut conn, "HPERSIST hash FIELDS 1 a");
+    let _: i64 = query(&mut conn, "HDEL hash a b c");
+    // Nothing happens for fields that don't change
+    let _: i64 = query(&mut conn, "HSETNX other a 1");
+    let _: i64 = query(&mut conn, "HSETNX other a 2");
+    let _: i64 = query(&mut conn, "HDEL other missing");
+
+    assert_eq!(
+        events_until_marker(&mut conn, &mut subscriber),
+        vec![
+            event("__keyevent@0__:hset", "hash"),
+            event("__keyevent@0__:hset", "hash"),
+            event("__keyevent@0__:hincrby", "hash"),
+            event("__keyevent@0__:hincrbyfloat", "hash"),
+            event("__keyevent@0__:hexpire", "hash"),
+            event("__keyevent@0__:hpersist", "hash"),
+            event("__keyevent@0__:hdel", "hash"),
+            event("__keyevent@0__:del", "hash"),
+            event("__keyevent@0__:hset", "other"),
+        ]
+    );
+}
+
+#[test]
+fn test_set_events() {
+    let server = TestServer::start(Some(vec!["--notify-keyspace-events", "Esg"]));
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+    let mut subscriber = client.get_connection().unwrap();
+    let mut subscriber = subscriber.as_pubsub();
+    subscribe(&mut subscriber);
+
+    let _: i64 = query(&mut conn, "SADD set a b c d");
+    let _: i64 = query(&mut conn, "SREM set d");
+    let _: i64 = query(&mut conn, "SMOVE set other a");
+    let _: i64 = query(&mut conn, "SINTERSTORE inter set other");
+    let _: i64 = query(&mut conn, "SUNIONSTORE union set other");
+    let _: i64 = query(&mut conn, "SDIFFSTORE union set set");
+    let _: Vec<String> = query(&mut conn, "SPOP set 2");
+    // Nothing happens for members that don't change
+    let _: i64 = query(&mut conn, "SADD other a");
+    let _: i64 = query(&mut conn, "SREM other missing");
+
+    assert_eq!(
+        events_until_marker(&mut conn, &mut subscriber),
+        vec![
+            event("__keyevent@0__:sadd", "set"),
+            event("__keyevent@0__:srem", "set"),
+            event("__keyevent@0__:srem", "set"),
+            event("__keyevent@0__:sadd", "other"),
+            event("__keyevent@0__:sunionstore", "union"),
+            event("__keyevent@0__:del", "union"),
+            event("__keyevent@0__:spop", "set"),
+            event("__keyevent@0__:del", "set"),
+        ]
+    );
+}
+
+#[test]
+fn test_sorted_set_events() {
+    let server = TestServer::start(Some(vec!["--notify-keyspace-events", "Ezg"]));
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+    let mut subscriber = client.get_connection().unwrap();
+    let mut subscriber = subscriber.as_pubsub();
+    subscribe(&mut subscriber);
+
+    let _: i64 = query(&mut conn, "ZADD zset 1 a 2 b 3 c 4 d 5 e 6 f 7 g");
+    let _: String = query(&mut conn, "ZINCRBY zset 10 a");
+    let _: i64 = query(&mut conn, "ZREM zset g");
+    let _: i64 = query(&mut conn, "ZREMRANGEBYSCORE zset 2 2");
+    let _: i64 = query(&mut conn, "ZREMRANGEBYRANK zset 0 0");
+    let _: i64 = query(&mut conn, "ZREMRANGEBYLEX zset [d [d");
+    let _: i64 = query(&mut conn, "ZUNIONSTORE union 1 zset");
+    let _: i64 = query(&mut conn, "ZINTERSTORE inter 2 zset union");
+    let _: i64 = query(&mut conn, "ZDIFFSTORE inter 2 zset union");
+    let _: Vec<String> = query(&mut conn, "ZPOPMIN zset");
+    let _: Vec<String> = query(&mut conn, "ZPOPMAX zset 2");
+    // Nothing happens for members that don't change
+    let _: i64 = query(&mut conn, "ZADD union 5 e");
+    let _: i64 = query(&mut conn, "ZREM union missing");
+
+    assert_eq!(
+        events_until_marker(&mut conn, &mut subscriber),
+        vec![
+            event("__keyevent@0__:zadd", "zset"),
+            event("__keyevent@0__:zincr", "zset"),
+            event("__keyevent@0__:zrem", "zset"),
+            event("__keyevent@0__:zremrangebyscore", "zset"),
+            event("__keyevent@0__:zremrangebyrank", "zset"),
+            event("__keyevent@0__:zremrangebylex", "zset"),
+            event("__keyevent@0__:zunionstore", "union"),
+            event("__keyevent@0__:zinterstore", "inter"),
+            event("__keyevent@0__:del", "inter"),
+            event("__keyevent@0__:zpopmin", "zset"),
+            event("__keyevent@0__:zpopmax", "zset"),
+            event("__keyevent@0__:del", "zset"),
+        ]
+    );
+}
+
+#[test]
+fn test_stream_events() {
+    let server = TestServer::start(Some(vec!["--notify-keyspace-events", "Etg"]));
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+    let mut subscriber = client.get_connection().unwrap();
+    let mut subscriber = subscriber.as_pubsub();
+    subscribe(&mut subscriber);
+
+    let _: String = query(&mut conn, "XADD stream 1-1 a 1");
+    let _: String = query(&mut conn, "XADD stream 2-1 b 2");
+    let _: String = query(&mut conn, "XADD stream MAXLEN 2 3-1 c 3");
+    let _: i64 = query(&mut conn, "XDEL stream 2-1");
+    let _: i64 = query(&mut conn, "XTRIM stream MAXLEN 0");
+    let _: () = query(&mut conn, "XGROUP CREATE stream group $");
+    let _: () = query(&mut conn, "XGROUP SETID stream group 0");
+    let _: i64 = query(&mut conn, "XGROUP CREATECONSUMER stream group alice");
+    let _: i64 = query(&mut conn, "XGROUP DELCONSUMER stream group alice");
+    let _: i64 = query(&mut conn, "XGROUP DESTROY stream group");
+    // Nothing happens for entries and groups that don't change
+    let _: i64 = query(&mut conn, "XDEL stream 2-1");
+    let _: i64 = query(&mut conn, "XTRIM stream MAXLEN 1");
+    let _: i64 = query(&mut conn, "XGROUP DESTROY stream group");
+
+    assert_eq!(
+        events_until_marker(&mut conn, &mut subscriber),
+        vec![
+            event("__keyevent@0__:xadd", "stream"),
+            event("__keyevent@0__:xadd", "stream"),
+            event("__keyevent@0__:xadd", "stream"),
+            event("__keyevent@0__:xtrim", "stream"),
+            event("__keyevent@0__:xdel", "stream"),
+            event("__keyevent@0__:xtrim", "stream"),
+            event("__keyevent@0__:xgroup-create", "stream"),
+            event("__keyevent@0__:xgroup-setid", "stream"),
+            event("__keyevent@0__:xgroup-createconsumer", "stream"),
+            event("__keyevent@0__:xgroup-delconsumer", "stream"),
+            event("__keyevent@0__:xgroup-destroy", "stream"),
+        ]
+    );
+}
+
+#[test]
+fn test_keymiss_events() {
+    let server = TestServer::start(Some(vec!["--notify-keyspace-events", "KEm"]));
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+    let mut subscriber = client.get_connection().unwrap();
+    let mut subscriber = subscriber.as_pubsub();
+    subscribe(&mut subscriber);
+
+    let _: () = query(&mut conn, "SET foo bar");
+    let _: Option<String> = query(&mut conn, "GET foo");
+    let _: Option<String> = query(&mut conn, "GET missing");
+    let _: Option<String> = query(&mut conn, "HGET hash field");
+    let _: Vec<String> = query(&mut conn, "LRANGE list 0 -1");
+    // Lookups by commands that write the key aren't misses
+    let _: i64 = query(&mut conn, "INCR counter");
+    let _: i64 = query(&mut conn, "RPUSH list a");
+    let _: i64 = query(&mut conn, "HSET hash field value");
+
+    assert_eq!(
+        events_until_marker(&mut conn, &mut subscriber),
+        vec![
+            event("__keyspace@0__:missing", "keymiss"),
+            event("__keyevent@0__:keymiss", "missing"),
+            event("__keyspace@0__:hash", "keymiss"),
+            event("__keyevent@0__:keymiss", "hash"),
+            event("__keyspace@0__:list", "keymiss"),
+            event("__keyevent@0__:keymiss", "list"),
+        ]
+    );
+}
@@ -0,0 +1,94 @@
+mod common;
+
+use common::TestServer;
+use redis::Value;
+use std::{
+    io::{Read, Write},
+    net::{IpAddr, TcpStream, UdpSocket},
+};
+
+/// A non-loopback address of this host, which is the source of the route to other hosts
+fn local_address() -> Option<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("198.51.100.1:9").ok()?;
+    let ip = socket.local_addr().ok()?.ip();
+    (!ip.is_loopback() && !ip.is_unspecified()).then_some(ip)
+}
+
+/// Send a command to the server through an address of this host that isn't the loopback one,
+/// returning the raw reply, if the host has such an address
+fn send_externally(server: &TestServer, command: &[u8]) -> Option<String> {
+    let port: u16 = server
+        .connection_string()
+        .rsplit(':')
+        .next()?
+        .parse()
+        .ok()?;
+    let mut stream = TcpStream::connect((local_address()?, port)).unwrap();
+    stream.write_all(command).unwrap();
+    let mut buf = [0; 4096];
+    let n = stream.read(&mut buf).unwrap();
+    Some(String::from_utf8_lossy(&buf[..n]).into_owned())
+}
+
+fn ping(conn: &mut redis::Connection) -> redis::RedisResult<String> {
+    redis::cmd("PING").query(conn)
+}
+
+#[test]
+fn test_protected_mode() {
+    let server = TestServer::start(Some(vec!["--host", "0.0.0.0"]));
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    // Local clients are always accepted
+    assert_eq!(ping(&mut conn).unwrap(), "PONG");
+    let mode: Vec<String> = redis::cmd("CONFIG")
+        .arg("GET")
+        .arg("protected-mode")
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(mode, ["protected-mode", "yes"]);
+
+    if let Some(reply) = send_externally(&server, b"*1\r\n$4\r\nPING\r\n") {
+        assert!(reply.starts_with("-DENIED Redis is running in protected mode"));
+        assert!(reply.ends_with("accepting connections from the outside.\r\n"));
+    }
+
+    // Clients are accepted once the default user has a password
+    let _: () = redis::cmd("CONFIG")
+        .arg("SET")
+        .arg("requirepass")
+        .arg("s3cret")
+        .query(&mut conn)
+        .unwrap();
+    if let Some(reply) = send_externally(&server, b"*2\r\n$4\r\nAUTH\r\n$6\r\ns3cret\r\n") {
+        assert_eq!(reply, "+OK\r\n");
+    }
+
+    // Or when protected mode is disabled
+    let _: () = redis::cmd("CONFIG")
+        .arg("SET")
+        .arg("requirepass")
+        .arg("")
+        .arg("protected-mode")
+        .arg("no")
+        .query(&mut conn)
+        .unwrap();
+    if let Some(reply) = send_externally(&server, b"*1\r\n$4\r\nPING\r\n") {
+        assert_eq!(reply, "+PONG\r\n");
+    }
+
+    let result: redis::RedisResult<Value> = redis::cmd("CONFIG")
+        .arg("SET")
+        .arg("protected-mode")
+        .arg("maybe")
+        .query(&mut conn);
+    assert_eq!(
+        result.unwrap_err().detail(),
+        Some(
+            "CONFIG SET failed (possibly related to argument 'protected-mode') - argument must \
+             be 'yes' or 'no'"
+        )
+    );
+}
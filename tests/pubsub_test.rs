@@ -0,0 +1,245 @@
+mod common;
+
+use common::{connect, expect, query, send, TestServer};
+use std::{io::Read, thread, time::Duration};
+
+#[test]
+fn test_publish_to_channels_and_patterns() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+    let mut subscriber = client.get_connection().unwrap();
+    let mut subscriber = subscriber.as_pubsub();
+    subscriber
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    subscriber.subscribe("news.tech").unwrap();
+    subscriber.psubscribe("news.*").unwrap();
+    // Make sure the subscriptions are in place before publishing
+    while query::<i64>(&mut conn, "PUBSUB NUMPAT") == 0 {
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    assert_eq!(query::<i64>(&mut conn, "PUBLISH news.tech hello"), 2);
+    assert_eq!(query::<i64>(&mut conn, "PUBLISH news.sport goal"), 1);
+    assert_eq!(query::<i64>(&mut conn, "PUBLISH weather rain"), 0);
+
+    let message = subscriber.get_message().unwrap();
+    assert_eq!(message.get_channel_name(), "news.tech");
+    assert_eq!(message.get_payload::<String>().unwrap(), "hello");
+    assert!(!message.from_pattern());
+
+    let message = subscriber.get_message().unwrap();
+    assert_eq!(message.get_channel_name(), "news.tech");
+    assert_eq!(message.get_pattern::<String>().unwrap(), "news.*");
+    assert_eq!(message.get_payload::<String>().unwrap(), "hello");
+
+    let message = subscriber.get_message().unwrap();
+    assert_eq!(message.get_channel_name(), "news.sport");
+    assert_eq!(message.get_payload::<String>().unwrap(), "goal");
+}
+
+#[test]
+fn test_subscribed_mode() {
+    let server = TestServer::start(None);
+    let mut stream = connect(&server);
+
+    send(
+        &mut stream,
+        "SUBSCRIBE a b",
+        b"*3\r\n$9\r\nsubscribe\r\n$1\r\na\r\n:1\r\n*3\r\n$9\r\nsubscribe\r\n$1\r\nb\r\n:2\r\n",
+    );
+    send(
+        &mut stream,
+        "PSUBSCRIBE c*",
+        b"*3\r\n$10\r\npsubscribe\r\n$2\r\nc*\r\n:3\r\n",
+    );
+
+    // Only a few commands are allowed while subscribed
+    send(
+        &mut stream,
+        "GET a",
+        b"-ERR Can't execute 'get': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET \
+          are allowed in this context\r\n",
+    );
+    send(&mut stream, "PING", b"*2\r\n$4\r\npong\r\n$0\r\n\r\n");
+    send(&mut stream, "PING hi", b"*2\r\n$4\r\npong\r\n$2\r\nhi\r\n");
+
+    send(
+        &mut stream,
+        "UNSUBSCRIBE",
+        b"*3\r\n$11\r\nunsubscribe\r\n$1\r\na\r\n:2\r\n*3\r\n$11\r\nunsubscribe\r\n$1\r\nb\r\n:1\r\n",
+    );
+    send(
+        &mut stream,
+        "PUNSUBSCRIBE c* d*",
+        b"*3\r\n$12\r\npunsubscribe\r\n$2\r\nc*\r\n:0\r\n*3\r\n$12\r\npunsubscribe\r\n$2\r\nd*\r\n:0\r\n",
+    );
+    send(
+        &mut stream,
+        "UNSUBSCRIBE",
+        b"*3\r\n$11\r\nunsubscribe\r\n$-1\r\n:0\r\n",
+    );
+
+    // Without subscriptions, the client is back to normal
+    send(&mut stream, "GET a", b"$-1\r\n");
+    send(&mut stream, "PING", b"+PONG\r\n");
+}
+
+#[test]
+fn test_pubsub_introspection() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+    let mut first = connect(&server);
+    let mut second = connect(&server);
+
+    send(
+        &mut first,
+        "SUBSCRIBE news.tech",
+        b"*3\r\n$9\r\nsubscribe\r\n$9\r\nnews.tech\r\n:1\r\n",
+    );
+    send(
+        &mut second,
+        "SUBSCRIBE news.tech weather",
+        b"*3\r\n$9\r\nsubscribe\r\n$9\r\nnews.tech\r\n:1\r\n*3\r\n$9\r\nsubscribe\r\n$7\r\nweather\r\n:2\r\n",
+    );
+    send(
+        &mut second,
+        "PSUBSCRIBE news.*",
+        b"*3\r\n$10\r\npsubscribe\r\n$6\r\nnews.*\r\n:3\r\n",
+    );
+
+    let mut channels: Vec<String> = query(&mut conn, "PUBSUB CHANNELS");
+    channels.sort();
+    assert_eq!(channels, vec!["news.tech", "weather"]);
+    let channels: Vec<String> = query(&mut conn, "PUBSUB CHANNELS news.*");
+    assert_eq!(channels, vec!["news.tech"]);
+    let counts: Vec<(String, i64)> = query(&mut conn, "PUBSUB NUMSUB news.tech weather other");
+    assert_eq!(
+        counts,
+        vec![
+            ("news.tech".to_string(), 2),
+            ("weather".to_string(), 1),
+            ("other".to_string(), 0)
+        ]
+    );
+    assert_eq!(query::<i64>(&mut conn, "PUBSUB NUMPAT"), 1);
+
+    // Disconnecting removes all subscriptions
+    drop(second);
+    let mut counts: Vec<(String, i64)> = query(&mut conn, "PUBSUB NUMSUB news.tech");
+    while counts[0].1 != 1 {
+        thread::sleep(Duration::from_millis(10));
+        counts = query(&mut conn, "PUBSUB NUMSUB news.tech");
+    }
+    assert_eq!(query::<i64>(&mut conn, "PUBSUB NUMPAT"), 0);
+
+    assert_eq!(query::<i64>(&mut conn, "PUBLISH news.tech hello"), 1);
+    expect(
+        &mut first,
+        b"*3\r\n$7\r\nmessage\r\n$9\r\nnews.tech\r\n$5\r\nhello\r\n",
+    );
+
+    let result: redis::RedisResult<i64> = redis::cmd("PUBSUB").arg("FOO").query(&mut conn);
+    assert_eq!(
+        result.unwrap_err().detail(),
+        Some("unknown subcommand 'FOO'. Try PUBSUB HELP.")
+    );
+}
+
+#[test]
+fn test_sharded_channels() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+    let mut stream = connect(&server);
+
+    // Sharded subscriptions are counted separately from the others
+    send(
+        &mut stream,
+        "SUBSCRIBE orders",
+        b"*3\r\n$9\r\nsubscribe\r\n$6\r\norders\r\n:1\r\n",
+    );
+    send(
+        &mut stream,
+        "SSUBSCRIBE {user}.orders {user}.payments",
+        b"*3\r\n$10\r\nssubscribe\r\n$13\r\n{user}.orders\r\n:1\r\n\
+          *3\r\n$10\r\nssubscribe\r\n$15\r\n{user}.payments\r\n:2\r\n",
+    );
+
+    let mut channels: Vec<String> = query(&mut conn, "PUBSUB SHARDCHANNELS");
+    channels.sort();
+    assert_eq!(channels, vec!["{user}.orders", "{user}.payments"]);
+    let channels: Vec<String> = query(&mut conn, "PUBSUB SHARDCHANNELS *.pay*");
+    assert_eq!(channels, vec!["{user}.payments"]);
+    let channels: Vec<String> = query(&mut conn, "PUBSUB CHANNELS");
+    assert_eq!(channels, vec!["orders"]);
+    let counts: Vec<(String, i64)> = query(&mut conn, "PUBSUB SHARDNUMSUB {user}.orders orders");
+    assert_eq!(
+        counts,
+        vec![("{user}.orders".to_string(), 1), ("orders".to_string(), 0)]
+    );
+
+    // Sharded channels and normal channels don't receive each other's messages
+    assert_eq!(query::<i64>(&mut conn, "PUBLISH {user}.orders hello"), 0);
+    assert_eq!(query::<i64>(&mut conn, "SPUBLISH orders hello"), 0);
+    assert_eq!(query::<i64>(&mut conn, "SPUBLISH {user}.orders hello"), 1);
+    expect(
+        &mut stream,
+        b"*3\r\n$8\r\nsmessage\r\n$13\r\n{user}.orders\r\n$5\r\nhello\r\n",
+    );
+
+    send(
+        &mut stream,
+        "SUNSUBSCRIBE",
+        b"*3\r\n$12\r\nsunsubscribe\r\n$13\r\n{user}.orders\r\n:1\r\n\
+          *3\r\n$12\r\nsunsubscribe\r\n$15\r\n{user}.payments\r\n:0\r\n",
+    );
+    send(
+        &mut stream,
+        "SUNSUBSCRIBE",
+        b"*3\r\n$12\r\nsunsubscribe\r\n$-1\r\n:0\r\n",
+    );
+    // Still subscribed to a normal channel
+    send(&mut stream, "PING", b"*2\r\n$4\r\npong\r\n$0\r\n\r\n");
+}
+
+#[test]
+fn test_quit_and_reset_while_subscribed() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let mut stream = connect(&server);
+    send(
+        &mut stream,
+        "SUBSCRIBE news",
+        b"*3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n",
+    );
+    send(&mut stream, "RESET", b"+RESET\r\n");
+    // No longer subscribed, so any command can be run
+    send(&mut stream, "PING", b"+PONG\r\n");
+    assert_eq!(query::<i64>(&mut conn, "PUBLISH news hello"), 0);
+
+    send(
+        &mut stream,
+        "SUBSCRIBE news",
+        b"*3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n",
+    );
+    send(&mut stream, "QUIT", b"+OK\r\n");
+    let mut buf = [0; 1];
+    assert_eq!(stream.read(&mut buf).unwrap(), 0);
+
+    // The server is still serving the other clients, and the subscriber is gone
+    assert_eq!(query::<i64>(&mut conn, "PUBLISH news hello"), 0);
+    let unknown = redis::cmd("NOPE")
+        .arg("x")
+        .query::<()>(&mut conn)
+        .unwrap_err();
+    assert_eq!(
+        unknown.detail(),
+        Some("unknown command 'NOPE', with args beginning with: 'x' ")
+    );
+}
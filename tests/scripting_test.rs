@@ -0,0 +1,355 @@
+mod common;
+
+use common::TestServer;
+use redis::Value;
+use std::{thread, time::Duration};
+
+fn eval(conn: &mut redis::Connection, script: &str, keys: &[&str], args: &[&str]) -> Value {
+    eval_result(conn, "EVAL", script, keys, args).unwrap()
+}
+
+fn eval_result(
+    conn: &mut redis::Connection,
+    command: &str,
+    script: &str,
+    keys: &[&str],
+    args: &[&str],
+) -> redis::RedisResult<Value> {
+    redis::cmd(command)
+        .arg(script)
+        .arg(keys.len())
+        .arg(keys)
+        .arg(args)
+        .query(conn)
+}
+
+/// The code and message of the error a script fails with
+fn eval_error(conn: &mut redis::Connection, command: &str, script: &str) -> String {
+    let e = eval_result(conn, command, script, &[], &[]).unwrap_err();
+    format!(
+        "{} {}",
+        e.code().unwrap_or_default(),
+        e.detail().unwrap_or_default()
+    )
+}
+
+fn bulk(s: &str) -> Value {
+    Value::BulkString(s.as_bytes().to_vec())
+}
+
+#[test]
+fn test_eval_conversions() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    assert_eq!(eval(&mut conn, "return 1", &[], &[]), Value::Int(1));
+    assert_eq!(eval(&mut conn, "return 3.99", &[], &[]), Value::Int(3));
+    assert_eq!(eval(&mut conn, "return 'foo'", &[], &[]), bulk("foo"));
+    assert_eq!(eval(&mut conn, "return true", &[], &[]), Value::Int(1));
+    assert_eq!(eval(&mut conn, "return false", &[], &[]), Value::Nil);
+    assert_eq!(eval(&mut conn, "return nil", &[], &[]), Value::Nil);
+    assert_eq!(eval(&mut conn, "local x = 1", &[], &[]), Value::Nil);
+    assert_eq!(
+        eval(&mut conn, "return {1, 'two', {3}, nil, 5}", &[], &[]),
+        Value::Array(vec![
+            Value::Int(1),
+            bulk("two"),
+            Value::Array(vec![Value::Int(3)])
+        ])
+    );
+    assert_eq!(
+        eval(&mut conn, "return redis.status_reply('FINE')", &[], &[]),
+        Value::SimpleString("FINE".to_string())
+    );
+    assert_eq!(
+        eval(
+            &mut conn,
+            "return {KEYS[1], KEYS[2], ARGV[1], #ARGV}",
+            &["a", "b"],
+            &["c", "d"]
+        ),
+        Value::Array(vec![bulk("a"), bulk("b"), bulk("c"), Value::Int(2)])
+    );
+
+    assert_eq!(
+        eval_error(&mut conn, "EVAL", "return redis.error_reply('My Error')"),
+        "My Error"
+    );
+    assert_eq!(
+        eval_error(&mut conn, "EVAL", "return {err = 'ERR oops'}"),
+        "ERR oops"
+    );
+}
+
+#[test]
+fn test_redis_call() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let script = "redis.call('SET', KEYS[1], ARGV[1]) \
+                  redis.call('INCRBY', KEYS[1], 5) \
+                  return redis.call('GET', KEYS[1])";
+    assert_eq!(eval(&mut conn, script, &["n"], &["10"]), bulk("15"));
+    let n: i64 = redis::cmd("GET").arg("n").query(&mut conn).unwrap();
+    assert_eq!(n, 15);
+
+    // Replies are converted to Lua values and back
+    redis::cmd("RPUSH")
+        .arg("list")
+        .arg(&["a", "b"])
+        .exec(&mut conn)
+        .unwrap();
+    assert_eq!(
+        eval(
+            &mut conn,
+            "return redis.call('LRANGE', 'list', 0, -1)",
+            &[],
+            &[]
+        ),
+        Value::Array(vec![bulk("a"), bulk("b")])
+    );
+    assert_eq!(
+        eval(
+            &mut conn,
+            "return type(redis.call('GET', 'nope'))",
+            &[],
+            &[]
+        ),
+        bulk("boolean")
+    );
+    assert_eq!(
+        eval(&mut conn, "return redis.call('SET', 'x', 1).ok", &[], &[]),
+        bulk("OK")
+    );
+
+    // Errors are raised by redis.call, saying where, and returned by redis.pcall
+    let script = "return redis.call('LPUSH', 'n', 'x')";
+    let sha: String = redis::cmd("SCRIPT")
+        .arg("LOAD")
+        .arg(script)
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(
+        eval_error(&mut conn, "EVAL", script),
+        format!(
+            "WRONGTYPE Operation against a key holding the wrong kind of value script: {}, on \
+             @user_script:1.",
+            sha
+        )
+    );
+    assert_eq!(
+        eval_error(&mut conn, "EVAL", "return redis.pcall('GET', 'a', 'b')"),
+        "ERR Wrong number of args calling Redis command from script"
+    );
+    assert_eq!(
+        eval(
+            &mut conn,
+            "local ok, e = pcall(redis.call, 'LPUSH', 'n', 'x') return e.err",
+            &[],
+            &[]
+        ),
+        bulk("WRONGTYPE Operation against a key holding the wrong kind of value")
+    );
+    assert!(eval_error(&mut conn, "EVAL", "return redis.call('NOPE')")
+        .starts_with("ERR Unknown Redis command called from script script: "));
+    assert!(eval_error(&mut conn, "EVAL", "return redis.call('GET')")
+        .starts_with("ERR Wrong number of args calling Redis command from script script: "));
+    assert!(eval_error(&mut conn, "EVAL", "return redis.call('MULTI')")
+        .starts_with("ERR This Redis command is not allowed from script script: "));
+    assert!(
+        eval_error(&mut conn, "EVAL", "return redis.call('GET', {})").starts_with(
+            "ERR Lua redis lib command arguments must be strings or integers script: "
+        )
+    );
+
+    // Blocking commands don't block
+    assert_eq!(
+        eval(
+            &mut conn,
+            "return redis.call('BLPOP', 'empty', 0)",
+            &[],
+            &[]
+        ),
+        Value::Nil
+    );
+}
+
+#[test]
+fn test_script_cache() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let sha: String = redis::cmd("SCRIPT")
+        .arg("LOAD")
+        .arg("return ARGV[1]")
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(sha, "098e0f0d1448c0a81dafe820f66d460eb09263da");
+    assert_eq!(
+        eval_result(&mut conn, "EVALSHA", &sha, &[], &["hi"]).unwrap(),
+        bulk("hi")
+    );
+    assert_eq!(
+        eval_result(&mut conn, "EVALSHA", &sha.to_uppercase(), &[], &["hi"]).unwrap(),
+        bulk("hi")
+    );
+
+    // EVAL caches the script too
+    eval(&mut conn, "return 2", &[], &[]);
+    let exists: Vec<i64> = redis::cmd("SCRIPT")
+        .arg("EXISTS")
+        .arg(&sha)
+        .arg("7f923f79fe76194c868d7e1d0820de36700eb649")
+        .arg("nope")
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(exists, vec![1, 1, 0]);
+
+    let _: () = redis::cmd("SCRIPT").arg("FLUSH").query(&mut conn).unwrap();
+    assert_eq!(
+        eval_error(&mut conn, "EVALSHA", &sha),
+        "NOSCRIPT No matching script. Please use EVAL."
+    );
+
+    let e = redis::cmd("SCRIPT")
+        .arg("NOPE")
+        .query::<Value>(&mut conn)
+        .unwrap_err();
+    assert_eq!(
+        e.detail(),
+        Some("unknown subcommand 'NOPE'. Try SCRIPT HELP.")
+    );
+    let e = redis::cmd("SCRIPT")
+        .arg("KILL")
+        .query::<Value>(&mut conn)
+        .unwrap_err();
+    assert_eq!(e.code(), Some("NOTBUSY"));
+}
+
+#[test]
+fn test_script_errors() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    assert_eq!(
+        eval_error(&mut conn, "EVAL", "return +"),
+        "ERR Error compiling script (new function): user_script:1: unexpected symbol near '+'"
+    );
+    assert!(eval_error(&mut conn, "EVAL", "\nreturn nope").starts_with(
+        "ERR user_script:2: Script attempted to access nonexistent global variable 'nope' \
+         script: "
+    ));
+    assert!(eval_error(&mut conn, "EVAL", "x = 1")
+        .starts_with("ERR user_script:1: Attempt to modify a readonly table script: "));
+    assert!(eval_error(&mut conn, "EVAL", "error('boom')")
+        .starts_with("ERR user_script:1: boom script: "));
+
+    let e = redis::cmd("EVAL")
+        .arg("return 1")
+        .arg(2)
+        .arg("a")
+        .query::<Value>(&mut conn)
+        .unwrap_err();
+    assert_eq!(
+        e.detail(),
+        Some("Number of keys can't be greater than number of args")
+    );
+    let e = redis::cmd("EVAL")
+        .arg("return 1")
+        .arg(-1)
+        .query::<Value>(&mut conn)
+        .unwrap_err();
+    assert_eq!(e.detail(), Some("Number of keys can't be negative"));
+}
+
+#[test]
+fn test_read_only_scripts() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    redis::cmd("SET").arg("a").arg("1").exec(&mut conn).unwrap();
+    assert_eq!(
+        eval_result(
+            &mut conn,
+            "EVAL_RO",
+            "return redis.call('GET', 'a')",
+            &[],
+            &[]
+        )
+        .unwrap(),
+        bulk("1")
+    );
+    assert!(
+        eval_error(&mut conn, "EVAL_RO", "return redis.call('SET', 'a', 2)")
+            .starts_with("ERR Write commands are not allowed from read-only scripts. script: ")
+    );
+
+    // The shebang can declare a script as read only, for EVAL too
+    let script = "#!lua flags=no-writes\nreturn redis.call('SET', 'a', 2)";
+    assert!(eval_error(&mut conn, "EVAL", script).ends_with("on @user_script:2."));
+    assert!(eval_error(&mut conn, "EVAL", "#!lua flags=nope\nreturn 1")
+        .starts_with("ERR Unexpected flag in script shebang: nope"));
+}
+
+#[test]
+fn test_script_kill() {
+    let server = TestServer::start(Some(vec!["--busy-reply-threshold", "100"]));
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let mut script_conn = client.get_connection().unwrap();
+    let script = thread::spawn(move || eval_error(&mut script_conn, "EVAL", "while true do end"));
+    thread::sleep(Duration::from_millis(300));
+
+    // Other clients can only kill the script while it's busy
+    let e = redis::cmd("PING").query::<Value>(&mut conn).unwrap_err();
+    assert_eq!(e.code(), Some("BUSY"));
+    let _: () = redis::cmd("SCRIPT").arg("KILL").query(&mut conn).unwrap();
+
+    assert!(script
+        .join()
+        .unwrap()
+        .starts_with("ERR Script killed by user with SCRIPT KILL... script: "));
+    let pong: String = redis::cmd("PING").query(&mut conn).unwrap();
+    assert_eq!(pong, "PONG");
+
+    // Scripts that wrote can't be killed
+    let mut script_conn = client.get_connection().unwrap();
+    thread::spawn(move || {
+        eval_error(
+            &mut script_conn,
+            "EVAL",
+            "redis.call('SET', 'a', 1) while true do end",
+        )
+    });
+    thread::sleep(Duration::from_millis(300));
+    let e = redis::cmd("SCRIPT")
+        .arg("KILL")
+        .query::<Value>(&mut conn)
+        .unwrap_err();
+    assert_eq!(e.code(), Some("UNKILLABLE"));
+}
+
+#[test]
+fn test_eval_in_transaction() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let (set, value): (i64, String) = redis::pipe()
+        .atomic()
+        .cmd("EVAL")
+        .arg("return redis.call('INCR', KEYS[1])")
+        .arg(1)
+        .arg("n")
+        .cmd("GET")
+        .arg("n")
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!((set, value.as_str()), (1, "1"));
+}
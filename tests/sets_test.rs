@@ -0,0 +1,231 @@
+mod common;
+
+use common::TestServer;
+use redis::Commands;
+use std::collections::HashSet;
+
+fn object_encoding(conn: &mut redis::Connection, key: &str) -> String {
+    redis::cmd("OBJECT")
+        .arg("ENCODING")
+        .arg(key)
+        .query(conn)
+        .unwrap()
+}
+
+#[test]
+fn test_sadd_srem_and_membership() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let added: i64 = conn.sadd("tags", &["rust", "redis", "rust"]).unwrap();
+    assert_eq!(added, 2);
+    let added: i64 = conn.sadd("tags", &["redis", "nom"]).unwrap();
+    assert_eq!(added, 1);
+
+    let members: HashSet<String> = conn.smembers("tags").unwrap();
+    assert_eq!(
+        members,
+        HashSet::from(["rust".into(), "redis".into(), "nom".into()])
+    );
+    let length: i64 = conn.scard("tags").unwrap();
+    assert_eq!(length, 3);
+
+    let is_member: bool = conn.sismember("tags", "nom").unwrap();
+    assert!(is_member);
+    let are_members: Vec<i64> = redis::cmd("SMISMEMBER")
+        .arg(&["tags", "rust", "go", "redis"])
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(are_members, vec![1, 0, 1]);
+
+    let removed: i64 = conn.srem("tags", &["rust", "go"]).unwrap();
+    assert_eq!(removed, 1);
+
+    // Removing the last members removes the key
+    let removed: i64 = conn.srem("tags", &["redis", "nom"]).unwrap();
+    assert_eq!(removed, 2);
+    let key_type: String = redis::cmd("TYPE").arg("tags").query(&mut conn).unwrap();
+    assert_eq!(key_type, "none");
+
+    let _: () = conn.set("string", "value").unwrap();
+    let result: redis::RedisResult<i64> = conn.sadd("string", "a");
+    assert_eq!(result.unwrap_err().code(), Some("WRONGTYPE"));
+}
+
+#[test]
+fn test_encodings() {
+    let server = TestServer::start(Some(vec!["--set-max-intset-entries", "3"]));
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let _: i64 = conn.sadd("numbers", &[3, -1, 2]).unwrap();
+    assert_eq!(object_encoding(&mut conn, "numbers"), "intset");
+    // Intsets are sorted
+    let members: Vec<i64> = conn.smembers("numbers").unwrap();
+    assert_eq!(members, vec![-1, 2, 3]);
+
+    let _: i64 = conn.sadd("numbers", 4).unwrap();
+    assert_eq!(object_encoding(&mut conn, "numbers"), "hashtable");
+
+    let _: i64 = conn.sadd("mixed", &["1", "a"]).unwrap();
+    assert_eq!(object_encoding(&mut conn, "mixed"), "hashtable");
+
+    let entries: Vec<String> = redis::cmd("CONFIG")
+        .arg(&["GET", "set-max-intset-entries"])
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(entries, vec!["set-max-intset-entries", "3"]);
+}
+
+#[test]
+fn test_spop_and_srandmember() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let missing: Option<String> = conn.spop("missing").unwrap();
+    assert_eq!(missing, None);
+
+    let _: i64 = conn.sadd("flags", &["a", "b", "c", "d"]).unwrap();
+
+    let member: String = conn.srandmember("flags").unwrap();
+    assert!(["a", "b", "c", "d"].contains(&member.as_str()));
+    let members: Vec<String> = conn.srandmember_multiple("flags", 10).unwrap();
+    assert_eq!(members.len(), 4);
+    let members: Vec<String> = redis::cmd("SRANDMEMBER")
+        .arg(&["flags", "-10"])
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(members.len(), 10);
+
+    let popped: String = conn.spop("flags").unwrap();
+    let popped_many: Vec<String> = redis::cmd("SPOP")
+        .arg(&["flags", "2"])
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(popped_many.len(), 2);
+    assert!(!popped_many.contains(&popped));
+    let length: i64 = conn.scard("flags").unwrap();
+    assert_eq!(length, 1);
+
+    let popped_many: Vec<String> = redis::cmd("SPOP")
+        .arg(&["flags", "5"])
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(popped_many.len(), 1);
+    let key_type: String = redis::cmd("TYPE").arg("flags").query(&mut conn).unwrap();
+    assert_eq!(key_type, "none");
+
+    let result: redis::RedisResult<Vec<String>> =
+        redis::cmd("SPOP").arg(&["flags", "-1"]).query(&mut conn);
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("value is out of range, must be positive"));
+}
+
+#[test]
+fn test_set_algebra() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let _: i64 = conn.sadd("a", &["1", "2", "3", "x"]).unwrap();
+    let _: i64 = conn.sadd("b", &["2", "3", "4"]).unwrap();
+    let _: i64 = conn.sadd("c", &["3", "x"]).unwrap();
+
+    let inter: HashSet<String> = conn.sinter(&["a", "b"]).unwrap();
+    assert_eq!(inter, HashSet::from(["2".into(), "3".into()]));
+    let inter: HashSet<String> = conn.sinter(&["a", "b", "missing"]).unwrap();
+    assert!(inter.is_empty());
+    let union: HashSet<String> = conn.sunion(&["b", "c", "missing"]).unwrap();
+    assert_eq!(
+        union,
+        HashSet::from(["2".into(), "3".into(), "4".into(), "x".into()])
+    );
+    let diff: HashSet<String> = conn.sdiff(&["a", "b", "c"]).unwrap();
+    assert_eq!(diff, HashSet::from(["1".into()]));
+
+    let stored: i64 = conn.sinterstore("dest", &["a", "c"]).unwrap();
+    assert_eq!(stored, 2);
+    let members: HashSet<String> = conn.smembers("dest").unwrap();
+    assert_eq!(members, HashSet::from(["3".into(), "x".into()]));
+    let stored: i64 = conn.sunionstore("dest", &["b"]).unwrap();
+    assert_eq!(stored, 3);
+    assert_eq!(object_encoding(&mut conn, "dest"), "intset");
+
+    // An empty result deletes the destination
+    let stored: i64 = conn.sdiffstore("dest", &["c", "a"]).unwrap();
+    assert_eq!(stored, 0);
+    let key_type: String = redis::cmd("TYPE").arg("dest").query(&mut conn).unwrap();
+    assert_eq!(key_type, "none");
+
+    let count: i64 = redis::cmd("SINTERCARD")
+        .arg(&["2", "a", "b"])
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(count, 2);
+    let count: i64 = redis::cmd("SINTERCARD")
+        .arg(&["2", "a", "b", "LIMIT", "1"])
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(count, 1);
+    let result: redis::RedisResult<i64> = redis::cmd("SINTERCARD")
+        .arg(&["3", "a", "b"])
+        .query(&mut conn);
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("Number of keys can't be greater than number of args"));
+
+    let moved: bool = conn.smove("a", "c", "1").unwrap();
+    assert!(moved);
+    let moved: bool = conn.smove("a", "c", "1").unwrap();
+    assert!(!moved);
+    let is_member: bool = conn.sismember("c", "1").unwrap();
+    assert!(is_member);
+
+    let _: () = conn.set("string", "value").unwrap();
+    let result: redis::RedisResult<HashSet<String>> = conn.sinter(&["missing", "string"]);
+    assert_eq!(result.unwrap_err().code(), Some("WRONGTYPE"));
+    let result: redis::RedisResult<bool> = conn.smove("a", "string", "2");
+    assert_eq!(result.unwrap_err().code(), Some("WRONGTYPE"));
+}
+
+#[test]
+fn test_sets_survive_save_and_restart() {
+    let dir = std::env::temp_dir().join(format!("rustis-sets-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let dir = dir.to_str().unwrap();
+    let args = vec!["--dir", dir, "--dbfilename", "sets.rdb"];
+
+    let large: Vec<i64> = (0..1000).map(|i| i * 100_000).collect();
+    {
+        let server = TestServer::start(Some(args.clone()));
+        let client = redis::Client::open(server.connection_string()).unwrap();
+        let mut conn = client.get_connection().unwrap();
+
+        let _: i64 = conn.sadd("ints", &[-70_000, 5, 1i64 << 40]).unwrap();
+        let _: i64 = conn.sadd("strings", &["a", "b", "c"]).unwrap();
+        let _: i64 = conn.sadd("large", &large).unwrap();
+        let _: () = redis::cmd("SAVE").query(&mut conn).unwrap();
+    }
+
+    let server = TestServer::start(Some(args));
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let ints: Vec<i64> = conn.smembers("ints").unwrap();
+    assert_eq!(ints, vec![-70_000, 5, 1i64 << 40]);
+    assert_eq!(object_encoding(&mut conn, "ints"), "intset");
+
+    let strings: HashSet<String> = conn.smembers("strings").unwrap();
+    assert_eq!(strings, HashSet::from(["a".into(), "b".into(), "c".into()]));
+
+    let loaded: HashSet<i64> = conn.smembers("large").unwrap();
+    assert_eq!(loaded, large.into_iter().collect());
+    assert_eq!(object_encoding(&mut conn, "large"), "hashtable");
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
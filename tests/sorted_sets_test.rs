@@ -0,0 +1,300 @@
+mod common;
+
+use common::{error_message, query, TestServer};
+use redis::Commands;
+use std::{thread, time::Duration};
+
+#[test]
+fn test_zadd_flags() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let added: i64 = query(&mut conn, "ZADD board 10 alice 20 bob");
+    assert_eq!(added, 2);
+
+    // NX only adds new members, XX only updates existing ones
+    let added: i64 = query(&mut conn, "ZADD board NX 5 alice 30 carol");
+    assert_eq!(added, 1);
+    let added: i64 = query(&mut conn, "ZADD board XX 15 alice 1 dave");
+    assert_eq!(added, 0);
+    let score: f64 = conn.zscore("board", "alice").unwrap();
+    assert_eq!(score, 15.0);
+    let exists: Option<f64> = conn.zscore("board", "dave").unwrap();
+    assert_eq!(exists, None);
+    let _: i64 = query(&mut conn, "ZADD scores 1e21 big 0.1 small -inf low");
+    let scores: Vec<String> = query(&mut conn, "ZRANGE scores 0 -1 WITHSCORES");
+    assert_eq!(scores, ["low", "-inf", "small", "0.1", "big", "1e+21"]);
+
+    // GT and LT only update when the score moves in that direction, CH counts the updates
+    let changed: i64 = query(&mut conn, "ZADD board GT CH 5 alice 25 bob");
+    assert_eq!(changed, 1);
+    let changed: i64 = query(&mut conn, "ZADD board LT CH 5 alice 50 bob");
+    assert_eq!(changed, 1);
+    let scores: Vec<Option<f64>> = query(&mut conn, "ZMSCORE board alice bob x");
+    assert_eq!(scores, vec![Some(5.0), Some(25.0), None]);
+
+    let score: Option<f64> = query(&mut conn, "ZADD board INCR 2.5 alice");
+    assert_eq!(score, Some(7.5));
+    let score: Option<f64> = query(&mut conn, "ZADD board NX INCR 1 alice");
+    assert_eq!(score, None);
+    let score: f64 = conn.zincr("board", "bob", -5).unwrap();
+    assert_eq!(score, 20.0);
+    let length: i64 = conn.zcard("board").unwrap();
+    assert_eq!(length, 3);
+
+    assert!(error_message(&mut conn, "ZADD board NX XX 1 a")
+        .contains("XX and NX options at the same time are not compatible"));
+    assert!(error_message(&mut conn, "ZADD board GT LT 1 a")
+        .contains("GT, LT, and/or NX options at the same time are not compatible"));
+    assert!(error_message(&mut conn, "ZADD board INCR 1 a 2 b")
+        .contains("INCR option supports a single increment-element pair"));
+    assert!(error_message(&mut conn, "ZADD board one a").contains("value is not a valid float"));
+    let _: i64 = query(&mut conn, "ZADD inf +inf a");
+    assert!(error_message(&mut conn, "ZINCRBY inf -inf a")
+        .contains("resulting score is not a number (NaN)"));
+
+    let _: () = conn.set("string", "value").unwrap();
+    let result: redis::RedisResult<i64> = conn.zadd("string", "a", 1);
+    assert_eq!(result.unwrap_err().code(), Some("WRONGTYPE"));
+}
+
+#[test]
+fn test_ranges_and_ranks() {
+    let server = TestServer::start(Some(vec!["--zset-max-listpack-entries", "4"]));
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    // Run the same queries against both encodings
+    for (key, encoding) in [("small", "listpack"), ("large", "skiplist")] {
+        let _: i64 = conn
+            .zadd_multiple(key, &[(1, "a"), (2, "b"), (2, "c"), (3, "d")])
+            .unwrap();
+        if key == "large" {
+            let _: i64 = conn.zadd(key, "e", 4).unwrap();
+            let _: i64 = conn.zrem(key, "e").unwrap();
+        }
+        let actual: String = query(&mut conn, &format!("OBJECT ENCODING {}", key));
+        assert_eq!(actual, encoding);
+
+        let members: Vec<String> = query(&mut conn, &format!("ZRANGE {} 0 -1", key));
+        assert_eq!(members, vec!["a", "b", "c", "d"]);
+        let members: Vec<String> = query(&mut conn, &format!("ZRANGE {} 0 1 REV", key));
+        assert_eq!(members, vec!["d", "c"]);
+        let members: Vec<String> = query(
+            &mut conn,
+            &format!("ZRANGE {} (1 3 BYSCORE WITHSCORES", key),
+        );
+        assert_eq!(members, vec!["b", "2", "c", "2", "d", "3"]);
+        let members: Vec<String> = query(
+            &mut conn,
+            &format!("ZRANGE {} +inf -inf BYSCORE REV LIMIT 1 2", key),
+        );
+        assert_eq!(members, vec!["c", "b"]);
+        let members: Vec<String> = query(&mut conn, &format!("ZRANGEBYSCORE {} 2 (3", key));
+        assert_eq!(members, vec!["b", "c"]);
+        let members: Vec<String> = query(&mut conn, &format!("ZREVRANGE {} 0 0", key));
+        assert_eq!(members, vec!["d"]);
+
+        let rank: Option<i64> = query(&mut conn, &format!("ZRANK {} c", key));
+        assert_eq!(rank, Some(2));
+        let rank: (i64, f64) = query(&mut conn, &format!("ZREVRANK {} c WITHSCORE", key));
+        assert_eq!(rank, (1, 2.0));
+        let rank: Option<i64> = query(&mut conn, &format!("ZRANK {} missing", key));
+        assert_eq!(rank, None);
+
+        let count: i64 = query(&mut conn, &format!("ZCOUNT {} (1 +inf", key));
+        assert_eq!(count, 3);
+    }
+
+    let _: i64 = conn
+        .zadd_multiple("names", &[(0, "apple"), (0, "banana"), (0, "cherry")])
+        .unwrap();
+    let members: Vec<String> = query(&mut conn, "ZRANGE names [b + BYLEX");
+    assert_eq!(members, vec!["banana", "cherry"]);
+    let members: Vec<String> = query(&mut conn, "ZREVRANGEBYLEX names (cherry -");
+    assert_eq!(members, vec!["banana", "apple"]);
+    let count: i64 = query(&mut conn, "ZLEXCOUNT names (apple [cherry");
+    assert_eq!(count, 2);
+
+    assert!(error_message(&mut conn, "ZRANGE names 0 1 LIMIT 0 1")
+        .contains("LIMIT is only supported in combination with either BYSCORE or BYLEX"));
+    assert!(
+        error_message(&mut conn, "ZRANGE names - + BYLEX WITHSCORES")
+            .contains("WITHSCORES not supported in combination with BYLEX")
+    );
+    assert!(error_message(&mut conn, "ZCOUNT names a 1").contains("min or max is not a float"));
+    assert!(error_message(&mut conn, "ZLEXCOUNT names a +")
+        .contains("min or max not valid string range item"));
+}
+
+#[test]
+fn test_removing_and_popping() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let members: Vec<(i64, String)> = (0..10).map(|i| (i, format!("m{}", i))).collect();
+    let _: i64 = conn.zadd_multiple("numbers", &members).unwrap();
+
+    let removed: i64 = conn.zrem("numbers", &["m0", "missing"]).unwrap();
+    assert_eq!(removed, 1);
+    let removed: i64 = query(&mut conn, "ZREMRANGEBYRANK numbers 0 1");
+    assert_eq!(removed, 2);
+    let removed: i64 = query(&mut conn, "ZREMRANGEBYSCORE numbers (7 +inf");
+    assert_eq!(removed, 2);
+    let members: Vec<String> = conn.zrange("numbers", 0, -1).unwrap();
+    assert_eq!(members, vec!["m3", "m4", "m5", "m6", "m7"]);
+
+    let popped: Vec<String> = query(&mut conn, "ZPOPMIN numbers");
+    assert_eq!(popped, vec!["m3", "3"]);
+    let popped: Vec<String> = query(&mut conn, "ZPOPMAX numbers 2");
+    assert_eq!(popped, vec!["m7", "7", "m6", "6"]);
+
+    let _: i64 = conn
+        .zadd_multiple("names", &[(0, "a"), (0, "b"), (0, "c")])
+        .unwrap();
+    let removed: i64 = query(&mut conn, "ZREMRANGEBYLEX names - [b");
+    assert_eq!(removed, 2);
+
+    // Emptying a sorted set removes the key
+    let popped: Vec<String> = query(&mut conn, "ZPOPMIN numbers 10");
+    assert_eq!(popped, vec!["m4", "4", "m5", "5"]);
+    let key_type: String = query(&mut conn, "TYPE numbers");
+    assert_eq!(key_type, "none");
+    let popped: Vec<String> = query(&mut conn, "ZPOPMIN numbers");
+    assert!(popped.is_empty());
+}
+
+#[test]
+fn test_combining_sorted_sets() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let _: i64 = conn
+        .zadd_multiple("week1", &[(10, "alice"), (20, "bob"), (5, "carol")])
+        .unwrap();
+    let _: i64 = conn
+        .zadd_multiple("week2", &[(15, "alice"), (1, "dave")])
+        .unwrap();
+    let _: i64 = conn.sadd("bonus", &["bob", "dave"]).unwrap();
+
+    let union: Vec<String> = query(&mut conn, "ZUNION 3 week1 week2 bonus WITHSCORES");
+    assert_eq!(
+        union,
+        vec!["dave", "2", "carol", "5", "bob", "21", "alice", "25"]
+    );
+    let inter: Vec<String> = query(
+        &mut conn,
+        "ZINTER 2 week1 week2 WEIGHTS 2 1 AGGREGATE MAX WITHSCORES",
+    );
+    assert_eq!(inter, vec!["alice", "20"]);
+    let diff: Vec<String> = query(&mut conn, "ZDIFF 3 week1 week2 bonus");
+    assert_eq!(diff, vec!["carol"]);
+
+    let stored: i64 = query(&mut conn, "ZUNIONSTORE total 2 week1 week2 AGGREGATE MIN");
+    assert_eq!(stored, 4);
+    let score: f64 = conn.zscore("total", "alice").unwrap();
+    assert_eq!(score, 10.0);
+    let stored: i64 = query(&mut conn, "ZINTERSTORE total 2 week1 missing");
+    assert_eq!(stored, 0);
+    let key_type: String = query(&mut conn, "TYPE total");
+    assert_eq!(key_type, "none");
+    let stored: i64 = query(&mut conn, "ZDIFFSTORE total 1 week2");
+    assert_eq!(stored, 2);
+
+    assert!(error_message(&mut conn, "ZUNION 0 week1")
+        .contains("at least 1 input key is needed for 'zunion' command"));
+    assert!(error_message(&mut conn, "ZUNION 1 week1 WEIGHTS x")
+        .contains("weight value is not a float"));
+    assert!(error_message(&mut conn, "ZDIFF 1 week1 AGGREGATE MIN").contains("syntax error"));
+    let _: () = conn.set("string", "value").unwrap();
+    let result: redis::RedisResult<Vec<String>> = redis::cmd("ZUNION")
+        .arg(&["2", "missing", "string"])
+        .query(&mut conn);
+    assert_eq!(result.unwrap_err().code(), Some("WRONGTYPE"));
+}
+
+#[test]
+fn test_bzpopmin_blocks_until_zadd() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let result: Option<(String, String, f64)> = query(&mut conn, "BZPOPMIN queue 0.1");
+    assert_eq!(result, None);
+
+    let mut waiter = client.get_connection().unwrap();
+    let handle = thread::spawn(move || {
+        let result: (String, String, f64) = query(&mut waiter, "BZPOPMAX other queue 5");
+        result
+    });
+    thread::sleep(Duration::from_millis(100));
+
+    let _: i64 = conn
+        .zadd_multiple("queue", &[(1, "low"), (9, "high")])
+        .unwrap();
+    assert_eq!(
+        handle.join().unwrap(),
+        ("queue".to_string(), "high".to_string(), 9.0)
+    );
+    let remaining: Vec<String> = conn.zrange("queue", 0, -1).unwrap();
+    assert_eq!(remaining, vec!["low"]);
+}
+
+#[test]
+fn test_sorted_sets_survive_save_and_restart() {
+    let dir = std::env::temp_dir().join(format!("rustis-sorted-sets-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let dir = dir.to_str().unwrap();
+    let args = vec!["--dir", dir, "--dbfilename", "sorted-sets.rdb"];
+
+    let large: Vec<(f64, String)> = (0..200)
+        .map(|i| (i as f64 / 4.0, format!("member-{}", i)))
+        .collect();
+    {
+        let server = TestServer::start(Some(args.clone()));
+        let client = redis::Client::open(server.connection_string()).unwrap();
+        let mut conn = client.get_connection().unwrap();
+
+        let _: i64 = query(&mut conn, "ZADD small 1.5 a -inf b +inf c");
+        let _: i64 = conn.zadd_multiple("large", &large).unwrap();
+        let _: i64 = query(&mut conn, "ZADD large -inf lowest");
+        let _: i64 = query(&mut conn, "ZADD large 1e300 highest");
+        let _: i64 = query(&mut conn, "ZADD huge 1e300 a -1e-300 b");
+        let _: () = conn.set("after", "sorted sets").unwrap();
+        let _: () = redis::cmd("SAVE").query(&mut conn).unwrap();
+    }
+
+    let server = TestServer::start(Some(args));
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let small: Vec<String> = query(&mut conn, "ZRANGE small 0 -1 WITHSCORES");
+    assert_eq!(small, vec!["b", "-inf", "a", "1.5", "c", "inf"]);
+    let encoding: String = query(&mut conn, "OBJECT ENCODING small");
+    assert_eq!(encoding, "listpack");
+
+    let length: i64 = conn.zcard("large").unwrap();
+    assert_eq!(length, 202);
+    let encoding: String = query(&mut conn, "OBJECT ENCODING large");
+    assert_eq!(encoding, "skiplist");
+    let score: f64 = conn.zscore("large", "member-199").unwrap();
+    assert_eq!(score, 49.75);
+    let lowest: Vec<String> = conn.zrange("large", 0, 0).unwrap();
+    assert_eq!(lowest, vec!["lowest"]);
+    let score: f64 = conn.zscore("large", "highest").unwrap();
+    assert_eq!(score, 1e300);
+
+    let huge: Vec<(String, f64)> = conn.zrange_withscores("huge", 0, -1).unwrap();
+    assert_eq!(
+        huge,
+        vec![("b".to_string(), -1e-300), ("a".to_string(), 1e300)]
+    );
+    // The keys after the sorted sets are loaded too
+    let after: String = conn.get("after").unwrap();
+    assert_eq!(after, "sorted sets");
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,212 @@
+mod common;
+
+use common::{error_message, query, TestServer};
+use std::{thread, time::Duration};
+
+type Entry = (String, Vec<String>);
+
+fn ids(entries: &[Entry]) -> Vec<&str> {
+    entries.iter().map(|(id, _)| id.as_str()).collect()
+}
+
+#[test]
+fn test_xadd_ids() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let id: String = query(&mut conn, "XADD events 1-1 type login");
+    assert_eq!(id, "1-1");
+    let id: String = query(&mut conn, "XADD events 1-* type logout");
+    assert_eq!(id, "1-2");
+    let id: String = query(&mut conn, "XADD events 5 type login");
+    assert_eq!(id, "5-0");
+
+    // Generated IDs are based on the current time, and always go up
+    let first: String = query(&mut conn, "XADD events * type login");
+    let second: String = query(&mut conn, "XADD events * type login");
+    let ms: u64 = first.split('-').next().unwrap().parse().unwrap();
+    assert!(ms > 1_600_000_000_000);
+    assert!(second > first || second.len() > first.len());
+
+    assert!(error_message(&mut conn, "XADD events 5-0 a b")
+        .contains("equal or smaller than the target stream top item"));
+    assert!(error_message(&mut conn, "XADD other 0-0 a b").contains("must be greater than 0-0"));
+    assert!(error_message(&mut conn, "XADD events bad a b").contains("Invalid stream ID"));
+    assert!(error_message(&mut conn, "XADD events * a").contains("wrong number of arguments"));
+
+    let created: Option<String> = query(&mut conn, "XADD missing NOMKSTREAM * a b");
+    assert_eq!(created, None);
+    let length: i64 = query(&mut conn, "XLEN events");
+    assert_eq!(length, 5);
+    let kind: String = query(&mut conn, "TYPE events");
+    assert_eq!(kind, "stream");
+
+    let _: () = query(&mut conn, "SET string value");
+    let result: redis::RedisResult<i64> = redis::cmd("XLEN").arg("string").query(&mut conn);
+    assert_eq!(result.unwrap_err().code(), Some("WRONGTYPE"));
+}
+
+#[test]
+fn test_xrange_and_xrevrange() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    for ms in 1..=5 {
+        let _: String = query(&mut conn, &format!("XADD log {}-0 n v{}", ms, ms));
+    }
+
+    let entries: Vec<Entry> = query(&mut conn, "XRANGE log - +");
+    assert_eq!(ids(&entries), vec!["1-0", "2-0", "3-0", "4-0", "5-0"]);
+    assert_eq!(entries[0].1, vec!["n", "v1"]);
+
+    let entries: Vec<Entry> = query(&mut conn, "XRANGE log 2 4 COUNT 2");
+    assert_eq!(ids(&entries), vec!["2-0", "3-0"]);
+    let entries: Vec<Entry> = query(&mut conn, "XRANGE log (2-0 (5-0");
+    assert_eq!(ids(&entries), vec!["3-0", "4-0"]);
+    let entries: Vec<Entry> = query(&mut conn, "XREVRANGE log + - COUNT 2");
+    assert_eq!(ids(&entries), vec!["5-0", "4-0"]);
+    let entries: Vec<Entry> = query(&mut conn, "XRANGE missing - +");
+    assert!(entries.is_empty());
+
+    let deleted: i64 = query(&mut conn, "XDEL log 2-0 3-0 9-0");
+    assert_eq!(deleted, 2);
+    let entries: Vec<Entry> = query(&mut conn, "XRANGE log - +");
+    assert_eq!(ids(&entries), vec!["1-0", "4-0", "5-0"]);
+
+    // Deleting the last entry doesn't allow reusing its ID
+    let _: i64 = query(&mut conn, "XDEL log 5-0");
+    assert!(error_message(&mut conn, "XADD log 5-0 a b").contains("equal or smaller"));
+}
+
+#[test]
+fn test_trimming() {
+    let server = TestServer::start(Some(vec!["--stream-node-max-entries", "10"]));
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    for ms in 1..=25 {
+        let _: String = query(&mut conn, &format!("XADD log {} n v", ms));
+    }
+
+    // Approximate trimming only removes whole nodes of 10 entries
+    let removed: i64 = query(&mut conn, "XTRIM log MAXLEN ~ 12");
+    assert_eq!(removed, 10);
+    let removed: i64 = query(&mut conn, "XTRIM log MAXLEN = 12");
+    assert_eq!(removed, 3);
+    let removed: i64 = query(&mut conn, "XTRIM log MINID 20");
+    assert_eq!(removed, 6);
+    let entries: Vec<Entry> = query(&mut conn, "XRANGE log - + COUNT 1");
+    assert_eq!(ids(&entries), vec!["20-0"]);
+
+    let id: String = query(&mut conn, "XADD log MAXLEN 3 30-0 n v");
+    assert_eq!(id, "30-0");
+    let entries: Vec<Entry> = query(&mut conn, "XRANGE log - +");
+    assert_eq!(ids(&entries), vec!["24-0", "25-0", "30-0"]);
+
+    assert!(error_message(&mut conn, "XTRIM log MAXLEN 1 LIMIT 5")
+        .contains("LIMIT cannot be used without the special ~ option"));
+    assert!(error_message(&mut conn, "XTRIM log MAXLEN -1").contains(">= 0"));
+}
+
+#[test]
+fn test_xread() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    for id in ["1-0", "2-0", "3-0"] {
+        let _: String = query(&mut conn, &format!("XADD a {} n {}", id, id));
+    }
+    let _: String = query(&mut conn, "XADD b 7-0 n b");
+
+    let read: Vec<(String, Vec<Entry>)> = query(&mut conn, "XREAD COUNT 2 STREAMS a b 1-0 $");
+    assert_eq!(read.len(), 1);
+    assert_eq!(read[0].0, "a");
+    assert_eq!(ids(&read[0].1), vec!["2-0", "3-0"]);
+
+    let read: Option<Vec<(String, Vec<Entry>)>> = query(&mut conn, "XREAD STREAMS a b $ $");
+    assert_eq!(read, None);
+    assert!(error_message(&mut conn, "XREAD STREAMS a b $").contains("Unbalanced"));
+}
+
+#[test]
+fn test_xread_blocks_until_xadd() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let _: String = query(&mut conn, "XADD events 1-0 n old");
+    let read: Option<Vec<(String, Vec<Entry>)>> =
+        query(&mut conn, "XREAD BLOCK 100 STREAMS events $");
+    assert_eq!(read, None);
+
+    let mut readers = Vec::new();
+    for _ in 0..2 {
+        let mut reader = client.get_connection().unwrap();
+        readers.push(thread::spawn(move || {
+            let read: Vec<(String, Vec<Entry>)> =
+                query(&mut reader, "XREAD BLOCK 0 STREAMS other events 0-0 $");
+            read
+        }));
+    }
+    thread::sleep(Duration::from_millis(100));
+
+    let _: String = query(&mut conn, "XADD events 2-0 n new");
+    // Every blocked reader gets the new entry, as reading doesn't consume it
+    for reader in readers {
+        let read = reader.join().unwrap();
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].0, "events");
+        assert_eq!(ids(&read[0].1), vec!["2-0"]);
+        assert_eq!(read[0].1[0].1, vec!["n", "new"]);
+    }
+}
+
+#[test]
+fn test_streams_survive_save_and_restart() {
+    let dir = std::env::temp_dir().join(format!("rustis-streams-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let dir = dir.to_str().unwrap();
+    let args = vec![
+        "--dir",
+        dir,
+        "--dbfilename",
+        "streams.rdb",
+        "--stream-node-max-entries",
+        "4",
+    ];
+
+    {
+        let server = TestServer::start(Some(args.clone()));
+        let client = redis::Client::open(server.connection_string()).unwrap();
+        let mut conn = client.get_connection().unwrap();
+
+        for ms in 1..=10 {
+            let id = format!("{}-{}", ms, 10 - ms);
+            let value = "x".repeat(ms * 20);
+            let _: String = query(&mut conn, &format!("XADD log {} n {} ms {}", id, value, id));
+        }
+        let _: i64 = query(&mut conn, "XDEL log 3-7 10-0");
+        let _: () = redis::cmd("SAVE").query(&mut conn).unwrap();
+    }
+
+    let server = TestServer::start(Some(args));
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let entries: Vec<Entry> = query(&mut conn, "XRANGE log - +");
+    assert_eq!(
+        ids(&entries),
+        vec!["1-9", "2-8", "4-6", "5-5", "6-4", "7-3", "8-2", "9-1"]
+    );
+    assert_eq!(
+        entries[2].1,
+        vec!["n".to_string(), "x".repeat(80), "ms".into(), "4-6".into()]
+    );
+    // The last ID is kept, even though that entry was deleted
+    assert!(error_message(&mut conn, "XADD log 10-0 a b").contains("equal or smaller"));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,131 @@
+mod common;
+
+use common::TestServer;
+use redis::Commands;
+use std::{thread, time::Duration};
+
+#[test]
+fn test_append_and_strlen() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let length: i64 = conn.append("my_key", "Hello").unwrap();
+    assert_eq!(length, 5);
+    let length: i64 = conn.append("my_key", " World").unwrap();
+    assert_eq!(length, 11);
+
+    let length: i64 = conn.strlen("my_key").unwrap();
+    assert_eq!(length, 11);
+    let length: i64 = conn.strlen("missing").unwrap();
+    assert_eq!(length, 0);
+}
+
+#[test]
+fn test_getrange_and_setrange() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let _: () = conn.set("my_key", "This is a string").unwrap();
+
+    let result: String = conn.getrange("my_key", 0, 3).unwrap();
+    assert_eq!(result, "This");
+    let result: String = conn.getrange("my_key", -3, -1).unwrap();
+    assert_eq!(result, "ing");
+    let result: String = conn.getrange("my_key", 10, 100).unwrap();
+    assert_eq!(result, "string");
+
+    // Setting past the end of a string pads it with zero bytes
+    let length: i64 = conn.setrange("padded", 3, "abc").unwrap();
+    assert_eq!(length, 6);
+    let result: Vec<u8> = conn.get("padded").unwrap();
+    assert_eq!(result, b"\0\0\0abc");
+
+    let result: redis::RedisResult<i64> = conn.setrange("padded", 512 * 1024 * 1024, "a");
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("string exceeds maximum allowed size"));
+}
+
+#[test]
+fn test_set_get_returns_previous_value() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let result: Option<String> = redis::cmd("SET")
+        .arg("my_key")
+        .arg("first")
+        .arg("GET")
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(result, None);
+
+    let result: Option<String> = redis::cmd("SET")
+        .arg("my_key")
+        .arg("second")
+        .arg("GET")
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(result, Some("first".to_string()));
+
+    let result: Option<String> = conn.getset("my_key", "third").unwrap();
+    assert_eq!(result, Some("second".to_string()));
+
+    let result: Option<String> = conn.get_del("my_key").unwrap();
+    assert_eq!(result, Some("third".to_string()));
+    let result: Option<String> = conn.get("my_key").unwrap();
+    assert_eq!(result, None);
+}
+
+#[test]
+fn test_setnx_setex_and_getex() {
+    let server = TestServer::start(None);
+    let client = redis::Client::open(server.connection_string()).unwrap();
+    let mut conn = client.get_connection().unwrap();
+
+    let result: bool = conn.set_nx("my_key", "first").unwrap();
+    assert!(result);
+    let result: bool = conn.set_nx("my_key", "second").unwrap();
+    assert!(!result);
+
+    let _: () = conn.pset_ex("expiring", "value", 50).unwrap();
+    let _: () = conn.set_ex("persisted", "value", 1).unwrap();
+    let result: String = redis::cmd("GETEX")
+        .arg("persisted")
+        .arg("PERSIST")
+        .query(&mut conn)
+        .unwrap();
+    assert_eq!(result, "value");
+
+    thread::sleep(Duration::from_millis(100));
+    let result: Option<String> = conn.get("expiring").unwrap();
+    assert_eq!(result, None);
+
+    let result: redis::RedisResult<()> = conn.set_ex("my_key", "value", 0);
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("invalid expire time"));
+    let result: redis::RedisResult<()> = redis::cmd("SET")
+        .arg("my_key")
+        .arg("value")
+        .arg("EX")
+        .arg(-1)
+        .query(&mut conn);
+    assert_eq!(
+        result.unwrap_err().detail(),
+        Some("invalid expire time in 'set' command")
+    );
+    let result: redis::RedisResult<Option<String>> = redis::cmd("GETEX")
+        .arg("my_key")
+        .arg("PX")
+        .arg(-100)
+        .query(&mut conn);
+    assert_eq!(
+        result.unwrap_err().detail(),
+        Some("invalid expire time in 'getex' command")
+    );
+}
@@ -0,0 +1,261 @@
+mod common;
+
+use common::{connect, send, TestServer};
+use std::{io::Read, time::Duration};
+
+#[test]
+fn test_multi_exec() {
+    let server = TestServer::start(None);
+    let mut stream = connect(&server);
+    let mut other = connect(&server);
+
+    send(&mut stream, "MULTI", b"+OK\r\n");
+    send(&mut stream, "SET a 1", b"+QUEUED\r\n");
+    send(&mut stream, "INCR a", b"+QUEUED\r\n");
+    send(&mut stream, "LPUSH a x", b"+QUEUED\r\n");
+    send(&mut stream, "GET a", b"+QUEUED\r\n");
+
+    // Nothing runs until EXEC
+    send(&mut other, "GET a", b"$-1\r\n");
+
+    // Errors while running a command don't stop the others
+    send(
+        &mut stream,
+        "EXEC",
+        b"*4\r\n+OK\r\n:2\r\n\
+          -WRONGTYPE Operation against a key holding the wrong kind of value\r\n$1\r\n2\r\n",
+    );
+    send(&mut other, "GET a", b"$1\r\n2\r\n");
+
+    // An empty transaction
+    send(&mut stream, "MULTI", b"+OK\r\n");
+    send(&mut stream, "EXEC", b"*0\r\n");
+}
+
+#[test]
+fn test_exec_abort() {
+    let server = TestServer::start(None);
+    let mut stream = connect(&server);
+
+    send(&mut stream, "MULTI", b"+OK\r\n");
+    send(&mut stream, "SET a 1", b"+QUEUED\r\n");
+    send(
+        &mut stream,
+        "GET a b",
+        b"-ERR wrong number of arguments for 'get' command\r\n",
+    );
+    send(
+        &mut stream,
+        "NOPE x y",
+        b"-ERR unknown command 'NOPE', with args beginning with: 'x' 'y' \r\n",
+    );
+    send(
+        &mut stream,
+        "EXEC",
+        b"-EXECABORT Transaction discarded because of previous errors.\r\n",
+    );
+
+    // Nothing was run, and the client is no longer in a transaction
+    send(&mut stream, "GET a", b"$-1\r\n");
+    send(&mut stream, "EXEC", b"-ERR EXEC without MULTI\r\n");
+}
+
+#[test]
+fn test_discard() {
+    let server = TestServer::start(None);
+    let mut stream = connect(&server);
+
+    send(&mut stream, "DISCARD", b"-ERR DISCARD without MULTI\r\n");
+    send(&mut stream, "MULTI", b"+OK\r\n");
+    send(
+        &mut stream,
+        "MULTI",
+        b"-ERR MULTI calls can not be nested\r\n",
+    );
+    send(&mut stream, "SET a 1", b"+QUEUED\r\n");
+    send(&mut stream, "DISCARD", b"+OK\r\n");
+    send(&mut stream, "GET a", b"$-1\r\n");
+}
+
+#[test]
+fn test_blocking_commands_in_transaction() {
+    let server = TestServer::start(None);
+    let mut stream = connect(&server);
+
+    // Blocking commands don't block inside a transaction, they time out right away
+    send(&mut stream, "MULTI", b"+OK\r\n");
+    send(&mut stream, "BLPOP list 0", b"+QUEUED\r\n");
+    send(&mut stream, "RPUSH list a", b"+QUEUED\r\n");
+    send(&mut stream, "BLPOP list 0", b"+QUEUED\r\n");
+    send(
+        &mut stream,
+        "EXEC",
+        b"*3\r\n*-1\r\n:1\r\n*2\r\n$4\r\nlist\r\n$1\r\na\r\n",
+    );
+}
+
+#[test]
+fn test_watch() {
+    let server = TestServer::start(None);
+    let mut stream = connect(&server);
+    let mut other = connect(&server);
+
+    // Reads by other clients don't affect watched keys
+    send(&mut stream, "SET a 1", b"+OK\r\n");
+    send(&mut stream, "WATCH a b", b"+OK\r\n");
+    send(&mut other, "GET a", b"$1\r\n1\r\n");
+    send(&mut stream, "MULTI", b"+OK\r\n");
+    send(&mut stream, "INCR a", b"+QUEUED\r\n");
+    send(&mut stream, "EXEC", b"*1\r\n:2\r\n");
+
+    // A write by another client aborts the transaction, even to a key that didn't exist
+    send(&mut stream, "WATCH a b", b"+OK\r\n");
+    send(&mut other, "SADD b x", b":1\r\n");
+    send(&mut stream, "MULTI", b"+OK\r\n");
+    send(&mut stream, "INCR a", b"+QUEUED\r\n");
+    send(&mut stream, "EXEC", b"*-1\r\n");
+    send(&mut stream, "GET a", b"$1\r\n2\r\n");
+
+    // EXEC always unwatches, so the next transaction runs
+    send(&mut other, "SADD b y", b":1\r\n");
+    send(&mut stream, "MULTI", b"+OK\r\n");
+    send(&mut stream, "INCR a", b"+QUEUED\r\n");
+    send(&mut stream, "EXEC", b"*1\r\n:3\r\n");
+
+    // As do UNWATCH and DISCARD
+    send(&mut stream, "WATCH a", b"+OK\r\n");
+    send(&mut stream, "UNWATCH", b"+OK\r\n");
+    send(&mut other, "SET a 10", b"+OK\r\n");
+    send(&mut stream, "WATCH b", b"+OK\r\n");
+    send(&mut stream, "MULTI", b"+OK\r\n");
+    send(&mut stream, "DISCARD", b"+OK\r\n");
+    send(&mut other, "SADD b z", b":1\r\n");
+    send(&mut stream, "MULTI", b"+OK\r\n");
+    send(&mut stream, "INCR a", b"+QUEUED\r\n");
+    send(&mut stream, "EXEC", b"*1\r\n:11\r\n");
+
+    send(&mut stream, "MULTI", b"+OK\r\n");
+    send(
+        &mut stream,
+        "WATCH a",
+        b"-ERR WATCH inside MULTI is not allowed\r\n",
+    );
+    send(&mut stream, "EXEC", b"*0\r\n");
+}
+
+#[test]
+fn test_watch_expired_key() {
+    let server = TestServer::start(None);
+    let mut stream = connect(&server);
+
+    // A key that expires after WATCH counts as modified
+    send(&mut stream, "SET a 1 PX 100", b"+OK\r\n");
+    send(&mut stream, "WATCH a", b"+OK\r\n");
+    std::thread::sleep(Duration::from_millis(200));
+    send(&mut stream, "MULTI", b"+OK\r\n");
+    send(&mut stream, "SET b 1", b"+QUEUED\r\n");
+    send(&mut stream, "EXEC", b"*-1\r\n");
+
+    // But one that had already expired doesn't
+    send(&mut stream, "SET a 1 PX 50", b"+OK\r\n");
+    std::thread::sleep(Duration::from_millis(100));
+    send(&mut stream, "WATCH a", b"+OK\r\n");
+    send(&mut stream, "MULTI", b"+OK\r\n");
+    send(&mut stream, "SET b 1", b"+QUEUED\r\n");
+    send(&mut stream, "EXEC", b"*1\r\n+OK\r\n");
+}
+
+#[test]
+fn test_quit_and_reset_in_transaction() {
+    let server = TestServer::start(None);
+    let mut stream = connect(&server);
+    let mut other = connect(&server);
+
+    // RESET discards the transaction and the watched keys
+    send(&mut stream, "WATCH a", b"+OK\r\n");
+    send(&mut stream, "MULTI", b"+OK\r\n");
+    send(&mut stream, "SET a 1", b"+QUEUED\r\n");
+    send(&mut stream, "RESET", b"+RESET\r\n");
+    send(&mut stream, "EXEC", b"-ERR EXEC without MULTI\r\n");
+    send(&mut other, "SET a 2", b"+OK\r\n");
+    send(&mut stream, "MULTI", b"+OK\r\n");
+    send(&mut stream, "GET a", b"+QUEUED\r\n");
+    send(&mut stream, "EXEC", b"*1\r\n$1\r\n2\r\n");
+
+    // QUIT runs right away, closing the connection without running the transaction
+    send(&mut stream, "MULTI", b"+OK\r\n");
+    send(&mut stream, "SET a 3", b"+QUEUED\r\n");
+    send(&mut stream, "QUIT", b"+OK\r\n");
+    let mut buf = [0; 1];
+    assert_eq!(stream.read(&mut buf).unwrap(), 0);
+    send(&mut other, "GET a", b"$1\r\n2\r\n");
+}
+
+#[test]
+fn test_watch_only_touched_by_changes() {
+    let server = TestServer::start(None);
+    let mut stream = connect(&server);
+    let mut other = connect(&server);
+
+    // Writes that don't change anything don't abort the transaction
+    send(&mut stream, "SADD s x", b":1\r\n");
+    send(&mut stream, "RPUSH l x", b":1\r\n");
+    send(&mut stream, "WATCH s l", b"+OK\r\n");
+    send(&mut other, "SREM s y", b":0\r\n");
+    send(&mut other, "SADD s x", b":0\r\n");
+    send(&mut other, "LREM l 0 y", b":0\r\n");
+    send(&mut other, "LPOP l 0", b"*0\r\n");
+    send(&mut stream, "MULTI", b"+OK\r\n");
+    send(&mut stream, "SCARD s", b"+QUEUED\r\n");
+    send(&mut stream, "EXEC", b"*1\r\n:1\r\n");
+
+    send(&mut stream, "WATCH s", b"+OK\r\n");
+    send(&mut other, "SREM s x", b":1\r\n");
+    send(&mut stream, "MULTI", b"+OK\r\n");
+    send(&mut stream, "SCARD s", b"+QUEUED\r\n");
+    send(&mut stream, "EXEC", b"*-1\r\n");
+}
+
+#[test]
+fn test_watch_flushdb_and_swapdb() {
+    let server = TestServer::start(None);
+    let mut stream = connect(&server);
+    let mut other = connect(&server);
+
+    // Flushing touches the watched keys that existed
+    send(&mut stream, "SET a 1", b"+OK\r\n");
+    send(&mut stream, "WATCH a", b"+OK\r\n");
+    send(&mut other, "FLUSHDB", b"+OK\r\n");
+    send(&mut stream, "MULTI", b"+OK\r\n");
+    send(&mut stream, "GET a", b"+QUEUED\r\n");
+    send(&mut stream, "EXEC", b"*-1\r\n");
+
+    send(&mut stream, "WATCH a", b"+OK\r\n");
+    send(&mut other, "FLUSHALL SYNC", b"+OK\r\n");
+    send(&mut stream, "MULTI", b"+OK\r\n");
+    send(&mut stream, "GET a", b"+QUEUED\r\n");
+    send(&mut stream, "EXEC", b"*1\r\n$-1\r\n");
+
+    // Swapping touches them when they exist in either database, such as when swapping back
+    send(&mut stream, "SET a 1", b"+OK\r\n");
+    send(&mut stream, "WATCH a", b"+OK\r\n");
+    send(&mut other, "SWAPDB 0 1", b"+OK\r\n");
+    send(&mut stream, "MULTI", b"+OK\r\n");
+    send(&mut stream, "GET a", b"+QUEUED\r\n");
+    send(&mut stream, "EXEC", b"*-1\r\n");
+
+    send(&mut stream, "WATCH a", b"+OK\r\n");
+    send(&mut other, "SWAPDB 1 0", b"+OK\r\n");
+    send(&mut stream, "MULTI", b"+OK\r\n");
+    send(&mut stream, "GET a", b"+QUEUED\r\n");
+    send(&mut stream, "EXEC", b"*-1\r\n");
+    send(&mut stream, "GET a", b"$1\r\n1\r\n");
+
+    send(
+        &mut other,
+        "SWAPDB 0 16",
+        b"-ERR DB index is out of range\r\n",
+    );
+    send(&mut other, "SWAPDB x 0", b"-ERR invalid first DB index\r\n");
+    send(&mut other, "FLUSHDB NOW", b"-ERR syntax error\r\n");
+}